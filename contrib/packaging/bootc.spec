@@ -32,6 +32,8 @@ BuildRequires: libzstd-devel
 %{_prefix}/lib/systemd/system-generators/*
 %{_prefix}/lib/bootc
 %{_unitdir}/*
+%{_datadir}/polkit-1/actions/org.containers.bootc.policy
+%{_prefix}/lib/systemd/catalog/bootc.catalog
 %{_mandir}/man*/bootc*
 
 %prep
@@ -0,0 +1,45 @@
+//! # Library API
+//!
+//! This module is the supported interface for Rust programs, such as image
+//! builders and test harnesses, which inspect bootc systems without running
+//! `bootc status`.  Unlike the rest of this crate, which is an implementation
+//! detail of the `bootc` binary, it follows semantic versioning.
+//!
+//! The types are those of the `org.containers.bootc/v1` API, i.e. the JSON
+//! and YAML output of `bootc status` (see `host-v1.schema.json`).  As in the
+//! API, fields may be added in minor releases, so values should be obtained
+//! via [`get_status`] or deserialization, or built from [`Default`] values,
+//! rather than with struct literals.
+
+pub use crate::k8sapitypes::{ObjectMeta, Resource};
+pub use crate::spec::{
+    BootEntry, BootEntryOstree, BootOrder, HealthStatus, Host, HostSpec, HostStatus, HostType,
+    ImageReference, ImageSignature, ImageStatus, OperationInProgress, RetentionPolicy, Store,
+    UkiStatus,
+};
+
+/// Read the status of the host, as shown by `bootc status --json`.
+///
+/// Nothing is changed on the system.  Reading the status requires root
+/// privileges; other callers get it from `bootc service`, which must be
+/// enabled via `bootc-service.socket`.  If the system was not booted via
+/// bootc (or ostree), the returned host has no deployments.
+pub async fn get_status() -> anyhow::Result<Host> {
+    crate::status::read_host().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_types() -> anyhow::Result<()> {
+        let host: Host = serde_yaml::from_str(include_str!("fixtures/spec-staged-booted.yaml"))?;
+        let booted: &BootEntry = host.status.booted.as_ref().unwrap();
+        let image: &ImageReference = &booted.image.as_ref().unwrap().image;
+        assert_eq!(image.transport, "registry");
+        assert_eq!(host.resource.kind, "BootcHost");
+        assert_eq!(host.spec.boot_order, BootOrder::Default);
+        Ok(())
+    }
+}
@@ -24,8 +24,14 @@ struct DevicesOutput {
 pub(crate) struct Device {
     pub(crate) name: String,
     pub(crate) serial: Option<String>,
+    /// The World Wide Name, e.g. `0x5000c500a1b2c3d4`
+    pub(crate) wwn: Option<String>,
     pub(crate) model: Option<String>,
     pub(crate) partlabel: Option<String>,
+    /// The GPT partition type GUID (or MBR type), in lowercase
+    pub(crate) parttype: Option<String>,
+    /// The type of the partition table, e.g. `gpt` or `dos`
+    pub(crate) pttype: Option<String>,
     pub(crate) children: Option<Vec<Device>>,
     pub(crate) size: u64,
     #[serde(rename = "maj:min")]
@@ -33,6 +39,17 @@ pub(crate) struct Device {
     // NOTE this one is not available on older util-linux, and
     // will also not exist for whole blockdevs (as opposed to partitions).
     pub(crate) start: Option<u64>,
+    /// The logical sector size in bytes
+    #[serde(rename = "log-sec")]
+    pub(crate) log_sec: Option<u64>,
+    /// The device type, e.g. `disk`, `loop` or `rom`
+    #[serde(rename = "type")]
+    pub(crate) devtype: Option<String>,
+    /// The transport, e.g. `nvme`, `sata` or `usb`
+    pub(crate) tran: Option<String>,
+    /// Whether the device is rotational, i.e. a hard disk; read from sysfs
+    #[serde(skip)]
+    pub(crate) rotational: Option<bool>,
 
     // Filesystem-related properties
     pub(crate) label: Option<String>,
@@ -50,6 +67,12 @@ impl Device {
         self.children.as_ref().map_or(false, |v| !v.is_empty())
     }
 
+    /// Whether this is a DASD of s390x, which is partitioned via a VTOC
+    /// rather than a GPT.
+    pub(crate) fn is_dasd(&self) -> bool {
+        self.name.starts_with("dasd")
+    }
+
     // The "start" parameter was only added in a version of util-linux that's only
     // in Fedora 40 as of this writing.
     fn backfill_start(&mut self) -> Result<()> {
@@ -72,10 +95,39 @@ impl Device {
         Ok(())
     }
 
+    // The ROTA column of older util-linux is a string, so read it from sysfs instead.
+    fn backfill_rotational(&mut self) -> Result<()> {
+        let Some(majmin) = self.maj_min.as_deref() else {
+            return Ok(());
+        };
+        // This only exists for whole disks
+        let path = format!("/sys/dev/block/{majmin}/queue/rotational");
+        if Utf8Path::new(&path).try_exists()? {
+            let v = std::fs::read_to_string(&path).with_context(|| format!("Reading {path}"))?;
+            self.rotational = Some(v.trim() == "1");
+        }
+        Ok(())
+    }
+
+    /// Returns true if the device supports discarding blocks, e.g. an SSD.  This is
+    /// only known for whole disks.
+    pub(crate) fn supports_discard(&self) -> Result<bool> {
+        let Some(majmin) = self.maj_min.as_deref() else {
+            return Ok(false);
+        };
+        let path = format!("/sys/dev/block/{majmin}/queue/discard_max_bytes");
+        if !Utf8Path::new(&path).try_exists()? {
+            return Ok(false);
+        }
+        let v = std::fs::read_to_string(&path).with_context(|| format!("Reading {path}"))?;
+        Ok(v.trim().parse::<u64>().unwrap_or_default() > 0)
+    }
+
     /// Older versions of util-linux may be missing some properties. Backfill them if they're missing.
     pub(crate) fn backfill_missing(&mut self) -> Result<()> {
         // Add new properties to backfill here
         self.backfill_start()?;
+        self.backfill_rotational()?;
         // And recurse to child devices
         for child in self.children.iter_mut().flatten() {
             child.backfill_missing()?;
@@ -93,6 +145,15 @@ pub(crate) fn wipefs(dev: &Utf8Path) -> Result<()> {
     )
 }
 
+#[context("Failed to discard {dev}")]
+pub(crate) fn discard(dev: &Utf8Path) -> Result<()> {
+    Task::new_and_run(
+        format!("Discarding blocks of {dev}"),
+        "blkdiscard",
+        ["-f", dev.as_str()],
+    )
+}
+
 #[context("Listing device {dev}")]
 pub(crate) fn list_dev(dev: &Utf8Path) -> Result<Device> {
     let mut devs: DevicesOutput = Command::new("lsblk")
@@ -108,6 +169,195 @@ pub(crate) fn list_dev(dev: &Utf8Path) -> Result<Device> {
         .ok_or_else(|| anyhow!("no device output from lsblk for {dev}"))
 }
 
+#[context("Listing disks")]
+/// List all whole disks, without their partitions.
+pub(crate) fn list_disks() -> Result<Vec<Device>> {
+    let mut devs: DevicesOutput = Command::new("lsblk")
+        .args(["-J", "-b", "-O", "-d"])
+        .run_and_parse_json()?;
+    for dev in devs.blockdevices.iter_mut() {
+        dev.backfill_missing()?;
+    }
+    Ok(devs.blockdevices)
+}
+
+/// How to choose among the disks matching a [`DeviceMatch`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DevicePick {
+    Smallest,
+    Largest,
+}
+
+/// A selector for a disk by its properties rather than its path, which may change
+/// between boots; see `--device-match`.
+///
+/// This is a comma separated list of terms which must all match:
+///
+/// - `serial=`, `wwn=`, `model=`, `name=`, `tran=` or `id=` (a name in `/dev/disk/by-id`)
+///   followed by a pattern, where `*` and `?` are wildcards
+/// - `min-size=` or `max-size=` followed by a size, e.g. `100G`
+/// - `ssd` or `hdd`
+/// - `smallest` or `largest`, to choose among multiple matching disks; these can be
+///   combined with the previous ones, e.g. `smallest-ssd`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct DeviceMatch {
+    spec: String,
+    patterns: Vec<(String, String)>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    rotational: Option<bool>,
+    pick: Option<DevicePick>,
+}
+
+const DEVICE_MATCH_KEYS: &[&str] = &["serial", "wwn", "model", "name", "tran", "id"];
+
+/// Match a value against a pattern with `*` and `?` wildcards.
+fn glob_matches(pattern: &str, value: &str) -> bool {
+    let re = pattern
+        .split('*')
+        .map(|p| {
+            p.split('?')
+                .map(regex::escape)
+                .collect::<Vec<_>>()
+                .join(".")
+        })
+        .collect::<Vec<_>>()
+        .join(".*");
+    Regex::new(&format!("^{re}$")).is_ok_and(|re| re.is_match(value))
+}
+
+impl std::str::FromStr for DeviceMatch {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut r = DeviceMatch {
+            spec: s.to_owned(),
+            ..Default::default()
+        };
+        for term in s.split(',').map(str::trim) {
+            if let Some((k, v)) = term.split_once('=') {
+                let size = || {
+                    parse_size_mib(v)
+                        .map(|mib| mib * 1024 * 1024)
+                        .with_context(|| format!("Parsing {k}"))
+                };
+                match k {
+                    "min-size" => r.min_size = Some(size()?),
+                    "max-size" => r.max_size = Some(size()?),
+                    k if DEVICE_MATCH_KEYS.contains(&k) => {
+                        r.patterns.push((k.to_owned(), v.to_owned()))
+                    }
+                    o => anyhow::bail!("Unknown device property: {o}"),
+                }
+                continue;
+            }
+            for word in term.split('-') {
+                match word {
+                    "smallest" if r.pick.is_none() => r.pick = Some(DevicePick::Smallest),
+                    "largest" if r.pick.is_none() => r.pick = Some(DevicePick::Largest),
+                    "ssd" if r.rotational.is_none() => r.rotational = Some(false),
+                    "hdd" if r.rotational.is_none() => r.rotational = Some(true),
+                    o => anyhow::bail!("Invalid device match term: {o}"),
+                }
+            }
+        }
+        Ok(r)
+    }
+}
+
+impl std::fmt::Display for DeviceMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.spec)
+    }
+}
+
+impl DeviceMatch {
+    fn matches(&self, dev: &Device, ids: &[String]) -> bool {
+        let patterns_match = self.patterns.iter().all(|(k, pattern)| {
+            let value = match k.as_str() {
+                "serial" => dev.serial.as_deref(),
+                "wwn" => dev.wwn.as_deref(),
+                "model" => dev.model.as_deref(),
+                "name" => Some(dev.name.as_str()),
+                "tran" => dev.tran.as_deref(),
+                "id" => return ids.iter().any(|id| glob_matches(pattern, id)),
+                _ => None,
+            };
+            value.is_some_and(|v| glob_matches(pattern, v.trim()))
+        });
+        patterns_match
+            && self.min_size.map_or(true, |min| dev.size >= min)
+            && self.max_size.map_or(true, |max| dev.size <= max)
+            && self.rotational.map_or(true, |r| dev.rotational == Some(r))
+    }
+
+    /// Select the matching disk among the given ones; `ids` maps disk names to their
+    /// names in `/dev/disk/by-id`.
+    pub(crate) fn select<'a>(
+        &self,
+        disks: &'a [Device],
+        ids: &HashMap<String, Vec<String>>,
+    ) -> Result<&'a Device> {
+        let candidates = disks
+            .iter()
+            .filter(|d| d.devtype.as_deref() == Some("disk") && d.size > 0)
+            .filter(|d| {
+                let ids = ids.get(&d.name).map(|v| v.as_slice()).unwrap_or_default();
+                self.matches(d, ids)
+            });
+        // Among disks of the same size, prefer the first by name
+        let found = match self.pick {
+            Some(DevicePick::Smallest) => {
+                candidates.min_by(|a, b| a.size.cmp(&b.size).then_with(|| a.name.cmp(&b.name)))
+            }
+            Some(DevicePick::Largest) => {
+                candidates.max_by(|a, b| a.size.cmp(&b.size).then_with(|| b.name.cmp(&a.name)))
+            }
+            None => {
+                let found = candidates.collect::<Vec<_>>();
+                if let [_, _, ..] = found.as_slice() {
+                    let names = found.iter().map(|d| d.path()).collect::<Vec<_>>();
+                    anyhow::bail!("Multiple disks match {self}: {}", names.join(", "));
+                }
+                found.into_iter().next()
+            }
+        };
+        found.ok_or_else(|| anyhow!("No disk matches {self}"))
+    }
+}
+
+/// Map the names of disks to their names in `/dev/disk/by-id`.
+#[context("Reading /dev/disk/by-id")]
+pub(crate) fn disk_ids() -> Result<HashMap<String, Vec<String>>> {
+    let mut r: HashMap<String, Vec<String>> = HashMap::new();
+    let dir = Utf8Path::new("/dev/disk/by-id");
+    if !dir.try_exists()? {
+        return Ok(r);
+    }
+    for entry in dir.read_dir_utf8()? {
+        let entry = entry?;
+        let target = entry.path().read_link_utf8()?;
+        if let Some(name) = target.file_name() {
+            r.entry(name.to_owned())
+                .or_default()
+                .push(entry.file_name().to_owned());
+        }
+    }
+    Ok(r)
+}
+
+/// Find the disk matching the given selector.
+#[context("Finding disk matching {spec}")]
+pub(crate) fn find_matching_disk(spec: &DeviceMatch) -> Result<Device> {
+    let disks = list_disks()?;
+    let ids = disk_ids()?;
+    let name = spec.select(&disks, &ids)?.name.clone();
+    Ok(disks
+        .into_iter()
+        .find(|d| d.name == name)
+        .expect("selected disk"))
+}
+
 #[derive(Debug, Deserialize)]
 struct SfDiskOutput {
     partitiontable: PartitionTable,
@@ -130,6 +380,8 @@ pub(crate) struct Partition {
 pub(crate) enum PartitionType {
     Dos,
     Gpt,
+    /// The VTOC of a DASD, which is not read via sfdisk
+    Dasd,
     Unknown(String),
 }
 
@@ -166,6 +418,14 @@ impl PartitionTable {
             .ok_or_else(|| anyhow::anyhow!("Missing partition for index {partno}"))?;
         Ok(r)
     }
+
+    /// Find the partition with the given GPT partition name
+    pub(crate) fn find_name(&self, name: &str) -> Result<&Partition> {
+        self.partitions
+            .iter()
+            .find(|p| p.name.as_deref() == Some(name))
+            .ok_or_else(|| anyhow::anyhow!("Missing partition named {name}"))
+    }
 }
 
 impl Partition {
@@ -176,6 +436,29 @@ impl Partition {
 
 #[context("Listing partitions of {dev}")]
 pub(crate) fn partitions_of(dev: &Utf8Path) -> Result<PartitionTable> {
+    // sfdisk does not support the VTOC of DASDs, so use what the kernel found
+    let device = list_dev(dev)?;
+    if device.is_dasd() {
+        let partitions = device
+            .children
+            .iter()
+            .flatten()
+            .map(|c| Partition {
+                node: c.path(),
+                start: c.start.unwrap_or_default(),
+                size: c.size / 512,
+                parttype: c.parttype.clone().unwrap_or_default(),
+                uuid: None,
+                name: None,
+            })
+            .collect();
+        return Ok(PartitionTable {
+            label: PartitionType::Dasd,
+            id: String::new(),
+            device: dev.to_string(),
+            partitions,
+        });
+    }
     let o = Task::new_quiet("sfdisk")
         .args(["-J", dev.as_str()])
         .read()?;
@@ -183,6 +466,53 @@ pub(crate) fn partitions_of(dev: &Utf8Path) -> Result<PartitionTable> {
     Ok(o.partitiontable)
 }
 
+/// The geometry of an ECKD DASD, as needed to partition it via `fdasd`
+/// and to write its boot record via `zipl`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct DasdGeometry {
+    pub(crate) cylinders: u64,
+    pub(crate) tracks_per_cylinder: u64,
+    pub(crate) blocks_per_track: u64,
+    pub(crate) block_size: u64,
+}
+
+impl DasdGeometry {
+    /// The size of a track in bytes; DASDs are partitioned in whole tracks.
+    pub(crate) fn track_size(&self) -> u64 {
+        self.blocks_per_track * self.block_size
+    }
+
+    /// Parse the output of `fdasd --table`.
+    fn parse(table: &str) -> Result<Self> {
+        let field = |key: &str| -> Result<u64> {
+            let v = table
+                .lines()
+                .find_map(|l| {
+                    let (k, v) = l.split_once(':')?;
+                    (k.trim().trim_end_matches('.').trim_end() == key).then_some(v.trim())
+                })
+                .ok_or_else(|| anyhow!("Missing {key}"))?;
+            v.parse().with_context(|| format!("Parsing {key}: {v}"))
+        };
+        Ok(Self {
+            cylinders: field("cylinders")?,
+            tracks_per_cylinder: field("tracks per cylinder")?,
+            blocks_per_track: field("blocks per track")?,
+            block_size: field("bytes per block")?,
+        })
+    }
+}
+
+/// Read the geometry of an ECKD DASD, which must have been formatted with
+/// the compatible disk layout (e.g. via `dasdfmt -d cdl`).
+#[context("Reading DASD geometry of {dev}")]
+pub(crate) fn dasd_geometry(dev: &Utf8Path) -> Result<DasdGeometry> {
+    let o = Task::new_quiet("fdasd")
+        .args(["--table", dev.as_str()])
+        .read()?;
+    DasdGeometry::parse(&o)
+}
+
 pub(crate) struct LoopbackDevice {
     pub(crate) dev: Option<Utf8PathBuf>,
 }
@@ -393,4 +723,102 @@ mod test {
         );
         Ok(())
     }
+
+    #[test]
+    fn test_parse_dasd_geometry() -> Result<()> {
+        let fixture = indoc::indoc! { r#"
+        reading volume label ..: VOL1
+        reading vtoc ..........: ok
+
+
+        Disk /dev/dasda:
+          cylinders ............: 10017
+          tracks per cylinder ..: 15
+          blocks per track .....: 12
+          bytes per block ......: 4096
+          volume label .........: VOL1
+          volume serial ........: 0X0150
+          max partitions .......: 3
+
+         ------------------------------- tracks -------------------------------
+                       Device      start      end   length   Id  System
+                  /dev/dasda1          2   150254   150253    1  Linux native
+        "# };
+        let geometry = DasdGeometry::parse(fixture)?;
+        assert_eq!(
+            geometry,
+            DasdGeometry {
+                cylinders: 10017,
+                tracks_per_cylinder: 15,
+                blocks_per_track: 12,
+                block_size: 4096,
+            }
+        );
+        assert_eq!(geometry.track_size(), 48 * 1024);
+        assert!(DasdGeometry::parse("reading vtoc ..........: ok").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_device_match() -> Result<()> {
+        const G: u64 = 1024 * 1024 * 1024;
+        let mut disks: Vec<Device> = serde_json::from_value(serde_json::json!([
+            { "name": "nvme0n1", "serial": "S4EVNF0M123456", "model": "Samsung SSD 970", "tran": "nvme", "type": "disk", "size": 512 * G },
+            { "name": "nvme1n1", "serial": "S4EVNF0M654321", "model": "Samsung SSD 970", "tran": "nvme", "type": "disk", "size": 256 * G },
+            { "name": "sda", "serial": "ZA1234", "model": "ST4000NM0035", "tran": "sata", "type": "disk", "size": 4000 * G },
+            { "name": "sdb", "serial": "ZA5678", "model": "ST2000NM0035", "tran": "sata", "type": "disk", "size": 2000 * G },
+            { "name": "sr0", "serial": null, "type": "rom", "size": G },
+            { "name": "loop0", "serial": null, "type": "loop", "size": G / 2 },
+        ]))?;
+        for d in disks.iter_mut() {
+            d.rotational = Some(d.name.starts_with("sd"));
+        }
+        let ids = HashMap::from([(
+            "sdb".to_owned(),
+            vec![
+                "ata-ST2000NM0035_ZA5678".to_owned(),
+                "wwn-0x5000c500a1b2c3d5".to_owned(),
+            ],
+        )]);
+        let select = |spec: &str| -> Result<String> {
+            let m: DeviceMatch = spec.parse()?;
+            Ok(m.select(&disks, &ids)?.name.clone())
+        };
+        for (spec, expected) in [
+            ("serial=S4EVNF0M654*", "nvme1n1"),
+            ("smallest-ssd", "nvme1n1"),
+            ("largest-ssd", "nvme0n1"),
+            ("smallest-hdd", "sdb"),
+            ("largest", "sda"),
+            ("smallest", "nvme1n1"),
+            ("id=ata-*", "sdb"),
+            ("tran=sata,max-size=3000G", "sdb"),
+            ("model=Samsung*,min-size=300G", "nvme0n1"),
+            ("hdd,smallest", "sdb"),
+        ] {
+            assert_eq!(select(spec)?, expected, "{spec}");
+        }
+        // Ambiguous or no match
+        for spec in [
+            "ssd",
+            "model=Samsung*",
+            "serial=nope",
+            "name=sr0",
+            "name=loop0",
+        ] {
+            assert!(select(spec).is_err(), "{spec}");
+        }
+        // Invalid
+        for spec in [
+            "",
+            "smallest-largest",
+            "ssd-hdd",
+            "foo=bar",
+            "min-size=big",
+            "fastest",
+        ] {
+            assert!(spec.parse::<DeviceMatch>().is_err(), "{spec}");
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,157 @@
+//! # Automatic rollback on boot failure
+//!
+//! If `attempts` is set in the `[boot-counting]` section of
+//! `/usr/lib/bootc/config.toml`, each time an update is staged the GRUB
+//! environment variables `boot_counter` and `boot_success=0` are set.  GRUB
+//! configurations with Fedora's boot counting support (`08_fallback_counting`
+//! and `10_reset_boot_success` in `grub2-tools`, as also used by greenboot)
+//! then decrement the counter on each boot, and once it is exhausted, boot
+//! the rollback entry, setting the counter to `-1`.
+//!
+//! `bootc boot-complete`, run by `bootc-boot-complete.service` once
+//! `boot-complete.target` is reached, marks the boot as successful.  If the
+//! system fell back to the rollback entry, it first makes that permanent by
+//! queueing the booted deployment as the default again, so that the failed
+//! update is not retried on the next boot.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use cap_std_ext::cap_std;
+use cap_std_ext::cap_std::fs::Dir;
+use fn_error_context::context;
+
+use crate::store::Storage;
+use crate::task::Task;
+
+/// The GRUB environment block, relative to `/boot`.
+pub(crate) const GRUBENV: &str = "grub2/grubenv";
+/// Counts down the remaining boot attempts; `-1` once GRUB fell back.
+const BOOT_COUNTER: &str = "boot_counter";
+/// Set to `1` once a boot succeeded.
+const BOOT_SUCCESS: &str = "boot_success";
+/// Logged when the system fell back to the rollback deployment.
+pub(crate) const BOOT_FAILED_JOURNAL_ID: &str = "8c2e5f3a7b1d4e6f9a0c3b5d7e9f1a2c";
+
+/// The boot counting state of the current boot.
+#[derive(Debug, PartialEq, Eq)]
+enum BootCount {
+    /// Boot counting is not armed
+    Inactive,
+    /// The default entry was booted, with this many attempts left
+    Counting(u32),
+    /// The attempts were exhausted, and the rollback entry was booted
+    FellBack,
+}
+
+/// Parse the output of `grub2-editenv list`.
+pub(crate) fn parse_env(list: &str) -> BTreeMap<&str, &str> {
+    list.lines().filter_map(|l| l.split_once('=')).collect()
+}
+
+/// The boot counting state in the GRUB environment.
+fn boot_count(env: &BTreeMap<&str, &str>) -> Result<BootCount> {
+    match env.get(BOOT_COUNTER) {
+        None | Some(&"") => Ok(BootCount::Inactive),
+        Some(&"-1") => Ok(BootCount::FellBack),
+        Some(v) => v
+            .parse()
+            .map(BootCount::Counting)
+            .map_err(|_| anyhow::anyhow!("Invalid {BOOT_COUNTER}: {v}")),
+    }
+}
+
+/// The path of the GRUB environment block.
+fn grubenv_path() -> String {
+    format!("/boot/{GRUBENV}")
+}
+
+/// Run `grub2-editenv` on the environment block with the given arguments.
+pub(crate) fn editenv<'a>(args: impl IntoIterator<Item = &'a str>) -> Task {
+    Task::new_quiet("grub2-editenv")
+        .arg(grubenv_path())
+        .args(args)
+}
+
+/// Arm boot counting for the next boot, if configured.
+#[context("Setting up boot counting")]
+pub(crate) fn arm(root: &Dir) -> Result<()> {
+    let Some(attempts) = crate::config::load_config(root)?.boot_counting_attempts() else {
+        return Ok(());
+    };
+    let boot = &Dir::open_ambient_dir("/boot", cap_std::ambient_authority())?;
+    if !boot.try_exists("grub2")? {
+        anyhow::bail!("Only GRUB is supported");
+    }
+    // We run in our own mount namespace, so this does not affect the host
+    crate::utils::ensure_writable_mount(boot, "/boot")?;
+    let counter = format!("{BOOT_COUNTER}={attempts}");
+    let success = format!("{BOOT_SUCCESS}=0");
+    editenv(["set", &counter, &success]).run()?;
+    println!("  Rolling back after {attempts} failed boot attempts");
+    Ok(())
+}
+
+/// Implementation of `bootc boot-complete`: mark the current boot as
+/// successful; if it fell back to the rollback deployment, keep booting it.
+#[context("Completing boot")]
+pub(crate) async fn complete(sysroot: &Storage) -> Result<()> {
+    let boot = &Dir::open_ambient_dir("/boot", cap_std::ambient_authority())?;
+    if !boot.try_exists(GRUBENV)? {
+        tracing::debug!("No GRUB environment block");
+        return Ok(());
+    }
+    let list = editenv(["list"]).read()?;
+    let state = boot_count(&parse_env(&list))?;
+    if state == BootCount::Inactive {
+        return Ok(());
+    }
+    crate::utils::ensure_writable_mount(boot, "/boot")?;
+    if let BootCount::Counting(left) = state {
+        println!("Boot succeeded with {left} attempts left");
+    } else {
+        let booted = sysroot.require_booted_deployment()?;
+        let msg = "Update failed to boot; fell back to the previous deployment";
+        libsystemd::logging::journal_send(
+            libsystemd::logging::Priority::Warning,
+            msg,
+            [
+                ("MESSAGE_ID", BOOT_FAILED_JOURNAL_ID),
+                ("BOOTC_OSTREE_COMMIT", booted.csum().as_str()),
+            ]
+            .into_iter(),
+        )?;
+        eprintln!("{msg}");
+        let index = booted.index().try_into()?;
+        crate::deploy::rollback_to(sysroot, &crate::deploy::RollbackTarget::Index(index)).await?;
+    }
+    let success = format!("{BOOT_SUCCESS}=1");
+    editenv(["set", &success]).run()?;
+    editenv(["unset", BOOT_COUNTER]).run()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boot_count() -> Result<()> {
+        let state = |list: &str| boot_count(&parse_env(list));
+        assert_eq!(state("")?, BootCount::Inactive);
+        assert_eq!(
+            state("saved_entry=abc\nboot_success=1\n")?,
+            BootCount::Inactive
+        );
+        assert_eq!(
+            state("saved_entry=abc\nboot_success=0\nboot_counter=2\n")?,
+            BootCount::Counting(2)
+        );
+        assert_eq!(
+            state("boot_counter=-1\nboot_success=0\n")?,
+            BootCount::FellBack
+        );
+        assert!(state("boot_counter=many\n").is_err());
+        Ok(())
+    }
+}
@@ -1,6 +1,10 @@
+use std::fmt::Display;
+
 use anyhow::{anyhow, bail, Context, Result};
 use camino::{Utf8Path, Utf8PathBuf};
+use clap::ValueEnum;
 use fn_error_context::context;
+use serde::{Deserialize, Serialize};
 
 use crate::blockdev::PartitionTable;
 use crate::task::Task;
@@ -13,6 +17,64 @@ pub(crate) const PREPBOOT_LABEL: &str = "PowerPC-PReP-boot";
 /// We make a best-effort to support MBR partitioning too.
 pub(crate) const PREPBOOT_MBR_TYPE: &str = "41";
 
+/// The bootloader installed by `bootc install`.
+#[derive(ValueEnum, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Bootloader {
+    /// GRUB, installed via bootupd; on ppc64le, to the PReP partition read by Open
+    /// Firmware, while petitboot reads its configuration from /boot
+    Grub,
+    /// systemd-boot, installed via bootctl, with boot entries managed by bootc
+    SystemdBoot,
+    /// zipl, the IPL loader of s390x
+    Zipl,
+}
+
+impl Display for Bootloader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value().unwrap().get_name().fmt(f)
+    }
+}
+
+impl Bootloader {
+    /// Determine the bootloader to install on the given architecture: `--bootloader`
+    /// takes precedence over the [`crate::metadata::BOOTLOADER_LABEL`] of the image;
+    /// otherwise, s390x uses zipl, images shipping a UKI are booted via systemd-boot,
+    /// and all others via GRUB.
+    pub(crate) fn resolve(
+        arch: &str,
+        requested: Option<Self>,
+        label: Option<&str>,
+        has_uki: bool,
+    ) -> Result<Self> {
+        let from_label = label
+            .map(|v| {
+                Self::from_str(v, false).map_err(|_| {
+                    anyhow!(
+                        "Invalid value for label {}: {v}",
+                        crate::metadata::BOOTLOADER_LABEL
+                    )
+                })
+            })
+            .transpose()?;
+        let default = match arch {
+            "s390x" => Self::Zipl,
+            _ if has_uki => Self::SystemdBoot,
+            _ => Self::Grub,
+        };
+        let r = requested.or(from_label).unwrap_or(default);
+        match (r, arch) {
+            (Self::Grub | Self::Zipl, _) if has_uki => {
+                bail!("The image ships a UKI, which can only be booted via systemd-boot")
+            }
+            (Self::SystemdBoot, "x86_64" | "aarch64") => Ok(r),
+            (Self::Grub, "x86_64" | "aarch64" | "powerpc64") => Ok(r),
+            (Self::Zipl, "s390x") => Ok(r),
+            (r, arch) => bail!("Bootloader {r} is not supported on {arch}"),
+        }
+    }
+}
+
 /// Find the device to pass to bootupd. Only on powerpc64 right now
 /// we explicitly find one with a specific label.
 ///
@@ -38,16 +100,25 @@ pub(crate) fn install_via_bootupd(
     device: &PartitionTable,
     rootfs: &Utf8Path,
     configopts: &crate::install::InstallConfigOpts,
+    cross_arch_root: Option<&Utf8Path>,
 ) -> Result<()> {
+    let devpath = get_bootupd_device(device)?;
     let verbose = std::env::var_os("BOOTC_BOOTLOADER_DEBUG").map(|_| "-vvvv");
-    // bootc defaults to only targeting the platform boot method.
-    let bootupd_opts = (!configopts.generic_image).then_some(["--update-firmware", "--auto"]);
+    // bootc defaults to only targeting the platform boot method.  For another architecture,
+    // the firmware of this system is irrelevant, and only the EFI files of the target root
+    // can be installed, as installing for BIOS requires running its grub2-install.
+    let bootupd_opts = (!configopts.generic_image && cross_arch_root.is_none())
+        .then_some(["--update-firmware", "--auto"]);
+    let cross_arch_opts = cross_arch_root
+        .map(|src| ["--src-root", src.as_str(), "--component", "EFI"])
+        .into_iter()
+        .flatten();
 
-    let devpath = get_bootupd_device(device)?;
     let args = ["backend", "install", "--write-uuid"]
         .into_iter()
         .chain(verbose)
         .chain(bootupd_opts.iter().copied().flatten())
+        .chain(cross_arch_opts)
         .chain(["--device", devpath.as_str(), rootfs.as_str()]);
     Task::new("Running bootupctl to install bootloader", "bootupctl")
         .args(args)
@@ -55,6 +126,39 @@ pub(crate) fn install_via_bootupd(
         .run()
 }
 
+/// The keys of a BLS configuration used to boot, e.g. via zipl.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct BlsEntry<'a> {
+    /// The kernel, relative to /boot
+    pub(crate) linux: &'a str,
+    /// The initramfs, relative to /boot
+    pub(crate) initrd: &'a str,
+    /// The kernel arguments
+    pub(crate) options: &'a str,
+}
+
+/// Parse the keys of a BLS configuration needed to boot.
+pub(crate) fn parse_bls_entry(conf: &str) -> Result<BlsEntry<'_>> {
+    let mut kernel = None;
+    let mut initrd = None;
+    let mut options = None;
+
+    for line in conf.lines() {
+        match line.split_once(char::is_whitespace) {
+            Some(("linux", val)) => kernel = Some(val.trim().trim_start_matches('/')),
+            Some(("initrd", val)) => initrd = Some(val.trim().trim_start_matches('/')),
+            Some(("options", val)) => options = Some(val.trim()),
+            _ => (),
+        }
+    }
+
+    Ok(BlsEntry {
+        linux: kernel.ok_or_else(|| anyhow!("missing 'linux' key in default BLS config"))?,
+        initrd: initrd.ok_or_else(|| anyhow!("missing 'initrd' key in default BLS config"))?,
+        options: options.ok_or_else(|| anyhow!("missing 'options' key in default BLS config"))?,
+    })
+}
+
 #[context("Installing bootloader using zipl")]
 pub(crate) fn install_via_zipl(device: &PartitionTable, boot_uuid: &str) -> Result<()> {
     // Identify the target boot partition from UUID
@@ -65,14 +169,29 @@ pub(crate) fn install_via_zipl(device: &PartitionTable, boot_uuid: &str) -> Resu
     // Ensure that the found partition is a part of the target device
     let device_path = device.path();
 
-    let partitions = crate::blockdev::list_dev(device_path)?
+    let disk = crate::blockdev::list_dev(device_path)?;
+    // zipl addresses the boot partition in units of logical blocks
+    let blocksize = disk.log_sec.unwrap_or(512);
+    let partitions = disk
         .children
         .with_context(|| format!("no partition found on {device_path}"))?;
     let boot_part = partitions
         .iter()
         .find(|part| part.maj_min.as_deref() == Some(maj_min.as_str()))
         .with_context(|| format!("partition device {maj_min} is not on {device_path}"))?;
-    let boot_part_offset = boot_part.start.unwrap_or(0);
+    // lsblk reports the start in 512 byte sectors
+    let boot_part_offset = boot_part.start.unwrap_or(0) * 512 / blocksize;
+    // ECKD DASDs are addressed via their geometry
+    let (targettype, geometry) = if disk.is_dasd() {
+        let g = crate::blockdev::dasd_geometry(device_path)?;
+        let geometry = format!(
+            "{},{},{}",
+            g.cylinders, g.tracks_per_cylinder, g.blocks_per_track
+        );
+        ("CDL", Some(geometry))
+    } else {
+        ("SCSI", None)
+    };
 
     // Find exactly one BLS configuration under /boot/loader/entries
     // TODO: utilize the BLS parser in ostree
@@ -88,7 +207,7 @@ pub(crate) fn install_via_zipl(device: &PartitionTable, boot_uuid: &str) -> Resu
                 }
                 Ok(Some(e.path().to_owned()))
             } else {
-                Ok(None)
+                Ok(acc)
             }
         })?
         .with_context(|| format!("no BLS configuration under {bls_dir}"))?;
@@ -96,38 +215,74 @@ pub(crate) fn install_via_zipl(device: &PartitionTable, boot_uuid: &str) -> Resu
     let bls_path = bls_dir.join(bls_entry);
     let bls_conf =
         std::fs::read_to_string(&bls_path).with_context(|| format!("reading {bls_path}"))?;
-
-    let mut kernel = None;
-    let mut initrd = None;
-    let mut options = None;
-
-    for line in bls_conf.lines() {
-        match line.split_once(char::is_whitespace) {
-            Some(("linux", val)) => kernel = Some(val.trim().trim_start_matches('/')),
-            Some(("initrd", val)) => initrd = Some(val.trim().trim_start_matches('/')),
-            Some(("options", val)) => options = Some(val.trim()),
-            _ => (),
-        }
-    }
-
-    let kernel = kernel.ok_or_else(|| anyhow!("missing 'linux' key in default BLS config"))?;
-    let initrd = initrd.ok_or_else(|| anyhow!("missing 'initrd' key in default BLS config"))?;
-    let options = options.ok_or_else(|| anyhow!("missing 'options' key in default BLS config"))?;
-
-    let image = boot_dir.join(kernel).canonicalize_utf8()?;
-    let ramdisk = boot_dir.join(initrd).canonicalize_utf8()?;
+    let entry = parse_bls_entry(&bls_conf).with_context(|| format!("parsing {bls_path}"))?;
+    let image = boot_dir.join(entry.linux).canonicalize_utf8()?;
+    let ramdisk = boot_dir.join(entry.initrd).canonicalize_utf8()?;
 
     // Execute the zipl command to install bootloader
     let zipl_desc = format!("running zipl to install bootloader on {device_path}");
-    let zipl_task = Task::new(&zipl_desc, "zipl")
+    let mut zipl_task = Task::new(&zipl_desc, "zipl")
         .args(["--target", boot_dir.as_str()])
         .args(["--image", image.as_str()])
         .args(["--ramdisk", ramdisk.as_str()])
-        .args(["--parameters", options])
+        .args(["--parameters", entry.options])
         .args(["--targetbase", device_path.as_str()])
-        .args(["--targettype", "SCSI"])
-        .args(["--targetblocksize", "512"])
+        .args(["--targettype", targettype])
+        .args(["--targetblocksize", &blocksize.to_string()])
         .args(["--targetoffset", &boot_part_offset.to_string()])
         .args(["--add-files", "--verbose"]);
+    if let Some(geometry) = geometry {
+        zipl_task = zipl_task.args(["--targetgeometry", geometry.as_str()]);
+    }
     zipl_task.verbose().run().context(zipl_desc)
 }
+
+#[test]
+fn test_resolve_bootloader() -> Result<()> {
+    use Bootloader::*;
+    let resolve = Bootloader::resolve;
+    assert_eq!(resolve("x86_64", None, None, false)?, Grub);
+    assert_eq!(
+        resolve("x86_64", Some(SystemdBoot), Some("grub"), false)?,
+        SystemdBoot
+    );
+    assert_eq!(
+        resolve("aarch64", None, Some("systemd-boot"), false)?,
+        SystemdBoot
+    );
+    assert_eq!(resolve("x86_64", None, None, true)?, SystemdBoot);
+    assert!(resolve("x86_64", None, Some("lilo"), false).is_err());
+    assert!(resolve("x86_64", Some(Grub), None, true).is_err());
+    assert_eq!(SystemdBoot.to_string(), "systemd-boot");
+
+    // Each architecture only supports some bootloaders
+    assert_eq!(resolve("powerpc64", None, None, false)?, Grub);
+    assert!(resolve("powerpc64", Some(SystemdBoot), None, false).is_err());
+    assert!(resolve("powerpc64", None, None, true).is_err());
+    assert_eq!(resolve("s390x", None, None, false)?, Zipl);
+    assert!(resolve("s390x", Some(Grub), None, false).is_err());
+    assert!(resolve("s390x", None, Some("systemd-boot"), false).is_err());
+    assert!(resolve("x86_64", Some(Zipl), None, false).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_parse_bls_entry() -> Result<()> {
+    let conf = indoc::indoc! { "
+        title Fedora Linux 41 (ostree:0)
+        version 1
+        options root=UUID=abc rw ostree=/ostree/boot.1/default/abc/0
+        linux /ostree/default-abc/vmlinuz-6.12.0
+        initrd /ostree/default-abc/initramfs-6.12.0.img
+    " };
+    assert_eq!(
+        parse_bls_entry(conf)?,
+        BlsEntry {
+            linux: "ostree/default-abc/vmlinuz-6.12.0",
+            initrd: "ostree/default-abc/initramfs-6.12.0.img",
+            options: "root=UUID=abc rw ostree=/ostree/boot.1/default/abc/0",
+        }
+    );
+    assert!(parse_bls_entry("title foo\nlinux /vmlinuz\n").is_err());
+    Ok(())
+}
@@ -3,12 +3,11 @@
 //! Command line tool to manage bootable ostree-based containers.
 
 use std::ffi::OsString;
-use std::io::Seek;
 use std::os::unix::process::CommandExt;
 use std::process::Command;
 
 use anyhow::{Context, Result};
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 use cap_std_ext::cap_std;
 use cap_std_ext::cap_std::fs::Dir;
 use clap::Parser;
@@ -21,17 +20,28 @@ use ostree_ext::keyfileext::KeyFileExt;
 use ostree_ext::ostree;
 use schemars::schema_for;
 
+use crate::config::RebootStrategy;
 use crate::deploy::RequiredHostSpec;
 use crate::lints;
-use crate::spec::Host;
 use crate::spec::ImageReference;
+use crate::spec::{Host, HostSpec};
 use crate::utils::sigpolicy_from_opts;
 
 include!(concat!(env!("OUT_DIR"), "/version.rs"));
 
+/// Logged when a mutating verb is rejected in read-only mode.
+pub(crate) const READ_ONLY_JOURNAL_ID: &str = "0b5a6b6c8e0a4e4c9e3c6d2a43f3f1d9";
+/// Logged when an update has been staged and a reboot is needed to apply it.
+pub(crate) const REBOOT_PENDING_JOURNAL_ID: &str = "5c3b0e1f8d7a4c2b9e6f1a0d3c8b7e42";
+
 /// Perform an upgrade operation
-#[derive(Debug, Parser, PartialEq, Eq)]
+#[derive(Debug, Default, Parser, PartialEq, Eq)]
+#[clap(args_conflicts_with_subcommands = true)]
 pub(crate) struct UpgradeOpts {
+    /// Hold or release updates instead of upgrading
+    #[clap(subcommand)]
+    pub(crate) hold: Option<UpdateHoldOpts>,
+
     /// Don't display progress
     #[clap(long)]
     pub(crate) quiet: bool,
@@ -40,16 +50,116 @@ pub(crate) struct UpgradeOpts {
     ///
     /// This only downloads an updated manifest and image configuration (i.e. typically kilobyte-sized metadata)
     /// as opposed to the image layers.
-    #[clap(long, conflicts_with = "apply")]
+    #[clap(long, group = "summary", conflicts_with = "apply")]
     pub(crate) check: bool,
 
+    /// Show what the upgrade would do without changing anything: the target
+    /// image, how it would be verified, the estimated download size and the
+    /// change to the origin of the deployment.
+    ///
+    /// Like `--check`, this only downloads the manifest and image configuration.
+    #[clap(long, group = "summary", conflicts_with_all = ["apply", "download_only", "stage_cached"])]
+    pub(crate) dry_run: bool,
+
+    /// The output format for `--check` and `--dry-run`; defaults to human readable.
+    #[clap(long, requires = "summary")]
+    pub(crate) format: Option<OutputFormat>,
+
+    /// Fetch the update into the local store, but do not stage it.
+    ///
+    /// Use `--stage-cached` later (e.g. in a maintenance window) to stage it
+    /// without accessing the network.
+    #[clap(long, conflicts_with_all = ["check", "apply", "stage_cached"])]
+    pub(crate) download_only: bool,
+
+    /// Stage the update previously fetched with `--download-only`, without
+    /// accessing the network.
+    #[clap(long, conflicts_with_all = ["check", "to_digest"])]
+    pub(crate) stage_cached: bool,
+
     /// Restart or reboot into the new target image.
     ///
-    /// Currently, this option always reboots.  In the future this command
-    /// will detect the case where no kernel changes are queued, and perform
-    /// a userspace-only restart.
+    /// By default this always reboots; see also `--soft-reboot`.
     #[clap(long, conflicts_with = "check")]
     pub(crate) apply: bool,
+
+    /// With `--apply`, if the kernel, initramfs and kernel arguments of the new
+    /// deployment are unchanged from the booted one, restart only userspace
+    /// via `systemctl soft-reboot`; otherwise a full reboot is performed.
+    #[clap(long, requires = "apply")]
+    pub(crate) soft_reboot: bool,
+
+    /// With `--apply`, wait at most this many seconds for the drain hook configured
+    /// in the `[reboot]` section of the host configuration, and for inhibitor locks
+    /// held by other processes to be released; if a reboot is still not possible,
+    /// the update stays queued for the next boot.
+    #[clap(long, requires = "apply")]
+    pub(crate) reboot_timeout: Option<u64>,
+
+    /// Write progress events as newline-delimited JSON to this (inherited) file descriptor.
+    #[clap(long)]
+    pub(crate) progress_fd: Option<i32>,
+
+    /// Retry a failed fetch this many times, overriding `retries` in the `[fetch]`
+    /// section of the host configuration.
+    #[clap(long)]
+    pub(crate) retry: Option<u32>,
+
+    /// Limit the download rate of layers, in bytes per second with an optional
+    /// `K`, `M` or `G` suffix (e.g. `2M`), overriding `limit-rate` in the `[fetch]`
+    /// section of the host configuration.  This is not supported yet when fetching
+    /// container images, so fetching fails with an error if it is set.
+    #[clap(long)]
+    pub(crate) limit_rate: Option<crate::config::DownloadRate>,
+
+    /// Download up to this many layers concurrently, overriding
+    /// `max-concurrent-downloads` in the `[fetch]` section of the host configuration.
+    /// Layers are currently fetched one at a time, so values above 1 fail with an error.
+    #[clap(long)]
+    pub(crate) max_concurrent_downloads: Option<std::num::NonZeroU32>,
+
+    /// Fetch exactly this manifest digest (e.g. `sha256:0a1b...`) of the tracked
+    /// image, instead of whatever its tag currently refers to.
+    ///
+    /// The digest is recorded in the origin of the staged deployment; the tracked
+    /// image is unchanged, so a later upgrade without this option follows the tag
+    /// again.  This is only supported for the `registry` transport.
+    #[clap(long)]
+    pub(crate) to_digest: Option<String>,
+
+    /// Refuse to fetch images unless they are verified this way: `sigstore`
+    /// (per the container signature policy) or `ostree-remote:NAME`.
+    ///
+    /// The requirement is recorded in the origin of the staged deployment, and
+    /// enforced by later upgrades and switches.
+    #[clap(long)]
+    pub(crate) require_signature: Option<crate::signature::SignatureRequirement>,
+
+    /// Before fetching, wait up to this many seconds (by default 300) for the
+    /// registry to be resolvable and reachable, retrying with exponential backoff.
+    ///
+    /// If it does not become reachable in time, the fetch is attempted anyway.
+    #[clap(long, value_name = "SECONDS", num_args = 0..=1, default_missing_value = "300")]
+    pub(crate) wait_for_network: Option<u64>,
+
+    /// If another bootc operation is in progress, wait for it to finish instead
+    /// of failing.
+    #[clap(long)]
+    pub(crate) lock_wait: bool,
+}
+
+/// Freezing updates, e.g. during an incident
+#[derive(Debug, clap::Subcommand, PartialEq, Eq)]
+pub(crate) enum UpdateHoldOpts {
+    /// Prevent `bootc upgrade` and the automatic update service from fetching updates,
+    /// until `bootc update unhold` is run.  This persists across reboots.
+    Hold {
+        /// Why updates are held; shown when an upgrade is refused
+        #[clap(long)]
+        reason: Option<String>,
+    },
+    /// Allow updates again.
+    Unhold,
 }
 
 /// Perform an switch operation
@@ -67,7 +177,12 @@ pub(crate) struct SwitchOpts {
     #[clap(long)]
     pub(crate) apply: bool,
 
-    /// The transport; e.g. oci, oci-archive, containers-storage.  Defaults to `registry`.
+    /// The transport; e.g. oci, oci-archive, dir, containers-storage.  Defaults to `registry`.
+    ///
+    /// For the oci, oci-archive and dir transports, a relative path is resolved
+    /// against the current directory.  For the containers-storage transport, an
+    /// image which is not qualified with a registry (e.g. `my-os:dev` as built via
+    /// `podman build`) is found under its `localhost/` name.
     #[clap(long, default_value = "registry")]
     pub(crate) transport: String,
 
@@ -87,6 +202,26 @@ pub(crate) struct SwitchOpts {
     #[clap(long)]
     pub(crate) ostree_remote: Option<String>,
 
+    /// Refuse to fetch images unless they are verified this way: `sigstore`
+    /// (per the container signature policy) or `ostree-remote:NAME`.
+    ///
+    /// The requirement is recorded in the origin of the staged deployment, and
+    /// enforced by later upgrades and switches.
+    #[clap(long, conflicts_with_all = ["enforce_container_sigpolicy", "ostree_remote"])]
+    pub(crate) require_signature: Option<crate::signature::SignatureRequirement>,
+
+    /// Show what the switch would do without changing anything: the target
+    /// image, how it would be verified, the estimated download size and the
+    /// change to the origin of the deployment.
+    ///
+    /// This only downloads the manifest and image configuration.
+    #[clap(long, conflicts_with_all = ["apply", "mutate_in_place"])]
+    pub(crate) dry_run: bool,
+
+    /// The output format for `--dry-run`; defaults to human readable.
+    #[clap(long, requires = "dry_run")]
+    pub(crate) format: Option<OutputFormat>,
+
     /// Don't create a new deployment, but directly mutate the booted state.
     /// This is hidden because it's not something we generally expect to be done,
     /// but this can be used in e.g. Anaconda %post to fixup
@@ -97,28 +232,191 @@ pub(crate) struct SwitchOpts {
     #[clap(long)]
     pub(crate) retain: bool,
 
+    /// Deploy into this stateroot instead of the booted one, creating it if it does
+    /// not exist.
+    ///
+    /// A new stateroot starts out with `/etc` and `/var` as shipped in the image; the
+    /// booted stateroot stays on disk and available as the rollback entry.
+    #[clap(long, conflicts_with_all = ["mutate_in_place", "dry_run"])]
+    pub(crate) stateroot: Option<String>,
+
+    /// Write progress events as newline-delimited JSON to this (inherited) file descriptor.
+    #[clap(long)]
+    pub(crate) progress_fd: Option<i32>,
+
+    /// Retry a failed fetch this many times, overriding `retries` in the `[fetch]`
+    /// section of the host configuration.
+    #[clap(long)]
+    pub(crate) retry: Option<u32>,
+
+    /// Limit the download rate of layers, in bytes per second with an optional
+    /// `K`, `M` or `G` suffix (e.g. `2M`), overriding `limit-rate` in the `[fetch]`
+    /// section of the host configuration.  This is not supported yet when fetching
+    /// container images, so fetching fails with an error if it is set.
+    #[clap(long)]
+    pub(crate) limit_rate: Option<crate::config::DownloadRate>,
+
+    /// Download up to this many layers concurrently, overriding
+    /// `max-concurrent-downloads` in the `[fetch]` section of the host configuration.
+    /// Layers are currently fetched one at a time, so values above 1 fail with an error.
+    #[clap(long)]
+    pub(crate) max_concurrent_downloads: Option<std::num::NonZeroU32>,
+
+    /// If another bootc operation is in progress, wait for it to finish instead
+    /// of failing.
+    #[clap(long)]
+    pub(crate) lock_wait: bool,
+
     /// Target image to use for the next boot.
     pub(crate) target: String,
 }
 
 /// Options controlling rollback
 #[derive(Debug, Parser, PartialEq, Eq)]
-pub(crate) struct RollbackOpts {}
+pub(crate) struct RollbackOpts {
+    /// Queue this deployment for the next boot instead of the rollback one: an
+    /// index as shown by `--list`, an ostree commit checksum (or a unique prefix
+    /// of one), or a manifest digest (`sha256:...`).
+    #[clap(long, conflicts_with = "list")]
+    pub(crate) to: Option<crate::deploy::RollbackTarget>,
+
+    /// List the deployments which can be rolled back to.
+    #[clap(long)]
+    pub(crate) list: bool,
+
+    /// Restart or reboot into the deployment queued for the next boot.
+    ///
+    /// By default this always reboots; see also `--soft-reboot`.
+    #[clap(long, conflicts_with = "list")]
+    pub(crate) apply: bool,
+
+    /// With `--apply`, if the kernel, initramfs and kernel arguments of the queued
+    /// deployment are unchanged from the booted one, restart only userspace
+    /// via `systemctl soft-reboot`; otherwise a full reboot is performed.
+    #[clap(long, requires = "apply")]
+    pub(crate) soft_reboot: bool,
+
+    /// With `--apply`, wait at most this many seconds for the drain hook configured
+    /// in the `[reboot]` section of the host configuration, and for inhibitor locks
+    /// held by other processes to be released; if a reboot is still not possible,
+    /// the rollback stays queued for the next boot.
+    #[clap(long, requires = "apply")]
+    pub(crate) reboot_timeout: Option<u64>,
+
+    /// If another bootc operation is in progress, wait for it to finish instead
+    /// of failing.
+    #[clap(long)]
+    pub(crate) lock_wait: bool,
+}
+
+/// Reinstall the system in place
+#[derive(Debug, Parser, PartialEq, Eq)]
+pub(crate) struct SystemReinstallOpts {
+    /// Carry over this path (below `/etc` or `/var`) into the reinstalled system;
+    /// may be specified multiple times.
+    #[clap(long)]
+    pub(crate) keep: Vec<Utf8PathBuf>,
+
+    /// The name of the new stateroot; defaults to `reinstall-` followed by the
+    /// current time.
+    #[clap(long)]
+    pub(crate) stateroot: Option<String>,
+
+    /// Don't display progress
+    #[clap(long)]
+    pub(crate) quiet: bool,
+
+    /// Reboot into the reinstalled system.
+    #[clap(long)]
+    pub(crate) apply: bool,
+}
+
+/// Options for migrating from rpm-ostree
+#[derive(Debug, Parser, PartialEq, Eq)]
+pub(crate) struct MigrateFromRpmOstreeOpts {
+    /// Discard the packages layered, overridden or removed via rpm-ostree (and
+    /// other local rpm-ostree changes), instead of only reporting them.
+    #[clap(long)]
+    pub(crate) discard_layering: bool,
+
+    /// The container image to track; defaults to the one the booted deployment
+    /// is based on.
+    #[clap(long)]
+    pub(crate) image: Option<String>,
+
+    /// The transport of `--image`; e.g. oci, oci-archive, dir, containers-storage.
+    /// Defaults to `registry`.
+    #[clap(long, requires = "image")]
+    pub(crate) transport: Option<String>,
+
+    /// Don't display progress
+    #[clap(long)]
+    pub(crate) quiet: bool,
+
+    /// Reboot into the migrated deployment.
+    #[clap(long)]
+    pub(crate) apply: bool,
+
+    /// If another bootc operation is in progress, wait for it to finish instead
+    /// of failing.
+    #[clap(long)]
+    pub(crate) lock_wait: bool,
+}
 
 /// Perform an edit operation
 #[derive(Debug, Parser, PartialEq, Eq)]
 pub(crate) struct EditOpts {
-    /// Use filename to edit system specification
-    #[clap(long, short = 'f')]
+    /// Use filename to edit system specification; `-` reads it from standard
+    /// input.
+    #[clap(long, short = 'f', alias = "file")]
     pub(crate) filename: Option<String>,
 
     /// Don't display progress
     #[clap(long)]
     pub(crate) quiet: bool,
+
+    /// If another bootc operation is in progress, wait for it to finish instead
+    /// of failing.
+    #[clap(long)]
+    pub(crate) lock_wait: bool,
 }
 
-#[derive(Debug, Clone, ValueEnum, PartialEq, Eq)]
-#[clap(rename_all = "lowercase")]
+/// Perform an apply operation
+#[derive(Debug, Parser, PartialEq, Eq)]
+pub(crate) struct ApplyOpts {
+    /// The host specification to apply; `-` reads it from standard input.
+    #[clap(long, short = 'f', alias = "file")]
+    pub(crate) filename: String,
+
+    /// Don't display progress
+    #[clap(long)]
+    pub(crate) quiet: bool,
+
+    /// If another bootc operation is in progress, wait for it to finish instead
+    /// of failing.
+    #[clap(long)]
+    pub(crate) lock_wait: bool,
+}
+
+/// Show the messages logged by bootc
+#[derive(Debug, Parser, PartialEq, Eq)]
+pub(crate) struct JournalOpts {
+    /// Only show messages of this kind; may be given multiple times
+    #[clap(long, value_enum)]
+    pub(crate) event: Vec<crate::journal::Event>,
+
+    /// List the kinds of messages and their `MESSAGE_ID`s
+    #[clap(long, conflicts_with = "event")]
+    pub(crate) list: bool,
+
+    /// Additional arguments for `journalctl`, e.g. `-- --since=today -o json`
+    #[clap(last = true)]
+    pub(crate) args: Vec<String>,
+}
+
+/// An output format: one of `humanreadable`, `yaml`, `json`, `markdown`, or
+/// `ext:NAME` for an external renderer (see [`crate::render`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) enum OutputFormat {
     /// Output in Human Readable format.
     HumanReadable,
@@ -126,6 +424,30 @@ pub(crate) enum OutputFormat {
     Yaml,
     /// Output in JSON format.
     Json,
+    /// Output a Markdown report, suitable for e.g. pasting into a ticket.
+    Markdown,
+    /// Output via the named external renderer.
+    External(String),
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let r = match s {
+            "humanreadable" => Self::HumanReadable,
+            "yaml" => Self::Yaml,
+            "json" => Self::Json,
+            "markdown" => Self::Markdown,
+            o => match o.strip_prefix("ext:") {
+                Some(name) if !name.is_empty() => Self::External(name.to_owned()),
+                _ => anyhow::bail!(
+                    "Unknown output format {o:?}; expected one of humanreadable, yaml, json, markdown, ext:NAME"
+                ),
+            },
+        };
+        Ok(r)
+    }
 }
 
 /// Perform an status operation
@@ -137,7 +459,9 @@ pub(crate) struct StatusOpts {
     #[clap(long, hide = true)]
     pub(crate) json: bool,
 
-    /// The output format.
+    /// The output format: `humanreadable`, `yaml`, `json`, `markdown`, or `ext:NAME`
+    /// to use the external renderer `NAME` from `/etc/bootc/renderers` or
+    /// `/usr/lib/bootc/renderers`.
     #[clap(long)]
     pub(crate) format: Option<OutputFormat>,
 
@@ -151,6 +475,22 @@ pub(crate) struct StatusOpts {
     /// Only display status for the booted deployment.
     #[clap(long)]
     pub(crate) booted: bool,
+
+    /// Print a compact summary of pending changes (e.g. `⬆ staged`) for use in
+    /// shell prompts.
+    ///
+    /// This does not load the system state, but reads a summary cached by the most
+    /// recent bootc operation, and prints nothing if there is none.
+    #[clap(long, conflicts_with_all = ["json", "format", "format_version", "booted", "full_digests"])]
+    pub(crate) prompt: bool,
+
+    /// Show complete digests in human readable output.
+    ///
+    /// By default, digests are abbreviated to the shortest prefix (of at least 12
+    /// characters) which is unique among the images in the local store.  Other
+    /// output formats always include complete digests.
+    #[clap(long)]
+    pub(crate) full_digests: bool,
 }
 
 #[cfg(feature = "install")]
@@ -167,6 +507,13 @@ pub(crate) enum InstallOpts {
     /// the EFI system partition. Use `install to-filesystem` for anything more
     /// complex such as RAID, LVM, LUKS etc.
     ToDisk(crate::install::InstallToDiskOpts),
+    /// Install to a block device as described by a blueprint.
+    ///
+    /// The blueprint is a TOML file which selects the target disk by device path,
+    /// serial number or WWN, and contains the options of `install to-disk` as well as
+    /// install configuration taking precedence over that of the image.  This allows
+    /// the same installation to be repeated on many machines from a single file.
+    FromConfig(crate::install::blueprint::InstallFromConfigOpts),
     /// Install to an externally created filesystem structure.
     ///
     /// In this variant of installation, the root filesystem alongside any necessary
@@ -181,6 +528,18 @@ pub(crate) enum InstallOpts {
     /// will be wiped, but the content of the existing root will otherwise be retained, and will
     /// need to be cleaned up if desired when rebooted into the new root.
     ToExistingRoot(crate::install::InstallToExistingRootOpts),
+    /// Run the first phase of a split installation: set up the target filesystems.
+    ///
+    /// The installation is split into `prepare`, `deploy` and `finalize`, which can be
+    /// run individually by an OS installer that performs some of these phases itself.
+    /// This writes a JSON handoff file which is passed to the next phase.
+    Prepare(crate::install::phases::InstallPrepareOpts),
+    /// Run the second phase of a split installation: pull and deploy the image, and
+    /// any logically bound images.
+    Deploy(crate::install::phases::InstallDeployOpts),
+    /// Run the last phase of a split installation: install the bootloader, finalize
+    /// the filesystems and deactivate any block devices set up by `install prepare`.
+    Finalize(crate::install::phases::InstallFinalizeOpts),
     /// Output JSON to stdout that contains the merged installation configuration
     /// as it may be relevant to calling processes using `install to-filesystem`
     /// that in particular want to discover the desired root filesystem type from the container image.
@@ -205,8 +564,40 @@ pub(crate) enum ContainerOpts {
     /// build.
     ///
     /// This is intended to be invoked via e.g. `RUN bootc container lint` as part
-    /// of a build process; it will error if any problems are detected.
-    Lint,
+    /// of a build process; it will error if any problems with the severity `error`
+    /// are detected, while those with the severity `warning` are only shown.
+    Lint(ContainerLintOpts),
+    /// Finalize the container image for bootc, failing if any problems are detected.
+    ///
+    /// This is intended to be invoked via `RUN bootc container commit` as the last step
+    /// of a build.  It removes the contents of `/run`, `/tmp`, `/var/tmp` and `/var/cache`,
+    /// empties `/etc/machine-id`, moves files in `/var` to `/usr/share/factory/var` and
+    /// creates everything in `/var` via `systemd-tmpfiles`, regenerates the initramfs
+    /// if `/usr/lib/ostree/prepare-root.conf` changed, and runs the lints of
+    /// `bootc container lint`.
+    Commit,
+}
+
+/// Options for `bootc container lint`.
+#[derive(Debug, Parser, PartialEq, Eq)]
+pub(crate) struct ContainerLintOpts {
+    /// The output format: `humanreadable`, `json` or `yaml`.
+    #[clap(long)]
+    pub(crate) format: Option<OutputFormat>,
+
+    /// Also fail if any problems with the severity `warning` are detected.
+    #[clap(long)]
+    pub(crate) fatal_warnings: bool,
+
+    /// Do not run the lint with this name.  This option can be provided multiple times.
+    ///
+    /// Lints can also be waived in the image via `/usr/lib/bootc/lint.d`.
+    #[clap(long)]
+    pub(crate) skip: Vec<String>,
+
+    /// List the lints with their severity and description, and exit.
+    #[clap(long, conflicts_with_all = ["format", "fatal_warnings", "skip"])]
+    pub(crate) list: bool,
 }
 
 /// Subcommands which operate on images.
@@ -239,8 +630,13 @@ pub(crate) enum ImageCmdOpts {
 pub(crate) enum ImageOpts {
     /// List fetched images stored in the bootc storage.
     ///
-    /// Note that these are distinct from images stored via e.g. `podman`.
-    List,
+    /// Note that these are distinct from images stored via e.g. `podman`.  For each image,
+    /// the digest, version, size and whether a deployment uses it are shown.
+    List {
+        /// The output format: `humanreadable`, `json` or `yaml`.
+        #[clap(long)]
+        format: Option<OutputFormat>,
+    },
     /// Copy a container image from the bootc storage to `containers-storage:`.
     ///
     /// The source and target are both optional; if both are left unspecified,
@@ -267,11 +663,30 @@ pub(crate) enum ImageOpts {
         /// this will make the image accessible via e.g. `podman run localhost/bootc` and for builds.
         target: Option<String>,
     },
+    /// Export the booted deployment as a container image, e.g. to capture the state of a
+    /// host and deploy it on other machines.
+    ///
+    /// The ostree commit of the booted deployment is written as a new image, split into
+    /// layers by package.  Machine-local state in `/etc` and `/var` is not included.
+    Export(ImageExportOpts),
+    /// Rewrite an image with its layers split by package, e.g. after deriving it via
+    /// `FROM` and `dnf install`, so that updates only change the layers of updated packages.
+    ///
+    /// If the destination already exists, its layer structure is kept, so that clients
+    /// pulling the new version can reuse the layers of unchanged packages.
+    Rechunk(ImageRechunkOpts),
     /// Copy a container image from the default `containers-storage:` to the bootc-owned container storage.
     PullFromDefaultStorage {
         /// The image to pull
         image: String,
     },
+    /// Write a bootable disk image of this container image, e.g. for virtual machines.
+    ///
+    /// This must be run from the container image, in the same way as
+    /// `bootc install to-disk`; it installs via a loopback device to a sparse
+    /// raw file, and then converts it with `qemu-img` to the requested format.
+    #[cfg(feature = "install")]
+    BuildDisk(Box<crate::install::BuildDiskOpts>),
     /// List fetched images stored in the bootc storage.
     ///
     /// Note that these are distinct from images stored via e.g. `podman`.
@@ -279,6 +694,32 @@ pub(crate) enum ImageOpts {
     Cmd(ImageCmdOpts),
 }
 
+/// Options for `bootc image export`.
+#[derive(Debug, Parser, PartialEq, Eq)]
+pub(crate) struct ImageExportOpts {
+    /// The destination, e.g. `oci-archive:host.tar` or `registry:quay.io/example/host:latest`.
+    #[clap(long)]
+    pub(crate) output: String,
+
+    /// The maximum number of layers of the image.
+    #[clap(long)]
+    pub(crate) max_layers: Option<std::num::NonZeroU32>,
+}
+
+/// Options for `bootc image rechunk`.
+#[derive(Debug, Parser, PartialEq, Eq)]
+pub(crate) struct ImageRechunkOpts {
+    /// The image to rechunk, e.g. `containers-storage:localhost/derived`.
+    pub(crate) src: String,
+
+    /// The destination, e.g. `registry:quay.io/example/derived:latest`.
+    pub(crate) dest: String,
+
+    /// The maximum number of layers of the image.
+    #[clap(long)]
+    pub(crate) max_layers: Option<std::num::NonZeroU32>,
+}
+
 /// Hidden, internal only options
 #[derive(Debug, clap::Subcommand, PartialEq, Eq)]
 pub(crate) enum InternalsOpts {
@@ -294,6 +735,232 @@ pub(crate) enum InternalsOpts {
     PrintJsonSchema,
     /// Perform cleanup actions
     Cleanup,
+    /// Run the pending first boot commands
+    RunFirstboot,
+    /// Verify that the staged (or booted) deployment is the image which was fetched
+    VerifyDeployment {
+        /// Verify the booted deployment instead of the staged one
+        #[clap(long)]
+        booted: bool,
+    },
+    /// Program the RTC to power on the system at the next configured wake time
+    ScheduleWake,
+    /// If the system was powered on at the scheduled wake time, fetch updates and power off
+    WakeUpdate,
+    /// Restore the state kept by `bootc system-reinstall`
+    RestoreReinstallBackup,
+    /// Log whether an update was booted or failed to be finalized, run at each boot
+    LogBoot,
+    /// Synchronize the systemd-boot entries, kernels and UKIs in the ESP with the deployments
+    SyncSystemdBoot,
+    /// Fetch and stage updates, optionally repeating at an interval.
+    ///
+    /// Without `--stage-only`, this is equivalent to `bootc update-service`.
+    FetchApplyUpdates {
+        /// Never reboot; log a journal message (`MESSAGE_ID=5c3b0e1f8d7a4c2b9e6f1a0d3c8b7e42`)
+        /// when an update has been staged and a reboot is needed to apply it
+        #[clap(long)]
+        stage_only: bool,
+        /// Keep running, checking for updates every this many seconds
+        #[clap(long)]
+        interval: Option<u64>,
+    },
+    /// Fabricate host status fixtures and scratch sysroots, for testing tooling built on bootc
+    #[clap(subcommand)]
+    Testing(TestingOpts),
+    /// Handle line-delimited JSON-RPC requests on standard input, for embedding bootc
+    /// in e.g. installers and provisioning agents.
+    Api,
+}
+
+/// Operations on host status fixtures and scratch sysroots
+#[derive(Debug, clap::Subcommand, PartialEq, Eq)]
+pub(crate) enum TestingOpts {
+    /// Write a fabricated host status with the given images.
+    ///
+    /// No deployments are created; the digests and ostree commits are fake.
+    FabricateStatus {
+        /// The booted image
+        #[clap(long)]
+        booted: Option<String>,
+        /// The staged image
+        #[clap(long)]
+        staged: Option<String>,
+        /// The rollback image
+        #[clap(long)]
+        rollback: Option<String>,
+        /// Queue the rollback image for the next boot
+        #[clap(long, requires = "rollback")]
+        rollback_queued: bool,
+        /// The output format
+        #[clap(long, default_value = "yaml")]
+        format: OutputFormat,
+    },
+    /// Render a host status read from a file, as `bootc status` would.
+    RenderStatus {
+        /// Path to a host status in YAML or JSON format
+        path: Utf8PathBuf,
+        /// The output format
+        #[clap(long, default_value = "humanreadable")]
+        format: OutputFormat,
+        /// Show digests in full
+        #[clap(long)]
+        full_digests: bool,
+    },
+    /// Create a scratch sysroot with real ostree deployments of fabricated images.
+    ///
+    /// The images have a fake kernel, manifest and configuration.  The booted
+    /// image is deployed last; staged deployments are not supported.
+    FabricateSysroot {
+        /// Path to the new sysroot, which must not exist or be empty
+        path: Utf8PathBuf,
+        /// The booted image
+        #[clap(long)]
+        booted: String,
+        /// The rollback image
+        #[clap(long)]
+        rollback: Option<String>,
+        /// Queue the rollback image for the next boot
+        #[clap(long, requires = "rollback")]
+        rollback_queued: bool,
+    },
+    /// Display the status of a fabricated sysroot, as `bootc status` would when
+    /// booted into it.
+    SysrootStatus {
+        /// Path to a sysroot created via `fabricate-sysroot`
+        path: Utf8PathBuf,
+        /// The output format
+        #[clap(long, default_value = "humanreadable")]
+        format: OutputFormat,
+    },
+    /// Display what `bootc upgrade --dry-run` would do when booted into a
+    /// fabricated sysroot; with `--image`, what `bootc switch --dry-run` would do.
+    Plan {
+        /// Path to a sysroot created via `fabricate-sysroot`
+        path: Utf8PathBuf,
+        /// Switch to this image, e.g. a directory with `--transport oci`
+        #[clap(long)]
+        image: Option<String>,
+        /// The transport of `--image`; e.g. oci, oci-archive, dir, containers-storage.
+        /// Defaults to `registry`.
+        #[clap(long, requires = "image")]
+        transport: Option<String>,
+        /// The output format
+        #[clap(long)]
+        format: Option<OutputFormat>,
+    },
+}
+
+/// Operations on a transaction, which groups multiple changes to the host specification
+#[derive(Debug, clap::Subcommand, PartialEq, Eq)]
+pub(crate) enum TransactionOpts {
+    /// Start a transaction; until it is committed, `bootc switch` and `bootc edit`
+    /// modify a pending host specification instead of staging a deployment.
+    Begin,
+    /// Apply the pending host specification, producing a single staged deployment.
+    Commit {
+        /// Don't display progress
+        #[clap(long)]
+        quiet: bool,
+    },
+    /// Discard the pending transaction.
+    Abort,
+    /// Display the pending host specification.
+    Show,
+}
+
+/// Selects the deployment whose kernel arguments are shown or changed
+#[derive(Debug, clap::Args, PartialEq, Eq)]
+pub(crate) struct KargsTargetOpts {
+    /// Use the staged deployment, which must exist
+    #[clap(long, conflicts_with = "booted")]
+    pub(crate) staged: bool,
+
+    /// Use the booted deployment; a change replaces any staged deployment
+    #[clap(long)]
+    pub(crate) booted: bool,
+}
+
+/// Options for changing kernel arguments
+#[derive(Debug, clap::Args, PartialEq, Eq)]
+pub(crate) struct KargsEditOpts {
+    /// The kernel arguments
+    #[clap(required = true)]
+    pub(crate) kargs: Vec<String>,
+
+    #[clap(flatten)]
+    pub(crate) target: KargsTargetOpts,
+
+    /// If another bootc operation is in progress, wait for it to finish instead
+    /// of failing.
+    #[clap(long)]
+    pub(crate) lock_wait: bool,
+}
+
+/// Operations on the kernel arguments
+#[derive(Debug, clap::Subcommand, PartialEq, Eq)]
+pub(crate) enum KargsOpts {
+    /// Print the kernel arguments.
+    List(KargsTargetOpts),
+    /// Add kernel arguments, unless already present.
+    Append(KargsEditOpts),
+    /// Remove kernel arguments given as `KEY=VALUE`, or all values of those given as `KEY`.
+    Delete(KargsEditOpts),
+    /// Replace all values of the kernel arguments given as `KEY=VALUE`.
+    Replace(KargsEditOpts),
+    /// Boot once with additional kernel arguments, e.g. `systemd.unit=rescue.target`;
+    /// the boot after that uses the regular kernel arguments again.  Arguments given
+    /// as `KEY=VALUE` replace all values of `KEY`.  Only GRUB is supported.
+    SetOnce(KargsEditOpts),
+    /// Compare the kernel arguments declared by the image in kargs.d, those added
+    /// locally, those of the boot entry of the booted deployment and those of the
+    /// running kernel, and show any drift between them.
+    Diff,
+}
+
+/// Operations on health checks
+#[derive(Debug, clap::Subcommand, PartialEq, Eq)]
+pub(crate) enum HealthOpts {
+    /// Run the health checks in `/usr/lib/bootc/health.d` and record the result.
+    ///
+    /// If a check fails, the actions configured in the `[health]` section of the
+    /// host configuration are taken, and the command fails.
+    Run,
+}
+
+/// The format used by `bootc deployment export`
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq)]
+#[clap(rename_all = "lowercase")]
+pub(crate) enum DeploymentExportFormat {
+    /// A plain directory tree.
+    Dir,
+    /// An OCI image layout directory.
+    Oci,
+}
+
+/// Operations on deployments
+#[derive(Debug, clap::Subcommand, PartialEq, Eq)]
+pub(crate) enum DeploymentOpts {
+    /// Write the contents of a deployment to a new directory, for offline analysis
+    /// or for feeding into e.g. scanning tools.
+    Export {
+        /// The index of the deployment, as shown by `ostree admin status`; `0` is
+        /// the default boot entry.
+        index: usize,
+
+        /// The output format.
+        #[clap(long, value_enum, default_value_t = DeploymentExportFormat::Dir)]
+        to: DeploymentExportFormat,
+
+        /// Include the deployment's merged `/etc` instead of the defaults from `/usr/etc`.
+        ///
+        /// This is only supported when exporting to a directory.
+        #[clap(long)]
+        etc: bool,
+
+        /// The path to write to, which must not exist.
+        output: Utf8PathBuf,
+    },
 }
 
 #[derive(Debug, clap::Subcommand, PartialEq, Eq)]
@@ -316,8 +983,21 @@ impl InternalsOpts {
 /// updates can be pulled and `bootc upgrade`.
 #[derive(Debug, Parser, PartialEq, Eq)]
 #[clap(name = "bootc")]
-#[clap(rename_all = "kebab-case")]
 #[clap(version,long_version=CLAP_LONG_VERSION)]
+pub(crate) struct Cli {
+    /// Disable all verbs which mutate the host.
+    ///
+    /// This is always the case for binaries built with the `read-only` feature.
+    #[clap(long, global = true)]
+    pub(crate) read_only: bool,
+
+    #[clap(subcommand)]
+    pub(crate) opt: Opt,
+}
+
+/// The verbs of `bootc`.
+#[derive(Debug, clap::Subcommand, PartialEq, Eq)]
+#[clap(rename_all = "kebab-case")]
 #[allow(clippy::large_enum_variant)]
 pub(crate) enum Opt {
     /// Download and queue an updated container image to apply.
@@ -334,6 +1014,49 @@ pub(crate) enum Opt {
     /// do *not* automatically apply the update in addition.
     #[clap(alias = "update")]
     Upgrade(UpgradeOpts),
+    /// Reinstall the currently configured image, discarding local state.
+    ///
+    /// The image is deployed into a new stateroot, so that the system boots with `/etc` and
+    /// `/var` as shipped in the image; paths passed via `--keep` are backed up first, and restored
+    /// on the next boot.  The previous stateroot remains on disk, and stays available as the
+    /// rollback deployment.
+    SystemReinstall(SystemReinstallOpts),
+    /// Migrate a deployment with local rpm-ostree changes to bootc.
+    ///
+    /// The local changes recorded by rpm-ostree (e.g. layered packages) are reported; with
+    /// `--discard-layering`, the container image the booted deployment is based on is staged
+    /// without them, so that the system can be updated via bootc from then on.
+    MigrateFromRpmOstree(MigrateFromRpmOstreeOpts),
+    /// Fetch and apply updates as configured in the `[updates]` section of the host configuration.
+    ///
+    /// This is run by `bootc-fetch-apply-updates.service`; an update which was staged is
+    /// applied according to the configured `reboot` strategy.
+    UpdateService,
+    /// Serve a varlink API for managing the host, e.g. for Cockpit.
+    ///
+    /// This is socket activated via `bootc-service.socket`, on
+    /// `/run/bootc/org.containers.bootc`.  The `org.containers.bootc` interface provides
+    /// `GetStatus`, `CheckUpdate`, `Upgrade`, `Switch` and `Rollback`; callers other than
+    /// root are authorized via polkit.
+    Service,
+    /// Mark the current boot as successful.
+    ///
+    /// This is run by `bootc-boot-complete.service` once `boot-complete.target` is reached.  If
+    /// boot counting is configured and the system fell back to the rollback deployment because an
+    /// update failed to boot, the booted deployment is queued as the default again.
+    BootComplete,
+    /// Run health checks for the booted deployment.
+    ///
+    /// `bootc health run` is run by `bootc-health.service` after each boot, before
+    /// `boot-complete.target`; the result is shown by `bootc status`.
+    #[clap(subcommand)]
+    Health(HealthOpts),
+    /// Show the structured messages logged by bootc.
+    ///
+    /// bootc logs messages with a stable `MESSAGE_ID` to the systemd journal at key
+    /// points, such as when an update was staged or booted, or a health check failed;
+    /// log pipelines can match on these identifiers.  This shows them via `journalctl`.
+    Journal(JournalOpts),
     /// Target a new container image reference to boot.
     ///
     /// This is almost exactly the same operation as `upgrade`, but additionally changes the container image reference
@@ -349,6 +1072,12 @@ pub(crate) enum Opt {
     /// and the current will become rollback.  If there is a `staged` entry (an unapplied, queued upgrade)
     /// then it will be discarded.
     ///
+    /// With `--to`, any other retained deployment (see `--list`) is queued for the next boot
+    /// instead; the remaining deployments keep their order after it.
+    ///
+    /// With `--apply`, the system is rebooted (or soft rebooted, with `--soft-reboot`)
+    /// into the queued deployment right away.
+    ///
     /// Note that absent any additional control logic, if there is an active agent doing automated upgrades
     /// (such as the default `bootc-fetch-apply-updates.timer` and associated `.service`) the
     /// change here may be reverted.  It's recommended to only use this in concert with an agent that
@@ -363,10 +1092,47 @@ pub(crate) enum Opt {
     /// then the current host specification will be presented in the system default `$EDITOR`
     /// for interactive changes.
     ///
-    /// It is also possible to directly provide new contents via `bootc edit --filename`.
+    /// It is also possible to directly provide new contents via `bootc edit --filename`,
+    /// or from standard input via `--filename -`.
+    ///
+    /// The new contents are validated against the schema of the host specification;
+    /// unknown fields are rejected.  The changes are shown as a diff before being applied.
     ///
     /// Only changes to the `spec` section are honored.
     Edit(EditOpts),
+    /// Converge the host to a declarative specification.
+    ///
+    /// This takes a complete host resource as shown by `bootc status --format=yaml`
+    /// (with `apiVersion`, `kind` and `spec`), validated as for `bootc edit`, and
+    /// switches the image, updates the kernel arguments added via `spec.kargs`, or
+    /// changes the boot order as needed.  If the host already matches, nothing is done,
+    /// so this is suitable for configuration management tools.
+    ///
+    /// Unlike `bootc edit`, a missing `spec.kargs` keeps the current kernel arguments.
+    Apply(ApplyOpts),
+    /// Group multiple changes into a single staged deployment.
+    ///
+    /// After `bootc transaction begin`, invocations of `bootc switch` and `bootc edit`
+    /// only modify a pending host specification; `bootc transaction commit` then
+    /// fetches and stages the result once.  The pending transaction is stored in
+    /// `/run` and hence does not persist across reboots.
+    #[clap(subcommand)]
+    Transaction(TransactionOpts),
+    /// Display and change the kernel arguments.
+    ///
+    /// By default, this operates on the staged deployment if there is one, and
+    /// otherwise on the booted one.  A change stages a new deployment of the same
+    /// image, without fetching anything.  Added and replaced arguments are recorded
+    /// in `spec.kargs` and kept by later updates; removed arguments stay removed
+    /// once the changed deployment is booted.
+    #[clap(subcommand)]
+    Kargs(KargsOpts),
+    /// Operations on deployments
+    ///
+    /// Stability: This interface is not declared stable and may change or be removed
+    /// at any point in the future.
+    #[clap(subcommand, hide = true)]
+    Deployment(DeploymentOpts),
     /// Display status
     ///
     /// This will output a YAML-formatted object using a schema intended to match a Kubernetes resource
@@ -544,23 +1310,178 @@ fn prepare_for_write() -> Result<()> {
     Ok(())
 }
 
-/// Implementation of the `bootc upgrade` CLI command.
-#[context("Upgrading")]
-async fn upgrade(opts: UpgradeOpts) -> Result<()> {
-    let sysroot = &get_storage().await?;
-    let repo = &sysroot.repo();
-    let (booted_deployment, _deployments, host) =
-        crate::status::get_status_require_booted(sysroot)?;
-    let imgref = host.spec.image.as_ref();
-    // If there's no specified image, let's be nice and check if the booted system is using rpm-ostree
-    if imgref.is_none() {
-        let booted_incompatible = host
-            .status
-            .booted
-            .as_ref()
-            .map_or(false, |b| b.incompatible);
+/// Reboot into the staged deployment; if `soft` is set, use a soft reboot
+/// when the deployment allows it.
+fn apply_staged(
+    sysroot: &crate::store::Storage,
+    update: &crate::hooks::Update,
+    soft: bool,
+    timeout: Option<std::time::Duration>,
+) -> Result<()> {
+    crate::hooks::run(crate::hooks::Hook::PreReboot, update)?;
+    if soft {
+        sysroot.load(gio::Cancellable::NONE)?;
+        let booted = sysroot.require_booted_deployment()?;
+        match sysroot.staged_deployment() {
+            Some(staged) if crate::deploy::soft_reboot_capable(&booted, &staged) => {
+                return crate::reboot::soft_reboot(timeout);
+            }
+            _ => {
+                println!("Kernel, initramfs or kernel arguments changed; performing a full reboot")
+            }
+        }
+    }
+    crate::reboot::reboot(timeout)
+}
 
-        let staged_incompatible = host
+/// Reboot into the deployment queued by `bootc rollback`; if `soft` is set,
+/// use a soft reboot when the deployment allows it.
+fn apply_rollback(
+    sysroot: &crate::store::Storage,
+    soft: bool,
+    timeout: Option<std::time::Duration>,
+) -> Result<()> {
+    sysroot.load(gio::Cancellable::NONE)?;
+    let booted = sysroot.require_booted_deployment()?;
+    let queued = sysroot
+        .deployments()
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("No deployments"))?;
+    if queued.equal(&booted) {
+        println!("The booted deployment is queued for the next boot; not rebooting");
+        return Ok(());
+    }
+    if soft {
+        if crate::deploy::soft_reboot_capable(&booted, &queued) {
+            return crate::reboot::soft_reboot(timeout);
+        }
+        println!("Kernel, initramfs or kernel arguments changed; performing a full reboot")
+    }
+    crate::reboot::reboot(timeout)
+}
+
+/// Write the result of `--dry-run` in the given format.
+fn write_plan(plan: &crate::deploy::TransactionPlan, format: Option<OutputFormat>) -> Result<()> {
+    let mut out = std::io::stdout().lock();
+    match format.unwrap_or(OutputFormat::HumanReadable) {
+        OutputFormat::HumanReadable => plan.write_human(&mut out)?,
+        OutputFormat::Json => serde_json::to_writer_pretty(&mut out, plan)?,
+        OutputFormat::Yaml => serde_yaml::to_writer(&mut out, plan)?,
+        OutputFormat::Markdown | OutputFormat::External(_) => {
+            anyhow::bail!("Only human readable, JSON and YAML output are supported for --dry-run")
+        }
+    }
+    Ok(())
+}
+
+/// Write the result of `bootc container lint`.
+fn write_lint_report(report: &lints::LintReport, format: Option<OutputFormat>) -> Result<()> {
+    let mut out = std::io::stdout().lock();
+    match format.unwrap_or(OutputFormat::HumanReadable) {
+        OutputFormat::HumanReadable => report.write_human(&mut out)?,
+        OutputFormat::Json => serde_json::to_writer_pretty(&mut out, report)?,
+        OutputFormat::Yaml => serde_yaml::to_writer(&mut out, report)?,
+        OutputFormat::Markdown | OutputFormat::External(_) => {
+            anyhow::bail!("Only human readable, JSON and YAML output are supported for lints")
+        }
+    }
+    Ok(())
+}
+
+/// Write the result of `bootc image list`.
+fn write_image_list(list: &crate::image::ImageList, format: Option<OutputFormat>) -> Result<()> {
+    let mut out = std::io::stdout().lock();
+    match format.unwrap_or(OutputFormat::HumanReadable) {
+        OutputFormat::HumanReadable => list.write_human(&mut out)?,
+        OutputFormat::Json => serde_json::to_writer_pretty(&mut out, list)?,
+        OutputFormat::Yaml => serde_yaml::to_writer(&mut out, list)?,
+        OutputFormat::Markdown | OutputFormat::External(_) => {
+            anyhow::bail!("Only human readable, JSON and YAML output are supported for images")
+        }
+    }
+    Ok(())
+}
+
+/// Write the result of `bootc upgrade --check`.
+fn write_update_check(
+    summary: &crate::deploy::UpdateCheck,
+    format: Option<OutputFormat>,
+) -> Result<()> {
+    let mut out = std::io::stdout().lock();
+    match format.unwrap_or(OutputFormat::HumanReadable) {
+        OutputFormat::HumanReadable => summary.write_human(&mut out)?,
+        OutputFormat::Json => serde_json::to_writer_pretty(&mut out, summary)?,
+        OutputFormat::Yaml => serde_yaml::to_writer(&mut out, summary)?,
+        OutputFormat::Markdown | OutputFormat::External(_) => {
+            anyhow::bail!("Only human readable, JSON and YAML output are supported for --check")
+        }
+    }
+    Ok(())
+}
+
+/// Implementation of the `bootc upgrade` CLI command.
+#[context("Upgrading")]
+async fn upgrade(opts: UpgradeOpts) -> Result<()> {
+    let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    match opts.hold {
+        Some(UpdateHoldOpts::Hold { reason }) => {
+            let hold = crate::hold::hold(root, reason.as_deref())?;
+            println!("{hold}");
+            return Ok(());
+        }
+        Some(UpdateHoldOpts::Unhold) => {
+            match crate::hold::unhold(root)? {
+                Some(hold) => println!("Released hold: {hold}"),
+                None => println!("Updates are not held"),
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+    if opts.check && !rustix::process::getuid().is_root() {
+        return check_unprivileged(opts).await;
+    }
+    if opts.check || opts.dry_run {
+        if let Some(hold) = crate::hold::load(root)? {
+            println!("Note: {hold}");
+        }
+    } else {
+        crate::hold::check(root)?;
+    }
+    let progress = crate::progress_jsonl::ProgressWriter::from_opt(opts.progress_fd)?;
+    let reboot_timeout = opts.reboot_timeout.map(std::time::Duration::from_secs);
+    let run = &Dir::open_ambient_dir("/run", cap_std::ambient_authority())?;
+    // Checking for updates does not change anything
+    let _lock = if opts.check || opts.dry_run {
+        None
+    } else {
+        Some(crate::lock::acquire(run, "upgrade", opts.lock_wait)?)
+    };
+    let sysroot = &get_storage().await?;
+    let repo = &sysroot.repo();
+    let (booted_deployment, deployments, host) = crate::status::get_status_require_booted(sysroot)?;
+    let mut host_spec = host.spec.clone();
+    let require_signature = match host_spec.image.as_mut() {
+        Some(imgref) => crate::signature::enforce(
+            root,
+            repo,
+            opts.require_signature.as_ref(),
+            deployments.staged.as_ref().unwrap_or(&booted_deployment),
+            imgref,
+        )?,
+        None => None,
+    };
+    let imgref = host_spec.image.as_ref();
+    // If there's no specified image, let's be nice and check if the booted system is using rpm-ostree
+    if imgref.is_none() {
+        let booted_incompatible = host
+            .status
+            .booted
+            .as_ref()
+            .map_or(false, |b| b.incompatible);
+
+        let staged_incompatible = host
             .status
             .staged
             .as_ref()
@@ -568,12 +1489,14 @@ async fn upgrade(opts: UpgradeOpts) -> Result<()> {
 
         if booted_incompatible || staged_incompatible {
             return Err(anyhow::anyhow!(
-                "Deployment contains local rpm-ostree modifications; cannot upgrade via bootc. You can run `rpm-ostree reset` to undo the modifications."
+                "Deployment contains local rpm-ostree modifications; cannot upgrade via bootc. You can run `rpm-ostree reset` to undo the modifications, or `bootc migrate-from-rpm-ostree` to migrate to bootc."
             ));
         }
     }
 
-    let spec = RequiredHostSpec::from_spec(&host.spec)?;
+    let spec = RequiredHostSpec::from_spec(&host_spec)?;
+    let policy = crate::policy::PolicyCheck::load(root, &host)?;
+    let booted_status = host.status.booted.as_ref().and_then(|b| b.image.clone());
     let booted_image = host
         .status
         .booted
@@ -581,34 +1504,149 @@ async fn upgrade(opts: UpgradeOpts) -> Result<()> {
         .transpose()?
         .flatten();
     let imgref = imgref.ok_or_else(|| anyhow::anyhow!("No image source specified"))?;
+    if let Some(timeout) = opts.wait_for_network.filter(|_| !opts.stage_cached) {
+        let timeout = std::time::Duration::from_secs(timeout);
+        crate::network::wait_for_registry(imgref, timeout).await?;
+    }
+    let config = crate::config::load_config(root)?;
+    // Unless a digest is given, an update graph may select one
+    let to_digest = match opts.to_digest.as_ref() {
+        Some(digest) => Some(digest.clone()),
+        None if !opts.stage_cached => {
+            crate::graph::target_digest(&config, imgref, booted_status.as_ref()).await?
+        }
+        None => None,
+    };
+    let pinned = to_digest
+        .as_deref()
+        .map(|digest| crate::deploy::pinned_imgref(imgref, digest))
+        .transpose()?;
+    // The image is stored under the tracked reference even if fetched by digest
+    let tracked = &ostree_container::OstreeImageReference::from(imgref.clone());
+    let fetch_imgref = pinned.as_ref().unwrap_or(imgref);
     // Find the currently queued digest, if any before we pull
     let staged = host.status.staged.as_ref();
     let staged_image = staged.as_ref().and_then(|s| s.image.as_ref());
+    if opts.dry_run {
+        let plan = crate::deploy::plan(
+            "upgrade",
+            repo,
+            deployments.staged.as_ref().unwrap_or(&booted_deployment),
+            imgref,
+            fetch_imgref,
+            booted_image.as_deref(),
+            require_signature.as_ref(),
+        )
+        .await?;
+        return write_plan(&plan, opts.format);
+    }
     let mut changed = false;
     if opts.check {
-        let imgref = imgref.clone().into();
+        let image = format!("{fetch_imgref:#}");
+        crate::deploy::check_local_source(fetch_imgref)?;
+        let imgref = fetch_imgref.clone().into();
         let mut imp = crate::deploy::new_importer(repo, &imgref).await?;
-        match imp.prepare().await? {
-            PrepareResult::AlreadyPresent(_) => {
-                println!("No changes in: {imgref:#}");
+        let summary = match imp.prepare().await? {
+            PrepareResult::AlreadyPresent(c) => {
+                crate::deploy::UpdateCheck::unchanged(&image, &c, booted_image.as_deref())
+            }
+            // A pinned digest is not stored under its own reference
+            PrepareResult::Ready(r)
+                if booted_image
+                    .as_ref()
+                    .is_some_and(|b| b.manifest_digest == r.manifest_digest) =>
+            {
+                let booted = booted_image.as_deref().unwrap();
+                crate::deploy::UpdateCheck::unchanged(&image, booted, Some(booted))
             }
             PrepareResult::Ready(r) => {
+                crate::deploy::verify_image_arch(&crate::deploy::ImageArch::host(), &r.config)?;
                 crate::deploy::check_bootc_label(&r.config);
-                println!("Update available for: {imgref:#}");
-                if let Some(version) = r.version() {
-                    println!("  Version: {version}");
+                let mut summary =
+                    crate::deploy::UpdateCheck::new(&image, &r, booted_image.as_deref());
+                if let Some(policy) = policy.as_ref() {
+                    let plan = crate::policy::Plan::from_prepared(
+                        &image,
+                        &r,
+                        policy.current_version.as_deref(),
+                    )?;
+                    summary.policy_rules = policy
+                        .matching(&plan)?
+                        .into_iter()
+                        .map(|rule| rule.name.clone())
+                        .collect();
                 }
-                println!("  Digest: {}", r.manifest_digest);
-                changed = true;
-                if let Some(previous_image) = booted_image.as_ref() {
-                    let diff =
-                        ostree_container::ManifestDiff::new(&previous_image.manifest, &r.manifest);
-                    diff.print();
+                summary
+            }
+        };
+        changed = summary.update_available;
+        write_update_check(&summary, opts.format)?;
+    } else {
+        // A new digest of the tag may not have been rolled out to this host yet
+        if to_digest.is_none() && !opts.stage_cached && config.update_rollout_duration().is_some() {
+            let mut imp = crate::deploy::new_importer(repo, &fetch_imgref.clone().into()).await?;
+            let (digest, created) = match imp.prepare().await? {
+                PrepareResult::AlreadyPresent(c) => (
+                    c.manifest_digest.to_string(),
+                    crate::rollout::created_at(&c.configuration),
+                ),
+                PrepareResult::Ready(r) => (
+                    r.manifest_digest.to_string(),
+                    crate::rollout::created_at(&r.config),
+                ),
+            };
+            let known = booted_status
+                .iter()
+                .map(|b| b.image_digest.as_str())
+                .chain(staged_image.map(|s| s.image_digest.as_str()))
+                .any(|d| d == digest);
+            if !known {
+                let now = chrono::Utc::now();
+                let at = crate::rollout::not_before(root, &config, &digest, created, now)?;
+                if let Some(at) = at {
+                    println!("Update {digest} is being rolled out; this host adopts it after {at}");
+                    return Ok(());
                 }
             }
         }
-    } else {
-        let fetched = crate::deploy::pull(repo, imgref, None, opts.quiet).await?;
+        let mut update = crate::hooks::Update {
+            operation: "upgrade",
+            booted: booted_status,
+            target: imgref.clone(),
+            target_digest: to_digest.clone(),
+        };
+        crate::hooks::run(crate::hooks::Hook::PreUpgrade, &update)?;
+        let booted_commit = booted_deployment.csum();
+        let mut fetched = if opts.stage_cached {
+            crate::deploy::cached_image(repo, tracked)?
+        } else {
+            crate::deploy::pull(
+                repo,
+                fetch_imgref,
+                &crate::deploy::PullOpts {
+                    target_imgref: pinned.is_some().then_some(tracked),
+                    quiet: opts.quiet,
+                    progress: progress.as_ref(),
+                    static_delta_from: Some(booted_commit.as_str()),
+                    policy: policy.as_ref(),
+                    retries: opts.retry,
+                    limit_rate: opts.limit_rate,
+                    max_concurrent_downloads: opts.max_concurrent_downloads,
+                    sysroot: Some(sysroot),
+                    ..Default::default()
+                },
+            )
+            .await?
+        };
+        if let Some(digest) = to_digest.as_deref() {
+            let fetched_digest = fetched.manifest_digest.to_string();
+            if fetched_digest != digest {
+                anyhow::bail!("Fetched digest {fetched_digest} does not match {digest}");
+            }
+            fetched.pinned = true;
+        }
+        fetched.require_signature = require_signature;
+        update.target_digest = Some(fetched.manifest_digest.to_string());
         let staged_digest = staged_image.map(|s| s.digest().expect("valid digest in status"));
         let fetched_digest = &fetched.manifest_digest;
         tracing::debug!("staged: {staged_digest:?}");
@@ -625,13 +1663,17 @@ async fn upgrade(opts: UpgradeOpts) -> Result<()> {
             println!("Staged update present, not changed.");
 
             if opts.apply {
-                crate::reboot::reboot()?;
+                apply_staged(sysroot, &update, opts.soft_reboot, reboot_timeout)?;
             }
         } else if booted_unchanged {
             println!("No update available.")
+        } else if opts.download_only {
+            println!("Downloaded update: {fetched_digest}");
+            println!("Use `bootc upgrade --stage-cached` to stage it.");
         } else {
             let osname = booted_deployment.osname();
-            crate::deploy::stage(sysroot, &osname, &fetched, &spec).await?;
+            crate::deploy::stage(sysroot, &osname, &fetched, &spec, progress.as_ref()).await?;
+            crate::hooks::run_post_stage(sysroot, &update)?;
             changed = true;
             if let Some(prev) = booted_image.as_ref() {
                 if let Some(fetched_manifest) = fetched.get_manifest(repo)? {
@@ -640,33 +1682,154 @@ async fn upgrade(opts: UpgradeOpts) -> Result<()> {
                     diff.print();
                 }
             }
+            if opts.apply {
+                apply_staged(sysroot, &update, opts.soft_reboot, reboot_timeout)?;
+            }
         }
     }
-    if changed {
-        if opts.apply {
-            crate::reboot::reboot()?;
-        }
-    } else {
+    if !changed {
         tracing::debug!("No changes");
     }
 
     Ok(())
 }
 
+/// Implementation of `bootc upgrade --check` without root privileges, via
+/// `bootc service`.
+async fn check_unprivileged(opts: UpgradeOpts) -> Result<()> {
+    let supported = UpgradeOpts {
+        check: true,
+        quiet: opts.quiet,
+        format: opts.format.clone(),
+        lock_wait: opts.lock_wait,
+        ..Default::default()
+    };
+    if opts != supported {
+        anyhow::bail!("Without root privileges, only --format is supported with --check");
+    }
+    let summary = crate::service::check_update().await?;
+    write_update_check(&summary, opts.format)
+}
+
+/// Implementation of the `bootc update-service` CLI command.
+#[context("Automatic update")]
+async fn update_service() -> Result<()> {
+    let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    let config = crate::config::load_config(root)?;
+    let strategy = config.update_reboot();
+    upgrade(UpgradeOpts {
+        quiet: true,
+        wait_for_network: Some(config.update_wait_for_network().as_secs()),
+        lock_wait: true,
+        apply: strategy != RebootStrategy::None,
+        soft_reboot: strategy == RebootStrategy::SoftReboot,
+        ..Default::default()
+    })
+    .await
+}
+
+/// Fetch and stage any update, and log if a reboot is needed to apply it.
+/// The digest of the last staged image which was logged is tracked in
+/// `notified`, to avoid repeating the message.
+async fn stage_updates(notified: &mut Option<String>) -> Result<()> {
+    let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    if let Some(hold) = crate::hold::load(root)? {
+        println!("{hold}");
+        return Ok(());
+    }
+    let config = crate::config::load_config(root)?;
+    upgrade(UpgradeOpts {
+        quiet: true,
+        wait_for_network: Some(config.update_wait_for_network().as_secs()),
+        lock_wait: true,
+        ..Default::default()
+    })
+    .await?;
+    let sysroot = &get_storage().await?;
+    let (_, _, host) = crate::status::get_status_require_booted(sysroot)?;
+    let Some(staged) = host.status.staged.as_ref().and_then(|s| s.image.as_ref()) else {
+        return Ok(());
+    };
+    if notified.as_deref() == Some(staged.image_digest.as_str()) {
+        return Ok(());
+    }
+    let image = &staged.image.image;
+    let msg = format!("Reboot pending to apply update to {image}");
+    println!("{msg}");
+    crate::journal::journal_send(
+        libsystemd::logging::Priority::Notice,
+        &msg,
+        [
+            ("MESSAGE_ID", REBOOT_PENDING_JOURNAL_ID),
+            ("BOOTC_STAGED_IMAGE", image.as_str()),
+            ("BOOTC_STAGED_DIGEST", staged.image_digest.as_str()),
+        ]
+        .into_iter(),
+    );
+    *notified = Some(staged.image_digest.clone());
+    Ok(())
+}
+
+/// Implementation of `bootc internals fetch-apply-updates`.
+async fn fetch_apply_updates(stage_only: bool, interval: Option<u64>) -> Result<()> {
+    let mut notified = None;
+    loop {
+        let r = if stage_only {
+            stage_updates(&mut notified).await
+        } else {
+            update_service().await
+        };
+        // Updating from e.g. removable media which is not inserted is not an error
+        let r = match r {
+            Err(e)
+                if e.downcast_ref::<crate::deploy::SourceUnavailable>()
+                    .is_some() =>
+            {
+                println!("Skipping update: {e:#}");
+                Ok(())
+            }
+            r => r,
+        };
+        let Some(interval) = interval else {
+            return r;
+        };
+        // Keep running; the next attempt may succeed
+        if let Err(e) = r {
+            eprintln!("{e:#}");
+        }
+        crate::notify::status("Waiting for the next check");
+        tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+    }
+}
+
 /// Implementation of the `bootc switch` CLI command.
 #[context("Switching")]
 async fn switch(opts: SwitchOpts) -> Result<()> {
-    let transport = ostree_container::Transport::try_from(opts.transport.as_str())?;
-    let imgref = ostree_container::ImageReference {
-        transport,
-        name: opts.target.to_string(),
+    let transport = opts.transport.as_str();
+    let name = if transport == "containers-storage" {
+        crate::podman::find_local_image(&opts.target)?.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Image {} not found in containers-storage; build or pull it via podman as root",
+                opts.target
+            )
+        })?
+    } else {
+        opts.target.clone()
     };
+    let transport = ostree_container::Transport::try_from(transport)?;
+    let imgref = ostree_container::ImageReference { transport, name };
     let sigverify = sigpolicy_from_opts(
         !opts.enforce_container_sigpolicy,
         opts.ostree_remote.as_deref(),
     );
     let target = ostree_container::OstreeImageReference { sigverify, imgref };
-    let target = ImageReference::from(target);
+    let target = crate::deploy::resolve_local_imgref(ImageReference::from(target))?;
+    let run = &Dir::open_ambient_dir("/run", cap_std::ambient_authority())?;
+    let _lock = if opts.dry_run {
+        None
+    } else {
+        Some(crate::lock::acquire(run, "switch", opts.lock_wait)?)
+    };
 
     // If we're doing an in-place mutation, we shortcut most of the rest of the work here
     if opts.mutate_in_place {
@@ -683,12 +1846,30 @@ async fn switch(opts: SwitchOpts) -> Result<()> {
         return Ok(());
     }
 
+    let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    if let Some(mut txn) = crate::transaction::load(root)?.filter(|_| !opts.dry_run) {
+        if opts.stateroot.is_some() {
+            anyhow::bail!("--stateroot cannot be used within a transaction");
+        }
+        txn.spec.image = Some(target.clone());
+        txn.store(root)?;
+        println!("Queued switch to {target} in the pending transaction");
+        return Ok(());
+    }
+
     let cancellable = gio::Cancellable::NONE;
 
     let sysroot = &get_storage().await?;
     let repo = &sysroot.repo();
-    let (booted_deployment, _deployments, host) =
-        crate::status::get_status_require_booted(sysroot)?;
+    let (booted_deployment, deployments, host) = crate::status::get_status_require_booted(sysroot)?;
+    let mut target = target;
+    let require_signature = crate::signature::enforce(
+        root,
+        repo,
+        opts.require_signature.as_ref(),
+        deployments.staged.as_ref().unwrap_or(&booted_deployment),
+        &mut target,
+    )?;
 
     let new_spec = {
         let mut new_spec = host.spec.clone();
@@ -696,13 +1877,64 @@ async fn switch(opts: SwitchOpts) -> Result<()> {
         new_spec
     };
 
-    if new_spec == host.spec {
+    let booted_stateroot = booted_deployment.osname();
+    let stateroot = opts
+        .stateroot
+        .as_deref()
+        .unwrap_or(booted_stateroot.as_str());
+    if new_spec == host.spec && stateroot == booted_stateroot {
         println!("Image specification is unchanged.");
         return Ok(());
     }
     let new_spec = RequiredHostSpec::from_spec(&new_spec)?;
 
-    let fetched = crate::deploy::pull(repo, &target, None, opts.quiet).await?;
+    if opts.dry_run {
+        let booted_image = host
+            .status
+            .booted
+            .as_ref()
+            .map(|b| b.query_image(repo))
+            .transpose()?
+            .flatten();
+        let plan = crate::deploy::plan(
+            "switch",
+            repo,
+            deployments.staged.as_ref().unwrap_or(&booted_deployment),
+            &target,
+            &target,
+            booted_image.as_deref(),
+            require_signature.as_ref(),
+        )
+        .await?;
+        return write_plan(&plan, opts.format);
+    }
+
+    let progress = crate::progress_jsonl::ProgressWriter::from_opt(opts.progress_fd)?;
+    let policy = crate::policy::PolicyCheck::load(root, &host)?;
+    let mut update = crate::hooks::Update {
+        operation: "switch",
+        booted: host.status.booted.as_ref().and_then(|b| b.image.clone()),
+        target: target.clone(),
+        target_digest: None,
+    };
+    crate::hooks::run(crate::hooks::Hook::PreUpgrade, &update)?;
+    let mut fetched = crate::deploy::pull(
+        repo,
+        &target,
+        &crate::deploy::PullOpts {
+            quiet: opts.quiet,
+            progress: progress.as_ref(),
+            policy: policy.as_ref(),
+            retries: opts.retry,
+            limit_rate: opts.limit_rate,
+            max_concurrent_downloads: opts.max_concurrent_downloads,
+            sysroot: Some(sysroot),
+            ..Default::default()
+        },
+    )
+    .await?;
+    fetched.require_signature = require_signature;
+    update.target_digest = Some(fetched.manifest_digest.to_string());
 
     if !opts.retain {
         // By default, we prune the previous ostree ref so it will go away after later upgrades
@@ -715,62 +1947,401 @@ async fn switch(opts: SwitchOpts) -> Result<()> {
         }
     }
 
-    let stateroot = booted_deployment.osname();
-    crate::deploy::stage(sysroot, &stateroot, &fetched, &new_spec).await?;
+    if stateroot == booted_stateroot {
+        crate::deploy::stage(sysroot, stateroot, &fetched, &new_spec, progress.as_ref()).await?;
+    } else {
+        crate::deploy::stage_into_stateroot(
+            sysroot,
+            &booted_deployment,
+            stateroot,
+            &fetched,
+            &new_spec,
+            progress.as_ref(),
+        )
+        .await?;
+    }
+    crate::hooks::run_post_stage(sysroot, &update)?;
 
     if opts.apply {
-        crate::reboot::reboot()?;
+        apply_staged(sysroot, &update, false, None)?;
     }
 
     Ok(())
 }
 
+/// Implementation of the `bootc migrate-from-rpm-ostree` CLI command.
+async fn migrate_from_rpm_ostree(opts: MigrateFromRpmOstreeOpts) -> Result<()> {
+    let image = opts
+        .image
+        .map(|name| -> Result<ImageReference> {
+            let transport = opts.transport.as_deref().unwrap_or("registry");
+            let imgref = ostree_container::OstreeImageReference {
+                sigverify: sigpolicy_from_opts(true, None),
+                imgref: ostree_container::ImageReference {
+                    transport: ostree_container::Transport::try_from(transport)?,
+                    name,
+                },
+            };
+            crate::deploy::resolve_local_imgref(ImageReference::from(imgref))
+        })
+        .transpose()?;
+    let run = &Dir::open_ambient_dir("/run", cap_std::ambient_authority())?;
+    let _lock = crate::lock::acquire(run, "migrate-from-rpm-ostree", opts.lock_wait)?;
+    let staged = crate::migrate::migrate(image.as_ref(), opts.discard_layering, opts.quiet).await?;
+    if staged && opts.apply {
+        crate::reboot::reboot(None)?;
+    }
+    Ok(())
+}
+
 /// Implementation of the `bootc rollback` CLI command.
 #[context("Rollback")]
-async fn rollback(_opts: RollbackOpts) -> Result<()> {
+async fn rollback(opts: RollbackOpts) -> Result<()> {
+    if opts.list {
+        let sysroot = &get_storage().await?;
+        let candidates = crate::deploy::rollback_candidates(sysroot)?;
+        return crate::deploy::write_rollback_candidates(std::io::stdout().lock(), &candidates);
+    }
+    let run = &Dir::open_ambient_dir("/run", cap_std::ambient_authority())?;
+    let _lock = crate::lock::acquire(run, "rollback", opts.lock_wait)?;
     let sysroot = &get_storage().await?;
-    crate::deploy::rollback(sysroot).await
+    match opts.to.as_ref() {
+        Some(target) => crate::deploy::rollback_to(sysroot, target).await?,
+        None => crate::deploy::rollback(sysroot).await?,
+    }
+    if opts.apply {
+        let reboot_timeout = opts.reboot_timeout.map(std::time::Duration::from_secs);
+        apply_rollback(sysroot, opts.soft_reboot, reboot_timeout)?;
+    }
+    Ok(())
+}
+
+/// Implementation of `bootc internals testing`.
+async fn testing(opts: TestingOpts) -> Result<()> {
+    match opts {
+        TestingOpts::FabricateStatus {
+            booted,
+            staged,
+            rollback,
+            rollback_queued,
+            format,
+        } => {
+            let host = crate::testing::fabricate_host(
+                booted.as_deref(),
+                staged.as_deref(),
+                rollback.as_deref(),
+                rollback_queued,
+            )?;
+            crate::status::write_status(&mut std::io::stdout().lock(), &host, format, None)
+        }
+        TestingOpts::RenderStatus {
+            path,
+            format,
+            full_digests,
+        } => {
+            let host = crate::testing::load_host(&path)?;
+            let digest_len = (!full_digests).then(|| {
+                crate::status::short_digest_len(crate::status::status_image_digests(&host))
+            });
+            crate::status::write_status(&mut std::io::stdout().lock(), &host, format, digest_len)
+        }
+        TestingOpts::FabricateSysroot {
+            path,
+            booted,
+            rollback,
+            rollback_queued,
+        } => {
+            crate::testing::fabricate_sysroot(&path, &booted, rollback.as_deref(), rollback_queued)
+        }
+        TestingOpts::SysrootStatus { path, format } => {
+            let host = crate::testing::sysroot_status(&path).await?;
+            crate::status::write_status(&mut std::io::stdout().lock(), &host, format, None)
+        }
+        TestingOpts::Plan {
+            path,
+            image,
+            transport,
+            format,
+        } => {
+            let target = image
+                .map(|name| -> Result<ImageReference> {
+                    let transport = transport.as_deref().unwrap_or("registry");
+                    let imgref = ostree_container::OstreeImageReference {
+                        sigverify: sigpolicy_from_opts(true, None),
+                        imgref: ostree_container::ImageReference {
+                            transport: ostree_container::Transport::try_from(transport)?,
+                            name,
+                        },
+                    };
+                    crate::deploy::resolve_local_imgref(ImageReference::from(imgref))
+                })
+                .transpose()?;
+            let plan = crate::testing::plan(&path, target.as_ref()).await?;
+            write_plan(&plan, format)
+        }
+    }
 }
 
 /// Implementation of the `bootc edit` CLI command.
 #[context("Editing spec")]
 async fn edit(opts: EditOpts) -> Result<()> {
+    let run = &Dir::open_ambient_dir("/run", cap_std::ambient_authority())?;
+    let _lock = crate::lock::acquire(run, "edit", opts.lock_wait)?;
     let sysroot = &get_storage().await?;
-    let repo = &sysroot.repo();
 
-    let (booted_deployment, _deployments, host) =
+    let (booted_deployment, _deployments, mut host) =
         crate::status::get_status_require_booted(sysroot)?;
-    let new_host: Host = if let Some(filename) = opts.filename {
-        let mut r = std::io::BufReader::new(std::fs::File::open(filename)?);
-        serde_yaml::from_reader(&mut r)?
+    let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    let txn = crate::transaction::load(root)?;
+    // In a transaction, edit the pending specification
+    if let Some(txn) = txn.as_ref() {
+        host.spec = txn.spec.clone();
+    }
+    let new_host = if let Some(filename) = opts.filename.as_deref() {
+        crate::edit::read_host(filename)?
     } else {
         let tmpf = tempfile::NamedTempFile::new()?;
         serde_yaml::to_writer(std::io::BufWriter::new(tmpf.as_file()), &host)?;
         crate::utils::spawn_editor(&tmpf)?;
-        tmpf.as_file().seek(std::io::SeekFrom::Start(0))?;
-        serde_yaml::from_reader(&mut tmpf.as_file())?
+        crate::edit::parse_host(&std::fs::read_to_string(tmpf.path())?)?
     };
 
     if new_host.spec == host.spec {
         println!("Edit cancelled, no changes made.");
         return Ok(());
     }
-    host.spec.verify_transition(&new_host.spec)?;
-    let new_spec = RequiredHostSpec::from_spec(&new_host.spec)?;
+    println!("Changes to the host specification:");
+    crate::edit::write_spec_diff(anstream::stdout().lock(), &host.spec, &new_host.spec)?;
+    if let Some(mut txn) = txn {
+        txn.base.verify_transition(&new_host.spec)?;
+        txn.spec = new_host.spec;
+        txn.store(root)?;
+        println!("Updated the pending transaction");
+        return Ok(());
+    }
+
+    apply_spec(
+        sysroot,
+        &booted_deployment,
+        &host,
+        &new_host.spec,
+        opts.quiet,
+    )
+    .await
+}
+
+/// Implementation of `bootc apply`
+#[context("Applying host specification")]
+async fn apply(opts: ApplyOpts) -> Result<()> {
+    let run = &Dir::open_ambient_dir("/run", cap_std::ambient_authority())?;
+    let _lock = crate::lock::acquire(run, "apply", opts.lock_wait)?;
+    let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    if crate::transaction::load(root)?.is_some() {
+        anyhow::bail!(
+            "A transaction is in progress; use `bootc transaction commit` or `abort` first"
+        );
+    }
+    let desired = crate::edit::read_host(&opts.filename)?;
+    desired.verify_kind()?;
+    let mut new_spec = desired.spec;
+    if new_spec.image.is_none() {
+        anyhow::bail!("Missing image in specification");
+    }
+
+    let sysroot = &get_storage().await?;
+    let (booted_deployment, _deployments, host) =
+        crate::status::get_status_require_booted(sysroot)?;
+    // Unset kernel arguments are left as they are
+    if new_spec.kargs.is_none() {
+        new_spec.kargs = host.spec.kargs.clone();
+    }
+    if new_spec == host.spec {
+        println!("The host is already in the desired state.");
+        return Ok(());
+    }
+    println!("Changes to the host specification:");
+    crate::edit::write_spec_diff(anstream::stdout().lock(), &host.spec, &new_spec)?;
+    apply_spec(sysroot, &booted_deployment, &host, &new_spec, opts.quiet).await
+}
+
+/// Apply a change to the host specification, by either queuing a rollback or
+/// fetching and staging the new image.
+async fn apply_spec(
+    sysroot: &crate::store::Storage,
+    booted_deployment: &ostree::Deployment,
+    host: &Host,
+    new_spec: &HostSpec,
+    quiet: bool,
+) -> Result<()> {
+    let repo = &sysroot.repo();
+    let spec = &host.spec;
+    spec.verify_transition(new_spec)?;
+    let required_spec = RequiredHostSpec::from_spec(new_spec)?;
 
-    // We only support two state transitions right now; switching the image,
-    // or flipping the bootloader ordering.
-    if host.spec.boot_order != new_host.spec.boot_order {
+    // We only support two state transitions right now; switching the image
+    // (and/or changing the kernel arguments), or flipping the bootloader ordering.
+    if spec.boot_order != new_spec.boot_order {
         return crate::deploy::rollback(sysroot).await;
     }
 
-    let fetched = crate::deploy::pull(repo, new_spec.image, None, opts.quiet).await?;
+    let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    let policy = crate::policy::PolicyCheck::load(root, host)?;
+    let fetched = crate::deploy::pull(
+        repo,
+        required_spec.image,
+        &crate::deploy::PullOpts {
+            quiet,
+            policy: policy.as_ref(),
+            sysroot: Some(sysroot),
+            ..Default::default()
+        },
+    )
+    .await?;
 
     // TODO gc old layers here
 
     let stateroot = booted_deployment.osname();
-    crate::deploy::stage(sysroot, &stateroot, &fetched, &new_spec).await?;
+    crate::deploy::stage(sysroot, &stateroot, &fetched, &required_spec, None).await?;
+
+    Ok(())
+}
+
+/// The deployment selected by `--staged` or `--booted`; by default the staged
+/// deployment if there is one, and otherwise the booted one.
+fn kargs_deployment(
+    sysroot: &crate::store::Storage,
+    target: &KargsTargetOpts,
+) -> Result<ostree::Deployment> {
+    let booted = sysroot.require_booted_deployment()?;
+    match (target.staged, target.booted, sysroot.staged_deployment()) {
+        (true, _, None) => anyhow::bail!("No staged deployment"),
+        (_, true, _) | (false, false, None) => Ok(booted),
+        (_, false, Some(staged)) => Ok(staged),
+    }
+}
+
+/// Implementation of `bootc kargs diff`.
+async fn kargs_diff() -> Result<()> {
+    let sysroot = &get_storage().await?;
+    let booted = sysroot.require_booted_deployment()?;
+    let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    let image = crate::kargs::get_kargs_in_root(root, std::env::consts::ARCH)?;
+    let local = crate::deploy::deployment_local_kargs(&booted);
+    let entry = crate::deploy::deployment_kargs(&booted);
+    let cmdline = std::fs::read_to_string("/proc/cmdline").context("Reading /proc/cmdline")?;
+    let show = |v: &[String]| {
+        if v.is_empty() {
+            "(none)".to_owned()
+        } else {
+            v.join(" ")
+        }
+    };
+    println!("Image (kargs.d): {}", show(&image));
+    println!("Local (spec.kargs): {}", show(&local));
+    println!("Boot entry: {}", show(&entry));
+    println!("Running kernel: {}", cmdline.trim());
+    let drift = crate::kargs::kargs_drift(&image, &local, &entry, &cmdline);
+    if drift.is_empty() {
+        println!("No drift detected.");
+    } else {
+        println!("Drift:");
+        for d in drift {
+            println!("  {d}");
+        }
+    }
+    Ok(())
+}
+
+/// Implementation of the `bootc kargs` CLI commands.
+#[context("Kernel arguments")]
+async fn kargs(opts: KargsOpts) -> Result<()> {
+    let (edit, opts) = match opts {
+        KargsOpts::List(target) => {
+            let sysroot = &get_storage().await?;
+            let deployment = kargs_deployment(sysroot, &target)?;
+            println!("{}", crate::deploy::deployment_kargs(&deployment).join(" "));
+            return Ok(());
+        }
+        KargsOpts::Diff => return kargs_diff().await,
+        KargsOpts::SetOnce(opts) => {
+            let run = &Dir::open_ambient_dir("/run", cap_std::ambient_authority())?;
+            let _lock = crate::lock::acquire(run, "kargs", opts.lock_wait)?;
+            let sysroot = &get_storage().await?;
+            let deployment = kargs_deployment(sysroot, &opts.target)?;
+            return crate::oneshot::set(sysroot, &deployment, &opts.kargs);
+        }
+        KargsOpts::Append(opts) => (crate::kargs::KargsEdit::Append, opts),
+        KargsOpts::Delete(opts) => (crate::kargs::KargsEdit::Delete, opts),
+        KargsOpts::Replace(opts) => (crate::kargs::KargsEdit::Replace, opts),
+    };
+    let run = &Dir::open_ambient_dir("/run", cap_std::ambient_authority())?;
+    let _lock = crate::lock::acquire(run, "kargs", opts.lock_wait)?;
+    let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    if crate::transaction::load(root)?.is_some() {
+        anyhow::bail!(
+            "A transaction is in progress; use `bootc transaction commit` or `abort` first"
+        );
+    }
+    let sysroot = &get_storage().await?;
+    let deployment = kargs_deployment(sysroot, &opts.target)?;
+    let mut kargs = crate::deploy::deployment_kargs(&deployment);
+    let mut local = crate::deploy::deployment_local_kargs(&deployment);
+    if !crate::kargs::edit_kargs(&mut kargs, &mut local, edit, &opts.kargs)? {
+        println!("No changes to the kernel arguments.");
+        return Ok(());
+    }
+    crate::deploy::stage_kargs(sysroot, &deployment, &kargs, &local).await?;
+    println!(
+        "Queued for next boot with kernel arguments: {}",
+        kargs.join(" ")
+    );
+    Ok(())
+}
 
+/// Implementation of the `bootc transaction` CLI commands.
+#[context("Transaction")]
+async fn transaction(opts: TransactionOpts) -> Result<()> {
+    let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    match opts {
+        TransactionOpts::Begin => {
+            let sysroot = &get_storage().await?;
+            let (_booted_deployment, _deployments, host) =
+                crate::status::get_status_require_booted(sysroot)?;
+            crate::transaction::begin(root, &host.spec)?;
+            println!("Started transaction; use `bootc transaction commit` to apply changes");
+        }
+        TransactionOpts::Commit { quiet } => {
+            let txn = crate::transaction::load(root)?
+                .ok_or_else(|| anyhow::anyhow!("No transaction in progress"))?;
+            let sysroot = &get_storage().await?;
+            let (booted_deployment, _deployments, host) =
+                crate::status::get_status_require_booted(sysroot)?;
+            if host.spec != txn.base {
+                anyhow::bail!(
+                    "The host specification was changed outside of the transaction; use `bootc transaction abort` and retry"
+                );
+            }
+            if txn.spec == host.spec {
+                println!("No changes in transaction.");
+            } else {
+                apply_spec(sysroot, &booted_deployment, &host, &txn.spec, quiet).await?;
+            }
+            crate::transaction::discard(root)?;
+        }
+        TransactionOpts::Abort => {
+            if crate::transaction::discard(root)?.is_none() {
+                anyhow::bail!("No transaction in progress");
+            }
+            println!("Discarded pending transaction");
+        }
+        TransactionOpts::Show => match crate::transaction::load(root)? {
+            Some(txn) => {
+                let mut stdout = std::io::stdout().lock();
+                serde_yaml::to_writer(&mut stdout, &txn)?;
+            }
+            None => println!("No transaction in progress"),
+        },
+    }
     Ok(())
 }
 
@@ -806,10 +2377,12 @@ where
     I: IntoIterator,
     I::Item: Into<OsString> + Clone,
 {
-    run_from_opt(Opt::parse_including_static(args)).await
+    let cli = Cli::parse_including_static(args);
+    let read_only = cli.is_read_only();
+    run_from_opt(cli.opt, read_only).await
 }
 
-impl Opt {
+impl Cli {
     /// In some cases (e.g. systemd generator) we dispatch specifically on argv0.  This
     /// requires some special handling in clap.
     fn parse_including_static<I>(args: I) -> Self
@@ -826,42 +2399,191 @@ impl Opt {
                 let base_args = ["bootc", "internals", "systemd-generator"]
                     .into_iter()
                     .map(OsString::from);
-                return Opt::parse_from(base_args.chain(args.map(|i| i.into())));
+                return Cli::parse_from(base_args.chain(args.map(|i| i.into())));
             }
             Some(first)
         } else {
             None
         };
-        Opt::parse_from(first.into_iter().chain(args.map(|i| i.into())))
+        Cli::parse_from(first.into_iter().chain(args.map(|i| i.into())))
+    }
+
+    /// Returns true if verbs which mutate the host are disabled.
+    pub(crate) fn is_read_only(&self) -> bool {
+        self.read_only || cfg!(feature = "read-only")
+    }
+}
+
+#[cfg(test)]
+impl Opt {
+    fn parse_including_static<I>(args: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<OsString> + Clone,
+    {
+        Cli::parse_including_static(args).opt
+    }
+
+    fn try_parse_from<I>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator,
+        I::Item: Into<OsString> + Clone,
+    {
+        Cli::try_parse_from(args).map(|cli| cli.opt)
+    }
+}
+
+impl Opt {
+    /// Returns true if this verb may change the state of the host system.
+    fn is_mutating(&self) -> bool {
+        match self {
+            Opt::Upgrade(opts) => !(opts.check || opts.dry_run),
+            Opt::Switch(opts) => !opts.dry_run,
+            Opt::UpdateService
+            | Opt::BootComplete
+            | Opt::Health(_)
+            | Opt::SystemReinstall(_)
+            | Opt::MigrateFromRpmOstree(_) => true,
+            Opt::Rollback(opts) => !opts.list,
+            Opt::Edit(_) | Opt::Apply(_) | Opt::UsrOverlay | Opt::State(_) => true,
+            Opt::Transaction(TransactionOpts::Show) => false,
+            Opt::Transaction(_) => true,
+            Opt::Kargs(KargsOpts::List(_) | KargsOpts::Diff) => false,
+            Opt::Kargs(_) => true,
+            #[cfg(feature = "install")]
+            Opt::Install(InstallOpts::PrintConfiguration) => false,
+            #[cfg(feature = "install")]
+            Opt::Install(_) | Opt::ExecInHostMountNamespace { .. } => true,
+            Opt::Image(ImageOpts::List { .. } | ImageOpts::Cmd(ImageCmdOpts::List { .. })) => false,
+            Opt::Image(_) => true,
+            Opt::Internals(
+                InternalsOpts::FixupEtcFstab
+                | InternalsOpts::Cleanup
+                | InternalsOpts::RunFirstboot
+                | InternalsOpts::ScheduleWake
+                | InternalsOpts::WakeUpdate
+                | InternalsOpts::FetchApplyUpdates { .. }
+                | InternalsOpts::SyncSystemdBoot
+                | InternalsOpts::RestoreReinstallBackup,
+            ) => true,
+            Opt::Internals(
+                InternalsOpts::SystemdGenerator { .. }
+                | InternalsOpts::PrintJsonSchema
+                | InternalsOpts::VerifyDeployment { .. }
+                | InternalsOpts::LogBoot
+                | InternalsOpts::Testing(_)
+                | InternalsOpts::Api,
+            ) => false,
+            Opt::Container(ContainerOpts::Commit) => true,
+            Opt::Container(_) | Opt::Status(_) | Opt::Deployment(_) | Opt::Journal(_) => false,
+            // The operations are run as separate processes, and with
+            // --read-only the mutating methods are rejected per call
+            Opt::Service => false,
+            #[cfg(feature = "docgen")]
+            Opt::Man(_) => false,
+        }
+    }
+}
+
+/// If we're in read-only mode, reject (and log) any verb which would mutate the host.
+fn verify_not_read_only(opt: &Opt, read_only: bool) -> Result<()> {
+    if !read_only || !opt.is_mutating() {
+        return Ok(());
     }
+    let msg = format!("Rejected mutating operation in read-only mode: {opt:?}");
+    tracing::warn!("{msg}");
+    crate::journal::journal_send(
+        libsystemd::logging::Priority::Warning,
+        &msg,
+        [("MESSAGE_ID", READ_ONLY_JOURNAL_ID)].into_iter(),
+    );
+    anyhow::bail!("This operation is disabled in read-only mode")
 }
 
 /// Internal (non-generic/monomorphized) primary CLI entrypoint
-async fn run_from_opt(opt: Opt) -> Result<()> {
+async fn run_from_opt(opt: Opt, read_only: bool) -> Result<()> {
+    verify_not_read_only(&opt, read_only)?;
     let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
     match opt {
         Opt::Upgrade(opts) => upgrade(opts).await,
+        Opt::UpdateService => update_service().await,
+        Opt::Service => crate::service::run(read_only).await,
+        Opt::BootComplete => {
+            let run = &Dir::open_ambient_dir("/run", cap_std::ambient_authority())?;
+            let _lock = crate::lock::acquire(run, "boot-complete", true)?;
+            let sysroot = &get_storage().await?;
+            if let Err(e) = crate::oneshot::cleanup() {
+                eprintln!("warning: {e:#}");
+            }
+            crate::bootcount::complete(sysroot).await
+        }
+        Opt::Journal(opts) => crate::journal::query(&opts.event, opts.list, &opts.args),
+        Opt::Health(HealthOpts::Run) => {
+            let run = &Dir::open_ambient_dir("/run", cap_std::ambient_authority())?;
+            let _lock = crate::lock::acquire(run, "health", true)?;
+            let sysroot = &get_storage().await?;
+            crate::health::run(sysroot).await
+        }
+        Opt::SystemReinstall(opts) => {
+            crate::reinstall::reinstall(&opts.keep, opts.stateroot.as_deref(), opts.quiet).await?;
+            if opts.apply {
+                crate::reboot::reboot(None)?;
+            }
+            Ok(())
+        }
+        Opt::MigrateFromRpmOstree(opts) => migrate_from_rpm_ostree(opts).await,
         Opt::Switch(opts) => switch(opts).await,
         Opt::Rollback(opts) => rollback(opts).await,
         Opt::Edit(opts) => edit(opts).await,
+        Opt::Apply(opts) => apply(opts).await,
+        Opt::Transaction(opts) => transaction(opts).await,
+        Opt::Kargs(opts) => kargs(opts).await,
         Opt::UsrOverlay => usroverlay().await,
         Opt::Container(opts) => match opts {
-            ContainerOpts::Lint => {
+            ContainerOpts::Lint(opts) => {
+                if opts.list {
+                    return lints::list(root, std::io::stdout().lock());
+                }
                 if !ostree_ext::container_utils::is_ostree_container()? {
                     anyhow::bail!(
                         "Not in a ostree container, this command only verifies ostree containers."
                     );
                 }
 
-                lints::lint(root)?;
+                let report = lints::lint(root, &opts.skip)?;
+                write_lint_report(&report, opts.format)?;
+                let failures = report.failures(opts.fatal_warnings);
+                if failures > 0 {
+                    anyhow::bail!("Lints failed: {failures}");
+                }
                 Ok(())
             }
+            ContainerOpts::Commit => {
+                if !ostree_ext::container_utils::is_ostree_container()? {
+                    anyhow::bail!(
+                        "Not in a ostree container, this command only finalizes ostree containers."
+                    );
+                }
+                crate::commit::commit(root)
+            }
         },
         Opt::Image(opts) => match opts {
-            ImageOpts::List => crate::image::list_entrypoint().await,
+            ImageOpts::List { format } => {
+                let sysroot = get_storage().await?;
+                let list = crate::image::list(&sysroot).await?;
+                write_image_list(&list, format)
+            }
             ImageOpts::CopyToStorage { source, target } => {
                 crate::image::push_entrypoint(source.as_deref(), target.as_deref()).await
             }
+            ImageOpts::Export(opts) => {
+                crate::image::export_entrypoint(&opts.output, opts.max_layers).await
+            }
+            ImageOpts::Rechunk(opts) => {
+                crate::image::rechunk_entrypoint(&opts.src, &opts.dest, opts.max_layers).await
+            }
+            #[cfg(feature = "install")]
+            ImageOpts::BuildDisk(opts) => crate::install::build_disk(*opts).await,
             ImageOpts::PullFromDefaultStorage { image } => {
                 let sysroot = get_storage().await?;
                 sysroot
@@ -891,12 +2613,16 @@ async fn run_from_opt(opt: Opt) -> Result<()> {
         #[cfg(feature = "install")]
         Opt::Install(opts) => match opts {
             InstallOpts::ToDisk(opts) => crate::install::install_to_disk(opts).await,
+            InstallOpts::FromConfig(opts) => crate::install::install_from_config(opts).await,
             InstallOpts::ToFilesystem(opts) => {
                 crate::install::install_to_filesystem(opts, false).await
             }
             InstallOpts::ToExistingRoot(opts) => {
                 crate::install::install_to_existing_root(opts).await
             }
+            InstallOpts::Prepare(opts) => crate::install::phases::install_prepare(opts).await,
+            InstallOpts::Deploy(opts) => crate::install::phases::install_deploy(opts).await,
+            InstallOpts::Finalize(opts) => crate::install::phases::install_finalize(opts).await,
             InstallOpts::PrintConfiguration => crate::install::print_configuration(),
         },
         #[cfg(feature = "install")]
@@ -904,6 +2630,29 @@ async fn run_from_opt(opt: Opt) -> Result<()> {
             crate::install::exec_in_host_mountns(args.as_slice())
         }
         Opt::Status(opts) => super::status::status(opts).await,
+        Opt::Deployment(DeploymentOpts::Export {
+            index,
+            to,
+            etc,
+            output,
+        }) => {
+            let sysroot = &get_storage().await?;
+            let deployments = sysroot.deployments();
+            let deployment = deployments
+                .get(index)
+                .ok_or_else(|| anyhow::anyhow!("No deployment at index {index}"))?;
+            match to {
+                DeploymentExportFormat::Dir => {
+                    crate::deploy::export_deployment_dir(sysroot, deployment, &output, etc)
+                }
+                DeploymentExportFormat::Oci => {
+                    if etc {
+                        anyhow::bail!("--etc is only supported when exporting to a directory");
+                    }
+                    crate::deploy::export_deployment_oci(sysroot, deployment, &output).await
+                }
+            }
+        }
         Opt::Internals(opts) => match opts {
             InternalsOpts::SystemdGenerator {
                 normal_dir,
@@ -924,6 +2673,31 @@ async fn run_from_opt(opt: Opt) -> Result<()> {
                 let sysroot = get_storage().await?;
                 crate::deploy::cleanup(&sysroot).await
             }
+            InternalsOpts::RunFirstboot => {
+                crate::firstboot::run(&Utf8Path::new("/").join(crate::firstboot::FIRSTBOOT_DIR))
+            }
+            InternalsOpts::VerifyDeployment { booted } => {
+                let sysroot = get_storage().await?;
+                crate::deploy::verify_deployment(&sysroot, booted)
+            }
+            InternalsOpts::ScheduleWake => crate::wake::schedule(root),
+            InternalsOpts::WakeUpdate => crate::wake::update(root),
+            InternalsOpts::RestoreReinstallBackup => crate::reinstall::restore(root),
+            InternalsOpts::LogBoot => {
+                let sysroot = get_storage().await?;
+                crate::journal::log_boot(&sysroot)
+            }
+            InternalsOpts::SyncSystemdBoot => {
+                let sysroot = get_storage().await?;
+                crate::systemd_boot::sync_booted(&sysroot)
+            }
+            InternalsOpts::FetchApplyUpdates {
+                stage_only,
+                interval,
+            } => fetch_apply_updates(stage_only, interval).await,
+            InternalsOpts::Testing(opts) => testing(opts).await,
+            // The operations are run as separate processes
+            InternalsOpts::Api => crate::jsonrpc::run(read_only).await,
         },
         #[cfg(feature = "docgen")]
         Opt::Man(manopts) => crate::docgen::generate_manpages(&manopts.directory),
@@ -957,6 +2731,143 @@ fn test_parse_install_args() {
     assert_eq!(o.filesystem_opts.root_path.as_str(), "/target");
 }
 
+#[test]
+fn test_parse_install_device_match_args() {
+    let o = Opt::try_parse_from([
+        "bootc",
+        "install",
+        "to-disk",
+        "--device-match",
+        "serial=S4EV*",
+        "--yes",
+    ])
+    .unwrap();
+    let o = match o {
+        Opt::Install(InstallOpts::ToDisk(opts)) => opts,
+        o => panic!("Expected to-disk opts, not {o:?}"),
+    };
+    assert_eq!(o.block_opts.device_match.as_deref(), Some("serial=S4EV*"));
+    assert!(o.block_opts.device.is_none());
+    assert!(o.block_opts.yes);
+
+    let invalid: [&[&str]; 3] = [
+        // A device is required
+        &["bootc", "install", "to-disk"],
+        &[
+            "bootc",
+            "install",
+            "to-disk",
+            "--device-match",
+            "smallest-ssd",
+            "/dev/vda",
+        ],
+        &["bootc", "install", "to-disk", "--yes", "/dev/vda"],
+    ];
+    for args in invalid {
+        assert!(Opt::try_parse_from(args).is_err(), "{args:?}");
+    }
+}
+
+#[test]
+fn test_parse_install_from_config_args() {
+    let o = Opt::try_parse_from([
+        "bootc",
+        "install",
+        "from-config",
+        "--print-plan",
+        "install.toml",
+    ])
+    .unwrap();
+    let o = match o {
+        Opt::Install(InstallOpts::FromConfig(opts)) => opts,
+        o => panic!("Expected from-config opts, not {o:?}"),
+    };
+    assert_eq!(o.path.as_str(), "install.toml");
+    assert!(o.print_plan);
+}
+
+#[test]
+fn test_parse_install_phases_args() {
+    use crate::install::baseline::WipeMode;
+    use crate::install::phases::PrepareTarget;
+
+    let o = Opt::try_parse_from([
+        "bootc",
+        "install",
+        "prepare",
+        "--handoff",
+        "/run/install.json",
+        "to-disk",
+        "--wipe",
+        "/dev/vda",
+    ])
+    .unwrap();
+    let o = match o {
+        Opt::Install(InstallOpts::Prepare(opts)) => opts,
+        o => panic!("Expected prepare opts, not {o:?}"),
+    };
+    assert_eq!(o.handoff.as_str(), "/run/install.json");
+    match o.target {
+        PrepareTarget::ToDisk(opts) => {
+            assert_eq!(opts.block_opts.device.unwrap(), "/dev/vda");
+            assert_eq!(opts.block_opts.wipe, WipeMode::All);
+        }
+        o => panic!("Expected to-disk opts, not {o:?}"),
+    }
+
+    let o = Opt::try_parse_from([
+        "bootc",
+        "install",
+        "finalize",
+        "--handoff",
+        "/run/install.json",
+        "--result-json",
+        "/run/result.json",
+    ])
+    .unwrap();
+    let o = match o {
+        Opt::Install(InstallOpts::Finalize(opts)) => opts,
+        o => panic!("Expected finalize opts, not {o:?}"),
+    };
+    assert_eq!(o.result_json.as_deref().unwrap(), "/run/result.json");
+
+    // The handoff file is required
+    assert!(Opt::try_parse_from(["bootc", "install", "deploy"]).is_err());
+}
+
+#[test]
+fn test_parse_build_disk_args() {
+    use crate::install::DiskImageFormat;
+
+    let o =
+        Opt::try_parse_from(["bootc", "image", "build-disk", "--output", "disk.qcow2"]).unwrap();
+    let o = match o {
+        Opt::Image(ImageOpts::BuildDisk(o)) => o,
+        o => panic!("Expected build-disk opts, not {o:?}"),
+    };
+    assert_eq!(o.format, DiskImageFormat::Qcow2);
+    assert_eq!(o.size, "10G");
+    assert_eq!(o.output.as_str(), "disk.qcow2");
+
+    let o = Opt::try_parse_from([
+        "bootc",
+        "image",
+        "build-disk",
+        "--format",
+        "vmdk",
+        "--size",
+        "20G",
+        "--output",
+        "disk.vmdk",
+    ])
+    .unwrap();
+    assert!(matches!(
+        o,
+        Opt::Image(ImageOpts::BuildDisk(o)) if o.format == DiskImageFormat::Vmdk && o.size == "20G"
+    ));
+    assert!(Opt::try_parse_from(["bootc", "image", "build-disk"]).is_err());
+}
+
 #[test]
 fn test_parse_opts() {
     assert!(matches!(
@@ -965,7 +2876,9 @@ fn test_parse_opts() {
             json: false,
             format: None,
             format_version: None,
-            booted: false
+            booted: false,
+            prompt: false,
+            full_digests: false
         })
     ));
     assert!(matches!(
@@ -975,6 +2888,603 @@ fn test_parse_opts() {
             ..
         })
     ));
+    assert!(matches!(
+        Opt::parse_including_static(["bootc", "status", "--prompt"]),
+        Opt::Status(StatusOpts { prompt: true, .. })
+    ));
+    assert!(Opt::try_parse_from(["bootc", "status", "--prompt", "--json"]).is_err());
+    assert!(matches!(
+        Opt::parse_including_static(["bootc", "status", "--full-digests"]),
+        Opt::Status(StatusOpts {
+            full_digests: true,
+            ..
+        })
+    ));
+    let o = Opt::parse_including_static(["bootc", "status", "--format=ext:report"]);
+    let Opt::Status(StatusOpts {
+        format: Some(OutputFormat::External(name)),
+        ..
+    }) = &o
+    else {
+        panic!("Unexpected {o:?}");
+    };
+    assert_eq!(name, "report");
+    for invalid in ["ext:", "toml"] {
+        assert!(Opt::try_parse_from(["bootc", "status", "--format", invalid]).is_err());
+    }
+}
+
+#[test]
+fn test_parse_read_only() {
+    let o = Opt::parse_including_static(["bootc", "--read-only", "upgrade", "--check"]);
+    assert!(matches!(o, Opt::Upgrade(UpgradeOpts { check: true, .. })));
+    assert!(matches!(
+        Opt::parse_including_static(["bootc", "upgrade", "--check", "--format=json"]),
+        Opt::Upgrade(UpgradeOpts {
+            check: true,
+            format: Some(OutputFormat::Json),
+            ..
+        })
+    ));
+    assert!(matches!(
+        Opt::parse_including_static(["bootc", "update", "hold", "--reason", "incident"]),
+        Opt::Upgrade(UpgradeOpts {
+            hold: Some(UpdateHoldOpts::Hold { reason: Some(_) }),
+            ..
+        })
+    ));
+    assert!(matches!(
+        Opt::parse_including_static(["bootc", "upgrade", "unhold"]),
+        Opt::Upgrade(UpgradeOpts {
+            hold: Some(UpdateHoldOpts::Unhold),
+            ..
+        })
+    ));
+    assert!(Opt::try_parse_from(["bootc", "update", "--apply", "hold"]).is_err());
+    assert!(Opt::parse_including_static(["bootc", "update", "unhold"]).is_mutating());
+    assert!(Opt::parse_including_static(["bootc", "update-service"]).is_mutating());
+    let o = Opt::parse_including_static([
+        "bootc",
+        "system-reinstall",
+        "--keep=/etc/ssh",
+        "--keep=/var/home",
+    ]);
+    assert!(o.is_mutating());
+    let Opt::SystemReinstall(opts) = &o else {
+        panic!("Unexpected {o:?}");
+    };
+    assert_eq!(opts.keep, ["/etc/ssh", "/var/home"]);
+    assert!(opts.stateroot.is_none());
+    assert!(matches!(
+        Opt::parse_including_static(["bootc", "upgrade", "--apply", "--reboot-timeout=600"]),
+        Opt::Upgrade(UpgradeOpts {
+            apply: true,
+            reboot_timeout: Some(600),
+            ..
+        })
+    ));
+    assert!(Opt::try_parse_from(["bootc", "upgrade", "--reboot-timeout=600"]).is_err());
+    assert!(matches!(
+        Opt::parse_including_static(["bootc", "upgrade", "--apply", "--soft-reboot"]),
+        Opt::Upgrade(UpgradeOpts {
+            apply: true,
+            soft_reboot: true,
+            ..
+        })
+    ));
+    assert!(Opt::try_parse_from(["bootc", "upgrade", "--soft-reboot"]).is_err());
+    assert!(matches!(
+        Opt::parse_including_static(["bootc", "upgrade", "--download-only"]),
+        Opt::Upgrade(UpgradeOpts {
+            download_only: true,
+            ..
+        })
+    ));
+    let o = Opt::parse_including_static(["bootc", "upgrade", "--limit-rate=2M"]);
+    let Opt::Upgrade(opts) = o else {
+        panic!("Unexpected {o:?}");
+    };
+    assert_eq!(opts.limit_rate, Some("2097152".parse().unwrap()));
+    assert!(Opt::try_parse_from(["bootc", "upgrade", "--limit-rate=0"]).is_err());
+    let o = Opt::parse_including_static([
+        "bootc",
+        "switch",
+        "--max-concurrent-downloads=4",
+        "quay.io/example/os:latest",
+    ]);
+    let Opt::Switch(opts) = o else {
+        panic!("Unexpected {o:?}");
+    };
+    assert_eq!(opts.max_concurrent_downloads.map(|n| n.get()), Some(4));
+    assert!(Opt::try_parse_from(["bootc", "upgrade", "--max-concurrent-downloads=0"]).is_err());
+    assert!(matches!(
+        Opt::parse_including_static(["bootc", "upgrade", "--stage-cached", "--apply"]),
+        Opt::Upgrade(UpgradeOpts {
+            stage_cached: true,
+            apply: true,
+            ..
+        })
+    ));
+    for invalid in [
+        ["--download-only", "--apply"],
+        ["--download-only", "--stage-cached"],
+        ["--stage-cached", "--check"],
+        ["--dry-run", "--check"],
+        ["--dry-run", "--apply"],
+        ["--dry-run", "--download-only"],
+    ] {
+        let args = ["bootc", "upgrade"].into_iter().chain(invalid);
+        assert!(Opt::try_parse_from(args).is_err(), "{invalid:?}");
+    }
+    let o = Opt::parse_including_static([
+        "bootc",
+        "internals",
+        "fetch-apply-updates",
+        "--stage-only",
+        "--interval=3600",
+    ]);
+    assert!(matches!(
+        o,
+        Opt::Internals(InternalsOpts::FetchApplyUpdates {
+            stage_only: true,
+            interval: Some(3600)
+        })
+    ));
+    assert!(o.is_mutating());
+    // --format only applies to --check and --dry-run
+    assert!(Opt::try_parse_from(["bootc", "upgrade", "--format=json"]).is_err());
+    let o = Opt::parse_including_static(["bootc", "upgrade", "--dry-run", "--format=json"]);
+    assert!(matches!(
+        o,
+        Opt::Upgrade(UpgradeOpts {
+            dry_run: true,
+            format: Some(OutputFormat::Json),
+            ..
+        })
+    ));
+    assert!(!o.is_mutating());
+    let o = Opt::parse_including_static([
+        "bootc",
+        "switch",
+        "--dry-run",
+        "--format=yaml",
+        "quay.io/example/foo",
+    ]);
+    assert!(matches!(
+        o,
+        Opt::Switch(SwitchOpts {
+            dry_run: true,
+            format: Some(OutputFormat::Yaml),
+            ..
+        })
+    ));
+    assert!(!o.is_mutating());
+    assert!(
+        Opt::try_parse_from(["bootc", "switch", "--format=json", "quay.io/example/foo"]).is_err()
+    );
+    let o = Opt::parse_including_static([
+        "bootc",
+        "switch",
+        "--stateroot=recovery",
+        "quay.io/example/recovery",
+    ]);
+    assert!(matches!(
+        o,
+        Opt::Switch(SwitchOpts {
+            stateroot: Some(ref s),
+            ..
+        }) if s == "recovery"
+    ));
+    assert!(o.is_mutating());
+    assert!(Opt::try_parse_from([
+        "bootc",
+        "switch",
+        "--stateroot=recovery",
+        "--dry-run",
+        "quay.io/example/recovery",
+    ])
+    .is_err());
+    assert!(matches!(
+        Opt::parse_including_static([
+            "bootc",
+            "upgrade",
+            "--to-digest",
+            "sha256:e7a3b5bd2ae2f7f1ec2a2ab1e1b5e1e0c8b0c8f4a3b0bbc3d6c6ce1d1f1c0b2a"
+        ]),
+        Opt::Upgrade(UpgradeOpts {
+            to_digest: Some(_),
+            ..
+        })
+    ));
+    assert!(matches!(
+        Opt::parse_including_static(["bootc", "upgrade", "--wait-for-network"]),
+        Opt::Upgrade(UpgradeOpts {
+            wait_for_network: Some(300),
+            ..
+        })
+    ));
+    assert!(matches!(
+        Opt::parse_including_static(["bootc", "upgrade", "--wait-for-network=30"]),
+        Opt::Upgrade(UpgradeOpts {
+            wait_for_network: Some(30),
+            ..
+        })
+    ));
+    assert!(matches!(
+        Opt::parse_including_static(["bootc", "rollback", "--lock-wait"]),
+        Opt::Rollback(RollbackOpts {
+            lock_wait: true,
+            ..
+        })
+    ));
+    assert!(matches!(
+        Opt::parse_including_static(["bootc", "edit", "--lock-wait"]),
+        Opt::Edit(EditOpts {
+            lock_wait: true,
+            ..
+        })
+    ));
+    match Opt::parse_including_static(["bootc", "edit", "--file", "-"]) {
+        Opt::Edit(opts) => assert_eq!(opts.filename.as_deref(), Some("-")),
+        o => panic!("Expected edit opts, not {o:?}"),
+    }
+    match Opt::parse_including_static(["bootc", "apply", "-f", "host.yaml"]) {
+        Opt::Apply(opts) => assert_eq!(opts.filename, "host.yaml"),
+        o => panic!("Expected apply opts, not {o:?}"),
+    }
+    assert!(Opt::try_parse_from(["bootc", "apply"]).is_err());
+    match Opt::parse_including_static(["bootc", "kargs", "append", "--booted", "nosmt", "quiet"]) {
+        Opt::Kargs(KargsOpts::Append(opts)) => {
+            assert_eq!(opts.kargs, ["nosmt", "quiet"]);
+            assert!(opts.target.booted && !opts.target.staged);
+        }
+        o => panic!("Expected kargs opts, not {o:?}"),
+    }
+    assert!(!Opt::parse_including_static(["bootc", "kargs", "list"]).is_mutating());
+    assert!(!Opt::parse_including_static(["bootc", "kargs", "diff"]).is_mutating());
+    match Opt::parse_including_static(["bootc", "kargs", "set-once", "systemd.unit=rescue.target"])
+    {
+        Opt::Kargs(KargsOpts::SetOnce(opts)) => {
+            assert_eq!(opts.kargs, ["systemd.unit=rescue.target"]);
+        }
+        o => panic!("Expected kargs opts, not {o:?}"),
+    }
+    assert!(Opt::try_parse_from(["bootc", "kargs", "delete"]).is_err());
+    match Opt::parse_including_static(["bootc", "container", "lint", "--format=json"]) {
+        Opt::Container(ContainerOpts::Lint(opts)) => {
+            assert_eq!(opts.format, Some(OutputFormat::Json));
+        }
+        o => panic!("Expected lint opts, not {o:?}"),
+    }
+    assert!(!Opt::parse_including_static(["bootc", "container", "lint"]).is_mutating());
+    match Opt::parse_including_static([
+        "bootc",
+        "container",
+        "lint",
+        "--fatal-warnings",
+        "--skip=var-content",
+        "--skip=sysusers",
+    ]) {
+        Opt::Container(ContainerOpts::Lint(opts)) => {
+            assert!(opts.fatal_warnings);
+            assert_eq!(opts.skip, ["var-content", "sysusers"]);
+        }
+        o => panic!("Expected lint opts, not {o:?}"),
+    }
+    assert!(
+        Opt::try_parse_from(["bootc", "container", "lint", "--list", "--skip=kernel"]).is_err()
+    );
+    match Opt::parse_including_static(["bootc", "image", "export", "--output=oci-archive:host.tar"])
+    {
+        Opt::Image(ImageOpts::Export(opts)) => {
+            assert_eq!(opts.output, "oci-archive:host.tar");
+        }
+        o => panic!("Expected export opts, not {o:?}"),
+    }
+    assert!(Opt::try_parse_from(["bootc", "image", "export"]).is_err());
+    assert!(matches!(
+        Opt::parse_including_static(["bootc", "image", "list", "--format=json"]),
+        Opt::Image(ImageOpts::List {
+            format: Some(OutputFormat::Json)
+        })
+    ));
+    match Opt::parse_including_static([
+        "bootc",
+        "image",
+        "rechunk",
+        "containers-storage:localhost/derived",
+        "oci:/var/tmp/derived",
+        "--max-layers=32",
+    ]) {
+        Opt::Image(ImageOpts::Rechunk(opts)) => {
+            assert_eq!(opts.src, "containers-storage:localhost/derived");
+            assert_eq!(opts.dest, "oci:/var/tmp/derived");
+            assert_eq!(opts.max_layers.unwrap().get(), 32);
+        }
+        o => panic!("Expected rechunk opts, not {o:?}"),
+    }
+    assert!(Opt::try_parse_from(["bootc", "image", "rechunk", "oci:/var/tmp/derived"]).is_err());
+    assert!(matches!(
+        Opt::parse_including_static(["bootc", "container", "commit"]),
+        Opt::Container(ContainerOpts::Commit)
+    ));
+    assert!(Opt::parse_including_static(["bootc", "container", "commit"]).is_mutating());
+    assert!(Opt::try_parse_from(["bootc", "kargs", "list", "--staged", "--booted"]).is_err());
+    assert!(matches!(
+        Opt::parse_including_static(["bootc", "upgrade", "--require-signature=sigstore"]),
+        Opt::Upgrade(UpgradeOpts {
+            require_signature: Some(crate::signature::SignatureRequirement::Sigstore),
+            ..
+        })
+    ));
+    assert!(matches!(
+        Opt::parse_including_static([
+            "bootc",
+            "switch",
+            "--require-signature=ostree-remote:example",
+            "quay.io/example/foo"
+        ]),
+        Opt::Switch(SwitchOpts {
+            require_signature: Some(crate::signature::SignatureRequirement::OstreeRemote(_)),
+            ..
+        })
+    ));
+    for invalid in [
+        &["bootc", "upgrade", "--require-signature=gpg"][..],
+        &[
+            "bootc",
+            "switch",
+            "--require-signature=sigstore",
+            "--enforce-container-sigpolicy",
+            "quay.io/example/foo",
+        ],
+    ] {
+        assert!(Opt::try_parse_from(invalid).is_err(), "{invalid:?}");
+    }
+    assert!(!o.is_mutating());
+    assert!(Opt::parse_including_static(["bootc", "upgrade"]).is_mutating());
+    assert!(Opt::parse_including_static(["bootc", "switch", "quay.io/example/foo"]).is_mutating());
+    assert!(!Opt::parse_including_static(["bootc", "status"]).is_mutating());
+    assert!(Opt::parse_including_static(["bootc", "boot-complete"]).is_mutating());
+    assert_eq!(
+        Opt::parse_including_static(["bootc", "health", "run"]),
+        Opt::Health(HealthOpts::Run)
+    );
+    assert!(Opt::parse_including_static(["bootc", "health", "run"]).is_mutating());
+    // The flag is global, so it is recognized after the subcommand too
+    for args in [
+        ["bootc", "--read-only", "status"],
+        ["bootc", "status", "--read-only"],
+    ] {
+        let cli = Cli::parse_including_static(args);
+        assert!(cli.read_only);
+        assert_eq!(cli.opt, Opt::parse_including_static(["bootc", "status"]));
+    }
+    assert!(!Cli::parse_including_static(["bootc", "status"]).read_only);
+    assert!(Opt::parse_including_static(["bootc", "internals", "cleanup"]).is_mutating());
+    assert!(!Opt::parse_including_static(["bootc", "internals", "log-boot"]).is_mutating());
+}
+
+#[test]
+fn test_parse_deployment_export() {
+    assert!(matches!(
+        Opt::parse_including_static(["bootc", "deployment", "export", "1", "/var/tmp/rollback"]),
+        Opt::Deployment(DeploymentOpts::Export {
+            index: 1,
+            to: DeploymentExportFormat::Dir,
+            etc: false,
+            ..
+        })
+    ));
+    let o = Opt::parse_including_static([
+        "bootc",
+        "deployment",
+        "export",
+        "--to=oci",
+        "0",
+        "/var/tmp/booted",
+    ]);
+    assert!(matches!(
+        o,
+        Opt::Deployment(DeploymentOpts::Export {
+            to: DeploymentExportFormat::Oci,
+            ..
+        })
+    ));
+    assert!(!o.is_mutating());
+}
+
+#[test]
+fn test_parse_rollback() {
+    let o = Opt::parse_including_static(["bootc", "rollback"]);
+    assert!(matches!(
+        o,
+        Opt::Rollback(RollbackOpts {
+            to: None,
+            list: false,
+            ..
+        })
+    ));
+    assert!(o.is_mutating());
+    let o = Opt::parse_including_static(["bootc", "rollback", "--list"]);
+    assert!(!o.is_mutating());
+    assert!(matches!(
+        Opt::parse_including_static(["bootc", "rollback", "--to", "2"]),
+        Opt::Rollback(RollbackOpts {
+            to: Some(crate::deploy::RollbackTarget::Index(2)),
+            ..
+        })
+    ));
+    assert!(matches!(
+        Opt::parse_including_static(["bootc", "rollback", "--apply", "--soft-reboot"]),
+        Opt::Rollback(RollbackOpts {
+            apply: true,
+            soft_reboot: true,
+            reboot_timeout: None,
+            ..
+        })
+    ));
+    for invalid in [
+        &["bootc", "rollback", "--to", "booted"][..],
+        &["bootc", "rollback", "--to", "1", "--list"],
+        &["bootc", "rollback", "--list", "--apply"],
+        &["bootc", "rollback", "--soft-reboot"],
+        &["bootc", "rollback", "--reboot-timeout", "60"],
+    ] {
+        assert!(Opt::try_parse_from(invalid).is_err(), "{invalid:?}");
+    }
+}
+
+#[test]
+fn test_parse_migrate_from_rpm_ostree() {
+    let o = Opt::parse_including_static(["bootc", "migrate-from-rpm-ostree"]);
+    assert!(matches!(
+        o,
+        Opt::MigrateFromRpmOstree(MigrateFromRpmOstreeOpts {
+            discard_layering: false,
+            image: None,
+            ..
+        })
+    ));
+    assert!(o.is_mutating());
+    assert!(matches!(
+        Opt::parse_including_static([
+            "bootc",
+            "migrate-from-rpm-ostree",
+            "--discard-layering",
+            "--image=quay.io/example/os:latest",
+            "--apply"
+        ]),
+        Opt::MigrateFromRpmOstree(MigrateFromRpmOstreeOpts {
+            discard_layering: true,
+            image: Some(_),
+            apply: true,
+            ..
+        })
+    ));
+    // The transport only applies to --image
+    assert!(Opt::try_parse_from(["bootc", "migrate-from-rpm-ostree", "--transport=oci"]).is_err());
+}
+
+#[test]
+fn test_parse_testing() {
+    let o = Opt::parse_including_static([
+        "bootc",
+        "internals",
+        "testing",
+        "fabricate-status",
+        "--booted",
+        "quay.io/example/os:latest",
+    ]);
+    assert!(matches!(
+        o,
+        Opt::Internals(InternalsOpts::Testing(TestingOpts::FabricateStatus {
+            booted: Some(_),
+            staged: None,
+            format: OutputFormat::Yaml,
+            ..
+        }))
+    ));
+    assert!(!o.is_mutating());
+    assert!(Opt::try_parse_from([
+        "bootc",
+        "internals",
+        "testing",
+        "fabricate-status",
+        "--rollback-queued"
+    ])
+    .is_err());
+    assert!(matches!(
+        Opt::parse_including_static([
+            "bootc",
+            "internals",
+            "testing",
+            "render-status",
+            "--full-digests",
+            "host.yaml"
+        ]),
+        Opt::Internals(InternalsOpts::Testing(TestingOpts::RenderStatus {
+            format: OutputFormat::HumanReadable,
+            full_digests: true,
+            ..
+        }))
+    ));
+    let o = Opt::parse_including_static([
+        "bootc",
+        "internals",
+        "testing",
+        "fabricate-sysroot",
+        "--booted=quay.io/example/os:latest",
+        "/var/tmp/sysroot",
+    ]);
+    assert!(matches!(
+        o,
+        Opt::Internals(InternalsOpts::Testing(TestingOpts::FabricateSysroot {
+            rollback: None,
+            rollback_queued: false,
+            ..
+        }))
+    ));
+    assert!(!o.is_mutating());
+    assert!(Opt::try_parse_from([
+        "bootc",
+        "internals",
+        "testing",
+        "fabricate-sysroot",
+        "/var/tmp/sysroot"
+    ])
+    .is_err());
+    assert!(matches!(
+        Opt::parse_including_static([
+            "bootc",
+            "internals",
+            "testing",
+            "plan",
+            "--image=/var/tmp/layout",
+            "--transport=oci",
+            "/var/tmp/sysroot"
+        ]),
+        Opt::Internals(InternalsOpts::Testing(TestingOpts::Plan {
+            image: Some(_),
+            transport: Some(_),
+            format: None,
+            ..
+        }))
+    ));
+}
+
+#[test]
+fn test_parse_journal() {
+    assert_eq!(
+        Opt::parse_including_static([
+            "bootc",
+            "journal",
+            "--event",
+            "update-staged",
+            "--event=health-failed",
+            "--",
+            "--since=today"
+        ]),
+        Opt::Journal(JournalOpts {
+            event: vec![
+                crate::journal::Event::UpdateStaged,
+                crate::journal::Event::HealthFailed
+            ],
+            list: false,
+            args: vec!["--since=today".into()],
+        })
+    );
+    assert!(!Opt::parse_including_static(["bootc", "journal"]).is_mutating());
+    assert!(Opt::try_parse_from(["bootc", "journal", "--list", "--event=rollback"]).is_err());
+    assert!(Opt::try_parse_from(["bootc", "journal", "--event=bogus"]).is_err());
+}
+
+#[test]
+fn test_parse_api() {
+    assert_eq!(
+        Opt::parse_including_static(["bootc", "internals", "api"]),
+        Opt::Internals(InternalsOpts::Api)
+    );
 }
 
 #[test]
@@ -0,0 +1,322 @@
+//! # Implementation of `bootc container commit`
+//!
+//! This finalizes the root filesystem of a container image for bootc, as the
+//! last step of a container build:
+//!
+//! - The contents of `/run`, `/tmp`, `/var/tmp` and `/var/cache` are removed.
+//! - `/etc` is prepared: the machine ID is emptied, and the backups of the user
+//!   databases are removed.
+//! - Files in `/var`, which would only be unpacked at installation time, are
+//!   moved to `/usr/share/factory/var` and copied back by `systemd-tmpfiles`;
+//!   directories and symbolic links in `/var` are created by it too.
+//! - The initramfs is regenerated if `prepare-root.conf` (e.g. the composefs
+//!   configuration) changed after it was generated.
+//! - The image is verified via the lints of `bootc container lint`.
+
+use std::fmt::Write as _;
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use cap_std::fs::{Dir, MetadataExt};
+use cap_std_ext::cap_std;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use fn_error_context::context;
+
+use crate::task::Task;
+
+/// The tmpfiles.d directories of the image.
+const TMPFILES_DIRS: &[&str] = &["usr/lib/tmpfiles.d", "etc/tmpfiles.d"];
+/// The generated tmpfiles.d file for the content of `/var`.
+const TMPFILES_CONF: &str = "usr/lib/tmpfiles.d/bootc-container-commit.conf";
+/// The directory the files in `/var` are moved to.
+const FACTORY_VAR: &str = "usr/share/factory/var";
+/// Directories in `/var` which are not handled here.
+const SKIP_VAR: &[&str] = &["tmp", "cache"];
+/// Backups of the user databases, written by e.g. `useradd`.
+const ETC_BACKUPS: &[&str] = &[
+    "etc/passwd-",
+    "etc/group-",
+    "etc/shadow-",
+    "etc/gshadow-",
+    "etc/subuid-",
+    "etc/subgid-",
+];
+
+/// Escape a path for a tmpfiles.d line.
+fn escape_path(path: &Utf8Path) -> String {
+    path.as_str().chars().fold(String::new(), |mut r, c| {
+        if c.is_whitespace() || c == '\\' {
+            let mut buf = [0; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                write!(r, "\\x{b:02x}").unwrap();
+            }
+        } else {
+            r.push(c);
+        }
+        r
+    })
+}
+
+/// The paths configured in the given tmpfiles.d file.
+fn parse_tmpfiles_paths(contents: &str) -> impl Iterator<Item = &str> {
+    contents
+        .lines()
+        .map(str::trim_start)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter_map(|l| l.split_ascii_whitespace().nth(1))
+}
+
+/// The paths configured in the tmpfiles.d files of the image.
+fn tmpfiles_paths(root: &Dir) -> Result<Vec<String>> {
+    let mut r = Vec::new();
+    for d in TMPFILES_DIRS {
+        let Some(d) = root.open_dir_optional(d)? else {
+            continue;
+        };
+        for entry in d.entries()? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let contents = std::io::read_to_string(entry.open()?)?;
+            r.extend(parse_tmpfiles_paths(&contents).map(ToOwned::to_owned));
+        }
+    }
+    Ok(r)
+}
+
+/// An entry in `/var`.
+#[derive(Debug, PartialEq, Eq)]
+enum VarEntry {
+    Dir { mode: u32, uid: u32, gid: u32 },
+    Symlink(Utf8PathBuf),
+    File,
+}
+
+/// Collect the entries below `path` (relative to `/var`), parents first.
+fn collect_var(dir: &Dir, path: &Utf8Path, out: &mut Vec<(Utf8PathBuf, VarEntry)>) -> Result<()> {
+    let mut entries = dir.entries()?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let name = entry.file_name();
+        let name = name
+            .to_str()
+            .with_context(|| format!("Invalid UTF-8 file name in /var/{path}: {name:?}"))?;
+        let child = path.join(name);
+        if path.as_str().is_empty() && SKIP_VAR.contains(&name) {
+            continue;
+        }
+        let ty = entry.file_type()?;
+        if ty.is_dir() {
+            let meta = entry.metadata()?;
+            out.push((
+                child.clone(),
+                VarEntry::Dir {
+                    mode: meta.mode() & 0o7777,
+                    uid: meta.uid(),
+                    gid: meta.gid(),
+                },
+            ));
+            collect_var(&entry.open_dir()?, &child, out)?;
+        } else if ty.is_symlink() {
+            let target = dir.read_link_contents(name)?;
+            let target = Utf8PathBuf::try_from(target)?;
+            out.push((child, VarEntry::Symlink(target)));
+        } else {
+            out.push((child, VarEntry::File));
+        }
+    }
+    Ok(())
+}
+
+/// The tmpfiles.d line creating the given entry.
+fn tmpfiles_line(path: &Utf8Path, entry: &VarEntry) -> String {
+    let target = escape_path(&Utf8Path::new("/var").join(path));
+    match entry {
+        VarEntry::Dir { mode, uid, gid } => format!("d {target} {mode:04o} {uid} {gid} -"),
+        VarEntry::Symlink(dest) => format!("L {target} - - - - {}", escape_path(dest)),
+        VarEntry::File => {
+            let src = escape_path(&Utf8Path::new("/").join(FACTORY_VAR).join(path));
+            format!("C {target} - - - - {src}")
+        }
+    }
+}
+
+/// Move the files in `/var` to [`FACTORY_VAR`], and write tmpfiles.d entries
+/// for everything in `/var` which is not created by tmpfiles.d yet.
+#[context("Converting /var content to tmpfiles.d")]
+fn convert_var(root: &Dir) -> Result<()> {
+    let Some(var) = root.open_dir_optional("var")? else {
+        return Ok(());
+    };
+    let mut entries = Vec::new();
+    collect_var(&var, Utf8Path::new(""), &mut entries)?;
+    let known = tmpfiles_paths(root)?;
+    let mut lines = String::new();
+    for (path, entry) in entries {
+        let abspath = Utf8Path::new("/var").join(&path);
+        if known.iter().any(|k| k == abspath.as_str()) {
+            continue;
+        }
+        writeln!(lines, "{}", tmpfiles_line(&path, &entry))?;
+        if entry == VarEntry::File {
+            let dest = Utf8Path::new(FACTORY_VAR).join(&path);
+            if let Some(parent) = dest.parent() {
+                root.create_dir_all(parent)?;
+            }
+            root.rename(Utf8Path::new("var").join(&path), root, &dest)
+                .with_context(|| format!("Moving {abspath}"))?;
+            println!("Moved {abspath} to /{dest}");
+        }
+    }
+    if lines.is_empty() {
+        return Ok(());
+    }
+    let mut contents = root
+        .open_optional(TMPFILES_CONF)?
+        .map(std::io::read_to_string)
+        .transpose()?
+        .unwrap_or_else(|| "# Generated by bootc container commit\n".to_owned());
+    contents.push_str(&lines);
+    root.create_dir_all("usr/lib/tmpfiles.d")?;
+    root.atomic_write(TMPFILES_CONF, contents)?;
+    println!("Wrote /{TMPFILES_CONF}");
+    Ok(())
+}
+
+/// Prepare `/etc` for use on many machines.
+#[context("Preparing /etc")]
+fn prepare_etc(root: &Dir) -> Result<()> {
+    if root.try_exists("usr/etc")? {
+        anyhow::bail!("/usr/etc must not exist; use /etc in container images");
+    }
+    if let Some(meta) = root.symlink_metadata_optional("etc/machine-id")? {
+        if meta.is_file() && meta.len() > 0 {
+            root.atomic_write("etc/machine-id", "")?;
+            println!("Emptied /etc/machine-id");
+        }
+    }
+    for path in ETC_BACKUPS {
+        if root.remove_file_optional(path)? {
+            println!("Removed /{path}");
+        }
+    }
+    Ok(())
+}
+
+/// Regenerate the initramfs if `prepare-root.conf` changed.
+#[context("Regenerating initramfs")]
+fn update_initramfs(root: &Dir) -> Result<()> {
+    let Some(kernel_dir) = crate::lints::outdated_initramfs(root)? else {
+        return Ok(());
+    };
+    let kver = kernel_dir
+        .file_name()
+        .with_context(|| format!("Invalid kernel directory {kernel_dir}"))?;
+    let initramfs = Utf8Path::new("/").join(&kernel_dir).join("initramfs.img");
+    Task::new("Regenerating initramfs", "dracut")
+        .args(["--force", "--no-hostonly", initramfs.as_str(), kver])
+        .run()
+}
+
+/// Finalize the given root filesystem, failing if the lints find errors.
+#[context("Committing container")]
+pub(crate) fn commit(root: &Dir) -> Result<()> {
+    ostree_ext::commit::prepare_ostree_commit_in(root)?;
+    prepare_etc(root)?;
+    convert_var(root)?;
+    update_initramfs(root)?;
+    let report = crate::lints::lint(root, &[])?;
+    report.write_human(std::io::stdout().lock())?;
+    let failures = report.failures(false);
+    if failures > 0 {
+        anyhow::bail!("Lints failed: {failures}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+fn fixture() -> Result<cap_std_ext::cap_tempfile::TempDir> {
+    let tempdir = cap_std_ext::cap_tempfile::tempdir(cap_std::ambient_authority())?;
+    Ok(tempdir)
+}
+
+#[test]
+fn test_escape_path() {
+    assert_eq!(escape_path(Utf8Path::new("/var/lib/foo")), "/var/lib/foo");
+    assert_eq!(
+        escape_path(Utf8Path::new("/var/lib/my app\\x")),
+        "/var/lib/my\\x20app\\x5cx"
+    );
+}
+
+#[test]
+fn test_convert_var() -> Result<()> {
+    let root = &fixture()?;
+    // Nothing to do
+    convert_var(root)?;
+    assert!(!root.try_exists(TMPFILES_CONF)?);
+
+    root.create_dir_all("usr/lib/tmpfiles.d")?;
+    root.write(
+        "usr/lib/tmpfiles.d/var.conf",
+        "# Base\nd /var/lib 0755 - - -\nL /var/run - - - - ../run\n",
+    )?;
+    root.create_dir_all("var/lib/example/data")?;
+    root.create_dir_all("var/cache/dnf")?;
+    root.write("var/cache/dnf/metadata", "cache")?;
+    root.symlink("../run", "var/run")?;
+    root.symlink("/usr/share/example", "var/lib/example/share")?;
+    root.write("var/lib/example/data/db", "data")?;
+    convert_var(root)?;
+
+    let conf = root.read_to_string(TMPFILES_CONF)?;
+    let lines = conf.lines().skip(1).collect::<Vec<_>>();
+    let uid = rustix::process::getuid().as_raw();
+    let gid = rustix::process::getgid().as_raw();
+    let mode = root.metadata("var/lib/example")?.mode() & 0o7777;
+    assert_eq!(
+        lines,
+        [
+            format!("d /var/lib/example {mode:04o} {uid} {gid} -"),
+            format!("d /var/lib/example/data {mode:04o} {uid} {gid} -"),
+            "C /var/lib/example/data/db - - - - /usr/share/factory/var/lib/example/data/db"
+                .to_owned(),
+            "L /var/lib/example/share - - - - /usr/share/example".to_owned(),
+        ]
+    );
+    assert!(!root.try_exists("var/lib/example/data/db")?);
+    assert_eq!(
+        root.read_to_string("usr/share/factory/var/lib/example/data/db")?,
+        "data"
+    );
+    // Directories and symbolic links are kept, the cache is not handled
+    assert!(root.symlink_metadata("var/lib/example/share")?.is_symlink());
+    assert!(root.try_exists("var/cache/dnf/metadata")?);
+
+    // Running again only adds new content
+    root.write("var/lib/example/other", "other")?;
+    convert_var(root)?;
+    let conf = root.read_to_string(TMPFILES_CONF)?;
+    assert_eq!(conf.lines().count(), lines.len() + 2);
+    assert!(conf
+        .ends_with("C /var/lib/example/other - - - - /usr/share/factory/var/lib/example/other\n"));
+    Ok(())
+}
+
+#[test]
+fn test_prepare_etc() -> Result<()> {
+    let root = &fixture()?;
+    prepare_etc(root)?;
+    root.create_dir_all("etc")?;
+    root.write("etc/machine-id", "0123456789abcdef0123456789abcdef\n")?;
+    root.write("etc/passwd", "root:x:0:0::/root:/bin/bash\n")?;
+    root.write("etc/passwd-", "root:x:0:0::/root:/bin/bash\n")?;
+    prepare_etc(root)?;
+    assert_eq!(root.read_to_string("etc/machine-id")?, "");
+    assert!(root.try_exists("etc/passwd")?);
+    assert!(!root.try_exists("etc/passwd-")?);
+    root.create_dir_all("usr/etc")?;
+    assert!(prepare_etc(root).is_err());
+    Ok(())
+}
@@ -0,0 +1,710 @@
+//! # Configuration for the host system
+//!
+//! This module handles the TOML configuration file `/usr/lib/bootc/config.toml`,
+//! which is expected to be shipped as part of the container image.  Unlike the
+//! `bootc install` configuration, this controls the behavior of bootc on the
+//! running system.
+
+use std::num::NonZeroU32;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use cap_std_ext::cap_std::fs::Dir;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use fn_error_context::context;
+use serde::{Deserialize, Serialize};
+
+/// Path to the configuration file, relative to the root.
+const CONFIG_PATH: &str = "usr/lib/bootc/config.toml";
+
+/// The toplevel configuration.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct HostConfiguration {
+    /// Configuration for `bootc status`
+    pub(crate) status: Option<StatusConfiguration>,
+    /// Policy applied to images before they are deployed
+    pub(crate) policy: Option<PolicyConfiguration>,
+    /// Configuration for fetching updates
+    pub(crate) fetch: Option<FetchConfiguration>,
+    /// Waking a powered-off system to fetch updates
+    pub(crate) wake: Option<WakeConfiguration>,
+    /// Automatic updates via `bootc-fetch-apply-updates.timer`
+    pub(crate) updates: Option<UpdatesConfiguration>,
+    /// The rescue boot entry
+    pub(crate) rescue: Option<RescueConfiguration>,
+    /// Coordinating reboots with workloads
+    pub(crate) reboot: Option<RebootConfiguration>,
+    /// Selecting updates via an update graph
+    pub(crate) update_graph: Option<UpdateGraphConfiguration>,
+    /// Rolling back automatically if an update fails to boot
+    pub(crate) boot_counting: Option<BootCountingConfiguration>,
+    /// Health checks run after boot
+    pub(crate) health: Option<HealthConfiguration>,
+    /// Retaining previous deployments
+    pub(crate) deployments: Option<DeploymentsConfiguration>,
+}
+
+/// The serialized `[status]` section
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct StatusConfiguration {
+    /// OCI label keys which will be copied into the image status
+    pub(crate) labels: Option<Vec<String>>,
+}
+
+/// The serialized `[policy]` section
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct PolicyConfiguration {
+    /// If set, images must be (or be derived from) one of these manifest digests
+    pub(crate) allowed_base_images: Option<Vec<String>>,
+    /// Rules evaluated before fetching an update
+    pub(crate) rules: Option<Vec<PolicyRule>>,
+    /// The maximum total size of the image layers, in bytes
+    pub(crate) max_image_size: Option<u64>,
+}
+
+/// What happens when a policy rule matches.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum PolicyAction {
+    /// The operation fails
+    Deny,
+    /// The operation requires interactive confirmation
+    Confirm,
+}
+
+/// A serialized `[[policy.rules]]` entry
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct PolicyRule {
+    /// Identifies the rule in error messages
+    pub(crate) name: String,
+    /// The condition, see [`crate::policy`]
+    pub(crate) when: String,
+    /// What to do if the condition is true
+    pub(crate) action: PolicyAction,
+    /// Explanation shown when the rule matches
+    pub(crate) message: Option<String>,
+}
+
+/// The serialized `[fetch]` section
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct FetchConfiguration {
+    /// Base URL of an ostree repository which may contain static deltas
+    /// between image versions.
+    pub(crate) static_delta_url: Option<String>,
+    /// Look for static deltas attached to the target image as OCI referrers
+    pub(crate) static_delta_referrers: Option<bool>,
+    /// The number of times a failed fetch is retried
+    pub(crate) retries: Option<u32>,
+    /// Seconds to wait before the first retry; this doubles for each later retry
+    pub(crate) backoff: Option<u64>,
+    /// Seconds after which a fetch attempt is abandoned
+    pub(crate) timeout: Option<u64>,
+    /// Proxies used to access registries and static deltas
+    pub(crate) proxy: Option<ProxyConfiguration>,
+    /// Alternative locations tried if fetching from a registry fails
+    pub(crate) mirrors: Option<Vec<MirrorConfiguration>>,
+    /// Fetch images with zstd:chunked or estargz layers via partial pulls
+    pub(crate) partial_pulls: Option<bool>,
+    /// Limit the download rate of layers, e.g. `2M` (bytes per second)
+    pub(crate) limit_rate: Option<String>,
+    /// Download up to this many layers concurrently
+    pub(crate) max_concurrent_downloads: Option<NonZeroU32>,
+}
+
+/// A serialized `[[fetch.mirrors]]` entry
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct MirrorConfiguration {
+    /// Images whose name starts with this repository (or registry) are mirrored
+    pub(crate) prefix: String,
+    /// Replacements for the prefix, tried in order
+    pub(crate) locations: Vec<String>,
+}
+
+/// The serialized `[fetch.proxy]` section
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct ProxyConfiguration {
+    /// Proxy URL for plain HTTP
+    pub(crate) http: Option<String>,
+    /// Proxy URL for HTTPS
+    pub(crate) https: Option<String>,
+    /// Hosts or domains which are accessed directly
+    pub(crate) no_proxy: Option<Vec<String>>,
+    /// A file containing `user:password` used to authenticate to the proxy
+    pub(crate) credentials_file: Option<String>,
+}
+
+/// The serialized `[wake]` section
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct WakeConfiguration {
+    /// The local time of day (`HH:MM`) at which to power on the system
+    pub(crate) time: String,
+    /// Whether to power off again after fetching updates; defaults to true
+    pub(crate) poweroff: Option<bool>,
+}
+
+/// The serialized `[updates]` section
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct UpdatesConfiguration {
+    /// A systemd calendar expression (`OnCalendar=`) for the maintenance window
+    pub(crate) schedule: Option<String>,
+    /// What to do after an update has been staged; defaults to `reboot`
+    pub(crate) reboot: Option<RebootStrategy>,
+    /// Seconds to wait for the registry to be reachable; 0 disables waiting
+    pub(crate) wait_for_network: Option<u64>,
+    /// Seconds over which a new digest of the tracked tag is adopted by all hosts
+    pub(crate) rollout_duration: Option<u64>,
+    /// The position of this host in rollouts (0-100), instead of one derived
+    /// from the machine ID
+    pub(crate) rollout_percentile: Option<u8>,
+}
+
+/// How an automatically staged update is applied.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum RebootStrategy {
+    /// The update is applied on the next (manual) reboot
+    None,
+    /// Reboot into the update
+    #[default]
+    Reboot,
+    /// Restart userspace into the update
+    SoftReboot,
+}
+
+/// The serialized `[rescue]` section
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct RescueConfiguration {
+    /// Maintain a boot entry for the booted kernel in `emergency.target`
+    pub(crate) enabled: Option<bool>,
+}
+
+/// The serialized `[reboot]` section
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct RebootConfiguration {
+    /// An executable which must succeed before rebooting to apply an update
+    pub(crate) drain_hook: Option<String>,
+    /// Seconds to wait for the drain hook and for inhibitor locks to be released
+    pub(crate) timeout: Option<u64>,
+}
+
+/// The serialized `[update-graph]` section
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct UpdateGraphConfiguration {
+    /// The URL of the update graph
+    pub(crate) url: String,
+    /// The channel passed to the update graph
+    pub(crate) channel: Option<String>,
+}
+
+/// The serialized `[boot-counting]` section
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct BootCountingConfiguration {
+    /// The number of failed boots of an update before rolling back
+    pub(crate) attempts: Option<u32>,
+}
+
+/// The serialized `[health]` section
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct HealthConfiguration {
+    /// What to do if a health check fails; defaults to `["journal"]`
+    pub(crate) on_failure: Option<Vec<HealthAction>>,
+}
+
+/// An action taken when a health check fails.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum HealthAction {
+    /// Log a journal message
+    Journal,
+    /// Hold updates, see [`crate::hold`]
+    Hold,
+    /// Roll back to the previous deployment and reboot
+    Rollback,
+}
+
+/// The actions taken by default when a health check fails.
+const DEFAULT_HEALTH_ON_FAILURE: &[HealthAction] = &[HealthAction::Journal];
+
+/// The serialized `[deployments]` section
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct DeploymentsConfiguration {
+    /// The number of rollback deployments kept when an update is staged,
+    /// including the booted one; defaults to 1
+    pub(crate) retain_rollbacks: Option<u32>,
+    /// Whether pinned deployments are kept in addition; defaults to true
+    pub(crate) retain_pinned: Option<bool>,
+}
+
+/// The default delay before retrying a failed fetch.
+const DEFAULT_FETCH_BACKOFF: Duration = Duration::from_secs(5);
+
+/// A download rate in bytes per second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DownloadRate(u64);
+
+impl FromStr for DownloadRate {
+    type Err = anyhow::Error;
+
+    /// Parse a number of bytes per second, with an optional `K`, `M` or `G`
+    /// suffix for powers of 1024, as for `curl --limit-rate`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (n, unit) = match s.char_indices().last() {
+            Some((i, c)) if c.is_ascii_alphabetic() => (&s[..i], c.to_ascii_uppercase()),
+            _ => (s, 'B'),
+        };
+        let shift = match unit {
+            'B' => 0,
+            'K' => 10,
+            'M' => 20,
+            'G' => 30,
+            _ => anyhow::bail!("Invalid unit in rate {s}, expected K, M or G"),
+        };
+        let rate = n
+            .parse::<u64>()
+            .ok()
+            .and_then(|n| n.checked_mul(1 << shift))
+            .filter(|&n| n > 0)
+            .ok_or_else(|| anyhow!("Invalid rate {s}"))?;
+        Ok(Self(rate))
+    }
+}
+
+impl std::fmt::Display for DownloadRate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} bytes/s", self.0)
+    }
+}
+
+impl HostConfiguration {
+    /// The image labels which should be included in the status.
+    pub(crate) fn status_labels(&self) -> &[String] {
+        self.status
+            .as_ref()
+            .and_then(|s| s.labels.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// The allowlist of base image digests, if one is configured.
+    pub(crate) fn allowed_base_images(&self) -> Option<&[String]> {
+        self.policy
+            .as_ref()
+            .and_then(|p| p.allowed_base_images.as_deref())
+    }
+
+    /// The maximum total size of an image, if one is configured.
+    pub(crate) fn max_image_size(&self) -> Option<u64> {
+        self.policy.as_ref().and_then(|p| p.max_image_size)
+    }
+
+    /// The number of times a failed fetch should be retried.
+    pub(crate) fn fetch_retries(&self) -> u32 {
+        self.fetch
+            .as_ref()
+            .and_then(|f| f.retries)
+            .unwrap_or_default()
+    }
+
+    /// The delay before the first retry of a failed fetch.
+    pub(crate) fn fetch_backoff(&self) -> Duration {
+        self.fetch
+            .as_ref()
+            .and_then(|f| f.backoff)
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_FETCH_BACKOFF)
+    }
+
+    /// The maximum duration of a fetch attempt, if any.
+    pub(crate) fn fetch_timeout(&self) -> Option<Duration> {
+        self.fetch
+            .as_ref()
+            .and_then(|f| f.timeout)
+            .map(Duration::from_secs)
+    }
+
+    /// The configured proxies, if any.
+    pub(crate) fn fetch_proxy(&self) -> Option<&ProxyConfiguration> {
+        self.fetch.as_ref().and_then(|f| f.proxy.as_ref())
+    }
+
+    /// Whether partial pulls are enabled.
+    pub(crate) fn fetch_partial_pulls(&self) -> bool {
+        self.fetch
+            .as_ref()
+            .and_then(|f| f.partial_pulls)
+            .unwrap_or_default()
+    }
+
+    /// The configured download rate limit, if any.
+    pub(crate) fn fetch_limit_rate(&self) -> Result<Option<DownloadRate>> {
+        self.fetch
+            .as_ref()
+            .and_then(|f| f.limit_rate.as_deref())
+            .map(|r| r.parse().context("Parsing fetch.limit-rate"))
+            .transpose()
+    }
+
+    /// The configured number of layers downloaded concurrently, if any.
+    pub(crate) fn fetch_max_concurrent_downloads(&self) -> Option<NonZeroU32> {
+        self.fetch.as_ref().and_then(|f| f.max_concurrent_downloads)
+    }
+
+    /// The configured registry mirrors.
+    pub(crate) fn fetch_mirrors(&self) -> &[MirrorConfiguration] {
+        self.fetch
+            .as_ref()
+            .and_then(|f| f.mirrors.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// The RTC wake configuration, if any.
+    pub(crate) fn wake(&self) -> Option<&WakeConfiguration> {
+        self.wake.as_ref()
+    }
+
+    /// The maintenance window for automatic updates, if configured.
+    pub(crate) fn update_schedule(&self) -> Option<&str> {
+        self.updates.as_ref().and_then(|u| u.schedule.as_deref())
+    }
+
+    /// How automatic updates are applied.
+    pub(crate) fn update_reboot(&self) -> RebootStrategy {
+        self.updates
+            .as_ref()
+            .and_then(|u| u.reboot)
+            .unwrap_or_default()
+    }
+
+    /// How long automatic updates wait for the registry to be reachable.
+    pub(crate) fn update_wait_for_network(&self) -> Duration {
+        self.updates
+            .as_ref()
+            .and_then(|u| u.wait_for_network)
+            .map(Duration::from_secs)
+            .unwrap_or(crate::network::DEFAULT_WAIT_FOR_NETWORK)
+    }
+
+    /// The duration of phased rollouts, if configured.
+    pub(crate) fn update_rollout_duration(&self) -> Option<Duration> {
+        self.updates
+            .as_ref()
+            .and_then(|u| u.rollout_duration)
+            .map(Duration::from_secs)
+    }
+
+    /// The configured position of this host in rollouts, if any.
+    pub(crate) fn update_rollout_percentile(&self) -> Option<u8> {
+        self.updates.as_ref().and_then(|u| u.rollout_percentile)
+    }
+
+    /// The update graph, if configured.
+    pub(crate) fn update_graph(&self) -> Option<&UpdateGraphConfiguration> {
+        self.update_graph.as_ref()
+    }
+
+    /// Whether the rescue boot entry is maintained.
+    pub(crate) fn rescue_enabled(&self) -> bool {
+        self.rescue
+            .as_ref()
+            .and_then(|r| r.enabled)
+            .unwrap_or_default()
+    }
+
+    /// The number of boot attempts of an update before rolling back, if boot
+    /// counting is enabled.
+    pub(crate) fn boot_counting_attempts(&self) -> Option<u32> {
+        self.boot_counting
+            .as_ref()
+            .and_then(|b| b.attempts)
+            .filter(|n| *n > 0)
+    }
+
+    /// The actions taken when a health check fails.
+    pub(crate) fn health_on_failure(&self) -> &[HealthAction] {
+        self.health
+            .as_ref()
+            .and_then(|h| h.on_failure.as_deref())
+            .unwrap_or(DEFAULT_HEALTH_ON_FAILURE)
+    }
+
+    /// The number of rollback deployments kept when an update is staged; the
+    /// booted deployment is always kept, so this is at least 1.
+    pub(crate) fn retain_rollbacks(&self) -> u32 {
+        self.deployments
+            .as_ref()
+            .and_then(|d| d.retain_rollbacks)
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    /// Whether pinned deployments are kept in addition to the rollback ones.
+    pub(crate) fn retain_pinned(&self) -> bool {
+        self.deployments
+            .as_ref()
+            .and_then(|d| d.retain_pinned)
+            .unwrap_or(true)
+    }
+
+    /// The drain hook run before rebooting, if any.
+    pub(crate) fn reboot_drain_hook(&self) -> Option<&str> {
+        self.reboot.as_ref().and_then(|r| r.drain_hook.as_deref())
+    }
+
+    /// How long to wait for a reboot to be possible, if configured.
+    pub(crate) fn reboot_timeout(&self) -> Option<Duration> {
+        self.reboot
+            .as_ref()
+            .and_then(|r| r.timeout)
+            .map(Duration::from_secs)
+    }
+
+    /// The pre-flight policy rules.
+    pub(crate) fn policy_rules(&self) -> &[PolicyRule] {
+        self.policy
+            .as_ref()
+            .and_then(|p| p.rules.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// The base URL for static deltas, if configured.
+    pub(crate) fn static_delta_url(&self) -> Option<&str> {
+        self.fetch
+            .as_ref()
+            .and_then(|f| f.static_delta_url.as_deref())
+    }
+
+    /// Whether to look for static deltas published as OCI referrers.
+    pub(crate) fn fetch_static_delta_referrers(&self) -> bool {
+        self.fetch
+            .as_ref()
+            .and_then(|f| f.static_delta_referrers)
+            .unwrap_or_default()
+    }
+}
+
+/// Load the host configuration from the provided root; if the configuration
+/// file does not exist, the default is returned.
+#[context("Loading host configuration")]
+pub(crate) fn load_config(root: &Dir) -> Result<HostConfiguration> {
+    let Some(f) = root.open_optional(CONFIG_PATH)? else {
+        return Ok(Default::default());
+    };
+    let buf = std::io::read_to_string(f)?;
+    toml::from_str(&buf).with_context(|| format!("Parsing /{CONFIG_PATH}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use cap_std_ext::cap_std;
+
+    use super::*;
+
+    #[test]
+    fn test_load_config() -> Result<()> {
+        let td = cap_std_ext::cap_tempfile::TempDir::new(cap_std::ambient_authority())?;
+        // No file
+        let c = load_config(&td)?;
+        assert_eq!(c, HostConfiguration::default());
+        assert!(c.status_labels().is_empty());
+        assert!(c.allowed_base_images().is_none());
+        assert!(c.max_image_size().is_none());
+        assert!(c.static_delta_url().is_none());
+        assert!(!c.fetch_static_delta_referrers());
+        assert!(c.policy_rules().is_empty());
+        assert_eq!(c.fetch_retries(), 0);
+        assert_eq!(c.fetch_backoff(), DEFAULT_FETCH_BACKOFF);
+        assert!(c.fetch_timeout().is_none());
+        assert!(c.fetch_proxy().is_none());
+        assert!(c.fetch_mirrors().is_empty());
+        assert!(!c.fetch_partial_pulls());
+        assert!(c.fetch_limit_rate()?.is_none());
+        assert!(c.fetch_max_concurrent_downloads().is_none());
+        assert!(c.wake().is_none());
+        assert!(c.update_schedule().is_none());
+        assert_eq!(c.update_reboot(), RebootStrategy::Reboot);
+        assert_eq!(
+            c.update_wait_for_network(),
+            crate::network::DEFAULT_WAIT_FOR_NETWORK
+        );
+        assert!(c.update_rollout_duration().is_none());
+        assert!(c.update_rollout_percentile().is_none());
+        assert!(c.update_graph().is_none());
+        assert!(!c.rescue_enabled());
+        assert!(c.boot_counting_attempts().is_none());
+        assert_eq!(c.health_on_failure(), [HealthAction::Journal]);
+        assert_eq!(c.retain_rollbacks(), 1);
+        assert!(c.retain_pinned());
+        assert!(c.reboot_drain_hook().is_none());
+        assert!(c.reboot_timeout().is_none());
+
+        td.create_dir_all("usr/lib/bootc")?;
+        td.write(
+            CONFIG_PATH,
+            indoc::indoc! { r#"
+            [status]
+            labels = ["org.opencontainers.image.revision", "com.example.build-id"]
+
+            [policy]
+            allowed-base-images = ["sha256:e7a3b5bd2ae2f7f1ec2a2ab1e1b5e1e0c8b0c8f4a3b0bbc3d6c6ce1d1f1c0b2a"]
+            max-image-size = 4000000000
+
+            [[policy.rules]]
+            name = "business-hours"
+            when = "hour >= 9 && hour < 17"
+            action = "confirm"
+
+            [fetch]
+            static-delta-url = "https://example.com/deltas"
+            static-delta-referrers = true
+            retries = 3
+            backoff = 10
+            timeout = 1800
+            partial-pulls = true
+            limit-rate = "512K"
+            max-concurrent-downloads = 4
+
+            [fetch.proxy]
+            https = "http://proxy.example.com:3128"
+            no-proxy = [".example.com"]
+
+            [[fetch.mirrors]]
+            prefix = "quay.io/example"
+            locations = ["mirror.example.com/example"]
+
+            [wake]
+            time = "03:30"
+
+            [updates]
+            schedule = "Sat *-*-* 03:00"
+            reboot = "soft-reboot"
+            wait-for-network = 0
+            rollout-duration = 604800
+            rollout-percentile = 10
+
+            [update-graph]
+            url = "https://updates.example.com/v1/graph"
+            channel = "stable"
+
+            [rescue]
+            enabled = true
+
+            [boot-counting]
+            attempts = 3
+
+            [health]
+            on-failure = ["journal", "hold", "rollback"]
+
+            [deployments]
+            retain-rollbacks = 3
+            retain-pinned = false
+
+            [reboot]
+            drain-hook = "/usr/libexec/example-drain"
+            timeout = 600
+        "#},
+        )?;
+        let c = load_config(&td)?;
+        assert_eq!(
+            c.status_labels(),
+            ["org.opencontainers.image.revision", "com.example.build-id"]
+        );
+        assert_eq!(
+            c.allowed_base_images().unwrap(),
+            ["sha256:e7a3b5bd2ae2f7f1ec2a2ab1e1b5e1e0c8b0c8f4a3b0bbc3d6c6ce1d1f1c0b2a"]
+        );
+        assert_eq!(c.max_image_size(), Some(4_000_000_000));
+        assert_eq!(c.static_delta_url(), Some("https://example.com/deltas"));
+        assert!(c.fetch_static_delta_referrers());
+        assert_eq!(c.fetch_retries(), 3);
+        assert_eq!(c.fetch_backoff(), Duration::from_secs(10));
+        assert_eq!(c.fetch_timeout(), Some(Duration::from_secs(1800)));
+        assert!(c.fetch_partial_pulls());
+        assert_eq!(
+            c.fetch_limit_rate()?,
+            Some("524288".parse::<DownloadRate>()?)
+        );
+        assert_eq!(c.fetch_max_concurrent_downloads(), NonZeroU32::new(4));
+        let proxy = c.fetch_proxy().unwrap();
+        assert_eq!(
+            proxy.https.as_deref(),
+            Some("http://proxy.example.com:3128")
+        );
+        assert!(proxy.http.is_none());
+        assert_eq!(proxy.no_proxy.as_deref().unwrap(), [".example.com"]);
+        let mirrors = c.fetch_mirrors();
+        assert_eq!(mirrors.len(), 1);
+        assert_eq!(mirrors[0].prefix, "quay.io/example");
+        assert_eq!(mirrors[0].locations, ["mirror.example.com/example"]);
+        let wake = c.wake().unwrap();
+        assert_eq!(wake.time, "03:30");
+        assert!(wake.poweroff.is_none());
+        assert_eq!(c.update_schedule(), Some("Sat *-*-* 03:00"));
+        assert_eq!(c.update_reboot(), RebootStrategy::SoftReboot);
+        assert!(c.update_wait_for_network().is_zero());
+        assert_eq!(
+            c.update_rollout_duration(),
+            Some(Duration::from_secs(604800))
+        );
+        assert_eq!(c.update_rollout_percentile(), Some(10));
+        let graph = c.update_graph().unwrap();
+        assert_eq!(graph.url, "https://updates.example.com/v1/graph");
+        assert_eq!(graph.channel.as_deref(), Some("stable"));
+        assert!(c.rescue_enabled());
+        assert_eq!(c.boot_counting_attempts(), Some(3));
+        assert_eq!(
+            c.health_on_failure(),
+            [
+                HealthAction::Journal,
+                HealthAction::Hold,
+                HealthAction::Rollback
+            ]
+        );
+        assert_eq!(c.retain_rollbacks(), 3);
+        assert!(!c.retain_pinned());
+        assert_eq!(c.reboot_drain_hook(), Some("/usr/libexec/example-drain"));
+        assert_eq!(c.reboot_timeout(), Some(Duration::from_secs(600)));
+        let rules = c.policy_rules();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].name, "business-hours");
+        assert_eq!(rules[0].action, PolicyAction::Confirm);
+        assert!(rules[0].message.is_none());
+
+        td.write(CONFIG_PATH, "[deployments]\nretain-rollbacks = 0\n")?;
+        assert_eq!(load_config(&td)?.retain_rollbacks(), 1);
+
+        td.write(CONFIG_PATH, "[status]\nunknown = true\n")?;
+        assert!(load_config(&td).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        let rate = |s: &str| s.parse::<DownloadRate>().map(|r| r.0);
+        assert_eq!(rate("1000").unwrap(), 1000);
+        assert_eq!(rate("512k").unwrap(), 512 * 1024);
+        assert_eq!(rate("2M").unwrap(), 2 * 1024 * 1024);
+        assert_eq!(rate("1G").unwrap(), 1024 * 1024 * 1024);
+        for invalid in ["", "0", "M", "1.5M", "2T", "-1", "99999999999999G"] {
+            assert!(rate(invalid).is_err(), "{invalid}");
+        }
+        assert_eq!(
+            "2K".parse::<DownloadRate>().unwrap().to_string(),
+            "2048 bytes/s"
+        );
+    }
+}
@@ -0,0 +1,280 @@
+//! # Package-aligned image layers
+//!
+//! Images written by `bootc image export` and `bootc image rechunk` are split
+//! into layers along the packages owning the content, as recorded in the rpm
+//! database of the commit.  A new version of an image then only changes the
+//! layers holding updated packages, so clients pulling it can reuse the others.
+//! Content not owned by any package (e.g. generated in a `RUN` step) is grouped
+//! into one component which is expected to change in every build.
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+use fn_error_context::context;
+use ostree_ext::chunking::ObjectMetaSized;
+use ostree_ext::objectsource::{ObjectMeta, ObjectMetaMap, ObjectMetaSet, ObjectSourceMeta};
+use ostree_ext::oci_spec::image::ImageManifest;
+use ostree_ext::ostree::{self, gio};
+use ostree_ext::prelude::{Cast, FileEnumeratorExt, FileExt};
+
+use crate::task::Task;
+
+/// The locations of the rpm database, in order of preference.
+const RPMDB_PATHS: &[&str] = &["/usr/lib/sysimage/rpm", "/usr/share/rpm"];
+/// One line per file of each installed package.
+const RPM_QUERYFORMAT: &str = "[%{NEVRA}\t%{NAME}\t%{SOURCERPM}\t%{BUILDTIME}\t%{FILENAMES}\n]";
+/// The component of content not owned by any package.
+const UNPACKAGED: &str = "bootc-unpackaged-content";
+/// The layer annotation listing the components of a layer, as written by
+/// [`ostree_ext::container::encapsulate`].
+const COMPONENTS_ANNOTATION: &str = "ostree.components";
+
+/// A file owned by an installed package.
+#[derive(Debug, PartialEq, Eq)]
+struct PackageFile<'a> {
+    nevra: &'a str,
+    name: &'a str,
+    srcrpm: &'a str,
+    buildtime: u64,
+    path: &'a str,
+}
+
+/// Parse the output of `rpm -qa` with [`RPM_QUERYFORMAT`].
+fn parse_package_files(buf: &str) -> Result<Vec<PackageFile<'_>>> {
+    buf.lines()
+        .map(|line| {
+            let mut fields = line.splitn(5, '\t');
+            let mut next = || {
+                fields
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Invalid line: {line}"))
+            };
+            Ok(PackageFile {
+                nevra: next()?,
+                name: next()?,
+                srcrpm: next()?,
+                buildtime: next()?
+                    .parse()
+                    .with_context(|| format!("Invalid build time: {line}"))?,
+                path: next()?,
+            })
+        })
+        .collect()
+}
+
+/// The path of a file of the commit as recorded in the rpm database, where the
+/// content of `/etc` is in `/usr/etc`.
+fn rpm_path(path: &str) -> &str {
+    match path.strip_prefix("/usr/etc") {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => &path["/usr".len()..],
+        _ => path,
+    }
+}
+
+/// Collect the path and content checksum of each non-directory in the tree.
+fn walk_tree(dir: &ostree::RepoFile, path: &str, out: &mut Vec<(String, String)>) -> Result<()> {
+    let cancellable = gio::Cancellable::NONE;
+    let queryattrs = "standard::name,standard::type";
+    let queryflags = gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS;
+    let children = dir.enumerate_children(queryattrs, queryflags, cancellable)?;
+    while let Some(info) = children.next_file(cancellable)? {
+        let name = info.name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        let child_path = format!("{path}/{name}");
+        let child = children.child(&info);
+        let child = child.downcast::<ostree::RepoFile>().expect("downcast");
+        if info.file_type() == gio::FileType::Directory {
+            walk_tree(&child, &child_path, out)?;
+        } else {
+            child.ensure_resolved()?;
+            out.push((child_path, child.checksum().to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Query the files of the packages in the rpm database of the commit, if any.
+fn query_rpmdb(repo: &ostree::Repo, commit: &str, tmp: &Utf8Path) -> Result<Option<String>> {
+    let cancellable = gio::Cancellable::NONE;
+    let (root, _) = repo.read_commit(commit, cancellable)?;
+    let queryflags = gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS;
+    let Some(rpmdb) = RPMDB_PATHS.iter().find(|p| {
+        root.resolve_relative_path(p)
+            .query_file_type(queryflags, cancellable)
+            == gio::FileType::Directory
+    }) else {
+        return Ok(None);
+    };
+    let dbpath = tmp.join("rpmdb");
+    let opts = ostree::RepoCheckoutAtOptions {
+        mode: ostree::RepoCheckoutMode::User,
+        force_copy: true,
+        subpath: Some(rpmdb.into()),
+        ..Default::default()
+    };
+    repo.checkout_at(
+        Some(&opts),
+        ostree::AT_FDCWD,
+        dbpath.as_std_path(),
+        commit,
+        cancellable,
+    )
+    .with_context(|| format!("Checking out {rpmdb}"))?;
+    let buf = Task::new("Querying rpm database", "rpm")
+        .args(["--dbpath", dbpath.as_str(), "-qa", "--queryformat"])
+        .arg(RPM_QUERYFORMAT)
+        .quiet()
+        .read()?;
+    Ok(Some(buf))
+}
+
+/// Map the content of the commit to the owning packages; `files` are the path
+/// and checksum of each non-directory of the commit.
+fn build_meta(packages: &[PackageFile], files: &[(String, String)]) -> ObjectMeta {
+    let oldest = packages
+        .iter()
+        .map(|p| p.buildtime)
+        .min()
+        .unwrap_or_default();
+    let mut set = ObjectMetaSet::new();
+    let mut names = HashSet::new();
+    let mut owners = HashMap::new();
+    for p in packages {
+        let id: Rc<str> = Rc::from(p.nevra);
+        if !set.contains(p.nevra) {
+            // Layers of prior builds are matched by name, so it must be unique even
+            // with multiple versions of a package installed (e.g. kernels).
+            let name = if names.insert(p.name) {
+                p.name
+            } else {
+                p.nevra
+            };
+            set.insert(ObjectSourceMeta {
+                identifier: Rc::clone(&id),
+                name: Rc::from(name),
+                srcid: Rc::from(p.srcrpm),
+                change_time_offset: ((p.buildtime - oldest) / 3600)
+                    .try_into()
+                    .unwrap_or(u32::MAX),
+                change_frequency: 1,
+            });
+        }
+        owners.entry(p.path).or_insert(id);
+    }
+    let unpackaged: Rc<str> = Rc::from(UNPACKAGED);
+    let mut map = ObjectMetaMap::default();
+    // Content shared by several files is assigned to the first package owning one
+    for (path, checksum) in files {
+        if let Some(id) = owners.get(rpm_path(path)) {
+            map.entry(checksum.clone()).or_insert_with(|| Rc::clone(id));
+        }
+    }
+    for (_, checksum) in files {
+        map.entry(checksum.clone())
+            .or_insert_with(|| Rc::clone(&unpackaged));
+    }
+    let used = map.values().map(|id| &**id).collect::<HashSet<_>>();
+    if used.contains(UNPACKAGED) {
+        set.insert(ObjectSourceMeta {
+            identifier: Rc::clone(&unpackaged),
+            name: Rc::clone(&unpackaged),
+            srcid: Rc::clone(&unpackaged),
+            change_time_offset: u32::MAX,
+            change_frequency: u32::MAX,
+        });
+    }
+    // Packages without content in the commit have no layer
+    set.retain(|m| used.contains(&*m.identifier));
+    ObjectMeta { set, map }
+}
+
+/// Compute the mapping of the content of the commit to the packages owning it,
+/// or `None` if the commit has no rpm database.
+#[context("Computing package content of {commit}")]
+pub(crate) fn package_meta(repo: &ostree::Repo, commit: &str) -> Result<Option<ObjectMetaSized>> {
+    let td = tempfile::tempdir()?;
+    let Some(buf) = query_rpmdb(repo, commit, td.path().try_into()?)? else {
+        return Ok(None);
+    };
+    let packages = parse_package_files(&buf)?;
+    let (root, _) = repo.read_commit(commit, gio::Cancellable::NONE)?;
+    let root = root.downcast::<ostree::RepoFile>().expect("downcast");
+    let mut files = Vec::new();
+    walk_tree(&root, "", &mut files)?;
+    let meta = build_meta(&packages, &files);
+    tracing::debug!("Found {} components", meta.set.len());
+    Ok(Some(ObjectMetaSized::compute_sizes(repo, meta)?))
+}
+
+/// Whether the layers of the image were split by component, so that the manifest
+/// can be used as the prior build of a new version.
+pub(crate) fn has_components(manifest: &ImageManifest) -> bool {
+    let layers = manifest.layers();
+    layers.len() > 1
+        && layers.iter().skip(1).all(|l| {
+            l.annotations()
+                .as_ref()
+                .is_some_and(|a| a.contains_key(COMPONENTS_ANNOTATION))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_meta() {
+        let buf = indoc::indoc! {"
+            bash-5.2.26-3.fc40.x86_64\tbash\tbash-5.2.26-3.fc40.src.rpm\t1700000000\t/usr/bin/bash
+            bash-5.2.26-3.fc40.x86_64\tbash\tbash-5.2.26-3.fc40.src.rpm\t1700000000\t/etc/skel/.bashrc
+            kernel-core-6.8.5-301.fc40.x86_64\tkernel-core\tkernel-6.8.5-301.fc40.src.rpm\t1700036000\t/usr/lib/modules/6.8.5/vmlinuz
+            kernel-core-6.8.6-301.fc40.x86_64\tkernel-core\tkernel-6.8.6-301.fc40.src.rpm\t1700072000\t/usr/lib/modules/6.8.6/vmlinuz
+            filesystem-3.18-8.fc40.x86_64\tfilesystem\tfilesystem-3.18-8.fc40.src.rpm\t1700000000\t/usr/share/empty
+        "};
+        let packages = parse_package_files(buf).unwrap();
+        assert_eq!(packages.len(), 5);
+        assert_eq!(
+            packages[1],
+            PackageFile {
+                nevra: "bash-5.2.26-3.fc40.x86_64",
+                name: "bash",
+                srcrpm: "bash-5.2.26-3.fc40.src.rpm",
+                buildtime: 1700000000,
+                path: "/etc/skel/.bashrc",
+            }
+        );
+        assert!(parse_package_files("bash\tbash\n").is_err());
+        assert!(parse_package_files("a\tb\tc\tnow\t/usr/bin/a\n").is_err());
+
+        let files = [
+            ("/usr/bin/bash", "aa"),
+            ("/usr/etc/skel/.bashrc", "bb"),
+            ("/usr/etcfoo", "cc"),
+            ("/usr/lib/modules/6.8.5/vmlinuz", "dd"),
+            ("/usr/lib/modules/6.8.6/vmlinuz", "ee"),
+            // The same content as a packaged file
+            ("/usr/local/bin/bash", "aa"),
+        ]
+        .map(|(p, c)| (p.to_owned(), c.to_owned()));
+        let meta = build_meta(&packages, &files);
+        let owner = |c: &str| &*meta.map[c];
+        assert_eq!(owner("aa"), "bash-5.2.26-3.fc40.x86_64");
+        assert_eq!(owner("bb"), "bash-5.2.26-3.fc40.x86_64");
+        assert_eq!(owner("cc"), UNPACKAGED);
+        assert_eq!(owner("dd"), "kernel-core-6.8.5-301.fc40.x86_64");
+        assert_eq!(meta.map.len(), 5);
+        // filesystem owns no content
+        assert_eq!(meta.set.len(), 4);
+        let kernel = meta.set.get("kernel-core-6.8.5-301.fc40.x86_64").unwrap();
+        assert_eq!(&*kernel.name, "kernel-core");
+        assert_eq!(kernel.change_time_offset, 10);
+        let kernel = meta.set.get("kernel-core-6.8.6-301.fc40.x86_64").unwrap();
+        assert_eq!(&*kernel.name, "kernel-core-6.8.6-301.fc40.x86_64");
+        assert_eq!(kernel.change_time_offset, 20);
+        assert_eq!(meta.set.get(UNPACKAGED).unwrap().change_frequency, u32::MAX);
+    }
+}
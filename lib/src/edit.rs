@@ -0,0 +1,449 @@
+//! # Validating edits to the host specification
+//!
+//! `bootc edit` accepts a modified [`Host`] from an editor, a file or
+//! standard input.  Before anything is applied, the input is validated
+//! against the JSON schema of [`Host`] (as also published in
+//! `host-v1.schema.json`), so that e.g. a misspelled field is rejected with
+//! its path instead of being silently ignored.  The accepted changes to the
+//! `spec` are then shown as a diff.
+
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use schemars::schema_for;
+use serde_json::{Map, Value};
+
+use crate::spec::{Host, HostSpec};
+
+/// A mismatch between the input and the schema.
+#[derive(Debug, PartialEq, Eq)]
+struct Violation {
+    /// The path of the offending value, e.g. `spec.image.transport`
+    path: String,
+    /// What is wrong with it
+    message: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let path = if self.path.is_empty() {
+            "(root)"
+        } else {
+            &self.path
+        };
+        write!(f, "{path}: {}", self.message)
+    }
+}
+
+/// Append a key to a path.
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+/// The JSON schema type name of a value.
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Whether a value is of the given JSON schema type.
+fn type_is(ty: &str, value: &Value) -> bool {
+    match (ty, value) {
+        ("null", Value::Null)
+        | ("boolean", Value::Bool(_))
+        | ("number", Value::Number(_))
+        | ("string", Value::String(_))
+        | ("array", Value::Array(_))
+        | ("object", Value::Object(_)) => true,
+        ("integer", Value::Number(n)) => n.is_i64() || n.is_u64(),
+        _ => false,
+    }
+}
+
+/// Check that a value is one of the allowed ones.
+fn check_enum<'a>(
+    allowed: impl IntoIterator<Item = &'a Value>,
+    value: &Value,
+    path: &str,
+    out: &mut Vec<Violation>,
+) {
+    let allowed = allowed.into_iter().collect::<Vec<_>>();
+    if !allowed.contains(&value) {
+        let allowed = allowed
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        out.push(Violation {
+            path: path.to_owned(),
+            message: format!("expected one of {allowed}"),
+        });
+    }
+}
+
+/// Validates values against the subset of JSON schema generated by `schemars`.
+struct Validator<'a> {
+    definitions: &'a Map<String, Value>,
+}
+
+impl<'a> Validator<'a> {
+    /// Follow `$ref` to a definition.
+    fn resolve(&self, mut schema: &'a Value) -> &'a Value {
+        while let Some(r) = schema.get("$ref").and_then(Value::as_str) {
+            match r
+                .strip_prefix("#/definitions/")
+                .and_then(|name| self.definitions.get(name))
+            {
+                Some(s) => schema = s,
+                None => break,
+            }
+        }
+        schema
+    }
+
+    /// The alternatives of a schema, if it has any.
+    fn alternatives(schema: &'a Value) -> Option<&'a Vec<Value>> {
+        schema
+            .get("anyOf")
+            .or_else(|| schema.get("oneOf"))
+            .and_then(Value::as_array)
+    }
+
+    /// Whether the type of a value matches the schema, ignoring its contents.
+    fn type_matches(&self, schema: &'a Value, value: &Value) -> bool {
+        let schema = self.resolve(schema);
+        if let Some(branches) = Self::alternatives(schema) {
+            return branches.iter().any(|b| self.type_matches(b, value));
+        }
+        if let Some(all) = schema.get("allOf").and_then(Value::as_array) {
+            if !all.iter().all(|s| self.type_matches(s, value)) {
+                return false;
+            }
+        }
+        match schema.get("type") {
+            Some(Value::String(ty)) => type_is(ty, value),
+            Some(Value::Array(types)) => types
+                .iter()
+                .filter_map(Value::as_str)
+                .any(|ty| type_is(ty, value)),
+            _ => true,
+        }
+    }
+
+    /// Validate a value, appending any violations to `out`.
+    fn validate(&self, schema: &'a Value, value: &Value, path: &str, out: &mut Vec<Violation>) {
+        let violation = |message: String| Violation {
+            path: path.to_owned(),
+            message,
+        };
+        let schema = self.resolve(schema);
+        if let Some(branches) = Self::alternatives(schema) {
+            let matching = branches
+                .iter()
+                .filter(|b| self.type_matches(b, value))
+                .collect::<Vec<_>>();
+            if let [branch] = matching.as_slice() {
+                self.validate(branch, value, path, out);
+                return;
+            }
+            // Unit variants of an enum are generated as one alternative each
+            let allowed = matching
+                .iter()
+                .map(|b| self.resolve(b).get("enum").and_then(Value::as_array))
+                .collect::<Option<Vec<_>>>();
+            if let Some(allowed) = allowed.filter(|a| !a.is_empty()) {
+                check_enum(allowed.into_iter().flatten(), value, path, out);
+                return;
+            }
+            // Valid if any alternative is; otherwise report the first one
+            let mut first = None;
+            for branch in matching {
+                let mut violations = Vec::new();
+                self.validate(branch, value, path, &mut violations);
+                if violations.is_empty() {
+                    return;
+                }
+                first.get_or_insert(violations);
+            }
+            match first {
+                Some(violations) => out.extend(violations),
+                None => out.push(violation(format!("unexpected {}", type_name(value)))),
+            }
+            return;
+        }
+        if let Some(all) = schema.get("allOf").and_then(Value::as_array) {
+            for s in all {
+                self.validate(s, value, path, out);
+            }
+        }
+        if !self.type_matches(schema, value) {
+            let expected = match schema.get("type") {
+                Some(Value::Array(types)) => types
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .collect::<Vec<_>>()
+                    .join(" or "),
+                Some(ty) => ty.as_str().unwrap_or_default().to_owned(),
+                None => "another type".to_owned(),
+            };
+            out.push(violation(format!(
+                "expected {expected}, found {}",
+                type_name(value)
+            )));
+            return;
+        }
+        if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+            check_enum(allowed, value, path, out);
+        }
+        match value {
+            Value::Object(map) => {
+                let properties = schema.get("properties").and_then(Value::as_object);
+                let required = schema.get("required").and_then(Value::as_array);
+                for key in required.into_iter().flatten().filter_map(Value::as_str) {
+                    if !map.contains_key(key) {
+                        out.push(Violation {
+                            path: join(path, key),
+                            message: "missing field".to_owned(),
+                        });
+                    }
+                }
+                for (key, v) in map {
+                    let path = &join(path, key);
+                    if let Some(s) = properties.and_then(|p| p.get(key)) {
+                        self.validate(s, v, path, out);
+                        continue;
+                    }
+                    match schema.get("additionalProperties") {
+                        Some(s @ Value::Object(_)) => self.validate(s, v, path, out),
+                        Some(Value::Bool(true)) => {}
+                        // `false`, or anything this does not understand
+                        Some(_) => out.push(Violation {
+                            path: path.clone(),
+                            message: "unknown field".to_owned(),
+                        }),
+                        None if properties.is_some() => out.push(Violation {
+                            path: path.clone(),
+                            message: "unknown field".to_owned(),
+                        }),
+                        None => {}
+                    }
+                }
+            }
+            Value::Array(items) => {
+                if let Some(s) = schema.get("items") {
+                    for (i, v) in items.iter().enumerate() {
+                        self.validate(s, v, &format!("{path}[{i}]"), out);
+                    }
+                }
+            }
+            Value::Number(n) => {
+                let minimum = schema.get("minimum").and_then(Value::as_f64);
+                if let (Some(minimum), Some(n)) = (minimum, n.as_f64()) {
+                    if n < minimum {
+                        out.push(violation(format!("must be at least {minimum}")));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Validate a value against a schema as generated by `schemars`.
+fn validate(schema: &Value, value: &Value) -> Vec<Violation> {
+    let empty = Map::new();
+    let validator = Validator {
+        definitions: schema
+            .get("definitions")
+            .and_then(Value::as_object)
+            .unwrap_or(&empty),
+    };
+    let mut r = Vec::new();
+    validator.validate(schema, value, "", &mut r);
+    r
+}
+
+/// Parse a host specification as YAML (or JSON), rejecting any input which
+/// does not match the schema, e.g. because of unknown fields.
+pub(crate) fn parse_host(buf: &str) -> Result<Host> {
+    let value: Value = serde_yaml::from_str(buf).context("Parsing YAML")?;
+    let schema = serde_json::to_value(schema_for!(Host))?;
+    let violations = validate(&schema, &value);
+    if !violations.is_empty() {
+        let violations = violations
+            .iter()
+            .map(|v| format!("  {v}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        anyhow::bail!("Invalid host specification:\n{violations}");
+    }
+    serde_json::from_value(value).context("Parsing host specification")
+}
+
+/// Read a host specification from a file, or from standard input if the
+/// filename is `-`, and parse it via [`parse_host`].
+pub(crate) fn read_host(filename: &str) -> Result<Host> {
+    let buf = if filename == "-" {
+        std::io::read_to_string(std::io::stdin()).context("Reading standard input")?
+    } else {
+        std::fs::read_to_string(filename).with_context(|| format!("Reading {filename}"))?
+    };
+    parse_host(&buf)
+}
+
+/// A line of a diff.
+#[derive(Debug, PartialEq, Eq)]
+enum DiffLine<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Compute a line based diff via the longest common subsequence; this is
+/// quadratic, which is fine for host specifications.
+fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<DiffLine<'a>> {
+    let a = old.lines().collect::<Vec<_>>();
+    let b = new.lines().collect::<Vec<_>>();
+    let (n, m) = (a.len(), b.len());
+    // lcs[i][j] is the length of the common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut r = Vec::new();
+    while i < n && j < m {
+        if a[i] == b[j] {
+            r.push(DiffLine::Same(a[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            r.push(DiffLine::Removed(a[i]));
+            i += 1;
+        } else {
+            r.push(DiffLine::Added(b[j]));
+            j += 1;
+        }
+    }
+    r.extend(a[i..].iter().map(|l| DiffLine::Removed(l)));
+    r.extend(b[j..].iter().map(|l| DiffLine::Added(l)));
+    r
+}
+
+/// Write the changes between two host specifications as a colored diff of
+/// their YAML serialization; use e.g. [`anstream::stdout`] to strip the
+/// colors when not writing to a terminal.
+pub(crate) fn write_spec_diff(mut out: impl Write, old: &HostSpec, new: &HostSpec) -> Result<()> {
+    let old = serde_yaml::to_string(old)?;
+    let new = serde_yaml::to_string(new)?;
+    let reset = anstyle::Reset.render();
+    for line in diff_lines(&old, &new) {
+        match line {
+            DiffLine::Same(l) => writeln!(out, "  {l}")?,
+            DiffLine::Removed(l) => {
+                writeln!(out, "{}- {l}{reset}", anstyle::AnsiColor::Red.render_fg())?
+            }
+            DiffLine::Added(l) => {
+                writeln!(out, "{}+ {l}{reset}", anstyle::AnsiColor::Green.render_fg())?
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_host() -> Result<()> {
+        let valid = include_str!("fixtures/spec-staged-booted.yaml");
+        let host = parse_host(valid)?;
+        assert_eq!(host, serde_yaml::from_str::<Host>(valid)?);
+        // JSON is accepted too
+        parse_host(&serde_json::to_string(&host)?)?;
+
+        let invalid = indoc::indoc! { r#"
+            apiVersion: org.containers.bootc/v1
+            kind: BootcHost
+            metadata:
+              name: host
+              labels:
+                example.com/role: 42
+            spec:
+              bootOrder: sideways
+              image:
+                image: quay.io/example/someimage:latest
+                signature:
+                  ostreeRemote: 1
+                transprot: registry
+            status:
+              rollbackQueued: "no"
+        "# };
+        let e = parse_host(invalid).unwrap_err().to_string();
+        similar_asserts::assert_eq!(
+            e,
+            indoc::indoc! { r#"
+                Invalid host specification:
+                  metadata.labels.example.com/role: expected string, found number
+                  spec.bootOrder: expected one of "default", "rollback"
+                  spec.image.transport: missing field
+                  spec.image.signature.ostreeRemote: expected string, found number
+                  spec.image.transprot: unknown field
+                  status.rollbackQueued: expected boolean, found string"# }
+        );
+
+        let e = parse_host("- a\n- b\n").unwrap_err().to_string();
+        assert!(e.contains("(root): expected object, found array"), "{e}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        use DiffLine::*;
+        assert_eq!(
+            diff_lines("a\nb\nc\n", "a\nc\nd\n"),
+            [Same("a"), Removed("b"), Same("c"), Added("d")]
+        );
+        assert_eq!(diff_lines("", "a\n"), [Added("a")]);
+        assert!(diff_lines("", "").is_empty());
+    }
+
+    #[test]
+    fn test_write_spec_diff() -> Result<()> {
+        let host: Host = serde_yaml::from_str(include_str!("fixtures/spec-staged-booted.yaml"))?;
+        let old = &host.spec;
+        let mut new = old.clone();
+        new.image.as_mut().unwrap().image = "quay.io/example/other:latest".into();
+        let mut w = Vec::new();
+        write_spec_diff(&mut w, old, &new)?;
+        let w = String::from_utf8(w)?;
+        let red = anstyle::AnsiColor::Red.render_fg().to_string();
+        assert!(
+            w.contains(&format!("{red}-   image: quay.io/example/someimage:latest")),
+            "{w}"
+        );
+        let stripped = anstream::adapter::strip_str(&w).to_string();
+        assert!(
+            stripped.contains("\n+   image: quay.io/example/other:latest\n"),
+            "{stripped}"
+        );
+        Ok(())
+    }
+}
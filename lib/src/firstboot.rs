@@ -0,0 +1,111 @@
+//! # Commands run once on the first boot of an installed system
+//!
+//! `bootc install --firstboot-command` writes executables into
+//! `/etc/bootc/firstboot.d`.  On boot, the generator enables
+//! `bootc-firstboot.service` if that directory is not empty, which runs
+//! each entry in lexicographic order, logs the result to the journal,
+//! and removes it so that it is never run again.
+
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+use cap_std_ext::cap_std::fs::Dir;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use fn_error_context::context;
+
+/// The directory holding the pending commands, relative to the root.
+pub(crate) const FIRSTBOOT_DIR: &str = "etc/bootc/firstboot.d";
+/// The unit which runs the pending commands.
+pub(crate) const FIRSTBOOT_UNIT: &str = "bootc-firstboot.service";
+/// Logged with the result of each command.
+pub(crate) const FIRSTBOOT_JOURNAL_ID: &str = "a7ea49f90d864c9f913d05e9eae159fb";
+
+/// Returns true if there are commands which have not been run yet.
+pub(crate) fn have_pending(root: &Dir) -> Result<bool> {
+    let Some(d) = root.open_dir_optional(FIRSTBOOT_DIR)? else {
+        return Ok(false);
+    };
+    Ok(d.entries()?.next().is_some())
+}
+
+/// Run and remove all pending commands in the given directory; an error is
+/// returned if any of them failed.
+#[context("Running first boot commands")]
+pub(crate) fn run(dir: &Utf8Path) -> Result<()> {
+    let d = Dir::open_ambient_dir(dir, cap_std_ext::cap_std::ambient_authority())?;
+    let mut failed = Vec::new();
+    for name in crate::utils::filenames_sorted(&d)? {
+        let path = dir.join(&name);
+        let r = Command::new(&path)
+            .status()
+            .with_context(|| format!("Executing {path}"));
+        // Whatever happened, this command is never run again
+        d.remove_file(&name)
+            .with_context(|| format!("Removing {path}"))?;
+        let (ok, msg) = match r {
+            Ok(st) if st.success() => (true, format!("First boot command {name} succeeded")),
+            Ok(st) => (false, format!("First boot command {name} failed: {st}")),
+            Err(e) => (false, format!("First boot command {name} failed: {e:#}")),
+        };
+        let priority = if ok {
+            libsystemd::logging::Priority::Info
+        } else {
+            libsystemd::logging::Priority::Error
+        };
+        println!("{msg}");
+        crate::journal::journal_send(
+            priority,
+            &msg,
+            [
+                ("MESSAGE_ID", FIRSTBOOT_JOURNAL_ID),
+                ("BOOTC_FIRSTBOOT_COMMAND", name.as_str()),
+            ]
+            .into_iter(),
+        );
+        if !ok {
+            failed.push(name);
+        }
+    }
+    if !failed.is_empty() {
+        anyhow::bail!("Failed: {}", failed.join(", "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use cap_std_ext::cap_std::fs::PermissionsExt;
+
+    use cap_std_ext::cap_std;
+
+    use super::*;
+
+    #[test]
+    fn test_run() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let tmp = Utf8Path::from_path(tmp.path()).unwrap();
+        let root = &Dir::open_ambient_dir(tmp, cap_std::ambient_authority())?;
+        assert!(!have_pending(root)?);
+        root.create_dir_all(FIRSTBOOT_DIR)?;
+        assert!(!have_pending(root)?);
+
+        let stamp = tmp.join("stamp");
+        for (name, contents) in [
+            ("10-ok", format!("#!/bin/sh\ntouch {stamp}\n")),
+            ("20-fail", "#!/bin/sh\nexit 1\n".to_owned()),
+        ] {
+            let path = format!("{FIRSTBOOT_DIR}/{name}");
+            root.write(&path, contents)?;
+            root.set_permissions(&path, cap_std::fs::Permissions::from_mode(0o755))?;
+        }
+        assert!(have_pending(root)?);
+
+        let e = run(&tmp.join(FIRSTBOOT_DIR)).unwrap_err();
+        assert!(format!("{e:#}").contains("Failed: 20-fail"));
+        assert!(stamp.exists());
+        // Everything is removed, even on failure
+        assert!(!have_pending(root)?);
+        Ok(())
+    }
+}
@@ -7,6 +7,10 @@ use fn_error_context::context;
 use rustix::{fd::AsFd, fs::StatVfsMountFlags};
 
 const EDIT_UNIT: &str = "bootc-fstab-edit.service";
+const UPDATE_TIMER: &str = "bootc-fetch-apply-updates.timer";
+const BOOT_COMPLETE_UNIT: &str = "bootc-boot-complete.service";
+const HEALTH_UNIT: &str = "bootc-health.service";
+const BOOT_LOG_UNIT: &str = "bootc-boot-log.service";
 const FSTAB_ANACONDA_STAMP: &str = "Created by anaconda";
 pub(crate) const BOOTC_EDITED_STAMP: &str = "Updated by bootc-fstab-edit.service";
 
@@ -46,8 +50,162 @@ pub(crate) fn fstab_generator_impl(root: &Dir, unit_dir: &Dir) -> Result<bool> {
     Ok(false)
 }
 
+/// Enable the unit running first boot commands if there are any pending.
+#[context("bootc firstboot generator")]
+pub(crate) fn firstboot_generator_impl(root: &Dir, unit_dir: &Dir) -> Result<bool> {
+    if !root.try_exists("run/ostree-booted")? || !crate::firstboot::have_pending(root)? {
+        return Ok(false);
+    }
+    let unit = crate::firstboot::FIRSTBOOT_UNIT;
+    let target = "multi-user.target.wants";
+    unit_dir.create_dir_all(target)?;
+    unit_dir.symlink(
+        &format!("/usr/lib/systemd/system/{unit}"),
+        &format!("{target}/{unit}"),
+    )?;
+    Ok(true)
+}
+
+/// Enable the unit restoring state kept by `bootc system-reinstall`; it is
+/// skipped unless there is a backup, which can't be checked here since `/var`
+/// may not be mounted yet.
+#[context("bootc reinstall restore generator")]
+pub(crate) fn reinstall_restore_generator_impl(root: &Dir, unit_dir: &Dir) -> Result<bool> {
+    if !root.try_exists("run/ostree-booted")? {
+        return Ok(false);
+    }
+    let unit = crate::reinstall::RESTORE_UNIT;
+    let target = "sysinit.target.wants";
+    unit_dir.create_dir_all(target)?;
+    unit_dir.symlink(
+        &format!("/usr/lib/systemd/system/{unit}"),
+        &format!("{target}/{unit}"),
+    )?;
+    Ok(true)
+}
+
+/// Enable the unit logging whether an update was booted.
+#[context("bootc boot log generator")]
+pub(crate) fn boot_log_generator_impl(root: &Dir, unit_dir: &Dir) -> Result<bool> {
+    if !root.try_exists("run/ostree-booted")? {
+        return Ok(false);
+    }
+    let unit = BOOT_LOG_UNIT;
+    let target = "multi-user.target.wants";
+    unit_dir.create_dir_all(target)?;
+    unit_dir.symlink(
+        &format!("/usr/lib/systemd/system/{unit}"),
+        &format!("{target}/{unit}"),
+    )?;
+    Ok(true)
+}
+
+/// Enable the unit marking boots as successful if boot counting is configured.
+#[context("bootc boot complete generator")]
+pub(crate) fn boot_complete_generator_impl(root: &Dir, unit_dir: &Dir) -> Result<bool> {
+    if !root.try_exists("run/ostree-booted")? {
+        return Ok(false);
+    }
+    if crate::config::load_config(root)?
+        .boot_counting_attempts()
+        .is_none()
+    {
+        return Ok(false);
+    }
+    let unit = BOOT_COMPLETE_UNIT;
+    let target = "multi-user.target.wants";
+    unit_dir.create_dir_all(target)?;
+    unit_dir.symlink(
+        &format!("/usr/lib/systemd/system/{unit}"),
+        &format!("{target}/{unit}"),
+    )?;
+    Ok(true)
+}
+
+/// Enable the health check unit if the image ships health checks; a failed
+/// check then also fails `boot-complete.target`.
+#[context("bootc health generator")]
+pub(crate) fn health_generator_impl(root: &Dir, unit_dir: &Dir) -> Result<bool> {
+    if !root.try_exists("run/ostree-booted")? {
+        return Ok(false);
+    }
+    if !root.try_exists(crate::health::HEALTH_DIR)? {
+        return Ok(false);
+    }
+    let unit = HEALTH_UNIT;
+    for target in ["multi-user.target.wants", "boot-complete.target.requires"] {
+        unit_dir.create_dir_all(target)?;
+        unit_dir.symlink(
+            &format!("/usr/lib/systemd/system/{unit}"),
+            &format!("{target}/{unit}"),
+        )?;
+    }
+    Ok(true)
+}
+
+/// Enable the unit synchronizing the ESP after finalization if the system was
+/// booted via a systemd-boot entry written by bootc.
+#[context("bootc systemd-boot sync generator")]
+pub(crate) fn systemd_boot_sync_generator_impl(root: &Dir, unit_dir: &Dir) -> Result<bool> {
+    if !root.try_exists("run/ostree-booted")? || crate::systemd_boot::booted_entry(root)?.is_none()
+    {
+        return Ok(false);
+    }
+    let unit = crate::systemd_boot::SYNC_UNIT;
+    let target = "ostree-finalize-staged.service.wants";
+    unit_dir.create_dir_all(target)?;
+    unit_dir.symlink(
+        &format!("/usr/lib/systemd/system/{unit}"),
+        &format!("{target}/{unit}"),
+    )?;
+    Ok(true)
+}
+
+/// Override the schedule of the automatic update timer with the maintenance
+/// window from the host configuration, if any.
+#[context("bootc update schedule generator")]
+pub(crate) fn update_schedule_generator_impl(root: &Dir, unit_dir: &Dir) -> Result<bool> {
+    if !root.try_exists("run/ostree-booted")? {
+        return Ok(false);
+    }
+    let config = crate::config::load_config(root)?;
+    let Some(schedule) = config.update_schedule() else {
+        return Ok(false);
+    };
+    if schedule.trim().is_empty() || schedule.contains('\n') {
+        anyhow::bail!("Invalid update schedule: {schedule:?}");
+    }
+    let dropin_dir = format!("{UPDATE_TIMER}.d");
+    unit_dir.create_dir_all(&dropin_dir)?;
+    unit_dir.atomic_write(
+        format!("{dropin_dir}/50-bootc-schedule.conf"),
+        format!(
+            "# Generated from [updates] in /usr/lib/bootc/config.toml\n\
+[Timer]\n\
+OnBootSec=\n\
+OnUnitInactiveSec=\n\
+OnCalendar={schedule}\n"
+        ),
+    )?;
+    Ok(true)
+}
+
 /// Main entrypoint for the generator
 pub(crate) fn generator(root: &Dir, unit_dir: &Dir) -> Result<()> {
+    let firstboot = firstboot_generator_impl(root, unit_dir)?;
+    tracing::trace!("Generated firstboot: {firstboot}");
+    let schedule = update_schedule_generator_impl(root, unit_dir)?;
+    tracing::trace!("Generated update schedule: {schedule}");
+    let restore = reinstall_restore_generator_impl(root, unit_dir)?;
+    tracing::trace!("Generated reinstall restore: {restore}");
+    let boot_log = boot_log_generator_impl(root, unit_dir)?;
+    tracing::trace!("Generated boot log: {boot_log}");
+    let boot_complete = boot_complete_generator_impl(root, unit_dir)?;
+    tracing::trace!("Generated boot complete: {boot_complete}");
+    let health = health_generator_impl(root, unit_dir)?;
+    tracing::trace!("Generated health: {health}");
+    let systemd_boot_sync = systemd_boot_sync_generator_impl(root, unit_dir)?;
+    tracing::trace!("Generated systemd-boot sync: {systemd_boot_sync}");
     // Right now we only do something if the root is a read-only overlayfs (a composefs really)
     let st = rustix::fs::fstatfs(root.as_fd())?;
     if st.f_type != libc::OVERLAYFS_SUPER_MAGIC {
@@ -127,6 +285,33 @@ fn test_generator_fstab() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_generator_update_schedule() -> Result<()> {
+    let tempdir = fixture()?;
+    let unit_dir = &tempdir.open_dir("run/systemd/system")?;
+    tempdir.atomic_write("run/ostree-booted", "ostree booted")?;
+    // No configuration
+    assert!(!update_schedule_generator_impl(&tempdir, unit_dir)?);
+    assert_eq!(unit_dir.entries()?.count(), 0);
+
+    tempdir.create_dir_all("usr/lib/bootc")?;
+    tempdir.atomic_write(
+        "usr/lib/bootc/config.toml",
+        "[updates]\nschedule = \"Sat *-*-* 03:00\"\n",
+    )?;
+    assert!(update_schedule_generator_impl(&tempdir, unit_dir)?);
+    let dropin = unit_dir.read_to_string(format!("{UPDATE_TIMER}.d/50-bootc-schedule.conf"))?;
+    assert!(dropin.contains("\nOnCalendar=Sat *-*-* 03:00\n"));
+    assert!(dropin.contains("\nOnUnitInactiveSec=\n"));
+
+    tempdir.atomic_write(
+        "usr/lib/bootc/config.toml",
+        "[updates]\nschedule = \"daily\\n[Service]\"\n",
+    )?;
+    assert!(update_schedule_generator_impl(&tempdir, unit_dir).is_err());
+    Ok(())
+}
+
 #[test]
 fn test_generator_fstab_idempotent() -> Result<()> {
     let anaconda_fstab = indoc::indoc! { "
@@ -155,3 +340,109 @@ UUID=341c4712-54e8-4839-8020-d94073b1dc8b /boot                   xfs     defaul
 
     Ok(())
 }
+
+#[test]
+fn test_generator_firstboot() -> Result<()> {
+    let tempdir = fixture()?;
+    let unit_dir = &tempdir.open_dir("run/systemd/system")?;
+    tempdir.create_dir_all(crate::firstboot::FIRSTBOOT_DIR)?;
+    tempdir.atomic_write("run/ostree-booted", "ostree booted")?;
+    // Nothing pending
+    assert!(!firstboot_generator_impl(&tempdir, unit_dir)?);
+    assert_eq!(unit_dir.entries()?.count(), 0);
+
+    tempdir.atomic_write(
+        format!("{}/10-install", crate::firstboot::FIRSTBOOT_DIR),
+        "#!/bin/sh\ntrue\n",
+    )?;
+    assert!(firstboot_generator_impl(&tempdir, unit_dir)?);
+    assert!(unit_dir.try_exists("multi-user.target.wants/bootc-firstboot.service")?);
+    Ok(())
+}
+
+#[test]
+fn test_generator_reinstall_restore() -> Result<()> {
+    let tempdir = fixture()?;
+    let unit_dir = &tempdir.open_dir("run/systemd/system")?;
+    // Not booted via ostree
+    assert!(!reinstall_restore_generator_impl(&tempdir, unit_dir)?);
+    assert_eq!(unit_dir.entries()?.count(), 0);
+
+    tempdir.atomic_write("run/ostree-booted", "ostree booted")?;
+    assert!(reinstall_restore_generator_impl(&tempdir, unit_dir)?);
+    assert!(unit_dir.try_exists("sysinit.target.wants/bootc-reinstall-restore.service")?);
+    Ok(())
+}
+
+#[test]
+fn test_generator_boot_log() -> Result<()> {
+    let tempdir = fixture()?;
+    let unit_dir = &tempdir.open_dir("run/systemd/system")?;
+    // Not booted via ostree
+    assert!(!boot_log_generator_impl(&tempdir, unit_dir)?);
+    assert_eq!(unit_dir.entries()?.count(), 0);
+
+    tempdir.atomic_write("run/ostree-booted", "ostree booted")?;
+    assert!(boot_log_generator_impl(&tempdir, unit_dir)?);
+    assert!(unit_dir.try_exists("multi-user.target.wants/bootc-boot-log.service")?);
+    Ok(())
+}
+
+#[test]
+fn test_generator_boot_complete() -> Result<()> {
+    let tempdir = fixture()?;
+    let unit_dir = &tempdir.open_dir("run/systemd/system")?;
+    tempdir.atomic_write("run/ostree-booted", "ostree booted")?;
+    // Boot counting is not configured
+    assert!(!boot_complete_generator_impl(&tempdir, unit_dir)?);
+    assert_eq!(unit_dir.entries()?.count(), 0);
+
+    tempdir.create_dir_all("usr/lib/bootc")?;
+    tempdir.atomic_write(
+        "usr/lib/bootc/config.toml",
+        "[boot-counting]\nattempts = 3\n",
+    )?;
+    assert!(boot_complete_generator_impl(&tempdir, unit_dir)?);
+    assert!(unit_dir.try_exists("multi-user.target.wants/bootc-boot-complete.service")?);
+    Ok(())
+}
+
+#[test]
+fn test_generator_health() -> Result<()> {
+    let tempdir = fixture()?;
+    let unit_dir = &tempdir.open_dir("run/systemd/system")?;
+    tempdir.atomic_write("run/ostree-booted", "ostree booted")?;
+    // No health checks
+    assert!(!health_generator_impl(&tempdir, unit_dir)?);
+    assert_eq!(unit_dir.entries()?.count(), 0);
+
+    tempdir.create_dir_all(crate::health::HEALTH_DIR)?;
+    assert!(health_generator_impl(&tempdir, unit_dir)?);
+    assert!(unit_dir.try_exists("multi-user.target.wants/bootc-health.service")?);
+    assert!(unit_dir.try_exists("boot-complete.target.requires/bootc-health.service")?);
+    Ok(())
+}
+
+#[test]
+fn test_generator_systemd_boot_sync() -> Result<()> {
+    let tempdir = fixture()?;
+    let unit_dir = &tempdir.open_dir("run/systemd/system")?;
+    tempdir.atomic_write("run/ostree-booted", "ostree booted")?;
+    // Not booted via systemd-boot
+    assert!(!systemd_boot_sync_generator_impl(&tempdir, unit_dir)?);
+    assert_eq!(unit_dir.entries()?.count(), 0);
+
+    let mut value = vec![6, 0, 0, 0];
+    value.extend(
+        "bootc-default-abc.0.conf"
+            .encode_utf16()
+            .chain([0])
+            .flat_map(|c| c.to_le_bytes()),
+    );
+    tempdir.create_dir_all("sys/firmware/efi/efivars")?;
+    tempdir.atomic_write(crate::systemd_boot::LOADER_ENTRY_SELECTED, value)?;
+    assert!(systemd_boot_sync_generator_impl(&tempdir, unit_dir)?);
+    assert!(unit_dir
+        .try_exists("ostree-finalize-staged.service.wants/bootc-systemd-boot-sync.service")?);
+    Ok(())
+}
@@ -0,0 +1,228 @@
+//! # Update graphs
+//!
+//! If `[update-graph]` is configured in `/usr/lib/bootc/config.toml`, `bootc
+//! upgrade` does not simply follow the tag of the tracked image.  Instead, it
+//! queries an update graph in the JSON format of
+//! [Cincinnati](https://github.com/openshift/cincinnati) (as used by Zincati
+//! for Fedora CoreOS), passing the channel, booted version and architecture as
+//! query parameters, and fetches the most recent release which has an edge
+//! from the booted one.  Releases which are only reachable via a barrier
+//! release hence require updating to the barrier first; phased rollouts are
+//! implemented by the server only adding edges to a fraction of the queries.
+//!
+//! The payload of each release must be a digested reference to the tracked
+//! image repository, e.g. `quay.io/example/os@sha256:...`; the release is
+//! fetched as with `bootc upgrade --to-digest`.
+
+use std::cmp::Ordering;
+
+use anyhow::{anyhow, Context, Result};
+use fn_error_context::context;
+use serde::Deserialize;
+
+use crate::spec::{ImageReference, ImageStatus};
+
+/// A release in the update graph.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+struct Node {
+    /// The version of the release
+    version: String,
+    /// The digested image reference of the release
+    payload: String,
+}
+
+/// The serialized update graph.
+#[derive(Debug, Deserialize)]
+struct Graph {
+    nodes: Vec<Node>,
+    /// Pairs of indices into `nodes`, from a release to a permitted update
+    edges: Vec<(usize, usize)>,
+}
+
+/// The manifest digest of a digested image reference.
+fn payload_digest(payload: &str) -> Option<&str> {
+    payload.split_once('@').map(|(_, digest)| digest)
+}
+
+/// Compare versions: numeric components are compared as numbers, and others
+/// as strings.
+fn version_cmp(a: &str, b: &str) -> Ordering {
+    let split = |v: &str| -> Vec<String> {
+        v.split(|c: char| !c.is_ascii_alphanumeric())
+            .map(ToOwned::to_owned)
+            .collect()
+    };
+    let (a, b) = (split(a), split(b));
+    for (a, b) in a.iter().zip(b.iter()) {
+        let r = match (a.parse::<u64>(), b.parse::<u64>()) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            _ => a.cmp(b),
+        };
+        if r != Ordering::Equal {
+            return r;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+impl Graph {
+    /// The release to update to from the booted image: the most recent one
+    /// with an edge from it, or the booted release itself if there is none.
+    fn target(&self, booted: &ImageStatus) -> Result<&Node> {
+        let current = self
+            .nodes
+            .iter()
+            .position(|n| payload_digest(&n.payload) == Some(booted.image_digest.as_str()))
+            .or_else(|| {
+                let version = booted.version.as_deref()?;
+                self.nodes.iter().position(|n| n.version == version)
+            })
+            .ok_or_else(|| anyhow!("The booted image is not part of the update graph"))?;
+        let next = self
+            .edges
+            .iter()
+            .filter(|(from, _)| *from == current)
+            .map(|(_, to)| {
+                self.nodes
+                    .get(*to)
+                    .ok_or_else(|| anyhow!("Invalid edge to node {to}"))
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .max_by(|a, b| version_cmp(&a.version, &b.version));
+        Ok(next.unwrap_or(&self.nodes[current]))
+    }
+}
+
+/// Query the update graph.
+#[context("Querying update graph {url}")]
+async fn fetch(
+    config: &crate::config::HostConfiguration,
+    url: &str,
+    channel: Option<&str>,
+    version: Option<&str>,
+) -> Result<Graph> {
+    let proxies = crate::deploy::Proxies::from_config(config)?;
+    let mut cmd = tokio::process::Command::new("curl");
+    cmd.envs(proxies.environment());
+    cmd.args(["--fail", "--silent", "--show-error", "--location", "--get"]);
+    cmd.args(["--header", "Accept: application/json"]);
+    let params = [
+        ("channel", channel),
+        ("version", version),
+        ("basearch", Some(std::env::consts::ARCH)),
+    ];
+    for (k, v) in params {
+        if let Some(v) = v {
+            cmd.arg("--data-urlencode").arg(format!("{k}={v}"));
+        }
+    }
+    cmd.arg(url);
+    cmd.stdin(std::process::Stdio::null());
+    let o = cmd.output().await?;
+    if !o.status.success() {
+        anyhow::bail!("{}", String::from_utf8_lossy(&o.stderr).trim());
+    }
+    serde_json::from_slice(&o.stdout).context("Parsing update graph")
+}
+
+/// If an update graph is configured, the manifest digest of the release to
+/// fetch for the tracked image.
+pub(crate) async fn target_digest(
+    config: &crate::config::HostConfiguration,
+    imgref: &ImageReference,
+    booted: Option<&ImageStatus>,
+) -> Result<Option<String>> {
+    let Some(graph_config) = config.update_graph() else {
+        return Ok(None);
+    };
+    let booted = booted.ok_or_else(|| anyhow!("Update graphs require a booted image"))?;
+    let graph = fetch(
+        config,
+        &graph_config.url,
+        graph_config.channel.as_deref(),
+        booted.version.as_deref(),
+    )
+    .await?;
+    let target = graph.target(booted)?;
+    let repository = crate::deploy::image_repository(&imgref.image);
+    match target.payload.split_once('@') {
+        Some((payload_repo, digest)) if payload_repo == repository => {
+            println!("Update graph target: {} ({})", target.version, digest);
+            Ok(Some(digest.to_owned()))
+        }
+        _ => anyhow::bail!(
+            "Release {} in the update graph is not a digested reference to {repository}: {}",
+            target.version,
+            target.payload
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST_A: &str =
+        "sha256:aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    const DIGEST_B: &str =
+        "sha256:bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+
+    #[test]
+    fn test_version_cmp() {
+        for (a, b, expected) in [
+            ("41.20241010.1", "41.20241010.1", Ordering::Equal),
+            ("41.9", "41.10", Ordering::Less),
+            ("42.1", "41.10", Ordering::Greater),
+            ("41", "41.1", Ordering::Less),
+            ("1.0-beta", "1.0-rc", Ordering::Less),
+        ] {
+            assert_eq!(version_cmp(a, b), expected, "{a} {b}");
+        }
+    }
+
+    #[test]
+    fn test_target() -> Result<()> {
+        // 1 -> 2 (barrier) -> {3, 10}; 1 can only update to the barrier
+        let graph: Graph = serde_json::from_value(serde_json::json!({
+            "nodes": [
+                {"version": "1", "payload": format!("quay.io/example/os@{DIGEST_A}")},
+                {"version": "2", "payload": format!("quay.io/example/os@{DIGEST_B}"), "metadata": {}},
+                {"version": "10", "payload": "quay.io/example/os@sha256:10"},
+                {"version": "3", "payload": "quay.io/example/os@sha256:3"},
+            ],
+            "edges": [[0, 1], [1, 3], [1, 2]]
+        }))?;
+        let booted = |version: Option<&str>, digest: &str| ImageStatus {
+            image: ImageReference {
+                image: "quay.io/example/os:latest".into(),
+                transport: "registry".into(),
+                signature: None,
+            },
+            version: version.map(ToOwned::to_owned),
+            timestamp: None,
+            image_digest: digest.into(),
+            labels: None,
+            platform: None,
+            fetched_from: None,
+        };
+        assert_eq!(graph.target(&booted(None, DIGEST_A))?.version, "2");
+        assert_eq!(
+            graph.target(&booted(Some("2"), "sha256:other"))?.version,
+            "10"
+        );
+        // No edges from the latest release
+        assert_eq!(
+            graph.target(&booted(None, "sha256:10"))?.payload,
+            "quay.io/example/os@sha256:10"
+        );
+        assert!(graph.target(&booted(Some("0"), "sha256:other")).is_err());
+
+        let invalid: Graph = serde_json::from_value(serde_json::json!({
+            "nodes": [{"version": "1", "payload": format!("quay.io/example/os@{DIGEST_A}")}],
+            "edges": [[0, 5]]
+        }))?;
+        assert!(invalid.target(&booted(None, DIGEST_A)).is_err());
+        Ok(())
+    }
+}
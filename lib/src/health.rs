@@ -0,0 +1,209 @@
+//! # Health checks
+//!
+//! `bootc health run`, run by `bootc-health.service` after each boot, runs
+//! the executables in `/usr/lib/bootc/health.d` in lexicographic order.  The
+//! result is recorded for the booted deployment under `/var/lib/bootc` and
+//! shown by `bootc status`.  If a check fails, the actions configured via
+//! `on-failure` in the `[health]` section of `/usr/lib/bootc/config.toml` are
+//! taken: by default, a journal message is logged; `hold` holds updates (see
+//! [`crate::hold`]), and `rollback` queues the rollback deployment and reboots
+//! into it, unless its own health checks failed before.
+//!
+//! `bootc-health.service` is ordered before `boot-complete.target`, so with
+//! boot counting (see [`crate::bootcount`]), a failed check also keeps the
+//! boot from being marked as successful.
+
+use std::collections::BTreeMap;
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+use cap_std_ext::cap_std;
+use cap_std_ext::cap_std::fs::Dir;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use chrono::Utc;
+use fn_error_context::context;
+
+use crate::config::HealthAction;
+use crate::spec::HealthStatus;
+use crate::store::Storage;
+
+/// The directory holding the health checks, relative to the root.
+pub(crate) const HEALTH_DIR: &str = "usr/lib/bootc/health.d";
+/// The directory holding the results, relative to the root.
+const STATE_DIR: &str = "var/lib/bootc";
+/// The results by deployment checksum, relative to [`STATE_DIR`].
+const STATE_FILE: &str = "health.json";
+/// Logged when a health check failed.
+pub(crate) const HEALTH_FAILED_JOURNAL_ID: &str = "3e9a7c1d5b2f4a8e9c6d0b1f7a4e2c5d";
+
+/// Run the health checks below `root`, returning the names of those which
+/// failed.
+#[context("Running health checks")]
+fn run_checks(root: &Utf8Path) -> Result<Vec<String>> {
+    let mut failed = Vec::new();
+    crate::hooks::run_parts(&root.join(HEALTH_DIR), |name, path| {
+        match Command::new(&path).stdin(Stdio::null()).status() {
+            Ok(status) if status.success() => println!("{name}: passed"),
+            Ok(status) => {
+                println!("{name}: failed: {status}");
+                failed.push(name);
+            }
+            Err(e) => {
+                println!("{name}: failed to execute: {e}");
+                failed.push(name);
+            }
+        }
+        Ok(())
+    })?;
+    Ok(failed)
+}
+
+/// Load the recorded results, by deployment checksum.
+#[context("Loading health check results")]
+pub(crate) fn load(root: &Dir) -> Result<BTreeMap<String, HealthStatus>> {
+    let Some(d) = root.open_dir_optional(STATE_DIR)? else {
+        return Ok(BTreeMap::new());
+    };
+    let Some(f) = d.open_optional(STATE_FILE)? else {
+        return Ok(BTreeMap::new());
+    };
+    serde_json::from_reader(std::io::BufReader::new(f))
+        .with_context(|| format!("Parsing /{STATE_DIR}/{STATE_FILE}"))
+}
+
+/// Record the result for the given deployment checksum; results for
+/// checksums not in `retain` are dropped.
+#[context("Recording health check results")]
+fn record(root: &Dir, checksum: &str, status: HealthStatus, retain: &[String]) -> Result<()> {
+    let mut results = load(root)?;
+    results.retain(|k, _| retain.contains(k));
+    results.insert(checksum.to_owned(), status);
+    root.create_dir_all(STATE_DIR)?;
+    let buf = serde_json::to_vec(&results)?;
+    root.open_dir(STATE_DIR)?.atomic_write(STATE_FILE, buf)?;
+    Ok(())
+}
+
+/// Queue the rollback deployment and reboot into it, unless there is none
+/// or its health checks failed too.
+async fn rollback(sysroot: &Storage, results: &BTreeMap<String, HealthStatus>) -> Result<()> {
+    let (_booted, deployments, _host) = crate::status::get_status_require_booted(sysroot)?;
+    let Some(rollback) = deployments.rollback else {
+        println!("No rollback deployment");
+        return Ok(());
+    };
+    let unhealthy = results
+        .get(rollback.csum().as_str())
+        .is_some_and(|r| !r.failed.is_empty());
+    if unhealthy {
+        println!("Not rolling back, as the health checks of the rollback deployment failed too");
+        return Ok(());
+    }
+    let index = rollback.index().try_into()?;
+    crate::deploy::rollback_to(sysroot, &crate::deploy::RollbackTarget::Index(index)).await?;
+    crate::reboot::reboot(None)
+}
+
+/// Implementation of `bootc health run`: run the health checks, record the
+/// result, and take the configured actions if a check failed.
+#[context("Checking health")]
+pub(crate) async fn run(sysroot: &Storage) -> Result<()> {
+    let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    let config = crate::config::load_config(root)?;
+    let booted = sysroot.require_booted_deployment()?;
+    let checksum = booted.csum();
+
+    let failed = run_checks(Utf8Path::new("/"))?;
+    let retain = sysroot
+        .deployments()
+        .iter()
+        .map(|d| d.csum().to_string())
+        .collect::<Vec<_>>();
+    let status = HealthStatus {
+        checked: Utc::now(),
+        failed,
+    };
+    record(root, &checksum, status.clone(), &retain)?;
+    if status.failed.is_empty() {
+        println!("All health checks passed");
+        return Ok(());
+    }
+
+    let msg = format!("Health checks failed: {}", status.failed.join(", "));
+    let actions = config.health_on_failure();
+    if actions.contains(&HealthAction::Journal) {
+        libsystemd::logging::journal_send(
+            libsystemd::logging::Priority::Warning,
+            &msg,
+            [
+                ("MESSAGE_ID", HEALTH_FAILED_JOURNAL_ID),
+                ("BOOTC_OSTREE_COMMIT", checksum.as_str()),
+            ]
+            .into_iter(),
+        )?;
+    }
+    if actions.contains(&HealthAction::Hold) {
+        let hold = crate::hold::hold(root, Some(&msg))?;
+        println!("{hold}");
+    }
+    if actions.contains(&HealthAction::Rollback) {
+        rollback(sysroot, &load(root)?).await?;
+    }
+    anyhow::bail!("{msg}")
+}
+
+#[cfg(test)]
+mod tests {
+    use cap_std_ext::cap_std::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn test_run_checks() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let tmp = Utf8Path::from_path(tmp.path()).unwrap();
+        let root = &Dir::open_ambient_dir(tmp, cap_std::ambient_authority())?;
+        // No checks at all
+        assert!(run_checks(tmp)?.is_empty());
+
+        root.create_dir_all(HEALTH_DIR)?;
+        for (name, contents, mode) in [
+            ("10-pass", "#!/bin/sh\nexit 0\n", 0o755),
+            ("20-fail", "#!/bin/sh\nexit 1\n", 0o755),
+            ("30-ignored", "#!/bin/sh\nexit 1\n", 0o644),
+            ("40-fail", "#!/bin/sh\nexit 2\n", 0o755),
+        ] {
+            let path = format!("{HEALTH_DIR}/{name}");
+            root.write(&path, contents)?;
+            root.set_permissions(&path, cap_std::fs::Permissions::from_mode(mode))?;
+        }
+        assert_eq!(run_checks(tmp)?, ["20-fail", "40-fail"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_record() -> Result<()> {
+        let td = cap_std_ext::cap_tempfile::TempDir::new(cap_std::ambient_authority())?;
+        assert!(load(&td)?.is_empty());
+        let checked = chrono::DateTime::from_timestamp(1697311335, 0).unwrap();
+        let healthy = HealthStatus {
+            checked,
+            failed: Vec::new(),
+        };
+        let unhealthy = HealthStatus {
+            checked,
+            failed: vec!["20-fail".into()],
+        };
+        let retain = ["a".to_owned(), "b".to_owned()];
+        record(&td, "a", healthy.clone(), &retain)?;
+        record(&td, "b", unhealthy.clone(), &retain)?;
+        let results = load(&td)?;
+        assert_eq!(results.get("a"), Some(&healthy));
+        assert_eq!(results.get("b"), Some(&unhealthy));
+        // Results of deployments which no longer exist are dropped
+        record(&td, "c", healthy.clone(), &["c".to_owned()])?;
+        assert_eq!(load(&td)?.into_keys().collect::<Vec<_>>(), ["c"]);
+        Ok(())
+    }
+}
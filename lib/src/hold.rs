@@ -0,0 +1,111 @@
+//! # Holding updates
+//!
+//! `bootc update hold` writes a marker under `/var/lib/bootc` which makes
+//! `bootc upgrade` refuse to fetch updates, and which skips
+//! `bootc-fetch-apply-updates.service` entirely, until it is removed with
+//! `bootc update unhold`.  Unlike masking the systemd units, this also covers
+//! interactive and agent-driven upgrades, and persists across reboots.
+
+use anyhow::{Context, Result};
+use cap_std_ext::cap_std::fs::Dir;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use chrono::{DateTime, Utc};
+use fn_error_context::context;
+use serde::{Deserialize, Serialize};
+
+/// The directory holding the marker, relative to the root.
+const HOLD_DIR: &str = "var/lib/bootc";
+/// The marker file, relative to [`HOLD_DIR`]; this path is also referenced
+/// by `bootc-fetch-apply-updates.service`.
+const HOLD_FILE: &str = "update-hold";
+
+/// An active hold on updates.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UpdateHold {
+    /// When the hold was placed
+    pub(crate) since: DateTime<Utc>,
+    /// Why updates are held
+    pub(crate) reason: Option<String>,
+}
+
+impl std::fmt::Display for UpdateHold {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Updates are held since {}", self.since)?;
+        if let Some(reason) = self.reason.as_deref() {
+            write!(f, ": {reason}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Load the active hold, if any.
+#[context("Loading update hold")]
+pub(crate) fn load(root: &Dir) -> Result<Option<UpdateHold>> {
+    let Some(d) = root.open_dir_optional(HOLD_DIR)? else {
+        return Ok(None);
+    };
+    let Some(f) = d.open_optional(HOLD_FILE)? else {
+        return Ok(None);
+    };
+    let r = serde_yaml::from_reader(std::io::BufReader::new(f))
+        .with_context(|| format!("Parsing /{HOLD_DIR}/{HOLD_FILE}"))?;
+    Ok(Some(r))
+}
+
+/// Hold updates, replacing any previous hold.
+#[context("Holding updates")]
+pub(crate) fn hold(root: &Dir, reason: Option<&str>) -> Result<UpdateHold> {
+    let hold = UpdateHold {
+        since: Utc::now(),
+        reason: reason.map(ToOwned::to_owned),
+    };
+    root.create_dir_all(HOLD_DIR)?;
+    let buf = serde_yaml::to_string(&hold)?;
+    root.open_dir(HOLD_DIR)?.atomic_write(HOLD_FILE, buf)?;
+    Ok(hold)
+}
+
+/// Remove the hold, returning it if there was one.
+#[context("Removing update hold")]
+pub(crate) fn unhold(root: &Dir) -> Result<Option<UpdateHold>> {
+    let r = load(root)?;
+    if r.is_some() {
+        root.open_dir(HOLD_DIR)?.remove_file(HOLD_FILE)?;
+    }
+    Ok(r)
+}
+
+/// Return an error if updates are held.
+pub(crate) fn check(root: &Dir) -> Result<()> {
+    if let Some(hold) = load(root)? {
+        anyhow::bail!("{hold}; use `bootc update unhold` to allow updates again");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use cap_std_ext::cap_std;
+
+    use super::*;
+
+    #[test]
+    fn test_hold() -> Result<()> {
+        let td = cap_std_ext::cap_tempfile::TempDir::new(cap_std::ambient_authority())?;
+        assert!(load(&td)?.is_none());
+        assert!(unhold(&td)?.is_none());
+        check(&td)?;
+
+        let h = hold(&td, Some("incident 1234"))?;
+        assert_eq!(load(&td)?.as_ref(), Some(&h));
+        let e = check(&td).unwrap_err().to_string();
+        assert!(e.contains("incident 1234"), "{e}");
+        assert!(e.contains("bootc update unhold"), "{e}");
+
+        assert_eq!(unhold(&td)?, Some(h));
+        assert!(load(&td)?.is_none());
+        check(&td)?;
+        Ok(())
+    }
+}
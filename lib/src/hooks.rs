@@ -0,0 +1,196 @@
+//! # Hooks run around updates
+//!
+//! `bootc upgrade` and `bootc switch` run the executables in
+//! `/usr/lib/bootc/hooks/pre-upgrade.d` before fetching the image,
+//! `/usr/lib/bootc/hooks/post-stage.d` after staging it, and
+//! `/usr/lib/bootc/hooks/pre-reboot.d` before rebooting into it (with
+//! `--apply`).  Each directory is run in lexicographic order, passing a JSON
+//! description of the update on standard input.  If a hook exits
+//! unsuccessfully, the operation is aborted; for `post-stage`, this also
+//! removes the staged deployment.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use cap_std_ext::cap_std;
+use cap_std_ext::cap_std::fs::{Dir, PermissionsExt};
+use fn_error_context::context;
+use ostree_ext::{gio, ostree};
+use serde::Serialize;
+
+use crate::spec::{ImageReference, ImageStatus};
+use crate::store::Storage;
+
+/// The directory holding the hooks, relative to the root.
+const HOOKS_DIR: &str = "usr/lib/bootc/hooks";
+
+/// A point in an update at which hooks are run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Hook {
+    /// Before fetching the image
+    PreUpgrade,
+    /// After staging the deployment
+    PostStage,
+    /// Before rebooting into the staged deployment
+    PreReboot,
+}
+
+impl Hook {
+    /// The directory holding hooks of this kind, relative to [`HOOKS_DIR`].
+    fn dirname(&self) -> &'static str {
+        match self {
+            Hook::PreUpgrade => "pre-upgrade.d",
+            Hook::PostStage => "post-stage.d",
+            Hook::PreReboot => "pre-reboot.d",
+        }
+    }
+}
+
+/// The description of the update passed to hooks.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Update {
+    /// The bootc command, e.g. `upgrade`
+    pub(crate) operation: &'static str,
+    /// The booted image
+    pub(crate) booted: Option<ImageStatus>,
+    /// The image being updated to
+    pub(crate) target: ImageReference,
+    /// The manifest digest of the target, once fetched
+    pub(crate) target_digest: Option<String>,
+}
+
+/// The input of a hook.
+#[derive(Serialize)]
+struct HookInput<'a> {
+    hook: Hook,
+    #[serde(flatten)]
+    update: &'a Update,
+}
+
+/// Like run-parts, call `f` with the name and path of each executable file
+/// in `dir` in order of their names; anything else is skipped, as is a
+/// missing `dir`.  Iteration stops at the first error returned by `f`.
+pub(crate) fn run_parts(
+    dir: &Utf8Path,
+    mut f: impl FnMut(String, Utf8PathBuf) -> Result<()>,
+) -> Result<()> {
+    let d = match Dir::open_ambient_dir(dir, cap_std::ambient_authority()) {
+        Ok(d) => d,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("Opening {dir}")),
+    };
+    for name in crate::utils::filenames_sorted(&d)? {
+        let meta = d.metadata(&name)?;
+        if !meta.is_file() || meta.permissions().mode() & 0o111 == 0 {
+            tracing::debug!("Skipping {name}");
+            continue;
+        }
+        let path = dir.join(&name);
+        f(name, path)?;
+    }
+    Ok(())
+}
+
+/// Run the hooks of the given kind below `root`; an error is returned for
+/// the first one which fails, and the remaining ones are not run.
+#[context("Running {} hooks", hook.dirname())]
+fn run_in(root: &Utf8Path, hook: Hook, update: &Update) -> Result<()> {
+    let dir = root.join(HOOKS_DIR).join(hook.dirname());
+    let input = serde_json::to_vec(&HookInput { hook, update })?;
+    run_parts(&dir, |_, path| {
+        let mut child = Command::new(&path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Executing {path}"))?;
+        let mut stdin = child.stdin.take().unwrap();
+        // A hook may legitimately exit without reading its input
+        let _ = stdin.write_all(&input);
+        drop(stdin);
+        let status = child.wait()?;
+        if !status.success() {
+            anyhow::bail!("Hook {path} failed: {status}");
+        }
+        Ok(())
+    })
+}
+
+/// Run the hooks of the given kind.
+pub(crate) fn run(hook: Hook, update: &Update) -> Result<()> {
+    run_in(Utf8Path::new("/"), hook, update)
+}
+
+/// Run the `post-stage` hooks; if one fails, the staged deployment is removed.
+pub(crate) fn run_post_stage(sysroot: &Storage, update: &Update) -> Result<()> {
+    let Err(e) = run(Hook::PostStage, update) else {
+        return Ok(());
+    };
+    let cancellable = gio::Cancellable::NONE;
+    sysroot.load(cancellable)?;
+    let deployments = sysroot
+        .deployments()
+        .into_iter()
+        .filter(|d| !d.is_staged())
+        .collect::<Vec<ostree::Deployment>>();
+    sysroot
+        .write_deployments(&deployments, cancellable)
+        .context("Removing staged deployment")?;
+    crate::status::update_prompt_cache(false, false);
+    println!("Removed staged deployment");
+    Err(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let tmp = Utf8Path::from_path(tmp.path()).unwrap();
+        let root = &Dir::open_ambient_dir(tmp, cap_std::ambient_authority())?;
+        let update = Update {
+            operation: "upgrade",
+            booted: None,
+            target: ImageReference {
+                image: "quay.io/example/os:latest".into(),
+                transport: "registry".into(),
+                signature: None,
+            },
+            target_digest: None,
+        };
+        // No hooks at all
+        run_in(tmp, Hook::PreUpgrade, &update)?;
+
+        let dir = format!("{HOOKS_DIR}/{}", Hook::PreUpgrade.dirname());
+        root.create_dir_all(&dir)?;
+        let input = tmp.join("input.json");
+        for (name, contents, mode) in [
+            ("10-save", format!("#!/bin/sh\ncat > {input}\n"), 0o755),
+            ("20-ignored", "#!/bin/sh\nexit 1\n".to_owned(), 0o644),
+        ] {
+            let path = format!("{dir}/{name}");
+            root.write(&path, contents)?;
+            root.set_permissions(&path, cap_std::fs::Permissions::from_mode(mode))?;
+        }
+        run_in(tmp, Hook::PreUpgrade, &update)?;
+        let v: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&input)?)?;
+        assert_eq!(v["hook"], "pre-upgrade");
+        assert_eq!(v["operation"], "upgrade");
+        assert_eq!(v["target"]["image"], "quay.io/example/os:latest");
+        // Hooks of other kinds are not run
+        std::fs::remove_file(&input)?;
+        run_in(tmp, Hook::PreReboot, &update)?;
+        assert!(!input.exists());
+
+        let path = format!("{dir}/30-fail");
+        root.write(&path, "#!/bin/sh\nexit 1\n")?;
+        root.set_permissions(&path, cap_std::fs::Permissions::from_mode(0o755))?;
+        let e = run_in(tmp, Hook::PreUpgrade, &update).unwrap_err();
+        assert!(format!("{e:#}").contains("30-fail failed"));
+        Ok(())
+    }
+}
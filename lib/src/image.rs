@@ -2,35 +2,120 @@
 //!
 //! APIs for operating on container images in the bootc storage.
 
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::num::NonZeroU32;
+
 use anyhow::{Context, Result};
 use bootc_utils::CommandRunExt;
+use camino::Utf8Path;
 use fn_error_context::context;
-use ostree_ext::container::{ImageReference, Transport};
+use ostree_ext::container::store::PrepareResult;
+use ostree_ext::container::{encapsulate, Config, ExportOpts};
+use ostree_ext::container::{ImageReference, OstreeImageReference, SignatureSource, Transport};
+use ostree_ext::ostree::{self, gio, glib};
+use serde::Serialize;
 
 use crate::imgstorage::Storage;
 
 /// The name of the image we push to containers-storage if nothing is specified.
 const IMAGE_DEFAULT: &str = "localhost/bootc";
 
+/// An image in the bootc storage.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct HostImage {
+    /// The image reference
+    pub(crate) image: String,
+    /// The manifest digest
+    pub(crate) digest: String,
+    /// The version of the image, if any
+    pub(crate) version: Option<String>,
+    /// The total (compressed) size of the layers
+    pub(crate) size: u64,
+    /// Whether the booted deployment uses the image
+    pub(crate) booted: bool,
+    /// Whether any deployment uses the image
+    pub(crate) in_use: bool,
+}
+
+/// The result of `bootc image list`.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ImageList {
+    /// Images fetched for deployments
+    pub(crate) host_images: Vec<HostImage>,
+    /// Logically bound images, by name (or ID if untagged)
+    pub(crate) bound_images: Vec<String>,
+}
+
+impl ImageList {
+    /// Write a human readable summary.
+    pub(crate) fn write_human(&self, mut out: impl Write) -> Result<()> {
+        writeln!(out, "# Host images")?;
+        for image in self.host_images.iter() {
+            let state = match (image.booted, image.in_use) {
+                (true, _) => " (booted)",
+                (false, true) => " (in use)",
+                (false, false) => "",
+            };
+            writeln!(out, "{}{state}", image.image)?;
+            if let Some(version) = image.version.as_deref() {
+                writeln!(out, "  Version: {version}")?;
+            }
+            writeln!(out, "  Digest: {}", image.digest)?;
+            writeln!(out, "  Size: {}", glib::format_size(image.size))?;
+        }
+        writeln!(out)?;
+        writeln!(out, "# Logically bound images")?;
+        for image in self.bound_images.iter() {
+            writeln!(out, "{image}")?;
+        }
+        Ok(())
+    }
+}
+
+/// List the images in the bootc storage.
 #[context("Listing images")]
-pub(crate) async fn list_entrypoint() -> Result<()> {
-    let sysroot = crate::cli::get_storage().await?;
+pub(crate) async fn list(sysroot: &crate::store::Storage) -> Result<ImageList> {
     let repo = &sysroot.repo();
+    let deployments = sysroot.deployments();
+    let booted = sysroot.booted_deployment();
 
-    let images = ostree_ext::container::store::list_images(repo).context("Querying images")?;
-
-    println!("# Host images");
-    for image in images {
-        println!("{image}");
+    let mut host_images = Vec::new();
+    for name in ostree_ext::container::store::list_images(repo).context("Querying images")? {
+        let imgref = ImageReference::try_from(name.as_str())?;
+        let Some(state) = ostree_ext::container::store::query_image(repo, &imgref)? else {
+            continue;
+        };
+        let commit = state.get_commit();
+        host_images.push(HostImage {
+            digest: state.manifest_digest.to_string(),
+            version: state.version().map(ToOwned::to_owned),
+            size: state.manifest.layers().iter().map(|l| l.size()).sum(),
+            booted: booted.as_ref().is_some_and(|d| d.csum().as_str() == commit),
+            in_use: deployments.iter().any(|d| d.csum().as_str() == commit),
+            image: name,
+        });
     }
-    println!();
 
-    println!("# Logically bound images");
-    let mut listcmd = sysroot.get_ensure_imgstore()?.new_image_cmd()?;
-    listcmd.arg("list");
-    listcmd.run()?;
+    let bound_images = sysroot
+        .get_ensure_imgstore()?
+        .list_images()
+        .await?
+        .into_iter()
+        .map(|entry| {
+            entry
+                .names
+                .and_then(|names| names.into_iter().next())
+                .unwrap_or(entry.id)
+        })
+        .collect();
 
-    Ok(())
+    Ok(ImageList {
+        host_images,
+        bound_images,
+    })
 }
 
 /// Implementation of `bootc image push-to-storage`.
@@ -76,6 +161,113 @@ pub(crate) async fn push_entrypoint(source: Option<&str>, target: Option<&str>)
     Ok(())
 }
 
+/// Implementation of `bootc image export`.
+#[context("Exporting booted deployment")]
+pub(crate) async fn export_entrypoint(output: &str, max_layers: Option<NonZeroU32>) -> Result<()> {
+    let target = ImageReference::try_from(output).context("Parsing output")?;
+    let sysroot = crate::cli::get_storage().await?;
+    let repo = &sysroot.repo();
+    let booted = sysroot.require_booted_deployment()?;
+    let commit = booted.csum();
+
+    let mut labels = BTreeMap::from([(
+        crate::metadata::BOOTC_COMPAT_LABEL.to_owned(),
+        "1".to_owned(),
+    )]);
+    // Record the image the host was deployed from, if any
+    let status = crate::status::get_status_require_booted(&sysroot)?;
+    if let Some(image) = status.2.status.booted.and_then(|b| b.image) {
+        labels.insert(
+            "org.opencontainers.image.base.name".to_owned(),
+            image.image.image,
+        );
+        labels.insert(
+            "org.opencontainers.image.base.digest".to_owned(),
+            image.image_digest,
+        );
+    }
+    let config = Config {
+        labels: Some(labels),
+        ..Default::default()
+    };
+    let contentmeta = crate::contentmeta::package_meta(repo, commit.as_str())?;
+    // ExportOpts is non-exhaustive, and so cannot be built with a struct literal
+    let mut opts = ExportOpts::default();
+    opts.max_layers = max_layers;
+    opts.copy_meta_opt_keys = vec!["version".to_owned()];
+    opts.contentmeta = contentmeta.as_ref();
+    println!("Exporting booted deployment {commit} to {target} ...");
+    let digest = encapsulate(repo, commit.as_str(), &config, Some(opts), &target).await?;
+    println!("Exported: {target} {digest}");
+    Ok(())
+}
+
+/// Implementation of `bootc image rechunk`.
+#[context("Rechunking image")]
+pub(crate) async fn rechunk_entrypoint(
+    src: &str,
+    dest: &str,
+    max_layers: Option<NonZeroU32>,
+) -> Result<()> {
+    let src = OstreeImageReference {
+        sigverify: SignatureSource::ContainerPolicy,
+        imgref: ImageReference::try_from(src).context("Parsing source")?,
+    };
+    let dest = ImageReference::try_from(dest).context("Parsing destination")?;
+
+    // This is generally run as part of a build rather than on a host, so the
+    // image is imported into a temporary repository.
+    let tmpdir = tempfile::tempdir_in("/var/tmp")?;
+    let repo_path = Utf8Path::from_path(tmpdir.path())
+        .context("Non-UTF8 temporary directory")?
+        .join("repo");
+    let repo = &ostree::Repo::create_at(
+        ostree::AT_FDCWD,
+        repo_path.as_str(),
+        ostree::RepoMode::BareUser,
+        None,
+        gio::Cancellable::NONE,
+    )?;
+    println!("Importing {src} ...");
+    let mut imp = crate::deploy::new_importer_with_config(repo, &src, Default::default()).await?;
+    let state = match imp.prepare().await? {
+        PrepareResult::AlreadyPresent(state) => state,
+        PrepareResult::Ready(prep) => imp.import(prep).await?,
+    };
+    let commit = state.get_commit();
+    let Some(contentmeta) = crate::contentmeta::package_meta(repo, commit)? else {
+        anyhow::bail!("No rpm database found in {src}");
+    };
+
+    // Keep the layer structure of the existing image, if any, so that the
+    // layers of unchanged packages are reused.
+    let prior = OstreeImageReference {
+        sigverify: SignatureSource::ContainerPolicyAllowInsecure,
+        imgref: dest.clone(),
+    };
+    let prior_build = match ostree_ext::container::fetch_manifest(&prior).await {
+        Ok((manifest, _)) if crate::contentmeta::has_components(&manifest) => Some(manifest),
+        Ok(_) => None,
+        Err(e) => {
+            tracing::debug!("Not using prior build: {e:#}");
+            None
+        }
+    };
+    if prior_build.is_some() {
+        println!("Using the layer structure of {dest}");
+    }
+    let mut opts = ExportOpts::default();
+    opts.max_layers = max_layers;
+    opts.copy_meta_opt_keys = vec!["version".to_owned()];
+    opts.container_config = state.configuration.config().clone();
+    opts.prior_build = prior_build.as_ref();
+    opts.contentmeta = Some(&contentmeta);
+    println!("Writing {dest} ...");
+    let digest = encapsulate(repo, commit, &Config::default(), Some(opts), &dest).await?;
+    println!("Rechunked: {dest} {digest}");
+    Ok(())
+}
+
 /// Thin wrapper for invoking `podman image <X>` but set up for our internal
 /// image store (as distinct from /var/lib/containers default).
 pub(crate) async fn imgcmd_entrypoint(
@@ -88,3 +280,47 @@ pub(crate) async fn imgcmd_entrypoint(
     cmd.args(args);
     cmd.run()
 }
+
+#[test]
+fn test_image_list_human() {
+    let image = |image: &str, booted, in_use| HostImage {
+        image: image.to_owned(),
+        digest: "sha256:abcd".to_owned(),
+        version: None,
+        size: 1_000_000,
+        booted,
+        in_use,
+    };
+    let list = ImageList {
+        host_images: vec![
+            HostImage {
+                version: Some("41.1".to_owned()),
+                ..image("docker://quay.io/example/os:latest", true, true)
+            },
+            image("docker://quay.io/example/os:old", false, true),
+            image("docker://quay.io/example/other:latest", false, false),
+        ],
+        bound_images: vec!["quay.io/example/agent:latest".to_owned()],
+    };
+    let mut out = Vec::new();
+    list.write_human(&mut out).unwrap();
+    similar_asserts::assert_eq!(
+        String::from_utf8(out).unwrap(),
+        indoc::indoc! {"
+            # Host images
+            docker://quay.io/example/os:latest (booted)
+              Version: 41.1
+              Digest: sha256:abcd
+              Size: 1.0 MB
+            docker://quay.io/example/os:old (in use)
+              Digest: sha256:abcd
+              Size: 1.0 MB
+            docker://quay.io/example/other:latest
+              Digest: sha256:abcd
+              Size: 1.0 MB
+
+            # Logically bound images
+            quay.io/example/agent:latest
+        "}
+    );
+}
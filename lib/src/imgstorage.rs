@@ -6,7 +6,7 @@
 //! This containers-storage: which canonically lives in `/sysroot/ostree/bootc`.
 
 use std::collections::HashSet;
-use std::io::Seek;
+use std::io::{Seek, Write};
 use std::os::unix::process::CommandExt;
 use std::process::{Command, Stdio};
 use std::sync::Arc;
@@ -40,6 +40,16 @@ pub(crate) const SUBPATH: &str = "ostree/bootc/storage";
 /// The path to the "runroot" with transient runtime state; this is
 /// relative to the /run directory
 const RUNROOT: &str = "bootc/storage";
+/// The name under which the last image fetched via [`Storage::pull_partial`]
+/// is kept, so that the files in its layers can be reused by the next one.
+pub(crate) const PARTIAL_PULL_BASE: &str = "localhost/bootc-partial-pull-base:latest";
+/// A `storage.conf` enabling partial pulls of zstd:chunked and estargz layers.
+const PARTIAL_PULL_STORAGE_CONF: &str = r#"[storage]
+driver = "overlay"
+
+[storage.options]
+pull_options = {enable_partial_images = "true", use_hard_links = "false"}
+"#;
 pub(crate) struct Storage {
     /// The root directory
     sysroot: Dir,
@@ -254,6 +264,75 @@ impl Storage {
         Ok(cmd.status().await?.success())
     }
 
+    /// The ID of the given image, if present.
+    async fn image_id(&self, image: &str) -> Result<Option<String>> {
+        if !self.exists(image).await? {
+            return Ok(None);
+        }
+        let mut cmd = self.new_image_cmd()?;
+        cmd.stdin(Stdio::null());
+        cmd.args(["inspect", "--format", "{{.Id}}", image]);
+        let o = AsyncCommand::from(cmd).output().await?;
+        if !o.status.success() {
+            anyhow::bail!(
+                "Inspecting {image}: {}",
+                String::from_utf8_lossy(&o.stderr).trim()
+            );
+        }
+        Ok(Some(String::from_utf8(o.stdout)?.trim().to_owned()))
+    }
+
+    /// Fetch an image with partial pulls enabled, so that for layers in the
+    /// zstd:chunked or estargz formats only the files which are not already
+    /// present in the storage are downloaded.  The image replaces the previous
+    /// [`PARTIAL_PULL_BASE`].
+    #[context("Partially pulling {image}")]
+    pub(crate) async fn pull_partial(&self, image: &str, env: &[(String, &str)]) -> Result<()> {
+        let previous = self.image_id(PARTIAL_PULL_BASE).await?;
+        let mut conf = tempfile::NamedTempFile::new()?;
+        conf.write_all(PARTIAL_PULL_STORAGE_CONF.as_bytes())?;
+        let mut cmd = self.new_image_cmd()?;
+        cmd.stdin(Stdio::null());
+        cmd.stdout(Stdio::null());
+        cmd.env("CONTAINERS_STORAGE_CONF", conf.path());
+        cmd.envs(env.iter().cloned());
+        cmd.args(["pull", image]);
+        let authfile = ostree_ext::globals::get_global_authfile(&self.sysroot)?
+            .map(|(authfile, _fd)| authfile);
+        if let Some(authfile) = authfile {
+            cmd.args(["--authfile", authfile.as_str()]);
+        }
+        let mut cmd = AsyncCommand::from(cmd);
+        // Don't leave podman pulling if the fetch is abandoned, e.g. on timeout
+        cmd.kill_on_drop(true);
+        cmd.run().await?;
+        let mut cmd = self.new_image_cmd()?;
+        cmd.args(["tag", image, PARTIAL_PULL_BASE]);
+        AsyncCommand::from(cmd).run().await?;
+        if let Some(previous) = previous {
+            if self.image_id(PARTIAL_PULL_BASE).await?.as_ref() != Some(&previous) {
+                let mut cmd = self.new_image_cmd()?;
+                cmd.stdout(Stdio::null());
+                cmd.args(["rm", previous.as_str()]);
+                AsyncCommand::from(cmd).run().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The name of an image in this storage for the `containers-storage`
+    /// transport; it is only valid for commands created by [`Self::new_skopeo_cmd`].
+    pub(crate) fn transport_name(image: &str) -> String {
+        format!("[overlay@{STORAGE_ALIAS_DIR}+/proc/self/fd/{STORAGE_RUN_FD}]{image}")
+    }
+
+    /// Create a `skopeo` Command instance which can access this storage.
+    pub(crate) fn new_skopeo_cmd(&self) -> Result<Command> {
+        let mut cmd = Command::new("skopeo");
+        bind_storage_roots(&mut cmd, &self.storage_root, &self.run)?;
+        Ok(cmd)
+    }
+
     /// Fetch the image if it is not already present; return whether
     /// or not the image was fetched.
     pub(crate) async fn pull(&self, image: &str, mode: PullMode) -> Result<bool> {
@@ -293,11 +372,10 @@ impl Storage {
         bind_storage_roots(&mut cmd, &self.storage_root, &temp_runroot)?;
 
         // The destination (target stateroot) + container storage dest
-        let storage_dest = &format!(
-            "containers-storage:[overlay@{STORAGE_ALIAS_DIR}+/proc/self/fd/{STORAGE_RUN_FD}]"
-        );
-        cmd.args(["image", "push", image])
-            .arg(format!("{storage_dest}{image}"));
+        cmd.args(["image", "push", image]).arg(format!(
+            "containers-storage:{}",
+            Self::transport_name(image)
+        ));
         let mut cmd = AsyncCommand::from(cmd);
         cmd.run().await?;
         temp_runroot.close()?;
@@ -309,4 +387,12 @@ impl Storage {
 mod tests {
     use super::*;
     static_assertions::assert_not_impl_any!(Storage: Sync);
+
+    #[test]
+    fn test_transport_name() {
+        assert_eq!(
+            Storage::transport_name("quay.io/example/os@sha256:0a1b"),
+            "[overlay@/run/bootc/storage+/proc/self/fd/3]quay.io/example/os@sha256:0a1b"
+        );
+    }
 }
@@ -7,9 +7,13 @@
 // This sub-module is the "basic" installer that handles creating basic block device
 // and filesystem setup.
 pub(crate) mod baseline;
+pub(crate) mod blueprint;
 pub(crate) mod config;
 mod osbuild;
 pub(crate) mod osconfig;
+pub(crate) mod phases;
+pub(crate) mod platform;
+mod verify;
 
 use std::io::Write;
 use std::os::fd::AsFd;
@@ -46,6 +50,7 @@ use serde::{Deserialize, Serialize};
 use self::baseline::InstallBlockDeviceOpts;
 use crate::containerenv::ContainerExecutionInfo;
 use crate::mount::Filesystem;
+use crate::progress_jsonl::{Event, ProgressWriter};
 use crate::spec::ImageReference;
 use crate::store::Storage;
 use crate::task::Task;
@@ -83,6 +88,14 @@ pub(crate) struct InstallTargetOpts {
     #[clap(long)]
     pub(crate) target_imgref: Option<String>,
 
+    /// Install for this architecture instead of the host's, e.g. `aarch64` or `arm64`.
+    ///
+    /// The source image is fetched for this architecture, and the partition layout,
+    /// bootloader and architecture specific configuration are chosen for it.  This is
+    /// only supported for x86_64 and aarch64 targets booting via UEFI.
+    #[clap(long)]
+    pub(crate) target_arch: Option<String>,
+
     /// This command line argument does nothing; it exists for compatibility.
     ///
     /// As of newer versions of bootc, this value is enabled by default,
@@ -128,6 +141,14 @@ pub(crate) struct InstallSourceOpts {
     /// in the previous paragraph. See skopeo(1) for accepted formats.
     #[clap(long)]
     pub(crate) source_imgref: Option<String>,
+
+    /// Fetch the source image for this architecture instead of the host's, using
+    /// the OCI naming with an optional variant, e.g. `arm64` or `arm/v7`.
+    ///
+    /// This is only supported in combination with `--source-imgref`.
+    #[clap(long, requires = "source_imgref")]
+    #[serde(default)]
+    pub(crate) arch: Option<String>,
 }
 
 #[derive(clap::Args, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -140,12 +161,32 @@ pub(crate) struct InstallConfigOpts {
     #[serde(default)]
     pub(crate) disable_selinux: bool,
 
+    /// Load the SELinux policy used to label the target from the root filesystem tree
+    /// at this path, instead of the running container image.
+    ///
+    /// This is useful with `--source-imgref`, where the running container may not
+    /// have the same policy as the target image.
+    #[clap(long, conflicts_with = "disable_selinux")]
+    pub(crate) selinux_policy: Option<Utf8PathBuf>,
+
     /// Add a kernel argument.  This option can be provided multiple times.
     ///
     /// Example: --karg=nosmt --karg=console=ttyS0,114800n8
     #[clap(long)]
     karg: Option<Vec<String>>,
 
+    /// Add the kernel arguments from a file, after any given via `--karg`.
+    ///
+    /// The arguments are separated by whitespace; lines starting with `#` are ignored.
+    #[clap(long, visible_alias = "karg-file")]
+    karg_append_from: Option<Utf8PathBuf>,
+
+    /// Print the kernel arguments from each source and the merged result, then exit
+    /// without installing anything.
+    #[clap(long)]
+    #[serde(skip)]
+    pub(crate) print_kargs: bool,
+
     /// The path to an `authorized_keys` that will be injected into the `root` account.
     ///
     /// The implementation of this uses systemd `tmpfiles.d`, writing to a file named
@@ -156,6 +197,70 @@ pub(crate) struct InstallConfigOpts {
     #[clap(long)]
     root_ssh_authorized_keys: Option<Utf8PathBuf>,
 
+    /// Create a user on first boot.  This option can be provided multiple times.
+    ///
+    /// The format is `name:uid:groups:sshkey`, where `groups` is a comma separated list of
+    /// supplementary groups, and `sshkey` an optional line for `~/.ssh/authorized_keys`.
+    /// The user is created via systemd `sysusers.d`, and the home directory and SSH key via
+    /// `tmpfiles.d`, in files named `bootc-install-users.conf` in `/etc`.
+    ///
+    /// Example: --add-user 'core:1000:wheel:ssh-ed25519 AAAA... core@example.com'
+    #[clap(long)]
+    add_user: Option<Vec<String>>,
+
+    /// The path to an Ignition config to provision the system with on first boot.
+    ///
+    /// The config is written to `/boot/ignition/config.ign`, along with the
+    /// `/boot/ignition.firstboot` stamp file which makes the bootloader enable Ignition.
+    /// The image must include Ignition.
+    #[clap(long)]
+    ignition_file: Option<Utf8PathBuf>,
+
+    /// The path to a NetworkManager keyfile (ending in `.nmconnection`) to write to
+    /// `/etc/NetworkManager/system-connections`, e.g. for a static IP address.
+    /// This option can be provided multiple times.
+    #[clap(long)]
+    network_config: Option<Vec<Utf8PathBuf>>,
+
+    /// The cloud-init datasource to provision the system with on first boot.
+    ///
+    /// With `NoCloud`, the file given via `--user-data` is written to
+    /// `/var/lib/cloud/seed/nocloud`, and cloud-init is configured to use only that datasource.
+    /// The image must include cloud-init.
+    #[clap(long, requires = "user_data")]
+    cloud_init_datasource: Option<osconfig::CloudInitDatasource>,
+
+    /// The path to the cloud-init user data, used with `--cloud-init-datasource`.
+    #[clap(long, requires = "cloud_init_datasource")]
+    user_data: Option<Utf8PathBuf>,
+
+    /// A shell command to run once on the first boot of the installed system.
+    /// This option can be provided multiple times; the commands are run in order.
+    ///
+    /// The commands are written to `/etc/bootc/firstboot.d`, and run by
+    /// `bootc-firstboot.service`; their results are logged to the journal.
+    #[clap(long)]
+    pub(crate) firstboot_command: Option<Vec<String>>,
+
+    /// Enable FIPS mode in the installed system.
+    ///
+    /// The image must include the dracut fips module and use the FIPS crypto policy
+    /// (`update-crypto-policies --no-reload --set FIPS`), which is verified before
+    /// anything is changed.  This adds the `fips=1` kernel argument, and `boot=` for the
+    /// filesystem holding the kernel if it is not otherwise set.
+    #[clap(long)]
+    #[serde(default)]
+    pub(crate) fips: bool,
+
+    /// The platform the installed system runs on, which determines the `console=`
+    /// kernel arguments added so that the serial console of clouds works.
+    ///
+    /// Defaults to the platform detected from the DMI data of the machine, or none
+    /// with `--generic-image`.  The arguments are not added if any `console=`
+    /// argument is set otherwise.
+    #[clap(long)]
+    pub(crate) target_platform: Option<platform::Platform>,
+
     /// Perform configuration changes suitable for a "generic" disk image.
     /// At the moment:
     ///
@@ -165,6 +270,14 @@ pub(crate) struct InstallConfigOpts {
     #[serde(default)]
     pub(crate) generic_image: bool,
 
+    /// The bootloader to install.
+    ///
+    /// Defaults to the value of the `containers.bootc.bootloader` label of the image
+    /// if present; otherwise zipl on s390x, systemd-boot if the image ships a UKI,
+    /// and GRUB if not.
+    #[clap(long)]
+    pub(crate) bootloader: Option<crate::bootloader::Bootloader>,
+
     /// Do not pull any "logically bound" images at install time.
     #[clap(long, hide = true)]
     #[serde(default)]
@@ -173,6 +286,27 @@ pub(crate) struct InstallConfigOpts {
     /// The stateroot name to use. Defaults to `default`.
     #[clap(long)]
     pub(crate) stateroot: Option<String>,
+
+    /// Write progress events as newline-delimited JSON to this (inherited) file descriptor.
+    ///
+    /// Each phase of the installation is reported with the approximate overall progress
+    /// in percent; `install to-disk` also reports the partitioning plan, and a final
+    /// `installComplete` event contains the same data as `--result-json`.
+    #[clap(long, visible_alias = "json-fd")]
+    #[serde(skip)]
+    pub(crate) progress_fd: Option<i32>,
+
+    /// On success, write a JSON description of the installed deployment to this file,
+    /// for consumption by e.g. an OS installer.
+    #[clap(long)]
+    #[serde(skip)]
+    pub(crate) result_json: Option<Utf8PathBuf>,
+
+    /// Install configuration taking precedence over that of the image, from the
+    /// `[install]` table of a blueprint passed to `bootc install from-config`.
+    #[clap(skip)]
+    #[serde(skip)]
+    pub(crate) install_config: Option<config::InstallConfiguration>,
 }
 
 #[derive(Debug, Clone, clap::Parser, Serialize, Deserialize, PartialEq, Eq)]
@@ -197,6 +331,83 @@ pub(crate) struct InstallToDiskOpts {
     #[clap(long)]
     #[serde(default)]
     pub(crate) via_loopback: bool,
+
+    /// With `--via-loopback`, create the file if it does not exist and grow it
+    /// to the given size (e.g. `10G`) as a sparse file.
+    #[clap(long)]
+    pub(crate) size: Option<String>,
+
+    /// Print the partitions which would be created, and exit without changing anything.
+    #[clap(long)]
+    #[serde(default)]
+    pub(crate) print_plan: bool,
+
+    /// After installing, verify the bootloader entry, kernel arguments and `/etc/fstab`
+    /// of the installed system, failing if they are inconsistent.
+    #[clap(long)]
+    #[serde(default)]
+    pub(crate) verify: bool,
+
+    /// With `--verify`, also boot the installed system once under qemu (if installed),
+    /// and verify that it reaches `multi-user.target`.  Changes made by this boot
+    /// are discarded.
+    #[clap(long, requires = "verify")]
+    #[serde(default)]
+    pub(crate) verify_boot: bool,
+}
+
+/// The format of a disk image written by `bootc image build-disk`.
+#[derive(ValueEnum, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum DiskImageFormat {
+    /// A raw (sparse) disk image
+    Raw,
+    /// The QEMU copy-on-write format
+    Qcow2,
+    /// The VMware virtual disk format
+    Vmdk,
+}
+
+impl DiskImageFormat {
+    /// The name of the format as understood by `qemu-img`.
+    fn as_qemu_img_format(&self) -> &'static str {
+        match self {
+            DiskImageFormat::Raw => "raw",
+            DiskImageFormat::Qcow2 => "qcow2",
+            DiskImageFormat::Vmdk => "vmdk",
+        }
+    }
+}
+
+#[derive(Debug, Clone, clap::Parser, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct BuildDiskOpts {
+    /// The format of the disk image.
+    #[clap(long, value_enum, default_value_t = DiskImageFormat::Qcow2)]
+    pub(crate) format: DiskImageFormat,
+
+    /// Path of the disk image to write; an existing file is replaced.
+    #[clap(long)]
+    pub(crate) output: Utf8PathBuf,
+
+    /// Size of the disk (default specifier: M).  Allowed specifiers: M (mebibytes), G (gibibytes), T (tebibytes).
+    #[clap(long, default_value = "10G")]
+    pub(crate) size: String,
+
+    /// Target root filesystem type.
+    #[clap(long, value_enum)]
+    pub(crate) filesystem: Option<baseline::Filesystem>,
+
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub(crate) source_opts: InstallSourceOpts,
+
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub(crate) target_opts: InstallTargetOpts,
+
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub(crate) config_opts: InstallConfigOpts,
 }
 
 #[derive(ValueEnum, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -257,6 +468,15 @@ pub(crate) struct InstallTargetFilesystemOpts {
     /// is then the responsibility of the invoking code to perform those operations.
     #[clap(long)]
     pub(crate) skip_finalize: bool,
+
+    /// Skip installing the bootloader.  It is then the responsibility of the invoking
+    /// code, such as an OS installer, to install and configure it.
+    #[clap(long)]
+    pub(crate) skip_bootloader: bool,
+
+    /// Grow the root partition and filesystem to fill the disk before installing.
+    #[clap(skip)]
+    pub(crate) resize_root: bool,
 }
 
 #[derive(Debug, Clone, clap::Parser, PartialEq, Eq)]
@@ -293,6 +513,12 @@ pub(crate) struct InstallToExistingRootOpts {
     #[clap(long)]
     pub(crate) acknowledge_destructive: bool,
 
+    /// Grow the root partition and filesystem to fill the disk before installing,
+    /// e.g. after the disk of a virtual machine was enlarged.  This is only supported
+    /// for a root filesystem directly on a partition.
+    #[clap(long)]
+    pub(crate) resize_root: bool,
+
     /// Path to the mounted root; it's expected to invoke podman with
     /// `-v /:/target`, then supplying this argument is unnecessary.
     #[clap(default_value = "/target")]
@@ -322,9 +548,27 @@ pub(crate) struct State {
     #[allow(dead_code)]
     pub(crate) config_opts: InstallConfigOpts,
     pub(crate) target_imgref: ostree_container::OstreeImageReference,
+    /// Architecture override for fetching the source image
+    pub(crate) source_arch: Option<crate::deploy::ImageArch>,
+    /// The architecture being installed for, as named by Rust (e.g. `aarch64`)
+    pub(crate) target_arch: &'static str,
+    /// Machine readable progress output
+    pub(crate) progress: Option<ProgressWriter>,
     pub(crate) install_config: Option<config::InstallConfiguration>,
     /// The parsed contents of the authorized_keys (not the file path)
     pub(crate) root_ssh_authorized_keys: Option<String>,
+    /// The kernel arguments read from `--karg-append-from`
+    pub(crate) karg_append: Vec<String>,
+    /// The users from `--add-user`
+    pub(crate) users: Vec<osconfig::InstallUser>,
+    /// The file names and contents of the keyfiles from `--network-config`
+    pub(crate) network_configs: Vec<(String, String)>,
+    /// The contents of the Ignition config from `--ignition-file`
+    pub(crate) ignition_config: Option<String>,
+    /// The contents of the cloud-init user data from `--user-data`
+    pub(crate) cloud_init_user_data: Option<String>,
+    /// The platform from `--target-platform`, or detected
+    pub(crate) platform: Option<platform::Platform>,
     /// The root filesystem of the running container
     pub(crate) container_root: Dir,
     pub(crate) tempdir: TempDir,
@@ -337,11 +581,24 @@ impl State {
         if !self.selinux_state.enabled() {
             return Ok(None);
         }
-        // We always use the physical container root to bootstrap policy
-        let r = ostree::SePolicy::new_at(self.container_root.as_raw_fd(), gio::Cancellable::NONE)?;
-        let csum = r
-            .csum()
-            .ok_or_else(|| anyhow::anyhow!("SELinux enabled, but no policy found in root"))?;
+        // By default, we use the physical container root to bootstrap policy
+        let r = if let Some(path) = self.config_opts.selinux_policy.as_deref() {
+            let root = Dir::open_ambient_dir(path, cap_std::ambient_authority())
+                .with_context(|| format!("Opening {path}"))?;
+            ostree::SePolicy::new_at(root.as_raw_fd(), gio::Cancellable::NONE)
+                .with_context(|| format!("Loading policy from {path}"))?
+        } else {
+            ostree::SePolicy::new_at(self.container_root.as_raw_fd(), gio::Cancellable::NONE)?
+        };
+        let csum = r.csum().ok_or_else(|| {
+            anyhow::anyhow!(
+                "SELinux is enabled, but no policy was found in {}; use --selinux-policy to load it from elsewhere, or --disable-selinux",
+                self.config_opts
+                    .selinux_policy
+                    .as_ref()
+                    .map_or_else(|| "the container image".to_owned(), |p| p.to_string())
+            )
+        })?;
         tracing::debug!("Loaded SELinux policy: {csum}");
         Ok(Some(r))
     }
@@ -385,6 +642,122 @@ struct InstallAleph {
     selinux: String,
 }
 
+/// The result of a successful installation, written to `--result-json`.
+/// This is a stable interface for OS installers; fields may be added, but
+/// not removed or changed.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct InstallResult {
+    /// The stateroot (ostree "osname") of the deployment
+    stateroot: String,
+    /// The path of the deployment root, relative to the target root filesystem
+    deployment_path: String,
+    /// The ostree commit of the deployment
+    commit: String,
+    /// The target image reference, used for updates
+    image: String,
+    /// The digest of the installed image manifest
+    digest: String,
+    /// The kernel arguments of the deployment
+    kargs: Vec<String>,
+    /// Whether the bootloader was installed, i.e. `--skip-bootloader` was not given
+    bootloader_installed: bool,
+    /// The bootloader used to boot the deployment
+    bootloader: crate::bootloader::Bootloader,
+}
+
+/// The kernel arguments for the deployment from each source, in increasing order of
+/// precedence:
+///
+/// - the install configuration
+/// - `/usr/lib/bootc/kargs.d` of the image
+/// - `--karg`
+/// - `--karg-file`
+///
+/// The kernel arguments for the root filesystem are not included; they come first.
+#[context("Gathering kernel arguments")]
+fn install_kargs(state: &State) -> Result<Vec<(&'static str, Vec<String>)>> {
+    // Load the kargs from the /usr/lib/bootc/kargs.d from the running root,
+    // which should be the same as the filesystem we'll deploy.
+    let kargsd = crate::kargs::get_kargs_in_root(&state.container_root, state.target_arch)?;
+    let install_config = state
+        .install_config
+        .as_ref()
+        .and_then(|c| c.kargs.clone())
+        .unwrap_or_default();
+    let mut sources = vec![
+        ("install configuration", install_config),
+        ("kargs.d", kargsd),
+        ("--karg", state.config_opts.karg.clone().unwrap_or_default()),
+        ("--karg-file", state.karg_append.clone()),
+    ];
+    // Ignition does nothing without a platform; default to bare metal
+    // unless one was given.
+    if state.ignition_config.is_some()
+        && !sources
+            .iter()
+            .flat_map(|(_, v)| v)
+            .any(|k| k.starts_with("ignition.platform.id="))
+    {
+        sources.push(("--ignition-file", vec!["ignition.platform.id=metal".into()]));
+    }
+    // Serial consoles, unless any console was configured
+    if let Some(platform) = state.platform {
+        if !sources
+            .iter()
+            .flat_map(|(_, v)| v)
+            .any(|k| k.starts_with("console="))
+        {
+            let config = state
+                .install_config
+                .as_ref()
+                .and_then(|c| c.console.as_ref());
+            let kargs = platform.console_kargs(state.target_arch, config);
+            sources.insert(0, ("platform", kargs));
+        }
+    }
+    if state.config_opts.fips && !sources.iter().flat_map(|(_, v)| v).any(|k| k == "fips=1") {
+        sources.push(("--fips", vec!["fips=1".into()]));
+    }
+    Ok(sources)
+}
+
+/// Write the kernel arguments from each source, and the merged result.
+fn render_kargs(sources: &[(&str, Vec<String>)], mut out: impl Write) -> Result<()> {
+    for (source, kargs) in sources {
+        if kargs.is_empty() {
+            writeln!(out, "{source}: (none)")?;
+        } else {
+            writeln!(out, "{source}: {}", kargs.join(" "))?;
+        }
+    }
+    let merged = sources
+        .iter()
+        .flat_map(|(_, v)| v)
+        .map(|s| s.as_str())
+        .collect::<Vec<_>>();
+    writeln!(out, "merged: {}", merged.join(" "))?;
+    Ok(())
+}
+
+/// Implementation of `--print-kargs`.
+fn print_kargs(state: &State) -> Result<()> {
+    println!("Kernel arguments, in increasing order of precedence:");
+    render_kargs(&install_kargs(state)?, std::io::stdout().lock())?;
+    println!("The kernel arguments for the root filesystem are added in front.");
+    Ok(())
+}
+
+/// Parse a file of kernel arguments for `--karg-file`.
+fn parse_karg_file(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .filter(|l| !l.trim_start().starts_with('#'))
+        .flat_map(|l| l.split_ascii_whitespace())
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
 /// A mount specification is a subset of a line in `/etc/fstab`.
 ///
 /// There are 3 (ASCII) whitespace separated values:
@@ -395,7 +768,7 @@ struct InstallAleph {
 ///   - /dev/vda3 /boot ext4 ro
 ///   - /dev/nvme0n1p4 /
 ///   - /dev/sda2 /var/mnt xfs
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct MountSpec {
     pub(crate) source: String,
     pub(crate) target: String,
@@ -557,7 +930,7 @@ impl SourceInfo {
 }
 
 pub(crate) fn print_configuration() -> Result<()> {
-    let mut install_config = config::load_config()?.unwrap_or_default();
+    let mut install_config = config::load_config(std::env::consts::ARCH)?.unwrap_or_default();
     install_config.filter_to_external();
     let stdout = std::io::stdout().lock();
     serde_json::to_writer(stdout, &install_config).map_err(Into::into)
@@ -572,6 +945,14 @@ async fn initialize_ostree_root(state: &State, root_setup: &RootSetup) -> Result
     let rootfs = root_setup.rootfs.as_path();
     let cancellable = gio::Cancellable::NONE;
 
+    // Verify that the physical root can be labeled, and repair the labels of content
+    // created without them (e.g. by mkfs) before ostree trips over it.
+    if let Some(policy) = sepolicy {
+        let n = crate::lsm::ensure_root_labeled(rootfs_dir, policy)?;
+        if n > 0 {
+            println!("Labeled {n} paths in the target root");
+        }
+    }
     // Ensure that the physical root is labeled.
     // Another implementation: https://github.com/coreos/coreos-assembler/blob/3cd3307904593b3a131b81567b13a4d0b6fe7c90/src/create_disk.sh#L295
     crate::lsm::ensure_dir_labeled(rootfs_dir, "", Some("/".into()), 0o755.into(), sepolicy)?;
@@ -634,8 +1015,15 @@ async fn initialize_ostree_root(state: &State, root_setup: &RootSetup) -> Result
         )?;
     }
 
+    open_target_sysroot(state, rootfs).await
+}
+
+/// Open the ostree sysroot initialized in the target root filesystem.
+async fn open_target_sysroot(state: &State, rootfs: &Utf8Path) -> Result<Storage> {
+    state.tempdir.create_dir_all("temp-run")?;
+    let temp_run = state.tempdir.open_dir("temp-run")?;
     let sysroot = ostree::Sysroot::new(Some(&gio::File::for_path(rootfs)));
-    sysroot.load(cancellable)?;
+    sysroot.load(gio::Cancellable::NONE)?;
     let sysroot = SysrootLock::new_from_sysroot(&sysroot).await?;
     Storage::new(sysroot, &temp_run)
 }
@@ -645,13 +1033,11 @@ async fn install_container(
     state: &State,
     root_setup: &RootSetup,
     sysroot: &ostree::Sysroot,
-) -> Result<(ostree::Deployment, InstallAleph)> {
+) -> Result<(ostree::Deployment, InstallAleph, InstallResult)> {
     let sepolicy = state.load_policy()?;
     let sepolicy = sepolicy.as_ref();
     let stateroot = state.stateroot();
 
-    let container_rootfs = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
-
     let (src_imageref, proxy_cfg) = if !state.source.in_host_mountns {
         (state.source.imageref.clone(), None)
     } else {
@@ -693,43 +1079,40 @@ async fn install_container(
 
     // Pull the container image into the target root filesystem. Since this is
     // an install path, we don't need to fsync() individual layers.
+    send_phase(state.progress.as_ref(), "pull");
     {
         let spec_imgref = ImageReference::from(src_imageref.clone());
         let repo = &sysroot.repo();
         repo.set_disable_fsync(true);
-        crate::deploy::pull(repo, &spec_imgref, Some(&state.target_imgref), false).await?;
+        let pull_opts = crate::deploy::PullOpts {
+            target_imgref: Some(&state.target_imgref),
+            arch: state.source_arch.as_ref(),
+            quiet: false,
+            progress: state.progress.as_ref(),
+            ..Default::default()
+        };
+        crate::deploy::pull(repo, &spec_imgref, &pull_opts).await?;
         repo.set_disable_fsync(false);
     }
 
-    // Load the kargs from the /usr/lib/bootc/kargs.d from the running root,
-    // which should be the same as the filesystem we'll deploy.
-    let kargsd = crate::kargs::get_kargs_in_root(container_rootfs, std::env::consts::ARCH)?;
-    let kargsd = kargsd.iter().map(|s| s.as_str());
-
-    let install_config_kargs = state
-        .install_config
-        .as_ref()
-        .and_then(|c| c.kargs.as_ref())
-        .into_iter()
-        .flatten()
-        .map(|s| s.as_str());
-    // Final kargs, in order:
-    // - root filesystem kargs
-    // - install config kargs
-    // - kargs.d from container image
-    // - args specified on the CLI
-    let kargs = root_setup
+    // The kargs for the root filesystem come first
+    let kargs_by_source = install_kargs(state)?;
+    let mut kargs = root_setup
         .kargs
         .iter()
-        .map(|v| v.as_str())
-        .chain(install_config_kargs)
-        .chain(kargsd)
-        .chain(state.config_opts.karg.iter().flatten().map(|v| v.as_str()))
+        .chain(kargs_by_source.iter().flat_map(|(_, v)| v))
+        .cloned()
         .collect::<Vec<_>>();
+    crate::kargs::merge_rootflags(&mut kargs);
+    if state.config_opts.fips {
+        kargs.extend(osconfig::fips_boot_karg(&kargs)?);
+    }
+    let kargs = kargs.iter().map(|v| v.as_str()).collect::<Vec<_>>();
     let mut options = ostree_container::deploy::DeployOpts::default();
     options.kargs = Some(kargs.as_slice());
     options.target_imgref = Some(&state.target_imgref);
     options.proxy_cfg = proxy_cfg;
+    send_phase(state.progress.as_ref(), "deploy");
     let imgstate = crate::utils::async_task_with_spinner(
         "Deploying container image",
         ostree_container::deploy::deploy(&sysroot, stateroot, &src_imageref, Some(options)),
@@ -775,9 +1158,22 @@ async fn install_container(
 
     // Write the entry for /boot to /etc/fstab.  TODO: Encourage OSes to use the karg?
     // Or better bind this with the grub data.
-    if let Some(boot) = root_setup.boot.as_ref() {
+    let fstab = root_setup
+        .boot
+        .iter()
+        .chain(root_setup.mounts.iter())
+        .collect::<Vec<_>>();
+    if !fstab.is_empty() {
         crate::lsm::atomic_replace_labeled(&root, "etc/fstab", 0o644.into(), sepolicy, |w| {
-            writeln!(w, "{}", boot.to_fstab()).map_err(Into::into)
+            for m in fstab.iter() {
+                writeln!(w, "{}", m.to_fstab())?;
+            }
+            Ok(())
+        })?;
+    }
+    if let Some(crypttab) = root_setup.crypttab.as_deref() {
+        crate::lsm::atomic_replace_labeled(&root, "etc/crypttab", 0o600.into(), sepolicy, |w| {
+            writeln!(w, "{crypttab}").map_err(Into::into)
         })?;
     }
 
@@ -785,6 +1181,38 @@ async fn install_container(
         osconfig::inject_root_ssh_authorized_keys(&root, sepolicy, contents)?;
     }
 
+    if !state.users.is_empty() {
+        osconfig::inject_users(&root, sepolicy, &state.users)?;
+    }
+
+    if let Some(commands) = state.config_opts.firstboot_command.as_deref() {
+        osconfig::inject_firstboot_commands(&root, sepolicy, commands)?;
+    }
+
+    if !state.network_configs.is_empty() {
+        osconfig::inject_network_configs(&root, sepolicy, &state.network_configs)?;
+    }
+
+    if let Some(config) = state.ignition_config.as_deref() {
+        osconfig::stage_ignition_config(&root_setup.rootfs_fd, sepolicy, config)?;
+    }
+
+    match (
+        state.config_opts.cloud_init_datasource,
+        state.cloud_init_user_data.as_deref(),
+    ) {
+        (Some(osconfig::CloudInitDatasource::NoCloud), Some(user_data)) => {
+            let stateroot_dir = root_setup
+                .rootfs_fd
+                .open_dir(format!("ostree/deploy/{stateroot}"))
+                .context("Opening stateroot")?;
+            osconfig::stage_nocloud_seed(&stateroot_dir, &root, sepolicy, user_data)?;
+        }
+        // Enforced by the CLI parser
+        (None, None) => {}
+        _ => anyhow::bail!("--cloud-init-datasource and --user-data must be given together"),
+    }
+
     let uname = rustix::system::uname();
 
     let labels = crate::status::labels_of_config(&imgstate.configuration);
@@ -794,6 +1222,14 @@ async fn install_container(
                 .map(|s| s.as_str())
         })
         .and_then(crate::status::try_deserialize_timestamp);
+    let bootloader = crate::bootloader::Bootloader::resolve(
+        state.target_arch,
+        state.config_opts.bootloader,
+        labels
+            .and_then(|l| l.get(crate::metadata::BOOTLOADER_LABEL))
+            .map(|s| s.as_str()),
+        crate::systemd_boot::find_uki(&root)?.is_some(),
+    )?;
     let aleph = InstallAleph {
         image: src_imageref.imgref.name.clone(),
         version: imgstate.version().as_ref().map(|s| s.to_string()),
@@ -801,8 +1237,18 @@ async fn install_container(
         kernel: uname.release().to_str()?.to_string(),
         selinux: state.selinux_state.to_aleph().to_string(),
     };
+    let result = InstallResult {
+        stateroot: stateroot.to_string(),
+        deployment_path: path.to_string(),
+        commit: deployment.csum().to_string(),
+        image: state.target_imgref.to_string(),
+        digest: imgstate.manifest_digest.to_string(),
+        kargs: kargs.iter().map(|s| s.to_string()).collect(),
+        bootloader_installed: !root_setup.skip_bootloader,
+        bootloader,
+    };
 
-    Ok((deployment, aleph))
+    Ok((deployment, aleph, result))
 }
 
 /// Run a command in the host mount namespace
@@ -864,15 +1310,56 @@ fn require_skopeo_with_containers_storage() -> Result<()> {
     }
 }
 
+/// Block devices set up underneath the target filesystems, which must be
+/// deactivated once they are unmounted.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct BlockStack {
+    /// The opened LUKS device holding the root filesystem, if any
+    pub(crate) luks_device: Option<String>,
+    /// The LVM volume group holding the root filesystem, if any
+    pub(crate) lvm_vg: Option<String>,
+    /// The md RAID arrays, if any
+    pub(crate) md_devices: Vec<String>,
+}
+
+impl BlockStack {
+    /// Deactivate all devices, from the top of the stack down.
+    fn deactivate(&self) -> Result<()> {
+        if let Some(vg) = self.lvm_vg.as_deref() {
+            Task::new_and_run("Deactivating volume group", "vgchange", ["-an", vg])?;
+        }
+        if let Some(luksdev) = self.luks_device.as_deref() {
+            Task::new_and_run("Closing root LUKS device", "cryptsetup", ["close", luksdev])?;
+        }
+        for md in self.md_devices.iter() {
+            Task::new_and_run(
+                format!("Stopping RAID array {md}"),
+                "mdadm",
+                ["--stop", md.as_str()],
+            )?;
+        }
+        Ok(())
+    }
+}
+
 pub(crate) struct RootSetup {
-    luks_device: Option<String>,
+    block_stack: BlockStack,
     device_info: crate::blockdev::PartitionTable,
+    /// The partition tables of further devices which get a bootloader installed, e.g. RAID mirrors
+    mirror_device_info: Vec<crate::blockdev::PartitionTable>,
     rootfs: Utf8PathBuf,
     rootfs_fd: Dir,
     rootfs_uuid: Option<String>,
     /// True if we should skip finalizing
     skip_finalize: bool,
+    /// True if we should skip installing the bootloader
+    skip_bootloader: bool,
     boot: Option<MountSpec>,
+    /// Additional entries for `/etc/fstab`
+    mounts: Vec<MountSpec>,
+    /// The entry for `/etc/crypttab`, if the root filesystem is encrypted
+    crypttab: Option<String>,
     kargs: Vec<String>,
 }
 
@@ -888,9 +1375,16 @@ impl RootSetup {
         self.boot.as_ref().map(require_boot_uuid).transpose()
     }
 
-    // Drop any open file descriptors and return just the mount path and backing luks device, if any
-    fn into_storage(self) -> (Utf8PathBuf, Option<String>) {
-        (self.rootfs, self.luks_device)
+    /// Get the UUID of the /boot filesystem, or of the root filesystem if there is no separate /boot.
+    fn boot_uuid(&self) -> Result<&str> {
+        self.get_boot_uuid()?
+            .or(self.rootfs_uuid.as_deref())
+            .ok_or_else(|| anyhow!("No uuid for boot/root"))
+    }
+
+    // Drop any open file descriptors and return just the mount path and backing block devices
+    fn into_storage(self) -> (Utf8PathBuf, BlockStack) {
+        (self.rootfs, self.block_stack)
     }
 }
 
@@ -1115,14 +1609,15 @@ pub(crate) fn setup_sys_mount(fstype: &str, fspath: &str) -> Result<()> {
 async fn verify_target_fetch(
     tmpdir: &Dir,
     imgref: &ostree_container::OstreeImageReference,
+    arch: Option<&crate::deploy::ImageArch>,
 ) -> Result<()> {
     let tmpdir = &TempDir::new_in(&tmpdir)?;
     let tmprepo = &ostree::Repo::create_at_dir(tmpdir.as_fd(), ".", ostree::RepoMode::Bare, None)
         .context("Init tmp repo")?;
 
     tracing::trace!("Verifying fetch for {imgref}");
-    let mut imp =
-        ostree_container::store::ImageImporter::new(tmprepo, imgref, Default::default()).await?;
+    let proxy_cfg = crate::deploy::image_proxy_config(&Default::default(), arch);
+    let mut imp = ostree_container::store::ImageImporter::new(tmprepo, imgref, proxy_cfg).await?;
     use ostree_container::store::PrepareResult;
     let prep = match imp.prepare().await? {
         // SAFETY: It's impossible that the image was already fetched into this newly created temporary repository
@@ -1133,6 +1628,29 @@ async fn verify_target_fetch(
     Ok(())
 }
 
+/// Parse `--target-arch`, if given.
+fn parse_target_arch(opts: &InstallTargetOpts) -> Result<Option<crate::deploy::ImageArch>> {
+    opts.target_arch
+        .as_deref()
+        .map(|a| a.parse::<crate::deploy::ImageArch>())
+        .transpose()
+        .context("Parsing --target-arch")
+}
+
+/// The architecture to install for, as named by Rust: the one given via `--target-arch`,
+/// or the host's.  Cross-architecture installs are only supported for targets booting via
+/// UEFI, as only the EFI bootloader files can be copied from the target image.
+pub(crate) fn target_arch_name(opts: &InstallTargetOpts) -> Result<&'static str> {
+    let Some(arch) = parse_target_arch(opts)? else {
+        return Ok(std::env::consts::ARCH);
+    };
+    let name = arch.system_name()?;
+    if name != std::env::consts::ARCH && !matches!(name, "x86_64" | "aarch64") {
+        anyhow::bail!("Installing for {arch} is only supported on {arch} hosts");
+    }
+    Ok(name)
+}
+
 /// Preparation for an install; validates and prepares some (thereafter immutable) global state.
 async fn prepare_install(
     config_opts: InstallConfigOpts,
@@ -1148,6 +1666,24 @@ async fn prepare_install(
         .context("Opening /")?;
 
     let external_source = source_opts.source_imgref.is_some();
+    let source_arch = source_opts
+        .arch
+        .as_deref()
+        .map(|a| a.parse::<crate::deploy::ImageArch>())
+        .transpose()
+        .context("Parsing --arch")?;
+    let target_arch = target_arch_name(&target_opts)?;
+    // The source image must be the one for the target architecture
+    let source_arch = match (source_arch, parse_target_arch(&target_opts)?) {
+        (Some(s), Some(t)) if s.arch != t.arch => {
+            anyhow::bail!("--arch {s} conflicts with --target-arch {t}")
+        }
+        (s, t) => s.or(t),
+    };
+    if target_arch != std::env::consts::ARCH {
+        println!("Installing for architecture: {target_arch}");
+    }
+    let progress = ProgressWriter::from_opt(config_opts.progress_fd)?;
     let source = match source_opts.source_imgref {
         None => {
             // Out of conservatism we only verify the host userns path when we're expecting
@@ -1210,7 +1746,7 @@ async fn prepare_install(
     osbuild::adjust_for_bootc_image_builder(&rootfs, &tempdir)?;
 
     if !target_opts.skip_fetch_check {
-        verify_target_fetch(&tempdir, &target_imgref).await?;
+        verify_target_fetch(&tempdir, &target_imgref, source_arch.as_ref()).await?;
     }
 
     // Even though we require running in a container, the mounts we create should be specific
@@ -1230,7 +1766,10 @@ async fn prepare_install(
         println!("Digest: {digest}");
     }
 
-    let install_config = config::load_config()?;
+    let mut install_config = config::load_config(target_arch)?;
+    if let Some(c) = config_opts.install_config.clone() {
+        install_config = Some(config::merge_config(install_config, c, target_arch));
+    }
     if install_config.is_some() {
         tracing::debug!("Loaded install configuration");
     } else {
@@ -1244,6 +1783,75 @@ async fn prepare_install(
         .as_ref()
         .map(|p| std::fs::read_to_string(p).with_context(|| format!("Reading {p}")))
         .transpose()?;
+    let karg_append = config_opts
+        .karg_append_from
+        .as_ref()
+        .map(|p| {
+            std::fs::read_to_string(p)
+                .map(|s| parse_karg_file(&s))
+                .with_context(|| format!("Reading {p}"))
+        })
+        .transpose()?
+        .unwrap_or_default();
+    let users = config_opts
+        .add_user
+        .iter()
+        .flatten()
+        .map(|u| {
+            u.parse::<osconfig::InstallUser>()
+                .context("Parsing --add-user")
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(dup) = users.iter().enumerate().find(|(i, u)| {
+        users[..*i]
+            .iter()
+            .any(|o| o.name == u.name || o.uid == u.uid)
+    }) {
+        anyhow::bail!("Duplicate user name or uid in --add-user: {}", dup.1.name);
+    }
+    let network_configs = config_opts
+        .network_config
+        .iter()
+        .flatten()
+        .map(|p| osconfig::read_network_config(p))
+        .collect::<Result<Vec<_>>>()?;
+    if let Some((i, _)) = network_configs
+        .iter()
+        .enumerate()
+        .find(|(i, (name, _))| network_configs[..*i].iter().any(|(n, _)| n == name))
+    {
+        anyhow::bail!(
+            "Duplicate file name in --network-config: {}",
+            network_configs[i].0
+        );
+    }
+    let ignition_config = config_opts
+        .ignition_file
+        .as_ref()
+        .map(|p| std::fs::read_to_string(p).with_context(|| format!("Reading {p}")))
+        .transpose()?;
+    if let Some(config) = ignition_config.as_deref() {
+        osconfig::validate_ignition_config(config)?;
+    }
+    let cloud_init_user_data = config_opts
+        .user_data
+        .as_ref()
+        .map(|p| std::fs::read_to_string(p).with_context(|| format!("Reading {p}")))
+        .transpose()?;
+    osconfig::verify_provisioning_support(
+        &rootfs,
+        ignition_config.is_some(),
+        config_opts.cloud_init_datasource.is_some(),
+    )?;
+    if config_opts.fips {
+        osconfig::verify_fips_support(&rootfs)?;
+    }
+    let platform = match config_opts.target_platform {
+        Some(p) => Some(p),
+        None if config_opts.generic_image => None,
+        None => Some(platform::Platform::detect()?),
+    };
+    tracing::debug!("Target platform: {platform:?}");
 
     // Create our global (read-only) state which gets wrapped in an Arc
     // so we can pass it to worker threads too. Right now this just
@@ -1253,29 +1861,37 @@ async fn prepare_install(
         source,
         config_opts,
         target_imgref,
+        source_arch,
+        target_arch,
+        progress,
         install_config,
         root_ssh_authorized_keys,
+        karg_append,
+        users,
+        network_configs,
+        ignition_config,
+        cloud_init_user_data,
+        platform,
         container_root: rootfs,
         tempdir,
     });
 
+    // Verify the SELinux policy can be loaded before changing anything
+    state.load_policy()?;
+
     Ok(state)
 }
 
-/// Given a baseline root filesystem with an ostree sysroot initialized:
-/// - install the container to that root
-/// - install the bootloader
-/// - Other post operations, such as pulling bound images
-async fn install_with_sysroot(
+/// Install the container to a baseline root filesystem with an ostree sysroot initialized,
+/// and write the aleph data.
+async fn install_deployment(
     state: &State,
     rootfs: &RootSetup,
     sysroot: &Storage,
-    boot_uuid: &str,
-    bound_images: &[crate::boundimage::ResolvedBoundImage],
-) -> Result<()> {
+) -> Result<(ostree::Deployment, InstallResult)> {
     // And actually set up the container in that root, returning a deployment and
     // the aleph state (see below).
-    let (_deployment, aleph) = install_container(state, rootfs, &sysroot).await?;
+    let (deployment, aleph, result) = install_container(state, rootfs, &sysroot).await?;
     // Write the aleph data that captures the system state at the time of provisioning for aid in future debugging.
     rootfs
         .rootfs_fd
@@ -1284,38 +1900,136 @@ async fn install_with_sysroot(
             anyhow::Ok(())
         })
         .context("Writing aleph version")?;
+    Ok((deployment, result))
+}
 
-    if cfg!(target_arch = "s390x") {
-        // TODO: Integrate s390x support into install_via_bootupd
-        crate::bootloader::install_via_zipl(&rootfs.device_info, boot_uuid)?;
+/// Install the bootloader for the deployment, unless skipped.
+fn install_bootloader(
+    state: &State,
+    rootfs: &RootSetup,
+    sysroot: &Storage,
+    deployment: &ostree::Deployment,
+    bootloader: crate::bootloader::Bootloader,
+) -> Result<()> {
+    send_phase(state.progress.as_ref(), "bootloader");
+    let boot_uuid = rootfs.boot_uuid()?;
+    // When installing for another architecture, the bootloader files must come from the
+    // deployment instead of this container
+    let cross_arch_root = (state.target_arch != std::env::consts::ARCH).then(|| {
+        rootfs
+            .rootfs
+            .join(sysroot.deployment_dirpath(deployment).as_str())
+    });
+    if cross_arch_root.is_some()
+        && !rootfs.skip_bootloader
+        && bootloader != crate::bootloader::Bootloader::Grub
+    {
+        anyhow::bail!("Installing {bootloader} is not supported for another architecture");
+    }
+    let esp_rel = Utf8Path::new("boot").join(crate::bootloader::EFI_DIR);
+    let esp_path = rootfs.rootfs.join(&esp_rel);
+    if bootloader == crate::bootloader::Bootloader::SystemdBoot
+        && ostree_ext::mountutil::is_mountpoint(&rootfs.rootfs_fd, &esp_rel)? != Some(true)
+    {
+        anyhow::bail!("Installing systemd-boot requires the ESP to be mounted at /{esp_rel}");
+    }
+    if rootfs.skip_bootloader {
+        println!("Skipping bootloader installation");
     } else {
-        crate::bootloader::install_via_bootupd(
-            &rootfs.device_info,
-            &rootfs.rootfs,
-            &state.config_opts,
-        )?;
+        println!("Installing bootloader: {bootloader}");
+        match bootloader {
+            crate::bootloader::Bootloader::SystemdBoot => {
+                crate::systemd_boot::install_systemd_boot(
+                    &esp_path,
+                    state.config_opts.generic_image,
+                )?;
+            }
+            crate::bootloader::Bootloader::Zipl => {
+                // TODO: Integrate s390x support into install_via_bootupd
+                crate::bootloader::install_via_zipl(&rootfs.device_info, boot_uuid)?;
+            }
+            crate::bootloader::Bootloader::Grub => {
+                // With RAID, each disk gets a bootloader so that any of them can boot
+                for device_info in
+                    std::iter::once(&rootfs.device_info).chain(&rootfs.mirror_device_info)
+                {
+                    crate::bootloader::install_via_bootupd(
+                        device_info,
+                        &rootfs.rootfs,
+                        &state.config_opts,
+                        cross_arch_root.as_deref(),
+                    )?;
+                }
+            }
+        }
     }
     tracing::debug!("Installed bootloader");
+    if bootloader == crate::bootloader::Bootloader::SystemdBoot {
+        let esp = rootfs.rootfs_fd.open_dir(&esp_rel)?;
+        let n = crate::systemd_boot::sync(sysroot, &esp)?;
+        tracing::debug!("Wrote {n} systemd-boot entries");
+    }
+    Ok(())
+}
+
+/// Find the logically bound images of the container, and verify each one is present
+/// in the container storage.
+async fn resolve_bound_images(state: &State) -> Result<Vec<crate::boundimage::ResolvedBoundImage>> {
+    let bound_images = if state.config_opts.skip_bound_images {
+        Vec::new()
+    } else {
+        crate::boundimage::query_bound_images(&state.container_root)?
+    };
+    tracing::debug!("bound images={bound_images:?}");
+
+    let mut r = Vec::with_capacity(bound_images.len());
+    for image in bound_images {
+        let resolved = crate::boundimage::ResolvedBoundImage::from_image(&image).await?;
+        tracing::debug!("Resolved {}: {}", resolved.image, resolved.digest);
+        r.push(resolved)
+    }
+    Ok(r)
+}
 
+/// Copy the bound images from the host's container storage into the target.
+async fn install_bound_images(
+    state: &State,
+    sysroot: &Storage,
+    bound_images: &[crate::boundimage::ResolvedBoundImage],
+) -> Result<()> {
     tracing::debug!("Perfoming post-deployment operations");
+    send_phase(state.progress.as_ref(), "bound-images");
     // Note that we *always* initialize this container storage, even
     // if there are no bound images today.
     let imgstore = sysroot.get_ensure_imgstore()?;
-    // Now copy each bound image from the host's container storage into the target.
     for image in bound_images {
         let image = image.image.as_str();
         imgstore.pull_from_host_storage(image).await?;
     }
-
     Ok(())
 }
 
-async fn install_to_filesystem_impl(state: &State, rootfs: &mut RootSetup) -> Result<()> {
+/// Given a baseline root filesystem with an ostree sysroot initialized:
+/// - install the container to that root
+/// - install the bootloader
+/// - Other post operations, such as pulling bound images
+async fn install_with_sysroot(
+    state: &State,
+    rootfs: &RootSetup,
+    sysroot: &Storage,
+    bound_images: &[crate::boundimage::ResolvedBoundImage],
+) -> Result<InstallResult> {
+    let (deployment, result) = install_deployment(state, rootfs, sysroot).await?;
+    install_bootloader(state, rootfs, sysroot, &deployment, result.bootloader)?;
+    install_bound_images(state, sysroot, bound_images).await?;
+    Ok(result)
+}
+
+/// Apply the global state to the root setup, and warn about unusual setups.
+fn prepare_root_setup(state: &State, rootfs: &mut RootSetup) -> Result<()> {
     if matches!(state.selinux_state, SELinuxFinalState::ForceTargetDisabled) {
         rootfs.kargs.push("selinux=0".to_string());
     }
-    // Drop exclusive ownership since we're done with mutation
-    let rootfs = &*rootfs;
 
     match &rootfs.device_info.label {
         crate::blockdev::PartitionType::Dos => crate::utils::medium_visibility_warning(
@@ -1324,46 +2038,24 @@ async fn install_to_filesystem_impl(state: &State, rootfs: &mut RootSetup) -> Re
         crate::blockdev::PartitionType::Gpt => {
             // The only thing we should be using in general
         }
+        crate::blockdev::PartitionType::Dasd => {
+            // The only option for DASDs on s390x
+        }
         crate::blockdev::PartitionType::Unknown(o) => {
             crate::utils::medium_visibility_warning(&format!("Unknown partition label {o}"))
         }
     }
 
     // We verify this upfront because it's currently required by bootupd
-    let boot_uuid = rootfs
-        .get_boot_uuid()?
-        .or(rootfs.rootfs_uuid.as_deref())
-        .ok_or_else(|| anyhow!("No uuid for boot/root"))?;
+    let boot_uuid = rootfs.boot_uuid()?;
     tracing::debug!("boot uuid={boot_uuid}");
+    Ok(())
+}
 
-    let bound_images = if state.config_opts.skip_bound_images {
-        Vec::new()
-    } else {
-        crate::boundimage::query_bound_images(&state.container_root)?
-    };
-    tracing::debug!("bound images={bound_images:?}");
-
-    // Verify each bound image is present in the container storage
-    let bound_images = {
-        let mut r = Vec::with_capacity(bound_images.len());
-        for image in bound_images {
-            let resolved = crate::boundimage::ResolvedBoundImage::from_image(&image).await?;
-            tracing::debug!("Resolved {}: {}", resolved.image, resolved.digest);
-            r.push(resolved)
-        }
-        r
-    };
-
-    // Initialize the ostree sysroot (repo, stateroot, etc.)
-    {
-        let sysroot = initialize_ostree_root(state, rootfs).await?;
-        install_with_sysroot(state, rootfs, &sysroot, &boot_uuid, &bound_images).await?;
-        // We must drop the sysroot here in order to close any open file
-        // descriptors.
-    }
-
-    // Finalize mounted filesystems
+/// Finalize the mounted filesystems, and report the result of the installation.
+fn complete_install(state: &State, rootfs: &RootSetup, result: &InstallResult) -> Result<()> {
     if !rootfs.skip_finalize {
+        send_phase(state.progress.as_ref(), "finalize");
         let bootfs = rootfs.boot.as_ref().map(|_| rootfs.rootfs.join("boot"));
         let bootfs = bootfs.as_ref().map(|p| p.as_path());
         for fs in std::iter::once(rootfs.rootfs.as_path()).chain(bootfs) {
@@ -1371,21 +2063,115 @@ async fn install_to_filesystem_impl(state: &State, rootfs: &mut RootSetup) -> Re
         }
     }
 
+    if let Some(path) = state.config_opts.result_json.as_deref() {
+        let buf = serde_json::to_vec_pretty(result)?;
+        std::fs::write(path, buf).with_context(|| format!("Writing {path}"))?;
+    }
+    crate::progress_jsonl::send(
+        state.progress.as_ref(),
+        Event::InstallComplete {
+            result: serde_json::to_value(result)?,
+        },
+    );
     Ok(())
 }
 
+async fn install_to_filesystem_impl(state: &State, rootfs: &mut RootSetup) -> Result<()> {
+    prepare_root_setup(state, rootfs)?;
+    // Drop exclusive ownership since we're done with mutation
+    let rootfs = &*rootfs;
+
+    let bound_images = resolve_bound_images(state).await?;
+
+    // Initialize the ostree sysroot (repo, stateroot, etc.)
+    send_phase(state.progress.as_ref(), "ostree-init");
+    let result = {
+        let sysroot = initialize_ostree_root(state, rootfs).await?;
+        install_with_sysroot(state, rootfs, &sysroot, &bound_images).await?
+        // We must drop the sysroot here in order to close any open file
+        // descriptors.
+    };
+
+    complete_install(state, rootfs, &result)
+}
+
+/// The phases of an installation in order, with the approximate overall
+/// progress in percent when each one starts.
+const INSTALL_PHASES: &[(&str, u32)] = &[
+    ("partitioning", 0),
+    ("mkfs", 5),
+    ("ostree-init", 10),
+    ("pull", 15),
+    ("deploy", 70),
+    ("bootloader", 80),
+    ("bound-images", 85),
+    ("finalize", 95),
+];
+
+/// Report the start of an installation phase.
+pub(crate) fn send_phase(progress: Option<&ProgressWriter>, name: &'static str) {
+    let percent = INSTALL_PHASES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, p)| *p);
+    debug_assert!(percent.is_some(), "Unknown install phase {name}");
+    crate::progress_jsonl::send(progress, Event::Phase { name, percent });
+}
+
 fn installation_complete() {
     println!("Installation complete!");
 }
 
+/// Create the target file of `--via-loopback` if necessary, and grow it to the
+/// given size without allocating any space.
+#[context("Preparing {path}")]
+fn prepare_loopback_file(path: &Utf8Path, size: u64) -> Result<()> {
+    let f = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    let current = f.metadata()?.len();
+    if current > size {
+        anyhow::bail!("Existing file is larger ({current} bytes) than the requested size");
+    }
+    f.set_len(size)?;
+    Ok(())
+}
+
 /// Implementation of the `bootc install to-disk` CLI command.
 #[context("Installing to disk")]
 pub(crate) async fn install_to_disk(mut opts: InstallToDiskOpts) -> Result<()> {
     let mut block_opts = opts.block_opts;
-    let target_blockdev_meta = block_opts
-        .device
+    if opts.via_loopback && block_opts.device_match.is_some() {
+        anyhow::bail!("--device-match cannot be used with --via-loopback");
+    }
+    block_opts.resolve_device()?;
+    let device = block_opts.device()?.to_owned();
+    let target_arch = target_arch_name(&opts.target_opts)?;
+    let loopback_size = opts
+        .size
+        .as_deref()
+        .map(|size| {
+            let mib = crate::blockdev::parse_size_mib(size).context("Parsing --size")?;
+            if mib == 0 {
+                anyhow::bail!("Invalid --size: must not be zero");
+            }
+            Ok(mib * 1024 * 1024)
+        })
+        .transpose()?;
+    if let Some(size) = loopback_size {
+        if !opts.via_loopback {
+            anyhow::bail!("--size requires --via-loopback");
+        }
+        if opts.print_plan {
+            return baseline::print_plan(&block_opts, size, target_arch);
+        }
+        prepare_loopback_file(&device, size)?;
+    }
+    let target_blockdev_meta = device
         .metadata()
-        .with_context(|| format!("Querying {}", &block_opts.device))?;
+        .with_context(|| format!("Querying {device}"))?;
     if opts.via_loopback {
         if !opts.config_opts.generic_image {
             crate::utils::medium_visibility_warning(
@@ -1394,30 +2180,48 @@ pub(crate) async fn install_to_disk(mut opts: InstallToDiskOpts) -> Result<()> {
             opts.config_opts.generic_image = true;
         }
         if !target_blockdev_meta.file_type().is_file() {
-            anyhow::bail!(
-                "Not a regular file (to be used via loopback): {}",
-                block_opts.device
-            );
+            anyhow::bail!("Not a regular file (to be used via loopback): {device}");
         }
         if !crate::mount::is_same_as_host(Utf8Path::new("/dev"))? {
             anyhow::bail!("Loopback mounts (--via-loopback) require host devices (-v /dev:/dev)");
         }
     } else if !target_blockdev_meta.file_type().is_block_device() {
-        anyhow::bail!("Not a block device: {}", block_opts.device);
+        anyhow::bail!("Not a block device: {device}");
+    }
+    if opts.via_loopback && !block_opts.mirror_devices.is_empty() {
+        anyhow::bail!("Multiple devices cannot be used with --via-loopback");
+    }
+    for dev in block_opts.mirror_devices.iter() {
+        let meta = dev.metadata().with_context(|| format!("Querying {dev}"))?;
+        if !meta.file_type().is_block_device() {
+            anyhow::bail!("Not a block device: {dev}");
+        }
+    }
+    if opts.print_plan {
+        let size = if opts.via_loopback {
+            target_blockdev_meta.len()
+        } else {
+            crate::blockdev::list_dev(&device)?.size
+        };
+        return baseline::print_plan(&block_opts, size, target_arch);
     }
     let state = prepare_install(opts.config_opts, opts.source_opts, opts.target_opts).await?;
+    if state.config_opts.print_kargs {
+        return print_kargs(&state);
+    }
+    block_opts.confirm_device()?;
 
     // This is all blocking stuff
     let (mut rootfs, loopback) = {
         let loopback_dev = if opts.via_loopback {
-            let loopback_dev =
-                crate::blockdev::LoopbackDevice::new(block_opts.device.as_std_path())?;
-            block_opts.device = loopback_dev.path().into();
+            let loopback_dev = crate::blockdev::LoopbackDevice::new(device.as_std_path())?;
+            block_opts.device = Some(loopback_dev.path().into());
             Some(loopback_dev)
         } else {
             None
         };
 
+        send_phase(state.progress.as_ref(), "partitioning");
         let state = state.clone();
         let rootfs = tokio::task::spawn_blocking(move || {
             baseline::install_create_rootfs(&state, block_opts)
@@ -1426,17 +2230,52 @@ pub(crate) async fn install_to_disk(mut opts: InstallToDiskOpts) -> Result<()> {
         (rootfs, loopback_dev)
     };
 
-    install_to_filesystem_impl(&state, &mut rootfs).await?;
+    // The installed system is verified while still mounted; booting it has to wait
+    // until it is unmounted, so the kernel and initramfs are copied out.
+    let qemu = opts
+        .verify_boot
+        .then(|| {
+            let qemu = verify::find_qemu(state.target_arch);
+            if qemu.is_none() {
+                eprintln!(
+                    "warning: qemu-system-{} not found; skipping --verify-boot",
+                    state.target_arch
+                );
+            }
+            qemu
+        })
+        .flatten();
+    let r = install_to_filesystem_impl(&state, &mut rootfs)
+        .await
+        .and_then(|()| {
+            if !opts.verify {
+                return Ok(None);
+            }
+            let entry = verify::verify_installation(&rootfs.rootfs_fd)?;
+            if qemu.is_some() && rootfs.crypttab.is_some() {
+                eprintln!("warning: Skipping --verify-boot, which is not supported with an encrypted root");
+                return Ok(None);
+            }
+            qemu.is_some()
+                .then(|| verify::stage_boot_files(&rootfs.rootfs_fd, entry))
+                .transpose()
+        });
 
     // Drop all data about the root except the bits we need to ensure any file descriptors etc. are closed.
-    let (root_path, luksdev) = rootfs.into_storage();
-    Task::new_and_run(
+    // This also happens on failure, so that e.g. a loopback device can be detached.
+    let device = rootfs.device_info.path().to_owned();
+    let (root_path, block_stack) = rootfs.into_storage();
+    let teardown = Task::new_and_run(
         "Unmounting filesystems",
         "umount",
         ["-R", root_path.as_str()],
-    )?;
-    if let Some(luksdev) = luksdev.as_deref() {
-        Task::new_and_run("Closing root LUKS device", "cryptsetup", ["close", luksdev])?;
+    )
+    .and_then(|()| block_stack.deactivate());
+    let boot_files = r?;
+    teardown?;
+
+    if let (Some(qemu), Some(boot_files)) = (qemu.as_deref(), boot_files.as_ref()) {
+        verify::smoke_boot(qemu, state.target_arch, &device, boot_files)?;
     }
 
     if let Some(loopback_dev) = loopback {
@@ -1456,6 +2295,87 @@ pub(crate) async fn install_to_disk(mut opts: InstallToDiskOpts) -> Result<()> {
     Ok(())
 }
 
+/// Implementation of `bootc install from-config`.
+pub(crate) async fn install_from_config(opts: blueprint::InstallFromConfigOpts) -> Result<()> {
+    let blueprint = blueprint::Blueprint::load(&opts.path)?;
+    let device = blueprint.target.resolve()?;
+    println!("Target disk: {device}");
+    let mut disk_opts = blueprint.to_disk_opts(&device)?;
+    disk_opts.print_plan |= opts.print_plan;
+    install_to_disk(disk_opts).await
+}
+
+/// Implementation of the `bootc image build-disk` CLI command: install to a
+/// temporary file via loopback, then convert it to the requested format.
+#[context("Building disk image")]
+pub(crate) async fn build_disk(opts: BuildDiskOpts) -> Result<()> {
+    let format = opts.format.as_qemu_img_format();
+    let output = opts.output.as_path();
+    if opts.format != DiskImageFormat::Raw {
+        // Check this upfront instead of after a complete installation
+        Task::new_quiet("qemu-img")
+            .arg("--version")
+            .quiet_output()
+            .run()
+            .context("qemu-img is required for formats other than raw")?;
+    }
+    let parent = output
+        .parent()
+        .filter(|p| !p.as_str().is_empty())
+        .unwrap_or(Utf8Path::new("."));
+    // The raw image is written next to the output, so that it can be renamed into place
+    let raw = tempfile::Builder::new()
+        .prefix(".bootc-disk-")
+        .suffix(".raw")
+        .tempfile_in(parent)
+        .with_context(|| format!("Creating temporary file in {parent}"))?;
+    let raw_path = Utf8Path::from_path(raw.path())
+        .ok_or_else(|| anyhow!("Invalid non-UTF8 path {:?}", raw.path()))?
+        .to_owned();
+
+    let block_opts = InstallBlockDeviceOpts {
+        device: Some(raw_path.clone()),
+        device_match: None,
+        yes: false,
+        wipe: Default::default(),
+        discard: false,
+        preserve_partitions: Vec::new(),
+        block_setup: None,
+        mirror_devices: Vec::new(),
+        encrypt: Vec::new(),
+        encrypt_passphrase_file: None,
+        encrypt_tang_url: None,
+        encrypt_tang_thumbprint: None,
+        filesystem: opts.filesystem,
+        root_size: None,
+        var_device: None,
+    };
+    install_to_disk(InstallToDiskOpts {
+        block_opts,
+        source_opts: opts.source_opts,
+        target_opts: opts.target_opts,
+        config_opts: opts.config_opts,
+        via_loopback: true,
+        size: Some(opts.size),
+        print_plan: false,
+        verify: false,
+        verify_boot: false,
+    })
+    .await?;
+
+    if opts.format == DiskImageFormat::Raw {
+        raw.persist(output)
+            .with_context(|| format!("Writing {output}"))?;
+    } else {
+        Task::new(format!("Converting to {format}"), "qemu-img")
+            .args(["convert", "-f", "raw", "-O", format])
+            .args([raw_path.as_str(), output.as_str()])
+            .run()?;
+    }
+    println!("Wrote {format} disk image: {output}");
+    Ok(())
+}
+
 #[context("Verifying empty rootfs")]
 fn require_empty_rootdir(rootfs_fd: &Dir) -> Result<()> {
     for e in rootfs_fd.entries()? {
@@ -1574,6 +2494,117 @@ fn warn_on_host_root(rootfs_fd: &Dir) -> Result<()> {
     Ok(())
 }
 
+/// The number of a partition, from its device node (e.g. `/dev/nvme0n1p3` is 3).
+fn partno_of(node: &str) -> Result<u32> {
+    let digits = node.len() - node.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    node[node.len() - digits..]
+        .parse()
+        .with_context(|| format!("Finding partition number of {node}"))
+}
+
+/// The command to grow a mounted filesystem of the given type to the size of its device.
+fn grow_fs_command(fstype: &str, dev: &str, mountpoint: &Utf8Path) -> Result<Vec<String>> {
+    let r = match fstype {
+        "xfs" => vec!["xfs_growfs".into(), mountpoint.to_string()],
+        "ext4" => vec!["resize2fs".into(), dev.to_owned()],
+        "btrfs" => vec![
+            "btrfs".into(),
+            "filesystem".into(),
+            "resize".into(),
+            "max".into(),
+            mountpoint.to_string(),
+        ],
+        o => anyhow::bail!("Resizing filesystem type {o} is not supported"),
+    };
+    Ok(r)
+}
+
+/// Grow the partition containing the root filesystem to fill the disk, and the
+/// filesystem to fill the partition.
+#[context("Resizing root")]
+fn grow_root(
+    inspect: &crate::mount::Filesystem,
+    backing_device: &str,
+    mountpoint: &Utf8Path,
+) -> Result<()> {
+    if crate::blockdev::find_parent_devices(&inspect.source)?.as_slice() != [backing_device] {
+        anyhow::bail!(
+            "The root filesystem {} must be directly on a partition of {backing_device}",
+            inspect.source
+        );
+    }
+    let partno = partno_of(&inspect.source)?;
+    // Keep the start of the partition, and extend it to the end of the disk
+    Task::new("Growing root partition", "sfdisk")
+        .args(["--no-reread", "-N", &partno.to_string(), backing_device])
+        .quiet()
+        .run_with_stdin_buf(Some(b", +\n"))?;
+    Task::new("Updating partition table", "partx")
+        .args(["-u", backing_device])
+        .run()?;
+    crate::blockdev::udev_settle()?;
+    let argv = grow_fs_command(&inspect.fstype, &inspect.source, mountpoint)?;
+    Task::new("Growing root filesystem", &argv[0])
+        .args(&argv[1..])
+        .verbose()
+        .run()?;
+    Ok(())
+}
+
+/// If /home is a separate filesystem in the target, return the mount for it in the
+/// installed system, where /home is usually a symlink to /var/home.
+fn find_home_mount(rootfs_fd: &Dir, rootfs: &Utf8Path) -> Result<Option<MountSpec>> {
+    if ostree_ext::mountutil::is_mountpoint(rootfs_fd, "home")? != Some(true) {
+        return Ok(None);
+    }
+    let home = crate::mount::inspect_filesystem(&rootfs.join("home"))?;
+    let uuid = home
+        .uuid
+        .as_deref()
+        .ok_or_else(|| anyhow!("No filesystem uuid found for /home"))?;
+    println!("Mounting existing /home filesystem at /var/home");
+    Ok(Some(MountSpec::new_uuid_src(uuid, "/var/home")))
+}
+
+/// Copy the contents of /home in the physical root to /var/home of the stateroot.
+/// The original /home is left in place, as the installing system may still use it.
+#[context("Preserving /home")]
+fn copy_home_to_stateroot(state: &State, rootfs_fd: &Dir) -> Result<()> {
+    if !rootfs_fd
+        .symlink_metadata_optional("home")?
+        .is_some_and(|m| m.is_dir())
+    {
+        return Ok(());
+    }
+    let home = rootfs_fd.open_dir("home")?;
+    let stateroot_path = format!("ostree/deploy/{}", state.stateroot());
+    let sepolicy = state.load_policy()?;
+    let stateroot = rootfs_fd.open_dir(&stateroot_path)?;
+    crate::lsm::ensure_dir_labeled(
+        &stateroot,
+        "var/home",
+        None,
+        0o755.into(),
+        sepolicy.as_ref(),
+    )?;
+    let target = stateroot.open_dir("var/home")?;
+    for entry in home.entries()? {
+        let name = entry?.file_name();
+        let name_str = name.to_string_lossy();
+        if target.symlink_metadata_optional(&name)?.is_some() {
+            eprintln!("warning: Not preserving /home/{name_str}; it exists in /var/home");
+            continue;
+        }
+        Task::new(format!("Copying /home/{name_str} to /var/home"), "cp")
+            .cwd(rootfs_fd)?
+            .args(["-a", "--reflink=auto", "--"])
+            .arg(Path::new("home").join(&name))
+            .arg(format!("{stateroot_path}/var/home/"))
+            .run()?;
+    }
+    Ok(())
+}
+
 /// Implementation of the `bootc install to-filsystem` CLI command.
 #[context("Installing to filesystem")]
 pub(crate) async fn install_to_filesystem(
@@ -1581,8 +2612,37 @@ pub(crate) async fn install_to_filesystem(
     targeting_host_root: bool,
 ) -> Result<()> {
     let fsopts = opts.filesystem_opts;
-    let root_path = &fsopts.root_path;
+    let rootfs_fd = open_target_root(&fsopts.root_path)?;
+
+    // Gather global state, destructuring the provided options.
+    // IMPORTANT: We might re-execute the current process in this function (for SELinux among other things)
+    // IMPORTANT: and hence anything that is done before MUST BE IDEMPOTENT.
+    // IMPORTANT: In practice, we should only be gathering information before this point,
+    // IMPORTANT: and not performing any mutations at all.
+    let state = prepare_install(opts.config_opts, opts.source_opts, opts.target_opts).await?;
+    if state.config_opts.print_kargs {
+        return print_kargs(&state);
+    }
+
+    let (mut rootfs, copy_home) =
+        setup_filesystem_root(fsopts, rootfs_fd, targeting_host_root).await?;
+
+    install_to_filesystem_impl(&state, &mut rootfs).await?;
+
+    if copy_home {
+        copy_home_to_stateroot(&state, &rootfs.rootfs_fd)?;
+    }
+
+    // Drop all data about the root except the path to ensure any file descriptors etc. are closed.
+    drop(rootfs);
 
+    installation_complete();
+
+    Ok(())
+}
+
+/// Open the mounted target root filesystem of `install to-filesystem`.
+fn open_target_root(root_path: &Utf8Path) -> Result<Dir> {
     let st = root_path
         .symlink_metadata()
         .with_context(|| format!("Querying target filesystem {root_path}"))?;
@@ -1594,13 +2654,18 @@ pub(crate) async fn install_to_filesystem(
     if let Some(false) = ostree_ext::mountutil::is_mountpoint(&rootfs_fd, ".")? {
         anyhow::bail!("Not a mountpoint: {root_path}");
     }
+    Ok(rootfs_fd)
+}
 
-    // Gather global state, destructuring the provided options.
-    // IMPORTANT: We might re-execute the current process in this function (for SELinux among other things)
-    // IMPORTANT: and hence anything that is done before MUST BE IDEMPOTENT.
-    // IMPORTANT: In practice, we should only be gathering information before this point,
-    // IMPORTANT: and not performing any mutations at all.
-    let state = prepare_install(opts.config_opts, opts.source_opts, opts.target_opts).await?;
+/// Prepare the externally set up root filesystem of `install to-filesystem` according
+/// to the replace mode, and gather the root setup from it.  Also returns whether the
+/// contents of `/home` should be copied to the stateroot after installing.
+async fn setup_filesystem_root(
+    fsopts: InstallTargetFilesystemOpts,
+    rootfs_fd: Dir,
+    targeting_host_root: bool,
+) -> Result<(RootSetup, bool)> {
+    let root_path = &fsopts.root_path;
 
     // Check to see if this happens to be the real host root
     if !fsopts.acknowledge_destructive {
@@ -1690,7 +2755,7 @@ pub(crate) async fn install_to_filesystem(
     // Find the real underlying backing device for the root.  This is currently just required
     // for GRUB (BIOS) and in the future zipl (I think).
     let backing_device = {
-        let mut dev = inspect.source;
+        let mut dev = inspect.source.clone();
         loop {
             tracing::debug!("Finding parents for {dev}");
             let mut parents = crate::blockdev::find_parent_devices(&dev)?.into_iter();
@@ -1707,6 +2772,9 @@ pub(crate) async fn install_to_filesystem(
         dev
     };
     tracing::debug!("Backing device: {backing_device}");
+    if fsopts.resize_root {
+        grow_root(&inspect, &backing_device, root_path)?;
+    }
     let device_info = crate::blockdev::partitions_of(Utf8Path::new(&backing_device))?;
 
     let rootarg = format!("root={}", root_info.mount_spec);
@@ -1735,24 +2803,31 @@ pub(crate) async fn install_to_filesystem(
     let skip_finalize =
         matches!(fsopts.replace, Some(ReplaceMode::Alongside)) || fsopts.skip_finalize;
     let mut rootfs = RootSetup {
-        luks_device: None,
+        block_stack: BlockStack::default(),
         device_info,
+        mirror_device_info: Vec::new(),
         rootfs: fsopts.root_path,
         rootfs_fd,
         rootfs_uuid: inspect.uuid.clone(),
         boot,
+        mounts: Vec::new(),
+        crypttab: None,
         kargs,
         skip_finalize,
+        skip_bootloader: fsopts.skip_bootloader,
     };
 
-    install_to_filesystem_impl(&state, &mut rootfs).await?;
-
-    // Drop all data about the root except the path to ensure any file descriptors etc. are closed.
-    drop(rootfs);
-
-    installation_complete();
+    // When taking over the host, keep the data in /home
+    let preserve_home = targeting_host_root && fsopts.replace == Some(ReplaceMode::Alongside);
+    let mut copy_home = false;
+    if preserve_home {
+        match find_home_mount(&rootfs.rootfs_fd, &rootfs.rootfs)? {
+            Some(mount) => rootfs.mounts.push(mount),
+            None => copy_home = true,
+        }
+    }
 
-    Ok(())
+    Ok((rootfs, copy_home))
 }
 
 pub(crate) async fn install_to_existing_root(opts: InstallToExistingRootOpts) -> Result<()> {
@@ -1763,7 +2838,9 @@ pub(crate) async fn install_to_existing_root(opts: InstallToExistingRootOpts) ->
             boot_mount_spec: None,
             replace: opts.replace,
             skip_finalize: true,
+            skip_bootloader: false,
             acknowledge_destructive: opts.acknowledge_destructive,
+            resize_root: opts.resize_root,
         },
         source_opts: opts.source_opts,
         target_opts: opts.target_opts,
@@ -1779,7 +2856,7 @@ fn install_opts_serializable() {
         "device": "/dev/vda"
     }))
     .unwrap();
-    assert_eq!(c.block_opts.device, "/dev/vda");
+    assert_eq!(c.block_opts.device.unwrap(), "/dev/vda");
 }
 
 #[test]
@@ -1792,6 +2869,63 @@ fn test_mountspec() {
     assert_eq!(ms.to_fstab(), "/dev/vda4 /boot auto ro,relatime 0 0");
 }
 
+#[test]
+fn test_target_arch_name() -> Result<()> {
+    #[derive(clap::Parser)]
+    struct Opts {
+        #[clap(flatten)]
+        target: InstallTargetOpts,
+    }
+    let parse = |args: &[&str]| {
+        let o = <Opts as clap::Parser>::try_parse_from(
+            std::iter::once("test").chain(args.iter().copied()),
+        )
+        .unwrap();
+        target_arch_name(&o.target)
+    };
+    assert_eq!(parse(&[])?, std::env::consts::ARCH);
+    assert_eq!(parse(&["--target-arch", "arm64"])?, "aarch64");
+    assert_eq!(parse(&["--target-arch", "x86_64"])?, "x86_64");
+    assert!(parse(&["--target-arch", "riscv64"]).is_err());
+    assert!(parse(&["--target-arch", "arm/v7"]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_render_kargs() -> Result<()> {
+    let sources = [
+        ("install configuration", vec!["console=ttyS0".to_owned()]),
+        ("kargs.d", vec![]),
+        ("--karg", vec!["nosmt".to_owned(), "foo=bar".to_owned()]),
+    ];
+    let mut out = Vec::new();
+    render_kargs(&sources, &mut out)?;
+    similar_asserts::assert_eq!(
+        String::from_utf8(out)?,
+        indoc::indoc! { "
+            install configuration: console=ttyS0
+            kargs.d: (none)
+            --karg: nosmt foo=bar
+            merged: console=ttyS0 nosmt foo=bar
+        " }
+    );
+    Ok(())
+}
+
+#[test]
+fn test_parse_karg_file() {
+    let contents = indoc::indoc! {"
+        # Serial console
+        console=ttyS0,115200n8
+        nosmt  rd.luks.options=discard
+    "};
+    assert_eq!(
+        parse_karg_file(contents),
+        ["console=ttyS0,115200n8", "nosmt", "rd.luks.options=discard"]
+    );
+    assert!(parse_karg_file("").is_empty());
+}
+
 #[test]
 fn test_gather_root_args() {
     // A basic filesystem using a UUID
@@ -1822,3 +2956,37 @@ fn test_gather_root_args() {
     assert_eq!(r.kargs.len(), 1);
     assert_eq!(r.kargs[0], "rd.lvm.lv=root");
 }
+
+#[test]
+fn test_partno_of() -> Result<()> {
+    assert_eq!(partno_of("/dev/vda3")?, 3);
+    assert_eq!(partno_of("/dev/nvme0n1p12")?, 12);
+    assert_eq!(partno_of("/dev/mmcblk0p2")?, 2);
+    assert!(partno_of("/dev/sda").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_grow_fs_command() -> Result<()> {
+    let mnt = Utf8Path::new("/target");
+    assert_eq!(
+        grow_fs_command("xfs", "/dev/vda3", mnt)?,
+        ["xfs_growfs", "/target"]
+    );
+    assert_eq!(
+        grow_fs_command("ext4", "/dev/vda3", mnt)?,
+        ["resize2fs", "/dev/vda3"]
+    );
+    assert_eq!(
+        grow_fs_command("btrfs", "/dev/vda3", mnt)?,
+        ["btrfs", "filesystem", "resize", "max", "/target"]
+    );
+    assert!(grow_fs_command("vfat", "/dev/vda3", mnt).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_install_phases() {
+    assert!(INSTALL_PHASES.windows(2).all(|w| w[0].1 < w[1].1));
+    assert!(INSTALL_PHASES.iter().all(|(_, p)| *p < 100));
+}
@@ -4,11 +4,15 @@
 //! it's very simple - just a direct filesystem (e.g. xfs, ext4, btrfs etc.).  It is
 //! intended to add opinionated handling of TPM2-bound LUKS too.  But that's about it;
 //! other more complex flows should set things up externally and use `bootc install to-filesystem`.
+//!
+//! The partition layout can be adjusted via `[install.partitions]` in the install
+//! configuration (see [`super::config::Partitions`]); it is turned into a
+//! [`PartitionPlan`], which is validated before anything is written.
 
 use std::borrow::Cow;
 use std::fmt::Display;
 use std::fmt::Write as _;
-use std::io::Write;
+use std::io::{BufRead, IsTerminal, Write};
 use std::process::Stdio;
 
 use anyhow::Ok;
@@ -21,6 +25,8 @@ use clap::ValueEnum;
 use fn_error_context::context;
 use serde::{Deserialize, Serialize};
 
+use super::config::{ExtraPartition, InstallConfiguration, Partitions, RootFS};
+use super::BlockStack;
 use super::MountSpec;
 use super::RootSetup;
 use super::State;
@@ -34,6 +40,41 @@ pub(crate) const BOOTPN_SIZE_MB: u32 = 510;
 pub(crate) const EFIPN_SIZE_MB: u32 = 512;
 /// The GPT type for "linux"
 pub(crate) const LINUX_PARTTYPE: &str = "0FC63DAF-8483-4772-8E79-3D69D8477DE4";
+/// The GPT type for the EFI system partition
+const ESP_PARTTYPE: &str = "C12A7328-F81F-11D2-BA4B-00A0C93EC93B";
+/// The GPT type for the BIOS boot partition
+const BIOS_BOOT_PARTTYPE: &str = "21686148-6449-6E6F-744E-656564454649";
+/// The GPT type for "linux swap"
+const SWAP_PARTTYPE: &str = "0657FD6D-A4AB-43C4-84E5-0933C84B4F4F";
+/// The GPT type for "linux RAID"
+const RAID_PARTTYPE: &str = "A19D880F-05FC-4D3B-A006-743F0F84911E";
+/// The GPT type for "linux LVM"
+const LVM_PARTTYPE: &str = "E6D6D379-F507-44C2-A23C-238F2A3DF928";
+/// The default name of the volume group.
+const DEFAULT_VG_NAME: &str = "bootc";
+/// The name of the thin pool.
+const THIN_POOL_NAME: &str = "pool";
+/// Space used by LVM metadata (and the thin pool metadata) in the physical volume.
+const LVM_OVERHEAD_MB: u64 = 128;
+/// The smallest ESP we create; below this, firmware may not accept the FAT filesystem.
+const EFIPN_MIN_SIZE_MB: u64 = 64;
+/// The smallest /boot we create, which must hold at least two kernels and initramfs images.
+const BOOTPN_MIN_SIZE_MB: u64 = 256;
+/// Space used by the partition table and alignment at the start and end of the disk.
+const PARTITION_TABLE_OVERHEAD_MB: u64 = 2;
+/// The number of partitions a DASD can hold.
+const DASD_MAX_PARTITIONS: usize = 3;
+/// The first track of a DASD available to partitions; the ones before hold the
+/// volume label and the VTOC.
+const DASD_FIRST_TRACK: u64 = 2;
+/// The conventional btrfs subvolume layout, as the subvolume name and where it is
+/// mounted; the first one holds the root filesystem.
+const BTRFS_SUBVOLUMES: &[(&str, &str)] = &[
+    ("root", "/"),
+    ("var", "/var"),
+    ("home", "/var/home"),
+    ("snapshots", "/var/.snapshots"),
+];
 
 #[derive(clap::ValueEnum, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -55,6 +96,65 @@ pub(crate) enum BlockSetup {
     #[default]
     Direct,
     Tpm2Luks,
+    Luks,
+    Raid1,
+}
+
+/// How the LUKS-encrypted root filesystem is unlocked.
+#[derive(clap::ValueEnum, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum EncryptionMethod {
+    /// A passphrase entered at boot
+    Passphrase,
+    /// The default TPM2 device, via systemd-cryptenroll
+    Tpm2,
+    /// A Tang server, via Clevis (network-bound disk encryption)
+    Tang,
+}
+
+impl Display for EncryptionMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value().unwrap().get_name().fmt(f)
+    }
+}
+
+/// How existing data on the target devices is handled.
+#[derive(clap::ValueEnum, Default, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum WipeMode {
+    /// Wipe the signatures of all partitions and the partition table
+    All,
+    /// Wipe only the partition table
+    GptOnly,
+    /// Fail if the device has partitions or a filesystem
+    #[default]
+    FailIfNotEmpty,
+}
+
+/// Accept the boolean `wipe` of older versions, where `true` means [`WipeMode::All`].
+fn deserialize_wipe<'de, D: serde::Deserializer<'de>>(d: D) -> Result<WipeMode, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Wipe {
+        Bool(bool),
+        Mode(WipeMode),
+    }
+    std::result::Result::Ok(match Wipe::deserialize(d)? {
+        Wipe::Bool(true) => WipeMode::All,
+        Wipe::Bool(false) => WipeMode::default(),
+        Wipe::Mode(mode) => mode,
+    })
+}
+
+impl Filesystem {
+    /// The longest filesystem label supported by `mkfs`.
+    fn max_label_len(&self) -> usize {
+        match self {
+            Filesystem::Xfs => 12,
+            Filesystem::Ext4 => 16,
+            Filesystem::Btrfs => 255,
+        }
+    }
 }
 
 impl Display for BlockSetup {
@@ -68,46 +168,1344 @@ impl Display for BlockSetup {
 #[serde(rename_all = "kebab-case")]
 pub(crate) struct InstallBlockDeviceOpts {
     /// Target block device for installation.  The entire device will be wiped.
-    pub(crate) device: Utf8PathBuf,
+    #[clap(required_unless_present = "device_match")]
+    pub(crate) device: Option<Utf8PathBuf>,
+
+    /// Select the target block device by its properties instead of its path, which
+    /// may change between boots.
+    ///
+    /// This is a comma separated list of terms which must all match:
+    /// `serial=`, `wwn=`, `model=`, `name=`, `tran=` (e.g. `nvme`) or `id=` (a name in
+    /// `/dev/disk/by-id`) followed by a pattern with `*` and `?` wildcards;
+    /// `min-size=` or `max-size=` followed by a size; `ssd` or `hdd`; and `smallest` or
+    /// `largest` to choose among multiple matching disks.
+    ///
+    /// Example: --device-match 'serial=S4EV*'  or  --device-match smallest-ssd
+    #[clap(long, conflicts_with = "device")]
+    pub(crate) device_match: Option<String>,
+
+    /// Install to the device selected by `--device-match` without asking for confirmation.
+    #[clap(long, requires = "device_match")]
+    #[serde(default)]
+    pub(crate) yes: bool,
+
+    /// How to handle existing data on the devices; `--wipe` alone is the same as `--wipe=all`.
+    ///
+    /// all: Wipe the signatures of all partitions and the partition table.
+    /// gpt-only: Wipe only the partition table, leaving mkfs to overwrite the partitions.
+    /// fail-if-not-empty: Fail if a device has partitions or a filesystem.
+    #[clap(
+        long,
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_value_t,
+        default_missing_value = "all"
+    )]
+    #[serde(default, deserialize_with = "deserialize_wipe")]
+    pub(crate) wipe: WipeMode,
 
-    /// Automatically wipe all existing data on device
+    /// Discard all blocks of the devices (or of the removed partitions) before
+    /// partitioning, if supported, e.g. by SSDs.
     #[clap(long)]
     #[serde(default)]
-    pub(crate) wipe: bool,
+    pub(crate) discard: bool,
+
+    /// Keep the existing partition with this GPT partition name or filesystem label,
+    /// and create the new partitions in the remaining free space; may be given multiple
+    /// times.  A preserved EFI system partition is used instead of creating one.  This
+    /// requires `--wipe=all` or `--wipe=gpt-only`, which then apply only to the other
+    /// partitions.
+    #[clap(long = "preserve-partition", value_name = "LABEL")]
+    #[serde(default)]
+    pub(crate) preserve_partitions: Vec<String>,
 
     /// Target root block device setup.
     ///
     /// direct: Filesystem written directly to block device
     /// tpm2-luks: Bind unlock of filesystem to presence of the default tpm2 device.
+    /// luks: Filesystem in LUKS, unlocked via the methods given with --encrypt.
+    /// raid1: Filesystems on md RAID1 arrays mirrored across all given devices.
     #[clap(long, value_enum)]
     pub(crate) block_setup: Option<BlockSetup>,
 
+    /// Additional target block devices for `--block-setup raid1`, which are wiped
+    /// and partitioned like the first one.
+    #[serde(default)]
+    pub(crate) mirror_devices: Vec<Utf8PathBuf>,
+
+    /// Encrypt the root filesystem with LUKS, unlocked via any of the given methods
+    /// (comma separated).  This implies `--block-setup=luks`.
+    ///
+    /// passphrase: A passphrase, read from --encrypt-passphrase-file
+    /// tpm2: Bind to the default tpm2 device
+    /// tang: Bind to the Tang server given via --encrypt-tang-url
+    #[clap(long, value_enum, value_delimiter = ',')]
+    #[serde(default)]
+    pub(crate) encrypt: Vec<EncryptionMethod>,
+
+    /// File containing the passphrase for `--encrypt=passphrase`; a trailing newline is ignored.
+    #[clap(long)]
+    pub(crate) encrypt_passphrase_file: Option<Utf8PathBuf>,
+
+    /// URL of the Tang server for `--encrypt=tang`.
+    #[clap(long)]
+    pub(crate) encrypt_tang_url: Option<String>,
+
+    /// Thumbprint of the signing key of the Tang server.  Without it, the key
+    /// advertised by the server is trusted.
+    #[clap(long)]
+    pub(crate) encrypt_tang_thumbprint: Option<String>,
+
     /// Target root filesystem type.
     #[clap(long, value_enum)]
     pub(crate) filesystem: Option<Filesystem>,
 
     /// Size of the root partition (default specifier: M).  Allowed specifiers: M (mebibytes), G (gibibytes), T (tebibytes).
     ///
-    /// By default, all remaining space on the disk will be used.
+    /// By default, all remaining space on the disk will be used.  This overrides
+    /// `root-size` in the install configuration.
     #[clap(long)]
     pub(crate) root_size: Option<String>,
+
+    /// Put /var on a single partition of this block device, which is wiped.  The label,
+    /// type and options of the `var` partition of the install configuration are used,
+    /// but not its size.
+    #[clap(long)]
+    pub(crate) var_device: Option<Utf8PathBuf>,
 }
 
 impl BlockSetup {
     /// Returns true if the block setup requires a separate /boot aka XBOOTLDR partition.
     pub(crate) fn requires_bootpart(&self) -> bool {
+        self.is_encrypted() || *self == BlockSetup::Raid1
+    }
+
+    /// Returns true if the root filesystem is encrypted.
+    pub(crate) fn is_encrypted(&self) -> bool {
         match self {
-            BlockSetup::Direct => false,
-            BlockSetup::Tpm2Luks => true,
+            BlockSetup::Direct | BlockSetup::Raid1 => false,
+            BlockSetup::Tpm2Luks | BlockSetup::Luks => true,
+        }
+    }
+}
+
+impl InstallBlockDeviceOpts {
+    /// The target block device.
+    pub(crate) fn device(&self) -> Result<&Utf8Path> {
+        self.device
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("No target device given"))
+    }
+
+    /// Resolve `--device-match` to the target block device.
+    #[context("Selecting target device")]
+    pub(crate) fn resolve_device(&mut self) -> Result<()> {
+        let Some(spec) = self.device_match.as_deref() else {
+            return Ok(());
+        };
+        let spec = spec.parse::<crate::blockdev::DeviceMatch>()?;
+        let dev = crate::blockdev::find_matching_disk(&spec)?;
+        let size = ostree_ext::glib::format_size(dev.size);
+        let details = [dev.model.as_deref(), dev.serial.as_deref()]
+            .into_iter()
+            .flatten()
+            .map(str::trim)
+            .chain(std::iter::once(size.as_str()))
+            .collect::<Vec<_>>();
+        println!("Selected device: {} ({})", dev.path(), details.join(", "));
+        self.device = Some(dev.path().into());
+        Ok(())
+    }
+
+    /// Confirm installing to the device selected by `--device-match`, unless `--yes` was given.
+    pub(crate) fn confirm_device(&self) -> Result<()> {
+        if self.device_match.is_none() || self.yes {
+            return Ok(());
+        }
+        let device = self.device()?;
+        if !std::io::stdin().is_terminal() {
+            anyhow::bail!("--device-match requires --yes when not running interactively");
+        }
+        let mut stdout = std::io::stdout().lock();
+        if self.preserve_partitions.is_empty() {
+            write!(stdout, "All data on {device} will be erased.")?;
+        } else {
+            write!(
+                stdout,
+                "All data on {device} except the partitions {} will be erased.",
+                self.preserve_partitions.join(", ")
+            )?;
+        }
+        write!(stdout, "\nContinue? [y/N] ")?;
+        stdout.flush()?;
+        let mut buf = String::new();
+        std::io::stdin().lock().read_line(&mut buf)?;
+        match buf.trim() {
+            "y" | "Y" | "yes" => Ok(()),
+            _ => anyhow::bail!("Installation cancelled"),
+        }
+    }
+
+    /// Determine how the root filesystem is unlocked for the given block setup,
+    /// and verify that the required options are set.
+    pub(crate) fn encryption_methods(
+        &self,
+        block_setup: BlockSetup,
+    ) -> Result<Vec<EncryptionMethod>> {
+        let methods = match block_setup {
+            BlockSetup::Direct | BlockSetup::Raid1 if self.encrypt.is_empty() => Vec::new(),
+            BlockSetup::Direct | BlockSetup::Raid1 => {
+                anyhow::bail!("--encrypt cannot be used with block setup {block_setup}")
+            }
+            BlockSetup::Tpm2Luks if self.encrypt.is_empty() => vec![EncryptionMethod::Tpm2],
+            BlockSetup::Tpm2Luks => {
+                anyhow::bail!("--encrypt cannot be used with block setup tpm2-luks; use luks")
+            }
+            BlockSetup::Luks if self.encrypt.is_empty() => {
+                anyhow::bail!("Block setup luks requires --encrypt")
+            }
+            BlockSetup::Luks => self.encrypt.iter().fold(Vec::new(), |mut v, m| {
+                if !v.contains(m) {
+                    v.push(*m);
+                }
+                v
+            }),
+        };
+        let uses = |m| methods.contains(&m);
+        match (
+            uses(EncryptionMethod::Passphrase),
+            self.encrypt_passphrase_file.is_some(),
+        ) {
+            (true, false) => {
+                anyhow::bail!("--encrypt=passphrase requires --encrypt-passphrase-file")
+            }
+            (false, true) => {
+                anyhow::bail!("--encrypt-passphrase-file requires --encrypt=passphrase")
+            }
+            _ => {}
+        }
+        match (
+            uses(EncryptionMethod::Tang),
+            self.encrypt_tang_url.is_some(),
+        ) {
+            (true, false) => anyhow::bail!("--encrypt=tang requires --encrypt-tang-url"),
+            (false, true) => anyhow::bail!("--encrypt-tang-url requires --encrypt=tang"),
+            _ => {}
+        }
+        if self.encrypt_tang_thumbprint.is_some() && self.encrypt_tang_url.is_none() {
+            anyhow::bail!("--encrypt-tang-thumbprint requires --encrypt-tang-url");
+        }
+        Ok(methods)
+    }
+}
+
+/// The `luks.options` kernel argument, and the options in `/etc/crypttab`.
+fn luks_options(methods: &[EncryptionMethod]) -> Option<String> {
+    let mut options = Vec::new();
+    if methods.contains(&EncryptionMethod::Tpm2) {
+        options.push("tpm2-device=auto");
+    }
+    // Unless there is something answering a password query (a human or the
+    // Clevis agent), don't wait for one.
+    if !methods
+        .iter()
+        .any(|m| matches!(m, EncryptionMethod::Passphrase | EncryptionMethod::Tang))
+    {
+        options.push("headless=true");
+    }
+    (!options.is_empty()).then(|| options.join(","))
+}
+
+/// Format the root partition with LUKS, enroll the given unlock methods, and open it
+/// as `/dev/mapper/{name}`.  Returns the LUKS UUID.
+fn setup_luks(
+    opts: &InstallBlockDeviceOpts,
+    devpath: &Utf8Path,
+    name: &str,
+    methods: &[EncryptionMethod],
+    passphrase: Option<&str>,
+) -> Result<String> {
+    let uuid = uuid::Uuid::new_v4().to_string();
+    // This is removed again once all methods are enrolled
+    let dummy_passphrase = uuid::Uuid::new_v4().to_string();
+    let mut tmp_keyfile = tempfile::NamedTempFile::new()?;
+    tmp_keyfile.write_all(dummy_passphrase.as_bytes())?;
+    tmp_keyfile.flush()?;
+    let tmp_keyfile = tmp_keyfile.path();
+
+    Task::new("Initializing LUKS for root", "cryptsetup")
+        .args(["luksFormat", "--uuid", uuid.as_str(), "--key-file"])
+        .args([tmp_keyfile])
+        .args([devpath])
+        .run()?;
+    // We use .verbose() for the enrollments as the details are important/notable.
+    for method in methods {
+        match method {
+            EncryptionMethod::Passphrase => {
+                let passphrase = passphrase.expect("passphrase");
+                let mut new_keyfile = tempfile::NamedTempFile::new()?;
+                new_keyfile.write_all(passphrase.as_bytes())?;
+                new_keyfile.flush()?;
+                Task::new("Enrolling passphrase for root device", "cryptsetup")
+                    .args(["luksAddKey", "--key-file"])
+                    .args([tmp_keyfile])
+                    .args([devpath.as_std_path(), new_keyfile.path()])
+                    .verbose()
+                    .run()?;
+            }
+            EncryptionMethod::Tpm2 => {
+                Task::new("Enrolling root device with TPM", "systemd-cryptenroll")
+                    .args(["--tpm2-device=auto", "--unlock-key-file"])
+                    .args([tmp_keyfile])
+                    .args([devpath])
+                    .verbose()
+                    .run()?;
+            }
+            EncryptionMethod::Tang => {
+                let url = opts.encrypt_tang_url.as_deref().expect("tang url");
+                let mut config = serde_json::json!({ "url": url });
+                if let Some(thp) = opts.encrypt_tang_thumbprint.as_deref() {
+                    config["thp"] = thp.into();
+                } else {
+                    crate::utils::medium_visibility_warning(&format!(
+                        "Trusting the advertised key of {url}; use --encrypt-tang-thumbprint to verify it"
+                    ));
+                }
+                Task::new("Binding root device to Tang", "clevis")
+                    .args(["luks", "bind", "-y", "-k"])
+                    .args([tmp_keyfile])
+                    .arg("-d")
+                    .arg(devpath)
+                    .args(["tang", config.to_string().as_str()])
+                    .verbose()
+                    .run()?;
+            }
+        }
+    }
+    Task::new("Opening root LUKS device", "cryptsetup")
+        .args(["luksOpen", "--key-file"])
+        .args([tmp_keyfile])
+        .args([devpath.as_str(), name])
+        .run()?;
+    Task::new("Removing temporary LUKS key", "cryptsetup")
+        .arg("luksRemoveKey")
+        .arg(devpath)
+        .arg(tmp_keyfile)
+        .run()?;
+    Ok(uuid)
+}
+
+/// What a partition is used for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum PartitionRole {
+    BiosBoot,
+    PrepBoot,
+    Esp,
+    Boot,
+    Swap,
+    Root,
+    Var,
+    Containers,
+    LvmPv,
+}
+
+impl PartitionRole {
+    /// Where the partition is mounted, in the style of `lsblk`.
+    fn mountpoint(&self) -> &'static str {
+        match self {
+            PartitionRole::BiosBoot | PartitionRole::PrepBoot | PartitionRole::LvmPv => "-",
+            PartitionRole::Esp => "/boot/efi",
+            PartitionRole::Boot => "/boot",
+            PartitionRole::Swap => "[SWAP]",
+            PartitionRole::Root => "/",
+            PartitionRole::Var => "/var",
+            PartitionRole::Containers => "/var/lib/containers",
+        }
+    }
+}
+
+/// A partition to be created by `install to-disk`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct PlannedPartition {
+    pub(crate) role: PartitionRole,
+    /// The GPT partition name, which is also the filesystem label
+    pub(crate) label: String,
+    /// The size; if unset, all remaining space is used
+    pub(crate) size_mib: Option<u64>,
+    pub(crate) parttype: &'static str,
+    /// The filesystem created by `mkfs.$fstype`, if any
+    pub(crate) fstype: Option<Filesystem>,
+}
+
+impl PlannedPartition {
+    fn new(
+        role: PartitionRole,
+        label: &str,
+        size_mib: Option<u64>,
+        parttype: &'static str,
+        fstype: Option<Filesystem>,
+    ) -> Self {
+        Self {
+            role,
+            label: label.to_owned(),
+            size_mib,
+            parttype,
+            fstype,
+        }
+    }
+
+    /// The filesystem type as shown in the plan.
+    fn fstype_name(&self) -> Cow<'static, str> {
+        match (self.role, self.fstype) {
+            (_, Some(fs)) => Cow::Owned(fs.to_string()),
+            (PartitionRole::Esp, None) => Cow::Borrowed("vfat"),
+            (PartitionRole::Swap, None) => Cow::Borrowed("swap"),
+            (PartitionRole::LvmPv, None) => Cow::Borrowed("LVM2"),
+            (_, None) => Cow::Borrowed("-"),
+        }
+    }
+}
+
+/// The volume group created in the LVM physical volume partition.  The logical
+/// volumes reuse [`PlannedPartition`], with the label as name; their `parttype`
+/// is unused.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct LvmPlan {
+    pub(crate) vg_name: String,
+    /// Root and /var are thin volumes in a thin pool using the remaining space
+    pub(crate) thin: bool,
+    /// The logical volumes, in order of creation
+    pub(crate) volumes: Vec<PlannedPartition>,
+}
+
+impl LvmPlan {
+    /// Returns true if the volume is created in the thin pool.
+    fn is_thin(&self, v: &PlannedPartition) -> bool {
+        self.thin && v.role != PartitionRole::Swap
+    }
+}
+
+/// The partitions created by `install to-disk`, in order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct PartitionPlan {
+    pub(crate) partitions: Vec<PlannedPartition>,
+    pub(crate) lvm: Option<LvmPlan>,
+    /// The /var partition on a separate disk, see `--var-device`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) var_disk: Option<PlannedPartition>,
+}
+
+/// Parse a size from the configuration, which must not be zero.
+fn parse_size(name: &str, size: &str) -> Result<u64> {
+    let v = crate::blockdev::parse_size_mib(size).with_context(|| format!("Parsing {name}"))?;
+    if v == 0 {
+        anyhow::bail!("Invalid {name}: must not be zero");
+    }
+    Ok(v)
+}
+
+/// Verify that a label is usable both as GPT partition name and filesystem label.
+fn validate_label(label: &str, max_len: usize) -> Result<()> {
+    if label.is_empty() {
+        anyhow::bail!("Invalid empty label");
+    }
+    if label.len() > max_len {
+        anyhow::bail!("Label {label} is longer than {max_len} characters");
+    }
+    if !label
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+    {
+        anyhow::bail!("Label {label} may only contain ASCII letters, digits, '-' and '_'");
+    }
+    Ok(())
+}
+
+impl PartitionPlan {
+    /// Compute the layout for the given architecture.  `root_size` overrides
+    /// the size of the root partition from `layout`.
+    #[context("Computing partition layout")]
+    pub(crate) fn new(
+        arch: &str,
+        block_setup: BlockSetup,
+        root_filesystem: Filesystem,
+        layout: &Partitions,
+        root_size: Option<&str>,
+    ) -> Result<Self> {
+        let raid = block_setup == BlockSetup::Raid1;
+        if raid {
+            if !matches!(arch, "x86_64" | "aarch64") {
+                anyhow::bail!("Block setup {block_setup} is not supported on {arch}");
+            }
+            if layout.var.is_some()
+                || layout.containers.is_some()
+                || layout.swap.is_some()
+                || layout.lvm.is_some()
+            {
+                anyhow::bail!(
+                    "Separate /var, /var/lib/containers and swap partitions and LVM are not supported with {block_setup}"
+                );
+            }
+        }
+        // Apart from the ESP, which the firmware must find, the mirrored partitions are RAID members
+        let linux_parttype = if raid { RAID_PARTTYPE } else { LINUX_PARTTYPE };
+        let mut partitions = Vec::new();
+        match arch {
+            "x86_64" => partitions.push(PlannedPartition::new(
+                PartitionRole::BiosBoot,
+                "BIOS-BOOT",
+                Some(1),
+                BIOS_BOOT_PARTTYPE,
+                None,
+            )),
+            "powerpc64" => partitions.push(PlannedPartition::new(
+                PartitionRole::PrepBoot,
+                crate::bootloader::PREPBOOT_LABEL,
+                Some(4),
+                crate::bootloader::PREPBOOT_GUID,
+                None,
+            )),
+            // No bootloader partition is necessary
+            "aarch64" | "s390x" => {}
+            o => anyhow::bail!("Unsupported architecture: {o}"),
+        }
+
+        // This matches super::ARCH_USES_EFI
+        if matches!(arch, "x86_64" | "aarch64") {
+            let size = match layout.esp_size.as_deref() {
+                Some(s) => parse_size("esp-size", s)?,
+                None => EFIPN_SIZE_MB.into(),
+            };
+            if size < EFIPN_MIN_SIZE_MB {
+                anyhow::bail!("The ESP must be at least {EFIPN_MIN_SIZE_MB} MiB");
+            }
+            partitions.push(PlannedPartition::new(
+                PartitionRole::Esp,
+                "EFI-SYSTEM",
+                Some(size),
+                ESP_PARTTYPE,
+                None,
+            ));
+        }
+
+        // Note that in the future, we may match what systemd/uapi-group encourages
+        // and make /boot be FAT32 as well, as it would aid systemd-boot.
+        // The bootloader may not be able to read /boot from a (thin) logical volume.
+        if block_setup.requires_bootpart() || layout.lvm.is_some() {
+            let size = match layout.boot_size.as_deref() {
+                Some(s) => parse_size("boot-size", s)?,
+                None => BOOTPN_SIZE_MB.into(),
+            };
+            if size < BOOTPN_MIN_SIZE_MB {
+                anyhow::bail!("The /boot partition must be at least {BOOTPN_MIN_SIZE_MB} MiB");
+            }
+            partitions.push(PlannedPartition::new(
+                PartitionRole::Boot,
+                "boot",
+                Some(size),
+                linux_parttype,
+                Some(root_filesystem),
+            ));
+        }
+
+        // Only the root filesystem is encrypted, so anything else would leak data.
+        if block_setup.is_encrypted()
+            && (layout.var.is_some() || layout.containers.is_some() || layout.swap.is_some())
+        {
+            anyhow::bail!(
+                "Separate /var, /var/lib/containers and swap partitions are not supported with {block_setup}"
+            );
+        }
+        if block_setup.is_encrypted() && layout.lvm.is_some() {
+            anyhow::bail!("LVM is not supported with {block_setup}");
+        }
+
+        // With LVM, these are the logical volumes
+        let mut volumes = Vec::new();
+        if let Some(swap) = layout.swap.as_ref() {
+            let size = swap
+                .size
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("The swap partition requires a size"))?;
+            let size = parse_size("swap size", size)?;
+            if swap.fstype.is_some() {
+                anyhow::bail!("The swap partition cannot have a filesystem type");
+            }
+            volumes.push(PlannedPartition::new(
+                PartitionRole::Swap,
+                swap.label.as_deref().unwrap_or("swap"),
+                Some(size),
+                SWAP_PARTTYPE,
+                None,
+            ));
+        }
+
+        let root_size = root_size
+            .or(layout.root_size.as_deref())
+            .map(|s| parse_size("root-size", s))
+            .transpose()?;
+        let root = PlannedPartition::new(
+            PartitionRole::Root,
+            layout.root_label.as_deref().unwrap_or("root"),
+            root_size,
+            linux_parttype,
+            Some(root_filesystem),
+        );
+        let extra = |p: Option<&ExtraPartition>, role, name: &str| -> Result<_> {
+            p.map(|p| -> Result<_> {
+                let size = p
+                    .size
+                    .as_deref()
+                    .map(|s| parse_size(&format!("{name} size"), s))
+                    .transpose()?;
+                Ok(PlannedPartition::new(
+                    role,
+                    p.label.as_deref().unwrap_or(name),
+                    size,
+                    LINUX_PARTTYPE,
+                    Some(p.fstype.unwrap_or(root_filesystem)),
+                ))
+            })
+            .transpose()
+        };
+        let var = extra(layout.var.as_ref(), PartitionRole::Var, "var")?;
+        let containers = extra(
+            layout.containers.as_ref(),
+            PartitionRole::Containers,
+            "containers",
+        )?;
+        let thin = layout
+            .lvm
+            .as_ref()
+            .and_then(|lvm| lvm.thin)
+            .unwrap_or_default();
+        // The one of root, /var and /var/lib/containers using the remaining space goes
+        // last; thin volumes default to the size of the pool instead.
+        let mut fs_volumes = std::iter::once(root)
+            .chain(var)
+            .chain(containers)
+            .collect::<Vec<_>>();
+        if fs_volumes.iter().filter(|v| v.size_mib.is_none()).count() > 1 && !thin {
+            anyhow::bail!(
+                "Only one of the root, /var and /var/lib/containers partitions can use the remaining space; specify a size for the others"
+            );
+        }
+        fs_volumes.sort_by_key(|v| v.size_mib.is_none());
+        volumes.extend(fs_volumes);
+
+        let lvm = if let Some(lvm) = layout.lvm.as_ref() {
+            partitions.push(PlannedPartition::new(
+                PartitionRole::LvmPv,
+                "lvm",
+                None,
+                LVM_PARTTYPE,
+                None,
+            ));
+            let vg_name = lvm.vg_name.as_deref().unwrap_or(DEFAULT_VG_NAME);
+            // The limit of LVM names is much higher, but a short name keeps the device paths readable
+            validate_label(vg_name, 64).context("Invalid volume group name")?;
+            Some(LvmPlan {
+                vg_name: vg_name.to_owned(),
+                thin,
+                volumes,
+            })
+        } else {
+            partitions.extend(volumes);
+            None
+        };
+
+        let mut labels = std::collections::HashSet::new();
+        let volumes = lvm.iter().flat_map(|lvm| lvm.volumes.iter());
+        for p in partitions.iter().chain(volumes) {
+            let max_len = match (p.role, p.fstype) {
+                (_, Some(fs)) => fs.max_label_len(),
+                // The limit of mkswap
+                (PartitionRole::Swap, None) => 16,
+                // The limit of GPT partition names
+                (_, None) => 36,
+            };
+            validate_label(&p.label, max_len)?;
+            if !labels.insert(p.label.as_str()) {
+                anyhow::bail!("Duplicate label: {}", p.label);
+            }
+        }
+
+        Ok(Self {
+            partitions,
+            lvm,
+            var_disk: None,
+        })
+    }
+
+    /// Put /var on a separate disk, using the given configuration except for the size.
+    pub(crate) fn set_var_disk(
+        &mut self,
+        var: &ExtraPartition,
+        root_filesystem: Filesystem,
+    ) -> Result<()> {
+        let fstype = var.fstype.unwrap_or(root_filesystem);
+        let p = PlannedPartition::new(
+            PartitionRole::Var,
+            var.label.as_deref().unwrap_or("var"),
+            None,
+            LINUX_PARTTYPE,
+            Some(fstype),
+        );
+        validate_label(&p.label, fstype.max_label_len())?;
+        let volumes = self.lvm.iter().flat_map(|lvm| lvm.volumes.iter());
+        if self
+            .partitions
+            .iter()
+            .chain(volumes)
+            .any(|o| o.label == p.label)
+        {
+            anyhow::bail!("Duplicate label: {}", p.label);
+        }
+        self.var_disk = Some(p);
+        Ok(())
+    }
+
+    /// Generate the input for `sfdisk` for the disk given via `--var-device`.
+    pub(crate) fn var_disk_to_sfdisk(&self, label_id: &uuid::Uuid) -> Option<String> {
+        self.var_disk.as_ref().map(|p| {
+            format!(
+                "label: gpt\nlabel-id: {label_id}\ntype={}, name=\"{}\"\n",
+                p.parttype, p.label
+            )
+        })
+    }
+
+    /// Verify that the partitions fit on a disk of the given size.
+    pub(crate) fn validate_disk_size(&self, disk_size_mib: u64) -> Result<()> {
+        let fixed = self
+            .partitions
+            .iter()
+            .filter_map(|p| p.size_mib)
+            .sum::<u64>()
+            + PARTITION_TABLE_OVERHEAD_MB;
+        let fills = self.partitions.iter().any(|p| p.size_mib.is_none());
+        if fixed > disk_size_mib || (fills && fixed >= disk_size_mib) {
+            anyhow::bail!(
+                "The partitions require more than the {disk_size_mib} MiB available on the disk"
+            );
+        }
+        if let Some(lvm) = self.lvm.as_ref() {
+            let available = disk_size_mib - fixed;
+            // Thin volumes may use more than the size of the pool
+            let volumes = lvm.volumes.iter().filter(|v| !lvm.is_thin(v));
+            let needed = volumes.clone().filter_map(|v| v.size_mib).sum::<u64>() + LVM_OVERHEAD_MB;
+            let fills = lvm.thin || volumes.clone().any(|v| v.size_mib.is_none());
+            if needed > available || (fills && needed >= available) {
+                anyhow::bail!(
+                    "The logical volumes require more than the {available} MiB available for LVM"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The partition number (starting at 1) of the partition with the given role.
+    pub(crate) fn partno(&self, role: PartitionRole) -> Option<u32> {
+        self.partitions
+            .iter()
+            .position(|p| p.role == role)
+            .map(|i| i as u32 + 1)
+    }
+
+    /// Find the partition with the given role.
+    pub(crate) fn get(&self, role: PartitionRole) -> Option<&PlannedPartition> {
+        self.partitions.iter().find(|p| p.role == role)
+    }
+
+    /// Find the partition or logical volume with the given role.
+    pub(crate) fn get_volume(&self, role: PartitionRole) -> Option<&PlannedPartition> {
+        self.lvm
+            .iter()
+            .flat_map(|lvm| lvm.volumes.iter())
+            .find(|p| p.role == role)
+            .or_else(|| self.get(role))
+            .or_else(|| self.var_disk.as_ref().filter(|p| p.role == role))
+    }
+
+    /// Generate the input for `sfdisk`.
+    pub(crate) fn to_sfdisk(&self, label_id: &uuid::Uuid) -> Result<String> {
+        let mut buf = String::new();
+        writeln!(buf, "label: gpt")?;
+        writeln!(buf, "label-id: {label_id}")?;
+        buf.push_str(&self.to_sfdisk_partitions()?);
+        Ok(buf)
+    }
+
+    /// Generate the configuration for `fdasd`, which partitions an ECKD DASD in
+    /// units of tracks.  Partitions on a DASD have no names, only a type.
+    pub(crate) fn to_fdasd(&self, geometry: &crate::blockdev::DasdGeometry) -> Result<String> {
+        let n = self.partitions.len();
+        if n > DASD_MAX_PARTITIONS {
+            anyhow::bail!(
+                "A DASD holds at most {DASD_MAX_PARTITIONS} partitions, but {n} are needed"
+            );
+        }
+        let mut buf = String::new();
+        let mut start = DASD_FIRST_TRACK;
+        for (i, p) in self.partitions.iter().enumerate() {
+            let kind = match p.parttype {
+                SWAP_PARTTYPE => "swap",
+                RAID_PARTTYPE => "raid",
+                LVM_PARTTYPE => "lvm",
+                _ => "native",
+            };
+            let first = if i == 0 {
+                Cow::Borrowed("first")
+            } else {
+                Cow::Owned(start.to_string())
+            };
+            let last = match p.size_mib {
+                Some(size) => {
+                    start += (size * 1024 * 1024).div_ceil(geometry.track_size());
+                    Cow::Owned((start - 1).to_string())
+                }
+                None if i + 1 == n => Cow::Borrowed("last"),
+                None => {
+                    anyhow::bail!("Only the last partition on a DASD can use the remaining space")
+                }
+            };
+            writeln!(buf, "[{first},{last},{kind}]")?;
+        }
+        Ok(buf)
+    }
+
+    /// Generate the input for `sfdisk` without the partition table header, as used
+    /// for `sfdisk --append`.
+    pub(crate) fn to_sfdisk_partitions(&self) -> Result<String> {
+        let mut buf = String::new();
+        for p in self.partitions.iter() {
+            if let Some(size) = p.size_mib {
+                write!(buf, "size={size}MiB, ")?;
+            }
+            if matches!(p.role, PartitionRole::BiosBoot | PartitionRole::PrepBoot) {
+                write!(buf, "bootable, ")?;
+            }
+            writeln!(buf, r#"type={}, name="{}""#, p.parttype, p.label)?;
+        }
+        Ok(buf)
+    }
+}
+
+impl Display for PartitionPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:<4} {:<12} {:>12} {:<6} MOUNTPOINT",
+            "PART", "LABEL", "SIZE", "FSTYPE"
+        )?;
+        for (i, p) in self.partitions.iter().enumerate() {
+            let size = p
+                .size_mib
+                .map(|v| Cow::Owned(format!("{v} MiB")))
+                .unwrap_or(Cow::Borrowed("remaining"));
+            writeln!(
+                f,
+                "{:<4} {:<12} {:>12} {:<6} {}",
+                i + 1,
+                p.label,
+                size,
+                p.fstype_name(),
+                p.role.mountpoint()
+            )?;
+        }
+        if let Some(lvm) = self.lvm.as_ref() {
+            writeln!(f)?;
+            let kind = if lvm.thin { " (thin)" } else { "" };
+            writeln!(f, "Volume group {}{kind}:", lvm.vg_name)?;
+            for v in lvm.volumes.iter() {
+                let size = match (v.size_mib, lvm.is_thin(v)) {
+                    (Some(v), _) => Cow::Owned(format!("{v} MiB")),
+                    (None, true) => Cow::Borrowed("pool"),
+                    (None, false) => Cow::Borrowed("remaining"),
+                };
+                writeln!(
+                    f,
+                    "{:<4} {:<12} {:>12} {:<6} {}",
+                    "LV",
+                    v.label,
+                    size,
+                    v.fstype_name(),
+                    v.role.mountpoint()
+                )?;
+            }
+        }
+        if let Some(var) = self.var_disk.as_ref() {
+            writeln!(f)?;
+            writeln!(f, "Var device:")?;
+            writeln!(
+                f,
+                "{:<4} {:<12} {:>12} {:<6} {}",
+                1,
+                var.label,
+                "remaining",
+                var.fstype_name(),
+                var.role.mountpoint()
+            )?;
+        }
+        std::fmt::Result::Ok(())
+    }
+}
+
+/// Create the volume group in the given physical volume, and its logical volumes.
+#[context("Setting up LVM")]
+fn setup_lvm(lvm: &LvmPlan, pv: &Utf8Path) -> Result<()> {
+    let vg = lvm.vg_name.as_str();
+    Task::new("Creating LVM physical volume", "pvcreate")
+        .arg("-y")
+        .arg(pv)
+        .quiet_output()
+        .run()?;
+    Task::new("Creating LVM volume group", "vgcreate")
+        .arg(vg)
+        .arg(pv)
+        .quiet_output()
+        .run()?;
+    let lvcreate = |name: &str| {
+        Task::new(format!("Creating logical volume {vg}/{name}"), "lvcreate").args([
+            "-y",
+            "--wipesignatures",
+            "y",
+            "-n",
+            name,
+        ])
+    };
+    // The regular volumes come first, so that the thin pool uses the remaining space
+    let (thin, regular): (Vec<_>, Vec<_>) = lvm.volumes.iter().partition(|v| lvm.is_thin(v));
+    for v in regular {
+        let t = lvcreate(&v.label);
+        let t = match v.size_mib {
+            Some(size) => t.arg(format!("--size={size}m")),
+            None => t.arg("--extents=100%FREE"),
+        };
+        t.arg(vg).quiet_output().run()?;
+    }
+    if thin.is_empty() {
+        return Ok(());
+    }
+    lvcreate(THIN_POOL_NAME)
+        .args(["--type=thin-pool", "--extents=100%FREE", vg])
+        .quiet_output()
+        .run()?;
+    let pool = format!("{vg}/{THIN_POOL_NAME}");
+    let pool_size = Task::new_quiet("lvs")
+        .args(["--noheadings", "--nosuffix", "--units=m", "-o", "lv_size"])
+        .arg(&pool)
+        .read()?;
+    let pool_size = pool_size
+        .trim()
+        .parse::<f64>()
+        .with_context(|| format!("Parsing size of {pool}: {pool_size}"))?
+        as u64;
+    for v in thin {
+        let size = v.size_mib.unwrap_or(pool_size);
+        lvcreate(&v.label)
+            .arg(format!("--virtualsize={size}m"))
+            .arg(format!("--thinpool={pool}"))
+            .quiet_output()
+            .run()?;
+    }
+    Ok(())
+}
+
+/// An md RAID1 array created for `--block-setup raid1`.
+#[derive(Debug)]
+struct RaidArray {
+    role: PartitionRole,
+    /// The path below `/dev/md`
+    path: String,
+    /// The array UUID, in the format used by mdadm
+    uuid: String,
+}
+
+/// The arrays created for `--block-setup raid1`: the name, and the metadata format.
+/// The ESP and /boot use format 1.0, which is stored at the end of the device, so
+/// that the firmware and bootloader can read each member as a plain filesystem.
+const RAID_ARRAYS: &[(PartitionRole, &str, &str)] = &[
+    (PartitionRole::Esp, "esp", "1.0"),
+    (PartitionRole::Boot, "boot", "1.0"),
+    (PartitionRole::Root, "root", "1.2"),
+];
+
+/// Format a UUID as mdadm does.
+fn md_uuid(u: &uuid::Uuid) -> String {
+    let s = u.simple().to_string();
+    [&s[0..8], &s[8..16], &s[16..24], &s[24..32]].join(":")
+}
+
+/// Create a RAID1 array of the partitions with the same role on each of the devices.
+#[context("Setting up RAID")]
+fn setup_raid(
+    plan: &PartitionPlan,
+    tables: &[&crate::blockdev::PartitionTable],
+) -> Result<Vec<RaidArray>> {
+    let mut arrays = Vec::new();
+    for &(role, name, metadata) in RAID_ARRAYS {
+        let Some(partno) = plan.partno(role) else {
+            continue;
+        };
+        let members = tables
+            .iter()
+            .map(|t| Ok(t.find_partno(partno)?.node.clone()))
+            .collect::<Result<Vec<_>>>()?;
+        let uuid = md_uuid(&uuid::Uuid::new_v4());
+        let path = format!("/dev/md/{name}");
+        // The homehost "any" ensures the array keeps its name on the installed system
+        Task::new(format!("Creating RAID1 array {name}"), "mdadm")
+            .args([
+                "--create",
+                path.as_str(),
+                "--run",
+                "--level=1",
+                "--homehost=any",
+            ])
+            .arg(format!("--name={name}"))
+            .arg(format!("--metadata={metadata}"))
+            .arg(format!("--uuid={uuid}"))
+            .arg(format!("--raid-devices={}", members.len()))
+            .args(members)
+            .quiet_output()
+            .run()?;
+        arrays.push(RaidArray { role, path, uuid });
+    }
+    Ok(arrays)
+}
+
+/// Returns true if the partition is an EFI system partition.
+fn is_esp(partition: &crate::blockdev::Device) -> bool {
+    partition
+        .parttype
+        .as_deref()
+        .is_some_and(|t| t.eq_ignore_ascii_case(ESP_PARTTYPE))
+}
+
+/// Find the partitions of the device to keep, which are given by their GPT partition
+/// name or filesystem label; each label must match exactly one partition.
+fn preserved_partitions<'a>(
+    device: &'a crate::blockdev::Device,
+    labels: &[String],
+) -> Result<Vec<&'a crate::blockdev::Device>> {
+    if labels.is_empty() {
+        return Ok(Vec::new());
+    }
+    let dev = device.path();
+    if device.pttype.as_deref() != Some("gpt") {
+        anyhow::bail!("Preserving partitions requires a GPT on {dev}");
+    }
+    let mut preserved = Vec::new();
+    for label in labels {
+        let found = device
+            .children
+            .iter()
+            .flatten()
+            .filter(|c| {
+                c.partlabel.as_deref() == Some(label.as_str())
+                    || c.label.as_deref() == Some(label.as_str())
+            })
+            .collect::<Vec<_>>();
+        match found.as_slice() {
+            [partition] => preserved.push(*partition),
+            [] => anyhow::bail!("No partition labeled {label} on {dev}"),
+            _ => anyhow::bail!("Multiple partitions labeled {label} on {dev}"),
+        }
+    }
+    Ok(preserved)
+}
+
+/// Wipe the given device according to `wipe`, or verify that it is empty.  With
+/// `preserve`, only the partitions which are not preserved are wiped and removed.
+fn prepare_device(
+    dev: &Utf8Path,
+    wipe: WipeMode,
+    discard: bool,
+    preserve: &[String],
+) -> Result<crate::blockdev::Device> {
+    // Verify that the target is empty (if not already wiped in particular, but it's
+    // also good to verify that the wipe worked)
+    let device = crate::blockdev::list_dev(dev)?;
+    // DASDs are partitioned via fdasd, which always writes a new VTOC
+    if device.is_dasd() && (!preserve.is_empty() || wipe == WipeMode::GptOnly) {
+        anyhow::bail!("Preserving partitions and --wipe=gpt-only are not supported on DASD {dev}");
+    }
+    let preserved = preserved_partitions(&device, preserve)?;
+    let discard = discard && {
+        let supported = device.supports_discard()?;
+        if !supported {
+            println!("Device {dev} does not support discard; skipping");
+        }
+        supported
+    };
+
+    // Handle wiping any existing data
+    match wipe {
+        WipeMode::FailIfNotEmpty if !preserve.is_empty() => {
+            anyhow::bail!("Preserving partitions requires --wipe=all or --wipe=gpt-only");
+        }
+        WipeMode::FailIfNotEmpty => {
+            if device.has_children() || device.fstype.is_some() {
+                anyhow::bail!(
+                    "Detected existing partitions or filesystem on {dev}; use --wipe if you intend to overwrite"
+                );
+            }
+            if discard {
+                crate::blockdev::discard(dev)?;
+            }
+        }
+        WipeMode::All | WipeMode::GptOnly => {
+            let removed = device
+                .children
+                .iter()
+                .flatten()
+                .filter(|c| !preserved.iter().any(|p| p.name == c.name))
+                .collect::<Vec<_>>();
+            for child in removed.iter() {
+                let child = child.path();
+                if wipe == WipeMode::All {
+                    println!("Wiping {child}");
+                    crate::blockdev::wipefs(Utf8Path::new(&child))?;
+                }
+                if discard && !preserved.is_empty() {
+                    crate::blockdev::discard(Utf8Path::new(&child))?;
+                }
+            }
+            if preserved.is_empty() {
+                println!("Wiping {dev}");
+                crate::blockdev::wipefs(dev)?;
+                if discard {
+                    crate::blockdev::discard(dev)?;
+                }
+            } else if !removed.is_empty() {
+                let partnos = removed
+                    .iter()
+                    .map(|c| super::partno_of(&c.path()).map(|n| n.to_string()))
+                    .collect::<Result<Vec<_>>>()?;
+                Task::new(format!("Removing partitions of {dev}"), "sfdisk")
+                    .arg("--delete")
+                    .arg(dev)
+                    .args(partnos)
+                    .quiet()
+                    .run()?;
+            }
+        }
+    }
+    Ok(device)
+}
+
+/// Determine the root filesystem and block setup from the options and install configuration.
+fn resolve_setup(
+    opts: &InstallBlockDeviceOpts,
+    config: Option<&InstallConfiguration>,
+) -> Result<(Filesystem, BlockSetup)> {
+    // Ensure we have a root filesystem upfront
+    let root_filesystem = opts
+        .filesystem
+        .or(config
+            .and_then(|c| c.filesystem_root())
+            .and_then(|r| r.fstype))
+        .ok_or_else(|| anyhow::anyhow!("No root filesystem specified"))?;
+    // --encrypt implies the luks block setup
+    let requested = opts
+        .block_setup
+        .or_else(|| (!opts.encrypt.is_empty()).then_some(BlockSetup::Luks));
+    // Use the install configuration to find the block setup, if we have one
+    let block_setup = if let Some(config) = config {
+        config.get_block_setup(requested)?
+    } else if opts.filesystem.is_some() {
+        // Otherwise, if a filesystem is specified then we default to whatever was
+        // specified via --block-setup, or the default
+        requested.unwrap_or_default()
+    } else {
+        // If there was no default filesystem, then there's no default block setup,
+        // and we need to error out.
+        anyhow::bail!("No install configuration found, and no filesystem specified")
+    };
+    Ok((root_filesystem, block_setup))
+}
+
+/// Compute the partition layout and encryption for the given options, install configuration
+/// and architecture.
+fn plan(
+    opts: &InstallBlockDeviceOpts,
+    config: Option<&InstallConfiguration>,
+    arch: &str,
+) -> Result<(BlockSetup, Vec<EncryptionMethod>, PartitionPlan)> {
+    let (root_filesystem, block_setup) = resolve_setup(opts, config)?;
+    let methods = opts.encryption_methods(block_setup)?;
+    match (block_setup, opts.mirror_devices.is_empty()) {
+        (BlockSetup::Raid1, true) => {
+            anyhow::bail!("Block setup {block_setup} requires at least two devices")
         }
+        (BlockSetup::Raid1, false) => {}
+        (_, false) => anyhow::bail!("Multiple devices require block setup raid1"),
+        (_, true) => {}
     }
+    let default_layout = Partitions::default();
+    let mut layout = Cow::Borrowed(
+        config
+            .and_then(|c| c.partitions.as_ref())
+            .unwrap_or(&default_layout),
+    );
+    // With --var-device, the var partition is not created on the main disk
+    let var_disk = if opts.var_device.is_some() {
+        if block_setup != BlockSetup::Direct {
+            anyhow::bail!("--var-device is not supported with block setup {block_setup}");
+        }
+        Some(layout.to_mut().var.take().unwrap_or_default())
+    } else {
+        None
+    };
+    let mut plan = PartitionPlan::new(
+        arch,
+        block_setup,
+        root_filesystem,
+        &layout,
+        opts.root_size.as_deref(),
+    )?;
+    if let Some(var) = var_disk {
+        plan.set_var_disk(&var, root_filesystem)?;
+    }
+    if let Some(root) = config.and_then(|c| c.filesystem_root()) {
+        if let Some(subvol) = root.var_subvolume.as_deref() {
+            validate_var_subvolume(subvol, root_filesystem, &plan)?;
+        }
+        if root.subvolumes.unwrap_or_default() {
+            validate_subvolume_layout(root, root_filesystem, &plan)?;
+        }
+    }
+    Ok((block_setup, methods, plan))
+}
+
+/// Verify that the conventional btrfs subvolume layout can be created.
+fn validate_subvolume_layout(
+    root: &RootFS,
+    root_filesystem: Filesystem,
+    plan: &PartitionPlan,
+) -> Result<()> {
+    if root_filesystem != Filesystem::Btrfs {
+        anyhow::bail!("A subvolume layout requires a btrfs root filesystem, not {root_filesystem}");
+    }
+    if root.var_subvolume.is_some() {
+        anyhow::bail!("var-subvolume cannot be combined with the subvolume layout");
+    }
+    if plan.get_volume(PartitionRole::Var).is_some() {
+        anyhow::bail!("The subvolume layout cannot be combined with a /var partition");
+    }
+    Ok(())
+}
+
+/// Create the subvolumes of [`BTRFS_SUBVOLUMES`] in the given btrfs filesystem, using
+/// the target path as temporary mountpoint.
+#[context("Creating btrfs subvolumes")]
+fn create_subvolume_layout(dev: &str, target: &Utf8Path) -> Result<()> {
+    mount::mount(dev, target)?;
+    for (name, _) in BTRFS_SUBVOLUMES {
+        Task::new(format!("Creating subvolume {name}"), "btrfs")
+            .args(["subvolume", "create"])
+            .arg(target.join(name))
+            .quiet_output()
+            .run()?;
+    }
+    Task::new_and_run(
+        "Unmounting top-level subvolume",
+        "umount",
+        [target.as_str()],
+    )
+}
+
+/// The mounts for `/etc/fstab` of the subvolume layout, i.e. all but the root.
+fn subvolume_mounts(root_uuid: &str, mount_options: &[String]) -> Vec<MountSpec> {
+    BTRFS_SUBVOLUMES[1..]
+        .iter()
+        .map(|(name, target)| {
+            let mut spec = MountSpec::new_uuid_src(root_uuid, target);
+            spec.fstype = "btrfs".into();
+            spec.push_option(&format!("subvol={name}"));
+            for o in mount_options {
+                spec.push_option(o);
+            }
+            spec
+        })
+        .collect()
+}
+
+/// Verify that /var can be created as the given btrfs subvolume of the root filesystem.
+fn validate_var_subvolume(
+    subvol: &str,
+    root_filesystem: Filesystem,
+    plan: &PartitionPlan,
+) -> Result<()> {
+    if subvol.is_empty() || subvol.contains('/') || matches!(subvol, "." | "..") {
+        anyhow::bail!("Invalid /var subvolume name: {subvol:?}");
+    }
+    if root_filesystem != Filesystem::Btrfs {
+        anyhow::bail!("A /var subvolume requires a btrfs root filesystem, not {root_filesystem}");
+    }
+    if plan.get_volume(PartitionRole::Var).is_some() {
+        anyhow::bail!("A /var subvolume cannot be combined with a /var partition");
+    }
+    Ok(())
+}
+
+/// The configured mkfs options, which only apply if the configured filesystem type
+/// was not overridden.
+fn root_mkfs_options(root: Option<&RootFS>, fstype: Filesystem) -> &[String] {
+    root.filter(|r| r.fstype == Some(fstype))
+        .and_then(|r| r.mkfs_options.as_deref())
+        .unwrap_or_default()
+}
+
+/// Implementation of `install to-disk --print-plan`: print the partitions which
+/// would be created on a disk of the given size, without changing anything.
+#[context("Printing partition plan")]
+pub(crate) fn print_plan(opts: &InstallBlockDeviceOpts, disk_size: u64, arch: &str) -> Result<()> {
+    let config = super::config::load_config(arch)?;
+    let (block_setup, methods, plan) = plan(opts, config.as_ref(), arch)?;
+    plan.validate_disk_size(disk_size / (1024 * 1024))?;
+    let size = ostree_ext::glib::format_size(disk_size);
+    println!("Block setup: {block_setup}");
+    if !methods.is_empty() {
+        let methods = methods.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+        println!(" Encryption: {}", methods.join(", "));
+    }
+    println!("     Device: {} (size={size})", opts.device()?);
+    for dev in opts.mirror_devices.iter() {
+        println!("     Mirror: {dev}");
+    }
+    println!();
+    print!("{plan}");
+    if let Some(subvol) = config
+        .as_ref()
+        .and_then(|c| c.filesystem_root())
+        .and_then(|r| r.var_subvolume.as_deref())
+    {
+        println!("/var: btrfs subvolume {subvol} of the root filesystem");
+    }
+    if config
+        .as_ref()
+        .and_then(|c| c.filesystem_root())
+        .and_then(|r| r.subvolumes)
+        .unwrap_or_default()
+    {
+        for (name, target) in BTRFS_SUBVOLUMES {
+            println!("{target}: btrfs subvolume {name} of the root filesystem");
+        }
+    }
+    Ok(())
 }
 
 fn mkfs<'a>(
     dev: &str,
     fs: Filesystem,
     label: &str,
-    wipe: bool,
+    force: bool,
     opts: impl IntoIterator<Item = &'a str>,
 ) -> Result<uuid::Uuid> {
     let devinfo = crate::blockdev::list_dev(dev.into())?;
@@ -119,7 +1517,7 @@ fn mkfs<'a>(
     );
     match fs {
         Filesystem::Xfs => {
-            if wipe {
+            if force {
                 t.cmd.arg("-f");
             }
             t.cmd.arg("-m");
@@ -147,37 +1545,71 @@ pub(crate) fn install_create_rootfs(
     opts: InstallBlockDeviceOpts,
 ) -> Result<RootSetup> {
     let luks_name = "root";
-    // Ensure we have a root filesystem upfront
-    let root_filesystem = opts
-        .filesystem
-        .or(state
-            .install_config
-            .as_ref()
-            .and_then(|c| c.filesystem_root())
-            .and_then(|r| r.fstype))
-        .ok_or_else(|| anyhow::anyhow!("No root filesystem specified"))?;
-    // Verify that the target is empty (if not already wiped in particular, but it's
-    // also good to verify that the wipe worked)
-    let device = crate::blockdev::list_dev(&opts.device)?;
+    let (block_setup, methods, mut plan) =
+        plan(&opts, state.install_config.as_ref(), state.target_arch)?;
+    let target = crate::blockdev::list_dev(opts.device()?)?;
+    let preserved = preserved_partitions(&target, &opts.preserve_partitions)?;
+    if !preserved.is_empty() {
+        if block_setup == BlockSetup::Raid1 || opts.var_device.is_some() {
+            anyhow::bail!(
+                "--preserve-partition cannot be used with --block-setup raid1 or --var-device"
+            );
+        }
+        for p in preserved.iter() {
+            if let Some(name) = p.partlabel.as_deref() {
+                if plan.partitions.iter().any(|planned| planned.label == name) {
+                    anyhow::bail!(
+                        "Preserved partition {} has the same name as a new partition",
+                        p.path()
+                    );
+                }
+            }
+        }
+    }
+    // A preserved ESP is used instead of creating one
+    let preserved_esp = preserved.iter().find(|p| is_esp(p)).map(|p| p.path());
+    if preserved_esp.is_some() {
+        plan.partitions.retain(|p| p.role != PartitionRole::Esp);
+    }
+    crate::progress_jsonl::send(
+        state.progress.as_ref(),
+        crate::progress_jsonl::Event::Plan {
+            plan: serde_json::to_value(&plan)?,
+        },
+    );
+    // Read the passphrase before changing anything
+    let passphrase = opts
+        .encrypt_passphrase_file
+        .as_ref()
+        .map(|p| {
+            let mut s = std::fs::read_to_string(p).with_context(|| format!("Reading {p}"))?;
+            if s.ends_with('\n') {
+                s.pop();
+            }
+            if s.is_empty() {
+                anyhow::bail!("Empty passphrase in {p}");
+            }
+            Ok(s)
+        })
+        .transpose()?;
+    let device = prepare_device(
+        opts.device()?,
+        opts.wipe,
+        opts.discard,
+        &opts.preserve_partitions,
+    )?;
     // Canonicalize devpath
     let devpath: Utf8PathBuf = device.path().into();
-
-    // Handle wiping any existing data
-    if opts.wipe {
-        let dev = &opts.device;
-        for child in device.children.iter().flatten() {
-            let child = child.path();
-            println!("Wiping {child}");
-            crate::blockdev::wipefs(Utf8Path::new(&child))?;
-        }
-        println!("Wiping {dev}");
-        crate::blockdev::wipefs(dev)?;
-    } else if device.has_children() {
-        anyhow::bail!(
-            "Detected existing partitions on {}; use e.g. `wipefs` if you intend to overwrite",
-            opts.device
-        );
-    }
+    let mirrors = opts
+        .mirror_devices
+        .iter()
+        .map(|dev| prepare_device(dev, opts.wipe, opts.discard, &[]))
+        .collect::<Result<Vec<_>>>()?;
+    let var_device = opts
+        .var_device
+        .as_deref()
+        .map(|dev| prepare_device(dev, opts.wipe, opts.discard, &[]))
+        .transpose()?;
 
     let run_bootc = Utf8Path::new(RUN_BOOTC);
     let mntdir = run_bootc.join("mounts");
@@ -185,31 +1617,25 @@ pub(crate) fn install_create_rootfs(
         std::fs::remove_dir_all(&mntdir)?;
     }
 
-    // Use the install configuration to find the block setup, if we have one
-    let block_setup = if let Some(config) = state.install_config.as_ref() {
-        config.get_block_setup(opts.block_setup.as_ref().copied())?
-    } else if opts.filesystem.is_some() {
-        // Otherwise, if a filesystem is specified then we default to whatever was
-        // specified via --block-setup, or the default
-        opts.block_setup.unwrap_or_default()
-    } else {
-        // If there was no default filesystem, then there's no default block setup,
-        // and we need to error out.
-        anyhow::bail!("No install configuration found, and no filesystem specified")
-    };
     let serial = device.serial.as_deref().unwrap_or("<unknown>");
     let model = device.model.as_deref().unwrap_or("<unknown>");
     println!("Block setup: {block_setup}");
     println!("       Size: {}", device.size);
     println!("     Serial: {serial}");
     println!("      Model: {model}");
-
-    let root_size = opts
-        .root_size
-        .as_deref()
-        .map(crate::blockdev::parse_size_mib)
-        .transpose()
-        .context("Parsing root size")?;
+    for mirror in mirrors.iter() {
+        println!("     Mirror: {} (size={})", mirror.path(), mirror.size);
+    }
+    for p in preserved.iter() {
+        println!("  Preserved: {} (size={})", p.path(), p.size);
+    }
+    // Mirrors are limited to the smallest device, and preserved partitions keep their space
+    let size = mirrors
+        .iter()
+        .map(|d| d.size)
+        .fold(device.size, u64::min)
+        .saturating_sub(preserved.iter().map(|p| p.size).sum());
+    plan.validate_disk_size(size / (1024 * 1024))?;
 
     // Load the policy from the container root, which also must be our install root
     let sepolicy = state.load_policy()?;
@@ -222,72 +1648,42 @@ pub(crate) fn install_create_rootfs(
     let bootfs = mntdir.join("boot");
     std::fs::create_dir_all(bootfs)?;
 
-    // Generate partitioning spec as input to sfdisk
-    let mut partno = 0;
-    let mut partitioning_buf = String::new();
-    writeln!(partitioning_buf, "label: gpt")?;
-    let random_label = uuid::Uuid::new_v4();
-    writeln!(&mut partitioning_buf, "label-id: {random_label}")?;
-    if cfg!(target_arch = "x86_64") {
-        partno += 1;
-        writeln!(
-            &mut partitioning_buf,
-            r#"size=1MiB, bootable, type=21686148-6449-6E6F-744E-656564454649, name="BIOS-BOOT""#
-        )?;
-    } else if cfg!(target_arch = "powerpc64") {
-        // PowerPC-PReP-boot
-        partno += 1;
-        let label = crate::bootloader::PREPBOOT_LABEL;
-        let uuid = crate::bootloader::PREPBOOT_GUID;
-        writeln!(
-            &mut partitioning_buf,
-            r#"size=4MiB, bootable, type={uuid}, name="{label}""#
-        )?;
-    } else if cfg!(any(target_arch = "aarch64", target_arch = "s390x")) {
-        // No bootloader partition is necessary
+    // Generate partitioning spec as input to sfdisk; mirrors get the same partitions
+    if device.is_dasd() {
+        let geometry = crate::blockdev::dasd_geometry(&devpath)?;
+        let partitioning_buf = plan.to_fdasd(&geometry)?;
+        tracing::debug!("Partitioning: {partitioning_buf}");
+        let mut conf = tempfile::NamedTempFile::new()?;
+        conf.write_all(partitioning_buf.as_bytes())?;
+        Task::new("Initializing partitions", "fdasd")
+            .args(["--silent", "--config"])
+            .arg(conf.path())
+            .arg(&devpath)
+            .quiet()
+            .run()
+            .context("Failed to run fdasd")?;
+    } else if preserved.is_empty() {
+        for dev in std::iter::once(&device).chain(mirrors.iter()) {
+            let partitioning_buf = plan.to_sfdisk(&uuid::Uuid::new_v4())?;
+            tracing::debug!("Partitioning: {partitioning_buf}");
+            Task::new("Initializing partitions", "sfdisk")
+                .arg("--wipe=always")
+                .arg(dev.path())
+                .quiet()
+                .run_with_stdin_buf(Some(partitioning_buf.as_bytes()))
+                .context("Failed to run sfdisk")?;
+        }
     } else {
-        anyhow::bail!("Unsupported architecture: {}", std::env::consts::ARCH);
+        // Add the new partitions to the existing partition table
+        let partitioning_buf = plan.to_sfdisk_partitions()?;
+        tracing::debug!("Partitioning: {partitioning_buf}");
+        Task::new("Adding partitions", "sfdisk")
+            .arg("--append")
+            .arg(&devpath)
+            .quiet()
+            .run_with_stdin_buf(Some(partitioning_buf.as_bytes()))
+            .context("Failed to run sfdisk")?;
     }
-
-    let esp_partno = if super::ARCH_USES_EFI {
-        partno += 1;
-        writeln!(
-            &mut partitioning_buf,
-            r#"size={EFIPN_SIZE_MB}MiB, type=C12A7328-F81F-11D2-BA4B-00A0C93EC93B, name="EFI-SYSTEM""#
-        )?;
-        Some(partno)
-    } else {
-        None
-    };
-
-    // Initialize the /boot filesystem.  Note that in the future, we may match
-    // what systemd/uapi-group encourages and make /boot be FAT32 as well, as
-    // it would aid systemd-boot.
-    let boot_partno = if block_setup.requires_bootpart() {
-        partno += 1;
-        writeln!(
-            &mut partitioning_buf,
-            r#"size={BOOTPN_SIZE_MB}MiB, name="boot""#
-        )?;
-        Some(partno)
-    } else {
-        None
-    };
-    let rootpn = partno + 1;
-    let root_size = root_size
-        .map(|v| Cow::Owned(format!("size={v}MiB, ")))
-        .unwrap_or_else(|| Cow::Borrowed(""));
-    writeln!(
-        &mut partitioning_buf,
-        r#"{root_size}type={LINUX_PARTTYPE}, name="root""#
-    )?;
-    tracing::debug!("Partitioning: {partitioning_buf}");
-    Task::new("Initializing partitions", "sfdisk")
-        .arg("--wipe=always")
-        .arg(device.path())
-        .quiet()
-        .run_with_stdin_buf(Some(partitioning_buf.as_bytes()))
-        .context("Failed to run sfdisk")?;
     tracing::debug!("Created partition table");
 
     // Full udev sync; it'd obviously be better to await just the devices
@@ -296,77 +1692,140 @@ pub(crate) fn install_create_rootfs(
 
     // Re-read what we wrote into structured information
     let base_partitions = &crate::blockdev::partitions_of(&devpath)?;
+    let mirror_partitions = mirrors
+        .iter()
+        .map(|d| crate::blockdev::partitions_of(Utf8Path::new(&d.path())))
+        .collect::<Result<Vec<_>>>()?;
 
-    let root_partition = base_partitions.find_partno(rootpn)?;
-    if root_partition.parttype.as_str() != LINUX_PARTTYPE {
-        anyhow::bail!(
-            "root partition {partno} has type {}; expected {LINUX_PARTTYPE}",
-            root_partition.parttype.as_str()
-        );
+    let find_partition = |role| -> Result<_> {
+        // The numbers of the new partitions depend on the preserved ones
+        if !preserved.is_empty() {
+            let label = &plan.get(role).expect("partition in plan").label;
+            return base_partitions.find_name(label);
+        }
+        let partno = plan.partno(role).expect("partition in plan");
+        base_partitions.find_partno(partno)
+    };
+    // With RAID1, the ESP, /boot and root are arrays
+    let raid_arrays = if block_setup == BlockSetup::Raid1 {
+        let tables = std::iter::once(base_partitions)
+            .chain(mirror_partitions.iter())
+            .collect::<Vec<_>>();
+        setup_raid(&plan, &tables)?
+    } else {
+        Vec::new()
+    };
+    // With LVM, root, /var and swap are logical volumes
+    let mut lvm_kargs = Vec::new();
+    if let Some(lvm) = plan.lvm.as_ref() {
+        setup_lvm(lvm, find_partition(PartitionRole::LvmPv)?.path())?;
+    }
+    let device_of = |role| -> Result<String> {
+        if let Some(array) = raid_arrays.iter().find(|a| a.role == role) {
+            return Ok(array.path.clone());
+        }
+        if let Some(lvm) = plan.lvm.as_ref() {
+            if let Some(v) = lvm.volumes.iter().find(|v| v.role == role) {
+                return Ok(format!("/dev/{}/{}", lvm.vg_name, v.label));
+            }
+        }
+        Ok(find_partition(role)?.node.clone())
+    };
+    let root = plan.get_volume(PartitionRole::Root).expect("root volume");
+    let root_filesystem = root.fstype.expect("root filesystem");
+    let root_devpath = device_of(PartitionRole::Root)?;
+    if let Some(lvm) = plan.lvm.as_ref() {
+        // Only activate the root volume in the initramfs
+        lvm_kargs.push(format!("rd.lvm.lv={}/{}", lvm.vg_name, root.label));
+    } else {
+        let root_partition = find_partition(PartitionRole::Root)?;
+        let expected = root.parttype;
+        if !root_partition.parttype.eq_ignore_ascii_case(expected) {
+            anyhow::bail!(
+                "root partition {} has type {}; expected {expected}",
+                root_partition.node,
+                root_partition.parttype.as_str()
+            );
+        }
     }
-    let (rootdev, root_blockdev_kargs) = match block_setup {
-        BlockSetup::Direct => (root_partition.node.to_owned(), None),
-        BlockSetup::Tpm2Luks => {
-            let uuid = uuid::Uuid::new_v4().to_string();
-            // This will be replaced via --wipe-slot=all when binding to tpm below
-            let dummy_passphrase = uuid::Uuid::new_v4().to_string();
-            let mut tmp_keyfile = tempfile::NamedTempFile::new()?;
-            tmp_keyfile.write_all(dummy_passphrase.as_bytes())?;
-            tmp_keyfile.flush()?;
-            let tmp_keyfile = tmp_keyfile.path();
-            let dummy_passphrase_input = Some(dummy_passphrase.as_bytes());
-
-            let root_devpath = root_partition.path();
-
-            Task::new("Initializing LUKS for root", "cryptsetup")
-                .args(["luksFormat", "--uuid", uuid.as_str(), "--key-file"])
-                .args([tmp_keyfile])
-                .args([root_devpath])
-                .run()?;
-            // The --wipe-slot=all removes our temporary passphrase, and binds to the local TPM device.
-            // We also use .verbose() here as the details are important/notable.
-            Task::new("Enrolling root device with TPM", "systemd-cryptenroll")
-                .args(["--wipe-slot=all", "--tpm2-device=auto", "--unlock-key-file"])
-                .args([tmp_keyfile])
-                .args([root_devpath])
-                .verbose()
-                .run_with_stdin_buf(dummy_passphrase_input)?;
-            Task::new("Opening root LUKS device", "cryptsetup")
-                .args(["luksOpen", root_devpath.as_str(), luks_name])
-                .run()?;
-            let rootdev = format!("/dev/mapper/{luks_name}");
-            let kargs = vec![
-                format!("luks.uuid={uuid}"),
-                format!("luks.options=tpm2-device=auto,headless=true"),
-            ];
-            (rootdev, Some(kargs))
+    // Likewise, only assemble the root array in the initramfs
+    let raid_kargs = raid_arrays
+        .iter()
+        .filter(|a| a.role == PartitionRole::Root)
+        .map(|a| format!("rd.md.uuid={}", a.uuid))
+        .collect::<Vec<_>>();
+    let (rootdev, root_blockdev_kargs, crypttab) = if block_setup.is_encrypted() {
+        let uuid = setup_luks(
+            &opts,
+            Utf8Path::new(&root_devpath),
+            luks_name,
+            &methods,
+            passphrase.as_deref(),
+        )?;
+        let rootdev = format!("/dev/mapper/{luks_name}");
+        let options = luks_options(&methods);
+        let mut kargs = vec![format!("luks.uuid={uuid}")];
+        kargs.extend(options.iter().map(|o| format!("luks.options={o}")));
+        if methods.contains(&EncryptionMethod::Tang) {
+            kargs.push("rd.neednet=1".into());
         }
+        // This uses the same name as the device activated in the initramfs
+        // via luks.uuid, so that it is not activated twice.
+        let crypttab = format!(
+            "luks-{uuid} UUID={uuid} none {}",
+            options.as_deref().unwrap_or("-")
+        );
+        (rootdev, Some(kargs), Some(crypttab))
+    } else {
+        (root_devpath, None, None)
     };
 
+    // Any existing signatures in the new partitions are overwritten after wiping
+    let force = opts.wipe != WipeMode::FailIfNotEmpty;
     // Initialize the /boot filesystem
-    let bootdev = if let Some(bootpn) = boot_partno {
-        Some(base_partitions.find_partno(bootpn)?)
+    super::send_phase(state.progress.as_ref(), "mkfs");
+    let bootdev = if plan.get(PartitionRole::Boot).is_some() {
+        Some(device_of(PartitionRole::Boot)?)
     } else {
         None
     };
-    let boot_uuid = if let Some(bootdev) = bootdev {
-        Some(
-            mkfs(
-                bootdev.node.as_str(),
-                root_filesystem,
-                "boot",
-                opts.wipe,
-                [],
-            )
-            .context("Initializing /boot")?,
-        )
+    let boot_uuid = if let Some(bootdev) = bootdev.as_deref() {
+        Some(mkfs(bootdev, root_filesystem, "boot", force, []).context("Initializing /boot")?)
     } else {
         None
     };
 
     // Initialize rootfs
-    let root_uuid = mkfs(&rootdev, root_filesystem, "root", opts.wipe, [])?;
+    let root_config = state
+        .install_config
+        .as_ref()
+        .and_then(|c| c.filesystem_root());
+    let root_uuid = mkfs(
+        &rootdev,
+        root_filesystem,
+        &root.label,
+        force,
+        root_mkfs_options(root_config, root_filesystem)
+            .iter()
+            .map(|s| s.as_str()),
+    )?;
+    let root_mount_options = root_config
+        .and_then(|r| r.mount_options.as_deref())
+        .unwrap_or_default();
+    let subvolume_layout = root_config.and_then(|r| r.subvolumes).unwrap_or_default();
+    let root_subvol = if subvolume_layout {
+        create_subvolume_layout(&rootdev, &rootfs)?;
+        Some(format!("subvol={}", BTRFS_SUBVOLUMES[0].0))
+    } else {
+        None
+    };
     let rootarg = format!("root=UUID={root_uuid}");
+    let rootflags = root_subvol
+        .iter()
+        .chain(root_mount_options)
+        .map(|s| s.as_str())
+        .collect::<Vec<_>>();
+    let rootflags = (!rootflags.is_empty()).then(|| format!("rootflags={}", rootflags.join(",")));
     let bootsrc = boot_uuid.as_ref().map(|uuid| format!("UUID={uuid}"));
     let bootarg = bootsrc.as_deref().map(|bootsrc| format!("boot={bootsrc}"));
     let boot = bootsrc.map(|bootsrc| MountSpec {
@@ -378,49 +1837,835 @@ pub(crate) fn install_create_rootfs(
     let kargs = root_blockdev_kargs
         .into_iter()
         .flatten()
+        .chain(lvm_kargs)
+        .chain(raid_kargs)
         .chain([rootarg, RW_KARG.to_string()].into_iter())
+        .chain(rootflags)
         .chain(bootarg)
         .collect::<Vec<_>>();
 
-    mount::mount(&rootdev, &rootfs)?;
+    if let Some(options) = root_subvol.as_deref() {
+        mount::mount_with_options(&rootdev, &rootfs, options)?;
+    } else {
+        mount::mount(&rootdev, &rootfs)?;
+    }
     let target_rootfs = Dir::open_ambient_dir(&rootfs, cap_std::ambient_authority())?;
     crate::lsm::ensure_dir_labeled(&target_rootfs, "", Some("/".into()), 0o755.into(), sepolicy)?;
     let rootfs_fd = Dir::open_ambient_dir(&rootfs, cap_std::ambient_authority())?;
     let bootfs = rootfs.join("boot");
     // Create the underlying mount point directory, which should be labeled
     crate::lsm::ensure_dir_labeled(&target_rootfs, "boot", None, 0o755.into(), sepolicy)?;
-    if let Some(bootdev) = bootdev {
-        mount::mount(bootdev.node.as_str(), &bootfs)?;
+    if let Some(bootdev) = bootdev.as_deref() {
+        mount::mount(bootdev, &bootfs)?;
     }
     // And we want to label the root mount of /boot
     crate::lsm::ensure_dir_labeled(&target_rootfs, "boot", None, 0o755.into(), sepolicy)?;
 
     // Create the EFI system partition, if applicable
-    if let Some(esp_partno) = esp_partno {
-        let espdev = base_partitions.find_partno(esp_partno)?;
+    if plan.get(PartitionRole::Esp).is_some() {
+        let espdev = device_of(PartitionRole::Esp)?;
         Task::new("Creating ESP filesystem", "mkfs.fat")
-            .args([espdev.node.as_str(), "-n", "EFI-SYSTEM"])
+            .args([espdev.as_str(), "-n", "EFI-SYSTEM"])
             .verbose()
             .quiet_output()
             .run()?;
         let efifs_path = bootfs.join(crate::bootloader::EFI_DIR);
         std::fs::create_dir(&efifs_path).context("Creating efi dir")?;
-        mount::mount(espdev.node.as_str(), &efifs_path)?;
+        mount::mount(&espdev, &efifs_path)?;
+    } else if let Some(espdev) = preserved_esp.as_deref() {
+        let efifs_path = bootfs.join(crate::bootloader::EFI_DIR);
+        std::fs::create_dir(&efifs_path).context("Creating efi dir")?;
+        mount::mount(espdev, &efifs_path)?;
+    }
+
+    // Any additional partitions are only mounted via /etc/fstab in the target system
+    let mut mounts = Vec::new();
+    let layout = state
+        .install_config
+        .as_ref()
+        .and_then(|c| c.partitions.as_ref());
+    for (role, config) in [
+        (PartitionRole::Var, layout.and_then(|p| p.var.as_ref())),
+        (
+            PartitionRole::Containers,
+            layout.and_then(|p| p.containers.as_ref()),
+        ),
+    ] {
+        let Some(volume) = plan.get_volume(role) else {
+            continue;
+        };
+        let partitioning = plan.var_disk_to_sfdisk(&uuid::Uuid::new_v4());
+        let dev = match (role, var_device.as_ref(), partitioning) {
+            (PartitionRole::Var, Some(dev), Some(partitioning)) => {
+                let devpath = Utf8PathBuf::from(dev.path());
+                if dev.is_dasd() {
+                    // A single partition spanning the DASD
+                    Task::new("Initializing /var device", "fdasd")
+                        .args(["--silent", "--auto"])
+                        .arg(&devpath)
+                        .quiet()
+                        .run()?;
+                } else {
+                    Task::new("Initializing /var device", "sfdisk")
+                        .arg("--wipe=always")
+                        .arg(&devpath)
+                        .quiet()
+                        .run_with_stdin_buf(Some(partitioning.as_bytes()))?;
+                }
+                crate::blockdev::udev_settle()?;
+                crate::blockdev::partitions_of(&devpath)?
+                    .find_partno(1)?
+                    .node
+                    .clone()
+            }
+            _ => device_of(role)?,
+        };
+        let mountpoint = role.mountpoint();
+        let fstype = volume.fstype.expect("filesystem");
+        let mkfs_options = config
+            .and_then(|v| v.mkfs_options.as_deref())
+            .unwrap_or_default();
+        let uuid = mkfs(
+            &dev,
+            fstype,
+            &volume.label,
+            force,
+            mkfs_options.iter().map(|s| s.as_str()),
+        )
+        .with_context(|| format!("Initializing {mountpoint}"))?;
+        let mut spec = MountSpec::new_uuid_src(&uuid.to_string(), mountpoint);
+        for o in config
+            .and_then(|v| v.mount_options.as_deref())
+            .unwrap_or_default()
+        {
+            spec.push_option(o);
+        }
+        mounts.push(spec);
+    }
+    if let Some(subvol) = root_config.and_then(|r| r.var_subvolume.as_deref()) {
+        Task::new("Creating /var subvolume", "btrfs")
+            .args(["subvolume", "create"])
+            .arg(rootfs.join(subvol))
+            .quiet_output()
+            .run()?;
+        let mut spec = MountSpec::new_uuid_src(&root_uuid.to_string(), "/var");
+        spec.fstype = "btrfs".into();
+        spec.push_option(&format!("subvol={subvol}"));
+        for o in root_mount_options {
+            spec.push_option(o);
+        }
+        mounts.push(spec);
+    }
+    if let Some(swap) = plan.get_volume(PartitionRole::Swap) {
+        let swapdev = device_of(PartitionRole::Swap)?;
+        let uuid = uuid::Uuid::new_v4().to_string();
+        Task::new("Initializing swap", "mkswap")
+            .args(["-L", swap.label.as_str(), "-U", uuid.as_str()])
+            .args([swapdev.as_str()])
+            .quiet_output()
+            .run()?;
+        let mut spec = MountSpec::new_uuid_src(&uuid, "none");
+        spec.fstype = "swap".into();
+        mounts.push(spec);
+    }
+    if subvolume_layout {
+        mounts.extend(subvolume_mounts(&root_uuid.to_string(), root_mount_options));
     }
 
-    let luks_device = match block_setup {
-        BlockSetup::Direct => None,
-        BlockSetup::Tpm2Luks => Some(luks_name.to_string()),
+    let block_stack = BlockStack {
+        luks_device: block_setup.is_encrypted().then(|| luks_name.to_string()),
+        lvm_vg: plan.lvm.as_ref().map(|lvm| lvm.vg_name.clone()),
+        md_devices: raid_arrays.into_iter().map(|a| a.path).collect(),
     };
     let device_info = crate::blockdev::partitions_of(&devpath)?;
     Ok(RootSetup {
-        luks_device,
+        block_stack,
         device_info,
+        mirror_device_info: mirror_partitions,
         rootfs,
         rootfs_fd,
         rootfs_uuid: Some(root_uuid.to_string()),
         boot,
+        mounts,
+        crypttab,
         kargs,
         skip_finalize: false,
+        skip_bootloader: false,
     })
 }
+
+#[test]
+fn test_partition_plan() {
+    let default_layout = Partitions::default();
+    // The built-in layout
+    let plan = PartitionPlan::new(
+        "x86_64",
+        BlockSetup::Direct,
+        Filesystem::Xfs,
+        &default_layout,
+        None,
+    )
+    .unwrap();
+    let roles = plan.partitions.iter().map(|p| p.role).collect::<Vec<_>>();
+    assert_eq!(
+        roles,
+        [
+            PartitionRole::BiosBoot,
+            PartitionRole::Esp,
+            PartitionRole::Root
+        ]
+    );
+    let label_id = uuid::Uuid::nil();
+    assert_eq!(
+        plan.to_sfdisk(&label_id).unwrap(),
+        r#"label: gpt
+label-id: 00000000-0000-0000-0000-000000000000
+size=1MiB, bootable, type=21686148-6449-6E6F-744E-656564454649, name="BIOS-BOOT"
+size=512MiB, type=C12A7328-F81F-11D2-BA4B-00A0C93EC93B, name="EFI-SYSTEM"
+type=0FC63DAF-8483-4772-8E79-3D69D8477DE4, name="root"
+"#
+    );
+    assert_eq!(plan.partno(PartitionRole::Root), Some(3));
+    assert!(plan.validate_disk_size(10 * 1024).is_ok());
+    assert!(plan.validate_disk_size(515).is_err());
+
+    let plan = PartitionPlan::new(
+        "s390x",
+        BlockSetup::Tpm2Luks,
+        Filesystem::Ext4,
+        &default_layout,
+        Some("10G"),
+    )
+    .unwrap();
+    let roles = plan.partitions.iter().map(|p| p.role).collect::<Vec<_>>();
+    assert_eq!(roles, [PartitionRole::Boot, PartitionRole::Root]);
+    assert_eq!(plan.partitions[1].size_mib, Some(10 * 1024));
+    // DASDs are partitioned in tracks, here of 48 KiB
+    let geometry = crate::blockdev::DasdGeometry {
+        cylinders: 10017,
+        tracks_per_cylinder: 15,
+        blocks_per_track: 12,
+        block_size: 4096,
+    };
+    assert_eq!(
+        plan.to_fdasd(&geometry).unwrap(),
+        "[first,10881,native]\n[10882,229335,native]\n"
+    );
+
+    // zipl needs no bootloader partition
+    let mut plan = PartitionPlan::new(
+        "s390x",
+        BlockSetup::Direct,
+        Filesystem::Xfs,
+        &default_layout,
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        plan.to_sfdisk(&label_id).unwrap(),
+        r#"label: gpt
+label-id: 00000000-0000-0000-0000-000000000000
+type=0FC63DAF-8483-4772-8E79-3D69D8477DE4, name="root"
+"#
+    );
+    assert_eq!(plan.to_fdasd(&geometry).unwrap(), "[first,last,native]\n");
+    // Only the last partition can use the remaining space, and there are at most three
+    plan.partitions.push(PlannedPartition::new(
+        PartitionRole::Swap,
+        "swap",
+        Some(1024),
+        SWAP_PARTTYPE,
+        None,
+    ));
+    assert!(plan.to_fdasd(&geometry).is_err());
+    plan.partitions.swap(0, 1);
+    assert_eq!(
+        plan.to_fdasd(&geometry).unwrap(),
+        "[first,21847,swap]\n[21848,last,native]\n"
+    );
+    let extra = plan.partitions.clone();
+    plan.partitions.extend(extra);
+    assert!(plan.to_fdasd(&geometry).is_err());
+
+    // On ppc64le, Open Firmware loads GRUB from the PReP partition
+    let plan = PartitionPlan::new(
+        "powerpc64",
+        BlockSetup::Direct,
+        Filesystem::Xfs,
+        &default_layout,
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        plan.to_sfdisk(&label_id).unwrap(),
+        r#"label: gpt
+label-id: 00000000-0000-0000-0000-000000000000
+size=4MiB, bootable, type=9E1A2D38-C612-4316-AA26-8B49521E5A8B, name="PowerPC-PReP-boot"
+type=0FC63DAF-8483-4772-8E79-3D69D8477DE4, name="root"
+"#
+    );
+    assert_eq!(plan.partno(PartitionRole::Root), Some(2));
+    // Without the header when appending to an existing partition table
+    assert_eq!(
+        plan.to_sfdisk_partitions().unwrap(),
+        r#"size=4MiB, bootable, type=9E1A2D38-C612-4316-AA26-8B49521E5A8B, name="PowerPC-PReP-boot"
+type=0FC63DAF-8483-4772-8E79-3D69D8477DE4, name="root"
+"#
+    );
+
+    // A separate /var using the remaining space goes last
+    let layout = Partitions {
+        esp_size: Some("1G".into()),
+        root_size: Some("20G".into()),
+        root_label: Some("system".into()),
+        var: Some(ExtraPartition {
+            fstype: Some(Filesystem::Ext4),
+            ..Default::default()
+        }),
+        swap: Some(ExtraPartition {
+            size: Some("4G".into()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let plan = PartitionPlan::new(
+        "aarch64",
+        BlockSetup::Direct,
+        Filesystem::Xfs,
+        &layout,
+        None,
+    )
+    .unwrap();
+    let summary = plan
+        .partitions
+        .iter()
+        .map(|p| (p.role, p.label.as_str(), p.size_mib, p.fstype))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        [
+            (PartitionRole::Esp, "EFI-SYSTEM", Some(1024), None),
+            (PartitionRole::Swap, "swap", Some(4096), None),
+            (
+                PartitionRole::Root,
+                "system",
+                Some(20 * 1024),
+                Some(Filesystem::Xfs)
+            ),
+            (PartitionRole::Var, "var", None, Some(Filesystem::Ext4)),
+        ]
+    );
+    assert!(plan.validate_disk_size(25 * 1024 + 2).is_err());
+    assert!(plan.validate_disk_size(30 * 1024).is_ok());
+    // The command line overrides the configured root size
+    let plan = PartitionPlan::new(
+        "aarch64",
+        BlockSetup::Direct,
+        Filesystem::Xfs,
+        &layout,
+        Some("30G"),
+    )
+    .unwrap();
+    assert_eq!(
+        plan.get(PartitionRole::Root).unwrap().size_mib,
+        Some(30 * 1024)
+    );
+}
+
+#[test]
+fn test_partition_plan_invalid() {
+    let new = |layout: Partitions, block_setup| {
+        PartitionPlan::new("x86_64", block_setup, Filesystem::Xfs, &layout, None)
+    };
+    let var = || ExtraPartition {
+        size: Some("10G".into()),
+        ..Default::default()
+    };
+    for layout in [
+        // Too small
+        Partitions {
+            esp_size: Some("32M".into()),
+            ..Default::default()
+        },
+        // Not a size
+        Partitions {
+            root_size: Some("lots".into()),
+            ..Default::default()
+        },
+        Partitions {
+            root_size: Some("0".into()),
+            ..Default::default()
+        },
+        // Both root and /var would use the remaining space
+        Partitions {
+            var: Some(ExtraPartition::default()),
+            ..Default::default()
+        },
+        // Swap requires a size
+        Partitions {
+            swap: Some(ExtraPartition::default()),
+            ..Default::default()
+        },
+        // Longer than the xfs limit
+        Partitions {
+            root_label: Some("a-very-long-root".into()),
+            ..Default::default()
+        },
+        Partitions {
+            root_label: Some("root fs".into()),
+            ..Default::default()
+        },
+        Partitions {
+            var: Some(ExtraPartition {
+                label: Some("root".into()),
+                ..var()
+            }),
+            ..Default::default()
+        },
+    ] {
+        assert!(
+            new(layout.clone(), BlockSetup::Direct).is_err(),
+            "{layout:?}"
+        );
+    }
+    let layout = Partitions {
+        var: Some(var()),
+        ..Default::default()
+    };
+    assert!(new(layout.clone(), BlockSetup::Direct).is_ok());
+    // Only the root filesystem would be encrypted
+    assert!(new(layout, BlockSetup::Tpm2Luks).is_err());
+    // A too small /boot
+    let layout = Partitions {
+        boot_size: Some("100M".into()),
+        ..Default::default()
+    };
+    assert!(new(layout.clone(), BlockSetup::Direct).is_ok());
+    assert!(new(layout, BlockSetup::Tpm2Luks).is_err());
+}
+
+#[test]
+fn test_encryption_methods() {
+    use EncryptionMethod::*;
+
+    let opts = |v: serde_json::Value| -> InstallBlockDeviceOpts {
+        let mut o = serde_json::json!({ "device": "/dev/vda" });
+        o.as_object_mut()
+            .unwrap()
+            .extend(v.as_object().unwrap().clone());
+        serde_json::from_value(o).unwrap()
+    };
+    let o = opts(serde_json::json!({}));
+    assert!(o.encryption_methods(BlockSetup::Direct).unwrap().is_empty());
+    assert_eq!(o.encryption_methods(BlockSetup::Tpm2Luks).unwrap(), [Tpm2]);
+    assert!(o.encryption_methods(BlockSetup::Luks).is_err());
+
+    let o = opts(serde_json::json!({
+        "encrypt": ["tpm2", "passphrase", "tpm2"],
+        "encrypt-passphrase-file": "/run/passphrase",
+    }));
+    assert_eq!(
+        o.encryption_methods(BlockSetup::Luks).unwrap(),
+        [Tpm2, Passphrase]
+    );
+    assert!(o.encryption_methods(BlockSetup::Direct).is_err());
+    assert!(o.encryption_methods(BlockSetup::Tpm2Luks).is_err());
+
+    for v in [
+        serde_json::json!({ "encrypt": ["passphrase"] }),
+        serde_json::json!({ "encrypt": ["tang"] }),
+        serde_json::json!({ "encrypt": ["tpm2"], "encrypt-tang-url": "http://tang.example.com" }),
+        serde_json::json!({ "encrypt": ["tpm2"], "encrypt-tang-thumbprint": "abc" }),
+    ] {
+        assert!(opts(v).encryption_methods(BlockSetup::Luks).is_err());
+    }
+    let o = opts(serde_json::json!({
+        "encrypt": ["tang"],
+        "encrypt-tang-url": "http://tang.example.com",
+    }));
+    assert_eq!(o.encryption_methods(BlockSetup::Luks).unwrap(), [Tang]);
+}
+
+#[test]
+fn test_luks_options() {
+    use EncryptionMethod::*;
+
+    assert_eq!(
+        luks_options(&[Tpm2]).unwrap(),
+        "tpm2-device=auto,headless=true"
+    );
+    assert_eq!(
+        luks_options(&[Tpm2, Passphrase]).unwrap(),
+        "tpm2-device=auto"
+    );
+    assert_eq!(luks_options(&[Tang]), None);
+    assert_eq!(luks_options(&[Passphrase]), None);
+}
+
+#[test]
+fn test_partition_plan_lvm() {
+    use super::config::{ExtraPartition, Lvm};
+
+    let volumes = |plan: &PartitionPlan| {
+        plan.lvm
+            .as_ref()
+            .unwrap()
+            .volumes
+            .iter()
+            .map(|v| (v.role, v.label.clone(), v.size_mib))
+            .collect::<Vec<_>>()
+    };
+    let mut layout = Partitions {
+        root_size: Some("20G".into()),
+        var: Some(ExtraPartition::default()),
+        swap: Some(ExtraPartition {
+            size: Some("2G".into()),
+            ..Default::default()
+        }),
+        lvm: Some(Lvm::default()),
+        ..Default::default()
+    };
+    let plan = PartitionPlan::new(
+        "aarch64",
+        BlockSetup::Direct,
+        Filesystem::Xfs,
+        &layout,
+        None,
+    )
+    .unwrap();
+    // A separate /boot is always created, and the physical volume uses the remaining space
+    let roles = plan.partitions.iter().map(|p| p.role).collect::<Vec<_>>();
+    assert_eq!(
+        roles,
+        [
+            PartitionRole::Esp,
+            PartitionRole::Boot,
+            PartitionRole::LvmPv
+        ]
+    );
+    assert_eq!(plan.lvm.as_ref().unwrap().vg_name, "bootc");
+    assert_eq!(
+        volumes(&plan),
+        [
+            (PartitionRole::Swap, "swap".into(), Some(2048)),
+            (PartitionRole::Root, "root".into(), Some(20 * 1024)),
+            (PartitionRole::Var, "var".into(), None),
+        ]
+    );
+    assert_eq!(
+        plan.get_volume(PartitionRole::Root).unwrap().size_mib,
+        Some(20 * 1024)
+    );
+    assert!(plan.get(PartitionRole::Root).is_none());
+    // 512M ESP, 510M /boot, and the volumes
+    assert!(plan.validate_disk_size(25 * 1024).is_ok());
+    assert!(plan.validate_disk_size(23 * 1024).is_err());
+
+    // Thin volumes may both default to the size of the pool
+    layout.root_size = None;
+    assert!(PartitionPlan::new(
+        "aarch64",
+        BlockSetup::Direct,
+        Filesystem::Xfs,
+        &layout,
+        None
+    )
+    .is_err());
+    layout.lvm = Some(Lvm {
+        vg_name: Some("system".into()),
+        thin: Some(true),
+    });
+    let plan = PartitionPlan::new(
+        "aarch64",
+        BlockSetup::Direct,
+        Filesystem::Xfs,
+        &layout,
+        None,
+    )
+    .unwrap();
+    let lvm = plan.lvm.as_ref().unwrap();
+    assert_eq!(lvm.vg_name, "system");
+    let thin = lvm
+        .volumes
+        .iter()
+        .filter(|v| lvm.is_thin(v))
+        .map(|v| v.role)
+        .collect::<Vec<_>>();
+    assert_eq!(thin, [PartitionRole::Root, PartitionRole::Var]);
+    assert!(plan.validate_disk_size(4 * 1024).is_ok());
+    assert!(plan.validate_disk_size(3 * 1024).is_err());
+
+    // LVM on LUKS is not supported
+    assert!(PartitionPlan::new(
+        "aarch64",
+        BlockSetup::Tpm2Luks,
+        Filesystem::Xfs,
+        &Partitions {
+            lvm: Some(Lvm::default()),
+            ..Default::default()
+        },
+        None
+    )
+    .is_err());
+}
+
+#[test]
+fn test_partition_plan_raid1() {
+    let new = |arch, layout: &Partitions| {
+        PartitionPlan::new(arch, BlockSetup::Raid1, Filesystem::Xfs, layout, None)
+    };
+    let plan = new("x86_64", &Partitions::default()).unwrap();
+    let parttypes = plan
+        .partitions
+        .iter()
+        .map(|p| (p.role, p.parttype))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        parttypes,
+        [
+            (PartitionRole::BiosBoot, BIOS_BOOT_PARTTYPE),
+            (PartitionRole::Esp, ESP_PARTTYPE),
+            (PartitionRole::Boot, RAID_PARTTYPE),
+            (PartitionRole::Root, RAID_PARTTYPE),
+        ]
+    );
+    assert!(new("s390x", &Partitions::default()).is_err());
+    let layout = Partitions {
+        var: Some(ExtraPartition::default()),
+        ..Default::default()
+    };
+    assert!(new("x86_64", &layout).is_err());
+
+    let uuid = uuid::Uuid::parse_str("0f2d0b4c-6c6e-4a5e-9d1a-8a8f4c4b7e21").unwrap();
+    assert_eq!(md_uuid(&uuid), "0f2d0b4c:6c6e4a5e:9d1a8a8f:4c4b7e21");
+}
+
+#[test]
+fn test_partition_plan_json() {
+    let plan = PartitionPlan::new(
+        "s390x",
+        BlockSetup::Direct,
+        Filesystem::Xfs,
+        &Partitions::default(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        serde_json::to_value(&plan).unwrap(),
+        serde_json::json!({
+            "partitions": [{
+                "role": "root",
+                "label": "root",
+                "size-mib": null,
+                "parttype": "0FC63DAF-8483-4772-8E79-3D69D8477DE4",
+                "fstype": "xfs",
+            }],
+            "lvm": null,
+        })
+    );
+}
+
+#[test]
+fn test_var_subvolume() {
+    let default_layout = Partitions::default();
+    let plan = PartitionPlan::new(
+        "x86_64",
+        BlockSetup::Direct,
+        Filesystem::Btrfs,
+        &default_layout,
+        None,
+    )
+    .unwrap();
+    assert!(validate_var_subvolume("var", Filesystem::Btrfs, &plan).is_ok());
+    for invalid in ["", "a/b", ".."] {
+        assert!(validate_var_subvolume(invalid, Filesystem::Btrfs, &plan).is_err());
+    }
+    assert!(validate_var_subvolume("var", Filesystem::Xfs, &plan).is_err());
+
+    let layout = Partitions {
+        root_size: Some("10G".into()),
+        var: Some(super::config::ExtraPartition::default()),
+        ..Default::default()
+    };
+    let plan = PartitionPlan::new(
+        "x86_64",
+        BlockSetup::Direct,
+        Filesystem::Btrfs,
+        &layout,
+        None,
+    )
+    .unwrap();
+    assert!(validate_var_subvolume("var", Filesystem::Btrfs, &plan).is_err());
+}
+
+#[test]
+fn test_root_mkfs_options() {
+    let root = RootFS {
+        fstype: Some(Filesystem::Xfs),
+        mkfs_options: Some(vec!["-i".into(), "size=512".into()]),
+        ..Default::default()
+    };
+    assert_eq!(
+        root_mkfs_options(Some(&root), Filesystem::Xfs),
+        ["-i", "size=512"]
+    );
+    // Overridden via --filesystem
+    assert!(root_mkfs_options(Some(&root), Filesystem::Ext4).is_empty());
+    assert!(root_mkfs_options(None, Filesystem::Xfs).is_empty());
+}
+
+#[test]
+fn test_subvolume_layout() {
+    let plan = PartitionPlan::new(
+        "x86_64",
+        BlockSetup::Direct,
+        Filesystem::Btrfs,
+        &Partitions::default(),
+        None,
+    )
+    .unwrap();
+    let root = RootFS {
+        subvolumes: Some(true),
+        ..Default::default()
+    };
+    assert!(validate_subvolume_layout(&root, Filesystem::Btrfs, &plan).is_ok());
+    assert!(validate_subvolume_layout(&root, Filesystem::Xfs, &plan).is_err());
+    let conflicting = RootFS {
+        var_subvolume: Some("var".into()),
+        ..root.clone()
+    };
+    assert!(validate_subvolume_layout(&conflicting, Filesystem::Btrfs, &plan).is_err());
+
+    let fstab = subvolume_mounts("abc", &["compress=zstd:1".into()])
+        .iter()
+        .map(|m| m.to_fstab())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        fstab,
+        [
+            "UUID=abc /var btrfs subvol=var,compress=zstd:1 0 0",
+            "UUID=abc /var/home btrfs subvol=home,compress=zstd:1 0 0",
+            "UUID=abc /var/.snapshots btrfs subvol=snapshots,compress=zstd:1 0 0",
+        ]
+    );
+}
+
+#[test]
+fn test_partition_plan_containers() {
+    let layout = Partitions {
+        root_size: Some("20G".into()),
+        containers: Some(ExtraPartition {
+            fstype: Some(Filesystem::Ext4),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let plan =
+        PartitionPlan::new("x86_64", BlockSetup::Direct, Filesystem::Xfs, &layout, None).unwrap();
+    let containers = plan.get(PartitionRole::Containers).unwrap();
+    assert_eq!(containers.label, "containers");
+    assert_eq!(containers.size_mib, None);
+    assert_eq!(containers.role.mountpoint(), "/var/lib/containers");
+    // The partition using the remaining space goes last
+    assert_eq!(
+        plan.partitions.last().unwrap().role,
+        PartitionRole::Containers
+    );
+    // Root and /var/lib/containers can't both use the remaining space
+    let layout = Partitions {
+        root_size: None,
+        ..layout
+    };
+    assert!(
+        PartitionPlan::new("x86_64", BlockSetup::Direct, Filesystem::Xfs, &layout, None).is_err()
+    );
+}
+
+#[test]
+fn test_partition_plan_var_disk() {
+    let mut plan = PartitionPlan::new(
+        "x86_64",
+        BlockSetup::Direct,
+        Filesystem::Xfs,
+        &Partitions::default(),
+        None,
+    )
+    .unwrap();
+    assert!(plan.var_disk_to_sfdisk(&uuid::Uuid::nil()).is_none());
+    let duplicate = ExtraPartition {
+        label: Some("root".into()),
+        ..Default::default()
+    };
+    assert!(plan.set_var_disk(&duplicate, Filesystem::Xfs).is_err());
+    plan.set_var_disk(&ExtraPartition::default(), Filesystem::Xfs)
+        .unwrap();
+    let var = plan.get_volume(PartitionRole::Var).unwrap();
+    assert_eq!(var.fstype, Some(Filesystem::Xfs));
+    assert!(plan.get(PartitionRole::Var).is_none());
+    assert_eq!(
+        plan.var_disk_to_sfdisk(&uuid::Uuid::nil()).unwrap(),
+        indoc::indoc! { r#"
+        label: gpt
+        label-id: 00000000-0000-0000-0000-000000000000
+        type=0FC63DAF-8483-4772-8E79-3D69D8477DE4, name="var"
+        "#}
+    );
+}
+
+#[test]
+fn test_wipe_mode() {
+    use clap::Parser;
+
+    let parse = |args: &[&str]| {
+        let args = ["to-disk"].iter().chain(args).chain(["/dev/vda"].iter());
+        super::InstallToDiskOpts::try_parse_from(args).map(|o| o.block_opts)
+    };
+    assert_eq!(parse(&[]).unwrap().wipe, WipeMode::FailIfNotEmpty);
+    assert_eq!(parse(&["--wipe"]).unwrap().wipe, WipeMode::All);
+    assert_eq!(parse(&["--wipe=gpt-only"]).unwrap().wipe, WipeMode::GptOnly);
+    assert!(parse(&["--wipe=foo"]).is_err());
+    let o = parse(&["--wipe", "--discard", "--preserve-partition=RECOVERY"]).unwrap();
+    assert!(o.discard);
+    assert_eq!(o.preserve_partitions, ["RECOVERY"]);
+
+    // The boolean of older versions
+    for (v, expected) in [
+        (serde_json::json!(true), WipeMode::All),
+        (serde_json::json!(false), WipeMode::FailIfNotEmpty),
+        (serde_json::json!("gpt-only"), WipeMode::GptOnly),
+    ] {
+        let o: InstallBlockDeviceOpts =
+            serde_json::from_value(serde_json::json!({ "device": "/dev/vda", "wipe": v })).unwrap();
+        assert_eq!(o.wipe, expected);
+    }
+}
+
+#[test]
+fn test_preserved_partitions() {
+    let device: crate::blockdev::Device = serde_json::from_value(serde_json::json!({
+        "name": "nvme0n1", "size": 512110190592u64, "pttype": "gpt",
+        "children": [
+            { "name": "nvme0n1p1", "size": 272629760, "partlabel": "EFI system partition",
+              "parttype": "c12a7328-f81f-11d2-ba4b-00a0c93ec93b", "label": "SYSTEM", "fstype": "vfat" },
+            { "name": "nvme0n1p2", "size": 1073741824, "partlabel": "Basic data partition",
+              "label": "RECOVERY", "fstype": "ntfs" },
+            { "name": "nvme0n1p3", "size": 510763810816u64, "partlabel": "root", "fstype": "xfs" },
+        ]
+    }))
+    .unwrap();
+    let labels = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    assert!(preserved_partitions(&device, &[]).unwrap().is_empty());
+    let found = preserved_partitions(&device, &labels(&["EFI system partition", "RECOVERY"]))
+        .unwrap()
+        .iter()
+        .map(|p| (p.path(), is_esp(p)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        [
+            ("/dev/nvme0n1p1".to_string(), true),
+            ("/dev/nvme0n1p2".to_string(), false)
+        ]
+    );
+    assert!(preserved_partitions(&device, &labels(&["nope"])).is_err());
+}
@@ -0,0 +1,225 @@
+//! # Declarative installations via `bootc install from-config`
+//!
+//! A blueprint is a TOML document which describes the target disk, the options
+//! of `bootc install to-disk` and install configuration overriding that of the
+//! image, so that the same installation can be repeated on many machines.
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::Parser;
+use fn_error_context::context;
+use serde::Deserialize;
+
+use super::config::InstallConfiguration;
+use super::InstallToDiskOpts;
+use crate::blockdev::Device;
+
+/// Options of `bootc install to-disk` which are set by other parts of a blueprint.
+const RESERVED_OPTIONS: &[&str] = &["device", "device-match"];
+
+/// Options for `bootc install from-config`.
+#[derive(Debug, Clone, Parser, PartialEq, Eq)]
+pub(crate) struct InstallFromConfigOpts {
+    /// The path to the blueprint.
+    pub(crate) path: Utf8PathBuf,
+
+    /// Print the partitions which would be created, and exit without changing anything.
+    #[clap(long)]
+    pub(crate) print_plan: bool,
+}
+
+/// The disk to install to; exactly one of the fields must be set.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct BlueprintTarget {
+    /// The path of the block device
+    pub(crate) device: Option<Utf8PathBuf>,
+    /// The serial number of the disk, as shown by `lsblk -o SERIAL`
+    pub(crate) serial: Option<String>,
+    /// The World Wide Name of the disk, as shown by `lsblk -o WWN`
+    pub(crate) wwn: Option<String>,
+}
+
+/// The contents of a blueprint.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Blueprint {
+    /// The disk to install to
+    pub(crate) target: BlueprintTarget,
+    /// Long options of `bootc install to-disk`, without the leading `--`
+    #[serde(default)]
+    pub(crate) options: toml::Table,
+    /// Install configuration, taking precedence over that of the image
+    pub(crate) install: Option<InstallConfiguration>,
+}
+
+fn normalize_wwn(wwn: &str) -> String {
+    wwn.trim().trim_start_matches("0x").to_ascii_lowercase()
+}
+
+impl BlueprintTarget {
+    fn matches(&self, dev: &Device) -> bool {
+        match (self.serial.as_deref(), self.wwn.as_deref()) {
+            (Some(serial), _) => dev.serial.as_deref().map(str::trim) == Some(serial.trim()),
+            (None, Some(wwn)) => dev.wwn.as_deref().map(normalize_wwn) == Some(normalize_wwn(wwn)),
+            (None, None) => false,
+        }
+    }
+
+    /// Find the target among the given disks.
+    fn find(&self, disks: &[Device]) -> Result<Utf8PathBuf> {
+        let found = disks.iter().filter(|d| self.matches(d)).collect::<Vec<_>>();
+        match found.as_slice() {
+            [dev] => Ok(dev.path().into()),
+            [] => anyhow::bail!("No disk found matching {self:?}"),
+            devs => {
+                let names = devs.iter().map(|d| d.path()).collect::<Vec<_>>();
+                anyhow::bail!("Multiple disks match {self:?}: {}", names.join(", "))
+            }
+        }
+    }
+
+    /// Find the block device to install to.
+    #[context("Finding target disk")]
+    pub(crate) fn resolve(&self) -> Result<Utf8PathBuf> {
+        match (&self.device, &self.serial, &self.wwn) {
+            (Some(dev), None, None) => Ok(dev.clone()),
+            (None, Some(_), None) | (None, None, Some(_)) => {
+                self.find(&crate::blockdev::list_disks()?)
+            }
+            _ => anyhow::bail!("Exactly one of device, serial and wwn must be set"),
+        }
+    }
+}
+
+impl Blueprint {
+    /// Read a blueprint from the given path.
+    #[context("Loading blueprint {path}")]
+    pub(crate) fn load(path: &Utf8Path) -> Result<Self> {
+        let buf = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&buf)?)
+    }
+
+    /// Convert the options to command line arguments of `bootc install to-disk`.
+    fn args(&self) -> Result<Vec<String>> {
+        let mut args = Vec::new();
+        for (name, value) in self.options.iter() {
+            if RESERVED_OPTIONS.contains(&name.as_str()) {
+                anyhow::bail!("The {name} option is set via the [target] table");
+            }
+            let values = match value {
+                toml::Value::Array(values) => values.as_slice(),
+                value => std::slice::from_ref(value),
+            };
+            for value in values {
+                match value {
+                    toml::Value::Boolean(true) => args.push(format!("--{name}")),
+                    toml::Value::Boolean(false) => {}
+                    toml::Value::String(v) => args.push(format!("--{name}={v}")),
+                    toml::Value::Integer(v) => args.push(format!("--{name}={v}")),
+                    o => anyhow::bail!("Unsupported value for option {name}: {o}"),
+                }
+            }
+        }
+        Ok(args)
+    }
+
+    /// The options of `bootc install to-disk` to install to the given device.
+    pub(crate) fn to_disk_opts(&self, device: &Utf8Path) -> Result<InstallToDiskOpts> {
+        let args = std::iter::once("to-disk".to_owned())
+            .chain(self.args()?)
+            .chain(std::iter::once(device.to_string()));
+        let mut opts = InstallToDiskOpts::try_parse_from(args).context("Parsing options")?;
+        opts.config_opts.install_config = self.install.clone();
+        Ok(opts)
+    }
+}
+
+#[test]
+fn test_blueprint_to_disk_opts() {
+    use super::baseline::{BlockSetup, WipeMode};
+
+    let blueprint: Blueprint = toml::from_str(indoc::indoc! {r#"
+        [target]
+        serial = "S4EVNF0M123456"
+
+        [options]
+        source-imgref = "docker://quay.io/example/os:latest"
+        block-setup = "tpm2-luks"
+        wipe = true
+        generic-image = false
+        karg = ["console=ttyS0", "nosmt"]
+        add-user = ["core:1000:wheel"]
+
+        [install.partitions]
+        root-size = "20G"
+    "#})
+    .unwrap();
+    let opts = blueprint.to_disk_opts(Utf8Path::new("/dev/sda")).unwrap();
+    assert_eq!(opts.block_opts.device.unwrap(), "/dev/sda");
+    assert_eq!(opts.block_opts.block_setup, Some(BlockSetup::Tpm2Luks));
+    assert_eq!(opts.block_opts.wipe, WipeMode::All);
+    assert!(!opts.config_opts.generic_image);
+    assert_eq!(
+        opts.config_opts.karg.as_deref().unwrap(),
+        ["console=ttyS0", "nosmt"]
+    );
+    assert_eq!(
+        opts.source_opts.source_imgref.as_deref(),
+        Some("docker://quay.io/example/os:latest")
+    );
+    let partitions = opts.config_opts.install_config.unwrap().partitions.unwrap();
+    assert_eq!(partitions.root_size.as_deref(), Some("20G"));
+
+    let invalid = [
+        // The device comes from the target
+        "[target]\ndevice = \"/dev/vda\"\n[options]\ndevice = \"/dev/vdb\"\n",
+        // Unknown options
+        "[target]\ndevice = \"/dev/vda\"\n[options]\nfoo = true\n",
+        "[target]\ndevice = \"/dev/vda\"\n[options.karg]\nfoo = \"bar\"\n",
+    ];
+    for s in invalid {
+        let blueprint: Blueprint = toml::from_str(s).unwrap();
+        assert!(
+            blueprint.to_disk_opts(Utf8Path::new("/dev/vda")).is_err(),
+            "{s}"
+        );
+    }
+    assert!(toml::from_str::<Blueprint>("[target]\n[options]\n[foo]\n").is_err());
+}
+
+#[test]
+fn test_blueprint_target() {
+    let disks: Vec<Device> = serde_json::from_value(serde_json::json!([
+        { "name": "sda", "serial": "S4EVNF0M123456", "wwn": "0x5000c500a1b2c3d4", "size": 1000 },
+        { "name": "sdb", "serial": "S4EVNF0M654321", "wwn": "0x5000c500a1b2c3d5", "size": 1000 },
+        { "name": "vda", "serial": null, "wwn": null, "size": 1000 },
+    ]))
+    .unwrap();
+    let serial = BlueprintTarget {
+        serial: Some("S4EVNF0M654321".into()),
+        ..Default::default()
+    };
+    assert_eq!(serial.find(&disks).unwrap(), "/dev/sdb");
+    let wwn = BlueprintTarget {
+        wwn: Some("5000C500A1B2C3D4".into()),
+        ..Default::default()
+    };
+    assert_eq!(wwn.find(&disks).unwrap(), "/dev/sda");
+    let missing = BlueprintTarget {
+        serial: Some("nope".into()),
+        ..Default::default()
+    };
+    assert!(missing.find(&disks).is_err());
+    let device = BlueprintTarget {
+        device: Some("/dev/vda".into()),
+        ..Default::default()
+    };
+    assert_eq!(device.resolve().unwrap(), "/dev/vda");
+    let both = BlueprintTarget {
+        wwn: Some("0x5000c500a1b2c3d4".into()),
+        ..device
+    };
+    assert!(both.resolve().is_err());
+    assert!(BlueprintTarget::default().resolve().is_err());
+}
@@ -2,11 +2,14 @@
 //!
 //! This module handles the TOML configuration file for `bootc install`.
 
+use std::collections::BTreeMap;
+
 use anyhow::{Context, Result};
 use fn_error_context::context;
 use serde::{Deserialize, Serialize};
 
 use super::baseline::BlockSetup;
+use super::platform::Platform;
 
 /// Properties of the environment, such as the system architecture
 /// Left open for future properties such as `platform.id`
@@ -23,16 +26,24 @@ pub(crate) struct InstallConfigurationToplevel {
 }
 
 /// Configuration for a filesystem
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub(crate) struct RootFS {
     #[serde(rename = "type")]
     pub(crate) fstype: Option<super::baseline::Filesystem>,
+    /// Additional arguments for `mkfs.$fstype`
+    pub(crate) mkfs_options: Option<Vec<String>>,
+    /// Mount options, passed via the `rootflags=` kernel argument
+    pub(crate) mount_options: Option<Vec<String>>,
+    /// Create /var as a btrfs subvolume with this name
+    pub(crate) var_subvolume: Option<String>,
+    /// Create the conventional btrfs subvolume layout
+    pub(crate) subvolumes: Option<bool>,
 }
 
 /// This structure should only define "system" or "basic" filesystems; we are
 /// not trying to generalize this into e.g. supporting `/var` or other ones.
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub(crate) struct BasicFilesystems {
     pub(crate) root: Option<RootFS>,
@@ -41,8 +52,58 @@ pub(crate) struct BasicFilesystems {
     // pub(crate) esp: Option<FilesystemCustomization>,
 }
 
+/// An additional partition created by `install to-disk`.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct ExtraPartition {
+    /// Size, with the same specifiers as `--root-size`
+    pub(crate) size: Option<String>,
+    /// Partition and filesystem label
+    pub(crate) label: Option<String>,
+    /// Filesystem type; defaults to that of the root filesystem
+    #[serde(rename = "type")]
+    pub(crate) fstype: Option<super::baseline::Filesystem>,
+    /// Additional arguments for `mkfs.$fstype`
+    pub(crate) mkfs_options: Option<Vec<String>>,
+    /// Mount options for `/etc/fstab`
+    pub(crate) mount_options: Option<Vec<String>>,
+}
+
+/// Use LVM for the root, /var and swap volumes of `install to-disk`.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Lvm {
+    /// Name of the volume group
+    pub(crate) vg_name: Option<String>,
+    /// Create root and /var as thin volumes in a thin pool
+    pub(crate) thin: Option<bool>,
+}
+
+/// The partition layout used by `install to-disk`; anything not set here
+/// uses the built-in default.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Partitions {
+    /// Size of the EFI system partition
+    pub(crate) esp_size: Option<String>,
+    /// Size of the separate /boot partition, if the block setup requires one
+    pub(crate) boot_size: Option<String>,
+    /// Size of the root partition; by default, all remaining space is used
+    pub(crate) root_size: Option<String>,
+    /// Partition and filesystem label of the root partition
+    pub(crate) root_label: Option<String>,
+    /// A separate partition for /var
+    pub(crate) var: Option<ExtraPartition>,
+    /// A separate partition for /var/lib/containers
+    pub(crate) containers: Option<ExtraPartition>,
+    /// A swap partition
+    pub(crate) swap: Option<ExtraPartition>,
+    /// Create root, /var and swap as logical volumes instead of partitions
+    pub(crate) lvm: Option<Lvm>,
+}
+
 /// The serialized [install] section
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
 #[serde(rename = "install", rename_all = "kebab-case", deny_unknown_fields)]
 pub(crate) struct InstallConfiguration {
     /// Root filesystem type
@@ -50,9 +111,15 @@ pub(crate) struct InstallConfiguration {
     /// Enabled block storage configurations
     pub(crate) block: Option<Vec<BlockSetup>>,
     pub(crate) filesystem: Option<BasicFilesystems>,
+    /// Partition layout for `install to-disk`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) partitions: Option<Partitions>,
     /// Kernel arguments, applied at installation time
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) kargs: Option<Vec<String>>,
+    /// Console kernel arguments per platform, replacing the built-in ones
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) console: Option<BTreeMap<Platform, Vec<String>>>,
     /// Supported architectures for this configuration
     pub(crate) match_architectures: Option<Vec<String>>,
 }
@@ -90,7 +157,11 @@ where
 impl Mergeable for RootFS {
     /// Apply any values in other, overriding any existing values in `self`.
     fn merge(&mut self, other: Self, env: &EnvProperties) {
-        merge_basic(&mut self.fstype, other.fstype, env)
+        merge_basic(&mut self.fstype, other.fstype, env);
+        merge_basic(&mut self.mkfs_options, other.mkfs_options, env);
+        merge_basic(&mut self.mount_options, other.mount_options, env);
+        merge_basic(&mut self.var_subvolume, other.var_subvolume, env);
+        merge_basic(&mut self.subvolumes, other.subvolumes, env);
     }
 }
 
@@ -101,6 +172,39 @@ impl Mergeable for BasicFilesystems {
     }
 }
 
+impl Mergeable for ExtraPartition {
+    /// Apply any values in other, overriding any existing values in `self`.
+    fn merge(&mut self, other: Self, env: &EnvProperties) {
+        merge_basic(&mut self.size, other.size, env);
+        merge_basic(&mut self.label, other.label, env);
+        merge_basic(&mut self.fstype, other.fstype, env);
+        merge_basic(&mut self.mkfs_options, other.mkfs_options, env);
+        merge_basic(&mut self.mount_options, other.mount_options, env);
+    }
+}
+
+impl Mergeable for Lvm {
+    /// Apply any values in other, overriding any existing values in `self`.
+    fn merge(&mut self, other: Self, env: &EnvProperties) {
+        merge_basic(&mut self.vg_name, other.vg_name, env);
+        merge_basic(&mut self.thin, other.thin, env);
+    }
+}
+
+impl Mergeable for Partitions {
+    /// Apply any values in other, overriding any existing values in `self`.
+    fn merge(&mut self, other: Self, env: &EnvProperties) {
+        merge_basic(&mut self.esp_size, other.esp_size, env);
+        merge_basic(&mut self.boot_size, other.boot_size, env);
+        merge_basic(&mut self.root_size, other.root_size, env);
+        merge_basic(&mut self.root_label, other.root_label, env);
+        self.var.merge(other.var, env);
+        self.containers.merge(other.containers, env);
+        self.swap.merge(other.swap, env);
+        self.lvm.merge(other.lvm, env);
+    }
+}
+
 impl Mergeable for InstallConfiguration {
     /// Apply any values in other, overriding any existing values in `self`.
     fn merge(&mut self, other: Self, env: &EnvProperties) {
@@ -114,11 +218,17 @@ impl Mergeable for InstallConfiguration {
             merge_basic(&mut self.root_fs_type, other.root_fs_type, env);
             merge_basic(&mut self.block, other.block, env);
             self.filesystem.merge(other.filesystem, env);
+            self.partitions.merge(other.partitions, env);
             if let Some(other_kargs) = other.kargs {
                 self.kargs
                     .get_or_insert_with(Default::default)
                     .extend(other_kargs)
             }
+            if let Some(other_console) = other.console {
+                self.console
+                    .get_or_insert_with(Default::default)
+                    .extend(other_console)
+            }
         }
     }
 }
@@ -152,6 +262,7 @@ impl InstallConfiguration {
     // Remove all configuration which is handled by `install to-filesystem`.
     pub(crate) fn filter_to_external(&mut self) {
         self.kargs.take();
+        self.console.take();
     }
 
     pub(crate) fn get_block_setup(&self, default: Option<BlockSetup>) -> Result<BlockSetup> {
@@ -168,10 +279,11 @@ impl InstallConfiguration {
 }
 
 #[context("Loading configuration")]
-/// Load the install configuration, merging all found configuration files.
-pub(crate) fn load_config() -> Result<Option<InstallConfiguration>> {
+/// Load the install configuration for the given architecture, merging all found
+/// configuration files.
+pub(crate) fn load_config(arch: &str) -> Result<Option<InstallConfiguration>> {
     let env = EnvProperties {
-        sys_arch: std::env::consts::ARCH.to_string(),
+        sys_arch: arch.to_string(),
     };
     const SYSTEMD_CONVENTIONAL_BASES: &[&str] = &["/usr/lib", "/usr/local/lib", "/etc", "/run"];
     let fragments = liboverdrop::scan(SYSTEMD_CONVENTIONAL_BASES, "bootc/install", &["toml"], true);
@@ -213,6 +325,29 @@ pub(crate) fn load_config() -> Result<Option<InstallConfiguration>> {
     Ok(config)
 }
 
+/// Apply configuration given at install time, such as the `[install]` table of a
+/// `bootc install from-config` blueprint, on top of the loaded configuration.
+pub(crate) fn merge_config(
+    config: Option<InstallConfiguration>,
+    mut other: InstallConfiguration,
+    arch: &str,
+) -> InstallConfiguration {
+    let env = EnvProperties {
+        sys_arch: arch.to_string(),
+    };
+    // The loaded configuration has both forms of the root filesystem type set,
+    // so set both here too for either to take precedence.
+    if let Some(fstype) = other.root_fs_type {
+        let fs = other.filesystem.get_or_insert_with(Default::default);
+        let root = fs.root.get_or_insert_with(Default::default);
+        root.fstype.get_or_insert(fstype);
+    }
+    let mut config = config.unwrap_or_default();
+    config.merge(other, &env);
+    config.canonicalize();
+    config
+}
+
 #[test]
 /// Verify that we can parse our default config file
 fn test_parse_config() {
@@ -304,6 +439,7 @@ type = "xfs"
             filesystem: Some(BasicFilesystems {
                 root: Some(RootFS {
                     fstype: Some(Filesystem::Ext4),
+                    ..Default::default()
                 }),
             }),
             ..Default::default()
@@ -359,6 +495,81 @@ block = ["tpm2-luks"]"##,
     assert!(install.get_block_setup(Some(BlockSetup::Direct)).is_err());
 }
 
+#[test]
+fn test_parse_partitions() {
+    let env = EnvProperties {
+        sys_arch: "x86_64".to_string(),
+    };
+    let c: InstallConfigurationToplevel = toml::from_str(
+        r##"[install.partitions]
+esp-size = "1G"
+root-size = "20G"
+[install.partitions.var]
+label = "var"
+"##,
+    )
+    .unwrap();
+    let mut install = c.install.unwrap();
+    let other: InstallConfigurationToplevel = toml::from_str(
+        r##"[install.partitions]
+root-label = "system"
+[install.partitions.var]
+type = "ext4"
+mount-options = ["noatime"]
+[install.partitions.swap]
+size = "4G"
+"##,
+    )
+    .unwrap();
+    install.merge(other.install.unwrap(), &env);
+    let partitions = install.partitions.unwrap();
+    assert_eq!(partitions.esp_size.as_deref(), Some("1G"));
+    assert_eq!(partitions.root_size.as_deref(), Some("20G"));
+    assert_eq!(partitions.root_label.as_deref(), Some("system"));
+    assert_eq!(
+        partitions.var.unwrap(),
+        ExtraPartition {
+            size: None,
+            label: Some("var".into()),
+            fstype: Some(super::baseline::Filesystem::Ext4),
+            mkfs_options: None,
+            mount_options: Some(vec!["noatime".into()]),
+        }
+    );
+    assert_eq!(partitions.swap.unwrap().size.as_deref(), Some("4G"));
+    assert!(partitions.lvm.is_none());
+
+    let c: InstallConfigurationToplevel = toml::from_str(
+        r##"[install.partitions.lvm]
+thin = true
+"##,
+    )
+    .unwrap();
+    let mut install = c.install.unwrap();
+    let other: InstallConfigurationToplevel = toml::from_str(
+        r##"[install.partitions.lvm]
+vg-name = "data"
+"##,
+    )
+    .unwrap();
+    install.merge(other.install.unwrap(), &env);
+    assert_eq!(
+        install.partitions.unwrap().lvm.unwrap(),
+        Lvm {
+            vg_name: Some("data".into()),
+            thin: Some(true),
+        }
+    );
+
+    // Unknown fields are rejected
+    assert!(toml::from_str::<InstallConfigurationToplevel>(
+        r##"[install.partitions]
+home-size = "1G"
+"##
+    )
+    .is_err());
+}
+
 #[test]
 /// Verify that kargs are only applied to supported architectures
 fn test_arch() {
@@ -550,3 +761,101 @@ root-fs-type = "xfs"
         )
     );
 }
+
+#[test]
+fn test_parse_filesystem_options() {
+    let env = EnvProperties {
+        sys_arch: "x86_64".to_string(),
+    };
+    let c: InstallConfigurationToplevel = toml::from_str(
+        r##"[install.filesystem.root]
+type = "btrfs"
+mkfs-options = ["--csum", "xxhash"]
+"##,
+    )
+    .unwrap();
+    let mut install = c.install.unwrap();
+    let other: InstallConfigurationToplevel = toml::from_str(
+        r##"[install.filesystem.root]
+mount-options = ["compress=zstd:1"]
+var-subvolume = "var"
+"##,
+    )
+    .unwrap();
+    install.merge(other.install.unwrap(), &env);
+    install.canonicalize();
+    let root = install.filesystem_root().unwrap();
+    assert_eq!(root.fstype, Some(super::baseline::Filesystem::Btrfs));
+    assert_eq!(root.mkfs_options.as_deref().unwrap(), ["--csum", "xxhash"]);
+    assert_eq!(root.mount_options.as_deref().unwrap(), ["compress=zstd:1"]);
+    assert_eq!(root.var_subvolume.as_deref(), Some("var"));
+}
+
+#[test]
+fn test_merge_config() {
+    use super::baseline::Filesystem;
+
+    let mut loaded: InstallConfiguration = toml::from_str(indoc::indoc! {r#"
+        root-fs-type = "xfs"
+        kargs = ["console=ttyS0"]
+        [partitions]
+        root-size = "20G"
+    "#})
+    .unwrap();
+    loaded.canonicalize();
+    let other: InstallConfiguration = toml::from_str(indoc::indoc! {r#"
+        root-fs-type = "ext4"
+        kargs = ["nosmt"]
+        [partitions.swap]
+        size = "4G"
+    "#})
+    .unwrap();
+    let c = merge_config(Some(loaded), other.clone(), "x86_64");
+    assert_eq!(c.root_fs_type, Some(Filesystem::Ext4));
+    assert_eq!(c.filesystem_root().unwrap().fstype, Some(Filesystem::Ext4));
+    assert_eq!(c.kargs.as_deref().unwrap(), ["console=ttyS0", "nosmt"]);
+    let partitions = c.partitions.unwrap();
+    assert_eq!(partitions.root_size.as_deref(), Some("20G"));
+    assert_eq!(partitions.swap.unwrap().size.as_deref(), Some("4G"));
+    // Without configuration files
+    let c = merge_config(None, other, "x86_64");
+    assert_eq!(c.block.as_deref(), Some([BlockSetup::Direct].as_slice()));
+}
+
+#[test]
+fn test_parse_console() {
+    let env = EnvProperties {
+        sys_arch: "x86_64".to_string(),
+    };
+    let c: InstallConfigurationToplevel = toml::from_str(
+        r##"[install.console]
+metal = ["console=ttyS1,115200n8"]
+aws = []
+"##,
+    )
+    .unwrap();
+    let mut install = c.install.unwrap();
+    let other: InstallConfigurationToplevel = toml::from_str(
+        r##"[install.console]
+metal = ["console=tty0", "console=ttyS0,115200n8"]
+"##,
+    )
+    .unwrap();
+    install.merge(other.install.unwrap(), &env);
+    let console = install.console.as_ref().unwrap();
+    assert_eq!(
+        console[&Platform::Metal],
+        ["console=tty0", "console=ttyS0,115200n8"]
+    );
+    assert!(console[&Platform::Aws].is_empty());
+    install.filter_to_external();
+    assert!(install.console.is_none());
+
+    // Unknown platforms are rejected
+    assert!(toml::from_str::<InstallConfigurationToplevel>(
+        r##"[install.console]
+vmware = []
+"##
+    )
+    .is_err());
+}
@@ -1,5 +1,7 @@
 use std::borrow::Cow;
+use std::fmt::Write as _;
 use std::io::Write;
+use std::str::FromStr;
 
 use anyhow::{Context, Result};
 use camino::{Utf8Path, Utf8PathBuf};
@@ -7,9 +9,122 @@ use cap_std::fs::Dir;
 use cap_std_ext::{cap_std, dirext::CapStdExtDirExt};
 use fn_error_context::context;
 use ostree_ext::ostree;
+use serde::{Deserialize, Serialize};
 
 const ETC_TMPFILES: &str = "etc/tmpfiles.d";
 const ROOT_SSH_TMPFILE: &str = "bootc-root-ssh.conf";
+const ETC_SYSUSERS: &str = "etc/sysusers.d";
+/// The name of the sysusers.d and tmpfiles.d files for users passed to `bootc install`.
+const USERS_CONF: &str = "bootc-install-users.conf";
+/// Prefix for the names of first boot commands passed to `bootc install`.
+const FIRSTBOOT_PREFIX: &str = "50-install-";
+/// The Ignition config in the physical root, where Ignition looks for a user config
+/// on bare metal.
+const IGNITION_CONFIG: &str = "boot/ignition/config.ign";
+/// The stamp file which makes the bootloader enable Ignition on the first boot.
+const IGNITION_FIRSTBOOT: &str = "boot/ignition.firstboot";
+/// The dracut module shipped by images using Ignition.
+const IGNITION_DRACUT_MODULE: &str = "usr/lib/dracut/modules.d/30ignition";
+/// The binary shipped by images using cloud-init.
+const CLOUD_INIT_BIN: &str = "usr/bin/cloud-init";
+/// The seed directory for the cloud-init NoCloud datasource, relative to the stateroot.
+const NOCLOUD_SEED: &str = "var/lib/cloud/seed/nocloud";
+const ETC_CLOUD_CFG: &str = "etc/cloud/cloud.cfg.d";
+const CLOUD_DATASOURCE_CFG: &str = "90-bootc-install-datasource.cfg";
+const NM_CONNECTIONS: &str = "etc/NetworkManager/system-connections";
+const NM_BIN: &str = "usr/sbin/NetworkManager";
+/// The FIPS policy of the system-wide crypto policies.
+const FIPS_POLICY: &str = "usr/share/crypto-policies/policies/FIPS.pol";
+/// The dracut module verifying the kernel and enabling FIPS mode early in boot.
+const FIPS_DRACUT_MODULE: &str = "usr/lib/dracut/modules.d/01fips";
+/// The configured system-wide crypto policy.
+const CRYPTO_POLICY_CONFIG: &str = "etc/crypto-policies/config";
+
+/// A cloud-init datasource which can be provisioned by `bootc install`.
+#[derive(clap::ValueEnum, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum CloudInitDatasource {
+    /// Read the user data from the local disk
+    #[value(name = "NoCloud")]
+    NoCloud,
+}
+
+/// A user to create on first boot, as passed via `--add-user name:uid:groups:sshkey`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InstallUser {
+    pub(crate) name: String,
+    pub(crate) uid: u32,
+    /// Supplementary groups, which are created if they do not exist
+    pub(crate) groups: Vec<String>,
+    /// A line for `~/.ssh/authorized_keys`
+    pub(crate) ssh_key: Option<String>,
+}
+
+/// Verify that a user or group name is portable, see useradd(8).
+fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 32
+        && name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-'));
+    if !valid {
+        anyhow::bail!("Invalid user or group name: {name:?}");
+    }
+    Ok(())
+}
+
+impl FromStr for InstallUser {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(4, ':');
+        // SAFETY: splitn always yields at least one item
+        let name = parts.next().unwrap();
+        validate_name(name)?;
+        let uid = parts
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Missing uid for user {name}"))?;
+        let uid = uid
+            .parse::<u32>()
+            .with_context(|| format!("Invalid uid for user {name}: {uid:?}"))?;
+        if uid == 0 {
+            anyhow::bail!("Invalid uid for user {name}: 0 is reserved for root");
+        }
+        let groups = parts
+            .next()
+            .unwrap_or_default()
+            .split(',')
+            .filter(|g| !g.is_empty())
+            .map(|g| validate_name(g).map(|_| g.to_owned()))
+            .collect::<Result<Vec<_>>>()?;
+        let ssh_key = parts
+            .next()
+            .map(str::trim)
+            .filter(|k| !k.is_empty())
+            .map(ToOwned::to_owned);
+        Ok(Self {
+            name: name.to_owned(),
+            uid,
+            groups,
+            ssh_key,
+        })
+    }
+}
+
+/// Eagerly resolve the path of a toplevel directory (e.g. /root) in order to avoid
+/// tmpfiles.d clashes/problems. If it's local state (i.e. /root -> /var/roothome)
+/// then we resolve that symlink now.
+fn resolve_toplevel_dir<'a>(root: &Dir, name: &'a str) -> Result<Cow<'a, Utf8Path>> {
+    let meta = root.symlink_metadata_optional(name)?;
+    if meta.as_ref().filter(|m| m.is_symlink()).is_some() {
+        let path = root.read_link(name)?;
+        Utf8PathBuf::try_from(path)
+            .with_context(|| format!("Reading /{name} symlink"))
+            .map(Cow::Owned)
+    } else {
+        Ok(Cow::Borrowed(Utf8Path::new(name)))
+    }
+}
 
 #[context("Injecting root authorized_keys")]
 pub(crate) fn inject_root_ssh_authorized_keys(
@@ -20,17 +135,7 @@ pub(crate) fn inject_root_ssh_authorized_keys(
     // While not documented right now, this one looks like it does not newline wrap
     let b64_encoded = ostree_ext::glib::base64_encode(contents.as_bytes());
 
-    // Eagerly resolve the path of /root in order to avoid tmpfiles.d clashes/problems.
-    // If it's local state (i.e. /root -> /var/roothome) then we resolve that symlink now.
-    let roothome_meta = root.symlink_metadata_optional("root")?;
-    let root_path = if roothome_meta.as_ref().filter(|m| m.is_symlink()).is_some() {
-        let path = root.read_link("root")?;
-        Utf8PathBuf::try_from(path)
-            .context("Reading /root symlink")
-            .map(Cow::Owned)?
-    } else {
-        Cow::Borrowed(Utf8Path::new("root"))
-    };
+    let root_path = resolve_toplevel_dir(root, "root")?;
 
     // See the example in https://systemd.io/CREDENTIALS/
     let tmpfiles_content =
@@ -50,6 +155,268 @@ pub(crate) fn inject_root_ssh_authorized_keys(
     Ok(())
 }
 
+/// Write a sysusers.d file creating the users on first boot, and a tmpfiles.d file
+/// creating their home directories and `authorized_keys`.
+#[context("Injecting users")]
+pub(crate) fn inject_users(
+    root: &Dir,
+    sepolicy: Option<&ostree::SePolicy>,
+    users: &[InstallUser],
+) -> Result<()> {
+    let home = resolve_toplevel_dir(root, "home")?;
+    let mut sysusers = String::new();
+    let mut tmpfiles = String::new();
+    for InstallUser {
+        name,
+        uid,
+        groups,
+        ssh_key,
+    } in users
+    {
+        writeln!(
+            sysusers,
+            r#"u {name} {uid} "{name}" /{home}/{name} /bin/bash"#
+        )?;
+        for group in groups {
+            writeln!(sysusers, "m {name} {group}")?;
+        }
+        let userhome = format!("/{home}/{name}");
+        writeln!(tmpfiles, "d {userhome} 0700 {name} {name} -")?;
+        if let Some(key) = ssh_key.as_deref() {
+            let b64_encoded = ostree_ext::glib::base64_encode(format!("{key}\n").as_bytes());
+            writeln!(tmpfiles, "d {userhome}/.ssh 0700 {name} {name} -")?;
+            writeln!(
+                tmpfiles,
+                "f~ {userhome}/.ssh/authorized_keys 600 {name} {name} - {b64_encoded}"
+            )?;
+        }
+    }
+
+    for (dir, contents) in [(ETC_SYSUSERS, sysusers), (ETC_TMPFILES, tmpfiles)] {
+        crate::lsm::ensure_dir_labeled(root, dir, None, 0o755.into(), sepolicy)?;
+        let d = root.open_dir(dir)?;
+        crate::lsm::atomic_replace_labeled(&d, USERS_CONF, 0o644.into(), sepolicy, |w| {
+            w.write_all(contents.as_bytes()).map_err(Into::into)
+        })?;
+        println!("Injected: {dir}/{USERS_CONF}");
+    }
+    Ok(())
+}
+
+/// Write commands to be run once on the first boot; see [`crate::firstboot`].
+#[context("Injecting first boot commands")]
+pub(crate) fn inject_firstboot_commands(
+    root: &Dir,
+    sepolicy: Option<&ostree::SePolicy>,
+    commands: &[String],
+) -> Result<()> {
+    let dir = Utf8Path::new(crate::firstboot::FIRSTBOOT_DIR);
+    // SAFETY: The constant has a parent
+    crate::lsm::ensure_dir_labeled(root, dir.parent().unwrap(), None, 0o755.into(), sepolicy)?;
+    crate::lsm::ensure_dir_labeled(root, dir, None, 0o755.into(), sepolicy)?;
+    let d = root.open_dir(dir)?;
+    for (i, command) in commands.iter().enumerate() {
+        let name = format!("{FIRSTBOOT_PREFIX}{i:03}");
+        crate::lsm::atomic_replace_labeled(&d, &name, 0o755.into(), sepolicy, |w| {
+            writeln!(w, "#!/bin/sh\n{command}").map_err(Into::into)
+        })?;
+        println!("Injected: {dir}/{name}");
+    }
+    Ok(())
+}
+
+/// Verify that the contents of `--ignition-file` look like an Ignition config.
+pub(crate) fn validate_ignition_config(contents: &str) -> Result<()> {
+    let config: serde_json::Value =
+        serde_json::from_str(contents).context("Parsing Ignition config")?;
+    config
+        .get("ignition")
+        .and_then(|v| v.get("version"))
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow::anyhow!("Ignition config is missing ignition.version"))?;
+    Ok(())
+}
+
+/// Verify that the image has the provisioning stack needed for the given options.
+pub(crate) fn verify_provisioning_support(
+    root: &Dir,
+    ignition: bool,
+    cloud_init: bool,
+) -> Result<()> {
+    if ignition && !root.try_exists(IGNITION_DRACUT_MODULE)? {
+        anyhow::bail!("--ignition-file was given, but the image does not include Ignition (missing /{IGNITION_DRACUT_MODULE})");
+    }
+    if cloud_init && !root.try_exists(CLOUD_INIT_BIN)? {
+        anyhow::bail!("--cloud-init-datasource was given, but the image does not include cloud-init (missing /{CLOUD_INIT_BIN})");
+    }
+    Ok(())
+}
+
+/// Verify that the image supports FIPS mode, i.e. that it includes the dracut fips
+/// module and uses the FIPS crypto policy.
+#[context("Verifying FIPS support")]
+pub(crate) fn verify_fips_support(root: &Dir) -> Result<()> {
+    for (path, what) in [
+        (FIPS_POLICY, "the FIPS crypto policy"),
+        (FIPS_DRACUT_MODULE, "the dracut fips module"),
+    ] {
+        if !root.try_exists(path)? {
+            anyhow::bail!(
+                "--fips was given, but the image does not include {what} (missing /{path})"
+            );
+        }
+    }
+    let policy = if root.try_exists(CRYPTO_POLICY_CONFIG)? {
+        root.read_to_string(CRYPTO_POLICY_CONFIG)?
+    } else {
+        "DEFAULT".into()
+    };
+    let policy = policy.trim();
+    // Subpolicies may be appended, e.g. FIPS:OSPP
+    if policy.split(':').next() != Some("FIPS") {
+        anyhow::bail!(
+            "--fips was given, but the crypto policy of the image is {policy}; run `update-crypto-policies --no-reload --set FIPS` in the container build"
+        );
+    }
+    Ok(())
+}
+
+/// The `boot=` kernel argument needed for FIPS mode, if not already present.  The
+/// initramfs verifies the kernel against its HMAC on that filesystem, which is the
+/// root filesystem if there is no separate /boot.
+pub(crate) fn fips_boot_karg(kargs: &[String]) -> Result<Option<String>> {
+    if kargs.iter().any(|k| k.starts_with("boot=")) {
+        return Ok(None);
+    }
+    let root = kargs
+        .iter()
+        .find_map(|k| k.strip_prefix("root=UUID="))
+        .ok_or_else(|| {
+            anyhow::anyhow!("FIPS mode requires the root filesystem to be given via root=UUID= if there is no separate /boot")
+        })?;
+    Ok(Some(format!("boot=UUID={root}")))
+}
+
+/// Write an Ignition config to /boot, along with the stamp file enabling Ignition
+/// on the first boot.  The root is the physical root of the target.
+#[context("Staging Ignition config")]
+pub(crate) fn stage_ignition_config(
+    physical_root: &Dir,
+    sepolicy: Option<&ostree::SePolicy>,
+    contents: &str,
+) -> Result<()> {
+    let config = Utf8Path::new(IGNITION_CONFIG);
+    // SAFETY: The constant has a parent
+    crate::lsm::ensure_dir_labeled(
+        physical_root,
+        config.parent().unwrap(),
+        None,
+        0o700.into(),
+        sepolicy,
+    )?;
+    // The config may contain secrets
+    crate::lsm::atomic_replace_labeled(physical_root, config, 0o600.into(), sepolicy, |w| {
+        w.write_all(contents.as_bytes()).map_err(Into::into)
+    })?;
+    println!("Injected: /{IGNITION_CONFIG}");
+    crate::lsm::atomic_replace_labeled(
+        physical_root,
+        IGNITION_FIRSTBOOT,
+        0o644.into(),
+        sepolicy,
+        |_| Ok(()),
+    )?;
+    println!("Injected: /{IGNITION_FIRSTBOOT}");
+    Ok(())
+}
+
+/// Write the seed for the cloud-init NoCloud datasource to the stateroot's /var, and
+/// restrict cloud-init to that datasource in the deployment's /etc.
+#[context("Staging cloud-init NoCloud seed")]
+pub(crate) fn stage_nocloud_seed(
+    stateroot: &Dir,
+    root: &Dir,
+    sepolicy: Option<&ostree::SePolicy>,
+    user_data: &str,
+) -> Result<()> {
+    let mut dir = Utf8PathBuf::new();
+    for component in Utf8Path::new(NOCLOUD_SEED).components() {
+        dir.push(component);
+        crate::lsm::ensure_dir_labeled(stateroot, &dir, None, 0o755.into(), sepolicy)?;
+    }
+    // The instance ID must be unique per installation, as cloud-init only runs
+    // once per instance.
+    let meta_data = format!("instance-id: bootc-{}\n", uuid::Uuid::new_v4());
+    for (name, contents, mode) in [
+        ("user-data", user_data, 0o600),
+        ("meta-data", meta_data.as_str(), 0o644),
+    ] {
+        crate::lsm::atomic_replace_labeled(
+            stateroot,
+            dir.join(name),
+            mode.into(),
+            sepolicy,
+            |w| w.write_all(contents.as_bytes()).map_err(Into::into),
+        )?;
+        println!("Injected: /{dir}/{name}");
+    }
+
+    let mut dir = Utf8PathBuf::new();
+    for component in Utf8Path::new(ETC_CLOUD_CFG).components() {
+        dir.push(component);
+        crate::lsm::ensure_dir_labeled(root, &dir, None, 0o755.into(), sepolicy)?;
+    }
+    crate::lsm::atomic_replace_labeled(
+        root,
+        dir.join(CLOUD_DATASOURCE_CFG),
+        0o644.into(),
+        sepolicy,
+        |w| writeln!(w, "datasource_list: [ NoCloud, None ]").map_err(Into::into),
+    )?;
+    println!("Injected: {ETC_CLOUD_CFG}/{CLOUD_DATASOURCE_CFG}");
+    Ok(())
+}
+
+/// Read a NetworkManager keyfile given via `--network-config`, returning its file name
+/// and contents.
+#[context("Reading network config {path}")]
+pub(crate) fn read_network_config(path: &Utf8Path) -> Result<(String, String)> {
+    let name = path
+        .file_name()
+        .filter(|n| n.ends_with(".nmconnection"))
+        .ok_or_else(|| anyhow::anyhow!("Expected a file name ending in .nmconnection"))?;
+    let contents = std::fs::read_to_string(path)?;
+    if !contents.lines().any(|l| l.trim() == "[connection]") {
+        anyhow::bail!("Missing [connection] section");
+    }
+    Ok((name.to_owned(), contents))
+}
+
+/// Write NetworkManager keyfiles to the target.
+#[context("Injecting network configuration")]
+pub(crate) fn inject_network_configs(
+    root: &Dir,
+    sepolicy: Option<&ostree::SePolicy>,
+    configs: &[(String, String)],
+) -> Result<()> {
+    if !root.try_exists(NM_BIN)? {
+        anyhow::bail!("The image does not include NetworkManager (missing /{NM_BIN})");
+    }
+    let mut dir = Utf8PathBuf::new();
+    for component in Utf8Path::new(NM_CONNECTIONS).components() {
+        dir.push(component);
+        crate::lsm::ensure_dir_labeled(root, &dir, None, 0o755.into(), sepolicy)?;
+    }
+    for (name, contents) in configs {
+        // NetworkManager ignores keyfiles readable by other users
+        crate::lsm::atomic_replace_labeled(root, dir.join(name), 0o600.into(), sepolicy, |w| {
+            w.write_all(contents.as_bytes()).map_err(Into::into)
+        })?;
+        println!("Injected: {dir}/{name}");
+    }
+    Ok(())
+}
+
 #[test]
 fn test_inject_root_ssh_symlinked() -> Result<()> {
     let root = &cap_std_ext::cap_tempfile::TempDir::new(cap_std::ambient_authority())?;
@@ -84,3 +451,199 @@ fn test_inject_root_ssh_dir() -> Result<()> {
     );
     Ok(())
 }
+
+#[test]
+fn test_inject_firstboot_commands() -> Result<()> {
+    let root = &cap_std_ext::cap_tempfile::TempDir::new(cap_std::ambient_authority())?;
+    root.create_dir("etc")?;
+    inject_firstboot_commands(
+        root,
+        None,
+        &["hostnamectl set-hostname appliance".into(), "true".into()],
+    )?;
+    let d = root.open_dir(crate::firstboot::FIRSTBOOT_DIR)?;
+    assert_eq!(
+        crate::utils::filenames_sorted(&d)?,
+        ["50-install-000", "50-install-001"]
+    );
+    assert_eq!(
+        d.read_to_string("50-install-000")?,
+        "#!/bin/sh\nhostnamectl set-hostname appliance\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_parse_install_user() -> Result<()> {
+    let user = InstallUser::from_str("core:1000:wheel,adm:ssh-ed25519 ABCDE core@example")?;
+    assert_eq!(
+        user,
+        InstallUser {
+            name: "core".into(),
+            uid: 1000,
+            groups: vec!["wheel".into(), "adm".into()],
+            ssh_key: Some("ssh-ed25519 ABCDE core@example".into()),
+        }
+    );
+    let user = InstallUser::from_str("admin:1001:")?;
+    assert!(user.groups.is_empty());
+    assert!(user.ssh_key.is_none());
+    for invalid in [
+        "",
+        "core",
+        "core:abc",
+        "core:0",
+        "Core:1000",
+        "1core:1000",
+        "core:1000:wheel,Adm",
+    ] {
+        assert!(InstallUser::from_str(invalid).is_err(), "{invalid}");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_inject_users() -> Result<()> {
+    let root = &cap_std_ext::cap_tempfile::TempDir::new(cap_std::ambient_authority())?;
+    root.create_dir("etc")?;
+    root.symlink("var/home", "home")?;
+    let users = [
+        InstallUser::from_str("core:1000:wheel:ssh-ed25519 ABCDE example@demo")?,
+        InstallUser::from_str("admin:1001:")?,
+    ];
+    inject_users(root, None, &users)?;
+
+    similar_asserts::assert_eq!(
+        root.read_to_string(format!("{ETC_SYSUSERS}/{USERS_CONF}"))?,
+        indoc::indoc! { r#"
+            u core 1000 "core" /var/home/core /bin/bash
+            m core wheel
+            u admin 1001 "admin" /var/home/admin /bin/bash
+        "# }
+    );
+    similar_asserts::assert_eq!(
+        root.read_to_string(format!("{ETC_TMPFILES}/{USERS_CONF}"))?,
+        indoc::indoc! { "
+            d /var/home/core 0700 core core -
+            d /var/home/core/.ssh 0700 core core -
+            f~ /var/home/core/.ssh/authorized_keys 600 core core - c3NoLWVkMjU1MTkgQUJDREUgZXhhbXBsZUBkZW1vCg==
+            d /var/home/admin 0700 admin admin -
+        " }
+    );
+    Ok(())
+}
+
+#[test]
+fn test_validate_ignition_config() {
+    assert!(validate_ignition_config(r#"{"ignition": {"version": "3.4.0"}}"#).is_ok());
+    for invalid in ["", "{}", r#"{"ignition": {}}"#, "ignition: 3.4.0"] {
+        assert!(validate_ignition_config(invalid).is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn test_stage_ignition_config() -> Result<()> {
+    let root = &cap_std_ext::cap_tempfile::TempDir::new(cap_std::ambient_authority())?;
+    root.create_dir("boot")?;
+    let config = r#"{"ignition": {"version": "3.4.0"}}"#;
+    stage_ignition_config(root, None, config)?;
+    assert_eq!(root.read_to_string(IGNITION_CONFIG)?, config);
+    assert_eq!(root.read_to_string(IGNITION_FIRSTBOOT)?, "");
+    Ok(())
+}
+
+#[test]
+fn test_stage_nocloud_seed() -> Result<()> {
+    let stateroot = &cap_std_ext::cap_tempfile::TempDir::new(cap_std::ambient_authority())?;
+    let root = &cap_std_ext::cap_tempfile::TempDir::new(cap_std::ambient_authority())?;
+    stateroot.create_dir("var")?;
+    root.create_dir("etc")?;
+    stage_nocloud_seed(stateroot, root, None, "#cloud-config\n")?;
+
+    assert_eq!(
+        stateroot.read_to_string(format!("{NOCLOUD_SEED}/user-data"))?,
+        "#cloud-config\n"
+    );
+    let meta_data = stateroot.read_to_string(format!("{NOCLOUD_SEED}/meta-data"))?;
+    assert!(meta_data.starts_with("instance-id: bootc-"), "{meta_data}");
+    assert_eq!(
+        root.read_to_string(format!("{ETC_CLOUD_CFG}/{CLOUD_DATASOURCE_CFG}"))?,
+        "datasource_list: [ NoCloud, None ]\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_inject_network_configs() -> Result<()> {
+    let tmp = tempfile::tempdir()?;
+    let tdpath = Utf8Path::from_path(tmp.path()).unwrap();
+    let td = &Dir::open_ambient_dir(tdpath, cap_std::ambient_authority())?;
+    let keyfile = indoc::indoc! { "
+        [connection]
+        id=eth0
+        type=ethernet
+
+        [ipv4]
+        method=manual
+        address1=192.168.1.10/24,192.168.1.1
+    " };
+    td.write("eth0.nmconnection", keyfile)?;
+    td.write("eth0.conf", keyfile)?;
+    td.write("empty.nmconnection", "")?;
+    let config = read_network_config(&tdpath.join("eth0.nmconnection"))?;
+    assert_eq!(
+        config,
+        (
+            "eth0.nmconnection".to_owned(),
+            td.read_to_string("eth0.nmconnection")?
+        )
+    );
+    assert!(read_network_config(&tdpath.join("eth0.conf")).is_err());
+    assert!(read_network_config(&tdpath.join("empty.nmconnection")).is_err());
+
+    let root = &cap_std_ext::cap_tempfile::TempDir::new(cap_std::ambient_authority())?;
+    root.create_dir("etc")?;
+    assert!(inject_network_configs(root, None, std::slice::from_ref(&config)).is_err());
+    root.create_dir_all("usr/sbin")?;
+    root.write(NM_BIN, "")?;
+    inject_network_configs(root, None, &[config])?;
+    assert_eq!(
+        root.read_to_string(format!("{NM_CONNECTIONS}/eth0.nmconnection"))?,
+        keyfile
+    );
+    Ok(())
+}
+
+#[test]
+fn test_verify_fips_support() -> Result<()> {
+    let root = &cap_std_ext::cap_tempfile::TempDir::new(cap_std::ambient_authority())?;
+    assert!(verify_fips_support(root).is_err());
+    root.create_dir_all(FIPS_DRACUT_MODULE)?;
+    root.create_dir_all(Utf8Path::new(FIPS_POLICY).parent().unwrap())?;
+    root.write(FIPS_POLICY, "")?;
+    // The default policy
+    assert!(verify_fips_support(root).is_err());
+    root.create_dir_all("etc/crypto-policies")?;
+    root.write(CRYPTO_POLICY_CONFIG, "DEFAULT:SHA1\n")?;
+    assert!(verify_fips_support(root).is_err());
+    root.write(CRYPTO_POLICY_CONFIG, "FIPS:OSPP\n")?;
+    verify_fips_support(root)?;
+    root.remove_dir(FIPS_DRACUT_MODULE)?;
+    assert!(verify_fips_support(root).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_fips_boot_karg() -> Result<()> {
+    let kargs = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        fips_boot_karg(&kargs(&["root=UUID=abc", "rw"]))?.as_deref(),
+        Some("boot=UUID=abc")
+    );
+    assert_eq!(
+        fips_boot_karg(&kargs(&["root=UUID=abc", "boot=UUID=def"]))?,
+        None
+    );
+    assert!(fips_boot_karg(&kargs(&["root=/dev/mapper/root"])).is_err());
+    Ok(())
+}
@@ -0,0 +1,571 @@
+//! # Split-phase installation
+//!
+//! `bootc install prepare`, `deploy` and `finalize` run the phases of an installation
+//! as separate invocations, so that an OS installer can perform some of them itself or
+//! run its own steps in between.  The state is passed from one phase to the next in a
+//! JSON handoff file.
+//!
+//! Each invocation uses its own mount namespace, so the filesystems created by
+//! `install prepare to-disk` are unmounted at the end of every phase, and mounted again
+//! by the next one.  Block devices such as LUKS or LVM stay active until `install finalize`.
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use cap_std::fs::Dir;
+use cap_std_ext::cap_std;
+use fn_error_context::context;
+use rustix::fs::FileTypeExt;
+use serde::{Deserialize, Serialize};
+
+use super::baseline::{self, InstallBlockDeviceOpts};
+use super::{
+    send_phase, BlockStack, InstallConfigOpts, InstallResult, InstallSourceOpts, InstallTargetOpts,
+    InstallToFilesystemOpts, MountSpec, RootSetup, State,
+};
+use crate::task::Task;
+
+/// Options for `bootc install prepare`.
+#[derive(Debug, Clone, clap::Parser, PartialEq, Eq)]
+pub(crate) struct InstallPrepareOpts {
+    /// Write the handoff file for `install deploy` to this path.
+    #[clap(long)]
+    pub(crate) handoff: Utf8PathBuf,
+
+    #[clap(subcommand)]
+    pub(crate) target: PrepareTarget,
+}
+
+/// The target of `bootc install prepare`.
+#[derive(Debug, Clone, clap::Subcommand, PartialEq, Eq)]
+pub(crate) enum PrepareTarget {
+    /// Partition the target block device and create the filesystems, as `install to-disk` does.
+    ToDisk(PrepareToDiskOpts),
+    /// Use an externally created and mounted root filesystem, as `install to-filesystem` does.
+    ///
+    /// The filesystems must stay mounted at the same path until `install finalize` is done.
+    ToFilesystem(InstallToFilesystemOpts),
+}
+
+/// Options for `bootc install prepare to-disk`.
+#[derive(Debug, Clone, clap::Parser, PartialEq, Eq)]
+pub(crate) struct PrepareToDiskOpts {
+    #[clap(flatten)]
+    pub(crate) block_opts: InstallBlockDeviceOpts,
+
+    #[clap(flatten)]
+    pub(crate) source_opts: InstallSourceOpts,
+
+    #[clap(flatten)]
+    pub(crate) target_opts: InstallTargetOpts,
+
+    #[clap(flatten)]
+    pub(crate) config_opts: InstallConfigOpts,
+}
+
+/// Options for `bootc install deploy`.
+#[derive(Debug, Clone, clap::Parser, PartialEq, Eq)]
+pub(crate) struct InstallDeployOpts {
+    /// The handoff file written by `install prepare`; it is updated for `install finalize`.
+    #[clap(long)]
+    pub(crate) handoff: Utf8PathBuf,
+
+    /// Write progress events as newline-delimited JSON to this (inherited) file descriptor.
+    #[clap(long, visible_alias = "json-fd")]
+    pub(crate) progress_fd: Option<i32>,
+}
+
+/// Options for `bootc install finalize`.
+#[derive(Debug, Clone, clap::Parser, PartialEq, Eq)]
+pub(crate) struct InstallFinalizeOpts {
+    /// The handoff file updated by `install deploy`.
+    #[clap(long)]
+    pub(crate) handoff: Utf8PathBuf,
+
+    /// Write progress events as newline-delimited JSON to this (inherited) file descriptor.
+    #[clap(long, visible_alias = "json-fd")]
+    pub(crate) progress_fd: Option<i32>,
+
+    /// On success, write a JSON description of the installed deployment to this file,
+    /// as `--result-json` of `install to-disk` does.
+    #[clap(long)]
+    pub(crate) result_json: Option<Utf8PathBuf>,
+}
+
+/// The last completed phase of an installation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Phase {
+    /// The target filesystems were set up by `install prepare`
+    Prepared,
+    /// The image was deployed by `install deploy`
+    Deployed,
+    /// The bootloader was installed by `install finalize`
+    Finalized,
+}
+
+impl Phase {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Phase::Prepared => "prepared",
+            Phase::Deployed => "deployed",
+            Phase::Finalized => "finalized",
+        }
+    }
+}
+
+/// A filesystem mounted in the target root by `install prepare to-disk`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct TargetMount {
+    /// The block device
+    source: String,
+    /// The mount point in the target system, e.g. `/boot`
+    path: Utf8PathBuf,
+    /// The mount options, e.g. for a btrfs subvolume
+    options: Option<String>,
+}
+
+impl TargetMount {
+    /// The path of the mount point below the given target root.
+    fn host_path(&self, root: &Utf8Path) -> Utf8PathBuf {
+        match self.path.strip_prefix("/") {
+            Ok(p) if !p.as_str().is_empty() => root.join(p),
+            _ => root.to_owned(),
+        }
+    }
+}
+
+/// The state passed between the phases of an installation.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Handoff {
+    /// The last completed phase
+    phase: Phase,
+    source_opts: InstallSourceOpts,
+    target_opts: InstallTargetOpts,
+    config_opts: InstallConfigOpts,
+    /// The path of the target root filesystem
+    root: Utf8PathBuf,
+    /// The filesystems to mount for each phase; empty if they were set up externally
+    target_mounts: Vec<TargetMount>,
+    /// The block device holding the root filesystem
+    device: Utf8PathBuf,
+    /// Further devices which get a bootloader installed, e.g. RAID mirrors
+    mirror_devices: Vec<Utf8PathBuf>,
+    root_uuid: Option<String>,
+    boot: Option<MountSpec>,
+    /// Additional entries for `/etc/fstab`
+    mounts: Vec<MountSpec>,
+    /// The entry for `/etc/crypttab`, if the root filesystem is encrypted
+    crypttab: Option<String>,
+    kargs: Vec<String>,
+    skip_finalize: bool,
+    skip_bootloader: bool,
+    /// Block devices to deactivate at the end of the installation
+    block_stack: BlockStack,
+    /// Whether to move the contents of `/home` to the stateroot after deploying
+    move_home: bool,
+    /// The result of the installation, once deployed
+    result: Option<InstallResult>,
+}
+
+impl Handoff {
+    fn new(
+        source_opts: InstallSourceOpts,
+        target_opts: InstallTargetOpts,
+        config_opts: InstallConfigOpts,
+        rootfs: &RootSetup,
+        target_mounts: Vec<TargetMount>,
+        move_home: bool,
+    ) -> Self {
+        Self {
+            phase: Phase::Prepared,
+            source_opts,
+            target_opts,
+            config_opts,
+            root: rootfs.rootfs.clone(),
+            target_mounts,
+            device: rootfs.device_info.path().to_owned(),
+            mirror_devices: rootfs
+                .mirror_device_info
+                .iter()
+                .map(|d| d.path().to_owned())
+                .collect(),
+            root_uuid: rootfs.rootfs_uuid.clone(),
+            boot: rootfs.boot.clone(),
+            mounts: rootfs.mounts.clone(),
+            crypttab: rootfs.crypttab.clone(),
+            kargs: rootfs.kargs.clone(),
+            skip_finalize: rootfs.skip_finalize,
+            skip_bootloader: rootfs.skip_bootloader,
+            block_stack: rootfs.block_stack.clone(),
+            move_home,
+            result: None,
+        }
+    }
+
+    #[context("Reading handoff {path}")]
+    fn load(path: &Utf8Path) -> Result<Self> {
+        let buf = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&buf)?)
+    }
+
+    #[context("Writing handoff {path}")]
+    fn write(&self, path: &Utf8Path) -> Result<()> {
+        let buf = serde_json::to_vec_pretty(self)?;
+        std::fs::write(path, buf)?;
+        Ok(())
+    }
+
+    fn require_phase(&self, phase: Phase) -> Result<()> {
+        if self.phase != phase {
+            anyhow::bail!(
+                "Expected an installation which is {}, but it is {}",
+                phase.as_str(),
+                self.phase.as_str()
+            );
+        }
+        Ok(())
+    }
+
+    /// Gather the global state from the recorded options.
+    async fn prepare_state(
+        &self,
+        progress_fd: Option<i32>,
+        result_json: Option<Utf8PathBuf>,
+    ) -> Result<Arc<State>> {
+        let mut config_opts = self.config_opts.clone();
+        config_opts.progress_fd = progress_fd;
+        config_opts.result_json = result_json;
+        let mut target_opts = self.target_opts.clone();
+        // This was already verified by `install prepare`
+        target_opts.skip_fetch_check = true;
+        super::prepare_install(config_opts, self.source_opts.clone(), target_opts).await
+    }
+
+    /// Mount the target filesystems, and reopen the root setup.
+    #[context("Mounting target filesystems")]
+    fn mount(&self) -> Result<RootSetup> {
+        for m in self.target_mounts.iter() {
+            let path = m.host_path(&self.root);
+            std::fs::create_dir_all(&path).with_context(|| format!("Creating {path}"))?;
+            match m.options.as_deref() {
+                Some(options) => crate::mount::mount_with_options(&m.source, &path, options)?,
+                None => crate::mount::mount(&m.source, &path)?,
+            }
+        }
+        let rootfs_fd = Dir::open_ambient_dir(&self.root, cap_std::ambient_authority())
+            .with_context(|| format!("Opening {}", self.root))?;
+        let mirror_device_info = self
+            .mirror_devices
+            .iter()
+            .map(|d| crate::blockdev::partitions_of(d))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(RootSetup {
+            block_stack: self.block_stack.clone(),
+            device_info: crate::blockdev::partitions_of(&self.device)?,
+            mirror_device_info,
+            rootfs: self.root.clone(),
+            rootfs_fd,
+            rootfs_uuid: self.root_uuid.clone(),
+            skip_finalize: self.skip_finalize,
+            skip_bootloader: self.skip_bootloader,
+            boot: self.boot.clone(),
+            mounts: self.mounts.clone(),
+            crypttab: self.crypttab.clone(),
+            kargs: self.kargs.clone(),
+        })
+    }
+
+    /// Unmount the filesystems mounted by [`Self::mount`], and optionally deactivate
+    /// the underlying block devices.
+    fn teardown(&self, deactivate: bool) -> Result<()> {
+        if !self.target_mounts.is_empty() {
+            unmount(&self.root)?;
+        }
+        if deactivate {
+            self.block_stack.deactivate()?;
+        }
+        Ok(())
+    }
+}
+
+fn unmount(root: &Utf8Path) -> Result<()> {
+    Task::new_and_run("Unmounting filesystems", "umount", ["-R", root.as_str()])
+}
+
+/// Find the filesystems mounted in the target root by `install_create_rootfs`.
+fn target_mounts(rootfs: &RootSetup) -> Result<Vec<TargetMount>> {
+    let esp = Utf8Path::new(super::BOOT).join(crate::bootloader::EFI_DIR);
+    let mut r = Vec::new();
+    for path in [Utf8Path::new(""), Utf8Path::new(super::BOOT), esp.as_path()] {
+        let host_path = if path.as_str().is_empty() {
+            rootfs.rootfs.clone()
+        } else if ostree_ext::mountutil::is_mountpoint(&rootfs.rootfs_fd, path)? == Some(true) {
+            rootfs.rootfs.join(path)
+        } else {
+            continue;
+        };
+        let fs = crate::mount::inspect_filesystem(&host_path)?;
+        let options = crate::utils::find_mount_option(&fs.options, "subvol")
+            .map(|subvol| format!("subvol={subvol}"));
+        r.push(TargetMount {
+            source: fs.source,
+            path: Utf8Path::new("/").join(path),
+            options,
+        });
+    }
+    Ok(r)
+}
+
+fn consume_state(state: Arc<State>) -> Result<()> {
+    if let Some(state) = Arc::into_inner(state) {
+        state.consume()?;
+    } else {
+        tracing::warn!("Failed to consume state Arc");
+    }
+    Ok(())
+}
+
+/// Implementation of `bootc install prepare`.
+#[context("Preparing installation")]
+pub(crate) async fn install_prepare(opts: InstallPrepareOpts) -> Result<()> {
+    match opts.target {
+        PrepareTarget::ToDisk(target) => prepare_disk(target, &opts.handoff).await,
+        PrepareTarget::ToFilesystem(target) => prepare_filesystem(target, &opts.handoff).await,
+    }
+}
+
+async fn prepare_disk(opts: PrepareToDiskOpts, handoff_path: &Utf8Path) -> Result<()> {
+    let mut block_opts = opts.block_opts;
+    block_opts.resolve_device()?;
+    for dev in std::iter::once(block_opts.device()?)
+        .chain(block_opts.mirror_devices.iter().map(|d| d.as_path()))
+    {
+        let meta = dev.metadata().with_context(|| format!("Querying {dev}"))?;
+        if !meta.file_type().is_block_device() {
+            anyhow::bail!("Not a block device: {dev}");
+        }
+    }
+    let (source_opts, target_opts, config_opts) = (
+        opts.source_opts.clone(),
+        opts.target_opts.clone(),
+        opts.config_opts.clone(),
+    );
+    let state =
+        super::prepare_install(opts.config_opts, opts.source_opts, opts.target_opts).await?;
+    if state.config_opts.print_kargs {
+        return super::print_kargs(&state);
+    }
+    block_opts.confirm_device()?;
+
+    send_phase(state.progress.as_ref(), "partitioning");
+    let rootfs = {
+        let state = state.clone();
+        tokio::task::spawn_blocking(move || baseline::install_create_rootfs(&state, block_opts))
+            .await??
+    };
+    let r = target_mounts(&rootfs).and_then(|target_mounts| {
+        Handoff::new(
+            source_opts,
+            target_opts,
+            config_opts,
+            &rootfs,
+            target_mounts,
+            false,
+        )
+        .write(handoff_path)
+    });
+    // The filesystems are mounted again by `install deploy`; on failure, the block
+    // devices are deactivated too.
+    let (root_path, block_stack) = rootfs.into_storage();
+    let teardown = unmount(&root_path).and_then(|()| match r {
+        Ok(()) => Ok(()),
+        Err(_) => block_stack.deactivate(),
+    });
+    r?;
+    teardown?;
+
+    consume_state(state)?;
+    println!("Prepared installation; continue with: bootc install deploy --handoff {handoff_path}");
+    Ok(())
+}
+
+async fn prepare_filesystem(opts: InstallToFilesystemOpts, handoff_path: &Utf8Path) -> Result<()> {
+    let fsopts = opts.filesystem_opts;
+    let rootfs_fd = super::open_target_root(&fsopts.root_path)?;
+    let (source_opts, target_opts, config_opts) = (
+        opts.source_opts.clone(),
+        opts.target_opts.clone(),
+        opts.config_opts.clone(),
+    );
+    let state =
+        super::prepare_install(opts.config_opts, opts.source_opts, opts.target_opts).await?;
+    if state.config_opts.print_kargs {
+        return super::print_kargs(&state);
+    }
+
+    let (rootfs, move_home) = super::setup_filesystem_root(fsopts, rootfs_fd, false).await?;
+    Handoff::new(
+        source_opts,
+        target_opts,
+        config_opts,
+        &rootfs,
+        Vec::new(),
+        move_home,
+    )
+    .write(handoff_path)?;
+    drop(rootfs);
+
+    consume_state(state)?;
+    println!("Prepared installation; continue with: bootc install deploy --handoff {handoff_path}");
+    Ok(())
+}
+
+/// Implementation of `bootc install deploy`.
+#[context("Deploying")]
+pub(crate) async fn install_deploy(opts: InstallDeployOpts) -> Result<()> {
+    let mut handoff = Handoff::load(&opts.handoff)?;
+    handoff.require_phase(Phase::Prepared)?;
+    let state = handoff.prepare_state(opts.progress_fd, None).await?;
+
+    let r = match handoff.mount() {
+        Ok(mut rootfs) => deploy(&state, &mut rootfs, handoff.move_home).await,
+        Err(e) => Err(e),
+    };
+    // On failure, the installation has to be prepared again
+    let teardown = handoff.teardown(r.is_err());
+    handoff.result = Some(r?);
+    teardown?;
+    handoff.phase = Phase::Deployed;
+    handoff.write(&opts.handoff)?;
+
+    consume_state(state)?;
+    println!(
+        "Deployed image; continue with: bootc install finalize --handoff {}",
+        opts.handoff
+    );
+    Ok(())
+}
+
+async fn deploy(state: &State, rootfs: &mut RootSetup, move_home: bool) -> Result<InstallResult> {
+    super::prepare_root_setup(state, rootfs)?;
+    let rootfs = &*rootfs;
+
+    let bound_images = super::resolve_bound_images(state).await?;
+
+    send_phase(state.progress.as_ref(), "ostree-init");
+    let result = {
+        let sysroot = super::initialize_ostree_root(state, rootfs).await?;
+        let (_, result) = super::install_deployment(state, rootfs, &sysroot).await?;
+        super::install_bound_images(state, &sysroot, &bound_images).await?;
+        result
+    };
+
+    if move_home {
+        super::move_home_to_stateroot(state, &rootfs.rootfs_fd)?;
+    }
+    Ok(result)
+}
+
+/// Implementation of `bootc install finalize`.
+#[context("Finalizing installation")]
+pub(crate) async fn install_finalize(opts: InstallFinalizeOpts) -> Result<()> {
+    let mut handoff = Handoff::load(&opts.handoff)?;
+    handoff.require_phase(Phase::Deployed)?;
+    let result = handoff
+        .result
+        .as_ref()
+        .ok_or_else(|| anyhow!("Missing result of the deployment"))?;
+    let state = handoff
+        .prepare_state(opts.progress_fd, opts.result_json)
+        .await?;
+
+    let r = match handoff.mount() {
+        Ok(rootfs) => finalize(&state, &rootfs, result).await,
+        Err(e) => Err(e),
+    };
+    let teardown = handoff.teardown(true);
+    r?;
+    teardown?;
+    handoff.phase = Phase::Finalized;
+    handoff.write(&opts.handoff)?;
+
+    consume_state(state)?;
+    super::installation_complete();
+    Ok(())
+}
+
+async fn finalize(state: &State, rootfs: &RootSetup, result: &InstallResult) -> Result<()> {
+    {
+        let sysroot = super::open_target_sysroot(state, &rootfs.rootfs).await?;
+        let deployment = sysroot
+            .deployments()
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("Failed to find deployment"))?;
+        super::install_bootloader(state, rootfs, &sysroot, &deployment, result.bootloader)?;
+        // We must drop the sysroot here in order to close any open file
+        // descriptors.
+    }
+    super::complete_install(state, rootfs, result)
+}
+
+#[test]
+fn test_handoff() {
+    let v = serde_json::json!({
+        "phase": "prepared",
+        "source-opts": {},
+        "target-opts": { "target_transport": "registry" },
+        "config-opts": { "karg": ["nosmt"] },
+        "root": "/run/bootc/mounts/rootfs",
+        "target-mounts": [
+            { "source": "/dev/vda3", "path": "/", "options": "subvol=root" },
+            { "source": "/dev/vda2", "path": "/boot", "options": null },
+            { "source": "/dev/vda1", "path": "/boot/efi", "options": null },
+        ],
+        "device": "/dev/vda",
+        "mirror-devices": [],
+        "root-uuid": "a2b0e3c4-1b3e-4f5a-8c6d-7e8f9a0b1c2d",
+        "boot": { "source": "UUID=0c7d7f1e-ffb9-4c1c-8ac9-1d4c2b1f3f2a", "target": "/boot", "fstype": "auto", "options": "ro" },
+        "mounts": [],
+        "crypttab": null,
+        "kargs": ["root=UUID=a2b0e3c4-1b3e-4f5a-8c6d-7e8f9a0b1c2d", "rw"],
+        "skip-finalize": false,
+        "skip-bootloader": false,
+        "block-stack": { "luks-device": null, "lvm-vg": "bootc", "md-devices": [] },
+        "move-home": false,
+        "result": null,
+    });
+    let handoff: Handoff = serde_json::from_value(v).unwrap();
+    assert!(handoff.require_phase(Phase::Prepared).is_ok());
+    assert!(handoff.require_phase(Phase::Deployed).is_err());
+    assert_eq!(handoff.config_opts.karg.as_deref().unwrap(), ["nosmt"]);
+    assert_eq!(handoff.block_stack.lvm_vg.as_deref(), Some("bootc"));
+    let paths = handoff
+        .target_mounts
+        .iter()
+        .map(|m| m.host_path(&handoff.root))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        [
+            "/run/bootc/mounts/rootfs",
+            "/run/bootc/mounts/rootfs/boot",
+            "/run/bootc/mounts/rootfs/boot/efi"
+        ]
+    );
+
+    // Round trip
+    let buf = serde_json::to_vec(&handoff).unwrap();
+    let handoff2: Handoff = serde_json::from_slice(&buf).unwrap();
+    assert_eq!(handoff2.target_mounts, handoff.target_mounts);
+    assert_eq!(handoff2.kargs, handoff.kargs);
+    assert_eq!(handoff2.config_opts, handoff.config_opts);
+    assert_eq!(
+        handoff2.boot.unwrap().to_fstab(),
+        handoff.boot.unwrap().to_fstab()
+    );
+}
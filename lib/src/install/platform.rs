@@ -0,0 +1,192 @@
+//! # Console kernel arguments for the target platform
+//!
+//! Clouds generally only expose a serial console, which the kernel does not
+//! write to unless told so via `console=`.  At installation time, the platform
+//! is taken from `--target-platform`, or otherwise detected from the DMI data
+//! of the machine (unless installing a generic image), and the `console=`
+//! kernel arguments for it are added, unless already given by another source.
+//! The arguments for each platform can be replaced via the `[install.console]`
+//! table of the install configuration.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use cap_std_ext::cap_std;
+use cap_std_ext::cap_std::fs::Dir;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use fn_error_context::context;
+use serde::{Deserialize, Serialize};
+
+/// The sysfs directory holding the DMI data.
+const DMI_ID: &str = "sys/class/dmi/id";
+/// The chassis asset tag of Azure virtual machines, which distinguishes them
+/// from other Hyper-V guests.
+const AZURE_ASSET_TAG: &str = "7783-7084-3265-9085-8269-3286-77";
+
+/// A platform the installed system runs on.
+#[derive(
+    clap::ValueEnum, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Platform {
+    /// Physical machines; the kernel default console is used
+    Metal,
+    /// QEMU virtual machines
+    Qemu,
+    /// Amazon Web Services
+    Aws,
+    /// Microsoft Azure
+    Azure,
+    /// Google Cloud Platform
+    Gcp,
+}
+
+/// The DMI data identifying the machine.
+#[derive(Debug, Default)]
+struct Dmi {
+    sys_vendor: String,
+    product_name: String,
+    chassis_asset_tag: String,
+}
+
+impl Dmi {
+    fn load(dir: &Dir) -> Result<Self> {
+        let read = |name: &str| -> Result<String> {
+            Ok(dir
+                .open_optional(name)?
+                .map(std::io::read_to_string)
+                .transpose()?
+                .map(|s| s.trim().to_owned())
+                .unwrap_or_default())
+        };
+        Ok(Self {
+            sys_vendor: read("sys_vendor")?,
+            product_name: read("product_name")?,
+            chassis_asset_tag: read("chassis_asset_tag")?,
+        })
+    }
+
+    fn platform(&self) -> Platform {
+        match (self.sys_vendor.as_str(), self.product_name.as_str()) {
+            ("Amazon EC2", _) => Platform::Aws,
+            ("Microsoft Corporation", "Virtual Machine")
+                if self.chassis_asset_tag == AZURE_ASSET_TAG =>
+            {
+                Platform::Azure
+            }
+            ("Google", _) | (_, "Google Compute Engine") => Platform::Gcp,
+            ("QEMU", _) => Platform::Qemu,
+            _ => Platform::Metal,
+        }
+    }
+}
+
+impl Platform {
+    /// Detect the platform of the running machine from its DMI data; machines
+    /// without DMI data are assumed to be physical.
+    #[context("Detecting platform")]
+    pub(crate) fn detect() -> Result<Self> {
+        let root = Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+        let Some(dir) = root.open_dir_optional(DMI_ID)? else {
+            return Ok(Self::Metal);
+        };
+        let dmi = Dmi::load(&dir)?;
+        tracing::debug!("DMI: {dmi:?}");
+        Ok(dmi.platform())
+    }
+
+    /// The built-in console kernel arguments for the platform and architecture.
+    fn default_console_kargs(&self, arch: &str) -> &'static [&'static str] {
+        match (self, arch) {
+            (Self::Metal, _) => &[],
+            (Self::Qemu | Self::Aws, "x86_64") => &["console=tty0", "console=ttyS0,115200n8"],
+            (Self::Qemu | Self::Azure, "aarch64") => &["console=tty0", "console=ttyAMA0,115200n8"],
+            (Self::Aws, "aarch64") => &["console=tty0", "console=ttyS0,115200n8"],
+            (Self::Azure, "x86_64") => &[
+                "console=tty0",
+                "console=ttyS0,115200n8",
+                "earlyprintk=ttyS0",
+            ],
+            (Self::Gcp, "x86_64") => &["console=ttyS0,115200n8"],
+            (Self::Gcp, "aarch64") => &["console=ttyAMA0,115200n8"],
+            (Self::Qemu, "powerpc64") => &["console=hvc0"],
+            _ => &[],
+        }
+    }
+
+    /// The console kernel arguments for the platform, from the `[install.console]`
+    /// table if set there.
+    pub(crate) fn console_kargs(
+        &self,
+        arch: &str,
+        config: Option<&BTreeMap<Platform, Vec<String>>>,
+    ) -> Vec<String> {
+        if let Some(kargs) = config.and_then(|c| c.get(self)) {
+            return kargs.clone();
+        }
+        self.default_console_kargs(arch)
+            .iter()
+            .map(|&k| k.to_owned())
+            .collect()
+    }
+}
+
+#[test]
+fn test_detect_platform() {
+    let dmi = |sys_vendor: &str, product_name: &str, chassis_asset_tag: &str| Dmi {
+        sys_vendor: sys_vendor.into(),
+        product_name: product_name.into(),
+        chassis_asset_tag: chassis_asset_tag.into(),
+    };
+    let cases = [
+        (dmi("Amazon EC2", "m5.large", "Amazon EC2"), Platform::Aws),
+        (
+            dmi("Microsoft Corporation", "Virtual Machine", AZURE_ASSET_TAG),
+            Platform::Azure,
+        ),
+        // Plain Hyper-V
+        (
+            dmi("Microsoft Corporation", "Virtual Machine", "1234"),
+            Platform::Metal,
+        ),
+        (dmi("Google", "Google Compute Engine", ""), Platform::Gcp),
+        (
+            dmi("QEMU", "Standard PC (Q35 + ICH9, 2009)", ""),
+            Platform::Qemu,
+        ),
+        (dmi("Dell Inc.", "PowerEdge R650", ""), Platform::Metal),
+        (Dmi::default(), Platform::Metal),
+    ];
+    for (dmi, platform) in cases {
+        assert_eq!(dmi.platform(), platform, "{dmi:?}");
+    }
+}
+
+#[test]
+fn test_console_kargs() {
+    assert!(Platform::Metal.console_kargs("x86_64", None).is_empty());
+    assert_eq!(
+        Platform::Aws.console_kargs("x86_64", None),
+        ["console=tty0", "console=ttyS0,115200n8"]
+    );
+    assert_eq!(
+        Platform::Gcp.console_kargs("aarch64", None),
+        ["console=ttyAMA0,115200n8"]
+    );
+    assert!(Platform::Azure.console_kargs("s390x", None).is_empty());
+    let config = BTreeMap::from([
+        (Platform::Metal, vec!["console=ttyS1,115200n8".to_owned()]),
+        (Platform::Aws, vec![]),
+    ]);
+    assert_eq!(
+        Platform::Metal.console_kargs("x86_64", Some(&config)),
+        ["console=ttyS1,115200n8"]
+    );
+    assert!(Platform::Aws
+        .console_kargs("x86_64", Some(&config))
+        .is_empty());
+    assert_eq!(
+        Platform::Gcp.console_kargs("x86_64", Some(&config)),
+        ["console=ttyS0,115200n8"]
+    );
+}
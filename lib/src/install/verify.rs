@@ -0,0 +1,443 @@
+//! # Verifying an installation
+//!
+//! This implements `bootc install to-disk --verify`: before the target is unmounted,
+//! the bootloader entry, kernel arguments and `/etc/fstab` of the installed system
+//! are checked for consistency.  With `--verify-boot`, the system is then booted
+//! once under qemu, discarding any changes.
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use cap_std::fs::Dir;
+use cap_std_ext::cap_std;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use fn_error_context::context;
+
+/// The BLS entries written by ostree, relative to the physical root.
+const BLS_ENTRIES: &str = "boot/loader/entries";
+/// Bootloader configuration, relative to /boot, of which at least one must exist.
+const BOOTLOADER_CONFIGS: &[&str] = &[
+    "grub2/grub.cfg",
+    "grub/grub.cfg",
+    // systemd-boot
+    "efi/loader/entries",
+    // zipl
+    "bootmap",
+];
+/// The unit which the installed system must reach when booted under qemu.
+const BOOT_TARGET: &str = "multi-user.target";
+/// How long to wait for the installed system to reach [`BOOT_TARGET`].
+const BOOT_TIMEOUT: Duration = Duration::from_secs(600);
+/// The number of console lines shown if booting fails.
+const CONSOLE_TAIL: usize = 30;
+
+/// The bootloader entry of the installed system.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct BootEntry {
+    /// The kernel, relative to /boot
+    pub(crate) linux: String,
+    /// The initramfs, relative to /boot
+    pub(crate) initrd: String,
+    /// The kernel arguments
+    pub(crate) options: String,
+}
+
+/// The kernel and initramfs of the installed system, copied to boot it under qemu
+/// after the target is unmounted.
+#[derive(Debug)]
+pub(crate) struct BootFiles {
+    _dir: tempfile::TempDir,
+    kernel: Utf8PathBuf,
+    initrd: Utf8PathBuf,
+    options: String,
+}
+
+/// Check `/etc/fstab` of the installed system against its kernel arguments,
+/// returning a description of each problem found.
+fn check_fstab(fstab: &str, kargs: &[&str]) -> Vec<String> {
+    let karg = |key: &str| kargs.iter().rev().find_map(|k| k.strip_prefix(key));
+    let mut problems = Vec::new();
+    let mut mountpoints = std::collections::HashSet::new();
+    for line in fstab.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = line.split_ascii_whitespace().collect::<Vec<_>>();
+        let [source, target, _fstype, _options, ..] = fields.as_slice() else {
+            problems.push(format!("/etc/fstab: Invalid entry: {line}"));
+            continue;
+        };
+        if *target != "none" && !target.starts_with('/') {
+            problems.push(format!("/etc/fstab: Invalid mount point: {line}"));
+        } else if *target != "none" && !mountpoints.insert(*target) {
+            problems.push(format!("/etc/fstab: Duplicate mount point {target}"));
+        }
+        let expected = match *target {
+            "/" => karg("root=").map(|v| ("root=", v)),
+            "/boot" => karg("boot=").map(|v| ("boot=", v)),
+            _ => None,
+        };
+        if let Some((key, v)) = expected.filter(|(_, v)| v != source) {
+            problems.push(format!(
+                "/etc/fstab: {target} is mounted from {source}, but the kernel argument is {key}{v}"
+            ));
+        }
+    }
+    problems
+}
+
+/// The filesystem UUIDs referenced by the kernel arguments and `/etc/fstab`.
+fn referenced_uuids<'a>(fstab: &'a str, kargs: &[&'a str]) -> Vec<&'a str> {
+    let fstab_sources = fstab
+        .lines()
+        .filter(|l| !l.trim_start().starts_with('#'))
+        .filter_map(|l| l.split_ascii_whitespace().next());
+    let karg_sources = kargs
+        .iter()
+        .filter_map(|k| k.strip_prefix("root=").or_else(|| k.strip_prefix("boot=")));
+    let mut uuids = karg_sources
+        .chain(fstab_sources)
+        .filter_map(|s| s.strip_prefix("UUID="))
+        .collect::<Vec<_>>();
+    uuids.sort_unstable();
+    uuids.dedup();
+    uuids
+}
+
+/// Whether a filesystem with the given UUID exists.
+fn uuid_exists(uuid: &str) -> Result<bool> {
+    let status = Command::new("blkid")
+        .args(["-U", uuid])
+        .stdout(Stdio::null())
+        .status()
+        .context("Running blkid")?;
+    Ok(status.success())
+}
+
+/// Resolve a path of a bootloader entry, which is relative to /boot, in the physical
+/// root.  With `sysroot.bootprefix`, the paths start with `/boot` themselves.
+fn resolve_boot_path(path: &str) -> Utf8PathBuf {
+    if path.starts_with("boot/") {
+        path.into()
+    } else {
+        Utf8Path::new("boot").join(path)
+    }
+}
+
+/// Verify the bootloader entry, kernel arguments and `/etc/fstab` of the installation
+/// in the given physical root, returning the bootloader entry.
+#[context("Verifying installation")]
+pub(crate) fn verify_installation(root: &Dir) -> Result<BootEntry> {
+    let mut entries = Vec::new();
+    for e in root
+        .read_dir(BLS_ENTRIES)
+        .with_context(|| format!("Reading /{BLS_ENTRIES}"))?
+    {
+        let name = e?.file_name().to_string_lossy().into_owned();
+        if name.ends_with(".conf") {
+            entries.push(name);
+        }
+    }
+    let name = match entries.as_mut_slice() {
+        [name] => std::mem::take(name),
+        [] => anyhow::bail!("No bootloader entry in /{BLS_ENTRIES}"),
+        names => anyhow::bail!(
+            "Expected one bootloader entry in /{BLS_ENTRIES}, found: {}",
+            names.join(", ")
+        ),
+    };
+    let conf = root.read_to_string(Utf8Path::new(BLS_ENTRIES).join(&name))?;
+    let entry = crate::bootloader::parse_bls_entry(&conf)
+        .with_context(|| format!("Parsing /{BLS_ENTRIES}/{name}"))?;
+    let boot = Utf8Path::new("boot");
+    let mut problems = Vec::new();
+    for path in [entry.linux, entry.initrd] {
+        if !root.try_exists(resolve_boot_path(path))? {
+            problems.push(format!(
+                "/boot/{path} of the bootloader entry does not exist"
+            ));
+        }
+    }
+    if !BOOTLOADER_CONFIGS
+        .iter()
+        .map(|p| root.try_exists(boot.join(p)))
+        .collect::<std::io::Result<Vec<_>>>()?
+        .contains(&true)
+    {
+        problems.push("No bootloader configuration found in /boot".to_owned());
+    }
+
+    let kargs = entry.options.split_ascii_whitespace().collect::<Vec<_>>();
+    let mut roots = kargs.iter().filter_map(|k| k.strip_prefix("root="));
+    match (roots.next(), roots.next()) {
+        (None, _) => problems.push("The root= kernel argument is missing".to_owned()),
+        (Some(a), Some(b)) if a != b => problems.push(format!(
+            "Conflicting kernel arguments root={a} and root={b}"
+        )),
+        _ => {}
+    }
+    // The ostree= argument names the deployment via the /ostree/boot.N symlinks
+    let mut fstab = String::new();
+    match kargs.iter().find_map(|k| k.strip_prefix("ostree=")) {
+        None => problems.push("The ostree= kernel argument is missing".to_owned()),
+        Some(path) => {
+            let deployment = path.trim_start_matches('/');
+            if !root.try_exists(deployment)? {
+                problems.push(format!(
+                    "The deployment {path} of the ostree= kernel argument does not exist"
+                ));
+            } else if let Some(f) = root.open_dir(deployment)?.open_optional("etc/fstab")? {
+                fstab = std::io::read_to_string(f)?;
+            }
+        }
+    }
+    problems.extend(check_fstab(&fstab, &kargs));
+    for uuid in referenced_uuids(&fstab, &kargs) {
+        if !uuid_exists(uuid)? {
+            problems.push(format!("No filesystem with UUID {uuid} exists"));
+        }
+    }
+
+    if !problems.is_empty() {
+        for problem in problems.iter() {
+            eprintln!("error: {problem}");
+        }
+        anyhow::bail!(
+            "Found {} problem(s) in the installed system",
+            problems.len()
+        );
+    }
+    println!("Verified bootloader entry, kernel arguments and /etc/fstab");
+    Ok(BootEntry {
+        linux: entry.linux.to_owned(),
+        initrd: entry.initrd.to_owned(),
+        options: entry.options.to_owned(),
+    })
+}
+
+/// Copy the kernel and initramfs of the entry out of the target, to boot it once
+/// the target is unmounted.
+#[context("Copying kernel and initramfs")]
+pub(crate) fn stage_boot_files(root: &Dir, entry: BootEntry) -> Result<BootFiles> {
+    let dir = tempfile::Builder::new().prefix("bootc-verify").tempdir()?;
+    let path = Utf8Path::from_path(dir.path())
+        .ok_or_else(|| anyhow::anyhow!("Invalid non-UTF-8 temporary directory"))?
+        .to_owned();
+    let target = Dir::open_ambient_dir(&path, cap_std::ambient_authority())?;
+    root.copy(resolve_boot_path(&entry.linux), &target, "vmlinuz")?;
+    root.copy(resolve_boot_path(&entry.initrd), &target, "initramfs.img")?;
+    Ok(BootFiles {
+        _dir: dir,
+        kernel: path.join("vmlinuz"),
+        initrd: path.join("initramfs.img"),
+        options: entry.options,
+    })
+}
+
+/// Find the qemu binary for the given architecture, if installed.
+pub(crate) fn find_qemu(arch: &str) -> Option<Utf8PathBuf> {
+    let name = format!("qemu-system-{arch}");
+    let path = std::env::var("PATH").ok()?;
+    path.split(':')
+        .map(|dir| Utf8Path::new(dir).join(&name))
+        .find(|p| p.exists())
+}
+
+/// The qemu arguments to boot the installed disk once, directly via its kernel.
+fn qemu_args(arch: &str, device: &Utf8Path, boot: &BootFiles, kvm: bool) -> Result<Vec<String>> {
+    let (machine, console) = match arch {
+        "x86_64" => ("q35", "ttyS0"),
+        "aarch64" => ("virt", "ttyAMA0"),
+        o => anyhow::bail!("Booting {o} under qemu is not supported"),
+    };
+    let (accel, cpu) = if kvm { ("kvm", "host") } else { ("tcg", "max") };
+    let append = format!(
+        "{} console={console} systemd.show_status=1 systemd.status_unit_format=name",
+        boot.options
+    );
+    // With snapshot=on, writes go to a temporary file instead of the disk
+    let drive = format!(
+        "if=virtio,format=raw,snapshot=on,file={}",
+        device.as_str().replace(',', ",,")
+    );
+    Ok([
+        "-machine",
+        machine,
+        "-accel",
+        accel,
+        "-cpu",
+        cpu,
+        "-m",
+        "2048",
+        "-smp",
+        "2",
+        "-nographic",
+        "-monitor",
+        "none",
+        "-nic",
+        "none",
+        "-drive",
+        drive.as_str(),
+        "-kernel",
+        boot.kernel.as_str(),
+        "-initrd",
+        boot.initrd.as_str(),
+        "-append",
+        append.as_str(),
+    ]
+    .into_iter()
+    .map(ToOwned::to_owned)
+    .collect())
+}
+
+/// Check a line of the console output for the result of booting.
+fn boot_status(line: &str) -> Option<Result<()>> {
+    if line.contains(&format!("Reached target {BOOT_TARGET}")) {
+        Some(Ok(()))
+    } else if line.contains("Reached target emergency.target") || line.contains("Kernel panic") {
+        Some(Err(anyhow::anyhow!("Boot failed: {}", line.trim())))
+    } else {
+        None
+    }
+}
+
+/// Boot the installed disk once under qemu, and wait until it reaches [`BOOT_TARGET`].
+#[context("Booting the installed system")]
+pub(crate) fn smoke_boot(
+    qemu: &Utf8Path,
+    arch: &str,
+    device: &Utf8Path,
+    boot: &BootFiles,
+) -> Result<()> {
+    let kvm = arch == std::env::consts::ARCH && Utf8Path::new("/dev/kvm").exists();
+    let args = qemu_args(arch, device, boot, kvm)?;
+    println!(
+        "Booting the installed system under qemu (timeout: {}s)",
+        BOOT_TIMEOUT.as_secs()
+    );
+    let mut child = Command::new(qemu)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Spawning {qemu}"))?;
+    // SAFETY: We requested a pipe for stdout
+    let stdout = child.stdout.take().unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).split(b'\n') {
+            let line = line.map(|l| String::from_utf8_lossy(&l).into_owned());
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    let deadline = Instant::now() + BOOT_TIMEOUT;
+    let mut tail = VecDeque::new();
+    let r = loop {
+        match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(Ok(line)) => {
+                tracing::debug!("console: {line}");
+                if let Some(r) = boot_status(&line) {
+                    break r;
+                }
+                if tail.len() == CONSOLE_TAIL {
+                    tail.pop_front();
+                }
+                tail.push_back(line);
+            }
+            Ok(Err(e)) => break Err(e).context("Reading console"),
+            Err(RecvTimeoutError::Timeout) => {
+                break Err(anyhow::anyhow!("Timed out waiting for {BOOT_TARGET}"))
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                break Err(anyhow::anyhow!("qemu exited before reaching {BOOT_TARGET}"))
+            }
+        }
+    };
+    // The result is already determined; qemu may have exited by itself
+    let _ = child.kill();
+    child.wait()?;
+    if r.is_err() {
+        eprintln!("Last console output:");
+        for line in tail {
+            eprintln!("  {}", line.trim_end());
+        }
+    } else {
+        println!("The installed system reached {BOOT_TARGET}");
+    }
+    r
+}
+
+#[test]
+fn test_check_fstab() {
+    let kargs = ["root=UUID=abc", "boot=UUID=def", "rw"];
+    let fstab = indoc::indoc! {"
+        # Comment
+        UUID=def /boot auto ro 0 0
+        UUID=ghi /var xfs defaults 0 0
+        UUID=jkl none swap defaults 0 0
+    "};
+    assert!(check_fstab(fstab, &kargs).is_empty());
+    assert_eq!(
+        referenced_uuids(fstab, &kargs),
+        ["abc", "def", "ghi", "jkl"]
+    );
+    for (fstab, problem) in [
+        (
+            "UUID=xyz /boot auto ro 0 0",
+            "/boot is mounted from UUID=xyz",
+        ),
+        ("UUID=xyz / xfs defaults 0 0", "/ is mounted from UUID=xyz"),
+        ("UUID=ghi /var", "Invalid entry"),
+        ("UUID=ghi var xfs defaults 0 0", "Invalid mount point"),
+        (
+            "UUID=ghi /var xfs defaults 0 0\nUUID=jkl /var xfs defaults 0 0",
+            "Duplicate mount point /var",
+        ),
+    ] {
+        let problems = check_fstab(fstab, &kargs);
+        assert_eq!(problems.len(), 1, "{fstab}");
+        assert!(problems[0].contains(problem), "{problems:?}");
+    }
+}
+
+#[test]
+fn test_boot_status() {
+    assert!(boot_status("[  OK  ] Reached target multi-user.target.")
+        .unwrap()
+        .is_ok());
+    assert!(boot_status("[  OK  ] Reached target emergency.target.")
+        .unwrap()
+        .is_err());
+    assert!(boot_status("[    1.2] Kernel panic - not syncing: VFS")
+        .unwrap()
+        .is_err());
+    assert!(boot_status("[  OK  ] Reached target basic.target.").is_none());
+}
+
+#[test]
+fn test_qemu_args() -> Result<()> {
+    let boot = BootFiles {
+        _dir: tempfile::tempdir()?,
+        kernel: "/tmp/vmlinuz".into(),
+        initrd: "/tmp/initramfs.img".into(),
+        options: "root=UUID=abc rw".into(),
+    };
+    let args = qemu_args("x86_64", Utf8Path::new("/dev/loop0"), &boot, true)?;
+    assert!(args.contains(&"if=virtio,format=raw,snapshot=on,file=/dev/loop0".to_owned()));
+    assert!(args.contains(
+        &"root=UUID=abc rw console=ttyS0 systemd.show_status=1 systemd.status_unit_format=name"
+            .to_owned()
+    ));
+    assert!(args.windows(2).any(|w| w == ["-accel", "kvm"]));
+    let args = qemu_args("aarch64", Utf8Path::new("/dev/loop0"), &boot, false)?;
+    assert!(args.windows(2).any(|w| w == ["-machine", "virt"]));
+    assert!(qemu_args("s390x", Utf8Path::new("/dev/loop0"), &boot, false).is_err());
+    Ok(())
+}
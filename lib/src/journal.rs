@@ -1,12 +1,83 @@
 //! Thin wrapper for systemd journaling; these APIs are no-ops
 //! when not running under systemd.  Only use them when
+//!
+//! At key points such as staging an update, bootc logs structured messages
+//! with a stable `MESSAGE_ID` (see [`Event`] and `bootc.catalog`), so that
+//! log pipelines can match on them; `bootc journal` shows them.
 
 use std::collections::HashMap;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use cap_std_ext::cap_std;
+use cap_std_ext::cap_std::fs::Dir;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use clap::ValueEnum;
+use fn_error_context::context;
+
+use crate::store::Storage;
+
+/// Logged when the system booted a different deployment than the last time.
+const UPDATE_APPLIED_JOURNAL_ID: &str = "4b6f1d2e8a3c4f5b9d7e0a1c6b2f8e3d";
+/// Logged when finalizing the staged deployment failed at shutdown.
+const FINALIZE_FAILED_JOURNAL_ID: &str = "c1e7a9d3f5b2468a8e0d4c6b9f1a3e57";
+/// Holds the checksum of the commit booted last, relative to the root.
+const LAST_BOOTED: &str = "var/lib/bootc/last-booted";
+/// Written by ostree if finalizing the staged deployment failed, relative to
+/// the root; it is removed once a deployment is finalized successfully.
+const FINALIZE_FAILURE_STAMP: &str = "boot/ostree/finalize-failure.stamp";
 
 /// Set to true if we failed to write to the journal once
 static EMITTED_JOURNAL_ERROR: AtomicBool = AtomicBool::new(false);
 
+/// The structured messages logged by bootc.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[clap(rename_all = "kebab-case")]
+pub(crate) enum Event {
+    /// An update was staged for the next boot
+    UpdateStaged,
+    /// A staged update needs a reboot to be applied
+    RebootPending,
+    /// The system booted a different deployment than the last time
+    UpdateApplied,
+    /// Finalizing the staged deployment failed at shutdown
+    FinalizeFailed,
+    /// The rollback deployment was queued for the next boot
+    Rollback,
+    /// The update failed to boot, and the system fell back to the previous deployment
+    BootFailed,
+    /// A deployment does not match the image it was staged from
+    VerifyFailed,
+    /// A health check failed
+    HealthFailed,
+    /// A first boot command was run
+    Firstboot,
+    /// A mutating operation was rejected in read-only mode
+    ReadOnly,
+}
+
+impl Event {
+    /// The `MESSAGE_ID` of the messages.
+    pub(crate) fn message_id(self) -> &'static str {
+        match self {
+            Event::UpdateStaged => crate::deploy::UPDATE_STAGED_JOURNAL_ID,
+            Event::RebootPending => crate::cli::REBOOT_PENDING_JOURNAL_ID,
+            Event::UpdateApplied => UPDATE_APPLIED_JOURNAL_ID,
+            Event::FinalizeFailed => FINALIZE_FAILED_JOURNAL_ID,
+            Event::Rollback => crate::deploy::ROLLBACK_JOURNAL_ID,
+            Event::BootFailed => crate::bootcount::BOOT_FAILED_JOURNAL_ID,
+            Event::VerifyFailed => crate::deploy::VERIFY_FAILED_JOURNAL_ID,
+            Event::HealthFailed => crate::health::HEALTH_FAILED_JOURNAL_ID,
+            Event::Firstboot => crate::firstboot::FIRSTBOOT_JOURNAL_ID,
+            Event::ReadOnly => crate::cli::READ_ONLY_JOURNAL_ID,
+        }
+    }
+}
+
 /// Wrapper for structured logging which is an explicit no-op
 /// when systemd is not in use (e.g. in a container).
 pub(crate) fn journal_send<K, V>(
@@ -34,3 +105,169 @@ pub(crate) fn journal_print(priority: libsystemd::logging::Priority, msg: &str)
     let vars: HashMap<&str, &str> = HashMap::new();
     journal_send(priority, msg, vars.into_iter())
 }
+
+/// The `journalctl` matches for the given events, or all of them if none
+/// are given.
+fn journalctl_matches(events: &[Event]) -> Vec<String> {
+    let events = if events.is_empty() {
+        Event::value_variants()
+    } else {
+        events
+    };
+    events
+        .iter()
+        .map(|e| format!("MESSAGE_ID={}", e.message_id()))
+        .collect()
+}
+
+/// Implementation of `bootc journal`: print the known events, or show their
+/// messages via `journalctl` with the given extra arguments.
+pub(crate) fn query(events: &[Event], list: bool, args: &[String]) -> Result<()> {
+    if list {
+        for event in Event::value_variants() {
+            let value = event.to_possible_value().unwrap();
+            let help = value.get_help().map(|h| h.to_string()).unwrap_or_default();
+            println!("{} {:<16} {help}", event.message_id(), value.get_name());
+        }
+        return Ok(());
+    }
+    // Multiple matches of the same field are ORed
+    let e = Command::new("journalctl")
+        .args(args)
+        .args(journalctl_matches(events))
+        .exec();
+    Err::<(), _>(e).context("Executing journalctl")
+}
+
+/// The events of this boot, given the commit booted now, the one booted
+/// last (and when it was recorded) and when finalizing last failed.
+fn boot_events(
+    booted: &str,
+    last: Option<(&str, SystemTime)>,
+    finalize_failed: Option<SystemTime>,
+) -> Vec<Event> {
+    let mut events = Vec::new();
+    // The stamp stays until the next successful finalization; only log it
+    // on the first boot after the failure.
+    if let Some(failed) = finalize_failed {
+        if last.map_or(true, |(_, recorded)| failed > recorded) {
+            events.push(Event::FinalizeFailed);
+        }
+    }
+    if last.is_some_and(|(last, _)| last != booted) {
+        events.push(Event::UpdateApplied);
+    }
+    events
+}
+
+/// Implementation of `bootc internals log-boot`: log whether the system
+/// booted into a different deployment, or finalizing the staged deployment
+/// failed at the last shutdown, and record the booted commit.
+#[context("Logging boot")]
+pub(crate) fn log_boot(sysroot: &Storage) -> Result<()> {
+    let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    let booted = sysroot.require_booted_deployment()?;
+    let checksum = booted.csum();
+    let last = match root.open_optional(LAST_BOOTED)? {
+        Some(mut f) => {
+            let recorded = f.metadata()?.modified()?.into_std();
+            let mut last = String::new();
+            f.read_to_string(&mut last)?;
+            Some((last.trim().to_owned(), recorded))
+        }
+        None => None,
+    };
+    let finalize_failed = root
+        .open_optional(FINALIZE_FAILURE_STAMP)?
+        .map(|f| anyhow::Ok(f.metadata()?.modified()?.into_std()))
+        .transpose()?;
+    let last_ref = last.as_ref().map(|(c, t)| (c.as_str(), *t));
+    for event in boot_events(&checksum, last_ref, finalize_failed) {
+        let id = event.message_id();
+        match event {
+            Event::FinalizeFailed => {
+                let msg =
+                    "Failed to finalize the staged deployment; booted the previous deployment";
+                eprintln!("{msg}");
+                journal_send(
+                    libsystemd::logging::Priority::Critical,
+                    msg,
+                    [("MESSAGE_ID", id)].into_iter(),
+                );
+            }
+            _ => {
+                let image = booted
+                    .origin()
+                    .map(|o| crate::status::get_image_origin(&o))
+                    .transpose()?
+                    .flatten()
+                    .map(|i| i.imgref.name)
+                    .unwrap_or_default();
+                let previous = last_ref.map(|(c, _)| c).unwrap_or_default();
+                let msg = format!("Booted a different deployment than the last time: {checksum}");
+                println!("{msg}");
+                journal_send(
+                    libsystemd::logging::Priority::Notice,
+                    &msg,
+                    [
+                        ("MESSAGE_ID", id),
+                        ("BOOTC_OSTREE_COMMIT", checksum.as_str()),
+                        ("BOOTC_PREVIOUS_OSTREE_COMMIT", previous),
+                        ("BOOTC_IMAGE", image.as_str()),
+                    ]
+                    .into_iter(),
+                );
+            }
+        }
+    }
+    root.create_dir_all("var/lib/bootc")?;
+    root.atomic_write(LAST_BOOTED, checksum.as_str())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn test_message_ids() {
+        let mut ids = std::collections::HashSet::new();
+        for event in Event::value_variants() {
+            let id = event.message_id();
+            assert_eq!(id.len(), 32, "{event:?}");
+            assert!(id.chars().all(|c| c.is_ascii_hexdigit()), "{event:?}");
+            assert!(ids.insert(id), "duplicate {event:?}");
+        }
+        assert_eq!(
+            journalctl_matches(&[Event::UpdateStaged, Event::Rollback]),
+            [
+                "MESSAGE_ID=9f2c4e6a8b1d4c3e7a5f0b2d4e6c8a1f",
+                "MESSAGE_ID=26f3b1eb24464d12aa5e7b544a6b5468"
+            ]
+        );
+        assert_eq!(journalctl_matches(&[]).len(), ids.len());
+    }
+
+    #[test]
+    fn test_boot_events() {
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1697311335);
+        let later = t + Duration::from_secs(60);
+        // First boot
+        assert_eq!(boot_events("a", None, None), []);
+        assert_eq!(boot_events("a", Some(("a", t)), None), []);
+        assert_eq!(
+            boot_events("b", Some(("a", t)), None),
+            [Event::UpdateApplied]
+        );
+        // Finalization failed at the last shutdown
+        assert_eq!(
+            boot_events("a", Some(("a", t)), Some(later)),
+            [Event::FinalizeFailed]
+        );
+        assert_eq!(boot_events("a", None, Some(later)), [Event::FinalizeFailed]);
+        // ...and was already logged on the boot after it
+        assert_eq!(boot_events("a", Some(("a", later)), Some(t)), []);
+    }
+}
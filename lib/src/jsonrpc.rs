@@ -0,0 +1,285 @@
+//! # JSON-RPC on standard input and output
+//!
+//! `bootc internals api` reads [JSON-RPC 2.0](https://www.jsonrpc.org/specification)
+//! requests from standard input and writes the responses to standard output,
+//! one per line.  It is meant for embedding bootc in e.g. installers and
+//! provisioning agents, where there is no system bus or `bootc service` to
+//! talk to.
+//!
+//! The methods `status`, `upgrade` and `switch` take the same parameters as
+//! the corresponding varlink methods (see [`crate::service`]) and are
+//! implemented the same way.  While `upgrade` and `switch` run, each progress
+//! event of `--progress-fd` is sent as a `progress` notification, with the
+//! `id` of the request and the `event`.  Requests are handled one at a time.
+//! With the global `--read-only` option, `upgrade` and `switch` are rejected.
+
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::service::{InvalidCall, Method};
+
+/// The request is not valid JSON.
+const PARSE_ERROR: i64 = -32700;
+/// The request is not a valid request object.
+const INVALID_REQUEST: i64 = -32600;
+/// There is no such method.
+const METHOD_NOT_FOUND: i64 = -32601;
+/// The parameters are not valid.
+const INVALID_PARAMS: i64 = -32602;
+/// The operation failed.
+const FAILED: i64 = -32000;
+/// The method would change the host, but `--read-only` is in effect.
+const READ_ONLY: i64 = -32001;
+
+/// A request; without an `id`, it is a notification, which is not answered.
+#[derive(Debug, Deserialize)]
+struct Request {
+    jsonrpc: String,
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Option<Value>,
+}
+
+/// An error response: the code and message.
+type Error = (i64, String);
+
+/// Serialize a message, terminated by a newline.
+fn encode_line(message: &Value) -> Result<Vec<u8>> {
+    let mut buf = serde_json::to_vec(message)?;
+    buf.push(b'\n');
+    Ok(buf)
+}
+
+/// Write the response to the request with the given `id`.
+async fn write_response(
+    w: &mut (impl AsyncWrite + Unpin),
+    id: Value,
+    r: std::result::Result<Value, Error>,
+) -> Result<()> {
+    let response = match r {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": code, "message": message },
+        }),
+    };
+    w.write_all(&encode_line(&response)?).await?;
+    w.flush().await?;
+    Ok(())
+}
+
+/// The name of the varlink method implementing a JSON-RPC method.
+fn method_name(method: &str) -> Option<&'static str> {
+    match method {
+        "status" => Some("GetStatus"),
+        "upgrade" => Some("Upgrade"),
+        "switch" => Some("Switch"),
+        _ => None,
+    }
+}
+
+/// Parse a request into the method to run.
+fn parse(request: &Request) -> std::result::Result<Method, Error> {
+    let not_found = || {
+        (
+            METHOD_NOT_FOUND,
+            format!("Method not found: {}", request.method),
+        )
+    };
+    let name = method_name(&request.method).ok_or_else(not_found)?;
+    let params = match &request.params {
+        None => Map::new(),
+        Some(Value::Object(params)) => params.clone(),
+        Some(_) => {
+            return Err((
+                INVALID_PARAMS,
+                "Parameters must be given by name".to_owned(),
+            ))
+        }
+    };
+    Method::new(name, &params).map_err(|e| match e {
+        InvalidCall::MethodNotFound => not_found(),
+        InvalidCall::InvalidParameter(message) => (INVALID_PARAMS, message),
+    })
+}
+
+/// Run a request, sending progress notifications to `w`.
+async fn call(
+    request: &Request,
+    read_only: bool,
+    w: &mut (impl AsyncWrite + Unpin),
+) -> std::result::Result<Value, Error> {
+    let method = parse(request)?;
+    tracing::debug!("Call: {method:?}");
+    if read_only && method.is_mutating() {
+        tracing::warn!("Rejected mutating call in read-only mode: {method:?}");
+        return Err((
+            READ_ONLY,
+            "This operation is disabled in read-only mode".to_owned(),
+        ));
+    }
+    let id = request.id.clone().unwrap_or_default();
+    let encode = |event: Value| {
+        encode_line(&json!({
+            "jsonrpc": "2.0",
+            "method": "progress",
+            "params": { "id": id, "event": event },
+        }))
+    };
+    let failed = |e: anyhow::Error| (FAILED, format!("{e:#}"));
+    let out = crate::service::run_bootc(&method.args(), read_only, Some(&mut *w), encode)
+        .await
+        .map_err(failed)?;
+    match method {
+        Method::GetStatus => serde_json::from_str::<Value>(&out).map_err(|e| failed(e.into())),
+        _ => Ok(json!({})),
+    }
+}
+
+/// Handle a line of input, writing the response (if any).
+async fn handle_line(line: &str, read_only: bool, w: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
+    let request = match serde_json::from_str::<Value>(line) {
+        Ok(v) => v,
+        Err(e) => return write_response(w, Value::Null, Err((PARSE_ERROR, e.to_string()))).await,
+    };
+    let request = match serde_json::from_value::<Request>(request) {
+        Ok(r) if r.jsonrpc == "2.0" => r,
+        Ok(_) => {
+            let e = (INVALID_REQUEST, "Unsupported jsonrpc version".to_owned());
+            return write_response(w, Value::Null, Err(e)).await;
+        }
+        Err(e) => {
+            return write_response(w, Value::Null, Err((INVALID_REQUEST, e.to_string()))).await
+        }
+    };
+    let r = call(&request, read_only, w).await;
+    match request.id {
+        Some(id) => write_response(w, id, r).await,
+        None => Ok(()),
+    }
+}
+
+/// Implementation of `bootc internals api`: handle requests on standard input
+/// until it is closed.  With `read_only`, the methods which would change the
+/// host are rejected.
+pub(crate) async fn run(read_only: bool) -> Result<()> {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdout = tokio::io::stdout();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        handle_line(&line, read_only, &mut stdout).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Handle a request, returning the output.
+    fn handle(line: &str) -> Result<Vec<Value>> {
+        handle_with(line, false)
+    }
+
+    fn handle_with(line: &str, read_only: bool) -> Result<Vec<Value>> {
+        let mut out = Vec::new();
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(handle_line(line, read_only, &mut out))?;
+        let out = String::from_utf8(out)?;
+        out.lines().map(|l| Ok(serde_json::from_str(l)?)).collect()
+    }
+
+    fn error_code(response: &Value) -> Option<i64> {
+        response["error"]["code"].as_i64()
+    }
+
+    #[test]
+    fn test_parse() {
+        let request = |method: &str, params: Value| Request {
+            jsonrpc: "2.0".to_owned(),
+            id: Some(json!(1)),
+            method: method.to_owned(),
+            params: Some(params),
+        };
+        assert_eq!(
+            parse(&request("status", json!({}))).unwrap(),
+            Method::GetStatus
+        );
+        assert_eq!(
+            parse(&request("upgrade", json!({ "apply": true }))).unwrap(),
+            Method::Upgrade { apply: true }
+        );
+        assert_eq!(
+            parse(&request(
+                "switch",
+                json!({ "image": "quay.io/example/os:latest" })
+            ))
+            .unwrap(),
+            Method::Switch {
+                image: "quay.io/example/os:latest".to_owned(),
+                transport: None,
+                apply: false
+            }
+        );
+        for (method, params, code) in [
+            ("switch", json!({}), INVALID_PARAMS),
+            ("upgrade", json!([true]), INVALID_PARAMS),
+            ("upgrade", json!({ "bogus": 1 }), INVALID_PARAMS),
+            ("rollback", json!({}), METHOD_NOT_FOUND),
+            ("GetStatus", json!({}), METHOD_NOT_FOUND),
+        ] {
+            assert_eq!(parse(&request(method, params)).unwrap_err().0, code);
+        }
+    }
+
+    #[test]
+    fn test_handle_line() -> Result<()> {
+        let out = handle("{")?;
+        assert_eq!(out.len(), 1);
+        assert_eq!(error_code(&out[0]), Some(PARSE_ERROR));
+        assert_eq!(out[0]["id"], Value::Null);
+
+        let out = handle(r#"{"jsonrpc":"1.0","id":1,"method":"status"}"#)?;
+        assert_eq!(error_code(&out[0]), Some(INVALID_REQUEST));
+        let out = handle(r#"{"jsonrpc":"2.0","id":1}"#)?;
+        assert_eq!(error_code(&out[0]), Some(INVALID_REQUEST));
+
+        let out = handle(r#"{"jsonrpc":"2.0","id":"a","method":"frobnicate"}"#)?;
+        assert_eq!(
+            out,
+            [json!({
+                "jsonrpc": "2.0",
+                "id": "a",
+                "error": { "code": METHOD_NOT_FOUND, "message": "Method not found: frobnicate" },
+            })]
+        );
+        // Notifications are not answered, even on errors
+        assert!(handle(r#"{"jsonrpc":"2.0","method":"frobnicate"}"#)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_only() -> Result<()> {
+        for line in [
+            r#"{"jsonrpc":"2.0","id":1,"method":"upgrade"}"#,
+            r#"{"jsonrpc":"2.0","id":1,"method":"switch","params":{"image":"quay.io/example/os"}}"#,
+        ] {
+            let out = handle_with(line, true)?;
+            assert_eq!(out.len(), 1);
+            assert_eq!(error_code(&out[0]), Some(READ_ONLY));
+        }
+        // Invalid requests are still reported as such
+        let out = handle_with(r#"{"jsonrpc":"2.0","id":1,"method":"frobnicate"}"#, true)?;
+        assert_eq!(error_code(&out[0]), Some(METHOD_NOT_FOUND));
+        Ok(())
+    }
+}
@@ -6,24 +6,33 @@ use std::collections::BTreeMap;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+/// The fields common to all API objects.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Resource {
+    /// The versioned schema of the object, e.g. `org.containers.bootc/v1`
     pub api_version: String,
+    /// The type of the object, e.g. `BootcHost`
     pub kind: String,
+    /// Metadata
     #[serde(default)]
     pub metadata: ObjectMeta,
 }
 
+/// Metadata of an object.
 #[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ObjectMeta {
+    /// Arbitrary non-identifying metadata
     #[serde(skip_serializing_if = "Option::is_none")]
     pub annotations: Option<BTreeMap<String, String>>,
+    /// Labels for organizing and selecting objects
     #[serde(skip_serializing_if = "Option::is_none")]
     pub labels: Option<BTreeMap<String, String>>,
+    /// The name of the object
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    /// The namespace of the object
     #[serde(skip_serializing_if = "Option::is_none")]
     pub namespace: Option<String>,
 }
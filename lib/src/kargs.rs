@@ -117,45 +117,82 @@ pub(crate) fn get_kargs(
     let merge_root = &crate::utils::deployment_fd(sysroot, merge_deployment)?;
     let existing_kargs = get_kargs_in_root(merge_root, sys_arch)?;
 
-    // Get the kargs in kargs.d of the pending image
+    // Get the kargs in kargs.d of the pending image; if it has no kargs.d directory,
+    // all the kargs of the merge deployment's kargs.d are removed.
     let (fetched_tree, _) = repo.read_commit(fetched.ostree_commit.as_str(), cancellable)?;
     let fetched_tree = fetched_tree.resolve_relative_path("/usr/lib/bootc/kargs.d");
     let fetched_tree = fetched_tree
         .downcast::<ostree::RepoFile>()
         .expect("downcast");
-    // A special case: if there's no kargs.d directory in the pending (fetched) image,
-    // then we can just use the combined current kargs + kargs from booted
-    if !fetched_tree.query_exists(cancellable) {
-        kargs.extend(existing_kargs);
-        return Ok(kargs);
-    }
+    let remote_kargs = if fetched_tree.query_exists(cancellable) {
+        get_kargs_from_ostree(repo, &fetched_tree, sys_arch)?
+    } else {
+        Vec::new()
+    };
 
-    // Fetch the kernel arguments from the new root
-    let remote_kargs = get_kargs_from_ostree(repo, &fetched_tree, sys_arch)?;
+    apply_kargs_diff(&mut kargs, &existing_kargs, &remote_kargs);
+    merge_rootflags(&mut kargs);
 
-    // get the diff between the existing and remote kargs
-    let mut added_kargs = remote_kargs
-        .clone()
-        .into_iter()
-        .filter(|item| !existing_kargs.contains(item))
+    Ok(kargs)
+}
+
+/// Apply the difference between the kargs.d arguments of the merge deployment
+/// (`existing`) and those of the new image (`remote`) to the kernel arguments.
+/// Arguments added by the machine, e.g. via `bootc kargs`, are kept unless the
+/// image removes the same argument.
+fn apply_kargs_diff(kargs: &mut Vec<String>, existing: &[String], remote: &[String]) {
+    let added = remote
+        .iter()
+        .filter(|item| !existing.contains(item))
         .collect::<Vec<_>>();
-    let removed_kargs = existing_kargs
-        .clone()
-        .into_iter()
-        .filter(|item| !remote_kargs.contains(item))
+    let removed = existing
+        .iter()
+        .filter(|item| !remote.contains(item))
         .collect::<Vec<_>>();
 
-    tracing::debug!(
-        "kargs: added={:?} removed={:?}",
-        &added_kargs,
-        removed_kargs
-    );
+    tracing::debug!("kargs: added={added:?} removed={removed:?}");
 
-    // apply the diff to the system kargs
-    kargs.retain(|x| !removed_kargs.contains(x));
-    kargs.append(&mut added_kargs);
+    kargs.retain(|x| !removed.contains(&x));
+    for k in added {
+        if !kargs.contains(k) {
+            kargs.push(k.clone());
+        }
+    }
+}
 
-    Ok(kargs)
+/// The kernel only uses the last `rootflags=`, so e.g. an image adding
+/// `rootflags=compress=zstd` via kargs.d would drop the `subvol=` of a root
+/// filesystem in a btrfs subvolume.  Merge all of them into the first one.
+pub(crate) fn merge_rootflags(kargs: &mut Vec<String>) {
+    const ROOTFLAGS: &str = "rootflags=";
+    let mut flags: Vec<String> = Vec::new();
+    let mut first = None;
+    let mut i = 0;
+    kargs.retain(|k| {
+        let keep = match k.strip_prefix(ROOTFLAGS) {
+            Some(v) => {
+                for flag in v.split(',').filter(|f| !f.is_empty()) {
+                    if !flags.iter().any(|f| f == flag) {
+                        flags.push(flag.to_owned());
+                    }
+                }
+                // Only keep the first one, which is replaced below
+                let is_first = first.is_none();
+                if is_first {
+                    first = Some(i);
+                }
+                is_first
+            }
+            None => true,
+        };
+        if keep {
+            i += 1;
+        }
+        keep
+    });
+    if let Some(first) = first {
+        kargs[first] = format!("{ROOTFLAGS}{}", flags.join(","));
+    }
 }
 
 /// This parses a bootc kargs.d toml file, returning the resulting
@@ -173,6 +210,158 @@ fn parse_kargs_toml(contents: &str, sys_arch: &str) -> Result<Vec<String>> {
     Ok(r)
 }
 
+/// A change to the kernel arguments via `bootc kargs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum KargsEdit {
+    /// Add the arguments, unless already present
+    Append,
+    /// Remove arguments given as `KEY=VALUE`, or all values of those given as `KEY`
+    Delete,
+    /// Replace all values of the arguments given as `KEY=VALUE`
+    Replace,
+}
+
+/// The key of a kernel argument, i.e. the part before the first `=`.
+fn karg_key(karg: &str) -> &str {
+    karg.split_once('=').map_or(karg, |(k, _)| k)
+}
+
+/// Apply an edit with the given arguments to the kernel arguments of a deployment,
+/// and to the subset of them added via `spec.kargs`.  Returns true if anything changed.
+pub(crate) fn edit_kargs(
+    kargs: &mut Vec<String>,
+    local: &mut Vec<String>,
+    edit: KargsEdit,
+    args: &[String],
+) -> Result<bool> {
+    let (previous_kargs, previous_local) = (kargs.clone(), local.clone());
+    for arg in args {
+        let key = karg_key(arg);
+        if key.is_empty() || arg.contains(char::is_whitespace) {
+            anyhow::bail!("Invalid kernel argument: {arg:?}");
+        }
+        if key == "ostree" {
+            anyhow::bail!("The ostree= kernel argument cannot be changed");
+        }
+        match edit {
+            KargsEdit::Append => {
+                if !kargs.contains(arg) {
+                    kargs.push(arg.clone());
+                }
+                if !local.contains(arg) {
+                    local.push(arg.clone());
+                }
+            }
+            KargsEdit::Delete => {
+                let matches = |k: &String| {
+                    if arg.contains('=') {
+                        k == arg
+                    } else {
+                        karg_key(k) == arg
+                    }
+                };
+                if !kargs.iter().any(matches) {
+                    anyhow::bail!("Kernel argument {arg} not found");
+                }
+                kargs.retain(|k| !matches(k));
+                local.retain(|k| !matches(k));
+            }
+            KargsEdit::Replace => {
+                if !arg.contains('=') {
+                    anyhow::bail!("Expected KEY=VALUE, not {arg}");
+                }
+                let Some(pos) = kargs.iter().position(|k| karg_key(k) == key) else {
+                    anyhow::bail!("Kernel argument {key} not found");
+                };
+                // Keep the position of the first value
+                kargs.retain(|k| karg_key(k) != key);
+                kargs.insert(pos, arg.clone());
+                local.retain(|k| karg_key(k) != key);
+                local.push(arg.clone());
+            }
+        }
+    }
+    Ok(*kargs != previous_kargs || *local != previous_local)
+}
+
+/// Kernel arguments added by the bootloader rather than taken from the boot entry.
+const BOOTLOADER_KARGS: &[&str] = &["BOOT_IMAGE", "initrd", "ostree"];
+
+/// A difference between the kernel arguments a deployment is expected to boot with
+/// and those it has, see [`kargs_drift`].
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum KargsDrift {
+    /// Declared in kargs.d of the image, but not in the boot entry
+    MissingImage(String),
+    /// Added via `spec.kargs`, but not in the boot entry
+    MissingLocal(String),
+    /// In the boot entry, but not used by the running kernel
+    NotBooted(String),
+    /// Used by the running kernel, but not in the boot entry
+    Unexpected(String),
+}
+
+impl std::fmt::Display for KargsDrift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KargsDrift::MissingImage(k) => {
+                write!(f, "{k}: declared by the image, but not in the boot entry")
+            }
+            KargsDrift::MissingLocal(k) => {
+                write!(f, "{k}: added locally, but not in the boot entry")
+            }
+            KargsDrift::NotBooted(k) => {
+                write!(
+                    f,
+                    "{k}: in the boot entry, but not used by the running kernel"
+                )
+            }
+            KargsDrift::Unexpected(k) => {
+                write!(
+                    f,
+                    "{k}: used by the running kernel, but not in the boot entry"
+                )
+            }
+        }
+    }
+}
+
+/// Compare the kernel arguments declared by the image (`image`), added locally
+/// (`local`), of the boot entry (`entry`) and of the running kernel (`cmdline`).
+pub(crate) fn kargs_drift(
+    image: &[String],
+    local: &[String],
+    entry: &[String],
+    cmdline: &str,
+) -> Vec<KargsDrift> {
+    let cmdline = cmdline
+        .split_ascii_whitespace()
+        .filter(|k| !BOOTLOADER_KARGS.contains(&karg_key(k)))
+        .collect::<Vec<_>>();
+    let missing = |k: &&String| !entry.contains(k);
+    let image = image
+        .iter()
+        .filter(missing)
+        .map(|k| KargsDrift::MissingImage(k.clone()));
+    let local = local
+        .iter()
+        .filter(missing)
+        .map(|k| KargsDrift::MissingLocal(k.clone()));
+    let not_booted = entry
+        .iter()
+        .filter(|k| !cmdline.contains(&k.as_str()))
+        .map(|k| KargsDrift::NotBooted(k.clone()));
+    let unexpected = cmdline
+        .iter()
+        .filter(|k| !entry.iter().any(|e| e == *k))
+        .map(|k| KargsDrift::Unexpected(k.to_string()));
+    image
+        .chain(local)
+        .chain(not_booted)
+        .chain(unexpected)
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use cap_std_ext::cap_std;
@@ -181,6 +370,35 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_merge_rootflags() {
+        let mut kargs = ["root=UUID=abc", "rootflags=subvol=root", "rw"]
+            .map(ToOwned::to_owned)
+            .to_vec();
+        merge_rootflags(&mut kargs);
+        assert_eq!(kargs, ["root=UUID=abc", "rootflags=subvol=root", "rw"]);
+
+        let mut kargs = [
+            "root=UUID=abc",
+            "rootflags=subvol=root",
+            "rw",
+            "rootflags=compress=zstd,subvol=root",
+            "quiet",
+        ]
+        .map(ToOwned::to_owned)
+        .to_vec();
+        merge_rootflags(&mut kargs);
+        assert_eq!(
+            kargs,
+            [
+                "root=UUID=abc",
+                "rootflags=subvol=root,compress=zstd",
+                "rw",
+                "quiet"
+            ]
+        );
+    }
+
     #[test]
     /// Verify that kargs are only applied to supported architectures
     fn test_arch() {
@@ -336,4 +554,156 @@ match-architectures = ["x86_64", "aarch64"]
 
         Ok(())
     }
+
+    #[test]
+    fn test_apply_kargs_diff() {
+        let strings = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let base = strings(&["root=UUID=abc", "rw", "console=ttyS0", "nosmt", "quiet"]);
+        let existing = strings(&["console=ttyS0", "nosmt"]);
+
+        // Unchanged kargs.d
+        let mut kargs = base.clone();
+        apply_kargs_diff(&mut kargs, &existing, &existing);
+        assert_eq!(kargs, base);
+
+        // An argument is replaced by another, and one the machine already has is added
+        let mut kargs = base.clone();
+        let remote = strings(&["console=ttyS1", "nosmt", "quiet"]);
+        apply_kargs_diff(&mut kargs, &existing, &remote);
+        assert_eq!(
+            kargs,
+            ["root=UUID=abc", "rw", "nosmt", "quiet", "console=ttyS1"]
+        );
+
+        // The new image has no kargs.d
+        let mut kargs = base.clone();
+        apply_kargs_diff(&mut kargs, &existing, &[]);
+        assert_eq!(kargs, ["root=UUID=abc", "rw", "quiet"]);
+    }
+
+    #[test]
+    fn test_kargs_drift() {
+        let strings = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let image = strings(&["console=ttyS0", "nosmt"]);
+        let local = strings(&["quiet"]);
+        let entry = strings(&["root=UUID=abc", "rw", "console=ttyS0", "nosmt", "quiet"]);
+        let cmdline = "BOOT_IMAGE=(hd0,gpt3)/vmlinuz root=UUID=abc rw console=ttyS0 nosmt quiet ostree=/ostree/boot.1/default/abc/0\n";
+        assert!(kargs_drift(&image, &local, &entry, cmdline).is_empty());
+
+        let image = strings(&["console=ttyS0", "nosmt", "mitigations=off"]);
+        let local = strings(&["quiet", "debug"]);
+        let cmdline = "initrd=/initrd root=UUID=abc rw console=ttyS0 nosmt single";
+        assert_eq!(
+            kargs_drift(&image, &local, &entry, cmdline),
+            [
+                KargsDrift::MissingImage("mitigations=off".into()),
+                KargsDrift::MissingLocal("debug".into()),
+                KargsDrift::NotBooted("quiet".into()),
+                KargsDrift::Unexpected("single".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_edit_kargs() {
+        let strings = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let mut kargs = strings(&[
+            "root=UUID=abc",
+            "rw",
+            "console=tty0",
+            "console=ttyS0",
+            "quiet",
+        ]);
+        let mut local = strings(&["console=ttyS0"]);
+
+        // Appending what is already there changes nothing
+        let unchanged = edit_kargs(
+            &mut kargs,
+            &mut local,
+            KargsEdit::Append,
+            &strings(&["console=ttyS0"]),
+        );
+        assert!(!unchanged.unwrap());
+        let mut kargs2 = kargs.clone();
+        let mut local2 = local.clone();
+        assert!(edit_kargs(
+            &mut kargs2,
+            &mut local2,
+            KargsEdit::Append,
+            &strings(&["nosmt"])
+        )
+        .unwrap());
+        assert_eq!(kargs2.last().unwrap(), "nosmt");
+        assert_eq!(local2, ["console=ttyS0", "nosmt"]);
+        assert!(!edit_kargs(
+            &mut kargs2,
+            &mut local2,
+            KargsEdit::Append,
+            &strings(&["nosmt"])
+        )
+        .unwrap());
+
+        // Deleting by key removes all values
+        let mut kargs2 = kargs.clone();
+        let mut local2 = local.clone();
+        assert!(edit_kargs(
+            &mut kargs2,
+            &mut local2,
+            KargsEdit::Delete,
+            &strings(&["console"])
+        )
+        .unwrap());
+        assert_eq!(kargs2, ["root=UUID=abc", "rw", "quiet"]);
+        assert!(local2.is_empty());
+        // And by KEY=VALUE only that value
+        let mut kargs2 = kargs.clone();
+        let mut local2 = local.clone();
+        edit_kargs(
+            &mut kargs2,
+            &mut local2,
+            KargsEdit::Delete,
+            &strings(&["console=tty0"]),
+        )
+        .unwrap();
+        assert_eq!(kargs2, ["root=UUID=abc", "rw", "console=ttyS0", "quiet"]);
+        assert_eq!(local2, ["console=ttyS0"]);
+        assert!(edit_kargs(
+            &mut kargs2,
+            &mut local2,
+            KargsEdit::Delete,
+            &strings(&["nosmt"])
+        )
+        .is_err());
+
+        // Replacing keeps the position of the first value
+        let mut kargs2 = kargs.clone();
+        let mut local2 = local.clone();
+        assert!(edit_kargs(
+            &mut kargs2,
+            &mut local2,
+            KargsEdit::Replace,
+            &strings(&["console=ttyS1,115200"])
+        )
+        .unwrap());
+        assert_eq!(
+            kargs2,
+            ["root=UUID=abc", "rw", "console=ttyS1,115200", "quiet"]
+        );
+        assert_eq!(local2, ["console=ttyS1,115200"]);
+        for (edit, invalid) in [
+            (KargsEdit::Replace, "console"),
+            (KargsEdit::Replace, "nosmt=1"),
+            (KargsEdit::Append, "ostree=/ostree/boot.1"),
+            (KargsEdit::Append, "a b"),
+            (KargsEdit::Append, "=foo"),
+        ] {
+            let r = edit_kargs(
+                &mut kargs.clone(),
+                &mut local.clone(),
+                edit,
+                &strings(&[invalid]),
+            );
+            assert!(r.is_err(), "{invalid}");
+        }
+    }
 }
@@ -4,22 +4,50 @@
 //! to provide a fully "container native" tool for using
 //! bootable container images.
 
+pub mod api;
+mod bootcount;
 mod boundimage;
 pub mod cli;
+mod commit;
+mod config;
+mod contentmeta;
 pub(crate) mod deploy;
+mod edit;
+mod firstboot;
 pub(crate) mod generator;
+mod graph;
+mod health;
+mod hold;
+mod hooks;
 mod image;
 pub(crate) mod journal;
+mod jsonrpc;
 pub(crate) mod kargs;
 mod lints;
+mod lock;
 mod lsm;
 pub(crate) mod metadata;
+mod migrate;
+mod network;
+mod notify;
+mod oneshot;
 mod reboot;
 mod reexec;
+mod reinstall;
+mod render;
+mod rescue;
+mod rollout;
+mod service;
+mod shutdown;
+mod signature;
 mod status;
 mod store;
+mod systemd_boot;
 mod task;
+mod testing;
+mod transaction;
 mod utils;
+mod wake;
 
 #[cfg(feature = "install")]
 mod blockdev;
@@ -35,6 +63,8 @@ mod kernel;
 #[cfg(feature = "install")]
 pub(crate) mod mount;
 mod podman;
+mod policy;
+mod progress_jsonl;
 pub mod spec;
 
 #[cfg(feature = "docgen")]
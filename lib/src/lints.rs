@@ -1,49 +1,524 @@
 //! # Implementation of container build lints
 //!
-//! This module implements `bootc container lint`.
+//! This module implements `bootc container lint`.  Each lint checks the root
+//! filesystem of the container for one problem, which is reported as a finding
+//! with the severity of the lint: errors fail the build, warnings are only shown.
+//!
+//! The TOML files in `/usr/lib/bootc/lint.d` of the image can change the
+//! severity of lints, and waive them with a justification, e.g.:
+//!
+//! ```toml
+//! [lints.var-content]
+//! waive = "The cache is removed by systemd-tmpfiles on boot"
+//! [lints.sysusers]
+//! severity = "error"
+//! ```
 
+use std::collections::BTreeMap;
 use std::env::consts::ARCH;
+use std::io::Write;
+use std::os::fd::AsFd;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
 use cap_std::fs::Dir;
 use cap_std_ext::cap_std;
 use cap_std_ext::dirext::CapStdExtDirExt as _;
 use fn_error_context::context;
+use ostree_ext::{gio, glib, ostree};
+use serde::{Deserialize, Serialize};
+
+/// The ostree configuration read by the initramfs.
+const PREPARE_ROOT_CONF: &str = "usr/lib/ostree/prepare-root.conf";
+/// The default public key for signed composefs images.
+const COMPOSEFS_DEFAULT_KEY: &str = "/etc/ostree/initramfs-root-binding.key";
+/// The ostree repository of the container, holding the commit of the base image.
+const CONTAINER_REPO: &str = "sysroot/ostree/repo";
+/// The configuration of the lints, in TOML files.
+const LINT_CONFIG_DIR: &str = "usr/lib/bootc/lint.d";
+/// The number of paths shown in a finding.
+const MAX_PATHS: usize = 5;
+
+/// The severity of a finding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Severity {
+    /// The image works, but likely not as intended
+    Warning,
+    /// The image does not work
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => f.write_str("warning"),
+            Severity::Error => f.write_str("error"),
+        }
+    }
+}
+
+/// The outcome of a lint: `Ok(Err(message))` if it found a problem, or an error
+/// if the check itself failed.
+type LintResult = Result<std::result::Result<(), String>>;
+
+fn lint_ok() -> LintResult {
+    Ok(Ok(()))
+}
+
+fn lint_err(msg: impl Into<String>) -> LintResult {
+    Ok(Err(msg.into()))
+}
+
+struct Lint {
+    /// The identifier shown in findings, and used by `--skip` and the configuration
+    name: &'static str,
+    severity: Severity,
+    description: &'static str,
+    f: fn(&Dir) -> LintResult,
+}
+
+const LINTS: &[Lint] = &[
+    Lint {
+        name: "var-run",
+        severity: Severity::Error,
+        description: "/var/run must be a symbolic link to /run",
+        f: check_var_run,
+    },
+    Lint {
+        name: "kernel",
+        severity: Severity::Error,
+        description: "There must be exactly one kernel in /usr/lib/modules",
+        f: check_kernel,
+    },
+    Lint {
+        name: "kargs",
+        severity: Severity::Error,
+        description: "The files in /usr/lib/bootc/kargs.d must be valid",
+        f: check_parse_kargs,
+    },
+    Lint {
+        name: "bootable",
+        severity: Severity::Error,
+        description: "The base image must be bootable (ostree.bootable)",
+        f: check_bootable,
+    },
+    Lint {
+        name: "composefs",
+        severity: Severity::Error,
+        description: "The composefs configuration must be valid and included in the initramfs",
+        f: check_composefs,
+    },
+    Lint {
+        name: "var-content",
+        severity: Severity::Warning,
+        description: "Files in /var are only unpacked at installation time",
+        f: check_var_content,
+    },
+    Lint {
+        name: "sysusers",
+        severity: Severity::Warning,
+        description: "Users in /etc/passwd should be declared via sysusers.d",
+        f: check_sysusers,
+    },
+];
+
+/// The configuration of a lint.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct LintConfig {
+    /// Replaces the built-in severity
+    pub(crate) severity: Option<Severity>,
+    /// Waive the findings of the lint, with this justification
+    pub(crate) waive: Option<String>,
+}
+
+/// A file in [`LINT_CONFIG_DIR`].
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct LintConfigFile {
+    #[serde(default)]
+    lints: BTreeMap<String, LintConfig>,
+}
+
+fn find_lint(name: &str) -> Result<&'static Lint> {
+    LINTS
+        .iter()
+        .find(|l| l.name == name)
+        .ok_or_else(|| anyhow::anyhow!("Unknown lint: {name}"))
+}
 
-/// check for the existence of the /var/run directory
-/// if it exists we need to check that it links to /run if not error
-/// if it does not exist error.
+/// Merge the given configuration files in order; later files take precedence.
+fn parse_config<'a>(
+    files: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Result<BTreeMap<&'static str, LintConfig>> {
+    let mut r = BTreeMap::<&'static str, LintConfig>::new();
+    for (name, contents) in files {
+        let file: LintConfigFile =
+            toml::from_str(contents).with_context(|| format!("Parsing {name}"))?;
+        for (lint, config) in file.lints {
+            let lint = find_lint(&lint).with_context(|| format!("Parsing {name}"))?;
+            let existing = r.entry(lint.name).or_default();
+            if config.severity.is_some() {
+                existing.severity = config.severity;
+            }
+            if config.waive.is_some() {
+                existing.waive = config.waive;
+            }
+        }
+    }
+    Ok(r)
+}
+
+/// Load the configuration of the lints from [`LINT_CONFIG_DIR`].
+#[context("Loading lint configuration")]
+fn load_config(root: &Dir) -> Result<BTreeMap<&'static str, LintConfig>> {
+    let Some(d) = root.open_dir_optional(LINT_CONFIG_DIR)? else {
+        return Ok(Default::default());
+    };
+    let mut files = Vec::new();
+    for entry in d.entries()? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if !name.ends_with(".toml") || !entry.file_type()?.is_file() {
+            continue;
+        }
+        files.push((name.to_owned(), std::io::read_to_string(entry.open()?)?));
+    }
+    files.sort();
+    parse_config(files.iter().map(|(n, c)| (n.as_str(), c.as_str())))
+}
+
+/// A problem found by a lint.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Finding {
+    /// The name of the lint
+    pub(crate) lint: &'static str,
+    pub(crate) severity: Severity,
+    pub(crate) message: String,
+    /// The justification, if the lint is waived
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) waived: Option<String>,
+}
+
+/// The result of `bootc container lint`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct LintReport {
+    /// The number of lints run
+    pub(crate) checks: usize,
+    /// The lints not run because of `--skip`
+    pub(crate) skipped: Vec<&'static str>,
+    pub(crate) findings: Vec<Finding>,
+}
+
+impl LintReport {
+    /// The number of findings with the given severity which are not waived.
+    pub(crate) fn count(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|f| f.severity == severity && f.waived.is_none())
+            .count()
+    }
+
+    /// The number of findings failing the build.
+    pub(crate) fn failures(&self, fatal_warnings: bool) -> usize {
+        let warnings = if fatal_warnings {
+            self.count(Severity::Warning)
+        } else {
+            0
+        };
+        self.count(Severity::Error) + warnings
+    }
+
+    pub(crate) fn write_human(&self, mut out: impl Write) -> Result<()> {
+        for finding in &self.findings {
+            if let Some(justification) = finding.waived.as_deref() {
+                writeln!(
+                    out,
+                    "waived: {}: {} (justification: {justification})",
+                    finding.lint, finding.message
+                )?;
+            } else {
+                writeln!(
+                    out,
+                    "{}: {}: {}",
+                    finding.severity, finding.lint, finding.message
+                )?;
+            }
+        }
+        writeln!(out, "Checks passed: {}", self.checks - self.findings.len())?;
+        let warnings = self.count(Severity::Warning);
+        if warnings > 0 {
+            writeln!(out, "Warnings: {warnings}")?;
+        }
+        let waived = self.findings.iter().filter(|f| f.waived.is_some()).count();
+        if waived > 0 {
+            writeln!(out, "Waived: {waived}")?;
+        }
+        if !self.skipped.is_empty() {
+            writeln!(out, "Skipped: {}", self.skipped.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Run all lints but the ones in `skip` against the given root filesystem.
 #[context("Linting")]
-pub(crate) fn lint(root: &Dir) -> Result<()> {
-    let lints = [check_var_run, check_kernel, check_parse_kargs];
-    for lint in lints {
-        lint(&root)?;
+pub(crate) fn lint(root: &Dir, skip: &[String]) -> Result<LintReport> {
+    let skipped = skip
+        .iter()
+        .map(|name| find_lint(name).map(|l| l.name))
+        .collect::<Result<Vec<_>>>()?;
+    let config = load_config(root)?;
+    let mut findings = Vec::new();
+    let mut checks = 0;
+    for lint in LINTS.iter().filter(|l| !skipped.contains(&l.name)) {
+        checks += 1;
+        if let Err(message) = (lint.f)(root)? {
+            let config = config.get(lint.name).cloned().unwrap_or_default();
+            findings.push(Finding {
+                lint: lint.name,
+                severity: config.severity.unwrap_or(lint.severity),
+                message,
+                waived: config.waive,
+            });
+        }
+    }
+    Ok(LintReport {
+        checks,
+        skipped,
+        findings,
+    })
+}
+
+/// Implementation of `bootc container lint --list`.
+pub(crate) fn list(root: &Dir, mut out: impl Write) -> Result<()> {
+    let config = load_config(root)?;
+    for lint in LINTS {
+        let config = config.get(lint.name);
+        let severity = config.and_then(|c| c.severity).unwrap_or(lint.severity);
+        write!(out, "{} ({severity}): {}", lint.name, lint.description)?;
+        if let Some(justification) = config.and_then(|c| c.waive.as_deref()) {
+            write!(out, " [waived: {justification}]")?;
+        }
+        writeln!(out)?;
     }
-    println!("Checks passed: {}", lints.len());
     Ok(())
 }
 
-fn check_var_run(root: &Dir) -> Result<()> {
+/// Format a list of paths for a finding, truncated to [`MAX_PATHS`].
+fn format_paths(paths: &[Utf8PathBuf]) -> String {
+    let mut r = paths
+        .iter()
+        .take(MAX_PATHS)
+        .map(|p| p.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if paths.len() > MAX_PATHS {
+        r.push_str(&format!(" (and {} more)", paths.len() - MAX_PATHS));
+    }
+    r
+}
+
+/// Check that /var/run, if it exists, is a symlink (to /run).
+fn check_var_run(root: &Dir) -> LintResult {
     if let Some(meta) = root.symlink_metadata_optional("var/run")? {
         if !meta.is_symlink() {
-            anyhow::bail!("Not a symlink: var/run");
+            return lint_err("Not a symlink: var/run");
         }
     }
-    Ok(())
+    lint_ok()
 }
 
 /// Validate that we can parse the /usr/lib/bootc/kargs.d files.
-fn check_parse_kargs(root: &Dir) -> Result<()> {
-    let _args = crate::kargs::get_kargs_in_root(root, ARCH)?;
-    Ok(())
+fn check_parse_kargs(root: &Dir) -> LintResult {
+    match crate::kargs::get_kargs_in_root(root, ARCH) {
+        Ok(_) => lint_ok(),
+        Err(e) => lint_err(format!("{e:#}")),
+    }
+}
+
+/// Check that there is exactly one kernel in /usr/lib/modules.
+fn check_kernel(root: &Dir) -> LintResult {
+    match ostree_ext::bootabletree::find_kernel_dir_fs(root) {
+        Ok(Some(result)) => {
+            tracing::debug!("Found kernel: {:?}", result);
+            lint_ok()
+        }
+        Ok(None) => lint_err("No kernel found in /usr/lib/modules"),
+        Err(e) => lint_err(format!("{e:#}")),
+    }
+}
+
+/// Check that the base image is bootable, i.e. its ostree commit has the
+/// `ostree.bootable` metadata which is also exposed as a label.
+fn check_bootable(root: &Dir) -> LintResult {
+    if !root.try_exists(CONTAINER_REPO)? {
+        return lint_ok();
+    }
+    let repo = ostree::Repo::open_at_dir(root.as_fd(), CONTAINER_REPO)?;
+    let commits = repo.list_commit_objects_starting_with(None, gio::Cancellable::NONE)?;
+    // Images not derived from an ostree commit have nothing to check
+    if commits.is_empty() {
+        return lint_ok();
+    }
+    for commit in commits {
+        let commitv = repo.load_commit(commit.as_str())?.0;
+        let commitmeta = glib::VariantDict::new(Some(&commitv.child_value(0)));
+        if commitmeta.lookup::<bool>(*ostree::METADATA_KEY_BOOTABLE)? == Some(true) {
+            return lint_ok();
+        }
+    }
+    lint_err(format!(
+        "The base image is not bootable (missing {})",
+        *ostree::METADATA_KEY_BOOTABLE
+    ))
+}
+
+/// Check the composefs configuration of the initramfs.
+fn check_composefs(root: &Dir) -> LintResult {
+    let Some(conf) = root
+        .open_optional(PREPARE_ROOT_CONF)?
+        .map(std::io::read_to_string)
+        .transpose()?
+    else {
+        return lint_ok();
+    };
+    let conf = match tini::Ini::from_string(&conf) {
+        Ok(c) => c,
+        Err(e) => return lint_err(format!("Parsing {PREPARE_ROOT_CONF}: {e}")),
+    };
+    match conf.get::<String>("composefs", "enabled").as_deref() {
+        None | Some("no" | "false" | "maybe") => return lint_ok(),
+        Some("yes" | "true") => {}
+        Some("signed") => {
+            let keypath = conf
+                .get::<String>("composefs", "keypath")
+                .unwrap_or_else(|| COMPOSEFS_DEFAULT_KEY.to_owned());
+            if !root.try_exists(keypath.trim_start_matches('/'))? {
+                return lint_err(format!(
+                    "composefs is signed, but the public key {keypath} is missing"
+                ));
+            }
+        }
+        Some(o) => return lint_err(format!("Invalid composefs.enabled value: {o}")),
+    }
+    if outdated_initramfs(root)?.is_some() {
+        return lint_err(format!(
+            "{PREPARE_ROOT_CONF} was changed after the initramfs was generated; regenerate it with dracut"
+        ));
+    }
+    lint_ok()
+}
+
+/// The initramfs contains a copy of `prepare-root.conf`, so it must be regenerated
+/// when changing it.  Returns the kernel directory if the configuration is newer
+/// than the initramfs; base images have normalized timestamps.
+pub(crate) fn outdated_initramfs(root: &Dir) -> Result<Option<Utf8PathBuf>> {
+    let Some(conf) = root.symlink_metadata_optional(PREPARE_ROOT_CONF)? else {
+        return Ok(None);
+    };
+    let Ok(Some(kernel_dir)) = ostree_ext::bootabletree::find_kernel_dir_fs(root) else {
+        return Ok(None);
+    };
+    let Some(initramfs) = root.symlink_metadata_optional(kernel_dir.join("initramfs.img"))? else {
+        return Ok(None);
+    };
+    Ok((conf.modified()? > initramfs.modified()?).then_some(kernel_dir))
 }
 
-fn check_kernel(root: &Dir) -> Result<()> {
-    let result = ostree_ext::bootabletree::find_kernel_dir_fs(&root)?;
-    tracing::debug!("Found kernel: {:?}", result);
+/// Collect the paths of everything but directories and symbolic links below `path`.
+fn collect_files(dir: &Dir, path: &Utf8Path, out: &mut Vec<Utf8PathBuf>) -> Result<()> {
+    for entry in dir.entries()? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            out.push(path.join(name.to_string_lossy().as_ref()));
+            continue;
+        };
+        let ty = entry.file_type()?;
+        if ty.is_dir() {
+            collect_files(&entry.open_dir()?, &path.join(name), out)?;
+        } else if !ty.is_symlink() {
+            out.push(path.join(name));
+        }
+    }
     Ok(())
 }
 
+/// Content in /var is only unpacked on the initial installation, and is not
+/// updated afterwards.
+fn check_var_content(root: &Dir) -> LintResult {
+    let Some(var) = root.open_dir_optional("var")? else {
+        return lint_ok();
+    };
+    let mut files = Vec::new();
+    collect_files(&var, Utf8Path::new("/var"), &mut files)?;
+    if files.is_empty() {
+        return lint_ok();
+    }
+    files.sort();
+    lint_err(format!(
+        "Found content in /var, which is only used at installation time and not updated; create it via systemd-tmpfiles or move it to /usr: {}",
+        format_paths(&files)
+    ))
+}
+
+/// The names of the users declared in the given sysusers.d file.
+fn parse_sysusers(contents: &str) -> impl Iterator<Item = &str> {
+    contents.lines().filter_map(|line| {
+        let mut fields = line.split_ascii_whitespace();
+        match fields.next() {
+            Some("u" | "u!") => fields.next(),
+            _ => None,
+        }
+    })
+}
+
+/// Users added to /etc/passwd (e.g. via `useradd`) are not updated on existing
+/// systems; they should be declared via sysusers.d instead.
+fn check_sysusers(root: &Dir) -> LintResult {
+    let Some(passwd) = root
+        .open_optional("etc/passwd")?
+        .map(std::io::read_to_string)
+        .transpose()?
+    else {
+        return lint_ok();
+    };
+    let mut declared = Vec::new();
+    for d in ["usr/lib/sysusers.d", "etc/sysusers.d"] {
+        let Some(d) = root.open_dir_optional(d)? else {
+            continue;
+        };
+        for entry in d.entries()? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let contents = std::io::read_to_string(entry.open()?)?;
+            declared.extend(parse_sysusers(&contents).map(ToOwned::to_owned));
+        }
+    }
+    let undeclared = passwd
+        .lines()
+        .filter_map(|l| l.split_once(':').map(|(name, _)| name))
+        .filter(|&name| name != "root" && !declared.iter().any(|d| d == name))
+        .map(Utf8PathBuf::from)
+        .collect::<Vec<_>>();
+    if undeclared.is_empty() {
+        return lint_ok();
+    }
+    lint_err(format!(
+        "Found users in /etc/passwd which are not declared via sysusers.d: {}",
+        format_paths(&undeclared)
+    ))
+}
+
 #[cfg(test)]
 fn fixture() -> Result<cap_std_ext::cap_tempfile::TempDir> {
     let tempdir = cap_std_ext::cap_tempfile::tempdir(cap_std::ambient_authority())?;
@@ -54,37 +529,160 @@ fn fixture() -> Result<cap_std_ext::cap_tempfile::TempDir> {
 fn test_var_run() -> Result<()> {
     let root = &fixture()?;
     // This one should pass
-    check_var_run(root).unwrap();
+    check_var_run(root)?.unwrap();
     root.create_dir_all("var/run/foo")?;
-    assert!(check_var_run(root).is_err());
+    assert!(check_var_run(root)?.is_err());
     root.remove_dir_all("var/run")?;
     // Now we should pass again
-    check_var_run(root).unwrap();
+    check_var_run(root)?.unwrap();
     Ok(())
 }
 
 #[test]
 fn test_kernel_lint() -> Result<()> {
     let root = &fixture()?;
-    // This one should pass
-    check_kernel(root).unwrap();
+    // No kernel
+    assert!(check_kernel(root)?.is_err());
     root.create_dir_all("usr/lib/modules/5.7.2")?;
     root.write("usr/lib/modules/5.7.2/vmlinuz", "old vmlinuz")?;
     root.create_dir_all("usr/lib/modules/6.3.1")?;
     root.write("usr/lib/modules/6.3.1/vmlinuz", "new vmlinuz")?;
-    assert!(check_kernel(root).is_err());
+    assert!(check_kernel(root)?.is_err());
     root.remove_dir_all("usr/lib/modules/5.7.2")?;
     // Now we should pass again
-    check_kernel(root).unwrap();
+    check_kernel(root)?.unwrap();
     Ok(())
 }
 
 #[test]
 fn test_kargs() -> Result<()> {
     let root = &fixture()?;
-    check_parse_kargs(root).unwrap();
+    check_parse_kargs(root)?.unwrap();
     root.create_dir_all("usr/lib/bootc")?;
     root.write("usr/lib/bootc/kargs.d", "not a directory")?;
-    assert!(check_parse_kargs(root).is_err());
+    assert!(check_parse_kargs(root)?.is_err());
     Ok(())
 }
+
+#[test]
+fn test_bootable() -> Result<()> {
+    let root = &fixture()?;
+    // Nothing to check without a repository
+    check_bootable(root)?.unwrap();
+    Ok(())
+}
+
+#[test]
+fn test_composefs() -> Result<()> {
+    let root = &fixture()?;
+    check_composefs(root)?.unwrap();
+    root.create_dir_all("usr/lib/ostree")?;
+    root.write(PREPARE_ROOT_CONF, "[composefs]\nenabled = yes\n")?;
+    check_composefs(root)?.unwrap();
+    root.write(PREPARE_ROOT_CONF, "[composefs]\nenabled = sure\n")?;
+    assert!(check_composefs(root)?.is_err());
+    root.write(PREPARE_ROOT_CONF, "[composefs]\nenabled = signed\n")?;
+    assert!(check_composefs(root)?.is_err());
+    root.create_dir_all("etc/ostree")?;
+    root.write("etc/ostree/initramfs-root-binding.key", "key")?;
+    check_composefs(root)?.unwrap();
+    Ok(())
+}
+
+#[test]
+fn test_var_content() -> Result<()> {
+    let root = &fixture()?;
+    check_var_content(root)?.unwrap();
+    root.create_dir_all("var/lib/foo")?;
+    root.symlink("../run", "var/run")?;
+    check_var_content(root)?.unwrap();
+    root.create_dir_all("var/log")?;
+    root.write("var/log/dnf.log", "log")?;
+    root.write("var/lib/foo/state", "state")?;
+    let msg = check_var_content(root)?.unwrap_err();
+    assert!(
+        msg.ends_with(": /var/lib/foo/state, /var/log/dnf.log"),
+        "{msg}"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_sysusers() -> Result<()> {
+    let root = &fixture()?;
+    check_sysusers(root)?.unwrap();
+    root.create_dir_all("etc")?;
+    root.write(
+        "etc/passwd",
+        "root:x:0:0:root:/root:/bin/bash\nexample:x:1000:1000::/home/example:/bin/bash\n",
+    )?;
+    let msg = check_sysusers(root)?.unwrap_err();
+    assert!(msg.ends_with(": example"), "{msg}");
+    root.create_dir_all("usr/lib/sysusers.d")?;
+    root.write(
+        "usr/lib/sysusers.d/example.conf",
+        "# Example\nu example 1000 \"Example\" /home/example\n",
+    )?;
+    check_sysusers(root)?.unwrap();
+    Ok(())
+}
+
+#[test]
+fn test_lint_config() -> Result<()> {
+    let config = parse_config([
+        (
+            "10-base.toml",
+            "[lints.var-content]\nwaive = \"Cleaned up on boot\"\n[lints.sysusers]\nseverity = \"error\"\n",
+        ),
+        ("20-local.toml", "[lints.sysusers]\nwaive = \"Legacy users\"\n"),
+    ])?;
+    assert_eq!(
+        config["var-content"].waive.as_deref(),
+        Some("Cleaned up on boot")
+    );
+    assert_eq!(
+        config["sysusers"],
+        LintConfig {
+            severity: Some(Severity::Error),
+            waive: Some("Legacy users".into()),
+        }
+    );
+    assert!(parse_config([("a.toml", "[lints.nope]\nwaive = \"x\"\n")]).is_err());
+    assert!(parse_config([("a.toml", "[lints.kernel]\nseverity = \"fatal\"\n")]).is_err());
+
+    // Findings of waived lints do not fail the build
+    let root = &fixture()?;
+    root.create_dir_all(LINT_CONFIG_DIR)?;
+    root.write(
+        format!("{LINT_CONFIG_DIR}/50-kernel.toml"),
+        "[lints.kernel]\nwaive = \"Kernel added later\"\n[lints.var-content]\nseverity = \"error\"\n",
+    )?;
+    root.create_dir_all("var/lib")?;
+    root.write("var/lib/state", "state")?;
+    let report = lint(root, &["composefs".to_owned()])?;
+    assert_eq!(report.checks, LINTS.len() - 1);
+    assert_eq!(report.skipped, ["composefs"]);
+    let kernel = report.findings.iter().find(|f| f.lint == "kernel").unwrap();
+    assert_eq!(kernel.waived.as_deref(), Some("Kernel added later"));
+    assert_eq!(report.count(Severity::Error), 1);
+    assert_eq!(report.failures(false), 1);
+    assert!(lint(root, &["nope".to_owned()]).is_err());
+    let mut out = Vec::new();
+    list(root, &mut out)?;
+    let out = String::from_utf8(out)?;
+    assert!(out.contains("kernel (error): There must be exactly one kernel in /usr/lib/modules [waived: Kernel added later]\n"));
+    assert!(out.contains("var-content (error): "));
+    Ok(())
+}
+
+#[test]
+fn test_format_paths() {
+    let paths = (0..7)
+        .map(|i| Utf8PathBuf::from(format!("/var/{i}")))
+        .collect::<Vec<_>>();
+    assert_eq!(format_paths(&paths[..2]), "/var/0, /var/1");
+    assert_eq!(
+        format_paths(&paths),
+        "/var/0, /var/1, /var/2, /var/3, /var/4 (and 2 more)"
+    );
+}
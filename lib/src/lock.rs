@@ -0,0 +1,122 @@
+//! # Serializing bootc operations
+//!
+//! `bootc upgrade`, `switch`, `rollback`, `edit` and `apply` hold an
+//! exclusive lock on `/run/bootc/lock` for their duration, and record the
+//! operation and when it started in that file.  A concurrent invocation fails
+//! with an error naming the operation holding the lock, or with
+//! `--lock-wait`, queues behind it.  `bootc status` shows the operation in
+//! progress.
+
+use std::io::Read;
+use std::os::unix::fs::FileExt;
+
+use anyhow::Result;
+use cap_std_ext::cap_std::fs::{Dir, OpenOptions};
+use cap_std_ext::dirext::CapStdExtDirExt;
+use chrono::Utc;
+use fn_error_context::context;
+use rustix::fs::FlockOperation;
+use rustix::io::Errno;
+
+use crate::spec::OperationInProgress;
+
+/// The directory holding the lock, relative to `/run`.
+const LOCK_DIR: &str = "bootc";
+/// The lock file, relative to [`LOCK_DIR`].
+const LOCK_FILE: &str = "lock";
+
+/// Held for the duration of an operation; the lock is released when dropped.
+pub(crate) struct OperationLock {
+    _file: std::fs::File,
+}
+
+/// The operation recorded in the lock file, if it can be parsed.
+fn read_holder(mut f: &std::fs::File) -> Option<OperationInProgress> {
+    let mut buf = String::new();
+    f.read_to_string(&mut buf).ok()?;
+    serde_json::from_str(&buf).ok()
+}
+
+/// Acquire the lock for the given operation.  If another operation holds it,
+/// fail unless `wait` is set, in which case wait for it to be released.
+#[context("Acquiring lock")]
+pub(crate) fn acquire(run: &Dir, operation: &str, wait: bool) -> Result<OperationLock> {
+    run.create_dir_all(LOCK_DIR)?;
+    let f = run
+        .open_dir(LOCK_DIR)?
+        .open_with(
+            LOCK_FILE,
+            OpenOptions::new().read(true).write(true).create(true),
+        )?
+        .into_std();
+    match rustix::fs::flock(&f, FlockOperation::NonBlockingLockExclusive) {
+        Ok(()) => {}
+        Err(Errno::WOULDBLOCK) => {
+            let msg = read_holder(&f)
+                .map(|op| op.to_string())
+                .unwrap_or_else(|| "another transaction in progress".to_owned());
+            if !wait {
+                anyhow::bail!("{msg}; use --lock-wait to wait for it to finish");
+            }
+            println!("Waiting for {msg}");
+            rustix::fs::flock(&f, FlockOperation::LockExclusive)?;
+        }
+        Err(e) => return Err(e.into()),
+    }
+    let holder = OperationInProgress {
+        operation: operation.to_owned(),
+        pid: std::process::id(),
+        started: Utc::now(),
+    };
+    f.set_len(0)?;
+    f.write_all_at(&serde_json::to_vec(&holder)?, 0)?;
+    Ok(OperationLock { _file: f })
+}
+
+/// The operation holding the lock, if any.
+#[context("Querying lock")]
+pub(crate) fn current(run: &Dir) -> Result<Option<OperationInProgress>> {
+    let Some(d) = run.open_dir_optional(LOCK_DIR)? else {
+        return Ok(None);
+    };
+    let Some(f) = d.open_optional(LOCK_FILE)? else {
+        return Ok(None);
+    };
+    let f = f.into_std();
+    match rustix::fs::flock(&f, FlockOperation::NonBlockingLockShared) {
+        // Nothing holds it; our shared lock is released when the file is closed
+        Ok(()) => Ok(None),
+        Err(Errno::WOULDBLOCK) => Ok(read_holder(&f)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cap_std_ext::cap_std;
+
+    use super::*;
+
+    #[test]
+    fn test_lock() -> Result<()> {
+        let td = cap_std_ext::cap_tempfile::TempDir::new(cap_std::ambient_authority())?;
+        assert!(current(&td)?.is_none());
+
+        let lock = acquire(&td, "upgrade", false)?;
+        let op = current(&td)?.unwrap();
+        assert_eq!(op.operation, "upgrade");
+        assert_eq!(op.pid, std::process::id());
+        let e = format!("{:#}", acquire(&td, "switch", false).err().unwrap());
+        assert!(
+            e.contains("another transaction in progress: upgrade started at"),
+            "{e}"
+        );
+        assert!(e.contains("--lock-wait"), "{e}");
+
+        drop(lock);
+        assert!(current(&td)?.is_none());
+        let _lock = acquire(&td, "switch", false)?;
+        assert_eq!(current(&td)?.unwrap().operation, "switch");
+        Ok(())
+    }
+}
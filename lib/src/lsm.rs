@@ -269,6 +269,41 @@ pub(crate) fn ensure_labeled(
     Ok(r)
 }
 
+/// Verify that the target root filesystem supports SELinux labels, and label the root
+/// directory and any top-level entries on the same filesystem which lack one, such as
+/// `lost+found` of a filesystem created without labels.  Returns the number of labeled paths.
+#[cfg(feature = "install")]
+#[context("Verifying SELinux labels of the target root")]
+pub(crate) fn ensure_root_labeled(root: &Dir, policy: &ostree::SePolicy) -> Result<u64> {
+    let mut n = 0u64;
+    let root_meta = root.dir_metadata()?;
+    match ensure_labeled(root, Utf8Path::new(""), &root_meta, policy)? {
+        SELinuxLabelState::Unlabeled => n += 1,
+        SELinuxLabelState::Unsupported => anyhow::bail!(
+            "The target root filesystem does not support SELinux labels; it must support extended attributes (or use --disable-selinux)"
+        ),
+        SELinuxLabelState::Labeled => {}
+    }
+    for ent in root.entries()? {
+        let ent = ent?;
+        let metadata = ent.metadata()?;
+        // Other mounts such as /boot are handled separately
+        if metadata.dev() != root_meta.dev() {
+            continue;
+        }
+        let name = ent.file_name();
+        let name = name
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid non-UTF-8 filename: {name:?}"))?;
+        if let SELinuxLabelState::Unlabeled =
+            ensure_labeled(root, Utf8Path::new(name), &metadata, policy)?
+        {
+            n += 1;
+        }
+    }
+    Ok(n)
+}
+
 /// A wrapper for creating a directory, also optionally setting a SELinux label.
 /// The provided `skip` parameter is a device/inode that we will ignore (and not traverse).
 #[cfg(feature = "install")]
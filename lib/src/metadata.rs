@@ -2,3 +2,10 @@
 pub(crate) const BOOTC_COMPAT_LABEL: &str = "containers.bootc";
 /// The current single well-known value for the label.
 pub(crate) const COMPAT_LABEL_V1: &str = "1";
+/// An image may declare the number of bytes which must remain free in the
+/// repository filesystem after it has been downloaded.
+pub(crate) const MIN_FREE_SPACE_LABEL: &str = "containers.bootc.min-free-space";
+/// An image may declare the bootloader `bootc install` installs by default,
+/// e.g. `systemd-boot`.
+#[cfg(feature = "install")]
+pub(crate) const BOOTLOADER_LABEL: &str = "containers.bootc.bootloader";
@@ -0,0 +1,196 @@
+//! # Migrating from rpm-ostree
+//!
+//! Deployments whose origin records rpm-ostree client-side changes (layered
+//! packages, overrides, modules or initramfs settings) are shown as
+//! `incompatible` by `bootc status`, and cannot be updated by bootc.
+//! `bootc migrate-from-rpm-ostree` reports these changes, and with
+//! `--discard-layering`, stages the container image the booted deployment is
+//! based on (or the one given via `--image`) with an origin holding only the
+//! container image reference.  As for any other update, `/etc` and `/var` are
+//! carried over; the changes made via rpm-ostree are not.
+
+use anyhow::{anyhow, Result};
+use cap_std_ext::cap_std;
+use cap_std_ext::cap_std::fs::Dir;
+use fn_error_context::context;
+use ostree::glib;
+use ostree_ext::ostree;
+
+use crate::deploy::RequiredHostSpec;
+use crate::spec::ImageReference;
+
+/// The rpm-ostree origin keys recording local changes, by group.
+const LOCAL_CHANGE_KEYS: &[(&str, &[&str])] = &[
+    (
+        "packages",
+        &[
+            "requested",
+            "requested-local",
+            "requested-local-fileoverride",
+        ],
+    ),
+    ("overrides", &["remove", "replace", "replace-local"]),
+    ("modules", &["enable", "install"]),
+    (
+        "rpmostree",
+        &[
+            "override-commit",
+            "initramfs-regenerate",
+            "initramfs-args",
+            "initramfs-etc",
+            "ex-cliwrap",
+        ],
+    ),
+];
+
+/// A local change recorded in an rpm-ostree origin.
+#[derive(Debug, PartialEq, Eq)]
+struct LocalChange {
+    /// The origin key, as `group.key`; just the group for unknown keys
+    key: String,
+    /// The values, e.g. package names
+    values: Vec<String>,
+}
+
+impl std::fmt::Display for LocalChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.values.is_empty() {
+            write!(f, "{}: (other settings)", self.key)
+        } else {
+            write!(f, "{}: {}", self.key, self.values.join(", "))
+        }
+    }
+}
+
+/// The local changes recorded in an origin.
+fn local_changes(origin: &glib::KeyFile) -> Vec<LocalChange> {
+    let mut changes = Vec::new();
+    for (group, keys) in LOCAL_CHANGE_KEYS {
+        if !origin.has_group(group) {
+            continue;
+        }
+        let n = changes.len();
+        for key in keys.iter() {
+            let Ok(values) = origin.string_list(group, key) else {
+                continue;
+            };
+            changes.push(LocalChange {
+                key: format!("{group}.{key}"),
+                values: values.iter().map(|v| v.to_string()).collect(),
+            });
+        }
+        if changes.len() == n {
+            changes.push(LocalChange {
+                key: (*group).to_owned(),
+                values: Vec::new(),
+            });
+        }
+    }
+    changes
+}
+
+/// Implementation of `bootc migrate-from-rpm-ostree`: stage `image`, or the
+/// image the booted deployment is based on, without any rpm-ostree changes.
+/// Unless `discard_layering` is set, this fails if there are any.  Returns
+/// whether a deployment was staged.
+#[context("Migrating from rpm-ostree")]
+pub(crate) async fn migrate(
+    image: Option<&ImageReference>,
+    discard_layering: bool,
+    quiet: bool,
+) -> Result<bool> {
+    let sysroot = &crate::cli::get_storage().await?;
+    let (booted, _deployments, host) = crate::status::get_status_require_booted(sysroot)?;
+    let origin = booted
+        .origin()
+        .ok_or_else(|| anyhow!("The booted deployment has no origin"))?;
+    let based_on = crate::status::get_image_origin(&origin)?.map(ImageReference::from);
+    if !crate::utils::origin_has_rpmostree_stuff(&origin) && based_on.is_some() && image.is_none() {
+        println!("The booted deployment is already managed by bootc.");
+        return Ok(false);
+    }
+    let imgref = image.cloned().or(based_on).ok_or_else(|| {
+        anyhow!("The booted deployment is not based on a container image; specify one via --image")
+    })?;
+
+    let changes = local_changes(&origin);
+    if !changes.is_empty() {
+        println!("Local rpm-ostree changes in the booted deployment:");
+        for change in changes.iter() {
+            println!("  {change}");
+        }
+        if !discard_layering {
+            anyhow::bail!("Refusing to discard local changes without --discard-layering");
+        }
+    }
+
+    let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    let policy = crate::policy::PolicyCheck::load(root, &host)?;
+    let fetched = crate::deploy::pull(
+        &sysroot.repo(),
+        &imgref,
+        &crate::deploy::PullOpts {
+            quiet,
+            policy: policy.as_ref(),
+            sysroot: Some(sysroot),
+            ..Default::default()
+        },
+    )
+    .await?;
+    let stateroot = booted.osname();
+    let spec = RequiredHostSpec {
+        image: &imgref,
+        kargs: None,
+    };
+    crate::deploy::stage(sysroot, &stateroot, &fetched, &spec, None).await?;
+    if !changes.is_empty() {
+        println!("  Discarded local rpm-ostree changes");
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_changes() -> Result<()> {
+        let origin = glib::KeyFile::new();
+        origin.load_from_data(
+            indoc::indoc! { r#"
+                [origin]
+                container-image-reference=ostree-unverified-registry:quay.io/fedora/fedora-silverblue:41
+
+                [packages]
+                requested=htop;vim-enhanced;
+
+                [overrides]
+                remove=firefox;firefox-langpacks;
+
+                [rpmostree]
+                unknown=true
+            "#},
+            glib::KeyFileFlags::NONE,
+        )?;
+        let changes = local_changes(&origin)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            [
+                "packages.requested: htop, vim-enhanced",
+                "overrides.remove: firefox, firefox-langpacks",
+                "rpmostree: (other settings)",
+            ]
+        );
+
+        let origin = glib::KeyFile::new();
+        origin.load_from_data(
+            "[origin]\ncontainer-image-reference=ostree-unverified-registry:quay.io/example/os\n",
+            glib::KeyFileFlags::NONE,
+        )?;
+        assert!(local_changes(&origin).is_empty());
+        Ok(())
+    }
+}
@@ -67,6 +67,15 @@ pub(crate) fn mount(dev: &str, target: &Utf8Path) -> Result<()> {
     )
 }
 
+/// Mount a device to the target path with the given options.
+pub(crate) fn mount_with_options(dev: &str, target: &Utf8Path, options: &str) -> Result<()> {
+    Task::new_and_run(
+        format!("Mounting {target}"),
+        "mount",
+        ["-o", options, dev, target.as_str()],
+    )
+}
+
 /// If the fsid of the passed path matches the fsid of the same path rooted
 /// at /proc/1/root, it is assumed that these are indeed the same mounted
 /// filesystem between container and host.
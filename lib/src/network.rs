@@ -0,0 +1,123 @@
+//! # Waiting for the network
+//!
+//! Update units started at boot may run before the network is up; rather than
+//! failing the fetch, `--wait-for-network` first waits (with exponential
+//! backoff) until the registry host resolves and accepts connections.  If it
+//! does not become reachable before the timeout, the fetch is attempted anyway,
+//! as the registry may e.g. only be reachable via a proxy.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use fn_error_context::context;
+
+use crate::spec::ImageReference;
+
+/// The default time to wait for the network.
+pub(crate) const DEFAULT_WAIT_FOR_NETWORK: Duration = Duration::from_secs(300);
+/// The delay before the first retry.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// The maximum delay between retries.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// The maximum duration of a single connection attempt.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The host and port of the registry for an image.
+fn registry_address(image: &str) -> (&str, u16) {
+    let hostport = match image.split_once('/') {
+        Some((host, _)) if host.contains(['.', ':']) || host == "localhost" => host,
+        _ => "docker.io",
+    };
+    match hostport.rsplit_once(':') {
+        Some((host, port)) if !port.ends_with(']') => match port.parse() {
+            Ok(port) => (host.trim_matches(['[', ']']), port),
+            Err(_) => (hostport, 443),
+        },
+        _ => (hostport.trim_matches(['[', ']']), 443),
+    }
+}
+
+/// Check once whether the registry host resolves and accepts connections.
+async fn check_reachable(host: &str, port: u16) -> Result<()> {
+    let addrs = tokio::net::lookup_host((host, port))
+        .await
+        .with_context(|| format!("Resolving {host}"))?;
+    let mut last_err = None;
+    for addr in addrs {
+        match tokio::time::timeout(CONNECT_TIMEOUT, tokio::net::TcpStream::connect(addr)).await {
+            Ok(Ok(_)) => return Ok(()),
+            Ok(Err(e)) => last_err = Some(anyhow::Error::new(e)),
+            Err(_) => last_err = Some(anyhow::anyhow!("Timed out")),
+        }
+    }
+    let e = last_err.unwrap_or_else(|| anyhow::anyhow!("No addresses"));
+    Err(e.context(format!("Connecting to {host}:{port}")))
+}
+
+/// Wait until the registry of the image is reachable, for at most `timeout`.
+/// Images which are not fetched from a registry are not waited for.
+#[context("Waiting for network")]
+pub(crate) async fn wait_for_registry(imgref: &ImageReference, timeout: Duration) -> Result<()> {
+    if imgref.transport != "registry" {
+        return Ok(());
+    }
+    let (host, port) = registry_address(&imgref.image);
+    let deadline = Instant::now() + timeout;
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let e = match check_reachable(host, port).await {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            eprintln!("warning: {e:#}; continuing anyway");
+            return Ok(());
+        }
+        let delay = backoff.min(remaining);
+        println!("Waiting for {host} to be reachable: {e:#}; retrying in {delay:?}");
+        tokio::time::sleep(delay).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_address() {
+        for (image, expected) in [
+            ("quay.io/example/os:latest", ("quay.io", 443)),
+            ("localhost:5000/os", ("localhost", 5000)),
+            ("localhost/os", ("localhost", 443)),
+            ("fedora", ("docker.io", 443)),
+            ("example/os", ("docker.io", 443)),
+            ("[::1]:5000/os", ("::1", 5000)),
+        ] {
+            assert_eq!(registry_address(image), expected, "{image}");
+        }
+    }
+
+    #[test]
+    fn test_wait_for_registry() -> Result<()> {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        rt.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+            let port = listener.local_addr()?.port();
+            let imgref = ImageReference {
+                image: format!("127.0.0.1:{port}/os"),
+                transport: "registry".into(),
+                signature: None,
+            };
+            check_reachable("127.0.0.1", port).await?;
+            wait_for_registry(&imgref, Duration::ZERO).await?;
+            drop(listener);
+            assert!(check_reachable("127.0.0.1", port).await.is_err());
+            // Not waited for beyond the timeout
+            wait_for_registry(&imgref, Duration::ZERO).await
+        })
+    }
+}
@@ -0,0 +1,112 @@
+//! # Service manager notifications
+//!
+//! When bootc runs in a systemd service which accepts notifications (e.g. with
+//! `Type=notify` or `NotifyAccess=main`), the progress of long operations such
+//! as fetching and deploying an image is sent as `STATUS=`, so that
+//! `systemctl status` shows it.  Each progress event also extends the timeout
+//! of the unit via `EXTEND_TIMEOUT_USEC=`, so that e.g. a slow download which
+//! is still making progress does not get the unit killed mid-transaction.
+//! Nothing is sent when not running under systemd.
+
+use std::time::Duration;
+
+use libsystemd::daemon::NotifyState;
+
+use crate::progress_jsonl::Event;
+
+/// How long each progress event extends the timeout of the unit.
+const EXTEND_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Returns true if the service manager accepts notifications from us.
+pub(crate) fn enabled() -> bool {
+    std::env::var_os("NOTIFY_SOCKET").is_some()
+}
+
+/// Send the given state; errors are logged, but otherwise ignored.
+fn notify(state: &[NotifyState]) {
+    if !enabled() {
+        return;
+    }
+    if let Err(e) = libsystemd::daemon::notify(false, state) {
+        tracing::debug!("Failed to notify the service manager: {e}");
+    }
+}
+
+/// Set the status shown by `systemctl status`.
+pub(crate) fn status(msg: &str) {
+    notify(&[NotifyState::Status(msg.to_owned())])
+}
+
+/// The status for a progress event.
+fn status_of(event: &Event) -> String {
+    let bytes = indicatif::HumanBytes;
+    match event {
+        Event::FetchStart { imgref, layers } => format!("Fetching {imgref} ({layers} layers)"),
+        Event::LayerStart { digest, size } => format!("Fetching layer {digest} ({})", bytes(*size)),
+        Event::LayerProgress {
+            digest,
+            fetched,
+            size,
+        } => format!(
+            "Fetching layer {digest}: {}/{}",
+            bytes(*fetched),
+            bytes(*size)
+        ),
+        Event::LayerComplete { digest, .. } => format!("Fetched layer {digest}"),
+        Event::FetchComplete { digest } => format!("Fetched image {digest}"),
+        Event::Phase {
+            name,
+            percent: Some(percent),
+        } => format!("Running phase: {name} ({percent}%)"),
+        Event::Phase { name, .. } => format!("Running phase: {name}"),
+        Event::Plan { .. } => "Planned partitions".to_owned(),
+        Event::InstallComplete { .. } => "Installation complete".to_owned(),
+    }
+}
+
+/// Report progress to the service manager, extending the timeout of the unit.
+pub(crate) fn progress(event: &Event) {
+    let extend = format!("EXTEND_TIMEOUT_USEC={}", EXTEND_TIMEOUT.as_micros());
+    notify(&[
+        NotifyState::Status(status_of(event)),
+        NotifyState::Other(extend),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_of() {
+        assert_eq!(
+            status_of(&Event::FetchStart {
+                imgref: "quay.io/example/os:latest",
+                layers: 3
+            }),
+            "Fetching quay.io/example/os:latest (3 layers)"
+        );
+        assert_eq!(
+            status_of(&Event::LayerProgress {
+                digest: "sha256:abc",
+                fetched: 1024,
+                size: 4096
+            }),
+            "Fetching layer sha256:abc: 1.00 KiB/4.00 KiB"
+        );
+        assert_eq!(
+            status_of(&Event::Phase {
+                name: "deploy",
+                percent: None
+            }),
+            "Running phase: deploy"
+        );
+        assert_eq!(
+            status_of(&Event::Phase {
+                name: "pull",
+                percent: Some(15)
+            }),
+            "Running phase: pull (15%)"
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! # One-shot kernel arguments
+//!
+//! `bootc kargs set-once` boots a deployment a single time with additional kernel
+//! arguments, e.g. `systemd.unit=rescue.target` for remote debugging.  Like the
+//! [rescue entry](crate::rescue), the kernel and initramfs of the deployment are
+//! copied to `/boot/bootc-oneshot`, and a GRUB menu entry booting them with the
+//! kernel arguments of the deployment plus the given ones is written to
+//! `/boot/grub2/custom.cfg`.  The `next_entry` GRUB environment variable selects
+//! the entry for the next boot only, as GRUB clears it when booting; the boot after
+//! that uses the default entry again.  `bootc boot-complete` removes the entry once
+//! it is no longer selected.
+//!
+//! This is only supported with GRUB configurations which source `custom.cfg` and
+//! honor `next_entry` (as generated by `grub2-mkconfig`).
+
+use anyhow::{Context, Result};
+use cap_std_ext::cap_std;
+use cap_std_ext::cap_std::fs::Dir;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use fn_error_context::context;
+use ostree_ext::ostree;
+
+use crate::rescue::GRUB_CUSTOM_CFG;
+use crate::store::Storage;
+
+/// The directory holding the kernel and initramfs, relative to `/boot`.
+const ONESHOT_DIR: &str = "bootc-oneshot";
+/// The GRUB menu entry identifier.
+const ENTRY_ID: &str = "bootc-oneshot";
+/// Delimits the entry in [`GRUB_CUSTOM_CFG`].
+const BEGIN_MARKER: &str = "### BEGIN bootc-oneshot ###";
+/// Delimits the entry in [`GRUB_CUSTOM_CFG`].
+const END_MARKER: &str = "### END bootc-oneshot ###";
+
+/// The kernel arguments for the entry: those of the deployment, with the ones
+/// given as `KEY=VALUE` in `extra` replacing all values of `KEY`.
+fn oneshot_kargs(kargs: &[String], extra: &[String], deployment_path: &str) -> String {
+    let key = |k: &str| k.split_once('=').map(|(k, _)| k.to_owned());
+    let replaced = extra.iter().filter_map(|k| key(k)).collect::<Vec<_>>();
+    let ostree = format!("ostree=/{deployment_path}");
+    kargs
+        .iter()
+        .filter(|k| !key(k).is_some_and(|k| replaced.contains(&k)))
+        .map(String::as_str)
+        .chain(std::iter::once(ostree.as_str()))
+        .chain(extra.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The GRUB menu entry; `prefix` is the path of `/boot` on the filesystem
+/// GRUB reads it from.
+fn grub_entry(prefix: &str, kargs: &str) -> String {
+    format!(
+        "{BEGIN_MARKER}\n\
+menuentry 'bootc one-shot' --id {ENTRY_ID} {{\n\
+\tlinux {prefix}/{ONESHOT_DIR}/vmlinuz {kargs}\n\
+\tinitrd {prefix}/{ONESHOT_DIR}/initramfs.img\n\
+}}\n\
+{END_MARKER}\n"
+    )
+}
+
+/// Boot the deployment once with the given additional kernel arguments.
+#[context("Setting one-shot kernel arguments")]
+pub(crate) fn set(
+    sysroot: &Storage,
+    deployment: &ostree::Deployment,
+    extra: &[String],
+) -> Result<()> {
+    for k in extra {
+        if k.is_empty() || k.contains(char::is_whitespace) {
+            anyhow::bail!("Invalid kernel argument: {k:?}");
+        }
+        if k.starts_with("ostree=") {
+            anyhow::bail!("The ostree= kernel argument cannot be changed");
+        }
+    }
+    let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    let boot = &Dir::open_ambient_dir("/boot", cap_std::ambient_authority())?;
+    if !boot.try_exists(crate::bootcount::GRUBENV)? {
+        anyhow::bail!("Only GRUB is supported");
+    }
+    let ostree: &ostree::Sysroot = sysroot;
+    let deployment_path = ostree.deployment_dirpath(deployment);
+    let deployment_root = &crate::utils::deployment_fd(ostree, deployment)?;
+    let kernel_dir = ostree_ext::bootabletree::find_kernel_dir_fs(deployment_root)?
+        .context("No kernel found in deployment")?;
+
+    // We run in our own mount namespace, so this does not affect the host
+    crate::utils::ensure_writable_mount(boot, "/boot")?;
+    boot.create_dir_all(ONESHOT_DIR)?;
+    for name in ["vmlinuz", "initramfs.img"] {
+        crate::rescue::copy_file(
+            deployment_root,
+            &kernel_dir.join(name),
+            boot,
+            &format!("{ONESHOT_DIR}/{name}"),
+        )?;
+    }
+
+    let prefix = crate::rescue::grub_boot_prefix(root, boot)?;
+    let kargs = oneshot_kargs(
+        &crate::deploy::deployment_kargs(deployment),
+        extra,
+        deployment_path.as_str(),
+    );
+    let existing = boot
+        .open_optional(GRUB_CUSTOM_CFG)?
+        .map(std::io::read_to_string)
+        .transpose()?
+        .unwrap_or_default();
+    let entry = grub_entry(prefix, &kargs);
+    let cfg = crate::rescue::replace_section(&existing, BEGIN_MARKER, END_MARKER, &entry);
+    boot.atomic_write(GRUB_CUSTOM_CFG, cfg)?;
+    let next_entry = format!("next_entry={ENTRY_ID}");
+    crate::bootcount::editenv(["set", &next_entry]).run()?;
+    println!("The next boot uses the kernel arguments: {kargs}");
+    Ok(())
+}
+
+/// Remove the one-shot entry, unless it is selected for the next boot.
+#[context("Removing one-shot boot entry")]
+pub(crate) fn cleanup() -> Result<()> {
+    let boot = &Dir::open_ambient_dir("/boot", cap_std::ambient_authority())?;
+    let Some(existing) = boot
+        .open_optional(GRUB_CUSTOM_CFG)?
+        .map(std::io::read_to_string)
+        .transpose()?
+    else {
+        return Ok(());
+    };
+    if !existing.lines().any(|l| l == BEGIN_MARKER) {
+        return Ok(());
+    }
+    let list = crate::bootcount::editenv(["list"]).read()?;
+    if crate::bootcount::parse_env(&list).get("next_entry") == Some(&ENTRY_ID) {
+        return Ok(());
+    }
+    crate::utils::ensure_writable_mount(boot, "/boot")?;
+    let cfg = crate::rescue::replace_section(&existing, BEGIN_MARKER, END_MARKER, "");
+    boot.atomic_write(GRUB_CUSTOM_CFG, cfg)?;
+    boot.remove_all_optional(ONESHOT_DIR)?;
+    println!("Removed one-shot boot entry");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oneshot_kargs() {
+        let strings = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let kargs = strings(&[
+            "root=UUID=abcd",
+            "rw",
+            "systemd.unit=graphical.target",
+            "quiet",
+        ]);
+        let deployment = "ostree/deploy/default/deploy/0a1b2c.0";
+        assert_eq!(
+            oneshot_kargs(&kargs, &strings(&["systemd.unit=rescue.target", "debug"]), deployment),
+            "root=UUID=abcd rw quiet ostree=/ostree/deploy/default/deploy/0a1b2c.0 systemd.unit=rescue.target debug"
+        );
+        let entry = grub_entry("/boot", "root=UUID=abcd");
+        assert!(entry.contains("--id bootc-oneshot {\n"));
+        assert!(entry.contains("\tlinux /boot/bootc-oneshot/vmlinuz root=UUID=abcd\n"));
+        // The entry is removed again without touching other content
+        let user = "set timeout=10\n";
+        let cfg = crate::rescue::replace_section(user, BEGIN_MARKER, END_MARKER, &entry);
+        assert_eq!(cfg, format!("{user}{entry}"));
+        assert_eq!(
+            crate::rescue::replace_section(&cfg, BEGIN_MARKER, END_MARKER, ""),
+            user
+        );
+    }
+}
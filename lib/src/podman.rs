@@ -40,6 +40,48 @@ pub(crate) fn imageid_to_digest(imgid: &str) -> Result<String> {
     Ok(i.digest)
 }
 
+/// The name under which podman stores an image which is not qualified with a
+/// registry, e.g. `localhost/my-os:dev` for `my-os:dev`; `None` for images of
+/// other registries.
+fn local_image_name(image: &str) -> Option<String> {
+    match image.split_once('/') {
+        Some(("localhost", _)) => Some(image.to_owned()),
+        Some((host, _)) if host.contains(['.', ':']) => None,
+        _ => Some(format!("localhost/{image}")),
+    }
+}
+
+/// Return true if the image exists in the container storage of root.
+pub(crate) fn image_exists(name: &str) -> anyhow::Result<bool> {
+    let status = match std::process::Command::new("podman")
+        .args(["image", "exists", name])
+        .stdin(std::process::Stdio::null())
+        .status()
+    {
+        Ok(status) => status,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(anyhow::Error::new(e).context("Executing podman")),
+    };
+    match status.code() {
+        Some(0) => Ok(true),
+        Some(1) => Ok(false),
+        _ => anyhow::bail!("podman image exists {name} failed: {status}"),
+    }
+}
+
+/// The name under which the image exists in the container storage of root,
+/// trying the name podman uses for an image which is not qualified with a
+/// registry (e.g. `localhost/my-os:dev` after `podman build -t my-os:dev`).
+pub(crate) fn find_local_image(image: &str) -> anyhow::Result<Option<String>> {
+    if image_exists(image)? {
+        return Ok(Some(image.to_owned()));
+    }
+    match local_image_name(image) {
+        Some(name) if name != image && image_exists(&name)? => Ok(Some(name)),
+        _ => Ok(None),
+    }
+}
+
 /// Return true if there is apparently an active container store at the target path.
 #[cfg(feature = "install")]
 pub(crate) fn storage_exists(root: &Dir, path: impl AsRef<Utf8Path>) -> Result<bool> {
@@ -59,3 +101,21 @@ pub(crate) fn storage_exists(root: &Dir, path: impl AsRef<Utf8Path>) -> Result<b
 pub(crate) fn storage_exists_default(root: &Dir) -> Result<bool> {
     storage_exists(root, CONTAINER_STORAGE.trim_start_matches('/'))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_image_name() {
+        for (image, expected) in [
+            ("localhost/my-os:dev", Some("localhost/my-os:dev")),
+            ("my-os:dev", Some("localhost/my-os:dev")),
+            ("example/os", Some("localhost/example/os")),
+            ("quay.io/example/os:latest", None),
+            ("localhost:5000/os", None),
+        ] {
+            assert_eq!(local_image_name(image).as_deref(), expected, "{image}");
+        }
+    }
+}
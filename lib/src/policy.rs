@@ -0,0 +1,445 @@
+//! # Pre-flight policy rules
+//!
+//! Administrators can declare rules in the `[policy]` section of the host
+//! configuration which are evaluated against an update before any layers are
+//! fetched.  Each rule has a condition written in a small expression language;
+//! if it matches, the operation is either denied or requires interactive
+//! confirmation.
+//!
+//! The expression language supports string, integer and boolean literals,
+//! the comparison operators `==`, `!=`, `<`, `<=`, `>`, `>=`, the boolean
+//! operators `&&`, `||` and `!`, and parentheses.  The following variables
+//! are available:
+//!
+//! - `image`: The target image reference
+//! - `digest`: The target manifest digest
+//! - `version`, `current_version`: The version of the target and booted image (or null)
+//! - `layers`: The number of layers which need to be fetched
+//! - `image_size`: The total size of the target image layers, in bytes
+//! - `hour`: The current local hour (0-23)
+//! - `weekday`: The current local day of the week, from 1 (Monday) to 7 (Sunday)
+//!
+//! And the functions:
+//!
+//! - `label("key")`: The value of the given image label (or null)
+//! - `major(version)`: The leading numeric component of a version (or null)
+
+use std::collections::HashMap;
+use std::io::{BufRead, IsTerminal, Write};
+
+use anyhow::{anyhow, Context, Result};
+use cap_std_ext::cap_std::fs::Dir;
+use chrono::{Datelike, Timelike};
+
+use crate::config::{PolicyAction, PolicyRule};
+use crate::spec::Host;
+
+/// The facts about an operation which rules are evaluated against.
+#[derive(Debug, Default)]
+pub(crate) struct Plan {
+    pub(crate) image: String,
+    pub(crate) digest: String,
+    pub(crate) version: Option<String>,
+    pub(crate) current_version: Option<String>,
+    pub(crate) labels: HashMap<String, String>,
+    pub(crate) layers: u64,
+    pub(crate) image_size: u64,
+    pub(crate) hour: u32,
+    pub(crate) weekday: u32,
+}
+
+impl Plan {
+    /// Gather the plan for fetching a prepared image.
+    pub(crate) fn from_prepared(
+        image: &str,
+        prep: &ostree_ext::container::store::PreparedImport,
+        current_version: Option<&str>,
+    ) -> Result<Self> {
+        let now = chrono::Local::now();
+        let labels = crate::status::labels_of_config(&prep.config)
+            .cloned()
+            .unwrap_or_default();
+        Ok(Self {
+            image: image.to_owned(),
+            digest: prep.manifest_digest.to_string(),
+            version: prep.version().map(ToOwned::to_owned),
+            current_version: current_version.map(ToOwned::to_owned),
+            labels,
+            layers: prep.layers_to_fetch().count().try_into()?,
+            image_size: prep.manifest.layers().iter().map(|l| l.size()).sum(),
+            hour: now.hour(),
+            weekday: now.weekday().number_from_monday(),
+        })
+    }
+}
+
+/// The policy rules to check, along with the state of the booted system.
+#[derive(Debug)]
+pub(crate) struct PolicyCheck {
+    pub(crate) rules: Vec<PolicyRule>,
+    pub(crate) current_version: Option<String>,
+}
+
+impl PolicyCheck {
+    /// Load the configured rules; returns `None` if there are none.
+    pub(crate) fn load(root: &Dir, host: &Host) -> Result<Option<Self>> {
+        let config = crate::config::load_config(root)?;
+        let rules = config.policy_rules();
+        if rules.is_empty() {
+            return Ok(None);
+        }
+        let current_version = host
+            .status
+            .booted
+            .as_ref()
+            .and_then(|b| b.image.as_ref())
+            .and_then(|i| i.version.clone());
+        Ok(Some(Self {
+            rules: rules.to_vec(),
+            current_version,
+        }))
+    }
+
+    /// Return the rules whose condition matches the plan.
+    pub(crate) fn matching(&self, plan: &Plan) -> Result<Vec<&PolicyRule>> {
+        let mut r = Vec::new();
+        for rule in self.rules.iter() {
+            let name = rule.name.as_str();
+            if evaluate(&rule.when, plan)
+                .with_context(|| format!("Evaluating policy rule {name}"))?
+            {
+                r.push(rule);
+            }
+        }
+        Ok(r)
+    }
+
+    /// Evaluate all rules; an error is returned if the operation is denied
+    /// (or if a rule is invalid).
+    pub(crate) fn check(&self, plan: &Plan) -> Result<()> {
+        for rule in self.matching(plan)? {
+            let name = rule.name.as_str();
+            let message = rule.message.as_deref().unwrap_or(rule.when.as_str());
+            match rule.action {
+                PolicyAction::Deny => {
+                    anyhow::bail!("Denied by policy rule {name}: {message}")
+                }
+                PolicyAction::Confirm => confirm(name, message)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prompt for confirmation on the terminal.
+fn confirm(name: &str, message: &str) -> Result<()> {
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!("Policy rule {name} requires interactive confirmation: {message}");
+    }
+    let mut stdout = std::io::stdout().lock();
+    write!(
+        stdout,
+        "Policy rule {name} requires confirmation: {message}\nContinue? [y/N] "
+    )?;
+    stdout.flush()?;
+    let mut buf = String::new();
+    std::io::stdin().lock().read_line(&mut buf)?;
+    match buf.trim() {
+        "y" | "Y" | "yes" => Ok(()),
+        _ => anyhow::bail!("Operation cancelled by policy rule {name}"),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Str(String),
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Int(i) => write!(f, "{i}"),
+            Value::Str(s) => write!(f, "{s:?}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Int(i64),
+    Op(&'static str),
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    const OPS: &[&str] = &[
+        "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "(", ")", ",",
+    ];
+    let mut r = Vec::new();
+    let mut rest = s.trim_start();
+    'outer: while !rest.is_empty() {
+        for op in OPS {
+            if let Some(next) = rest.strip_prefix(op) {
+                r.push(Token::Op(op));
+                rest = next.trim_start();
+                continue 'outer;
+            }
+        }
+        let c = rest.chars().next().unwrap();
+        if c == '"' {
+            let end = rest[1..]
+                .find('"')
+                .ok_or_else(|| anyhow!("Unterminated string"))?;
+            r.push(Token::Str(rest[1..end + 1].to_owned()));
+            rest = &rest[end + 2..];
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            r.push(Token::Int(rest[..end].parse()?));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            r.push(Token::Ident(rest[..end].to_owned()));
+            rest = &rest[end..];
+        } else {
+            anyhow::bail!("Unexpected character {c:?}");
+        }
+        rest = rest.trim_start();
+    }
+    Ok(r)
+}
+
+/// A recursive descent evaluator; since expressions are tiny, we evaluate
+/// directly while parsing.
+struct Evaluator<'a> {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+    plan: &'a Plan,
+}
+
+impl<'a> Evaluator<'a> {
+    fn eat(&mut self, op: &str) -> bool {
+        self.tokens
+            .next_if(|t| matches!(t, Token::Op(o) if *o == op))
+            .is_some()
+    }
+
+    fn expect(&mut self, op: &str) -> Result<()> {
+        if !self.eat(op) {
+            anyhow::bail!("Expected {op}");
+        }
+        Ok(())
+    }
+
+    fn bool(v: Value) -> Result<bool> {
+        match v {
+            Value::Bool(b) => Ok(b),
+            o => anyhow::bail!("Expected boolean, found {o}"),
+        }
+    }
+
+    fn or(&mut self) -> Result<Value> {
+        let mut v = self.and()?;
+        while self.eat("||") {
+            let rhs = self.and()?;
+            v = Value::Bool(Self::bool(v)? || Self::bool(rhs)?);
+        }
+        Ok(v)
+    }
+
+    fn and(&mut self) -> Result<Value> {
+        let mut v = self.not()?;
+        while self.eat("&&") {
+            let rhs = self.not()?;
+            v = Value::Bool(Self::bool(v)? && Self::bool(rhs)?);
+        }
+        Ok(v)
+    }
+
+    fn not(&mut self) -> Result<Value> {
+        if self.eat("!") {
+            return Ok(Value::Bool(!Self::bool(self.not()?)?));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Value> {
+        let lhs = self.primary()?;
+        let Some(Token::Op(op)) = self
+            .tokens
+            .next_if(|t| matches!(t, Token::Op("==" | "!=" | "<" | "<=" | ">" | ">=")))
+        else {
+            return Ok(lhs);
+        };
+        let rhs = self.primary()?;
+        let ordering = match (&lhs, &rhs) {
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+            _ => None,
+        };
+        let r = match (op, ordering) {
+            ("==", _) => lhs == rhs,
+            ("!=", _) => lhs != rhs,
+            ("<", Some(o)) => o.is_lt(),
+            ("<=", Some(o)) => o.is_le(),
+            (">", Some(o)) => o.is_gt(),
+            (">=", Some(o)) => o.is_ge(),
+            // Ordering comparisons with null or mismatched types are false
+            _ => false,
+        };
+        Ok(Value::Bool(r))
+    }
+
+    fn primary(&mut self) -> Result<Value> {
+        let plan = self.plan;
+        let opt_str = |s: Option<&String>| s.map_or(Value::Null, |s| Value::Str(s.clone()));
+        let v = match self.tokens.next() {
+            Some(Token::Op("(")) => {
+                let v = self.or()?;
+                self.expect(")")?;
+                v
+            }
+            Some(Token::Str(s)) => Value::Str(s),
+            Some(Token::Int(i)) => Value::Int(i),
+            Some(Token::Ident(ident)) if self.eat("(") => {
+                let arg = self.or()?;
+                self.expect(")")?;
+                match (ident.as_str(), arg) {
+                    ("label", Value::Str(k)) => opt_str(plan.labels.get(&k)),
+                    ("major", Value::Str(v)) => {
+                        let end = v.find(|c: char| !c.is_ascii_digit()).unwrap_or(v.len());
+                        v[..end].parse().map_or(Value::Null, Value::Int)
+                    }
+                    ("major", Value::Null) => Value::Null,
+                    (f, arg) => anyhow::bail!("Invalid call {f}({arg})"),
+                }
+            }
+            Some(Token::Ident(ident)) => match ident.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                "null" => Value::Null,
+                "image" => Value::Str(plan.image.clone()),
+                "digest" => Value::Str(plan.digest.clone()),
+                "version" => opt_str(plan.version.as_ref()),
+                "current_version" => opt_str(plan.current_version.as_ref()),
+                "layers" => Value::Int(plan.layers.try_into()?),
+                "image_size" => Value::Int(plan.image_size.try_into()?),
+                "hour" => Value::Int(plan.hour.into()),
+                "weekday" => Value::Int(plan.weekday.into()),
+                o => anyhow::bail!("Unknown variable {o}"),
+            },
+            Some(t) => anyhow::bail!("Unexpected token {t:?}"),
+            None => anyhow::bail!("Unexpected end of expression"),
+        };
+        Ok(v)
+    }
+}
+
+/// Evaluate a rule condition against the plan.
+fn evaluate(expr: &str, plan: &Plan) -> Result<bool> {
+    let tokens = tokenize(expr)?;
+    let mut e = Evaluator {
+        tokens: tokens.into_iter().peekable(),
+        plan,
+    };
+    let v = e.or()?;
+    if let Some(t) = e.tokens.next() {
+        anyhow::bail!("Unexpected trailing token {t:?}");
+    }
+    Evaluator::bool(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan() -> Plan {
+        Plan {
+            image: "quay.io/example/os:latest".into(),
+            digest: "sha256:abcd".into(),
+            version: Some("42.20240901.0".into()),
+            current_version: Some("41.20240801.0".into()),
+            labels: [("com.example.tier".to_owned(), "gold".to_owned())]
+                .into_iter()
+                .collect(),
+            layers: 3,
+            image_size: 1_500_000_000,
+            hour: 10,
+            weekday: 3,
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let plan = &plan();
+        for (expr, expected) in [
+            ("true", true),
+            ("!false", true),
+            ("hour >= 9 && hour < 17", true),
+            ("weekday > 5", false),
+            ("(weekday == 6 || weekday == 7) || layers > 2", true),
+            (r#"label("com.example.tier") == "gold""#, true),
+            (r#"label("com.example.missing") == null"#, true),
+            ("major(version) != major(current_version)", true),
+            ("major(version) > 41", true),
+            ("image_size > 1000000000", true),
+            (r#"image == "quay.io/example/os:latest""#, true),
+            // Ordering against null is always false
+            (r#"label("com.example.missing") < "z""#, false),
+        ] {
+            assert_eq!(evaluate(expr, plan).unwrap(), expected, "{expr}");
+        }
+        for invalid in [
+            "",
+            "hour",
+            "hour >=",
+            "(true",
+            "true false",
+            "nosuchvar == 1",
+            r#"label(1)"#,
+            r#""unterminated"#,
+            "hour > 1 && 2",
+            "$",
+        ] {
+            assert!(evaluate(invalid, plan).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let plan = &plan();
+        let rule = |when: &str, action| PolicyRule {
+            name: "test".into(),
+            when: when.into(),
+            action,
+            message: None,
+        };
+        let check = PolicyCheck {
+            rules: vec![rule("weekday > 5", PolicyAction::Deny)],
+            current_version: None,
+        };
+        check.check(plan).unwrap();
+        let check = PolicyCheck {
+            rules: vec![
+                rule("weekday > 5", PolicyAction::Confirm),
+                rule("layers > 2", PolicyAction::Deny),
+            ],
+            current_version: None,
+        };
+        assert_eq!(check.matching(plan).unwrap().len(), 1);
+        assert!(check.check(plan).is_err());
+        let check = PolicyCheck {
+            rules: vec![rule("invalid ==", PolicyAction::Deny)],
+            current_version: None,
+        };
+        assert!(check.check(plan).is_err());
+    }
+}
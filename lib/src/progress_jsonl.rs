@@ -0,0 +1,182 @@
+//! # Machine readable progress
+//!
+//! This module implements the `--progress-fd` option, which writes progress
+//! events as newline-delimited JSON to a file descriptor provided by the
+//! caller (e.g. a pipe set up by Cockpit or Anaconda).
+//! Events are also reported to systemd, see [`crate::notify`].
+
+use std::fs::File;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use fn_error_context::context;
+use serde::Serialize;
+
+/// A progress event; each one is serialized as a single line of JSON, with
+/// the variant name in the `type` field.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub(crate) enum Event<'a> {
+    /// Fetching an image has started.
+    FetchStart {
+        /// The image being fetched
+        imgref: &'a str,
+        /// The number of layers which need to be fetched
+        layers: u64,
+    },
+    /// Fetching a layer has started.
+    LayerStart {
+        /// The layer digest
+        digest: &'a str,
+        /// The size of the layer
+        size: u64,
+    },
+    /// Periodic progress for the current layer.
+    LayerProgress {
+        /// The layer digest
+        digest: &'a str,
+        /// The number of bytes fetched so far
+        fetched: u64,
+        /// The size of the layer
+        size: u64,
+    },
+    /// A layer was fetched.
+    LayerComplete {
+        /// The layer digest
+        digest: &'a str,
+        /// The size of the layer
+        size: u64,
+    },
+    /// Fetching the image is complete.
+    FetchComplete {
+        /// The manifest digest of the fetched image
+        digest: &'a str,
+    },
+    /// A new phase of the operation has started, e.g. `deploy`.
+    Phase {
+        /// The name of the phase
+        name: &'a str,
+        /// The approximate overall progress of the operation in percent, if known
+        #[serde(skip_serializing_if = "Option::is_none")]
+        percent: Option<u32>,
+    },
+    /// The partitions which will be created by `bootc install to-disk`.
+    Plan {
+        /// The partitioning plan
+        plan: serde_json::Value,
+    },
+    /// An installation completed successfully.
+    InstallComplete {
+        /// The same data as written to `--result-json`
+        result: serde_json::Value,
+    },
+}
+
+/// Writes progress events to a file descriptor.  Write errors are logged, but
+/// otherwise ignored; a consumer going away should not cause an operation to fail.
+#[derive(Debug, Clone)]
+pub(crate) struct ProgressWriter {
+    fd: Arc<Mutex<File>>,
+}
+
+impl From<File> for ProgressWriter {
+    fn from(f: File) -> Self {
+        Self {
+            fd: Arc::new(Mutex::new(f)),
+        }
+    }
+}
+
+impl ProgressWriter {
+    /// Open a writer for the given file descriptor, which must be inherited from the caller.
+    #[context("Opening progress fd {fd}")]
+    pub(crate) fn from_raw_fd(fd: i32) -> Result<Self> {
+        // Reopen via procfs rather than taking ownership of the raw fd, which would require unsafe
+        let f = std::fs::OpenOptions::new()
+            .write(true)
+            .open(format!("/proc/self/fd/{fd}"))?;
+        Ok(f.into())
+    }
+
+    /// Open a writer if a file descriptor was provided.
+    pub(crate) fn from_opt(fd: Option<i32>) -> Result<Option<Self>> {
+        fd.map(Self::from_raw_fd).transpose()
+    }
+
+    fn send_impl(&self, event: &Event) -> Result<()> {
+        let mut buf = serde_json::to_vec(event)?;
+        buf.push(b'\n');
+        // SAFETY: Propagate panics
+        let mut fd = self.fd.lock().unwrap();
+        fd.write_all(&buf).context("Writing progress")
+    }
+
+    /// Write an event.
+    pub(crate) fn send(&self, event: Event) {
+        if let Err(e) = self.send_impl(&event) {
+            tracing::warn!("Failed to write progress: {e:#}");
+        }
+    }
+}
+
+/// Write an event if a progress writer is provided, and report it to the
+/// service manager if running under one.
+pub(crate) fn send(progress: Option<&ProgressWriter>, event: Event) {
+    crate::notify::progress(&event);
+    if let Some(progress) = progress {
+        progress.send(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek};
+
+    use super::*;
+
+    #[test]
+    fn test_progress() -> Result<()> {
+        let tf = tempfile::tempfile()?;
+        let w = ProgressWriter::from(tf.try_clone()?);
+        send(
+            Some(&w),
+            Event::Phase {
+                name: "deploy",
+                percent: Some(70),
+            },
+        );
+        w.send(Event::LayerProgress {
+            digest: "sha256:abc",
+            fetched: 10,
+            size: 20,
+        });
+        send(
+            None,
+            Event::Phase {
+                name: "ignored",
+                percent: None,
+            },
+        );
+        w.send(Event::InstallComplete {
+            result: serde_json::json!({ "stateroot": "default" }),
+        });
+        drop(w);
+
+        let mut tf = tf;
+        tf.rewind()?;
+        let mut buf = String::new();
+        tf.read_to_string(&mut buf)?;
+        let expected = indoc::indoc! { r#"
+            {"type":"phase","name":"deploy","percent":70}
+            {"type":"layerProgress","digest":"sha256:abc","fetched":10,"size":20}
+            {"type":"installComplete","result":{"stateroot":"default"}}
+        "#};
+        similar_asserts::assert_eq!(buf, expected);
+        Ok(())
+    }
+}
@@ -1,21 +1,172 @@
 //! Handling of system restarts/reboot
 
 use std::io::Write;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
 
+use anyhow::{Context, Result};
+use cap_std_ext::cap_std;
+use cap_std_ext::cap_std::fs::Dir;
 use fn_error_context::context;
 
 use crate::task::Task;
 
-/// Initiate a system reboot.
-/// This function will only return in case of error.
-#[context("Initiating reboot")]
-pub(crate) fn reboot() -> anyhow::Result<()> {
+/// How often to check whether the drain hook has exited, or whether
+/// inhibitor locks have been released.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How often to retry a reboot refused because of an inhibitor lock.
+const REBOOT_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Wait for the child process to exit successfully before the deadline;
+/// it is killed if the deadline passes.
+fn wait_until(child: &mut Child, deadline: Option<Instant>) -> Result<()> {
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            let _ = child.kill();
+            let _ = child.wait();
+            anyhow::bail!("Timed out");
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+    if !status.success() {
+        anyhow::bail!("{status}");
+    }
+    Ok(())
+}
+
+/// Run the drain hook, holding an inhibitor lock so that nothing else
+/// reboots the system meanwhile.
+#[context("Running drain hook {hook}")]
+fn drain(hook: &str, deadline: Option<Instant>) -> Result<()> {
+    println!("Running drain hook: {hook}");
+    let mut child = Command::new("systemd-inhibit")
+        .args([
+            "--what=shutdown:sleep",
+            "--mode=block",
+            "--who=bootc",
+            "--why=Draining workloads before applying an update",
+            hook,
+        ])
+        .spawn()?;
+    wait_until(&mut child, deadline)
+}
+
+/// Whether a full reboot or a userspace-only soft reboot is performed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Reboot,
+    SoftReboot,
+}
+
+impl Kind {
+    /// The `systemctl` verb.
+    fn verb(self) -> &'static str {
+        match self {
+            Kind::Reboot => "reboot",
+            Kind::SoftReboot => "soft-reboot",
+        }
+    }
+}
+
+/// Shared implementation of [`reboot`] and [`soft_reboot`].
+fn restart(kind: Kind, timeout: Option<Duration>) -> Result<()> {
+    let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    let config = crate::config::load_config(root)?;
+    let timeout = timeout.or_else(|| config.reboot_timeout());
+    let deadline = timeout.map(|t| Instant::now() + t);
+    if let Some(hook) = config.reboot_drain_hook() {
+        drain(hook, deadline)?;
+    }
+    let verb = kind.verb();
     // Flush output streams
     let _ = std::io::stdout().flush();
     let _ = std::io::stderr().flush();
-    Task::new("Rebooting system", "reboot").run()?;
-    tracing::debug!("Initiated reboot, sleeping forever...");
+    if let Some((timeout, deadline)) = timeout.zip(deadline) {
+        let mut logged = false;
+        loop {
+            let st = Command::new("systemctl")
+                .args([verb, "--check-inhibitors=yes"])
+                .status()
+                .with_context(|| format!("Executing systemctl {verb}"))?;
+            if st.success() {
+                break;
+            }
+            if Instant::now() >= deadline {
+                anyhow::bail!("Reboot still inhibited after {}s", timeout.as_secs());
+            }
+            if !logged {
+                println!("Waiting for inhibitor locks to be released");
+                logged = true;
+            }
+            std::thread::sleep(REBOOT_RETRY_INTERVAL);
+        }
+    } else {
+        match kind {
+            Kind::Reboot => Task::new("Rebooting system", "reboot").run()?,
+            Kind::SoftReboot => Task::new("Soft rebooting system", "systemctl")
+                .arg(verb)
+                .run()?,
+        }
+    }
+    tracing::debug!("Initiated {verb}, sleeping forever...");
     loop {
         std::thread::park();
     }
 }
+
+/// Initiate a system reboot.
+/// This function will only return in case of error.
+///
+/// If a drain hook is configured, it must succeed first.  If a timeout is
+/// provided (or configured), block inhibitor locks held by other processes
+/// are honored until it expires.  On failure, any staged update stays queued
+/// for the next boot.
+#[context("Initiating reboot")]
+pub(crate) fn reboot(timeout: Option<Duration>) -> anyhow::Result<()> {
+    restart(Kind::Reboot, timeout)
+}
+
+/// Apply the staged deployment via `systemctl soft-reboot`, restarting only
+/// userspace into it; the caller must have checked that its kernel, initramfs
+/// and kernel arguments match the booted deployment.  If ostree cannot
+/// prepare the soft reboot, a full reboot is performed instead.
+/// This function will only return in case of error.
+#[context("Initiating soft reboot")]
+pub(crate) fn soft_reboot(timeout: Option<Duration>) -> anyhow::Result<()> {
+    // Sets up /run/nextroot for systemd to switch into
+    let prepared = Task::new("Preparing soft reboot", "ostree")
+        .args(["admin", "prepare-soft-reboot", "0"])
+        .run();
+    match prepared {
+        Ok(()) => restart(Kind::SoftReboot, timeout),
+        Err(e) => {
+            println!("Soft reboot unavailable, performing a full reboot: {e:#}");
+            restart(Kind::Reboot, timeout)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_until() -> Result<()> {
+        wait_until(&mut Command::new("true").spawn()?, None)?;
+        let soon = Some(Instant::now() + Duration::from_secs(60));
+        wait_until(&mut Command::new("true").spawn()?, soon)?;
+        assert!(wait_until(&mut Command::new("false").spawn()?, soon).is_err());
+
+        let start = Instant::now();
+        let mut child = Command::new("sleep").arg("60").spawn()?;
+        let e = wait_until(&mut child, Some(start + Duration::from_millis(100))).unwrap_err();
+        assert_eq!(e.to_string(), "Timed out");
+        assert!(start.elapsed() < Duration::from_secs(30));
+        // The child was reaped
+        assert!(child.try_wait()?.is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+//! # Reinstalling the system in place
+//!
+//! `bootc system-reinstall` deploys the configured image into a new
+//! stateroot, so that on the next boot the system starts with a pristine
+//! `/etc` and `/var` as shipped in the image, while the previous stateroot
+//! stays on disk (and bootable as the rollback entry).  Selected state can be
+//! carried over: it is copied into `/var/lib/bootc/reinstall-backup` of the
+//! new stateroot before the deployment is staged, and restored into place by
+//! `bootc-reinstall-restore.service` on the first boot.
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use cap_std_ext::cap_std;
+use cap_std_ext::cap_std::fs::Dir;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use fn_error_context::context;
+use ostree_ext::ostree;
+
+use crate::deploy::RequiredHostSpec;
+use crate::task::Task;
+
+/// The backup of the state to restore, relative to the root.
+pub(crate) const BACKUP_DIR: &str = "var/lib/bootc/reinstall-backup";
+/// The unit restoring the backup.
+pub(crate) const RESTORE_UNIT: &str = "bootc-reinstall-restore.service";
+
+/// Check that a (canonicalized) path to keep is machine-local state; all
+/// other content is either part of the image or transient.
+fn validate_keep(path: &Utf8Path) -> Result<()> {
+    let mut components = path.components().skip(1);
+    match components.next().map(|c| c.as_str()) {
+        Some("etc" | "var") if components.next().is_some() => Ok(()),
+        _ => anyhow::bail!("Only paths below /etc and /var can be kept: {path}"),
+    }
+}
+
+/// The default name of the new stateroot.
+fn default_stateroot(now: chrono::DateTime<chrono::Utc>) -> String {
+    format!("reinstall-{}", now.format("%Y%m%d%H%M%S"))
+}
+
+/// Implementation of `bootc system-reinstall`.
+#[context("Reinstalling")]
+pub(crate) async fn reinstall(
+    keep: &[Utf8PathBuf],
+    stateroot: Option<&str>,
+    quiet: bool,
+) -> Result<()> {
+    let keep = keep
+        .iter()
+        .map(|p| -> Result<Utf8PathBuf> {
+            let p = p
+                .canonicalize_utf8()
+                .with_context(|| format!("Resolving {p}"))?;
+            validate_keep(&p)?;
+            Ok(p)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let sysroot = &crate::cli::get_storage().await?;
+    let ostree: &ostree::Sysroot = sysroot;
+    let (booted, _deployments, host) = crate::status::get_status_require_booted(sysroot)?;
+    let imgref = host
+        .spec
+        .image
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("No image source specified"))?;
+    let stateroot = stateroot
+        .map(ToOwned::to_owned)
+        .unwrap_or_else(|| default_stateroot(chrono::Utc::now()));
+    let sysroot_dir = &Dir::reopen_dir(&crate::utils::sysroot_fd(ostree))?;
+    let stateroot_path = format!("ostree/deploy/{stateroot}");
+    if sysroot_dir.try_exists(&stateroot_path)? {
+        anyhow::bail!("Stateroot {stateroot} already exists");
+    }
+
+    let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    let policy = crate::policy::PolicyCheck::load(root, &host)?;
+    let fetched = crate::deploy::pull(
+        &sysroot.repo(),
+        imgref,
+        &crate::deploy::PullOpts {
+            quiet,
+            policy: policy.as_ref(),
+            sysroot: Some(sysroot),
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    ostree.init_osname(&stateroot, ostree::gio::Cancellable::NONE)?;
+    if !keep.is_empty() {
+        // We run in our own mount namespace, so this does not affect the host
+        crate::utils::ensure_writable_mount(sysroot_dir, "/sysroot")?;
+        let backup = format!("{stateroot_path}/{BACKUP_DIR}");
+        sysroot_dir.create_dir_all(&backup)?;
+        let backup = Utf8Path::new("/sysroot").join(backup);
+        for path in keep.iter() {
+            Task::new(format!("Backing up {path}"), "cp")
+                .args(["-a", "--reflink=auto", "--parents", path.as_str()])
+                .arg(backup.as_str())
+                .run()?;
+        }
+    }
+    crate::deploy::stage_fresh(
+        sysroot,
+        &booted,
+        &stateroot,
+        &fetched,
+        &RequiredHostSpec {
+            image: imgref,
+            kargs: None,
+        },
+    )
+    .await?;
+    if !keep.is_empty() {
+        println!("  Kept state will be restored on the next boot by {RESTORE_UNIT}");
+    }
+    Ok(())
+}
+
+/// Restore the state kept by `bootc system-reinstall` into place, then remove
+/// the backup.
+#[context("Restoring reinstall backup")]
+pub(crate) fn restore(root: &Dir) -> Result<()> {
+    if !root.try_exists(BACKUP_DIR)? {
+        return Ok(());
+    }
+    let backup = Utf8Path::new("/").join(BACKUP_DIR);
+    // Each toplevel entry is either etc or var
+    for name in crate::utils::filenames_sorted(&root.open_dir(BACKUP_DIR)?)? {
+        Task::new(format!("Restoring kept state in /{name}"), "cp")
+            .args(["-a", "--reflink=auto"])
+            .arg(format!("{backup}/{name}/."))
+            .arg(format!("/{name}/"))
+            .run()?;
+    }
+    root.remove_all_optional(BACKUP_DIR)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_keep() {
+        for valid in [
+            "/etc/ssh",
+            "/var/home/user",
+            "/etc/NetworkManager/system-connections",
+        ] {
+            validate_keep(Utf8Path::new(valid)).unwrap();
+        }
+        for invalid in [
+            "/",
+            "/etc",
+            "/var",
+            "/usr/bin",
+            "/boot/efi",
+            "/run/foo",
+            "/sysroot",
+        ] {
+            assert!(validate_keep(Utf8Path::new(invalid)).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_default_stateroot() {
+        let now = chrono::DateTime::parse_from_rfc3339("2024-05-01T03:30:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        assert_eq!(default_stateroot(now), "reinstall-20240501033000");
+    }
+}
@@ -0,0 +1,172 @@
+//! # Rendering the host status
+//!
+//! Each output format of `bootc status` is implemented by a [`Renderer`].
+//! In addition to the built-in formats, `--format=ext:NAME` runs the
+//! executable `NAME` from `/etc/bootc/renderers` or (if not found there)
+//! `/usr/lib/bootc/renderers`, passing it the host status as JSON (as with
+//! `--format=json`) on standard input; its standard output is used as the
+//! output of bootc.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+use camino::Utf8PathBuf;
+use cap_std_ext::cap_std;
+use cap_std_ext::cap_std::fs::Dir;
+use fn_error_context::context;
+
+use crate::cli::OutputFormat;
+use crate::spec::Host;
+
+/// Directories searched for external renderers, relative to the root, in order
+/// of precedence.
+const EXTERNAL_RENDERER_DIRS: &[&str] = &["etc/bootc/renderers", "usr/lib/bootc/renderers"];
+
+/// Writes the host status in a particular format.
+pub(crate) trait Renderer {
+    /// Write the status; if `digest_len` is set, digests should be abbreviated
+    /// to that length where the format allows it.
+    fn render(&self, out: &mut dyn Write, host: &Host, digest_len: Option<usize>) -> Result<()>;
+}
+
+/// The `json` format.
+struct Json;
+
+impl Renderer for Json {
+    fn render(&self, out: &mut dyn Write, host: &Host, _digest_len: Option<usize>) -> Result<()> {
+        serde_json::to_writer(out, host).map_err(anyhow::Error::new)
+    }
+}
+
+/// The `yaml` format.
+struct Yaml;
+
+impl Renderer for Yaml {
+    fn render(&self, out: &mut dyn Write, host: &Host, _digest_len: Option<usize>) -> Result<()> {
+        serde_yaml::to_writer(out, host).map_err(anyhow::Error::new)
+    }
+}
+
+/// The `humanreadable` format.
+struct HumanReadable;
+
+impl Renderer for HumanReadable {
+    fn render(&self, out: &mut dyn Write, host: &Host, digest_len: Option<usize>) -> Result<()> {
+        crate::status::human_readable_output(out, host, digest_len)
+    }
+}
+
+/// The `markdown` format.
+struct Markdown;
+
+impl Renderer for Markdown {
+    fn render(&self, out: &mut dyn Write, host: &Host, _digest_len: Option<usize>) -> Result<()> {
+        crate::status::markdown_output(out, host)
+    }
+}
+
+/// A renderer implemented by an external executable.
+struct External {
+    path: Utf8PathBuf,
+}
+
+impl Renderer for External {
+    fn render(&self, out: &mut dyn Write, host: &Host, _digest_len: Option<usize>) -> Result<()> {
+        let path = &self.path;
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Executing {path}"))?;
+        let input = serde_json::to_vec(host)?;
+        let mut stdin = child.stdin.take().unwrap();
+        // Write from a separate thread, so that a renderer which produces
+        // output before consuming all of its input does not deadlock.
+        let writer = std::thread::spawn(move || stdin.write_all(&input));
+        let output = child.wait_with_output()?;
+        // A renderer may legitimately exit without reading all of its input
+        let _ = writer.join();
+        if !output.status.success() {
+            anyhow::bail!("Renderer {path} failed: {}", output.status);
+        }
+        out.write_all(&output.stdout)?;
+        Ok(())
+    }
+}
+
+/// Find the external renderer with the given name.
+#[context("Finding renderer {name}")]
+fn find_external(root: &Dir, name: &str) -> Result<External> {
+    if name.is_empty() || name.starts_with('.') || name.contains('/') {
+        anyhow::bail!("Invalid renderer name");
+    }
+    for dir in EXTERNAL_RENDERER_DIRS {
+        let path = format!("{dir}/{name}");
+        if root.try_exists(&path)? {
+            return Ok(External {
+                path: Utf8PathBuf::from("/").join(path),
+            });
+        }
+    }
+    anyhow::bail!(
+        "Not found in {}",
+        EXTERNAL_RENDERER_DIRS
+            .iter()
+            .map(|d| format!("/{d}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+/// The renderer for the given format.
+pub(crate) fn renderer(format: &OutputFormat) -> Result<Box<dyn Renderer>> {
+    let r: Box<dyn Renderer> = match format {
+        OutputFormat::HumanReadable => Box::new(HumanReadable),
+        OutputFormat::Yaml => Box::new(Yaml),
+        OutputFormat::Json => Box::new(Json),
+        OutputFormat::Markdown => Box::new(Markdown),
+        OutputFormat::External(name) => {
+            let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+            Box::new(find_external(root, name)?)
+        }
+    };
+    Ok(r)
+}
+
+#[cfg(test)]
+mod tests {
+    use cap_std_ext::cap_std::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn test_external() -> Result<()> {
+        let td = cap_std_ext::cap_tempfile::TempDir::new(cap_std::ambient_authority())?;
+        assert!(find_external(&td, "count").is_err());
+        for invalid in ["", ".hidden", "../count"] {
+            assert!(find_external(&td, invalid).is_err());
+        }
+        td.create_dir_all(EXTERNAL_RENDERER_DIRS[1])?;
+        let path = format!("{}/count", EXTERNAL_RENDERER_DIRS[1]);
+        td.write(&path, "#!/bin/sh\nwc -c\n")?;
+        td.set_permissions(&path, cap_std::fs::Permissions::from_mode(0o755))?;
+        let r = find_external(&td, "count")?;
+        assert_eq!(r.path, format!("/{path}"));
+
+        // Render through a real executable, rather than one under the temporary root
+        let r = External {
+            path: "/bin/cat".into(),
+        };
+        let host = crate::testing::fabricate_host(Some("quay.io/example/os"), None, None, false)?;
+        let mut buf = Vec::new();
+        r.render(&mut buf, &host, None)?;
+        assert_eq!(serde_json::from_slice::<Host>(&buf)?, host);
+
+        let r = External {
+            path: "/bin/false".into(),
+        };
+        assert!(r.render(&mut Vec::new(), &host, None).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+//! # A rescue boot entry
+//!
+//! If enabled via `[rescue]` in the host configuration, each time an update is
+//! staged the kernel and initramfs of the booted deployment are copied to
+//! `/boot/bootc-rescue`, and a GRUB menu entry booting them into
+//! `emergency.target` is written to `/boot/grub2/custom.cfg`.  Unlike the
+//! entries managed by ostree, which are regenerated on every deployment
+//! change, this entry always exists and points at a deployment which has
+//! successfully booted.
+//!
+//! The entry is only supported with GRUB configurations which source
+//! `custom.cfg` (as generated by `grub2-mkconfig`).
+
+use std::os::fd::AsFd;
+
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+use cap_std_ext::cap_std;
+use cap_std_ext::cap_std::fs::Dir;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use fn_error_context::context;
+use ostree_ext::ostree;
+
+use crate::store::Storage;
+
+/// The directory holding the rescue kernel and initramfs, relative to `/boot`.
+const RESCUE_DIR: &str = "bootc-rescue";
+/// The GRUB configuration fragment, relative to `/boot`.
+pub(crate) const GRUB_CUSTOM_CFG: &str = "grub2/custom.cfg";
+/// Delimits the rescue entry in [`GRUB_CUSTOM_CFG`].
+const BEGIN_MARKER: &str = "### BEGIN bootc-rescue ###";
+/// Delimits the rescue entry in [`GRUB_CUSTOM_CFG`].
+const END_MARKER: &str = "### END bootc-rescue ###";
+/// Kernel arguments which are not carried over into the rescue entry.
+const DROPPED_KARGS: &[&str] = &["BOOT_IMAGE", "ostree", "systemd.unit", "rhgb", "quiet"];
+
+/// The kernel arguments for the rescue entry, derived from those of the
+/// booted system.
+fn rescue_kargs(cmdline: &str, deployment_path: &str) -> String {
+    let mut kargs = cmdline
+        .split_ascii_whitespace()
+        .filter(|k| {
+            let name = k.split_once('=').map_or(*k, |(name, _)| name);
+            !DROPPED_KARGS.contains(&name)
+        })
+        .collect::<Vec<_>>();
+    let ostree = format!("ostree=/{deployment_path}");
+    kargs.push(&ostree);
+    kargs.push("systemd.unit=emergency.target");
+    kargs.join(" ")
+}
+
+/// The GRUB menu entry; `prefix` is the path of `/boot` on the filesystem
+/// GRUB reads it from.
+fn grub_entry(prefix: &str, kargs: &str) -> String {
+    format!(
+        "{BEGIN_MARKER}\n\
+menuentry 'bootc rescue' --id bootc-rescue {{\n\
+\tlinux {prefix}/{RESCUE_DIR}/vmlinuz {kargs}\n\
+\tinitrd {prefix}/{RESCUE_DIR}/initramfs.img\n\
+}}\n\
+{END_MARKER}\n"
+    )
+}
+
+/// Replace (or append) the rescue entry in the existing `custom.cfg`,
+/// preserving any other content.
+fn replace_entry(existing: &str, entry: &str) -> String {
+    replace_section(existing, BEGIN_MARKER, END_MARKER, entry)
+}
+
+/// Replace the lines between `begin` and `end` (inclusive) in the existing
+/// `custom.cfg` with `section`, which is appended; it may be empty to only
+/// remove them.
+pub(crate) fn replace_section(existing: &str, begin: &str, end: &str, section: &str) -> String {
+    let mut r = String::new();
+    let mut in_entry = false;
+    for line in existing.lines() {
+        if line == begin {
+            in_entry = true;
+        } else if line == end {
+            in_entry = false;
+        } else if !in_entry {
+            r.push_str(line);
+            r.push('\n');
+        }
+    }
+    r.push_str(section);
+    r
+}
+
+/// The path of `/boot` on the filesystem GRUB reads it from: if `/boot` is a
+/// separate filesystem, GRUB sees its contents at the root.
+pub(crate) fn grub_boot_prefix(root: &Dir, boot: &Dir) -> Result<&'static str> {
+    let separate_boot =
+        rustix::fs::fstat(boot.as_fd())?.st_dev != rustix::fs::fstat(root.as_fd())?.st_dev;
+    Ok(if separate_boot { "" } else { "/boot" })
+}
+
+/// Copy a file from the deployment to `/boot`.
+pub(crate) fn copy_file(src: &Dir, src_path: &Utf8Path, dest: &Dir, dest_path: &str) -> Result<()> {
+    let mut f = src
+        .open(src_path)
+        .with_context(|| format!("Opening {src_path}"))?;
+    dest.atomic_replace_with(dest_path, |w| std::io::copy(&mut f, w))
+        .with_context(|| format!("Writing /boot/{dest_path}"))?;
+    Ok(())
+}
+
+/// Point the rescue entry at the booted deployment, if enabled.
+#[context("Updating rescue boot entry")]
+pub(crate) fn refresh(sysroot: &Storage) -> Result<()> {
+    let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    if !crate::config::load_config(root)?.rescue_enabled() {
+        return Ok(());
+    }
+    let boot = &Dir::open_ambient_dir("/boot", cap_std::ambient_authority())?;
+    if !boot.try_exists("grub2")? {
+        anyhow::bail!("Only GRUB is supported");
+    }
+    let booted = sysroot.require_booted_deployment()?;
+    let ostree: &ostree::Sysroot = sysroot;
+    let deployment_path = ostree.deployment_dirpath(&booted);
+    let deployment_root = &crate::utils::deployment_fd(ostree, &booted)?;
+    let kernel_dir = ostree_ext::bootabletree::find_kernel_dir_fs(deployment_root)?
+        .context("No kernel found in booted deployment")?;
+
+    // We run in our own mount namespace, so this does not affect the host
+    crate::utils::ensure_writable_mount(boot, "/boot")?;
+    boot.create_dir_all(RESCUE_DIR)?;
+    for name in ["vmlinuz", "initramfs.img"] {
+        copy_file(
+            deployment_root,
+            &kernel_dir.join(name),
+            boot,
+            &format!("{RESCUE_DIR}/{name}"),
+        )?;
+    }
+
+    let prefix = grub_boot_prefix(root, boot)?;
+    let cmdline = std::fs::read_to_string("/proc/cmdline").context("Reading /proc/cmdline")?;
+    let entry = grub_entry(prefix, &rescue_kargs(&cmdline, deployment_path.as_str()));
+    let existing = boot
+        .open_optional(GRUB_CUSTOM_CFG)?
+        .map(std::io::read_to_string)
+        .transpose()?
+        .unwrap_or_default();
+    boot.atomic_write(GRUB_CUSTOM_CFG, replace_entry(&existing, &entry))?;
+    println!("Updated rescue boot entry");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rescue_kargs() {
+        let deployment = "ostree/deploy/default/deploy/0a1b2c.0";
+        assert_eq!(
+            rescue_kargs(
+                "BOOT_IMAGE=(hd0,gpt3)/vmlinuz root=UUID=abcd rw ostree=/ostree/boot.1/default/ff/0 systemd.unit=multi-user.target quiet console=ttyS0",
+                deployment
+            ),
+            "root=UUID=abcd rw console=ttyS0 ostree=/ostree/deploy/default/deploy/0a1b2c.0 systemd.unit=emergency.target"
+        );
+    }
+
+    #[test]
+    fn test_replace_entry() {
+        let entry = grub_entry("", "root=UUID=abcd");
+        assert!(entry.contains("\tlinux /bootc-rescue/vmlinuz root=UUID=abcd\n"));
+        assert!(entry.contains("\tinitrd /bootc-rescue/initramfs.img\n"));
+        let user = "set timeout=10\n";
+        let once = replace_entry(user, &entry);
+        assert_eq!(once, format!("{user}{entry}"));
+        // Idempotent, and other content is preserved
+        let new_entry = grub_entry("/boot", "root=UUID=ef01");
+        let twice = replace_entry(&once, &new_entry);
+        assert_eq!(twice, format!("{user}{new_entry}"));
+        assert_eq!(replace_entry("", &entry), entry);
+    }
+}
@@ -0,0 +1,195 @@
+//! # Phased rollouts
+//!
+//! If `rollout-duration` is set in the `[updates]` section of
+//! `/usr/lib/bootc/config.toml`, a new digest of the tracked tag is not
+//! adopted immediately: each host has a percentile (derived from a hash of
+//! `/etc/machine-id`, or configured via `rollout-percentile`), and waits
+//! for that fraction of the rollout duration after the image was created,
+//! as recorded in its configuration.  Hosts following the same tag hence
+//! adopt an update gradually, and a bad update can be withdrawn before it
+//! has reached the whole fleet.  For images without a creation time, the
+//! rollout starts when this host first sees the digest instead.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use cap_std_ext::cap_std::fs::Dir;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use chrono::{DateTime, Utc};
+use fn_error_context::context;
+use ostree_ext::oci_spec::image::ImageConfiguration;
+use serde::{Deserialize, Serialize};
+
+/// The directory holding the rollout state, relative to the root.
+const STATE_DIR: &str = "var/lib/bootc";
+/// The rollout state, relative to [`STATE_DIR`].
+const STATE_FILE: &str = "rollout.json";
+/// The machine ID, relative to the root.
+const MACHINE_ID: &str = "etc/machine-id";
+
+/// The digest being rolled out, and when this host first saw it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+struct RolloutState {
+    digest: String,
+    first_seen: DateTime<Utc>,
+}
+
+/// The position of a machine in rollouts, in `[0, 1)`.
+fn percentile_of(machine_id: &str) -> f64 {
+    let h = openssl::sha::sha256(machine_id.trim().as_bytes());
+    let v = u64::from_be_bytes(h[..8].try_into().unwrap());
+    (v >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// The position of this host in rollouts, in `[0, 1)`.
+fn host_percentile(root: &Dir, config: &crate::config::HostConfiguration) -> Result<f64> {
+    if let Some(p) = config.update_rollout_percentile() {
+        if p > 100 {
+            anyhow::bail!("Invalid rollout-percentile {p}; expected at most 100");
+        }
+        return Ok(f64::from(p) / 100.0);
+    }
+    let machine_id = root
+        .read_to_string(MACHINE_ID)
+        .with_context(|| format!("Reading /{MACHINE_ID}"))?;
+    Ok(percentile_of(&machine_id))
+}
+
+/// When this host first saw the digest, recording it as now if it is new.
+fn first_seen(root: &Dir, digest: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    let path = format!("{STATE_DIR}/{STATE_FILE}");
+    let prev: Option<RolloutState> = root
+        .open_optional(&path)?
+        .map(|f| serde_json::from_reader(std::io::BufReader::new(f)))
+        .transpose()
+        .with_context(|| format!("Parsing /{path}"))?;
+    if let Some(prev) = prev.filter(|p| p.digest == digest) {
+        return Ok(prev.first_seen);
+    }
+    let state = RolloutState {
+        digest: digest.to_owned(),
+        first_seen: now,
+    };
+    root.create_dir_all(STATE_DIR)?;
+    root.open_dir(STATE_DIR)?
+        .atomic_write(STATE_FILE, serde_json::to_vec(&state)?)?;
+    Ok(now)
+}
+
+/// When the image was created, if recorded (and valid) in its configuration.
+pub(crate) fn created_at(config: &ImageConfiguration) -> Option<DateTime<Utc>> {
+    let created = config.created().as_deref()?;
+    DateTime::parse_from_rfc3339(created)
+        .map(|t| t.with_timezone(&Utc))
+        .ok()
+}
+
+/// If a rollout is configured and has not yet reached this host for the
+/// digest, the time at which it will.  The rollout starts when the image was
+/// created, or if that is unknown, when this host first saw the digest.
+#[context("Checking rollout of {digest}")]
+pub(crate) fn not_before(
+    root: &Dir,
+    config: &crate::config::HostConfiguration,
+    digest: &str,
+    created: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>> {
+    let Some(duration) = config.update_rollout_duration() else {
+        return Ok(None);
+    };
+    let start = match created {
+        Some(created) => created,
+        None => first_seen(root, digest, now)?,
+    };
+    let delay = duration.mul_f64(host_percentile(root, config)?);
+    // Round to whole seconds for display
+    let delay = Duration::from_secs(delay.as_secs());
+    let at = start + chrono::Duration::from_std(delay)?;
+    Ok((now < at).then_some(at))
+}
+
+#[cfg(test)]
+mod tests {
+    use cap_std_ext::cap_std;
+
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let a = percentile_of("e4f2b1c3d5a6478899aabbccddeeff00\n");
+        assert_eq!(a, percentile_of("e4f2b1c3d5a6478899aabbccddeeff00"));
+        assert!((0.0..1.0).contains(&a));
+        assert_ne!(a, percentile_of("00ffeeddccbbaa9988746a5d3c1b2f4e"));
+    }
+
+    #[test]
+    fn test_not_before() -> Result<()> {
+        let td = cap_std_ext::cap_tempfile::TempDir::new(cap_std::ambient_authority())?;
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let hours = |h| start + chrono::Duration::hours(h);
+        let mut config = crate::config::HostConfiguration::default();
+        // No rollout configured
+        assert!(not_before(&td, &config, "sha256:a", None, start)?.is_none());
+
+        config.updates = Some(crate::config::UpdatesConfiguration {
+            rollout_duration: Some(100 * 3600),
+            rollout_percentile: Some(25),
+            ..Default::default()
+        });
+        assert_eq!(
+            not_before(&td, &config, "sha256:a", None, start)?,
+            Some(hours(25))
+        );
+        // The first sighting is remembered
+        assert_eq!(
+            not_before(&td, &config, "sha256:a", None, hours(10))?,
+            Some(hours(25))
+        );
+        assert!(not_before(&td, &config, "sha256:a", None, hours(25))?.is_none());
+        // A new digest restarts the rollout
+        assert_eq!(
+            not_before(&td, &config, "sha256:b", None, hours(30))?,
+            Some(hours(55))
+        );
+
+        config.updates.as_mut().unwrap().rollout_percentile = Some(101);
+        assert!(not_before(&td, &config, "sha256:b", None, hours(30)).is_err());
+        // The percentile is otherwise derived from the machine ID
+        config.updates.as_mut().unwrap().rollout_percentile = None;
+        assert!(not_before(&td, &config, "sha256:b", None, hours(30)).is_err());
+        td.create_dir_all("etc")?;
+        td.write(MACHINE_ID, "e4f2b1c3d5a6478899aabbccddeeff00\n")?;
+        not_before(&td, &config, "sha256:b", None, hours(30))?;
+
+        // The creation time of the image takes precedence over the first sighting
+        config.updates.as_mut().unwrap().rollout_percentile = Some(25);
+        let created = Some(hours(-10));
+        assert_eq!(
+            not_before(&td, &config, "sha256:b", created, hours(0))?,
+            Some(hours(15))
+        );
+        assert!(not_before(&td, &config, "sha256:c", created, hours(15))?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_created_at() {
+        let config = |created: Option<&str>| {
+            let mut c = ImageConfiguration::default();
+            c.set_created(created.map(ToOwned::to_owned));
+            c
+        };
+        assert_eq!(
+            created_at(&config(Some("2023-11-14T22:13:20Z"))),
+            DateTime::from_timestamp(1_700_000_000, 0)
+        );
+        assert_eq!(
+            created_at(&config(Some("2023-11-14T23:13:20+01:00"))),
+            DateTime::from_timestamp(1_700_000_000, 0)
+        );
+        assert!(created_at(&config(Some("yesterday"))).is_none());
+        assert!(created_at(&config(None)).is_none());
+    }
+}
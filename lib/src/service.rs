@@ -0,0 +1,791 @@
+//! # IPC service
+//!
+//! `bootc service` implements a small [varlink](https://varlink.org/) API,
+//! so that e.g. Cockpit can manage the host without parsing the output of
+//! the CLI.  It is socket activated via `bootc-service.socket`, listening on
+//! `/run/bootc/org.containers.bootc`, and exits once idle.
+//!
+//! Each method runs the corresponding `bootc` command as a subprocess, so
+//! locking, policy checks and so on are the same as on the command line.
+//! With `more`, the progress events of `--progress-fd` are sent as they
+//! happen, before the final reply.  Callers other than root are authorized
+//! via polkit, see `org.containers.bootc.policy`.
+//!
+//! When not running as root, `bootc status` and `bootc upgrade --check` are
+//! implemented by calling the service.
+
+use std::os::fd::AsFd;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use fn_error_context::context;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::unix::UCred;
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::deploy::UpdateCheck;
+use crate::spec::Host;
+
+/// The socket the service listens on, unless socket activated.
+const SOCKET_PATH: &str = "/run/bootc/org.containers.bootc";
+/// When socket activated, exit after being idle for this long.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// The interface implemented by the service.
+const INTERFACE: &str = "org.containers.bootc";
+/// The description of [`INTERFACE`], in the varlink interface definition language.
+const INTERFACE_DESCRIPTION: &str = r#"# Manage a host via bootc
+interface org.containers.bootc
+
+# The host, as shown by `bootc status --format=json`
+method GetStatus() -> (host: object)
+
+# Check whether an update is available, as `bootc upgrade --check --format=json`
+method CheckUpdate() -> (update: object)
+
+# Fetch and stage an update; with `more`, progress events are sent first
+method Upgrade(apply: ?bool) -> (progress: ?object)
+
+# Switch to another image; with `more`, progress events are sent first
+method Switch(image: string, transport: ?string, apply: ?bool) -> (progress: ?object)
+
+# Queue the rollback deployment for the next boot
+method Rollback(apply: ?bool) -> ()
+
+# The bootc command failed
+error Failed (message: string)
+
+# The caller is not authorized for the polkit action
+error NotAuthorized (action: string)
+
+# The method would change the host, but the service runs with `--read-only`
+error ReadOnly (method: string)
+"#;
+/// The varlink service interface, implemented for introspection.
+const VARLINK_SERVICE: &str = "org.varlink.service";
+/// The description of [`VARLINK_SERVICE`].
+const VARLINK_SERVICE_DESCRIPTION: &str = r#"# The Varlink Service Interface is provided by every varlink service.
+interface org.varlink.service
+
+method GetInfo() -> (vendor: string, product: string, version: string, url: string, interfaces: []string)
+
+method GetInterfaceDescription(interface: string) -> (description: string)
+
+error InterfaceNotFound (interface: string)
+error MethodNotFound (method: string)
+error MethodNotImplemented (method: string)
+error InvalidParameter (parameter: string)
+"#;
+
+/// A method call.
+#[derive(Debug, Deserialize)]
+struct Call {
+    /// The fully qualified method name
+    method: String,
+    #[serde(default)]
+    parameters: Map<String, Value>,
+    /// Whether the caller accepts multiple replies
+    #[serde(default)]
+    more: bool,
+    /// Whether the caller does not want a reply
+    #[serde(default)]
+    oneway: bool,
+}
+
+/// A reply to a method call.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Reply {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(default)]
+    parameters: Value,
+    /// More replies follow
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    continues: bool,
+}
+
+impl Reply {
+    fn ok(parameters: Value) -> Self {
+        Self {
+            error: None,
+            parameters,
+            continues: false,
+        }
+    }
+
+    fn error(error: &str, parameters: Value) -> Self {
+        Self {
+            error: Some(error.to_owned()),
+            parameters,
+            continues: false,
+        }
+    }
+
+    /// The parameters of a successful reply, or the error.
+    fn into_result(self) -> Result<Value> {
+        let Some(error) = self.error else {
+            return Ok(self.parameters);
+        };
+        let param = |name: &str| self.parameters.get(name).and_then(Value::as_str);
+        match (error.as_str(), param("message"), param("action")) {
+            ("org.containers.bootc.Failed", Some(message), _) => anyhow::bail!("{message}"),
+            ("org.containers.bootc.NotAuthorized", _, Some(action)) => {
+                anyhow::bail!("Not authorized for {action}")
+            }
+            ("org.containers.bootc.ReadOnly", _, _) => {
+                anyhow::bail!("This operation is disabled in read-only mode")
+            }
+            _ => anyhow::bail!("{error}: {}", self.parameters),
+        }
+    }
+}
+
+/// The parameters of methods which may reboot into the new deployment.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ApplyParameters {
+    #[serde(default)]
+    apply: bool,
+}
+
+/// The parameters of `Switch`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SwitchParameters {
+    image: String,
+    transport: Option<String>,
+    #[serde(default)]
+    apply: bool,
+}
+
+/// A method implemented by the service.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Method {
+    GetInfo,
+    GetInterfaceDescription(String),
+    GetStatus,
+    CheckUpdate,
+    Upgrade {
+        apply: bool,
+    },
+    Switch {
+        image: String,
+        transport: Option<String>,
+        apply: bool,
+    },
+    Rollback {
+        apply: bool,
+    },
+}
+
+/// Why a method call is invalid.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum InvalidCall {
+    /// There is no such method
+    MethodNotFound,
+    /// The parameters are not valid
+    InvalidParameter(String),
+}
+
+impl Method {
+    /// Parse a call of the method `name` of [`INTERFACE`], e.g. `Upgrade`.
+    pub(crate) fn new(
+        name: &str,
+        params: &Map<String, Value>,
+    ) -> std::result::Result<Self, InvalidCall> {
+        let params = Value::Object(params.clone());
+        let invalid = |e: serde_json::Error| InvalidCall::InvalidParameter(e.to_string());
+        let apply = || {
+            serde_json::from_value::<ApplyParameters>(params.clone())
+                .map(|p| p.apply)
+                .map_err(invalid)
+        };
+        let r = match name {
+            "GetStatus" => Self::GetStatus,
+            "CheckUpdate" => Self::CheckUpdate,
+            "Upgrade" => Self::Upgrade { apply: apply()? },
+            "Switch" => {
+                let p: SwitchParameters =
+                    serde_json::from_value(params.clone()).map_err(invalid)?;
+                Self::Switch {
+                    image: p.image,
+                    transport: p.transport,
+                    apply: p.apply,
+                }
+            }
+            "Rollback" => Self::Rollback { apply: apply()? },
+            _ => return Err(InvalidCall::MethodNotFound),
+        };
+        Ok(r)
+    }
+
+    /// Parse a varlink call, returning the error reply if it is not valid.
+    fn parse(call: &Call) -> std::result::Result<Self, Reply> {
+        let r = match call.method.as_str() {
+            "org.varlink.service.GetInfo" => Ok(Self::GetInfo),
+            "org.varlink.service.GetInterfaceDescription" => {
+                match call.parameters.get("interface").and_then(Value::as_str) {
+                    Some(interface) => Ok(Self::GetInterfaceDescription(interface.to_owned())),
+                    None => Err(InvalidCall::InvalidParameter("interface".to_owned())),
+                }
+            }
+            method => method
+                .strip_prefix(INTERFACE)
+                .and_then(|name| name.strip_prefix('.'))
+                .ok_or(InvalidCall::MethodNotFound)
+                .and_then(|name| Self::new(name, &call.parameters)),
+        };
+        r.map_err(|e| match e {
+            InvalidCall::MethodNotFound => Reply::error(
+                "org.varlink.service.MethodNotFound",
+                json!({ "method": call.method }),
+            ),
+            InvalidCall::InvalidParameter(parameter) => Reply::error(
+                "org.varlink.service.InvalidParameter",
+                json!({ "parameter": parameter }),
+            ),
+        })
+    }
+
+    /// The polkit action a caller other than root must be authorized for.
+    fn action(&self) -> Option<&'static str> {
+        match self {
+            Self::GetInfo | Self::GetInterfaceDescription(_) | Self::GetStatus => None,
+            Self::CheckUpdate => Some("org.containers.bootc.check-update"),
+            Self::Upgrade { .. } => Some("org.containers.bootc.upgrade"),
+            Self::Switch { .. } => Some("org.containers.bootc.switch"),
+            Self::Rollback { .. } => Some("org.containers.bootc.rollback"),
+        }
+    }
+
+    /// Whether this method may change the state of the host.
+    pub(crate) fn is_mutating(&self) -> bool {
+        matches!(
+            self,
+            Self::Upgrade { .. } | Self::Switch { .. } | Self::Rollback { .. }
+        )
+    }
+
+    /// The arguments of the `bootc` command implementing this method.
+    pub(crate) fn args(&self) -> Vec<String> {
+        let apply = |apply: bool| apply.then(|| "--apply".to_owned());
+        // Progress events are written to stderr, see `run_bootc`
+        let progress = "--progress-fd=2".to_owned();
+        match self {
+            Self::GetInfo | Self::GetInterfaceDescription(_) => Vec::new(),
+            Self::GetStatus => ["status", "--format=json", "--format-version=1"]
+                .map(ToOwned::to_owned)
+                .to_vec(),
+            Self::CheckUpdate => ["upgrade", "--check", "--format=json"]
+                .map(ToOwned::to_owned)
+                .to_vec(),
+            Self::Upgrade { apply: a } => ["upgrade".to_owned(), progress]
+                .into_iter()
+                .chain(apply(*a))
+                .collect(),
+            Self::Switch {
+                image,
+                transport,
+                apply: a,
+            } => {
+                let mut args = vec!["switch".to_owned(), progress];
+                if let Some(transport) = transport {
+                    args.extend(["--transport".to_owned(), transport.clone()]);
+                }
+                args.extend(apply(*a));
+                args.extend(["--".to_owned(), image.clone()]);
+                args
+            }
+            Self::Rollback { apply: a } => std::iter::once("rollback".to_owned())
+                .chain(apply(*a))
+                .collect(),
+        }
+    }
+}
+
+/// Parse a line written to `--progress-fd`.
+fn parse_progress(line: &str) -> Option<Value> {
+    let v: Value = serde_json::from_str(line).ok()?;
+    v.get("type").and_then(Value::as_str)?;
+    Some(v)
+}
+
+/// The start time of a process, from the contents of `/proc/PID/stat`.
+fn parse_start_time(stat: &str) -> Result<u64> {
+    // The command name may contain anything, so skip past it
+    let (_, fields) = stat
+        .rsplit_once(')')
+        .ok_or_else(|| anyhow::anyhow!("Invalid stat"))?;
+    // The start time is the 22nd field; the remainder starts at the 3rd
+    let start = fields
+        .split_ascii_whitespace()
+        .nth(22 - 3)
+        .ok_or_else(|| anyhow::anyhow!("Missing start time in stat"))?;
+    Ok(start.parse()?)
+}
+
+/// Whether the peer is authorized for a polkit action; root always is.
+#[context("Checking authorization for {action}")]
+async fn authorized(peer: &UCred, action: &str) -> Result<bool> {
+    if peer.uid() == 0 {
+        return Ok(true);
+    }
+    let Some(pid) = peer.pid() else {
+        return Ok(false);
+    };
+    let stat = tokio::fs::read_to_string(format!("/proc/{pid}/stat")).await?;
+    let start = parse_start_time(&stat)?;
+    let process = format!("{pid},{start},{}", peer.uid());
+    let status = tokio::process::Command::new("pkcheck")
+        .args(["--action-id", action, "--process", &process])
+        .arg("--allow-user-interaction")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .await?;
+    Ok(status.success())
+}
+
+/// Serialize a reply, terminated by a NUL byte.
+fn encode_reply(reply: &Reply) -> Result<Vec<u8>> {
+    let mut buf = serde_json::to_vec(reply)?;
+    buf.push(0);
+    Ok(buf)
+}
+
+/// Serialize a progress event as a reply with `continues`.
+fn encode_progress(event: Value) -> Result<Vec<u8>> {
+    encode_reply(&Reply {
+        continues: true,
+        ..Reply::ok(json!({ "progress": event }))
+    })
+}
+
+/// Write a reply.
+async fn write_reply(w: &mut (impl AsyncWrite + Unpin), reply: &Reply) -> Result<()> {
+    w.write_all(&encode_reply(reply)?).await?;
+    Ok(())
+}
+
+/// Run `bootc` with the given arguments, returning its output.  If `progress`
+/// is given, the progress events are serialized via `encode` and written to it.
+/// With `read_only`, the command is run with `--read-only` too.
+pub(crate) async fn run_bootc(
+    args: &[String],
+    read_only: bool,
+    mut progress: Option<&mut (impl AsyncWrite + Unpin)>,
+    encode: impl Fn(Value) -> Result<Vec<u8>>,
+) -> Result<String> {
+    let mut child = tokio::process::Command::new("/proc/self/exe")
+        .args(read_only.then_some("--read-only"))
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let read_stdout = async {
+        let mut buf = String::new();
+        stdout.read_to_string(&mut buf).await?;
+        anyhow::Ok(buf)
+    };
+    // Everything on stderr which is not a progress event is e.g. an error
+    let read_stderr = async {
+        let mut messages = Vec::new();
+        let mut lines = BufReader::new(stderr).lines();
+        while let Some(line) = lines.next_line().await? {
+            match (parse_progress(&line), progress.as_deref_mut()) {
+                (Some(event), Some(w)) => w.write_all(&encode(event)?).await?,
+                (Some(_), None) => {}
+                (None, _) => messages.push(line),
+            }
+        }
+        anyhow::Ok(messages)
+    };
+    let (stdout, messages) = tokio::try_join!(read_stdout, read_stderr)?;
+    let status = child.wait().await?;
+    if !status.success() {
+        let messages = messages.join("\n");
+        anyhow::bail!("bootc {}: {status}: {messages}", args[0]);
+    }
+    Ok(stdout)
+}
+
+/// Handle a call, writing the reply (or replies).
+async fn handle_call(
+    call: Call,
+    peer: &UCred,
+    read_only: bool,
+    w: &mut (impl AsyncWrite + Unpin),
+) -> Result<()> {
+    let method = match Method::parse(&call) {
+        Ok(m) => m,
+        Err(reply) => return write_reply(w, &reply).await,
+    };
+    tracing::debug!("Call: {method:?}");
+    if read_only && method.is_mutating() {
+        tracing::warn!("Rejected mutating call in read-only mode: {method:?}");
+        let reply = Reply::error(
+            "org.containers.bootc.ReadOnly",
+            json!({ "method": call.method }),
+        );
+        return write_reply(w, &reply).await;
+    }
+    if let Some(action) = method.action() {
+        if !authorized(peer, action).await? {
+            let reply = Reply::error(
+                "org.containers.bootc.NotAuthorized",
+                json!({ "action": action }),
+            );
+            return write_reply(w, &reply).await;
+        }
+    }
+    let progress = call.more.then_some(&mut *w);
+    let r = match &method {
+        Method::GetInfo => Ok(json!({
+            "vendor": "bootc",
+            "product": "bootc",
+            "version": env!("CARGO_PKG_VERSION"),
+            "url": "https://github.com/containers/bootc",
+            "interfaces": [VARLINK_SERVICE, INTERFACE],
+        })),
+        Method::GetInterfaceDescription(interface) => {
+            let description = match interface.as_str() {
+                VARLINK_SERVICE => VARLINK_SERVICE_DESCRIPTION,
+                INTERFACE => INTERFACE_DESCRIPTION,
+                _ => {
+                    let reply = Reply::error(
+                        "org.varlink.service.InterfaceNotFound",
+                        json!({ "interface": interface }),
+                    );
+                    return write_reply(w, &reply).await;
+                }
+            };
+            Ok(json!({ "description": description }))
+        }
+        Method::GetStatus => run_bootc(&method.args(), read_only, progress, encode_progress)
+            .await
+            .and_then(|out| Ok(json!({ "host": serde_json::from_str::<Value>(&out)? }))),
+        Method::CheckUpdate => run_bootc(&method.args(), read_only, progress, encode_progress)
+            .await
+            .and_then(|out| Ok(json!({ "update": serde_json::from_str::<Value>(&out)? }))),
+        Method::Upgrade { .. } | Method::Switch { .. } | Method::Rollback { .. } => {
+            run_bootc(&method.args(), read_only, progress, encode_progress)
+                .await
+                .map(|_| json!({}))
+        }
+    };
+    let reply = match r {
+        Ok(parameters) => Reply::ok(parameters),
+        Err(e) => Reply::error(
+            "org.containers.bootc.Failed",
+            json!({ "message": format!("{e:#}") }),
+        ),
+    };
+    if call.oneway {
+        return Ok(());
+    }
+    write_reply(w, &reply).await
+}
+
+/// Handle the calls on a connection until it is closed.
+async fn handle_connection(stream: UnixStream, read_only: bool) -> Result<()> {
+    let peer = stream.peer_cred()?;
+    let (r, mut w) = stream.into_split();
+    let mut r = BufReader::new(r);
+    loop {
+        let mut buf = Vec::new();
+        if r.read_until(0, &mut buf).await? == 0 {
+            return Ok(());
+        }
+        if buf.pop() != Some(0) {
+            anyhow::bail!("Truncated message");
+        }
+        let call = serde_json::from_slice(&buf).context("Parsing call")?;
+        handle_call(call, &peer, read_only, &mut w).await?;
+    }
+}
+
+/// Call a method of [`INTERFACE`], returning the parameters of the reply.
+#[context("Calling {method} via bootc-service.socket")]
+async fn call(method: &str, parameters: Value) -> Result<Value> {
+    let stream = UnixStream::connect(SOCKET_PATH)
+        .await
+        .with_context(|| format!("Connecting to {SOCKET_PATH}"))?;
+    let mut stream = BufReader::new(stream);
+    let call = json!({
+        "method": format!("{INTERFACE}.{method}"),
+        "parameters": parameters,
+    });
+    let mut buf = serde_json::to_vec(&call)?;
+    buf.push(0);
+    stream.get_mut().write_all(&buf).await?;
+    let mut buf = Vec::new();
+    stream.read_until(0, &mut buf).await?;
+    if buf.pop() != Some(0) {
+        anyhow::bail!("Connection closed");
+    }
+    let reply: Reply = serde_json::from_slice(&buf).context("Parsing reply")?;
+    reply.into_result()
+}
+
+/// The host status, as seen by the service; used by `bootc status` when not
+/// running as root.
+pub(crate) async fn get_status() -> Result<Host> {
+    let mut r = call("GetStatus", json!({})).await?;
+    serde_json::from_value(r["host"].take()).context("Parsing host")
+}
+
+/// Check for an update via the service; used by `bootc upgrade --check` when
+/// not running as root.
+pub(crate) async fn check_update() -> Result<UpdateCheck> {
+    let mut r = call("CheckUpdate", json!({})).await?;
+    serde_json::from_value(r["update"].take()).context("Parsing update check")
+}
+
+/// The listening socket, and whether it was passed via socket activation.
+fn listener() -> Result<(UnixListener, bool)> {
+    // bootc-service.service uses `StandardInput=socket`
+    let stdin = std::io::stdin();
+    let st = rustix::fs::fstat(&stdin)?;
+    if rustix::fs::FileType::from_raw_mode(st.st_mode) == rustix::fs::FileType::Socket {
+        let fd = stdin.as_fd().try_clone_to_owned()?;
+        let listener = std::os::unix::net::UnixListener::from(fd);
+        listener.set_nonblocking(true)?;
+        return Ok((UnixListener::from_std(listener)?, true));
+    }
+    let path = std::path::Path::new(SOCKET_PATH);
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    // Access is controlled via polkit
+    std::fs::set_permissions(path, std::os::unix::fs::PermissionsExt::from_mode(0o666))?;
+    Ok((listener, false))
+}
+
+/// Implementation of `bootc service`.  With `read_only`, the methods which
+/// would change the host are rejected.
+#[context("Running service")]
+pub(crate) async fn run(read_only: bool) -> Result<()> {
+    let (listener, activated) = listener()?;
+    // Each connection holds a reference
+    let active = Arc::new(());
+    loop {
+        let accepted = if activated {
+            match tokio::time::timeout(IDLE_TIMEOUT, listener.accept()).await {
+                Ok(r) => r,
+                Err(_) if Arc::strong_count(&active) == 1 => {
+                    tracing::debug!("Exiting after being idle");
+                    return Ok(());
+                }
+                Err(_) => continue,
+            }
+        } else {
+            listener.accept().await
+        };
+        let (stream, _) = accepted?;
+        let active = Arc::clone(&active);
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, read_only).await {
+                tracing::warn!("Connection: {e:#}");
+            }
+            drop(active);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(method: &str, parameters: Value) -> Call {
+        Call {
+            method: method.to_owned(),
+            parameters: parameters.as_object().unwrap().clone(),
+            more: false,
+            oneway: false,
+        }
+    }
+
+    #[test]
+    fn test_method() {
+        let parse = |method, parameters| Method::parse(&call(method, parameters));
+        assert_eq!(
+            parse("org.containers.bootc.GetStatus", json!({})).unwrap(),
+            Method::GetStatus
+        );
+        let upgrade = parse("org.containers.bootc.Upgrade", json!({ "apply": true })).unwrap();
+        assert_eq!(upgrade, Method::Upgrade { apply: true });
+        assert_eq!(upgrade.args(), ["upgrade", "--progress-fd=2", "--apply"]);
+        assert_eq!(upgrade.action(), Some("org.containers.bootc.upgrade"));
+        let switch = parse(
+            "org.containers.bootc.Switch",
+            json!({ "image": "quay.io/example/os:latest", "transport": "registry" }),
+        )
+        .unwrap();
+        assert_eq!(
+            switch.args(),
+            [
+                "switch",
+                "--progress-fd=2",
+                "--transport",
+                "registry",
+                "--",
+                "quay.io/example/os:latest"
+            ]
+        );
+        let rollback = parse("org.containers.bootc.Rollback", json!({})).unwrap();
+        assert_eq!(rollback.args(), ["rollback"]);
+        assert_eq!(Method::GetStatus.action(), None);
+        assert!(upgrade.is_mutating() && switch.is_mutating() && rollback.is_mutating());
+        assert!(!Method::GetStatus.is_mutating());
+        assert!(!Method::CheckUpdate.is_mutating());
+
+        let e = parse("org.containers.bootc.Switch", json!({})).unwrap_err();
+        assert_eq!(
+            e.error.as_deref(),
+            Some("org.varlink.service.InvalidParameter")
+        );
+        let e = parse("org.containers.bootc.Upgrade", json!({ "bogus": 1 })).unwrap_err();
+        assert_eq!(
+            e.error.as_deref(),
+            Some("org.varlink.service.InvalidParameter")
+        );
+        let e = parse("org.containers.bootc.Frobnicate", json!({})).unwrap_err();
+        assert_eq!(
+            e,
+            Reply::error(
+                "org.varlink.service.MethodNotFound",
+                json!({ "method": "org.containers.bootc.Frobnicate" })
+            )
+        );
+    }
+
+    #[test]
+    fn test_reply_result() {
+        let r = Reply::ok(json!({ "host": {} })).into_result().unwrap();
+        assert_eq!(r, json!({ "host": {} }));
+        let failed = Reply::error(
+            "org.containers.bootc.Failed",
+            json!({ "message": "bootc upgrade: exit status: 1: error: Upgrading: oops" }),
+        );
+        assert_eq!(
+            failed.into_result().unwrap_err().to_string(),
+            "bootc upgrade: exit status: 1: error: Upgrading: oops"
+        );
+        let denied = Reply::error(
+            "org.containers.bootc.NotAuthorized",
+            json!({ "action": "org.containers.bootc.upgrade" }),
+        );
+        assert_eq!(
+            denied.into_result().unwrap_err().to_string(),
+            "Not authorized for org.containers.bootc.upgrade"
+        );
+        // Replies as sent over the wire
+        let reply: Reply = serde_json::from_str(
+            r#"{"error":"org.varlink.service.MethodNotFound","parameters":{"method":"x.Y"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            reply.into_result().unwrap_err().to_string(),
+            r#"org.varlink.service.MethodNotFound: {"method":"x.Y"}"#
+        );
+    }
+
+    #[test]
+    fn test_parse_progress() {
+        let event = parse_progress(r#"{"type":"phase","name":"deploy"}"#).unwrap();
+        assert_eq!(event, json!({ "type": "phase", "name": "deploy" }));
+        assert!(parse_progress("error: Upgrading: Pulling: oops").is_none());
+        assert!(parse_progress(r#"{"name":"deploy"}"#).is_none());
+    }
+
+    #[test]
+    fn test_parse_start_time() -> Result<()> {
+        let stat = "1234 (some (odd) name) S 1 1234 1234 0 -1 4194560 1046 0 0 0 2 1 0 0 20 0 1 0 987654 22511616 2401 18446744073709551615";
+        assert_eq!(parse_start_time(stat)?, 987654);
+        assert!(parse_start_time("1234 (truncated) S 1").is_err());
+        Ok(())
+    }
+
+    /// Send a call, and return the reply.
+    async fn roundtrip(client: &mut BufReader<UnixStream>, call: Value) -> Result<Value> {
+        let mut buf = serde_json::to_vec(&call)?;
+        buf.push(0);
+        client.get_mut().write_all(&buf).await?;
+        let mut reply = Vec::new();
+        client.read_until(0, &mut reply).await?;
+        assert_eq!(reply.pop(), Some(0));
+        Ok(serde_json::from_slice(&reply)?)
+    }
+
+    #[test]
+    fn test_connection() -> Result<()> {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(async {
+                let (client, server) = UnixStream::pair()?;
+                let server = tokio::spawn(handle_connection(server, false));
+                let client = &mut BufReader::new(client);
+                let info =
+                    roundtrip(client, json!({ "method": "org.varlink.service.GetInfo" })).await?;
+                assert_eq!(
+                    info["parameters"]["interfaces"],
+                    json!([VARLINK_SERVICE, INTERFACE])
+                );
+                let desc = roundtrip(
+                    client,
+                    json!({
+                        "method": "org.varlink.service.GetInterfaceDescription",
+                        "parameters": { "interface": INTERFACE },
+                    }),
+                )
+                .await?;
+                assert_eq!(desc["parameters"]["description"], INTERFACE_DESCRIPTION);
+                let e = roundtrip(
+                    client,
+                    json!({
+                        "method": "org.varlink.service.GetInterfaceDescription",
+                        "parameters": { "interface": "com.example.Other" },
+                    }),
+                )
+                .await?;
+                assert_eq!(e["error"], "org.varlink.service.InterfaceNotFound");
+                // Closing the connection ends the handler
+                client.get_mut().shutdown().await?;
+                server.await??;
+                Ok(())
+            })
+    }
+
+    #[test]
+    fn test_connection_read_only() -> Result<()> {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(async {
+                let (client, server) = UnixStream::pair()?;
+                let server = tokio::spawn(handle_connection(server, true));
+                let client = &mut BufReader::new(client);
+                for method in ["Upgrade", "Rollback"] {
+                    let method = format!("{INTERFACE}.{method}");
+                    let e = roundtrip(client, json!({ "method": method })).await?;
+                    assert_eq!(e["error"], "org.containers.bootc.ReadOnly");
+                    assert_eq!(e["parameters"]["method"], method.as_str());
+                }
+                let info =
+                    roundtrip(client, json!({ "method": "org.varlink.service.GetInfo" })).await?;
+                assert!(info.get("error").is_none());
+                client.get_mut().shutdown().await?;
+                server.await??;
+                Ok(())
+            })
+    }
+}
@@ -0,0 +1,136 @@
+//! # Protecting deployments from shutdown
+//!
+//! While a deployment is written, e.g. by `bootc upgrade`, bootc holds a
+//! systemd-logind inhibitor lock via `systemd-inhibit`, so that a shutdown or
+//! reboot requested meanwhile is refused instead of interrupting it.  The
+//! staged deployment is finalized by `ostree-finalize-staged.service` during
+//! shutdown; while that runs, `bootc status` shows it as the operation in
+//! progress.
+
+use std::process::{Child, Command, Stdio};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use crate::spec::OperationInProgress;
+use crate::task::Task;
+
+/// The unit finalizing the staged deployment at shutdown.
+const FINALIZE_UNIT: &str = "ostree-finalize-staged.service";
+/// The operation shown while [`FINALIZE_UNIT`] runs.
+const FINALIZE_OPERATION: &str = "finalize-staged";
+
+/// Holds an inhibitor lock until dropped.
+#[derive(Debug)]
+pub(crate) struct Inhibitor {
+    child: Option<Child>,
+}
+
+impl Inhibitor {
+    /// Block shutdown and sleep with the given reason.  If this is not
+    /// possible, e.g. in a container, the operation proceeds unprotected.
+    pub(crate) fn new(why: &str) -> Self {
+        if !libsystemd::daemon::booted() {
+            return Self { child: None };
+        }
+        // A delay lock would only postpone a shutdown by InhibitDelayMaxSec
+        // (by default 5 seconds), which is not long enough to finish writing
+        // a deployment.
+        let child = Command::new("systemd-inhibit")
+            .args([
+                "--what=shutdown:sleep",
+                "--mode=block",
+                "--who=bootc",
+                &format!("--why={why}"),
+                "sleep",
+                "infinity",
+            ])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn();
+        match child {
+            Ok(child) => Self { child: Some(child) },
+            Err(e) => {
+                tracing::warn!("Failed to inhibit shutdown: {e}");
+                Self { child: None }
+            }
+        }
+    }
+}
+
+impl Drop for Inhibitor {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Parse the output of `systemctl show --timestamp=unix` for the properties
+/// `ActiveState`, `ControlPID` and `StateChangeTimestamp` of [`FINALIZE_UNIT`].
+fn parse_finalizing(show: &str) -> Option<OperationInProgress> {
+    let mut state = None;
+    let mut pid = None;
+    let mut started = None;
+    for line in show.lines() {
+        match line.split_once('=') {
+            Some(("ActiveState", v)) => state = Some(v),
+            Some(("ControlPID", v)) => pid = v.parse().ok(),
+            Some(("StateChangeTimestamp", v)) => {
+                started = v
+                    .strip_prefix('@')
+                    .and_then(|v| v.parse().ok())
+                    .and_then(|t| DateTime::<Utc>::from_timestamp(t, 0))
+            }
+            _ => {}
+        }
+    }
+    // The unit is active from staging on; its stop job finalizes
+    if state != Some("deactivating") {
+        return None;
+    }
+    Some(OperationInProgress {
+        operation: FINALIZE_OPERATION.to_owned(),
+        pid: pid.unwrap_or_default(),
+        started: started.unwrap_or_else(Utc::now),
+    })
+}
+
+/// The finalization of the staged deployment, if it is in progress.
+pub(crate) fn finalizing() -> Result<Option<OperationInProgress>> {
+    if !libsystemd::daemon::booted() {
+        return Ok(None);
+    }
+    let show = Task::new_quiet("systemctl")
+        .args([
+            "show",
+            "--timestamp=unix",
+            "--property=ActiveState,ControlPID,StateChangeTimestamp",
+            FINALIZE_UNIT,
+        ])
+        .read()?;
+    Ok(parse_finalizing(&show))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_finalizing() {
+        let active = "ActiveState=active\nControlPID=0\nStateChangeTimestamp=@1697311335\n";
+        assert_eq!(parse_finalizing(active), None);
+        assert_eq!(parse_finalizing(""), None);
+        let finalizing =
+            "ActiveState=deactivating\nControlPID=1234\nStateChangeTimestamp=@1697311335\n";
+        assert_eq!(
+            parse_finalizing(finalizing),
+            Some(OperationInProgress {
+                operation: "finalize-staged".into(),
+                pid: 1234,
+                started: DateTime::from_timestamp(1697311335, 0).unwrap(),
+            })
+        );
+    }
+}
@@ -0,0 +1,341 @@
+//! # Requiring signature verification
+//!
+//! `--require-signature` on `bootc switch` and `bootc upgrade` refuses to fetch
+//! images unless they are verified in the given way.  For `sigstore`, the
+//! requirement which `containers-policy.json(5)` resolves to for the image must
+//! include `sigstoreSigned`; for `ostree-remote:NAME`, the ostree remote must
+//! exist and verify signatures.  The requirement is recorded in the origin of
+//! the staged deployment, so that later upgrades (and switches) keep enforcing
+//! it without the option.
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use cap_std_ext::cap_std::fs::Dir;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use fn_error_context::context;
+use ostree_ext::keyfileext::KeyFileExt;
+use ostree_ext::ostree;
+
+use crate::spec::{ImageReference, ImageSignature};
+
+/// The signature policy, relative to the root.
+const POLICY_PATH: &str = "etc/containers/policy.json";
+/// The origin key recording the requirement.
+pub(crate) const ORIGIN_REQUIRE_SIGNATURE: &str = "require-signature";
+
+/// How an image is required to be verified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum SignatureRequirement {
+    /// A sigstore signature, per the container signature policy
+    Sigstore,
+    /// A signature verified via the given ostree remote
+    OstreeRemote(String),
+}
+
+impl FromStr for SignatureRequirement {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            None if s == "sigstore" => Ok(Self::Sigstore),
+            Some(("ostree-remote", remote)) if !remote.is_empty() => {
+                Ok(Self::OstreeRemote(remote.to_owned()))
+            }
+            _ => anyhow::bail!("Expected `sigstore` or `ostree-remote:NAME`, not {s}"),
+        }
+    }
+}
+
+impl std::fmt::Display for SignatureRequirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Sigstore => f.write_str("sigstore"),
+            Self::OstreeRemote(remote) => write!(f, "ostree-remote:{remote}"),
+        }
+    }
+}
+
+impl SignatureRequirement {
+    /// The signature verification to use for images.
+    fn signature(&self) -> ImageSignature {
+        match self {
+            Self::Sigstore => ImageSignature::ContainerPolicy,
+            Self::OstreeRemote(remote) => ImageSignature::OstreeRemote(remote.clone()),
+        }
+    }
+}
+
+/// Normalize a registry image name as the container tools do, e.g.
+/// `fedora` to `docker.io/library/fedora`.
+fn normalize_image(image: &str) -> String {
+    match image.split_once('/') {
+        Some((host, _)) if host.contains(['.', ':']) || host == "localhost" => image.to_owned(),
+        Some(_) => format!("docker.io/{image}"),
+        None => format!("docker.io/library/{image}"),
+    }
+}
+
+/// The scopes of `containers-policy.json` matching a registry image, from
+/// the most to the least specific; see `containers-policy.json(5)`.
+fn docker_scopes(image: &str) -> Vec<String> {
+    let image = normalize_image(image);
+    let mut scopes = vec![image.clone()];
+    // Strip the digest or tag
+    let repo = crate::deploy::image_repository(&image);
+    let mut scope = repo;
+    loop {
+        if scopes.last().map(|s| s.as_str()) != Some(scope) {
+            scopes.push(scope.to_owned());
+        }
+        match scope.rsplit_once('/') {
+            Some((parent, _)) => scope = parent,
+            None => break,
+        }
+    }
+    // Wildcards matching subdomains of the registry host
+    let host = scope.split_once(':').map_or(scope, |(host, _)| host);
+    let mut domain = host;
+    while let Some((_, parent)) = domain.split_once('.') {
+        scopes.push(format!("*.{parent}"));
+        domain = parent;
+    }
+    scopes
+}
+
+/// The requirement types which the policy resolves to for a registry image.
+fn resolve_policy(policy: &serde_json::Value, image: &str) -> Result<Vec<String>> {
+    let docker = policy.get("transports").and_then(|t| t.get("docker"));
+    let requirements = docker
+        .and_then(|d| {
+            docker_scopes(image)
+                .iter()
+                .chain(std::iter::once(&String::new()))
+                .find_map(|scope| d.get(scope.as_str()))
+        })
+        .or_else(|| policy.get("default"))
+        .ok_or_else(|| anyhow!("No default policy"))?;
+    requirements
+        .as_array()
+        .ok_or_else(|| anyhow!("Invalid policy requirements"))?
+        .iter()
+        .map(|r| {
+            r.get("type")
+                .and_then(|t| t.as_str())
+                .map(ToOwned::to_owned)
+                .ok_or_else(|| anyhow!("Policy requirement without type"))
+        })
+        .collect()
+}
+
+/// Load the signature policy, if present.
+fn load_policy(root: &Dir) -> Result<Option<serde_json::Value>> {
+    root.open_optional(POLICY_PATH)?
+        .map(|f| serde_json::from_reader(std::io::BufReader::new(f)))
+        .transpose()
+        .with_context(|| format!("Parsing /{POLICY_PATH}"))
+}
+
+/// Check that the signature policy requires sigstore signatures for the image.
+fn check_sigstore(root: &Dir, imgref: &ImageReference) -> Result<()> {
+    if imgref.transport != "registry" {
+        anyhow::bail!("Sigstore signatures require the registry transport");
+    }
+    let policy = load_policy(root)?.ok_or_else(|| anyhow!("/{POLICY_PATH} not found"))?;
+    let types = resolve_policy(&policy, &imgref.image)?;
+    if !types.iter().any(|t| t == "sigstoreSigned") {
+        anyhow::bail!(
+            "The policy for {} does not require sigstore signatures (found: {})",
+            imgref.image,
+            types.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Describe how the image would be verified when fetched; for registry images,
+/// this includes the requirement types of the signature policy.
+pub(crate) fn describe(root: &Dir, imgref: &ImageReference) -> Result<String> {
+    if let Some(ImageSignature::OstreeRemote(remote)) = imgref.signature.as_ref() {
+        return Ok(format!("ostree remote {remote}"));
+    }
+    if imgref.transport != "registry" {
+        return Ok("container policy".to_owned());
+    }
+    match load_policy(root)? {
+        Some(policy) => {
+            let types = resolve_policy(&policy, &imgref.image)?;
+            Ok(format!("container policy ({})", types.join(", ")))
+        }
+        None => Ok(format!("container policy (/{POLICY_PATH} not found)")),
+    }
+}
+
+/// Check that the image would be verified per the requirement.
+#[context("Checking signature requirement {requirement}")]
+fn check(
+    requirement: &SignatureRequirement,
+    root: &Dir,
+    repo: &ostree::Repo,
+    imgref: &ImageReference,
+) -> Result<()> {
+    match requirement {
+        SignatureRequirement::Sigstore => check_sigstore(root, imgref),
+        SignatureRequirement::OstreeRemote(remote) => {
+            if !repo.remote_list().iter().any(|r| r == remote) {
+                anyhow::bail!("Remote {remote} not found");
+            }
+            let sign_verify = repo.remote_option(remote, "sign-verify", Some("false"))?;
+            if !repo.remote_get_gpg_verify(remote)? && sign_verify.as_deref() != Some("true") {
+                anyhow::bail!("Remote {remote} does not verify signatures");
+            }
+            Ok(())
+        }
+    }
+}
+
+/// The requirement recorded in the origin of a deployment.
+pub(crate) fn requirement_of(
+    deployment: &ostree::Deployment,
+) -> Result<Option<SignatureRequirement>> {
+    let Some(origin) = deployment.origin() else {
+        return Ok(None);
+    };
+    origin
+        .optional_string(crate::deploy::ORIGIN_BOOTC_GROUP, ORIGIN_REQUIRE_SIGNATURE)?
+        .map(|v| v.parse())
+        .transpose()
+        .context("Parsing origin")
+}
+
+/// Enforce the requirement given on the command line, or else the one recorded
+/// for the current deployment, on the image: its verification is set to match
+/// and checked.  Returns the requirement to record for the new deployment.
+pub(crate) fn enforce(
+    root: &Dir,
+    repo: &ostree::Repo,
+    requirement: Option<&SignatureRequirement>,
+    current: &ostree::Deployment,
+    imgref: &mut ImageReference,
+) -> Result<Option<SignatureRequirement>> {
+    let requirement = match requirement {
+        Some(r) => r.clone(),
+        None => match requirement_of(current)? {
+            Some(r) => r,
+            None => return Ok(None),
+        },
+    };
+    imgref.signature = Some(requirement.signature());
+    check(&requirement, root, repo, imgref)?;
+    Ok(Some(requirement))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_requirement() {
+        for (s, expected) in [
+            ("sigstore", SignatureRequirement::Sigstore),
+            (
+                "ostree-remote:fedora",
+                SignatureRequirement::OstreeRemote("fedora".into()),
+            ),
+        ] {
+            let r: SignatureRequirement = s.parse().unwrap();
+            assert_eq!(r, expected);
+            assert_eq!(r.to_string(), s);
+        }
+        for invalid in ["", "gpg", "ostree-remote:", "sigstore:foo"] {
+            assert!(
+                invalid.parse::<SignatureRequirement>().is_err(),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_docker_scopes() {
+        assert_eq!(
+            docker_scopes("quay.io/example/os:latest"),
+            [
+                "quay.io/example/os:latest",
+                "quay.io/example/os",
+                "quay.io/example",
+                "quay.io",
+                "*.io"
+            ]
+        );
+        assert_eq!(
+            docker_scopes("fedora"),
+            [
+                "docker.io/library/fedora",
+                "docker.io/library",
+                "docker.io",
+                "*.io"
+            ]
+        );
+        assert_eq!(
+            docker_scopes("localhost:5000/os"),
+            ["localhost:5000/os", "localhost:5000"]
+        );
+    }
+
+    #[test]
+    fn test_resolve_policy() -> Result<()> {
+        let policy = serde_json::json!({
+            "default": [{"type": "insecureAcceptAnything"}],
+            "transports": {
+                "docker": {
+                    "quay.io/example": [
+                        {"type": "sigstoreSigned", "keyPath": "/etc/pki/example.pub"}
+                    ],
+                    "quay.io/example/unsigned": [{"type": "insecureAcceptAnything"}],
+                    "*.example.com": [{"type": "reject"}]
+                }
+            }
+        });
+        let resolve = |image| resolve_policy(&policy, image).unwrap();
+        assert_eq!(resolve("quay.io/example/os:latest"), ["sigstoreSigned"]);
+        assert_eq!(
+            resolve("quay.io/example/unsigned:latest"),
+            ["insecureAcceptAnything"]
+        );
+        assert_eq!(resolve("registry.example.com/os"), ["reject"]);
+        assert_eq!(resolve("quay.io/other/os"), ["insecureAcceptAnything"]);
+        assert!(resolve_policy(&serde_json::json!({}), "quay.io/other/os").is_err());
+
+        let td =
+            cap_std_ext::cap_tempfile::TempDir::new(cap_std_ext::cap_std::ambient_authority())?;
+        let imgref = |image: &str| ImageReference {
+            image: image.into(),
+            transport: "registry".into(),
+            signature: None,
+        };
+        assert!(check_sigstore(&td, &imgref("quay.io/example/os:latest")).is_err());
+        assert_eq!(
+            describe(&td, &imgref("quay.io/example/os:latest"))?,
+            "container policy (/etc/containers/policy.json not found)"
+        );
+        td.create_dir_all("etc/containers")?;
+        td.write(POLICY_PATH, serde_json::to_vec(&policy)?)?;
+        check_sigstore(&td, &imgref("quay.io/example/os:latest"))?;
+        assert_eq!(
+            describe(&td, &imgref("quay.io/example/os:latest"))?,
+            "container policy (sigstoreSigned)"
+        );
+        let remote = ImageReference {
+            signature: Some(ImageSignature::OstreeRemote("fedora".into())),
+            ..imgref("quay.io/example/os:latest")
+        };
+        assert_eq!(describe(&td, &remote)?, "ostree remote fedora");
+        assert!(check_sigstore(&td, &imgref("quay.io/other/os")).is_err());
+        let oci = ImageReference {
+            transport: "oci".into(),
+            ..imgref("/var/mnt/os")
+        };
+        assert!(check_sigstore(&td, &oci).is_err());
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 //! The definition for host system state.
 
+use std::collections::BTreeMap;
 use std::fmt::Display;
 
 use ostree_ext::container::OstreeImageReference;
@@ -62,6 +63,10 @@ pub struct HostSpec {
     /// If set, and there is a rollback deployment, it will be set for the next boot.
     #[serde(default)]
     pub boot_order: BootOrder,
+    /// Kernel arguments to add to those of the image and the system, replacing
+    /// those previously added this way; if unset, they are kept as they are.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kargs: Option<Vec<String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
@@ -101,6 +106,15 @@ pub struct ImageStatus {
     pub timestamp: Option<chrono::DateTime<chrono::Utc>>,
     /// The digest of the fetched image (e.g. sha256:a0...);
     pub image_digest: String,
+    /// Image labels selected via `status.labels` in `/usr/lib/bootc/config.toml`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub labels: Option<BTreeMap<String, String>>,
+    /// The platform of the image, in the form `os/architecture[/variant]` (e.g. `linux/arm64`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    /// The registry mirror the image was fetched from, if not its own location
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fetched_from: Option<String>,
 }
 
 /// A bootable entry
@@ -111,6 +125,21 @@ pub struct BootEntryOstree {
     pub checksum: String,
     /// The deployment serial
     pub deploy_serial: u32,
+    /// The version string from the commit metadata, if any
+    #[serde(default)]
+    pub version: Option<String>,
+    /// The human readable source of the commit (`ostree.source-title`), if any
+    #[serde(default)]
+    pub source_title: Option<String>,
+    /// Whether the commit is marked as bootable (`ostree.bootable`)
+    #[serde(default)]
+    pub bootable: bool,
+    /// The commit timestamp
+    #[serde(default)]
+    pub timestamp: Option<chrono::DateTime<chrono::Utc>>,
+    /// The stateroot holding the deployment
+    #[serde(default)]
+    pub stateroot: Option<String>,
 }
 
 /// A bootable entry
@@ -125,11 +154,41 @@ pub struct BootEntry {
     pub incompatible: bool,
     /// Whether this entry will be subject to garbage collection
     pub pinned: bool,
+    /// Whether this (staged) entry has the same kernel, initramfs and kernel arguments
+    /// as the booted entry, so that it can be applied via `systemctl soft-reboot`
+    #[serde(default)]
+    pub soft_reboot_capable: bool,
     /// The container storage backend
     #[serde(default)]
     pub store: Option<Store>,
     /// If this boot entry is ostree based, the corresponding state
     pub ostree: Option<BootEntryOstree>,
+    /// The result of the last health checks run while this entry was booted
+    #[serde(default)]
+    pub health: Option<HealthStatus>,
+    /// The unified kernel image shipped by this entry, if any
+    #[serde(default)]
+    pub uki: Option<UkiStatus>,
+}
+
+/// A unified kernel image (UKI), which is booted via systemd-boot
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UkiStatus {
+    /// The path of the UKI in the image, e.g. `/usr/lib/modules/6.12.0/uki.efi`
+    pub path: String,
+    /// Whether the system was booted via this UKI
+    pub booted: bool,
+}
+
+/// The result of running the health checks in `/usr/lib/bootc/health.d`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthStatus {
+    /// When the health checks were run
+    pub checked: chrono::DateTime<chrono::Utc>,
+    /// The names of the health checks which failed; empty if all succeeded
+    pub failed: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
@@ -142,6 +201,28 @@ pub enum HostType {
     BootcHost,
 }
 
+/// A bootc operation (e.g. `upgrade`) which is in progress
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationInProgress {
+    /// The bootc command, e.g. `upgrade`
+    pub operation: String,
+    /// The process ID of bootc
+    pub pid: u32,
+    /// When the operation started
+    pub started: chrono::DateTime<chrono::Utc>,
+}
+
+impl std::fmt::Display for OperationInProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "another transaction in progress: {} started at {} (pid {})",
+            self.operation, self.started, self.pid
+        )
+    }
+}
+
 /// The status of the host system
 #[derive(Debug, Clone, Serialize, Default, Deserialize, PartialEq, Eq, JsonSchema)]
 #[serde(rename_all = "camelCase")]
@@ -159,6 +240,28 @@ pub struct HostStatus {
     /// The detected type of system
     #[serde(rename = "type")]
     pub ty: Option<HostType>,
+
+    /// The bootc operation holding the lock, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_progress: Option<OperationInProgress>,
+
+    /// The newest deployment of each stateroot other than the booted one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub other_stateroots: Vec<BootEntry>,
+
+    /// The effective policy for retaining previous deployments
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention: Option<RetentionPolicy>,
+}
+
+/// Which previous deployments are kept when an update is staged
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionPolicy {
+    /// The number of rollback deployments kept, including the booted one
+    pub rollbacks: u32,
+    /// Whether pinned deployments are kept in addition
+    pub pinned: bool,
 }
 
 impl Host {
@@ -178,6 +281,20 @@ impl Host {
             status: Default::default(),
         }
     }
+
+    /// Verify that this is a host resource, as opposed to e.g. another kind
+    /// of Kubernetes object.
+    pub(crate) fn verify_kind(&self) -> anyhow::Result<()> {
+        let (group, _version) = API_VERSION.split_once('/').unwrap();
+        let resource = &self.resource;
+        if resource.api_version.split_once('/').map(|(g, _)| g) != Some(group) {
+            anyhow::bail!("Unsupported apiVersion: {}", resource.api_version);
+        }
+        if resource.kind != KIND {
+            anyhow::bail!("Unsupported kind: {}, expected {KIND}", resource.kind);
+        }
+        Ok(())
+    }
 }
 
 impl Default for Host {
@@ -195,6 +312,9 @@ impl HostSpec {
         if rollback && image_change {
             anyhow::bail!("Invalid state transition: rollback and image change");
         }
+        if rollback && self.kargs != new.kargs {
+            anyhow::bail!("Invalid state transition: rollback and kernel argument change");
+        }
         Ok(())
     }
 }
@@ -271,6 +391,46 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_verify_kind() {
+        let mut host = Host::default();
+        host.verify_kind().unwrap();
+        host.resource.api_version = "org.containers.bootc/v1alpha1".into();
+        host.verify_kind().unwrap();
+        host.resource.api_version = "v1".into();
+        assert!(host.verify_kind().is_err());
+        let mut host = Host::default();
+        host.resource.kind = "ConfigMap".into();
+        assert!(host.verify_kind().is_err());
+    }
+
+    #[test]
+    fn test_verify_transition() {
+        let spec = HostSpec {
+            image: Some(ImageReference {
+                image: "quay.io/example/someimage:latest".into(),
+                transport: "registry".into(),
+                signature: None,
+            }),
+            ..Default::default()
+        };
+        let kargs = HostSpec {
+            kargs: Some(vec!["console=ttyS0".into()]),
+            ..spec.clone()
+        };
+        spec.verify_transition(&kargs).unwrap();
+        let rollback = HostSpec {
+            boot_order: BootOrder::Rollback,
+            ..spec.clone()
+        };
+        spec.verify_transition(&rollback).unwrap();
+        let both = HostSpec {
+            boot_order: BootOrder::Rollback,
+            ..kargs
+        };
+        assert!(spec.verify_transition(&both).is_err());
+    }
+
     #[test]
     fn test_display_imgref() {
         let src = "ostree-unverified-registry:quay.io/example/foo:sometag";
@@ -5,6 +5,9 @@ use std::io::Write;
 
 use anyhow::{Context, Result};
 use camino::Utf8Path;
+use cap_std_ext::cap_std;
+use cap_std_ext::cap_std::fs::Dir;
+use cap_std_ext::dirext::CapStdExtDirExt;
 use fn_error_context::context;
 use ostree::glib;
 use ostree_container::OstreeImageReference;
@@ -14,10 +17,21 @@ use ostree_ext::oci_spec;
 use ostree_ext::ostree;
 
 use crate::cli::OutputFormat;
-use crate::spec::{BootEntry, BootOrder, Host, HostSpec, HostStatus, HostType};
-use crate::spec::{ImageReference, ImageSignature};
+use crate::spec::{BootEntry, BootEntryOstree, BootOrder, Host, HostSpec, HostStatus, HostType};
+use crate::spec::{ImageReference, ImageSignature, RetentionPolicy, UkiStatus};
 use crate::store::{CachedImageStatus, ContainerImageStore, Storage};
 
+/// The directory holding the prompt summary, relative to `/run`.
+const PROMPT_CACHE_DIR: &str = "bootc";
+/// The cached summary for `bootc status --prompt`, relative to `/run`.
+const PROMPT_CACHE_PATH: &str = "bootc/status-prompt";
+/// The commit metadata key for the version.
+const COMMIT_META_VERSION: &str = "version";
+/// The commit metadata key for the human readable source of a commit.
+const COMMIT_META_SOURCE_TITLE: &str = "ostree.source-title";
+/// The commit metadata key set if the commit contains a kernel.
+const COMMIT_META_BOOTABLE: &str = "ostree.bootable";
+
 impl From<ostree_container::SignatureSource> for ImageSignature {
     fn from(sig: ostree_container::SignatureSource) -> Self {
         use ostree_container::SignatureSource;
@@ -86,7 +100,7 @@ impl From<ImageReference> for OstreeImageReference {
 
 /// Parse an ostree origin file (a keyfile) and extract the targeted
 /// container image reference.
-fn get_image_origin(origin: &glib::KeyFile) -> Result<Option<OstreeImageReference>> {
+pub(crate) fn get_image_origin(origin: &glib::KeyFile) -> Result<Option<OstreeImageReference>> {
     origin
         .optional_string("origin", ostree_container::deploy::ORIGIN_CONTAINER)
         .context("Failed to load container image from origin")?
@@ -123,6 +137,7 @@ pub(crate) fn labels_of_config(
 fn boot_entry_from_deployment(
     sysroot: &Storage,
     deployment: &ostree::Deployment,
+    labels: &[String],
 ) -> Result<BootEntry> {
     let (
         store,
@@ -140,7 +155,7 @@ fn boot_entry_from_deployment(
             let store = deployment.store()?;
             let store = store.as_ref().unwrap_or(&sysroot.store);
             let spec = Some(store.spec());
-            let status = store.imagestatus(sysroot, deployment, image)?;
+            let status = store.imagestatus(sysroot, deployment, image, labels)?;
 
             (spec, status)
         } else {
@@ -159,15 +174,45 @@ fn boot_entry_from_deployment(
         incompatible,
         store,
         pinned: deployment.is_pinned(),
-        ostree: Some(crate::spec::BootEntryOstree {
-            checksum: deployment.csum().into(),
-            // SAFETY: The deployserial is really unsigned
-            deploy_serial: deployment.deployserial().try_into().unwrap(),
-        }),
+        soft_reboot_capable: false,
+        ostree: Some(boot_entry_ostree(&sysroot.repo(), deployment)?),
+        health: None,
+        uki: None,
     };
     Ok(r)
 }
 
+/// Gather the ostree state of a deployment, including its commit metadata.
+#[context("Reading commit metadata")]
+fn boot_entry_ostree(
+    repo: &ostree::Repo,
+    deployment: &ostree::Deployment,
+) -> Result<BootEntryOstree> {
+    let checksum = deployment.csum();
+    let commitv = repo.load_commit(&checksum)?.0;
+    let commitmeta = commitv.child_value(0);
+    let commitmeta = &glib::VariantDict::new(Some(&commitmeta));
+    let version = commitmeta.lookup::<String>(COMMIT_META_VERSION)?;
+    let source_title = commitmeta.lookup::<String>(COMMIT_META_SOURCE_TITLE)?;
+    let bootable = commitmeta
+        .lookup::<bool>(COMMIT_META_BOOTABLE)?
+        .unwrap_or_default();
+    let timestamp = ostree::commit_get_timestamp(&commitv)
+        .try_into()
+        .ok()
+        .and_then(|t| chrono::DateTime::from_timestamp(t, 0));
+    Ok(BootEntryOstree {
+        checksum: checksum.into(),
+        // SAFETY: The deployserial is really unsigned
+        deploy_serial: deployment.deployserial().try_into().unwrap(),
+        version,
+        source_title,
+        bootable,
+        timestamp,
+        stateroot: Some(deployment.osname().into()),
+    })
+}
+
 impl BootEntry {
     /// Given a boot entry, find its underlying ostree container image
     pub(crate) fn query_image(
@@ -194,6 +239,23 @@ pub(crate) fn get_status_require_booted(
     Ok((booted_deployment, deployments, host))
 }
 
+/// Load the host configuration of the booted deployment.  An invalid
+/// configuration must not break `bootc status`, so errors are only reported.
+fn status_config(
+    sysroot: &Storage,
+    booted_deployment: Option<&ostree::Deployment>,
+) -> crate::config::HostConfiguration {
+    let Some(booted_deployment) = booted_deployment else {
+        return Default::default();
+    };
+    crate::utils::deployment_fd(&sysroot.sysroot, booted_deployment)
+        .and_then(|root| crate::config::load_config(&root))
+        .unwrap_or_else(|e| {
+            eprintln!("warning: {e:#}");
+            Default::default()
+        })
+}
+
 /// Gather the ostree deployment objects, but also extract metadata from them into
 /// a more native Rust structure.
 #[context("Computing status")]
@@ -202,15 +264,16 @@ pub(crate) fn get_status(
     booted_deployment: Option<&ostree::Deployment>,
 ) -> Result<(Deployments, Host)> {
     let stateroot = booted_deployment.as_ref().map(|d| d.osname());
-    let (mut related_deployments, other_deployments) = sysroot
-        .deployments()
-        .into_iter()
-        .partition::<VecDeque<_>, _>(|d| Some(d.osname()) == stateroot);
-    let staged = related_deployments
+    let mut all_deployments = sysroot.deployments();
+    // The staged deployment may be in another stateroot, see `bootc switch --stateroot`
+    let staged = all_deployments
         .iter()
         .position(|d| d.is_staged())
-        .map(|i| related_deployments.remove(i).unwrap());
+        .map(|i| all_deployments.remove(i));
     tracing::debug!("Staged: {staged:?}");
+    let (mut related_deployments, other_deployments) = all_deployments
+        .into_iter()
+        .partition::<VecDeque<_>, _>(|d| Some(d.osname()) == stateroot);
     // Filter out the booted, the caller already found that
     if let Some(booted) = booted_deployment.as_ref() {
         related_deployments.retain(|f| !f.equal(booted));
@@ -236,23 +299,79 @@ pub(crate) fn get_status(
         other,
     };
 
-    let staged = deployments
+    let config = status_config(sysroot, booted_deployment);
+    let labels = config.status_labels();
+
+    let mut staged = deployments
         .staged
         .as_ref()
-        .map(|d| boot_entry_from_deployment(sysroot, d))
+        .map(|d| boot_entry_from_deployment(sysroot, d, labels))
         .transpose()
         .context("Staged deployment")?;
-    let booted = booted_deployment
+    if let (Some(entry), Some(staged), Some(booted)) = (
+        staged.as_mut(),
+        deployments.staged.as_ref(),
+        booted_deployment.as_ref(),
+    ) {
+        entry.soft_reboot_capable = crate::deploy::soft_reboot_capable(booted, staged);
+    }
+    let mut booted = booted_deployment
         .as_ref()
-        .map(|d| boot_entry_from_deployment(sysroot, d))
+        .map(|d| boot_entry_from_deployment(sysroot, d, labels))
         .transpose()
         .context("Booted deployment")?;
-    let rollback = deployments
+    let mut rollback = deployments
         .rollback
         .as_ref()
-        .map(|d| boot_entry_from_deployment(sysroot, d))
+        .map(|d| boot_entry_from_deployment(sysroot, d, labels))
         .transpose()
         .context("Rollback deployment")?;
+    let mut other_stateroots = Vec::new();
+    if stateroot.is_some() {
+        let mut seen = std::collections::HashSet::new();
+        for d in deployments.other.iter() {
+            if Some(d.osname()) != stateroot && seen.insert(d.osname()) {
+                let entry = boot_entry_from_deployment(sysroot, d, labels)
+                    .with_context(|| format!("Deployment in stateroot {}", d.osname()))?;
+                other_stateroots.push(entry);
+            }
+        }
+    }
+    let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    let health = crate::health::load(root)?;
+    for entry in [&mut staged, &mut booted, &mut rollback]
+        .into_iter()
+        .flatten()
+    {
+        entry.health = entry
+            .ostree
+            .as_ref()
+            .and_then(|o| health.get(&o.checksum))
+            .cloned();
+    }
+    let booted_uki_entry = crate::systemd_boot::booted_entry(root)?;
+    for (entry, deployment) in [
+        (&mut staged, deployments.staged.as_ref()),
+        (&mut booted, booted_deployment),
+        (&mut rollback, deployments.rollback.as_ref()),
+    ] {
+        let (Some(entry), Some(deployment)) = (entry.as_mut(), deployment) else {
+            continue;
+        };
+        let deployment_root = crate::utils::deployment_fd(sysroot, deployment)?;
+        entry.uki = crate::systemd_boot::find_uki(&deployment_root)?.map(|path| UkiStatus {
+            path: format!("/{path}"),
+            booted: booted_uki_entry
+                .as_deref()
+                .is_some_and(|id| crate::systemd_boot::is_entry_of(id, deployment)),
+        });
+    }
+    let kargs = deployments
+        .staged
+        .as_ref()
+        .or(booted_deployment)
+        .and_then(|d| d.origin())
+        .and_then(|origin| crate::deploy::origin_kargs(&origin));
     let spec = staged
         .as_ref()
         .or(booted.as_ref())
@@ -260,6 +379,7 @@ pub(crate) fn get_status(
         .map(|img| HostSpec {
             image: Some(img.image.clone()),
             boot_order,
+            kargs,
         })
         .unwrap_or_default();
 
@@ -281,65 +401,242 @@ pub(crate) fn get_status(
         rollback,
         rollback_queued,
         ty,
+        in_progress: None,
+        other_stateroots,
+        retention: Some(RetentionPolicy {
+            rollbacks: config.retain_rollbacks(),
+            pinned: config.retain_pinned(),
+        }),
     };
     Ok((deployments, host))
 }
 
+/// The bootc operation holding the lock, or else the finalization of the
+/// staged deployment at shutdown, if either is in progress.
+fn in_progress(run: &Dir) -> Result<Option<crate::spec::OperationInProgress>> {
+    match crate::lock::current(run)? {
+        Some(op) => Ok(Some(op)),
+        None => crate::shutdown::finalizing(),
+    }
+}
+
+/// Read the status of the host without changing anything, for [`crate::api`].
+#[context("Reading status")]
+pub(crate) async fn read_host() -> Result<Host> {
+    let run = &Dir::open_ambient_dir("/run", cap_std::ambient_authority())?;
+    let in_progress = in_progress(run)?;
+    let mut host: Host = if !Utf8Path::new("/run/ostree-booted").try_exists()? {
+        Default::default()
+    } else if !rustix::process::getuid().is_root() {
+        crate::service::get_status().await?
+    } else {
+        // Unlike `get_storage()`, don't set up for writing
+        let sysroot = ostree::Sysroot::new_default();
+        let sysroot = ostree_ext::sysroot::SysrootLock::new_from_sysroot(&sysroot).await?;
+        sysroot.load(ostree::gio::Cancellable::NONE)?;
+        let sysroot = Storage::new(sysroot, run)?;
+        let booted_deployment = sysroot.booted_deployment();
+        get_status(&sysroot, booted_deployment.as_ref())?.1
+    };
+    host.status.in_progress = in_progress;
+    Ok(host)
+}
+
 /// Implementation of the `bootc status` CLI command.
 #[context("Status")]
 pub(crate) async fn status(opts: super::cli::StatusOpts) -> Result<()> {
+    if opts.prompt {
+        let run = &Dir::open_ambient_dir("/run", cap_std::ambient_authority())?;
+        return prompt_output(std::io::stdout().lock(), run);
+    }
     match opts.format_version.unwrap_or_default() {
         // For historical reasons, both 0 and 1 mean "v1".
         0 | 1 => {}
         o => anyhow::bail!("Unsupported format version: {o}"),
     };
-    let host = if !Utf8Path::new("/run/ostree-booted").try_exists()? {
+    let legacy_opt = if opts.json {
+        OutputFormat::Json
+    } else if std::io::stdout().is_terminal() {
+        OutputFormat::HumanReadable
+    } else {
+        OutputFormat::Yaml
+    };
+    let format = opts.format.unwrap_or(legacy_opt);
+    let abbreviate = format == OutputFormat::HumanReadable && !opts.full_digests;
+    // Digests of stored images which are not part of the status, but must
+    // still be distinguishable from the abbreviated ones
+    let mut stored_digests = Vec::new();
+    let run = &Dir::open_ambient_dir("/run", cap_std::ambient_authority())?;
+    let in_progress = in_progress(run)?;
+    if let Some(op) = in_progress.as_ref() {
+        // The sysroot may stay locked until it is finished
+        eprintln!("Note: {op}");
+    }
+    let mut host: Host = if !Utf8Path::new("/run/ostree-booted").try_exists()? {
         Default::default()
+    } else if !rustix::process::getuid().is_root() {
+        // Reading the sysroot requires privileges; ask the service instead
+        crate::service::get_status().await?
     } else {
         let sysroot = super::cli::get_storage().await?;
         let booted_deployment = sysroot.booted_deployment();
         let (_deployments, host) = get_status(&sysroot, booted_deployment.as_ref())?;
+        update_prompt_cache(host.status.staged.is_some(), host.status.rollback_queued);
+        if abbreviate {
+            stored_digests = stored_image_digests(&sysroot.repo())?;
+        }
         host
     };
+    host.status.in_progress = in_progress;
+    let digest_len = abbreviate.then(|| {
+        short_digest_len(
+            status_image_digests(&host).chain(stored_digests.iter().map(|s| s.as_str())),
+        )
+    });
 
-    // If we're in JSON mode, then convert the ostree data into Rust-native
-    // structures that can be serialized.
-    // Filter to just the serializable status structures.
     let out = std::io::stdout();
     let mut out = out.lock();
-    let legacy_opt = if opts.json {
-        OutputFormat::Json
-    } else if std::io::stdout().is_terminal() {
-        OutputFormat::HumanReadable
-    } else {
-        OutputFormat::Yaml
-    };
-    let format = opts.format.unwrap_or(legacy_opt);
-    match format {
-        OutputFormat::Json => serde_json::to_writer(&mut out, &host).map_err(anyhow::Error::new),
-        OutputFormat::Yaml => serde_yaml::to_writer(&mut out, &host).map_err(anyhow::Error::new),
-        OutputFormat::HumanReadable => human_readable_output(&mut out, &host),
-    }
-    .context("Writing to stdout")?;
+    write_status(&mut out, &host, format, digest_len).context("Writing to stdout")?;
 
     Ok(())
 }
 
-/// Write the data for a container image based status.
-fn human_render_imagestatus(
+/// Write the host status in the given format; if `digest_len` is set, digests
+/// in human readable output are abbreviated to that length.
+pub(crate) fn write_status(
     mut out: impl Write,
-    slot_name: &str,
-    image: &crate::spec::ImageStatus,
+    host: &Host,
+    format: OutputFormat,
+    digest_len: Option<usize>,
 ) -> Result<()> {
-    let transport = &image.image.transport;
-    let imagename = &image.image.image;
+    crate::render::renderer(&format)?.render(&mut out, host, digest_len)
+}
+
+/// The minimum number of hex characters shown for an abbreviated digest.
+const SHORT_DIGEST_MIN_LEN: usize = 12;
+
+/// The manifest digests of all images in the ostree repository.
+#[context("Querying stored images")]
+fn stored_image_digests(repo: &ostree::Repo) -> Result<Vec<String>> {
+    let mut r = Vec::new();
+    for image in ostree_container::store::list_images(repo)? {
+        let imgref = ostree_container::ImageReference::try_from(image.as_str())?;
+        if let Some(state) = ostree_container::store::query_image(repo, &imgref)? {
+            r.push(state.manifest_digest.to_string());
+        }
+    }
+    Ok(r)
+}
+
+/// The digests of all images referenced by the status.
+pub(crate) fn status_image_digests(host: &Host) -> impl Iterator<Item = &str> {
+    [
+        &host.status.staged,
+        &host.status.booted,
+        &host.status.rollback,
+    ]
+    .into_iter()
+    .flatten()
+    .chain(host.status.other_stateroots.iter())
+    .flat_map(|e| [e.image.as_ref(), e.cached_update.as_ref()])
+    .flatten()
+    .map(|i| i.image_digest.as_str())
+}
+
+/// The shortest length (but at least [`SHORT_DIGEST_MIN_LEN`]) at which the
+/// hex encoded part of each of the provided digests is unique.
+pub(crate) fn short_digest_len<'a>(digests: impl IntoIterator<Item = &'a str>) -> usize {
+    let mut hex = digests
+        .into_iter()
+        .map(|d| d.split_once(':').map_or(d, |(_, v)| v))
+        .collect::<Vec<_>>();
+    hex.sort_unstable();
+    hex.dedup();
+    // When sorted, the longest common prefix is always between neighbors
+    let common = hex
+        .windows(2)
+        .map(|w| {
+            w[0].bytes()
+                .zip(w[1].bytes())
+                .take_while(|(a, b)| a == b)
+                .count()
+        })
+        .max()
+        .unwrap_or_default();
+    (common + 1).max(SHORT_DIGEST_MIN_LEN)
+}
+
+/// Abbreviate a digest such as `sha256:0a1b...` to the given number of hex characters.
+fn short_digest(digest: &str, len: usize) -> &str {
+    match digest.split_once(':') {
+        Some((algorithm, hex)) if hex.len() > len => &digest[..algorithm.len() + 1 + len],
+        _ => digest,
+    }
+}
+
+/// Render a compact summary of pending changes, suitable for shell prompts.
+fn prompt_string(staged: bool, rollback_queued: bool) -> String {
+    let mut r = Vec::new();
+    if staged {
+        r.push("⬆ staged");
+    }
+    if rollback_queued {
+        r.push("↶ rollback");
+    }
+    r.join(" ")
+}
+
+/// Update the summary read by `bootc status --prompt`; since this is purely
+/// informational, errors are only logged.
+pub(crate) fn update_prompt_cache(staged: bool, rollback_queued: bool) {
+    let r = Dir::open_ambient_dir("/run", cap_std::ambient_authority())
+        .map_err(anyhow::Error::new)
+        .and_then(|run| write_prompt_cache(&run, &prompt_string(staged, rollback_queued)));
+    if let Err(e) = r {
+        tracing::debug!("Failed to update prompt cache: {e:#}");
+    }
+}
+
+#[context("Writing /run/{PROMPT_CACHE_PATH}")]
+fn write_prompt_cache(run: &Dir, contents: &str) -> Result<()> {
+    run.create_dir_all(PROMPT_CACHE_DIR)?;
+    run.atomic_write(PROMPT_CACHE_PATH, contents)?;
+    Ok(())
+}
+
+/// Print the cached summary, without loading the system state; if there is
+/// no cache (e.g. no bootc operation has run since boot), nothing is printed.
+fn prompt_output(mut out: impl Write, run: &Dir) -> Result<()> {
+    if let Some(f) = run.open_optional(PROMPT_CACHE_PATH)? {
+        let contents = std::io::read_to_string(f)?;
+        if !contents.is_empty() {
+            writeln!(out, "{contents}")?;
+        }
+    }
+    Ok(())
+}
+
+/// Format an image reference, omitting the transport if it is the default.
+fn human_imageref(imgref: &ImageReference) -> Cow<'_, str> {
+    let transport = &imgref.transport;
+    let imagename = &imgref.image;
     // Registry is the default, so don't show that
-    let imageref = if transport == "registry" {
+    if transport == "registry" {
         Cow::Borrowed(imagename)
     } else {
         // But for non-registry we include the transport
         Cow::Owned(format!("{transport}:{imagename}"))
-    };
+    }
+}
+
+/// Write the data for a container image based status.
+fn human_render_imagestatus(
+    mut out: impl Write,
+    slot_name: &str,
+    image: &crate::spec::ImageStatus,
+    digest_len: Option<usize>,
+) -> Result<()> {
+    let imageref = human_imageref(&image.image);
     writeln!(out, "Current {slot_name} image: {imageref}")?;
 
     let version = image
@@ -351,21 +648,63 @@ fn human_render_imagestatus(
         .as_ref()
         .map(|t| t.to_string())
         .unwrap_or_else(|| "No timestamp present".to_owned());
-    let digest = &image.image_digest;
+    let digest = match digest_len {
+        Some(len) => short_digest(&image.image_digest, len),
+        None => image.image_digest.as_str(),
+    };
 
     writeln!(out, "    Image version: {version} ({timestamp})")?;
     writeln!(out, "    Image digest: {digest}")?;
+    if let Some(platform) = image.platform.as_deref() {
+        writeln!(out, "    Image platform: {platform}")?;
+    }
+    if let Some(mirror) = image.fetched_from.as_deref() {
+        writeln!(out, "    Fetched from mirror: {mirror}")?;
+    }
     Ok(())
 }
 
-fn human_render_ostree(mut out: impl Write, slot_name: &str, _ostree_commit: &str) -> Result<()> {
+fn human_render_ostree(
+    mut out: impl Write,
+    slot_name: &str,
+    ostree: &BootEntryOstree,
+) -> Result<()> {
     // TODO consider rendering more ostree stuff here like rpm-ostree status does
     writeln!(out, "Current {slot_name} state is native ostree")?;
+    if let Some(version) = ostree.version.as_deref() {
+        let timestamp = ostree
+            .timestamp
+            .as_ref()
+            .map(|t| t.to_string())
+            .unwrap_or_else(|| "No timestamp present".to_owned());
+        writeln!(out, "    Version: {version} ({timestamp})")?;
+    }
+    if let Some(source_title) = ostree.source_title.as_deref() {
+        writeln!(out, "    Source: {source_title}")?;
+    }
     Ok(())
 }
 
-/// Implementation of rendering our host structure in a "human readable" way.
-fn human_readable_output(mut out: impl Write, host: &Host) -> Result<()> {
+/// Implementation of rendering our host structure in a "human readable" way;
+/// if `digest_len` is set, digests are abbreviated to that length.
+pub(crate) fn human_readable_output(
+    mut out: impl Write,
+    host: &Host,
+    digest_len: Option<usize>,
+) -> Result<()> {
+    if let Some(op) = host.status.in_progress.as_ref() {
+        writeln!(
+            out,
+            "Operation in progress: {} (pid {}) since {}",
+            op.operation, op.pid, op.started
+        )?;
+    }
+    let booted_stateroot = host
+        .status
+        .booted
+        .as_ref()
+        .and_then(|b| b.ostree.as_ref())
+        .and_then(|o| o.stateroot.as_deref());
     for (slot_name, status) in [
         ("staged", &host.status.staged),
         ("booted", &host.status.booted),
@@ -373,16 +712,127 @@ fn human_readable_output(mut out: impl Write, host: &Host) -> Result<()> {
     ] {
         if let Some(host_status) = status {
             if let Some(image) = &host_status.image {
-                human_render_imagestatus(&mut out, slot_name, image)?;
+                human_render_imagestatus(&mut out, slot_name, image, digest_len)?;
             } else if let Some(ostree) = host_status.ostree.as_ref() {
-                human_render_ostree(&mut out, slot_name, &ostree.checksum)?;
+                human_render_ostree(&mut out, slot_name, ostree)?;
             } else {
                 writeln!(out, "Current {slot_name} state is unknown")?;
             }
+            let stateroot = host_status
+                .ostree
+                .as_ref()
+                .and_then(|o| o.stateroot.as_deref())
+                .filter(|s| Some(*s) != booted_stateroot);
+            if let Some(stateroot) = stateroot {
+                writeln!(out, "    Stateroot: {stateroot}")?;
+            }
+            if host_status.soft_reboot_capable {
+                writeln!(out, "    Soft reboot: capable")?;
+            }
+            if let Some(uki) = host_status.uki.as_ref() {
+                let booted = if uki.booted { " (booted)" } else { "" };
+                writeln!(out, "    UKI: {}{booted}", uki.path)?;
+            }
+            if let Some(health) = host_status.health.as_ref() {
+                let result = if health.failed.is_empty() {
+                    Cow::Borrowed("passed")
+                } else {
+                    Cow::Owned(format!("failed ({})", health.failed.join(", ")))
+                };
+                writeln!(out, "    Health checks: {result} at {}", health.checked)?;
+            }
         } else {
             writeln!(out, "No {slot_name} image present")?;
         }
     }
+    for entry in host.status.other_stateroots.iter() {
+        let Some(ostree) = entry.ostree.as_ref() else {
+            continue;
+        };
+        let stateroot = ostree.stateroot.as_deref().unwrap_or("unknown");
+        let slot_name = format!("{stateroot} stateroot");
+        if let Some(image) = &entry.image {
+            human_render_imagestatus(&mut out, &slot_name, image, digest_len)?;
+        } else {
+            human_render_ostree(&mut out, &slot_name, ostree)?;
+        }
+    }
+    if let Some(retention) = host.status.retention.as_ref() {
+        let n = retention.rollbacks;
+        let plural = if n == 1 { "" } else { "s" };
+        let pinned = if retention.pinned {
+            ", and pinned deployments"
+        } else {
+            ""
+        };
+        writeln!(
+            out,
+            "Retained on update: {n} rollback deployment{plural}{pinned}"
+        )?;
+    }
+    Ok(())
+}
+
+/// Escape a value for use in a Markdown table cell.
+fn markdown_cell(s: &str) -> String {
+    s.replace('|', "\\|")
+}
+
+/// Render the host status as a Markdown report: a summary table of the
+/// boot entries, followed by the full status as JSON in a collapsed section.
+pub(crate) fn markdown_output(mut out: impl Write, host: &Host) -> Result<()> {
+    writeln!(out, "## bootc status")?;
+    writeln!(out)?;
+    writeln!(out, "| Entry | Image | Version | Timestamp | Digest |")?;
+    writeln!(out, "| --- | --- | --- | --- | --- |")?;
+    for (slot_name, status) in [
+        ("staged", &host.status.staged),
+        ("booted", &host.status.booted),
+        ("rollback", &host.status.rollback),
+    ] {
+        let (image, version, timestamp, digest) = match status {
+            Some(BootEntry {
+                image: Some(image), ..
+            }) => (
+                markdown_cell(&human_imageref(&image.image)),
+                image.version.as_deref().map(markdown_cell),
+                image.timestamp.as_ref().map(|t| t.to_string()),
+                Some(format!("`{}`", image.image_digest)),
+            ),
+            Some(BootEntry {
+                ostree: Some(ostree),
+                ..
+            }) => (
+                "(native ostree)".to_owned(),
+                ostree.version.as_deref().map(markdown_cell),
+                ostree.timestamp.as_ref().map(|t| t.to_string()),
+                Some(format!("`{}`", ostree.checksum)),
+            ),
+            Some(_) => ("(unknown)".to_owned(), None, None, None),
+            None => ("(none)".to_owned(), None, None, None),
+        };
+        let version = version.as_deref().unwrap_or("-");
+        let timestamp = timestamp.as_deref().unwrap_or("-");
+        let digest = digest.as_deref().unwrap_or("-");
+        writeln!(
+            out,
+            "| {slot_name} | {image} | {version} | {timestamp} | {digest} |"
+        )?;
+    }
+    if host.status.rollback_queued {
+        writeln!(out)?;
+        writeln!(out, "**Note:** A rollback is queued for the next boot.")?;
+    }
+    writeln!(out)?;
+    writeln!(out, "<details>")?;
+    writeln!(out, "<summary>Raw status (JSON)</summary>")?;
+    writeln!(out)?;
+    writeln!(out, "```json")?;
+    serde_json::to_writer_pretty(&mut out, host)?;
+    writeln!(out)?;
+    writeln!(out, "```")?;
+    writeln!(out)?;
+    writeln!(out, "</details>")?;
     Ok(())
 }
 
@@ -393,7 +843,7 @@ mod tests {
     fn human_status_from_spec_fixture(spec_fixture: &str) -> Result<String> {
         let host: Host = serde_yaml::from_str(spec_fixture).unwrap();
         let mut w = Vec::new();
-        human_readable_output(&mut w, &host).unwrap();
+        human_readable_output(&mut w, &host, None).unwrap();
         let w = String::from_utf8(w).unwrap();
         Ok(w)
     }
@@ -415,6 +865,135 @@ mod tests {
         similar_asserts::assert_eq!(w, expected);
     }
 
+    #[test]
+    fn test_human_readable_soft_reboot() {
+        let mut host: Host =
+            serde_yaml::from_str(include_str!("fixtures/spec-staged-booted.yaml")).unwrap();
+        assert!(!host.status.staged.as_ref().unwrap().soft_reboot_capable);
+        host.status.staged.as_mut().unwrap().soft_reboot_capable = true;
+        let mut w = Vec::new();
+        human_readable_output(&mut w, &host, None).unwrap();
+        let w = String::from_utf8(w).unwrap();
+        let expected = indoc::indoc! { r"
+    Current staged image: quay.io/example/someimage:latest
+        Image version: nightly (2023-10-14 19:22:15 UTC)
+        Image digest: sha256:16dc2b6256b4ff0d2ec18d2dbfb06d117904010c8cf9732cdb022818cf7a7566
+        Soft reboot: capable
+    Current booted image: quay.io/example/someimage:latest
+    "};
+        assert!(w.starts_with(expected), "{w}");
+    }
+
+    #[test]
+    fn test_human_readable_uki() {
+        let mut host: Host =
+            serde_yaml::from_str(include_str!("fixtures/spec-staged-booted.yaml")).unwrap();
+        host.status.booted.as_mut().unwrap().uki = Some(UkiStatus {
+            path: "/usr/lib/modules/6.12.0/uki.efi".into(),
+            booted: true,
+        });
+        let mut w = Vec::new();
+        human_readable_output(&mut w, &host, None).unwrap();
+        let w = String::from_utf8(w).unwrap();
+        let expected = indoc::indoc! { r"
+    Current booted image: quay.io/example/someimage:latest
+        Image version: nightly (2023-09-30 19:22:16 UTC)
+        Image digest: sha256:736b359467c9437c1ac915acaae952aad854e07eb4a16a94999a48af08c83c34
+        UKI: /usr/lib/modules/6.12.0/uki.efi (booted)
+    "};
+        assert!(w.contains(expected), "{w}");
+    }
+
+    #[test]
+    fn test_human_readable_health() {
+        let mut host: Host =
+            serde_yaml::from_str(include_str!("fixtures/spec-staged-booted.yaml")).unwrap();
+        host.status.booted.as_mut().unwrap().health = Some(crate::spec::HealthStatus {
+            checked: chrono::DateTime::from_timestamp(1697311335, 0).unwrap(),
+            failed: vec!["10-network".into(), "20-app".into()],
+        });
+        let mut w = Vec::new();
+        human_readable_output(&mut w, &host, None).unwrap();
+        let w = String::from_utf8(w).unwrap();
+        let expected = indoc::indoc! { r"
+    Current booted image: quay.io/example/someimage:latest
+        Image version: nightly (2023-09-30 19:22:16 UTC)
+        Image digest: sha256:736b359467c9437c1ac915acaae952aad854e07eb4a16a94999a48af08c83c34
+        Health checks: failed (10-network, 20-app) at 2023-10-14 19:22:15 UTC
+    No rollback image present
+    "};
+        assert!(w.ends_with(expected), "{w}");
+    }
+
+    #[test]
+    fn test_human_readable_stateroots() {
+        let mut host = crate::testing::fabricate_host(
+            Some("quay.io/example/os:41"),
+            Some("quay.io/example/recovery:latest"),
+            None,
+            false,
+        )
+        .unwrap();
+        let mut other = host.status.staged.clone().unwrap();
+        other.ostree.as_mut().unwrap().stateroot = Some("test".into());
+        host.status.other_stateroots.push(other);
+        let staged = host.status.staged.as_mut().unwrap();
+        staged.ostree.as_mut().unwrap().stateroot = Some("recovery".into());
+        let mut w = Vec::new();
+        human_readable_output(&mut w, &host, Some(12)).unwrap();
+        let w = String::from_utf8(w).unwrap();
+        let lines = w.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            "Current staged image: quay.io/example/recovery:latest"
+        );
+        assert_eq!(lines[3], "    Stateroot: recovery");
+        assert_eq!(lines[4], "Current booted image: quay.io/example/os:41");
+        // The booted stateroot is not shown
+        assert_eq!(lines.iter().filter(|l| l.contains("Stateroot")).count(), 1);
+        assert!(
+            lines.contains(&"Current test stateroot image: quay.io/example/recovery:latest"),
+            "{w}"
+        );
+    }
+
+    #[test]
+    fn test_human_readable_retention() {
+        let mut host: Host =
+            serde_yaml::from_str(include_str!("fixtures/spec-staged-booted.yaml")).unwrap();
+        host.status.retention = Some(RetentionPolicy {
+            rollbacks: 2,
+            pinned: true,
+        });
+        let mut w = Vec::new();
+        human_readable_output(&mut w, &host, None).unwrap();
+        let w = String::from_utf8(w).unwrap();
+        let expected = indoc::indoc! { r"
+    No rollback image present
+    Retained on update: 2 rollback deployments, and pinned deployments
+    "};
+        assert!(w.ends_with(expected), "{w}");
+    }
+
+    #[test]
+    fn test_human_readable_in_progress() {
+        let mut host: Host =
+            serde_yaml::from_str(include_str!("fixtures/spec-staged-booted.yaml")).unwrap();
+        host.status.in_progress = Some(crate::spec::OperationInProgress {
+            operation: "upgrade".into(),
+            pid: 42,
+            started: chrono::DateTime::from_timestamp(1697311335, 0).unwrap(),
+        });
+        let mut w = Vec::new();
+        human_readable_output(&mut w, &host, None).unwrap();
+        let w = String::from_utf8(w).unwrap();
+        let expected = indoc::indoc! { r"
+    Operation in progress: upgrade (pid 42) since 2023-10-14 19:22:15 UTC
+    Current staged image: quay.io/example/someimage:latest
+    "};
+        assert!(w.starts_with(expected), "{w}");
+    }
+
     #[test]
     fn test_human_readable_rfe_spec() {
         // Basic rhel for edge bootc install with nothing
@@ -430,6 +1009,21 @@ mod tests {
         similar_asserts::assert_eq!(w, expected);
     }
 
+    #[test]
+    fn test_human_readable_ostree_commit_meta() {
+        let w =
+            human_status_from_spec_fixture(include_str!("fixtures/spec-ostree-commit-meta.yaml"))
+                .unwrap();
+        let expected = indoc::indoc! { r"
+    No staged image present
+    Current booted state is native ostree
+        Version: 9.4.20240801.0 (2024-08-01 12:00:00 UTC)
+        Source: oci-archive:/srv/builds/rhel-edge.ociarchive
+    Current rollback state is native ostree
+    "};
+        similar_asserts::assert_eq!(w, expected);
+    }
+
     #[test]
     fn test_human_readable_staged_spec() {
         // staged image, no boot/rollback
@@ -486,11 +1080,90 @@ mod tests {
         Current booted image: oci:/var/mnt/osupdate
             Image version: stream9.20240807.0 (No timestamp present)
             Image digest: sha256:47e5ed613a970b6574bfa954ab25bb6e85656552899aa518b5961d9645102b38
+            Image platform: linux/arm64
         No rollback image present
     "};
         similar_asserts::assert_eq!(w, expected);
     }
 
+    #[test]
+    fn test_short_digest() {
+        let a = "sha256:16dc2b6256b4ff0d2ec18d2dbfb06d117904010c8cf9732cdb022818cf7a7566";
+        let b = "sha256:736b359467c9437c1ac915acaae952aad854e07eb4a16a94999a48af08c83c34";
+        let c = "sha256:16dc2b6256b4ff0d2ec1ffffffffffffffffffffffffffffffffffffffffffff";
+        assert_eq!(short_digest_len([]), SHORT_DIGEST_MIN_LEN);
+        assert_eq!(short_digest_len([a, b, a]), SHORT_DIGEST_MIN_LEN);
+        // The first 20 characters are shared
+        assert_eq!(short_digest_len([a, b, c]), 21);
+        assert_eq!(short_digest(a, 12), "sha256:16dc2b6256b4");
+        assert_eq!(short_digest(a, 100), a);
+        assert_eq!(short_digest("invalid", 12), "invalid");
+
+        let host: Host =
+            serde_yaml::from_str(include_str!("fixtures/spec-staged-booted.yaml")).unwrap();
+        let len = short_digest_len(status_image_digests(&host));
+        let mut w = Vec::new();
+        human_readable_output(&mut w, &host, Some(len)).unwrap();
+        let expected = indoc::indoc! { r"
+    Current staged image: quay.io/example/someimage:latest
+        Image version: nightly (2023-10-14 19:22:15 UTC)
+        Image digest: sha256:16dc2b6256b4
+    Current booted image: quay.io/example/someimage:latest
+        Image version: nightly (2023-09-30 19:22:16 UTC)
+        Image digest: sha256:736b359467c9
+    No rollback image present
+    "};
+        similar_asserts::assert_eq!(String::from_utf8(w).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_markdown_output() {
+        let host: Host =
+            serde_yaml::from_str(include_str!("fixtures/spec-staged-booted.yaml")).unwrap();
+        let mut w = Vec::new();
+        markdown_output(&mut w, &host).unwrap();
+        let w = String::from_utf8(w).unwrap();
+        let expected_table = indoc::indoc! { r"
+        ## bootc status
+
+        | Entry | Image | Version | Timestamp | Digest |
+        | --- | --- | --- | --- | --- |
+        | staged | quay.io/example/someimage:latest | nightly | 2023-10-14 19:22:15 UTC | `sha256:16dc2b6256b4ff0d2ec18d2dbfb06d117904010c8cf9732cdb022818cf7a7566` |
+        | booted | quay.io/example/someimage:latest | nightly | 2023-09-30 19:22:16 UTC | `sha256:736b359467c9437c1ac915acaae952aad854e07eb4a16a94999a48af08c83c34` |
+        | rollback | (none) | - | - | - |
+
+        <details>
+    "};
+        assert!(w.starts_with(expected_table), "{w}");
+        // The embedded JSON must round trip
+        let json = w
+            .split_once("```json\n")
+            .and_then(|(_, rest)| rest.split_once("\n```"))
+            .map(|(json, _)| json)
+            .unwrap();
+        let parsed: Host = serde_json::from_str(json).unwrap();
+        assert_eq!(parsed, host);
+        assert!(w.ends_with("</details>\n"));
+
+        assert_eq!(markdown_cell("a|b"), "a\\|b");
+    }
+
+    #[test]
+    fn test_prompt() -> Result<()> {
+        assert_eq!(prompt_string(false, false), "");
+        assert_eq!(prompt_string(true, false), "⬆ staged");
+        assert_eq!(prompt_string(true, true), "⬆ staged ↶ rollback");
+
+        let td = cap_std_ext::cap_tempfile::TempDir::new(cap_std::ambient_authority())?;
+        let mut w = Vec::new();
+        prompt_output(&mut w, &td)?;
+        assert!(w.is_empty());
+        write_prompt_cache(&td, &prompt_string(true, false))?;
+        prompt_output(&mut w, &td)?;
+        assert_eq!(String::from_utf8(w).unwrap(), "⬆ staged\n");
+        Ok(())
+    }
+
     #[test]
     fn test_convert_signatures() {
         use std::str::FromStr;
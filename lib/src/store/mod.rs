@@ -40,6 +40,7 @@ pub(crate) trait ContainerImageStoreImpl {
         sysroot: &SysrootLock,
         deployment: &ostree::Deployment,
         image: OstreeImageReference,
+        labels: &[String],
     ) -> Result<CachedImageStatus>;
 }
 
@@ -1,6 +1,7 @@
 use anyhow::{Context, Result};
 
 use ostree_ext::container as ostree_container;
+use ostree_ext::keyfileext::KeyFileExt;
 use ostree_ext::oci_spec;
 use ostree_ext::oci_spec::image::{Digest, ImageConfiguration};
 use ostree_ext::ostree;
@@ -21,16 +22,34 @@ impl super::ContainerImageStoreImpl for OstreeContainerStore {
         sysroot: &SysrootLock,
         deployment: &ostree::Deployment,
         image: ostree_container::OstreeImageReference,
+        labels: &[String],
     ) -> Result<CachedImageStatus> {
         let repo = &sysroot.repo();
         let image = ImageReference::from(image);
         let csum = deployment.csum();
         let imgstate = ostree_container::store::query_image_commit(repo, &csum)?;
         let cached = imgstate.cached_update.map(|cached| {
-            create_imagestatus(image.clone(), &cached.manifest_digest, &cached.config)
+            create_imagestatus(
+                image.clone(),
+                &cached.manifest_digest,
+                &cached.config,
+                labels,
+            )
         });
-        let imagestatus =
-            create_imagestatus(image, &imgstate.manifest_digest, &imgstate.configuration);
+        let mut imagestatus = create_imagestatus(
+            image,
+            &imgstate.manifest_digest,
+            &imgstate.configuration,
+            labels,
+        );
+        if let Some(origin) = deployment.origin() {
+            imagestatus.fetched_from = origin
+                .optional_string(
+                    crate::deploy::ORIGIN_BOOTC_GROUP,
+                    crate::deploy::ORIGIN_FETCHED_FROM,
+                )?
+                .map(Into::into);
+        }
 
         Ok(CachedImageStatus {
             image: Some(imagestatus),
@@ -44,6 +63,7 @@ fn create_imagestatus(
     image: ImageReference,
     manifest_digest: &Digest,
     config: &ImageConfiguration,
+    allowed_labels: &[String],
 ) -> ImageStatus {
     let labels = labels_of_config(config);
     let timestamp = labels
@@ -54,11 +74,29 @@ fn create_imagestatus(
         .and_then(try_deserialize_timestamp);
 
     let version = ostree_container::version_for_config(config).map(ToOwned::to_owned);
+    let selected_labels = (!allowed_labels.is_empty()).then(|| {
+        allowed_labels
+            .iter()
+            .filter_map(|k| {
+                labels
+                    .and_then(|l| l.get(k))
+                    .map(|v| (k.to_owned(), v.to_owned()))
+            })
+            .collect()
+    });
+    let mut platform = format!("{}/{}", config.os(), config.architecture());
+    if let Some(variant) = config.variant().as_deref() {
+        platform.push('/');
+        platform.push_str(variant);
+    }
     ImageStatus {
         image,
         version,
         timestamp,
         image_digest: manifest_digest.to_string(),
+        labels: selected_labels,
+        platform: Some(platform),
+        fetched_from: None,
     }
 }
 
@@ -0,0 +1,421 @@
+//! # systemd-boot
+//!
+//! As an alternative to GRUB (installed via bootupd), `bootc install` can install
+//! [systemd-boot](https://www.freedesktop.org/software/systemd/man/latest/systemd-boot.html),
+//! via `--bootloader systemd-boot`; this is the default for images which ship a
+//! [UKI](https://uapi-group.org/specifications/specs/unified_kernel_image/) as
+//! `/usr/lib/modules/$kver/*.efi`.  systemd-boot reads boot entries from the ESP
+//! only, so bootc writes an entry for each deployment to `loader/entries` in the ESP,
+//! which passes the kernel arguments of the deployment (including `ostree=`).  The
+//! UKI of a deployment is copied to `EFI/Linux` and referred to via the `efi` key;
+//! without a UKI, its kernel and initramfs are copied to `bootc/$commit` and referred
+//! to via the `linux` and `initrd` keys.
+//!
+//! The ESP is synchronized with the deployments at install time, on rollback,
+//! and by `bootc-systemd-boot-sync.service` after the staged deployment was
+//! finalized at shutdown.
+
+use std::collections::HashSet;
+use std::io::Read;
+
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use cap_std::fs::Dir;
+use cap_std_ext::cap_std;
+use cap_std_ext::cap_std::fs_utf8::DirEntry as DirEntryUtf8;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use fn_error_context::context;
+use ostree_ext::ostree;
+
+use crate::task::Task;
+
+/// The directory of UKIs in the ESP
+const ESP_UKI_DIR: &str = "EFI/Linux";
+/// The directory of kernels and initramfs images in the ESP, with a subdirectory per commit
+const ESP_KERNEL_DIR: &str = "bootc";
+/// The directory of boot entries in the ESP
+const ESP_ENTRIES_DIR: &str = "loader/entries";
+/// The prefix of the UKIs and boot entries written by bootc
+const PREFIX: &str = "bootc-";
+/// The EFI variable in which systemd-boot records the identifier of the booted entry
+pub(crate) const LOADER_ENTRY_SELECTED: &str =
+    "sys/firmware/efi/efivars/LoaderEntrySelected-4a67b082-0a4c-41cf-b6c7-440b29bb8c4f";
+/// The mount points of the ESP, in order of preference
+const ESP_MOUNTPOINTS: &[&str] = &["boot/efi", "efi"];
+/// The unit synchronizing the ESP after the staged deployment was finalized
+pub(crate) const SYNC_UNIT: &str = "bootc-systemd-boot-sync.service";
+
+/// The kernel directories `/usr/lib/modules/$kver` of a root filesystem, relative to the root.
+fn kernel_dirs(root: &Dir) -> Result<Vec<Utf8PathBuf>> {
+    let Some(modules) = root.open_dir_optional("usr/lib/modules")? else {
+        return Ok(Vec::new());
+    };
+    let mut r = Vec::new();
+    for kdir in modules.entries()? {
+        let kdir = DirEntryUtf8::from_cap_std(kdir?);
+        if kdir.file_type()?.is_dir() {
+            r.push(Utf8Path::new("usr/lib/modules").join(kdir.file_name()?));
+        }
+    }
+    Ok(r)
+}
+
+/// Find the UKI shipped in `/usr/lib/modules/$kver` of a root filesystem, returning
+/// its path relative to the root.
+#[context("Finding UKI")]
+pub(crate) fn find_uki(root: &Dir) -> Result<Option<Utf8PathBuf>> {
+    let mut found: Option<Utf8PathBuf> = None;
+    for kdir in kernel_dirs(root)? {
+        for e in root.open_dir(&kdir)?.entries()? {
+            let e = DirEntryUtf8::from_cap_std(e?);
+            let name = e.file_name()?;
+            if !name.ends_with(".efi") || !e.file_type()?.is_file() {
+                continue;
+            }
+            let path = kdir.join(&name);
+            if let Some(prev) = found.as_ref() {
+                anyhow::bail!("Found multiple UKIs: /{prev} and /{path}");
+            }
+            found = Some(path);
+        }
+    }
+    Ok(found)
+}
+
+/// Find the kernel directory `/usr/lib/modules/$kver` of a root filesystem which
+/// contains `vmlinuz` and `initramfs.img`, returning its path relative to the root.
+#[context("Finding kernel")]
+fn find_kernel(root: &Dir) -> Result<Option<Utf8PathBuf>> {
+    let mut found: Option<Utf8PathBuf> = None;
+    for kdir in kernel_dirs(root)? {
+        if !root.try_exists(kdir.join("vmlinuz"))? {
+            continue;
+        }
+        if !root.try_exists(kdir.join("initramfs.img"))? {
+            anyhow::bail!("Missing /{kdir}/initramfs.img");
+        }
+        if let Some(prev) = found.as_ref() {
+            anyhow::bail!("Found multiple kernels: /{prev} and /{kdir}");
+        }
+        found = Some(kdir);
+    }
+    Ok(found)
+}
+
+/// The file name of the UKI of a commit in the ESP; deployments of the same
+/// commit share it.
+fn uki_name(checksum: &str) -> String {
+    format!("{PREFIX}{checksum}.efi")
+}
+
+/// The identifier (file name) of the boot entry of a deployment.
+fn entry_id(stateroot: &str, checksum: &str, serial: i32) -> String {
+    format!("{PREFIX}{stateroot}-{checksum}.{serial}.conf")
+}
+
+/// What a boot entry boots.
+#[derive(Debug, PartialEq, Eq)]
+enum EntryImage {
+    /// A UKI in [`ESP_UKI_DIR`], by file name
+    Uki(String),
+    /// A kernel and initramfs in [`ESP_KERNEL_DIR`], by the name of their directory
+    Linux(String),
+}
+
+/// Render a boot entry; systemd-boot sorts entries with a higher version first.
+fn render_entry(title: &str, version: usize, image: &EntryImage, options: &str) -> String {
+    let image = match image {
+        EntryImage::Uki(name) => format!("efi /{ESP_UKI_DIR}/{name}\n"),
+        EntryImage::Linux(dir) => format!(
+            "linux /{ESP_KERNEL_DIR}/{dir}/vmlinuz\n\
+             initrd /{ESP_KERNEL_DIR}/{dir}/initramfs.img\n"
+        ),
+    };
+    format!(
+        "title {title}\n\
+         version {version}\n\
+         sort-key bootc\n\
+         {image}\
+         options {options}\n"
+    )
+}
+
+/// Copy a file of a root filesystem into the ESP, unless it is already present.
+fn copy_to_esp(root: &Dir, src: &Utf8Path, esp: &Dir, dest: &str) -> Result<()> {
+    if esp.try_exists(dest)? {
+        return Ok(());
+    }
+    let mut f = root.open(src).with_context(|| format!("Opening {src}"))?;
+    esp.atomic_replace_with(dest, |w| -> std::io::Result<()> {
+        std::io::copy(&mut f, w)?;
+        Ok(())
+    })
+    .with_context(|| format!("Writing {dest}"))
+}
+
+/// Get `PRETTY_NAME` from the `os-release` of a root filesystem.
+fn pretty_name(root: &Dir) -> Result<Option<String>> {
+    let Some(mut f) = root.open_optional("usr/lib/os-release")? else {
+        return Ok(None);
+    };
+    let mut buf = String::new();
+    f.read_to_string(&mut buf)?;
+    let r = buf
+        .lines()
+        .find_map(|l| l.strip_prefix("PRETTY_NAME="))
+        .map(|v| v.trim_matches(|c| c == '"' || c == '\'').to_owned());
+    Ok(r)
+}
+
+/// Parse a string EFI variable as set by systemd-boot: the attributes, followed
+/// by NUL terminated UTF-16.
+fn parse_efivar_string(buf: &[u8]) -> Option<String> {
+    let data = buf.get(4..)?;
+    let units = data
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&c| c != 0)
+        .collect::<Vec<_>>();
+    String::from_utf16(&units).ok()
+}
+
+/// The identifier of the boot entry written by bootc which the system was booted from, if any.
+pub(crate) fn booted_entry(root: &Dir) -> Result<Option<String>> {
+    let Some(mut f) = root.open_optional(LOADER_ENTRY_SELECTED)? else {
+        return Ok(None);
+    };
+    let mut buf = Vec::new();
+    f.read_to_end(&mut buf)
+        .with_context(|| format!("Reading {LOADER_ENTRY_SELECTED}"))?;
+    Ok(parse_efivar_string(&buf).filter(|id| id.starts_with(PREFIX)))
+}
+
+/// Returns true if the given booted entry identifier is the one of the deployment.
+pub(crate) fn is_entry_of(id: &str, deployment: &ostree::Deployment) -> bool {
+    id == entry_id(
+        &deployment.osname(),
+        &deployment.csum(),
+        deployment.deployserial(),
+    )
+}
+
+/// Install systemd-boot to the ESP mounted at `esp`.
+#[cfg(feature = "install")]
+#[context("Installing systemd-boot")]
+pub(crate) fn install_systemd_boot(esp: &Utf8Path, generic_image: bool) -> Result<()> {
+    // Like bootupd, don't change the firmware boot order for generic images
+    let variables = generic_image.then_some("--no-variables");
+    Task::new("Running bootctl to install systemd-boot", "bootctl")
+        .args(["install", "--graceful"])
+        .arg(format!("--esp-path={esp}"))
+        .args(variables)
+        .verbose()
+        .run()
+}
+
+/// Copy the UKIs (or kernels and initramfs images) of all (finalized) deployments
+/// into the ESP, write a boot entry for each one, and remove those of deployments
+/// which no longer exist.  Returns the number of boot entries.
+#[context("Synchronizing systemd-boot entries in the ESP")]
+pub(crate) fn sync(sysroot: &ostree::Sysroot, esp: &Dir) -> Result<usize> {
+    let deployments = sysroot
+        .deployments()
+        .into_iter()
+        .filter(|d| !d.is_staged())
+        .collect::<Vec<_>>();
+    esp.create_dir_all(ESP_UKI_DIR)?;
+    esp.create_dir_all(ESP_KERNEL_DIR)?;
+    esp.create_dir_all(ESP_ENTRIES_DIR)?;
+    let mut ukis = HashSet::new();
+    let mut kernels = HashSet::new();
+    let mut entries = HashSet::new();
+    for (i, d) in deployments.iter().enumerate() {
+        let root = crate::utils::deployment_fd(sysroot, d)?;
+        let checksum = d.csum();
+        let image = if let Some(src) = find_uki(&root)? {
+            let name = uki_name(&checksum);
+            copy_to_esp(&root, &src, esp, &format!("{ESP_UKI_DIR}/{name}"))?;
+            ukis.insert(name.clone());
+            EntryImage::Uki(name)
+        } else if let Some(kdir) = find_kernel(&root)? {
+            let dir = checksum.to_string();
+            esp.create_dir_all(format!("{ESP_KERNEL_DIR}/{dir}"))?;
+            for f in ["vmlinuz", "initramfs.img"] {
+                copy_to_esp(
+                    &root,
+                    &kdir.join(f),
+                    esp,
+                    &format!("{ESP_KERNEL_DIR}/{dir}/{f}"),
+                )?;
+            }
+            kernels.insert(dir.clone());
+            EntryImage::Linux(dir)
+        } else {
+            tracing::debug!("No kernel found in deployment {checksum}");
+            continue;
+        };
+        let options = d
+            .bootconfig()
+            .and_then(|c| c.get("options"))
+            .map(|o| o.to_string())
+            .unwrap_or_default();
+        let stateroot = d.osname();
+        let pretty = pretty_name(&root)?.unwrap_or_else(|| "Linux".to_owned());
+        let short = &checksum[..checksum.len().min(12)];
+        let title = format!("{pretty} ({stateroot}, {short})");
+        let id = entry_id(&stateroot, &checksum, d.deployserial());
+        // The first deployment is the default, so it gets the highest version
+        let entry = render_entry(&title, deployments.len() - i, &image, &options);
+        esp.atomic_write(format!("{ESP_ENTRIES_DIR}/{id}"), entry)
+            .with_context(|| format!("Writing {id}"))?;
+        entries.insert(id);
+    }
+    // The kernel directory is owned by bootc, so anything else in it is stale
+    for e in esp.read_dir(ESP_KERNEL_DIR)? {
+        let e = e?;
+        let name = e.file_name();
+        if !name.to_str().is_some_and(|n| kernels.contains(n)) {
+            tracing::debug!("Removing {ESP_KERNEL_DIR}/{name:?}");
+            esp.remove_dir_all(std::path::Path::new(ESP_KERNEL_DIR).join(&name))?;
+        }
+    }
+    for (dir, keep) in [(ESP_UKI_DIR, &ukis), (ESP_ENTRIES_DIR, &entries)] {
+        for e in esp.read_dir(dir)? {
+            let e = e?;
+            let name = e.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            if name.starts_with(PREFIX) && !keep.contains(name) {
+                tracing::debug!("Removing {dir}/{name}");
+                esp.remove_file(format!("{dir}/{name}"))?;
+            }
+        }
+    }
+    Ok(entries.len())
+}
+
+/// Synchronize the ESP of the running system if it was booted via a systemd-boot
+/// entry written by bootc; otherwise, nothing is done.
+pub(crate) fn sync_booted(sysroot: &ostree::Sysroot) -> Result<()> {
+    let root = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    if booted_entry(root)?.is_none() {
+        tracing::debug!("Not booted via a bootc systemd-boot entry");
+        return Ok(());
+    }
+    let esp = ESP_MOUNTPOINTS
+        .iter()
+        .map(|p| root.open_dir_optional(p))
+        .collect::<std::io::Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .find(|d| d.try_exists(ESP_ENTRIES_DIR).unwrap_or_default())
+        .ok_or_else(|| anyhow!("Failed to find the mounted ESP"))?;
+    let n = sync(sysroot, &esp)?;
+    tracing::debug!("Synchronized {n} systemd-boot entries");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_uki() -> Result<()> {
+        let td = cap_std_ext::cap_tempfile::tempdir(cap_std::ambient_authority())?;
+        assert!(find_uki(&td)?.is_none());
+        td.create_dir_all("usr/lib/modules/6.12.0")?;
+        td.write("usr/lib/modules/6.12.0/vmlinuz", "kernel")?;
+        assert!(find_uki(&td)?.is_none());
+        td.write("usr/lib/modules/6.12.0/uki.efi", "uki")?;
+        assert_eq!(
+            find_uki(&td)?.unwrap().as_str(),
+            "usr/lib/modules/6.12.0/uki.efi"
+        );
+        td.create_dir_all("usr/lib/modules/6.13.0")?;
+        td.write("usr/lib/modules/6.13.0/uki.efi", "uki")?;
+        assert!(find_uki(&td).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_kernel() -> Result<()> {
+        let td = cap_std_ext::cap_tempfile::tempdir(cap_std::ambient_authority())?;
+        assert!(find_kernel(&td)?.is_none());
+        td.create_dir_all("usr/lib/modules/6.12.0")?;
+        td.write("usr/lib/modules/6.12.0/vmlinuz", "kernel")?;
+        assert!(find_kernel(&td).is_err());
+        td.write("usr/lib/modules/6.12.0/initramfs.img", "initramfs")?;
+        assert_eq!(
+            find_kernel(&td)?.unwrap().as_str(),
+            "usr/lib/modules/6.12.0"
+        );
+        // Directories without a kernel, e.g. of extra modules, are ignored
+        td.create_dir_all("usr/lib/modules/extra")?;
+        assert!(find_kernel(&td)?.is_some());
+        td.create_dir_all("usr/lib/modules/6.13.0")?;
+        td.write("usr/lib/modules/6.13.0/vmlinuz", "kernel")?;
+        td.write("usr/lib/modules/6.13.0/initramfs.img", "initramfs")?;
+        assert!(find_kernel(&td).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_entry() {
+        let entry = render_entry(
+            "Fedora Linux 41 (default, 0123456789ab)",
+            2,
+            &EntryImage::Uki(uki_name("0123456789abcdef")),
+            "rw ostree=/ostree/boot.1/default/abc/0",
+        );
+        similar_asserts::assert_eq!(
+            entry,
+            "title Fedora Linux 41 (default, 0123456789ab)\n\
+             version 2\n\
+             sort-key bootc\n\
+             efi /EFI/Linux/bootc-0123456789abcdef.efi\n\
+             options rw ostree=/ostree/boot.1/default/abc/0\n"
+        );
+        let entry = render_entry(
+            "Fedora Linux 41 (default, 0123456789ab)",
+            1,
+            &EntryImage::Linux("0123456789abcdef".into()),
+            "rw ostree=/ostree/boot.1/default/abc/1",
+        );
+        similar_asserts::assert_eq!(
+            entry,
+            "title Fedora Linux 41 (default, 0123456789ab)\n\
+             version 1\n\
+             sort-key bootc\n\
+             linux /bootc/0123456789abcdef/vmlinuz\n\
+             initrd /bootc/0123456789abcdef/initramfs.img\n\
+             options rw ostree=/ostree/boot.1/default/abc/1\n"
+        );
+        assert_eq!(
+            entry_id("default", "0123456789abcdef", 0),
+            "bootc-default-0123456789abcdef.0.conf"
+        );
+    }
+
+    #[test]
+    fn test_booted_entry() -> Result<()> {
+        let td = cap_std_ext::cap_tempfile::tempdir(cap_std::ambient_authority())?;
+        assert!(booted_entry(&td)?.is_none());
+
+        let value = |s: &str| {
+            let mut buf = vec![6, 0, 0, 0];
+            buf.extend(s.encode_utf16().chain([0]).flat_map(|c| c.to_le_bytes()));
+            buf
+        };
+        td.create_dir_all("sys/firmware/efi/efivars")?;
+        td.write(LOADER_ENTRY_SELECTED, value("fedora.conf"))?;
+        assert!(booted_entry(&td)?.is_none());
+        td.write(LOADER_ENTRY_SELECTED, value("bootc-default-abc.0.conf"))?;
+        assert_eq!(
+            booted_entry(&td)?.as_deref(),
+            Some("bootc-default-abc.0.conf")
+        );
+        assert_eq!(parse_efivar_string(&[6, 0]), None);
+        Ok(())
+    }
+}
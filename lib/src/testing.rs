@@ -0,0 +1,418 @@
+//! # Fixtures for testing derived images
+//!
+//! `bootc internals testing` fabricates host status documents (in the same
+//! form as the `spec-*.yaml` fixtures used by the status tests) and renders
+//! them with the same code as `bootc status`, so that CI for derived images
+//! and distributions can exercise tooling which consumes bootc status without
+//! a booted bootc system or a registry.
+//!
+//! It can also create a scratch sysroot with real ostree deployments of
+//! fabricated images (with a fake kernel, manifest and configuration), and
+//! run the status and upgrade planning code against it as if the system was
+//! booted into it.
+
+use std::os::fd::{AsFd, AsRawFd};
+
+use anyhow::{anyhow, Context, Result};
+use camino::Utf8Path;
+use cap_std_ext::cap_std;
+use cap_std_ext::cap_std::fs::Dir;
+use cap_std_ext::cap_tempfile;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use fn_error_context::context;
+use ostree_ext::ostree::{self, gio, glib};
+use ostree_ext::sysroot::SysrootLock;
+
+use crate::spec::{
+    BootEntry, BootEntryOstree, BootOrder, Host, HostSpec, HostType, ImageReference, ImageStatus,
+    Store,
+};
+use crate::store::Storage;
+
+/// The stateroot of fabricated sysroots.
+const STATEROOT: &str = "default";
+/// Records the deployment of a fabricated sysroot which is treated as booted,
+/// as the path of its root relative to the sysroot.
+const BOOTED_FILE: &str = "bootc-testing-booted";
+/// The kernel version in fabricated images.
+const KERNEL_VERSION: &str = "0.0.0-bootc.testing";
+
+/// A deterministic fake digest for the given input.
+fn fake_digest(kind: &str, image: &str) -> String {
+    let mut h = openssl::sha::Sha256::new();
+    h.update(kind.as_bytes());
+    h.update(b"\0");
+    h.update(image.as_bytes());
+    hex::encode(h.finish())
+}
+
+/// A boot entry for the given registry image, with fake digests.
+fn fabricate_entry(image: &str, deploy_serial: u32) -> BootEntry {
+    let status = ImageStatus {
+        image: ImageReference {
+            image: image.to_owned(),
+            transport: "registry".to_owned(),
+            signature: None,
+        },
+        version: None,
+        timestamp: None,
+        image_digest: format!("sha256:{}", fake_digest("manifest", image)),
+        labels: None,
+        platform: None,
+        fetched_from: None,
+    };
+    BootEntry {
+        image: Some(status),
+        cached_update: None,
+        incompatible: false,
+        pinned: false,
+        soft_reboot_capable: false,
+        uki: None,
+        store: Some(Store::OstreeContainer),
+        ostree: Some(BootEntryOstree {
+            checksum: fake_digest("commit", image),
+            deploy_serial,
+            version: None,
+            source_title: None,
+            bootable: true,
+            timestamp: None,
+            stateroot: Some("default".into()),
+        }),
+        health: None,
+    }
+}
+
+/// Fabricate a host status with the given images; the booted image is
+/// also the one in the spec.
+pub(crate) fn fabricate_host(
+    booted: Option<&str>,
+    staged: Option<&str>,
+    rollback: Option<&str>,
+    rollback_queued: bool,
+) -> Result<Host> {
+    if rollback_queued && rollback.is_none() {
+        anyhow::bail!("A queued rollback requires a rollback image");
+    }
+    let spec = HostSpec {
+        image: booted.map(|image| ImageReference {
+            image: image.to_owned(),
+            transport: "registry".to_owned(),
+            signature: None,
+        }),
+        boot_order: if rollback_queued {
+            BootOrder::Rollback
+        } else {
+            BootOrder::Default
+        },
+        kargs: None,
+    };
+    let mut host = Host::new(spec);
+    // The same image can be deployed multiple times, so give each
+    // entry a distinct serial as ostree would.
+    let mut serials = std::collections::HashMap::<String, u32>::new();
+    let mut entry = |image: &str| {
+        let serial = serials.entry(image.to_owned()).or_default();
+        let r = fabricate_entry(image, *serial);
+        *serial += 1;
+        r
+    };
+    host.status.rollback = rollback.map(&mut entry);
+    host.status.booted = booted.map(&mut entry);
+    host.status.staged = staged.map(&mut entry);
+    host.status.rollback_queued = rollback_queued;
+    host.status.ty = booted.is_some().then_some(HostType::BootcHost);
+    Ok(host)
+}
+
+/// The registry image reference for `image`.
+fn registry_imgref(image: &str) -> ImageReference {
+    ImageReference {
+        image: image.to_owned(),
+        transport: "registry".to_owned(),
+        signature: None,
+    }
+}
+
+/// The `sha256:` digest of the given data.
+fn sha256_digest(data: &[u8]) -> String {
+    format!("sha256:{}", hex::encode(openssl::sha::sha256(data)))
+}
+
+/// Write an ostree commit for the given registry image, with the metadata
+/// ostree-ext writes when importing an image, but a fake manifest and
+/// configuration.  The tree has a fake kernel and initramfs, so that it can
+/// be deployed.  Returns the commit and the manifest digest.
+#[context("Fabricating image {image}")]
+fn fabricate_image_commit(repo: &ostree::Repo, image: &str) -> Result<(String, String)> {
+    let cancellable = gio::Cancellable::NONE;
+    let layer = format!("sha256:{}", fake_digest("layer", image));
+    let config = serde_json::json!({
+        "architecture": crate::deploy::ImageArch::host().arch.to_string(),
+        "os": "linux",
+        "config": {
+            "Labels": {
+                "containers.bootc": "1",
+            },
+        },
+        "rootfs": {
+            "type": "layers",
+            "diff_ids": [&layer],
+        },
+    })
+    .to_string();
+    let manifest = serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.oci.image.manifest.v1+json",
+        "config": {
+            "mediaType": "application/vnd.oci.image.config.v1+json",
+            "digest": sha256_digest(config.as_bytes()),
+            "size": config.len(),
+        },
+        "layers": [{
+            "mediaType": "application/vnd.oci.image.layer.v1.tar+gzip",
+            "digest": &layer,
+            "size": 0,
+        }],
+    })
+    .to_string();
+    let manifest_digest = sha256_digest(manifest.as_bytes());
+
+    let td = cap_tempfile::TempDir::new(cap_std::ambient_authority())?;
+    let modules = format!("usr/lib/modules/{KERNEL_VERSION}");
+    td.create_dir_all(&modules)?;
+    td.create_dir_all("usr/etc")?;
+    td.create_dir_all("usr/share/bootc-testing")?;
+    td.write(format!("{modules}/vmlinuz"), "fake kernel\n")?;
+    td.write(format!("{modules}/initramfs.img"), "fake initramfs\n")?;
+    td.write(
+        "usr/lib/os-release",
+        "ID=bootc-testing\nNAME=\"bootc testing\"\n",
+    )?;
+    // Make the tree (and so the commit) distinct for each image
+    td.write("usr/share/bootc-testing/image", format!("{image}\n"))?;
+
+    let txn = repo.auto_transaction(cancellable)?;
+    let mt = ostree::MutableTree::new();
+    repo.write_dfd_to_mtree(td.as_fd().as_raw_fd(), ".", &mt, None, cancellable)
+        .context("Writing tree")?;
+    let root = repo
+        .write_mtree(&mt, cancellable)
+        .context("Writing mtree")?;
+    let root = root.downcast::<ostree::RepoFile>().unwrap();
+    // The keys ostree-ext reads via `query_image_commit()`
+    let meta = glib::VariantDict::new(None);
+    meta.insert("ostree.manifest-digest", manifest_digest.as_str());
+    meta.insert("ostree.manifest", manifest.as_str());
+    meta.insert("ostree.container.image-config", config.as_str());
+    meta.insert(*ostree::METADATA_KEY_BOOTABLE, true);
+    let meta = meta.end();
+    let commit = repo
+        .write_commit(None, None, None, Some(&meta), &root, cancellable)
+        .context("Writing commit")?;
+    // The (only) layer is the base layer, which holds the whole tree
+    let layer_ref = ostree_ext::refescape::prefix_escape_for_ref("ostree/container/blob", &layer)?;
+    repo.transaction_set_ref(None, &layer_ref, Some(commit.as_str()));
+    txn.commit(cancellable)?;
+    Ok((commit.to_string(), manifest_digest))
+}
+
+/// Create a scratch sysroot at `path` with real ostree deployments of
+/// fabricated images: the rollback image (if any) is deployed first, then
+/// the booted one.  Staging a deployment requires a booted system, so there
+/// is no staged deployment.
+#[context("Fabricating sysroot {path}")]
+pub(crate) fn fabricate_sysroot(
+    path: &Utf8Path,
+    booted: &str,
+    rollback: Option<&str>,
+    rollback_queued: bool,
+) -> Result<()> {
+    if rollback_queued && rollback.is_none() {
+        anyhow::bail!("A queued rollback requires a rollback image");
+    }
+    let cancellable = gio::Cancellable::NONE;
+    std::fs::create_dir_all(path)?;
+    let d = Dir::open_ambient_dir(path, cap_std::ambient_authority())?;
+    if d.entries()?.next().is_some() {
+        anyhow::bail!("{path} is not empty");
+    }
+    let sysroot = ostree::Sysroot::new(Some(&gio::File::for_path(path)));
+    sysroot.ensure_initialized(cancellable)?;
+    sysroot.load(cancellable)?;
+    sysroot
+        .init_osname(STATEROOT, cancellable)
+        .context("Initializing stateroot")?;
+    let repo = sysroot.repo();
+    let deploy = |image: &str| -> Result<ostree::Deployment> {
+        let (commit, digest) = fabricate_image_commit(&repo, image)?;
+        let origin =
+            crate::deploy::origin_for(&registry_imgref(image), &digest, None, false, None)?;
+        let merge = sysroot.merge_deployment(Some(STATEROOT));
+        let deployment = sysroot
+            .deploy_tree(
+                Some(STATEROOT),
+                &commit,
+                Some(&origin),
+                merge.as_ref(),
+                &[],
+                cancellable,
+            )
+            .with_context(|| format!("Deploying {image}"))?;
+        sysroot.simple_write_deployment(
+            Some(STATEROOT),
+            &deployment,
+            merge.as_ref(),
+            ostree::SysrootSimpleWriteDeploymentFlags::RETAIN,
+            cancellable,
+        )?;
+        sysroot.load(cancellable)?;
+        Ok(deployment)
+    };
+    if let Some(rollback) = rollback {
+        deploy(rollback)?;
+    }
+    let booted = deploy(booted)?;
+    if rollback_queued {
+        let mut deployments = sysroot.deployments();
+        deployments.swap(0, 1);
+        sysroot.write_deployments(&deployments, cancellable)?;
+    }
+    d.atomic_write(BOOTED_FILE, sysroot.deployment_dirpath(&booted).as_str())?;
+    Ok(())
+}
+
+/// Open a sysroot created by [`fabricate_sysroot`], along with the deployment
+/// which is treated as booted.
+#[context("Opening fabricated sysroot {path}")]
+async fn open_sysroot(path: &Utf8Path) -> Result<(Storage, ostree::Deployment)> {
+    let d = Dir::open_ambient_dir(path, cap_std::ambient_authority())?;
+    let booted = d
+        .read_to_string(BOOTED_FILE)
+        .with_context(|| format!("Reading {BOOTED_FILE}"))?;
+    let sysroot = ostree::Sysroot::new(Some(&gio::File::for_path(path)));
+    let sysroot = SysrootLock::new_from_sysroot(&sysroot).await?;
+    sysroot.load(gio::Cancellable::NONE)?;
+    let deployment = sysroot
+        .deployments()
+        .into_iter()
+        .find(|dep| sysroot.deployment_dirpath(dep).as_str() == booted)
+        .ok_or_else(|| anyhow!("Deployment {booted} not found"))?;
+    let run = &Dir::open_ambient_dir("/run", cap_std::ambient_authority())?;
+    Ok((Storage::new(sysroot, run)?, deployment))
+}
+
+/// Compute the host status of a fabricated sysroot, as `bootc status` would
+/// when booted into it.
+pub(crate) async fn sysroot_status(path: &Utf8Path) -> Result<Host> {
+    let (sysroot, booted) = open_sysroot(path).await?;
+    let (_, host) = crate::status::get_status(&sysroot, Some(&booted))?;
+    Ok(host)
+}
+
+/// Compute what `bootc upgrade --dry-run` (or with a `target`, `bootc switch
+/// --dry-run`) would do when booted into a fabricated sysroot.  This fetches
+/// the manifest and configuration of the image, so the target is typically
+/// an image in a local `oci` directory.
+pub(crate) async fn plan(
+    path: &Utf8Path,
+    target: Option<&ImageReference>,
+) -> Result<crate::deploy::TransactionPlan> {
+    let (sysroot, booted) = open_sysroot(path).await?;
+    let (_, host) = crate::status::get_status(&sysroot, Some(&booted))?;
+    let entry = host
+        .status
+        .booted
+        .as_ref()
+        .ok_or_else(|| anyhow!("No booted deployment"))?;
+    let repo = &sysroot.repo();
+    let booted_image = entry.query_image(repo)?;
+    let (operation, spec_image) = match target {
+        Some(target) => ("switch", target),
+        None => {
+            let image = entry
+                .image
+                .as_ref()
+                .ok_or_else(|| anyhow!("The booted deployment is not based on an image"))?;
+            ("upgrade", &image.image)
+        }
+    };
+    crate::deploy::plan(
+        operation,
+        repo,
+        &booted,
+        spec_image,
+        spec_image,
+        booted_image.as_deref(),
+        None,
+    )
+    .await
+}
+
+/// Load a host status as written by `bootc status --format=yaml` or
+/// `--format=json` (JSON is a subset of YAML).
+pub(crate) fn load_host(path: &Utf8Path) -> Result<Host> {
+    let f = std::fs::File::open(path).with_context(|| format!("Opening {path}"))?;
+    serde_yaml::from_reader(std::io::BufReader::new(f)).with_context(|| format!("Parsing {path}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fabricate_host() -> Result<()> {
+        let booted = "quay.io/example/os:latest";
+        let host = fabricate_host(Some(booted), Some(booted), None, false)?;
+        assert_eq!(host.status.ty, Some(HostType::BootcHost));
+        assert_eq!(host.spec.image.as_ref().unwrap().image, booted);
+        let b = host.status.booted.as_ref().unwrap();
+        let s = host.status.staged.as_ref().unwrap();
+        // The same image results in the same digests, but a new deployment
+        assert_eq!(b.image, s.image);
+        let (bo, so) = (b.ostree.as_ref().unwrap(), s.ostree.as_ref().unwrap());
+        assert_eq!(bo.checksum, so.checksum);
+        assert_eq!((bo.deploy_serial, so.deploy_serial), (0, 1));
+        assert!(host.status.rollback.is_none());
+
+        // Round trip through the serialized form
+        let td = tempfile::tempdir()?;
+        let path = Utf8Path::from_path(td.path()).unwrap().join("host.yaml");
+        std::fs::write(&path, serde_yaml::to_string(&host)?)?;
+        assert_eq!(load_host(&path)?, host);
+
+        let host = fabricate_host(None, None, None, false)?;
+        assert!(host.status.ty.is_none());
+        assert!(host.spec.image.is_none());
+        assert!(fabricate_host(Some(booted), None, None, true).is_err());
+        let host = fabricate_host(Some(booted), None, Some("quay.io/example/os:old"), true)?;
+        assert_eq!(host.spec.boot_order, BootOrder::Rollback);
+        assert!(host.status.rollback_queued);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fabricate_image_commit() -> Result<()> {
+        let td = cap_tempfile::TempDir::new(cap_std::ambient_authority())?;
+        td.create_dir("repo")?;
+        let repo = &ostree::Repo::create_at(
+            td.as_fd().as_raw_fd(),
+            "repo",
+            ostree::RepoMode::Bare,
+            None,
+            gio::Cancellable::NONE,
+        )?;
+        let image = "quay.io/example/os:latest";
+        let (commit, digest) = fabricate_image_commit(repo, image)?;
+        // Read back as ostree-ext (and so `bootc status`) does
+        let state = ostree_ext::container::store::query_image_commit(repo, &commit)?;
+        assert_eq!(state.manifest_digest.to_string(), digest);
+        assert_eq!(state.base_commit, commit);
+        let labels = crate::status::labels_of_config(&state.configuration).unwrap();
+        assert_eq!(labels.get("containers.bootc").unwrap(), "1");
+        // Each image results in a distinct commit
+        let (other, other_digest) = fabricate_image_commit(repo, "quay.io/example/os:old")?;
+        assert_ne!(commit, other);
+        assert_ne!(digest, other_digest);
+        Ok(())
+    }
+}
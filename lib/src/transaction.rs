@@ -0,0 +1,123 @@
+//! # Grouping multiple specification changes
+//!
+//! While a transaction is active, `bootc switch` and `bootc edit` modify
+//! a pending host specification stored under `/run/bootc` instead of
+//! staging a deployment; `bootc transaction commit` then applies all of the
+//! changes at once, producing a single staged deployment.  Because the
+//! pending state lives in `/run`, it does not persist across reboots.
+
+use anyhow::{Context, Result};
+use cap_std_ext::cap_std::fs::Dir;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use chrono::{DateTime, Utc};
+use fn_error_context::context;
+use serde::{Deserialize, Serialize};
+
+use crate::spec::HostSpec;
+
+/// The directory holding the pending transaction, relative to the root.
+const TRANSACTION_DIR: &str = "run/bootc";
+/// The file holding the pending transaction, relative to [`TRANSACTION_DIR`].
+const TRANSACTION_FILE: &str = "transaction.yaml";
+
+/// A transaction which has been started but not yet committed.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PendingTransaction {
+    /// When the transaction was started
+    pub(crate) started: DateTime<Utc>,
+    /// The host specification when the transaction was started
+    pub(crate) base: HostSpec,
+    /// The specification which will be applied on commit
+    pub(crate) spec: HostSpec,
+}
+
+impl PendingTransaction {
+    /// Write the transaction, replacing any previous state.
+    #[context("Writing transaction")]
+    pub(crate) fn store(&self, root: &Dir) -> Result<()> {
+        root.create_dir_all(TRANSACTION_DIR)?;
+        let buf = serde_yaml::to_string(self)?;
+        let d = root.open_dir(TRANSACTION_DIR)?;
+        d.atomic_write(TRANSACTION_FILE, buf)?;
+        Ok(())
+    }
+}
+
+/// Load the pending transaction, if any.
+#[context("Loading transaction")]
+pub(crate) fn load(root: &Dir) -> Result<Option<PendingTransaction>> {
+    let Some(d) = root.open_dir_optional(TRANSACTION_DIR)? else {
+        return Ok(None);
+    };
+    let Some(f) = d.open_optional(TRANSACTION_FILE)? else {
+        return Ok(None);
+    };
+    let r = serde_yaml::from_reader(std::io::BufReader::new(f))
+        .with_context(|| format!("Parsing /{TRANSACTION_DIR}/{TRANSACTION_FILE}"))?;
+    Ok(Some(r))
+}
+
+/// Start a new transaction from the current host specification.
+#[context("Starting transaction")]
+pub(crate) fn begin(root: &Dir, spec: &HostSpec) -> Result<PendingTransaction> {
+    if let Some(existing) = load(root)? {
+        anyhow::bail!(
+            "A transaction is already in progress (started at {})",
+            existing.started
+        );
+    }
+    let txn = PendingTransaction {
+        started: Utc::now(),
+        base: spec.clone(),
+        spec: spec.clone(),
+    };
+    txn.store(root)?;
+    Ok(txn)
+}
+
+/// Discard the pending transaction, returning it if there was one.
+#[context("Discarding transaction")]
+pub(crate) fn discard(root: &Dir) -> Result<Option<PendingTransaction>> {
+    let r = load(root)?;
+    if r.is_some() {
+        root.open_dir(TRANSACTION_DIR)?
+            .remove_file(TRANSACTION_FILE)?;
+    }
+    Ok(r)
+}
+
+#[cfg(test)]
+mod tests {
+    use cap_std_ext::cap_std;
+
+    use super::*;
+    use crate::spec::ImageReference;
+
+    #[test]
+    fn test_transaction() -> Result<()> {
+        let td = cap_std_ext::cap_tempfile::TempDir::new(cap_std::ambient_authority())?;
+        assert!(load(&td)?.is_none());
+        assert!(discard(&td)?.is_none());
+
+        let base = HostSpec::default();
+        let mut txn = begin(&td, &base)?;
+        assert_eq!(txn.spec, base);
+        // Only one transaction at a time
+        assert!(begin(&td, &base).is_err());
+
+        txn.spec.image = Some(ImageReference {
+            image: "quay.io/example/os:latest".into(),
+            transport: "registry".into(),
+            signature: None,
+        });
+        txn.store(&td)?;
+        let loaded = load(&td)?.unwrap();
+        assert_eq!(loaded, txn);
+        assert_eq!(loaded.base, base);
+
+        assert_eq!(discard(&td)?.unwrap(), txn);
+        assert!(load(&td)?.is_none());
+        Ok(())
+    }
+}
@@ -40,6 +40,36 @@ pub(crate) fn deployment_fd(
     sysroot_dir.open_dir(&dirpath).map_err(Into::into)
 }
 
+/// The names of the entries in `d`, sorted; an error is returned if one of
+/// them is not valid UTF-8.
+pub(crate) fn filenames_sorted(d: &Dir) -> Result<Vec<String>> {
+    let mut names = d
+        .entries()?
+        .map(|e| {
+            let name = e?.file_name();
+            name.into_string()
+                .map_err(|n| anyhow::anyhow!("Invalid non-UTF8 filename: {n:?}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    names.sort();
+    Ok(names)
+}
+
+/// Remount the filesystem at `path` (opened as `d`) writable if it is
+/// read-only; callers must be running in their own mount namespace, so that
+/// this does not affect the host.
+pub(crate) fn ensure_writable_mount(d: &Dir, path: &str) -> Result<()> {
+    use std::os::fd::AsFd;
+    let st = rustix::fs::fstatvfs(d.as_fd())?;
+    if st.f_flag.contains(rustix::fs::StatVfsMountFlags::RDONLY) {
+        crate::task::Task::new(format!("Remounting {path} writable"), "mount")
+            .args(["-o", "remount,rw", path])
+            .quiet()
+            .run()?;
+    }
+    Ok(())
+}
+
 /// Given an mount option string list like foo,bar=baz,something=else,ro parse it and find
 /// the first entry like $optname=
 /// This will not match a bare `optname` without an equals.
@@ -142,7 +172,6 @@ where
 /// Given a possibly tagged image like quay.io/foo/bar:latest and a digest 0ab32..., return
 /// the digested form quay.io/foo/bar:latest@sha256:0ab32...
 /// If the image already has a digest, it will be replaced.
-#[allow(dead_code)]
 pub(crate) fn digested_pullspec(image: &str, digest: &str) -> String {
     let image = image.rsplit_once('@').map(|v| v.0).unwrap_or(image);
     format!("{image}@{digest}")
@@ -0,0 +1,161 @@
+//! # Waking powered-off systems to fetch updates
+//!
+//! If the `[wake]` section of the host configuration is set, then at shutdown
+//! `bootc-rtc-wake.service` programs the real time clock to power on the
+//! system at the next occurrence of the configured time, and records that time
+//! under `/var/lib/bootc`.  When the system is booted at (or shortly after)
+//! the recorded time, the same unit fetches and stages any available update,
+//! then powers the system off again.  The staged update is applied on the
+//! next boot, like any other.
+
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use cap_std_ext::cap_std::fs::Dir;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use chrono::{DateTime, NaiveTime, TimeZone};
+use fn_error_context::context;
+
+use crate::task::Task;
+
+/// The kernel interface for the RTC alarm, as seconds since the epoch.
+const RTC_WAKEALARM: &str = "/sys/class/rtc/rtc0/wakealarm";
+/// The directory holding the wake state, relative to the root.
+const WAKE_STATE_DIR: &str = "var/lib/bootc";
+/// The scheduled wake time as seconds since the epoch, relative to the root.
+const WAKE_STATE: &str = "var/lib/bootc/rtc-wake";
+/// A boot this long after the scheduled time is still considered a scheduled wake.
+const WAKE_GRACE_SECS: i64 = 15 * 60;
+
+/// Parse a time of day in the form `HH:MM`.
+fn parse_time(s: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(s, "%H:%M")
+        .with_context(|| format!("Invalid wake time {s:?}, expected HH:MM"))
+}
+
+/// The next occurrence of the time of day, strictly after `now`.
+fn next_wake<Tz: TimeZone>(now: &DateTime<Tz>, time: NaiveTime) -> Result<DateTime<Tz>> {
+    let mut date = now.date_naive();
+    if now.time() >= time {
+        date = date.succ_opt().context("Date out of range")?;
+    }
+    // On a DST transition the time may be ambiguous or not exist at all; in
+    // the latter case, wake on the following day.
+    let tz = now.timezone();
+    tz.from_local_datetime(&date.and_time(time))
+        .earliest()
+        .or_else(|| {
+            date.succ_opt()
+                .and_then(|d| tz.from_local_datetime(&d.and_time(time)).earliest())
+        })
+        .context("Computing wake time")
+}
+
+/// Program the RTC to wake the system at the next configured time; this is
+/// run at shutdown.
+#[context("Scheduling wake")]
+pub(crate) fn schedule(root: &Dir) -> Result<()> {
+    let config = crate::config::load_config(root)?;
+    let Some(wake) = config.wake() else {
+        tracing::debug!("No wake time configured");
+        return Ok(());
+    };
+    let when = next_wake(&chrono::Local::now(), parse_time(&wake.time)?)?;
+    let timestamp = when.timestamp().to_string();
+    // Setting an alarm fails if one is already set
+    std::fs::write(RTC_WAKEALARM, "0").with_context(|| format!("Clearing {RTC_WAKEALARM}"))?;
+    std::fs::write(RTC_WAKEALARM, &timestamp)
+        .with_context(|| format!("Writing {RTC_WAKEALARM}"))?;
+    root.create_dir_all(WAKE_STATE_DIR)?;
+    root.atomic_write(WAKE_STATE, timestamp)?;
+    println!("Scheduled wake at {when}");
+    Ok(())
+}
+
+/// Returns true if the system was booted at the scheduled wake time.  The
+/// recorded time is removed, so this returns true at most once.
+fn take_scheduled_wake(root: &Dir, now: i64) -> Result<bool> {
+    let Some(f) = root.open_optional(WAKE_STATE)? else {
+        return Ok(false);
+    };
+    let scheduled = std::io::read_to_string(f)?;
+    root.remove_file(WAKE_STATE)?;
+    let scheduled: i64 = scheduled
+        .trim()
+        .parse()
+        .with_context(|| format!("Parsing /{WAKE_STATE}"))?;
+    Ok((scheduled..=scheduled + WAKE_GRACE_SECS).contains(&now))
+}
+
+/// If the system was booted at the scheduled wake time, fetch and stage any
+/// update, then power off if configured.
+#[context("Fetching updates after wake")]
+pub(crate) fn update(root: &Dir) -> Result<()> {
+    let config = crate::config::load_config(root)?;
+    let Some(wake) = config.wake() else {
+        return Ok(());
+    };
+    if !take_scheduled_wake(root, chrono::Utc::now().timestamp())? {
+        tracing::debug!("Not a scheduled wake");
+        return Ok(());
+    }
+    let mut cmd = Command::new("/proc/self/exe");
+    cmd.args(["upgrade", "--quiet"]);
+    let r = Task::new_cmd("Fetching updates", cmd).run();
+    if let Err(e) = r.as_ref() {
+        // Don't stay powered on indefinitely because of a failed fetch
+        eprintln!("{e:#}");
+    }
+    if wake.poweroff.unwrap_or(true) {
+        Task::new("Powering off", "systemctl")
+            .args(["--no-block", "poweroff"])
+            .run()?;
+    }
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use cap_std_ext::cap_std;
+    use chrono::Utc;
+
+    use super::*;
+
+    #[test]
+    fn test_next_wake() -> Result<()> {
+        let time = parse_time("03:30")?;
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        assert_eq!(
+            next_wake(&at("2024-05-01T01:00:00Z"), time)?,
+            at("2024-05-01T03:30:00Z")
+        );
+        assert_eq!(
+            next_wake(&at("2024-05-01T03:30:00Z"), time)?,
+            at("2024-05-02T03:30:00Z")
+        );
+        assert_eq!(
+            next_wake(&at("2024-12-31T22:00:00Z"), time)?,
+            at("2025-01-01T03:30:00Z")
+        );
+        for invalid in ["", "3", "25:00", "03:30:00"] {
+            assert!(parse_time(invalid).is_err(), "{invalid}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_take_scheduled_wake() -> Result<()> {
+        let td = cap_std_ext::cap_tempfile::TempDir::new(cap_std::ambient_authority())?;
+        assert!(!take_scheduled_wake(&td, 1000)?);
+        td.create_dir_all(WAKE_STATE_DIR)?;
+        td.write(WAKE_STATE, "1000\n")?;
+        assert!(take_scheduled_wake(&td, 1060)?);
+        // Only once
+        assert!(!take_scheduled_wake(&td, 1060)?);
+        td.write(WAKE_STATE, "1000")?;
+        assert!(!take_scheduled_wake(&td, 1000 + WAKE_GRACE_SECS + 1)?);
+        td.write(WAKE_STATE, "1000")?;
+        assert!(!take_scheduled_wake(&td, 999)?);
+        Ok(())
+    }
+}
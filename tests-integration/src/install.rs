@@ -72,6 +72,37 @@ fn generic_post_install_verification() -> Result<()> {
     Ok(())
 }
 
+/// The GPT partition types `bootc install to-disk` creates by default on the given architecture.
+fn expected_parttypes(arch: &str) -> Result<&'static [&'static str]> {
+    const BIOS_BOOT: &str = "21686148-6449-6E6F-744E-656564454649";
+    const ESP: &str = "C12A7328-F81F-11D2-BA4B-00A0C93EC93B";
+    const PREP_BOOT: &str = "9E1A2D38-C612-4316-AA26-8B49521E5A8B";
+    const LINUX: &str = "0FC63DAF-8483-4772-8E79-3D69D8477DE4";
+    let r: &'static [&'static str] = match arch {
+        "x86_64" => &[BIOS_BOOT, ESP, LINUX],
+        "aarch64" => &[ESP, LINUX],
+        "powerpc64" => &[PREP_BOOT, LINUX],
+        "s390x" => &[LINUX],
+        o => anyhow::bail!("Unhandled architecture: {o}"),
+    };
+    Ok(r)
+}
+
+/// Verify the partition layout of a disk (image) created by `bootc install to-disk`.
+#[context("Verifying partition layout")]
+fn verify_partition_layout(sh: &Shell, disk: &str) -> Result<()> {
+    let out = cmd!(sh, "sudo sfdisk --json {disk}").read()?;
+    let out: serde_json::Value = serde_json::from_str(&out)?;
+    let parttypes = out["partitiontable"]["partitions"]
+        .as_array()
+        .ok_or_else(|| anyhow::anyhow!("Missing partitions"))?
+        .iter()
+        .map(|p| p["type"].as_str().unwrap_or_default().to_ascii_uppercase())
+        .collect::<Vec<_>>();
+    assert_eq!(parttypes, expected_parttypes(std::env::consts::ARCH)?);
+    Ok(())
+}
+
 #[context("Install tests")]
 pub(crate) fn run_alongside(image: &str, mut testargs: libtest_mimic::Arguments) -> Result<()> {
     // Force all of these tests to be serial because they mutate global state
@@ -94,6 +125,7 @@ pub(crate) fn run_alongside(image: &str, mut testargs: libtest_mimic::Arguments)
             let tmpdisk = tmpdisk.into_temp_path();
             let tmpdisk = tmpdisk.to_str().unwrap();
             cmd!(sh, "sudo {BASE_ARGS...} -v {tmpdisk}:/disk {image} bootc install to-disk --via-loopback {generic_inst_args...} /disk").run()?;
+            verify_partition_layout(sh, tmpdisk)?;
             Ok(())
         }),
         Trial::test(